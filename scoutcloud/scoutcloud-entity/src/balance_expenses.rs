@@ -8,7 +8,7 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub user_id: i32,
-    pub deployment_id: i32,
+    pub deployment_id: Option<i32>,
     pub hours: i32,
     pub expense_amount: Decimal,
     pub created_at: DateTimeWithTimeZone,
@@ -21,7 +21,7 @@ pub enum Relation {
         from = "Column::DeploymentId",
         to = "super::deployments::Column::Id",
         on_update = "NoAction",
-        on_delete = "NoAction"
+        on_delete = "SetNull"
     )]
     Deployments,
     #[sea_orm(
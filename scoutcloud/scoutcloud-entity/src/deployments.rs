@@ -23,12 +23,44 @@ pub struct Model {
     pub status: DeploymentStatusType,
     pub error: Option<String>,
     pub total_cost: Decimal,
+    pub run_id: Option<i64>,
+    pub terminal_error: bool,
+    pub workflow_timeout_seconds: Option<i32>,
+    pub expires_at: Option<DateTimeWithTimeZone>,
+    pub updated_at: DateTimeWithTimeZone,
+    pub idempotency_key: Option<String>,
+    pub idempotency_fingerprint: Option<String>,
+    pub version: i32,
+    pub liveness_failures: i32,
+    pub error_code: Option<String>,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub labels: Json,
+    pub git_ref: Option<String>,
+    pub last_polled_at: Option<DateTimeWithTimeZone>,
+    pub scheduled_at: Option<DateTimeWithTimeZone>,
+    pub max_auto_retries: i32,
+    pub auto_retries: i32,
+    pub image: Option<String>,
+    pub image_tag: Option<String>,
+    pub started_by: Option<String>,
+    pub stopped_by: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub stop_reason: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::balance_expenses::Entity")]
     BalanceExpenses,
+    #[sea_orm(has_many = "super::deployment_logs::Entity")]
+    DeploymentLogs,
+    #[sea_orm(has_many = "super::deployment_status_history::Entity")]
+    DeploymentStatusHistory,
+    #[sea_orm(has_many = "super::deployment_workflow_runs::Entity")]
+    DeploymentWorkflowRuns,
+    #[sea_orm(has_one = "super::instance_config_versions::Entity")]
+    InstanceConfigVersions,
     #[sea_orm(
         belongs_to = "super::instances::Entity",
         from = "Column::InstanceId",
@@ -45,6 +77,10 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     ServerSpecs,
+    #[sea_orm(has_many = "super::task_runs::Entity")]
+    TaskRuns,
+    #[sea_orm(has_many = "super::webhook_deliveries::Entity")]
+    WebhookDeliveries,
 }
 
 impl Related<super::balance_expenses::Entity> for Entity {
@@ -53,6 +89,30 @@ impl Related<super::balance_expenses::Entity> for Entity {
     }
 }
 
+impl Related<super::deployment_logs::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeploymentLogs.def()
+    }
+}
+
+impl Related<super::deployment_status_history::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeploymentStatusHistory.def()
+    }
+}
+
+impl Related<super::deployment_workflow_runs::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DeploymentWorkflowRuns.def()
+    }
+}
+
+impl Related<super::instance_config_versions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::InstanceConfigVersions.def()
+    }
+}
+
 impl Related<super::instances::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Instances.def()
@@ -65,4 +125,16 @@ impl Related<super::server_specs::Entity> for Entity {
     }
 }
 
+impl Related<super::task_runs::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TaskRuns.def()
+    }
+}
+
+impl Related<super::webhook_deliveries::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::WebhookDeliveries.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
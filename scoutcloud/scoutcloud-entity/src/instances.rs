@@ -18,10 +18,25 @@ pub struct Model {
     pub parsed_config: Json,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
+    pub auto_redeploy: bool,
+    pub template_id: Option<i32>,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub template_variables: Json,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub template_overrides: Json,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::config_templates::Entity",
+        from = "Column::TemplateId",
+        to = "super::config_templates::Column::Id",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    ConfigTemplates,
+
     #[sea_orm(
         belongs_to = "super::users::Entity",
         from = "Column::CreatorId",
@@ -33,6 +48,15 @@ pub enum Relation {
 
     #[sea_orm(has_many = "super::deployments::Entity")]
     Deployments,
+
+    #[sea_orm(has_many = "super::instance_config_versions::Entity")]
+    InstanceConfigVersions,
+}
+
+impl Related<super::config_templates::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ConfigTemplates.def()
+    }
 }
 
 impl Related<super::users::Entity> for Entity {
@@ -47,4 +71,10 @@ impl Related<super::deployments::Entity> for Entity {
     }
 }
 
+impl Related<super::instance_config_versions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::InstanceConfigVersions.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
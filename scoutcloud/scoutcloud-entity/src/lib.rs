@@ -5,10 +5,18 @@ pub mod prelude;
 pub mod auth_tokens;
 pub mod balance_changes;
 pub mod balance_expenses;
+pub mod config_templates;
+pub mod deployment_logs;
+pub mod deployment_status_history;
+pub mod deployment_workflow_runs;
 pub mod deployments;
 pub mod fang_tasks;
+pub mod instance_config_versions;
 pub mod instances;
 pub mod sea_orm_active_enums;
+pub mod secrets;
 pub mod server_specs;
+pub mod task_runs;
 pub mod user_actions;
 pub mod users;
+pub mod webhook_deliveries;
@@ -2,8 +2,13 @@
 
 pub use super::{
     auth_tokens::Entity as AuthTokens, balance_changes::Entity as BalanceChanges,
-    balance_expenses::Entity as BalanceExpenses, deployments::Entity as Deployments,
-    fang_tasks::Entity as FangTasks, instances::Entity as Instances,
-    server_specs::Entity as ServerSpecs, user_actions::Entity as UserActions,
-    users::Entity as Users,
+    balance_expenses::Entity as BalanceExpenses, config_templates::Entity as ConfigTemplates,
+    deployment_logs::Entity as DeploymentLogs,
+    deployment_status_history::Entity as DeploymentStatusHistory,
+    deployment_workflow_runs::Entity as DeploymentWorkflowRuns, deployments::Entity as Deployments,
+    fang_tasks::Entity as FangTasks, instance_config_versions::Entity as InstanceConfigVersions,
+    instances::Entity as Instances, secrets::Entity as Secrets,
+    server_specs::Entity as ServerSpecs, task_runs::Entity as TaskRuns,
+    user_actions::Entity as UserActions, users::Entity as Users,
+    webhook_deliveries::Entity as WebhookDeliveries,
 };
@@ -17,6 +17,8 @@ pub enum DeploymentStatusType {
     Pending,
     #[sea_orm(string_value = "running")]
     Running,
+    #[sea_orm(string_value = "scheduled")]
+    Scheduled,
     #[sea_orm(string_value = "stopped")]
     Stopped,
     #[sea_orm(string_value = "stopping")]
@@ -12,6 +12,9 @@ pub struct Model {
     pub created_at: DateTimeWithTimeZone,
     pub is_superuser: bool,
     pub balance: Decimal,
+    pub quota: Option<i32>,
+    pub webhook_url: Option<String>,
+    pub webhook_secret: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -20,8 +23,12 @@ pub enum Relation {
     AuthTokens,
     #[sea_orm(has_many = "super::balance_expenses::Entity")]
     BalanceExpenses,
+    #[sea_orm(has_many = "super::config_templates::Entity")]
+    ConfigTemplates,
     #[sea_orm(has_many = "super::instances::Entity")]
     Instances,
+    #[sea_orm(has_many = "super::webhook_deliveries::Entity")]
+    WebhookDeliveries,
 }
 
 impl Related<super::auth_tokens::Entity> for Entity {
@@ -36,10 +43,22 @@ impl Related<super::balance_expenses::Entity> for Entity {
     }
 }
 
+impl Related<super::config_templates::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ConfigTemplates.def()
+    }
+}
+
 impl Related<super::instances::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Instances.def()
     }
 }
 
+impl Related<super::webhook_deliveries::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::WebhookDeliveries.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
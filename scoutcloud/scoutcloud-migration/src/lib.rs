@@ -5,6 +5,40 @@ mod m20220101_000001_create_table;
 mod m20240208_092748_create_triggers;
 mod m20240409_105319_fill_server_specs;
 mod m20240415_094154_add_fang;
+mod m20240506_101245_add_deployments_run_id;
+mod m20240508_143020_add_deployments_terminal_error;
+mod m20240510_112233_add_deployments_workflow_timeout;
+mod m20240513_094512_add_deployment_logs;
+mod m20240514_101530_add_instance_config_versions;
+mod m20240515_083010_add_users_quota;
+mod m20240516_120045_add_deployments_expires_at;
+mod m20240517_091530_add_deployments_updated_at;
+mod m20240518_104512_add_deployments_updated_at_index;
+mod m20240520_093015_add_webhook_deliveries;
+mod m20240521_101204_add_deployments_idempotency_key;
+mod m20240522_084530_add_deployments_version;
+mod m20240523_090412_add_deployment_status_history;
+mod m20240524_101530_add_liveness_checks;
+mod m20240525_091020_add_instances_creator_name_index;
+mod m20240526_083045_keep_expenses_of_deleted_deployments;
+mod m20240527_094210_add_deployments_error_code;
+mod m20240528_103015_add_auth_tokens_scopes;
+mod m20240529_091530_add_status_history_export;
+mod m20240530_101245_add_deployments_deleted_at;
+mod m20240531_093020_add_deployments_labels;
+mod m20240601_094510_add_task_runs;
+mod m20240602_101530_add_deployments_git_ref;
+mod m20240603_083040_add_deployments_last_polled_at;
+mod m20240604_091015_add_secrets;
+mod m20240605_090210_add_deployments_scheduled_at;
+mod m20240606_083520_add_deployments_auto_retries;
+mod m20240607_094025_add_deployments_image;
+mod m20240608_101512_add_status_history_override;
+mod m20240609_093045_add_deployment_workflow_runs;
+mod m20240610_091530_add_config_templates;
+mod m20240611_084210_add_deployment_logs_fetch_error;
+mod m20240612_090120_add_deployment_initiators;
+mod m20240613_083015_add_deployments_created_at_index;
 
 pub struct Migrator;
 
@@ -16,6 +50,40 @@ impl MigratorTrait for Migrator {
             Box::new(m20240208_092748_create_triggers::Migration),
             Box::new(m20240409_105319_fill_server_specs::Migration),
             Box::new(m20240415_094154_add_fang::Migration),
+            Box::new(m20240506_101245_add_deployments_run_id::Migration),
+            Box::new(m20240508_143020_add_deployments_terminal_error::Migration),
+            Box::new(m20240510_112233_add_deployments_workflow_timeout::Migration),
+            Box::new(m20240513_094512_add_deployment_logs::Migration),
+            Box::new(m20240514_101530_add_instance_config_versions::Migration),
+            Box::new(m20240515_083010_add_users_quota::Migration),
+            Box::new(m20240516_120045_add_deployments_expires_at::Migration),
+            Box::new(m20240517_091530_add_deployments_updated_at::Migration),
+            Box::new(m20240518_104512_add_deployments_updated_at_index::Migration),
+            Box::new(m20240520_093015_add_webhook_deliveries::Migration),
+            Box::new(m20240521_101204_add_deployments_idempotency_key::Migration),
+            Box::new(m20240522_084530_add_deployments_version::Migration),
+            Box::new(m20240523_090412_add_deployment_status_history::Migration),
+            Box::new(m20240524_101530_add_liveness_checks::Migration),
+            Box::new(m20240525_091020_add_instances_creator_name_index::Migration),
+            Box::new(m20240526_083045_keep_expenses_of_deleted_deployments::Migration),
+            Box::new(m20240527_094210_add_deployments_error_code::Migration),
+            Box::new(m20240528_103015_add_auth_tokens_scopes::Migration),
+            Box::new(m20240529_091530_add_status_history_export::Migration),
+            Box::new(m20240530_101245_add_deployments_deleted_at::Migration),
+            Box::new(m20240531_093020_add_deployments_labels::Migration),
+            Box::new(m20240601_094510_add_task_runs::Migration),
+            Box::new(m20240602_101530_add_deployments_git_ref::Migration),
+            Box::new(m20240603_083040_add_deployments_last_polled_at::Migration),
+            Box::new(m20240604_091015_add_secrets::Migration),
+            Box::new(m20240605_090210_add_deployments_scheduled_at::Migration),
+            Box::new(m20240606_083520_add_deployments_auto_retries::Migration),
+            Box::new(m20240607_094025_add_deployments_image::Migration),
+            Box::new(m20240608_101512_add_status_history_override::Migration),
+            Box::new(m20240609_093045_add_deployment_workflow_runs::Migration),
+            Box::new(m20240610_091530_add_config_templates::Migration),
+            Box::new(m20240611_084210_add_deployment_logs_fetch_error::Migration),
+            Box::new(m20240612_090120_add_deployment_initiators::Migration),
+            Box::new(m20240613_083015_add_deployments_created_at_index::Migration),
         ]
     }
 }
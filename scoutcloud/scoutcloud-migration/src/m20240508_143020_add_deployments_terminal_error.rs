@@ -0,0 +1,29 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments" ADD COLUMN "terminal_error" BOOLEAN NOT NULL DEFAULT FALSE;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "terminal_error";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments" ADD COLUMN "workflow_timeout_seconds" INTEGER;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "workflow_timeout_seconds";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
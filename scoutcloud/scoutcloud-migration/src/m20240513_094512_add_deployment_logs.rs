@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            CREATE TABLE "deployment_logs" (
+              "id" INT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+              "deployment_id" int NOT NULL REFERENCES "deployments" ("id") ON DELETE CASCADE,
+              "run_id" bigint NOT NULL,
+              "logs" text NOT NULL,
+              "truncated" boolean NOT NULL DEFAULT FALSE,
+              "created_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP)
+            );
+            CREATE INDEX "deployment_logs_deployment_id_index" ON "deployment_logs" ("deployment_id");
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP TABLE IF EXISTS "deployment_logs";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_statements(
+            manager,
+            &[
+                r#"
+                CREATE TABLE "instance_config_versions" (
+                  "id" INT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                  "instance_id" int NOT NULL REFERENCES "instances" ("id"),
+                  "deployment_id" int NOT NULL UNIQUE REFERENCES "deployments" ("id") ON DELETE CASCADE,
+                  "version" int NOT NULL,
+                  "user_config" jsonb NOT NULL,
+                  "parsed_config" jsonb NOT NULL,
+                  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+                  UNIQUE ("instance_id", "version")
+                );
+                "#,
+                r#"
+                -- Existing deployments already store configs they were created from
+                INSERT INTO "instance_config_versions"
+                  ("instance_id", "deployment_id", "version", "user_config", "parsed_config", "created_at")
+                SELECT
+                  "instance_id",
+                  "id",
+                  ROW_NUMBER() OVER (PARTITION BY "instance_id" ORDER BY "created_at", "id"),
+                  "user_config",
+                  "parsed_config",
+                  "created_at"
+                FROM "deployments";
+                "#,
+                r#"
+                -- Snapshots of configs are immutable
+                CREATE OR REPLACE FUNCTION prevent_instance_config_versions_update()
+                RETURNS TRIGGER AS $$
+                BEGIN
+                    RAISE EXCEPTION 'instance config versions are immutable';
+                END;
+                $$ LANGUAGE plpgsql;
+                "#,
+                r#"
+                CREATE TRIGGER instance_config_versions_immutable_trigger
+                BEFORE UPDATE
+                ON instance_config_versions
+                FOR EACH ROW
+                EXECUTE FUNCTION prevent_instance_config_versions_update();
+                "#,
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP TABLE IF EXISTS "instance_config_versions";
+            DROP FUNCTION IF EXISTS prevent_instance_config_versions_update() CASCADE;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
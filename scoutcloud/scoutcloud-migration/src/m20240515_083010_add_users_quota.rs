@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- max number of active deployments, global default is used if it is null
+            ALTER TABLE "users" ADD COLUMN "quota" INTEGER;
+            CREATE INDEX IF NOT EXISTS "instances_creator_id_index" ON "instances" ("creator_id");
+            CREATE INDEX IF NOT EXISTS "deployments_instance_id_status_index" ON "deployments" ("instance_id", "status");
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP INDEX IF EXISTS "deployments_instance_id_status_index";
+            DROP INDEX IF EXISTS "instances_creator_id_index";
+            ALTER TABLE "users" DROP COLUMN IF EXISTS "quota";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
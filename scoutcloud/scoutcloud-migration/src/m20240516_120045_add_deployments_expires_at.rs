@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- deployment is stopped automatically after this time, never if it is null
+            ALTER TABLE "deployments" ADD COLUMN "expires_at" TIMESTAMPTZ;
+            CREATE INDEX IF NOT EXISTS "deployments_expires_at_index" ON "deployments" ("expires_at")
+                WHERE "expires_at" IS NOT NULL;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP INDEX IF EXISTS "deployments_expires_at_index";
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "expires_at";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_statements(
+            manager,
+            &[
+                r#"
+                ALTER TABLE "deployments"
+                ADD COLUMN "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP);
+                "#,
+                r#"
+                -- Only progress of the deployment is tracked: changes of cost made by
+                -- balance checks shouldn't make a stuck deployment look alive
+                CREATE OR REPLACE FUNCTION update_deployments_updated_at()
+                RETURNS TRIGGER AS $$
+                BEGIN
+                    IF NEW.status IS DISTINCT FROM OLD.status
+                        OR NEW.run_id IS DISTINCT FROM OLD.run_id THEN
+                        NEW.updated_at = CURRENT_TIMESTAMP;
+                    END IF;
+                    RETURN NEW;
+                END;
+                $$ LANGUAGE plpgsql;
+                "#,
+                r#"
+                CREATE TRIGGER deployments_updated_at_trigger
+                BEFORE UPDATE
+                ON deployments
+                FOR EACH ROW
+                EXECUTE FUNCTION update_deployments_updated_at();
+                "#,
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP FUNCTION IF EXISTS update_deployments_updated_at() CASCADE;
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "updated_at";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- keyset pagination of deployments, the most recently updated go first
+            CREATE INDEX IF NOT EXISTS "deployments_updated_at_id_index"
+                ON "deployments" ("updated_at" DESC, "id" DESC);
+            CREATE INDEX IF NOT EXISTS "deployments_status_updated_at_id_index"
+                ON "deployments" ("status", "updated_at" DESC, "id" DESC);
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP INDEX IF EXISTS "deployments_status_updated_at_id_index";
+            DROP INDEX IF EXISTS "deployments_updated_at_id_index";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
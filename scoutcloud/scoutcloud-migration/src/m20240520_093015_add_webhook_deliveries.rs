@@ -0,0 +1,78 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_statements(
+            manager,
+            &[
+                r#"
+                ALTER TABLE "users"
+                ADD COLUMN "webhook_url" text,
+                ADD COLUMN "webhook_secret" text;
+                "#,
+                r#"
+                CREATE TABLE "webhook_deliveries" (
+                  "id" INT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                  "user_id" int NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+                  "deployment_id" int NOT NULL REFERENCES "deployments" ("id") ON DELETE CASCADE,
+                  "old_status" deployment_status_type NOT NULL,
+                  "new_status" deployment_status_type NOT NULL,
+                  "attempts" int NOT NULL DEFAULT 0,
+                  "next_attempt_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+                  "last_error" text,
+                  "delivered_at" TIMESTAMP WITH TIME ZONE,
+                  "dead_at" TIMESTAMP WITH TIME ZONE,
+                  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP)
+                );
+                "#,
+                r#"
+                CREATE INDEX "webhook_deliveries_pending_index" ON "webhook_deliveries" ("next_attempt_at")
+                WHERE "delivered_at" IS NULL AND "dead_at" IS NULL;
+                "#,
+                r#"
+                -- Status is changed in many places, so transitions are recorded by the database
+                -- and delivered later without blocking the code which changed the status
+                CREATE OR REPLACE FUNCTION record_deployment_status_change()
+                RETURNS TRIGGER AS $$
+                BEGIN
+                    IF NEW.status IS DISTINCT FROM OLD.status THEN
+                        INSERT INTO webhook_deliveries (user_id, deployment_id, old_status, new_status)
+                        SELECT users.id, NEW.id, OLD.status, NEW.status
+                        FROM instances
+                        JOIN users ON users.id = instances.creator_id
+                        WHERE instances.id = NEW.instance_id AND users.webhook_url IS NOT NULL;
+                    END IF;
+                    RETURN NEW;
+                END;
+                $$ LANGUAGE plpgsql;
+                "#,
+                r#"
+                CREATE TRIGGER deployments_status_change_trigger
+                AFTER UPDATE
+                ON deployments
+                FOR EACH ROW
+                EXECUTE FUNCTION record_deployment_status_change();
+                "#,
+            ],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP FUNCTION IF EXISTS record_deployment_status_change() CASCADE;
+            DROP TABLE IF EXISTS "webhook_deliveries";
+            ALTER TABLE "users" DROP COLUMN IF EXISTS "webhook_url", DROP COLUMN IF EXISTS "webhook_secret";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments"
+            ADD COLUMN "idempotency_key" text,
+            ADD COLUMN "idempotency_fingerprint" text;
+            CREATE UNIQUE INDEX "deployments_instance_id_idempotency_key_index"
+            ON "deployments" ("instance_id", "idempotency_key")
+            WHERE "idempotency_key" IS NOT NULL;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP INDEX IF EXISTS "deployments_instance_id_idempotency_key_index";
+            ALTER TABLE "deployments"
+            DROP COLUMN IF EXISTS "idempotency_key",
+            DROP COLUMN IF EXISTS "idempotency_fingerprint";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            CREATE TABLE "deployment_status_history" (
+              "id" INT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+              "deployment_id" int NOT NULL REFERENCES "deployments" ("id") ON DELETE CASCADE,
+              "old_status" deployment_status_type,
+              "new_status" deployment_status_type NOT NULL,
+              "error" text,
+              "actor" varchar NOT NULL,
+              "created_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP)
+            );
+
+            CREATE INDEX "deployment_status_history_deployment_id_index"
+            ON "deployment_status_history" ("deployment_id", "id");
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP TABLE IF EXISTS "deployment_status_history";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
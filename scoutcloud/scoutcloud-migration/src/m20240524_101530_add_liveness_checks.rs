@@ -0,0 +1,31 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "instances" ADD COLUMN "auto_redeploy" boolean NOT NULL DEFAULT false;
+            ALTER TABLE "deployments" ADD COLUMN "liveness_failures" int NOT NULL DEFAULT 0;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "liveness_failures";
+            ALTER TABLE "instances" DROP COLUMN IF EXISTS "auto_redeploy";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // names equal after trimming and lowercasing had the same slug,
+        // which is unique already, so existing rows can't violate the index
+        crate::from_sql(
+            manager,
+            r#"
+            CREATE UNIQUE INDEX "instances_creator_id_name_key"
+                ON "instances" ("creator_id", lower(trim("name")));
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP INDEX IF EXISTS "instances_creator_id_name_key";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // expenses are kept for accounting when old deployments are purged
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "balance_expenses" ALTER COLUMN "deployment_id" DROP NOT NULL;
+            ALTER TABLE "balance_expenses" DROP CONSTRAINT "balance_expenses_deployment_id_fkey";
+            ALTER TABLE "balance_expenses" ADD CONSTRAINT "balance_expenses_deployment_id_fkey"
+                FOREIGN KEY ("deployment_id") REFERENCES "deployments" ("id") ON DELETE SET NULL;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DELETE FROM "balance_expenses" WHERE "deployment_id" IS NULL;
+            ALTER TABLE "balance_expenses" DROP CONSTRAINT "balance_expenses_deployment_id_fkey";
+            ALTER TABLE "balance_expenses" ADD CONSTRAINT "balance_expenses_deployment_id_fkey"
+                FOREIGN KEY ("deployment_id") REFERENCES "deployments" ("id");
+            ALTER TABLE "balance_expenses" ALTER COLUMN "deployment_id" SET NOT NULL;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
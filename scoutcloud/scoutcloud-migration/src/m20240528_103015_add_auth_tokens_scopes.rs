@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // existing tokens keep full access
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "auth_tokens" ADD COLUMN "scopes" text[] NOT NULL DEFAULT ARRAY[
+                'instances:read',
+                'instances:write',
+                'deployments:read',
+                'deployments:write',
+                'users:read',
+                'users:write'
+            ];
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "auth_tokens" DROP COLUMN IF EXISTS "scopes";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // changes recorded before are not exported as events
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployment_status_history"
+                ADD COLUMN "run_id" bigint,
+                ADD COLUMN "exported_at" TIMESTAMP WITH TIME ZONE;
+
+            UPDATE "deployment_status_history" SET "exported_at" = CURRENT_TIMESTAMP;
+
+            CREATE INDEX "deployment_status_history_not_exported_index"
+            ON "deployment_status_history" ("id")
+            WHERE "exported_at" IS NULL;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP INDEX IF EXISTS "deployment_status_history_not_exported_index";
+
+            ALTER TABLE "deployment_status_history"
+                DROP COLUMN IF EXISTS "run_id",
+                DROP COLUMN IF EXISTS "exported_at";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- free-form key/value labels used to group deployments
+            ALTER TABLE "deployments" ADD COLUMN "labels" JSONB NOT NULL DEFAULT '{}';
+            -- label selectors are checked with containment operator `@>`
+            CREATE INDEX "deployments_labels_index" ON "deployments" USING GIN ("labels" jsonb_path_ops);
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP INDEX IF EXISTS "deployments_labels_index";
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "labels";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- every execution of a deployment task, including retried and failed ones
+            CREATE TABLE "task_runs" (
+              "id" INT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+              "task_type" varchar NOT NULL,
+              "deployment_id" int NOT NULL REFERENCES "deployments" ("id") ON DELETE CASCADE,
+              "run_id" bigint,
+              "started_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+              "finished_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+              "poll_count" int NOT NULL DEFAULT 0,
+              "outcome" varchar NOT NULL,
+              "error" text
+            );
+
+            CREATE INDEX "task_runs_deployment_id_index"
+            ON "task_runs" ("deployment_id", "id");
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP TABLE IF EXISTS "task_runs";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- secrets referenced by instance configs, values are encrypted
+            CREATE TABLE "secrets" (
+              "path" varchar PRIMARY KEY,
+              "value" text NOT NULL,
+              "created_at" TIMESTAMPTZ NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+              "updated_at" TIMESTAMPTZ NOT NULL DEFAULT (CURRENT_TIMESTAMP)
+            );
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP TABLE IF EXISTS "secrets";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
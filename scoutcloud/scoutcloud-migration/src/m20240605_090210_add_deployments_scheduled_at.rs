@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- scheduled deployments wait for `scheduled_at` before their starting task runs
+            ALTER TYPE "deployment_status_type" ADD VALUE IF NOT EXISTS 'scheduled';
+            ALTER TABLE "deployments" ADD COLUMN "scheduled_at" TIMESTAMPTZ;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // values can't be removed from enum types, so only scheduled deployments are failed
+        crate::from_sql(
+            manager,
+            r#"
+            UPDATE "deployments" SET "status" = 'failed' WHERE "status" = 'scheduled';
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "scheduled_at";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
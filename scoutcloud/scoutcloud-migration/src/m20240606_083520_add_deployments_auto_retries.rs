@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- failed start is dispatched again up to `max_auto_retries` times,
+            -- `auto_retries` counts starts which were already dispatched again
+            ALTER TABLE "deployments"
+                ADD COLUMN "max_auto_retries" INTEGER NOT NULL DEFAULT 0,
+                ADD COLUMN "auto_retries" INTEGER NOT NULL DEFAULT 0;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments"
+                DROP COLUMN IF EXISTS "max_auto_retries",
+                DROP COLUMN IF EXISTS "auto_retries";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
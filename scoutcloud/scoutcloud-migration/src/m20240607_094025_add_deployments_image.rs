@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- image and tag requested for the deployment, override the ones from the config.
+            -- empty values mean that the default of the workflow is used
+            ALTER TABLE "deployments" ADD COLUMN "image" varchar;
+            ALTER TABLE "deployments" ADD COLUMN "image_tag" varchar;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "image_tag";
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "image";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
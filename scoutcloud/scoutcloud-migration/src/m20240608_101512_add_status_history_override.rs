@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- status was set by an administrator bypassing validation of the transition
+            ALTER TABLE "deployment_status_history"
+                ADD COLUMN "forced" boolean NOT NULL DEFAULT false;
+            ALTER TABLE "deployment_status_history" ADD COLUMN "reason" text;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployment_status_history" DROP COLUMN IF EXISTS "reason";
+            ALTER TABLE "deployment_status_history" DROP COLUMN IF EXISTS "forced";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- runs which are waited for together with "deployments"."run_id",
+            -- like separate runs of frontend and indexer of the instance
+            CREATE TABLE "deployment_workflow_runs" (
+              "id" INT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+              "deployment_id" int NOT NULL REFERENCES "deployments" ("id") ON DELETE CASCADE,
+              "run_id" bigint NOT NULL,
+              "created_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+              UNIQUE ("deployment_id", "run_id")
+            );
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            DROP TABLE IF EXISTS "deployment_workflow_runs";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- partial configs with `${variable}` placeholders shared by instances of the user
+            CREATE TABLE "config_templates" (
+              "id" INT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+              "external_id" UUID NOT NULL UNIQUE DEFAULT (gen_random_uuid()),
+              "creator_id" int NOT NULL REFERENCES "users" ("id"),
+              "name" varchar NOT NULL,
+              "config" jsonb NOT NULL DEFAULT '{}',
+              "created_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+              "updated_at" TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT (CURRENT_TIMESTAMP),
+              UNIQUE ("creator_id", "name")
+            );
+
+            -- config of the instance is resolved from the template every time it is started
+            ALTER TABLE "instances"
+              ADD COLUMN "template_id" int REFERENCES "config_templates" ("id"),
+              ADD COLUMN "template_variables" jsonb NOT NULL DEFAULT '{}',
+              ADD COLUMN "template_overrides" jsonb NOT NULL DEFAULT '{}';
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "instances"
+              DROP COLUMN IF EXISTS "template_overrides",
+              DROP COLUMN IF EXISTS "template_variables",
+              DROP COLUMN IF EXISTS "template_id";
+            DROP TABLE IF EXISTS "config_templates";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
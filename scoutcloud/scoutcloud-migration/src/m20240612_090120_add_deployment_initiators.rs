@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            -- who requested the last start and stop of the deployment: `user:<id>` or `system`
+            ALTER TABLE "deployments" ADD COLUMN "started_by" varchar;
+            ALTER TABLE "deployments" ADD COLUMN "stopped_by" varchar;
+            ALTER TABLE "deployments" ADD COLUMN "stop_reason" text;
+            -- who requested the action the change belongs to, `actor` is the one performing it
+            ALTER TABLE "deployment_status_history" ADD COLUMN "initiator" varchar;
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        crate::from_sql(
+            manager,
+            r#"
+            ALTER TABLE "deployment_status_history" DROP COLUMN IF EXISTS "initiator";
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "stop_reason";
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "stopped_by";
+            ALTER TABLE "deployments" DROP COLUMN IF EXISTS "started_by";
+            "#,
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -4,13 +4,7 @@ use scoutcloud::{logic::github::Workflow, server::Settings};
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let settings = Settings::build().expect("failed to read config");
-    let client = scoutcloud::logic::github::GithubClient::new(
-        settings.github.token,
-        settings.github.owner,
-        settings.github.repo,
-        Some("main".to_string()),
-        None,
-    )?;
+    let client = scoutcloud::logic::github::GithubClient::from_settings(&settings.github)?;
 
     let r = scoutcloud::logic::github::DeployWorkflow::get_latest_run(&client, None)
         .await?
@@ -18,6 +12,10 @@ async fn main() -> Result<(), anyhow::Error> {
     println!("{}: {} - {}", r.id, r.name, r.status);
     let r = scoutcloud::logic::github::DeployWorkflow {
         client: "sevenzing-test-2".to_string(),
+        git_ref: None,
+        image: None,
+        tag: None,
+        secrets: Default::default(),
     }
     .run_and_get_latest_with_mutex(&client, 5)
     .await?
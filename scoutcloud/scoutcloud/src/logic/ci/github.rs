@@ -0,0 +1,441 @@
+use super::{CiBackend, CiRun, CiTarget, CiWorkflow};
+use crate::logic::{
+    github::{
+        logs::RunLogs, types::RunConclusion, CleanupWorkflow, DeployWorkflow, PollBackoff, Workflow,
+    },
+    GithubClient, GithubError,
+};
+use octocrab::models::{workflows::Run, RunId};
+use std::{borrow::Cow, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Github doesn't return the run of dispatched workflow,
+/// so the list of runs is checked up to this number of times
+const MAX_TRY_GET_RUN: u8 = 10;
+
+#[async_trait::async_trait]
+impl CiBackend for GithubClient {
+    async fn dispatch(
+        &self,
+        workflow: CiWorkflow,
+        target: &CiTarget,
+    ) -> Result<CiRun, GithubError> {
+        // workflows read values of the instance from the file committed to the repo,
+        // secret references are committed unresolved and their values are sent as input
+        let github = self.for_ci_target(target)?;
+        let client = target.client.clone();
+        tracing::info!(
+            deployment_target = ?target.deployment_target,
+            workflow_repo = ?target.workflow_repo,
+            git_ref = ?target.git_ref,
+            image = ?target.image,
+            tag = ?target.tag,
+            region = ?github.region(),
+            "dispatching {} workflow",
+            workflow.name()
+        );
+        let run = match workflow {
+            CiWorkflow::Deploy => {
+                DeployWorkflow::new(client)
+                    .with_git_ref(target.git_ref.clone())
+                    .with_image(target.image.clone(), target.tag.clone())
+                    .with_secrets(target.secrets.clone())
+                    .run_and_get_latest_with_mutex(&github, MAX_TRY_GET_RUN)
+                    .await?
+            }
+            CiWorkflow::Cleanup => {
+                CleanupWorkflow::new(client)
+                    .run_and_get_latest_with_mutex(&github, MAX_TRY_GET_RUN)
+                    .await?
+            }
+        };
+        let run = run.ok_or(anyhow::anyhow!(
+            "no {} workflow found after running",
+            workflow.name()
+        ))?;
+        Ok(target_run(&run, target))
+    }
+
+    async fn get_run(
+        &self,
+        _workflow: CiWorkflow,
+        target: &CiTarget,
+        run_id: RunId,
+    ) -> Result<CiRun, GithubError> {
+        let github = self.for_ci_target(target)?;
+        let run = github.get_workflow_run(run_id).await?;
+        Ok(target_run(&run, target))
+    }
+
+    async fn wait_for_success(
+        &self,
+        run: &CiRun,
+        timeout: Duration,
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<RunConclusion, GithubError> {
+        self.for_run(run)?
+            .wait_for_success_workflow(run, timeout, backoff, cancel)
+            .await
+    }
+
+    /// Runs are checked together, so they should live in the same repo
+    async fn wait_for_all_success(
+        &self,
+        runs: &[CiRun],
+        timeout: Duration,
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<RunConclusion>, GithubError> {
+        let first = runs.first();
+        let deployment_target = first.and_then(|run| run.deployment_target.as_deref());
+        let workflow_repo = first.and_then(|run| run.workflow_repo.as_deref());
+        if runs.iter().any(|run| {
+            run.deployment_target.as_deref() != deployment_target
+                || run.workflow_repo.as_deref() != workflow_repo
+        }) {
+            return Err(GithubError::Internal(anyhow::anyhow!(
+                "workflow runs of the deployment belong to different repos"
+            )));
+        }
+        self.for_instance(deployment_target, workflow_repo)?
+            .wait_for_success_workflows(runs, timeout, backoff, cancel)
+            .await
+    }
+
+    async fn cancel(&self, run_id: RunId) -> Result<(), GithubError> {
+        self.cancel_workflow_run(run_id).await
+    }
+
+    async fn fetch_logs(
+        &self,
+        target: &CiTarget,
+        run_id: RunId,
+        max_bytes: usize,
+    ) -> Result<RunLogs, GithubError> {
+        let github = self.for_ci_target(target)?;
+        let archive = github.fetch_run_logs(run_id).await?;
+        RunLogs::from_archive(&archive, max_bytes)
+    }
+
+    /// Archive with logs of the run is available only after the run is completed,
+    /// so logs of its finished jobs are downloaded one by one
+    async fn fetch_live_logs(
+        &self,
+        target: &CiTarget,
+        run_id: RunId,
+    ) -> Result<String, GithubError> {
+        self.for_ci_target(target)?
+            .fetch_finished_jobs_logs(run_id)
+            .await
+    }
+
+    fn run_url(
+        &self,
+        deployment_target: Option<&str>,
+        workflow_repo: Option<&str>,
+        run_id: RunId,
+    ) -> Option<String> {
+        let github = self.for_instance(deployment_target, workflow_repo).ok()?;
+        Some(github.run_url(run_id))
+    }
+}
+
+impl GithubClient {
+    fn for_ci_target(&self, target: &CiTarget) -> Result<Cow<'_, GithubClient>, GithubError> {
+        self.for_instance(
+            target.deployment_target.as_deref(),
+            target.workflow_repo.as_deref(),
+        )
+    }
+
+    fn for_run(&self, run: &CiRun) -> Result<Cow<'_, GithubClient>, GithubError> {
+        self.for_instance(
+            run.deployment_target.as_deref(),
+            run.workflow_repo.as_deref(),
+        )
+    }
+}
+
+/// Remembers the repo of the run, so it is waited for in the repo it was dispatched to
+fn target_run(run: &Run, target: &CiTarget) -> CiRun {
+    CiRun {
+        deployment_target: target.deployment_target.clone(),
+        workflow_repo: target.workflow_repo.clone(),
+        ..CiRun::from(run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::github::{MockedGithubRepo, RepoAllowlist, WorkflowFiles},
+        server::{DeploymentTargetSettings, RepoAllowlistSettings, WorkflowFilesSettings},
+    };
+    use pretty_assertions::assert_eq;
+    use std::collections::BTreeMap;
+
+    fn target(client: &str, deployment_target: &str) -> CiTarget {
+        CiTarget {
+            client: client.to_string(),
+            namespace: None,
+            deployment_target: Some(deployment_target.to_string()),
+            workflow_repo: None,
+            git_ref: None,
+            image: None,
+            tag: None,
+            values: serde_json::json!({}),
+            secrets: Default::default(),
+        }
+    }
+
+    fn target_settings(repo: &str, region: &str) -> DeploymentTargetSettings {
+        DeploymentTargetSettings {
+            owner: None,
+            repo: repo.to_string(),
+            branch: None,
+            region: Some(region.to_string()),
+            pinned_workflows: Default::default(),
+            workflow_files: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn instances_are_dispatched_to_repos_of_their_targets() {
+        let repo = MockedGithubRepo::default();
+        let default_handles = repo.build_handles();
+        let eu_handles = repo.build_handles_of_repo("test-repo-eu");
+        let us_handles = repo.build_handles_of_repo("test-repo-us");
+        let github = GithubClient::try_from(&repo)
+            .unwrap()
+            .with_deployment_targets(&BTreeMap::from([
+                ("eu".to_string(), target_settings("test-repo-eu", "eu-west")),
+                ("us".to_string(), target_settings("test-repo-us", "us-east")),
+            ]));
+
+        let eu_run = github
+            .dispatch(CiWorkflow::Deploy, &target("instance-1", "eu"))
+            .await
+            .unwrap();
+        let us_run = github
+            .dispatch(CiWorkflow::Cleanup, &target("instance-2", "us"))
+            .await
+            .unwrap();
+        eu_handles.assert_hits("dispatch_deploy_yaml", 1);
+        eu_handles.assert_hits("dispatch_cleanup_yaml", 0);
+        us_handles.assert_hits("dispatch_cleanup_yaml", 1);
+        us_handles.assert_hits("dispatch_deploy_yaml", 0);
+        default_handles.assert_hits("dispatch_deploy_yaml", 0);
+        default_handles.assert_hits("dispatch_cleanup_yaml", 0);
+        assert_eq!(eu_run.deployment_target.as_deref(), Some("eu"));
+        assert_eq!(us_run.deployment_target.as_deref(), Some("us"));
+
+        // runs are waited for in the repos they were dispatched to
+        github
+            .wait_for_success(
+                &us_run,
+                Duration::from_secs(5),
+                PollBackoff::from_initial(Duration::from_millis(100)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        us_handles.assert("single_run_cleanup_yaml");
+        default_handles.assert_hits("single_run_cleanup_yaml", 0);
+
+        let err = github
+            .dispatch(CiWorkflow::Deploy, &target("instance-3", "asia"))
+            .await
+            .expect_err("unknown target should be rejected");
+        assert!(
+            matches!(err, GithubError::UnknownDeploymentTarget(_)),
+            "{err:?}"
+        );
+        assert!(!err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn instances_with_workflow_repo_are_dispatched_to_their_forks() {
+        let repo = MockedGithubRepo::default();
+        let default_handles = repo.build_handles();
+        let fork_handles = repo.build_handles_of_fork("power-user");
+        let allowlist = RepoAllowlist::from_settings(&RepoAllowlistSettings {
+            repos: vec![
+                "test-owner/test-repo".to_string(),
+                "power-user/test-repo".to_string(),
+            ],
+            deny_if_empty: false,
+        })
+        .unwrap();
+        let github = GithubClient::try_from(&repo)
+            .unwrap()
+            .with_repo_allowlist(allowlist);
+        let default_target = CiTarget {
+            deployment_target: None,
+            ..target("instance-1", "eu")
+        };
+        let fork_target = |workflow_repo: &str| CiTarget {
+            deployment_target: None,
+            workflow_repo: Some(workflow_repo.to_string()),
+            ..target("instance-2", "eu")
+        };
+
+        let default_run = github
+            .dispatch(CiWorkflow::Deploy, &default_target)
+            .await
+            .unwrap();
+        default_handles.assert_hits("dispatch_deploy_yaml", 1);
+        fork_handles.assert_hits("dispatch_deploy_yaml", 0);
+        assert_eq!(default_run.workflow_repo, None);
+
+        let fork_run = github
+            .dispatch(CiWorkflow::Deploy, &fork_target("power-user/test-repo"))
+            .await
+            .unwrap();
+        fork_handles.assert_hits("dispatch_deploy_yaml", 1);
+        default_handles.assert_hits("dispatch_deploy_yaml", 1);
+        assert_eq!(
+            fork_run.workflow_repo.as_deref(),
+            Some("power-user/test-repo")
+        );
+
+        // runs are waited for and linked in the fork they were dispatched to
+        github
+            .wait_for_success(
+                &fork_run,
+                Duration::from_secs(5),
+                PollBackoff::from_initial(Duration::from_millis(100)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        fork_handles.assert("single_run_deploy_yaml");
+        default_handles.assert_hits("single_run_deploy_yaml", 0);
+        assert_eq!(
+            CiBackend::run_url(&github, None, Some("power-user/test-repo"), fork_run.id),
+            Some(format!(
+                "{}/power-user/test-repo/actions/runs/{}",
+                repo.server.base_url(),
+                fork_run.id
+            ))
+        );
+
+        let err = github
+            .dispatch(CiWorkflow::Deploy, &fork_target("other-user/test-repo"))
+            .await
+            .expect_err("fork outside of allowlist should be rejected");
+        assert!(matches!(err, GithubError::RepoNotAllowed(_)), "{err:?}");
+        assert!(!err.is_retryable());
+
+        // forks are chosen by users, so empty allowlist doesn't allow them
+        let err = GithubClient::try_from(&repo)
+            .unwrap()
+            .dispatch(CiWorkflow::Deploy, &fork_target("power-user/test-repo"))
+            .await
+            .expect_err("fork should be listed in the allowlist");
+        assert!(matches!(err, GithubError::RepoNotAllowed(_)), "{err:?}");
+        fork_handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    async fn targets_dispatch_their_workflow_files() {
+        let repo = MockedGithubRepo::default();
+        let files = WorkflowFilesSettings {
+            deploy: "start.yml".to_string(),
+            cleanup: "stop.yml".to_string(),
+        };
+        let eu_handles = repo.build_handles_with_workflow_files(
+            "test-repo-eu",
+            &WorkflowFiles::from_settings(&files),
+        );
+        let us_handles = repo.build_handles_of_repo("test-repo-us");
+        let github = GithubClient::try_from(&repo)
+            .unwrap()
+            .with_deployment_targets(&BTreeMap::from([
+                (
+                    "eu".to_string(),
+                    DeploymentTargetSettings {
+                        workflow_files: Some(files),
+                        ..target_settings("test-repo-eu", "eu-west")
+                    },
+                ),
+                ("us".to_string(), target_settings("test-repo-us", "us-east")),
+            ]));
+
+        for workflow in [CiWorkflow::Deploy, CiWorkflow::Cleanup] {
+            github
+                .dispatch(workflow, &target("instance-1", "eu"))
+                .await
+                .unwrap();
+        }
+        eu_handles.assert_hits("dispatch_deploy_yaml", 1);
+        eu_handles.assert_hits("dispatch_cleanup_yaml", 1);
+
+        // targets without their own files use files of the client
+        github
+            .dispatch(CiWorkflow::Cleanup, &target("instance-2", "us"))
+            .await
+            .unwrap();
+        us_handles.assert_hits("dispatch_cleanup_yaml", 1);
+        us_handles.assert_hits("dispatch_deploy_yaml", 0);
+    }
+
+    #[tokio::test]
+    async fn targets_outside_of_allowlist_are_not_dispatched() {
+        let repo = MockedGithubRepo::default();
+        let eu_handles = repo.build_handles_of_repo("test-repo-eu");
+        let us_handles = repo.build_handles_of_repo("test-repo-us");
+        let targets = BTreeMap::from([
+            ("eu".to_string(), target_settings("test-repo-eu", "eu-west")),
+            ("us".to_string(), target_settings("test-repo-us", "us-east")),
+        ]);
+        let allowlist = |repos: &[&str], deny_if_empty: bool| {
+            RepoAllowlist::from_settings(&RepoAllowlistSettings {
+                repos: repos.iter().map(|repo| repo.to_string()).collect(),
+                deny_if_empty,
+            })
+            .unwrap()
+        };
+        let github = GithubClient::try_from(&repo)
+            .unwrap()
+            .with_repo_allowlist(allowlist(&["test-owner/test-repo-eu"], false))
+            .with_deployment_targets(&targets);
+
+        github
+            .dispatch(CiWorkflow::Deploy, &target("instance-1", "eu"))
+            .await
+            .unwrap();
+        eu_handles.assert_hits("dispatch_deploy_yaml", 1);
+        let err = github
+            .dispatch(CiWorkflow::Deploy, &target("instance-2", "us"))
+            .await
+            .expect_err("repo outside of allowlist should be rejected");
+        assert_eq!(
+            err.to_string(),
+            "repo `test-owner/test-repo-us` is not in the allowlist of repos, \
+             its workflows can't be dispatched"
+        );
+        assert!(!err.is_retryable());
+        us_handles.assert_hits("dispatch_deploy_yaml", 0);
+
+        // empty allowlist denies every repo only if it is configured so
+        let with_empty_allowlist = |deny_if_empty| {
+            GithubClient::try_from(&repo)
+                .unwrap()
+                .with_repo_allowlist(allowlist(&[], deny_if_empty))
+                .with_deployment_targets(&targets)
+        };
+        with_empty_allowlist(false)
+            .dispatch(CiWorkflow::Deploy, &target("instance-1", "eu"))
+            .await
+            .unwrap();
+        eu_handles.assert_hits("dispatch_deploy_yaml", 2);
+        let err = with_empty_allowlist(true)
+            .dispatch(CiWorkflow::Deploy, &target("instance-1", "eu"))
+            .await
+            .expect_err("empty allowlist should deny all repos");
+        assert!(matches!(err, GithubError::RepoNotAllowed(_)), "{err:?}");
+        eu_handles.assert_hits("dispatch_deploy_yaml", 2);
+    }
+}
@@ -0,0 +1,382 @@
+use super::{poll_until_success, CiBackend, CiRun, CiTarget, CiWorkflow};
+use crate::{
+    logic::{
+        github::{
+            logs::RunLogs,
+            types::{RunConclusion, RunStatus},
+            PollBackoff,
+        },
+        GithubError,
+    },
+    server::GitlabSettings,
+};
+use octocrab::models::RunId;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs workflows of instances as pipelines of the gitlab project.
+/// Pipeline gets the workflow and the instance in `WORKFLOW` and `CLIENT` variables.
+/// Resolved secret references of the config are passed in `SECRETS` variable as json
+/// object keyed by their fields, the pipeline should mask them before use
+#[derive(Clone)]
+pub struct GitlabClient {
+    http: reqwest::Client,
+    base_url: Url,
+    token: String,
+    project: String,
+    branch: String,
+}
+
+impl std::fmt::Debug for GitlabClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GitlabClient")
+            .field("base_url", &self.base_url.as_str())
+            .field("project", &self.project)
+            .field("branch", &self.branch)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct CreatePipelineRequest<'a> {
+    #[serde(rename = "ref")]
+    _ref: &'a str,
+    variables: Vec<PipelineVariable<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+struct PipelineVariable<'a> {
+    key: &'a str,
+    value: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct Pipeline {
+    id: u64,
+    status: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Job {
+    id: u64,
+    name: String,
+}
+
+impl GitlabClient {
+    pub fn from_settings(settings: &GitlabSettings) -> Result<Self, GithubError> {
+        let base_url = Url::parse(&settings.base_url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https") && !url.cannot_be_a_base())
+            .ok_or(anyhow::anyhow!(
+                "invalid gitlab base url `{}`",
+                settings.base_url
+            ))?;
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            http,
+            base_url,
+            token: settings.token.clone(),
+            project: settings.project.clone(),
+            branch: settings.branch.clone(),
+        })
+    }
+
+    /// Project is a single segment of the path, so slashes in its full path are escaped
+    fn url(&self, path: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("base url is validated")
+            .pop_if_empty()
+            .extend(["api", "v4", "projects", self.project.as_str()])
+            .extend(path);
+        url
+    }
+
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, GithubError> {
+        let response = request
+            .header("PRIVATE-TOKEN", &self.token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response)
+    }
+
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, GithubError> {
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    async fn get_pipeline(&self, run_id: RunId) -> Result<Pipeline, GithubError> {
+        let url = self.url(&["pipelines", &run_id.to_string()]);
+        self.send_json(self.http.get(url)).await
+    }
+}
+
+/// Maps status of the pipeline to status of github workflow run,
+/// conclusion is returned only for completed pipelines
+fn pipeline_state(status: &str) -> Result<(RunStatus, Option<RunConclusion>), GithubError> {
+    let conclusion = match status {
+        "created" | "waiting_for_resource" | "preparing" | "pending" | "scheduled" => {
+            return Ok((RunStatus::Queued, None))
+        }
+        "running" => return Ok((RunStatus::InProgress, None)),
+        "success" => RunConclusion::Success,
+        "failed" => RunConclusion::Failure,
+        "canceled" => RunConclusion::Cancelled,
+        "skipped" => RunConclusion::Skipped,
+        // pipeline is blocked until someone runs its manual jobs
+        "manual" => RunConclusion::ActionRequired,
+        status => {
+            return Err(GithubError::Internal(anyhow::anyhow!(
+                "invalid pipeline status from gitlab: {status}"
+            )))
+        }
+    };
+    Ok((RunStatus::Completed, Some(conclusion)))
+}
+
+impl From<Pipeline> for CiRun {
+    fn from(pipeline: Pipeline) -> Self {
+        let name = pipeline
+            .name
+            .unwrap_or_else(|| format!("pipeline {}", pipeline.id));
+        Self::new(RunId(pipeline.id), name)
+    }
+}
+
+#[async_trait::async_trait]
+impl CiBackend for GitlabClient {
+    async fn dispatch(
+        &self,
+        workflow: CiWorkflow,
+        target: &CiTarget,
+    ) -> Result<CiRun, GithubError> {
+        let client = target.client.as_str();
+        let secrets = (!target.secrets.is_empty())
+            .then(|| serde_json::to_string(&target.secrets))
+            .transpose()
+            .map_err(anyhow::Error::from)?;
+        let mut variables = vec![
+            PipelineVariable {
+                key: "WORKFLOW",
+                value: workflow.name(),
+            },
+            PipelineVariable {
+                key: "CLIENT",
+                value: client,
+            },
+        ];
+        if let Some(secrets) = &secrets {
+            variables.push(PipelineVariable {
+                key: "SECRETS",
+                value: secrets,
+            });
+        }
+        let request = CreatePipelineRequest {
+            _ref: &self.branch,
+            variables,
+        };
+        let pipeline: Pipeline = self
+            .send_json(self.http.post(self.url(&["pipeline"])).json(&request))
+            .await?;
+        let name = format!("{} {client}", workflow.name());
+        Ok(CiRun::new(
+            RunId(pipeline.id),
+            pipeline.name.unwrap_or(name),
+        ))
+    }
+
+    async fn get_run(
+        &self,
+        _workflow: CiWorkflow,
+        _target: &CiTarget,
+        run_id: RunId,
+    ) -> Result<CiRun, GithubError> {
+        Ok(self.get_pipeline(run_id).await?.into())
+    }
+
+    #[tracing::instrument(skip_all, fields(run_id = run.id.0), level = "info")]
+    async fn wait_for_success(
+        &self,
+        run: &CiRun,
+        timeout: Duration,
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<RunConclusion, GithubError> {
+        tracing::info!("waiting for gitlab pipeline '{}'", run.name);
+        poll_until_success(run, timeout, backoff, cancel, || async {
+            let pipeline = self.get_pipeline(run.id).await?;
+            pipeline_state(&pipeline.status)
+        })
+        .await
+    }
+
+    async fn cancel(&self, run_id: RunId) -> Result<(), GithubError> {
+        let url = self.url(&["pipelines", &run_id.to_string(), "cancel"]);
+        let _: Pipeline = self.send_json(self.http.post(url)).await?;
+        Ok(())
+    }
+
+    async fn fetch_logs(
+        &self,
+        _target: &CiTarget,
+        run_id: RunId,
+        max_bytes: usize,
+    ) -> Result<RunLogs, GithubError> {
+        let url = self.url(&["pipelines", &run_id.to_string(), "jobs"]);
+        let mut jobs: Vec<Job> = self
+            .send_json(self.http.get(url).query(&[("per_page", "100")]))
+            .await?;
+        jobs.sort_by_key(|job| job.id);
+
+        let mut logs = String::new();
+        for job in jobs {
+            let url = self.url(&["jobs", &job.id.to_string(), "trace"]);
+            let trace = self.send(self.http.get(url)).await?.text().await?;
+            logs.push_str(&format!("===== {} =====\n", job.name));
+            logs.push_str(&trace);
+            if !logs.ends_with('\n') {
+                logs.push('\n');
+            }
+        }
+        Ok(RunLogs::from_text(&logs, max_bytes))
+    }
+
+    /// Page of the pipeline is addressed by the full path of the project, not by its id
+    fn run_url(
+        &self,
+        _deployment_target: Option<&str>,
+        _workflow_repo: Option<&str>,
+        run_id: RunId,
+    ) -> Option<String> {
+        if self.project.parse::<u64>().is_ok() {
+            return None;
+        }
+        let base_url = self.base_url.as_str().trim_end_matches('/');
+        Some(format!("{base_url}/{}/-/pipelines/{run_id}", self.project))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::{
+        Method::{GET, POST},
+        MockServer,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn client(server: &MockServer) -> GitlabClient {
+        GitlabClient::from_settings(&GitlabSettings {
+            base_url: server.base_url(),
+            token: "test-token".to_string(),
+            project: "123".to_string(),
+            branch: "main".to_string(),
+        })
+        .unwrap()
+    }
+
+    fn backoff() -> PollBackoff {
+        PollBackoff::from_initial(Duration::from_millis(10)).with_jitter(0.0)
+    }
+
+    #[tokio::test]
+    async fn pipeline_is_dispatched_and_awaited() {
+        let server = MockServer::start_async().await;
+        let dispatch = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/api/v4/projects/123/pipeline")
+                    .header("PRIVATE-TOKEN", "test-token")
+                    .json_body(serde_json::json!({
+                        "ref": "main",
+                        "variables": [
+                            {"key": "WORKFLOW", "value": "cleanup"},
+                            {"key": "CLIENT", "value": "instance-1"},
+                        ]
+                    }));
+                then.status(201)
+                    .json_body(serde_json::json!({"id": 42, "status": "created"}));
+            })
+            .await;
+        let status = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/v4/projects/123/pipelines/42");
+                then.json_body(serde_json::json!({"id": 42, "status": "success"}));
+            })
+            .await;
+        let client = client(&server);
+
+        let target = CiTarget {
+            client: "instance-1".to_string(),
+            namespace: None,
+            deployment_target: None,
+            workflow_repo: None,
+            git_ref: None,
+            image: None,
+            tag: None,
+            values: serde_json::json!({}),
+            secrets: Default::default(),
+        };
+        let run = client.dispatch(CiWorkflow::Cleanup, &target).await.unwrap();
+        assert_eq!(run, CiRun::new(RunId(42), "cleanup instance-1".to_string()));
+        let conclusion = client
+            .wait_for_success(
+                &run,
+                Duration::from_secs(1),
+                backoff(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(conclusion, RunConclusion::Success);
+        dispatch.assert_hits_async(1).await;
+        status.assert_hits_async(1).await;
+    }
+
+    #[tokio::test]
+    async fn failed_pipeline_fails_workflow() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/v4/projects/123/pipelines/42");
+                then.json_body(serde_json::json!({"id": 42, "status": "failed"}));
+            })
+            .await;
+        let client = client(&server);
+        let run = CiRun::new(RunId(42), "pipeline 42".to_string());
+
+        let err = client
+            .wait_for_success(
+                &run,
+                Duration::from_secs(1),
+                backoff(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                GithubError::WorkflowFailed {
+                    conclusion: RunConclusion::Failure,
+                    ..
+                }
+            ),
+            "unexpected error: {err}"
+        );
+    }
+}
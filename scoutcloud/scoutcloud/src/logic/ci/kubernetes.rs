@@ -0,0 +1,614 @@
+use super::{poll_until_success, CiBackend, CiRun, CiTarget, CiWorkflow};
+use crate::{
+    logic::{
+        github::{
+            logs::RunLogs,
+            types::{RunConclusion, RunStatus},
+            PollBackoff,
+        },
+        GithubError,
+    },
+    server::{HelmChartSettings, KubernetesSettings},
+};
+use octocrab::models::RunId;
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const FIELD_MANAGER: &str = "scoutcloud";
+const HELM_RELEASE_API_VERSION: &str = "helm.toolkit.fluxcd.io/v2beta1";
+const INSTANCE_LABEL: &str = "app.kubernetes.io/instance";
+const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+/// Only the end of logs of every container is fetched
+const MAX_LOG_LINES: &str = "500";
+
+/// Rollout of the helm release of the instance. Run id is the generation
+/// of the release which should be rolled out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rollout {
+    pub workflow: CiWorkflow,
+    pub namespace: String,
+    pub release: String,
+    pub deployment: String,
+}
+
+/// Deploys instances as flux `HelmRelease` resources instead of running ci workflows.
+/// Values of the release are the parsed config of the deployment, resolved secret
+/// references are kept in a `Secret` which the release takes values from.
+/// Readiness is decided by rollout status of blockscout `Deployment`
+#[derive(Clone)]
+pub struct KubernetesClient {
+    http: reqwest::Client,
+    api_url: String,
+    token: String,
+    namespace: String,
+    deployment: String,
+    chart: HelmChartSettings,
+}
+
+impl std::fmt::Debug for KubernetesClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KubernetesClient")
+            .field("api_url", &self.api_url)
+            .field("namespace", &self.namespace)
+            .field("chart", &self.chart)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KubernetesClient {
+    pub fn from_settings(settings: &KubernetesSettings) -> Result<Self, GithubError> {
+        let is_valid = url::Url::parse(&settings.api_url)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+        if !is_valid {
+            return Err(
+                anyhow::anyhow!("invalid kubernetes api url `{}`", settings.api_url).into(),
+            );
+        }
+        let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(ca_cert) = &settings.ca_cert {
+            builder =
+                builder.add_root_certificate(reqwest::Certificate::from_pem(ca_cert.as_bytes())?);
+        }
+        Ok(Self {
+            http: builder.build()?,
+            api_url: settings.api_url.trim_end_matches('/').to_string(),
+            token: settings.token.clone(),
+            namespace: settings.namespace.clone(),
+            deployment: settings.deployment.clone(),
+            chart: settings.chart.clone(),
+        })
+    }
+
+    fn rollout(&self, workflow: CiWorkflow, target: &CiTarget) -> Rollout {
+        let with_client = |template: &str| template.replace("{client}", &target.client);
+        Rollout {
+            workflow,
+            namespace: target
+                .namespace
+                .clone()
+                .unwrap_or_else(|| with_client(&self.namespace)),
+            release: target.client.clone(),
+            deployment: with_client(&self.deployment),
+        }
+    }
+
+    fn run(&self, rollout: Rollout, generation: u64) -> CiRun {
+        let name = format!(
+            "{} {}/{}",
+            rollout.workflow.name(),
+            rollout.namespace,
+            rollout.release
+        );
+        CiRun {
+            id: RunId(generation),
+            name,
+            rollout: Some(rollout),
+            deployment_target: None,
+            workflow_repo: None,
+        }
+    }
+
+    fn namespace_url(&self, namespace: &str) -> String {
+        format!("{}/api/v1/namespaces/{namespace}", self.api_url)
+    }
+
+    fn release_url(&self, rollout: &Rollout) -> String {
+        format!(
+            "{}/apis/{HELM_RELEASE_API_VERSION}/namespaces/{}/helmreleases/{}",
+            self.api_url, rollout.namespace, rollout.release
+        )
+    }
+
+    fn secrets_url(&self, namespace: &str) -> String {
+        format!("{}/secrets", self.namespace_url(namespace))
+    }
+
+    fn deployment_url(&self, rollout: &Rollout) -> String {
+        format!(
+            "{}/apis/apps/v1/namespaces/{}/deployments/{}",
+            self.api_url, rollout.namespace, rollout.deployment
+        )
+    }
+
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, GithubError> {
+        let response = request
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response)
+    }
+
+    /// Returns `None` if the object doesn't exist
+    async fn get_object(&self, url: &str) -> Result<Option<Value>, GithubError> {
+        let response = self.http.get(url).bearer_auth(&self.token).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.json().await?))
+    }
+
+    /// Creates or updates the object with server-side apply
+    async fn apply(&self, url: &str, object: &Value) -> Result<Value, GithubError> {
+        let request = self
+            .http
+            .patch(url)
+            .query(&[("fieldManager", FIELD_MANAGER), ("force", "true")])
+            .header(CONTENT_TYPE, "application/apply-patch+yaml")
+            .body(object.to_string());
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    fn helm_release(
+        &self,
+        rollout: &Rollout,
+        values: &Value,
+        secrets: &BTreeMap<String, String>,
+    ) -> Value {
+        let mut release = json!({
+            "apiVersion": HELM_RELEASE_API_VERSION,
+            "kind": "HelmRelease",
+            "metadata": {
+                "name": rollout.release,
+                "namespace": rollout.namespace,
+                "labels": {MANAGED_BY_LABEL: FIELD_MANAGER},
+            },
+            "spec": {
+                "interval": "10m",
+                "chart": {
+                    "spec": {
+                        "chart": self.chart.name,
+                        "version": self.chart.version,
+                        "sourceRef": {
+                            "kind": "HelmRepository",
+                            "name": self.chart.repository,
+                            "namespace": self.chart.repository_namespace,
+                        },
+                    },
+                },
+                "values": values,
+            },
+        });
+        if !secrets.is_empty() {
+            let name = secret_name(rollout, secrets);
+            let values_from: Vec<Value> = secrets
+                .keys()
+                .map(|field| {
+                    json!({
+                        "kind": "Secret",
+                        "name": name,
+                        "valuesKey": field,
+                        "targetPath": field,
+                    })
+                })
+                .collect();
+            release["spec"]["valuesFrom"] = json!(values_from);
+        }
+        release
+    }
+
+    fn values_secret(&self, rollout: &Rollout, secrets: &BTreeMap<String, String>) -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {
+                "name": secret_name(rollout, secrets),
+                "namespace": rollout.namespace,
+                "labels": {MANAGED_BY_LABEL: FIELD_MANAGER, INSTANCE_LABEL: rollout.release},
+            },
+            "type": "Opaque",
+            "stringData": secrets,
+        })
+    }
+
+    async fn container_logs(
+        &self,
+        namespace: &str,
+        pod: &str,
+        container: &str,
+    ) -> Result<String, GithubError> {
+        let url = format!("{}/pods/{pod}/log", self.namespace_url(namespace));
+        let request = self
+            .http
+            .get(url)
+            .query(&[("container", container), ("tailLines", MAX_LOG_LINES)]);
+        Ok(self.send(request).await?.text().await?)
+    }
+}
+
+/// Name of the secret changes with its data, so the release gets a new generation
+/// and is rolled out again even if only secrets are changed
+fn secret_name(rollout: &Rollout, secrets: &BTreeMap<String, String>) -> String {
+    let data = serde_json::to_vec(secrets).expect("map of strings is serializable");
+    let digest = hex::encode(Sha256::digest(data));
+    format!("{}-secrets-{}", rollout.release, &digest[..10])
+}
+
+fn object_generation(object: &Value) -> u64 {
+    object["metadata"]["generation"]
+        .as_u64()
+        .unwrap_or_default()
+}
+
+fn condition<'a>(object: &'a Value, condition_type: &str) -> Option<&'a Value> {
+    object["status"]["conditions"]
+        .as_array()?
+        .iter()
+        .find(|condition| condition["type"] == condition_type)
+}
+
+/// State of the rollout of the release `generation` by the objects in the cluster,
+/// conclusion is returned only for completed rollouts
+fn rollout_state(
+    rollout: &Rollout,
+    generation: u64,
+    release: Option<&Value>,
+    deployment: Option<&Value>,
+) -> (RunStatus, Option<RunConclusion>) {
+    let in_progress = (RunStatus::InProgress, None);
+    let completed = |conclusion| (RunStatus::Completed, Some(conclusion));
+    if rollout.workflow == CiWorkflow::Cleanup {
+        return match (release, deployment) {
+            (None, None) => completed(RunConclusion::Success),
+            _ => in_progress,
+        };
+    }
+
+    let Some(release) = release else {
+        // release was deleted while it was rolled out
+        return completed(RunConclusion::Cancelled);
+    };
+    if release["status"]["observedGeneration"]
+        .as_u64()
+        .unwrap_or_default()
+        < generation
+    {
+        return (RunStatus::Queued, None);
+    }
+    if let Some(ready) = condition(release, "Ready") {
+        let reason = ready["reason"].as_str().unwrap_or_default();
+        if ready["status"] == "False" && reason.ends_with("Failed") {
+            return completed(RunConclusion::Failure);
+        }
+    }
+
+    let Some(deployment) = deployment else {
+        return in_progress;
+    };
+    if condition(deployment, "Progressing")
+        .is_some_and(|progressing| progressing["reason"] == "ProgressDeadlineExceeded")
+    {
+        return completed(RunConclusion::TimedOut);
+    }
+    let status = &deployment["status"];
+    let count = |field: &str| status[field].as_u64().unwrap_or_default();
+    let replicas = deployment["spec"]["replicas"].as_u64().unwrap_or(1);
+    // old replicas are gone and all new ones are available
+    let rolled_out = count("observedGeneration") >= object_generation(deployment)
+        && count("updatedReplicas") >= replicas
+        && count("availableReplicas") >= replicas
+        && count("replicas") <= count("updatedReplicas");
+    if rolled_out {
+        completed(RunConclusion::Success)
+    } else {
+        in_progress
+    }
+}
+
+#[async_trait::async_trait]
+impl CiBackend for KubernetesClient {
+    async fn dispatch(
+        &self,
+        workflow: CiWorkflow,
+        target: &CiTarget,
+    ) -> Result<CiRun, GithubError> {
+        let rollout = self.rollout(workflow, target);
+        let generation = match workflow {
+            CiWorkflow::Deploy => {
+                let namespace = json!({
+                    "apiVersion": "v1",
+                    "kind": "Namespace",
+                    "metadata": {"name": rollout.namespace},
+                });
+                self.apply(&self.namespace_url(&rollout.namespace), &namespace)
+                    .await?;
+                if !target.secrets.is_empty() {
+                    let secret = self.values_secret(&rollout, &target.secrets);
+                    let url = format!(
+                        "{}/{}",
+                        self.secrets_url(&rollout.namespace),
+                        secret_name(&rollout, &target.secrets)
+                    );
+                    self.apply(&url, &secret).await?;
+                }
+                let release = self.helm_release(&rollout, &target.values, &target.secrets);
+                let applied = self.apply(&self.release_url(&rollout), &release).await?;
+                object_generation(&applied)
+            }
+            CiWorkflow::Cleanup => {
+                let response = self
+                    .http
+                    .delete(self.release_url(&rollout))
+                    .bearer_auth(&self.token)
+                    .send()
+                    .await?;
+                let generation = if response.status() == StatusCode::NOT_FOUND {
+                    0
+                } else {
+                    let deleted: Value = response.error_for_status()?.json().await?;
+                    object_generation(&deleted)
+                };
+                // secrets of all previous rollouts are deleted together with the release
+                let selector = format!(
+                    "{MANAGED_BY_LABEL}={FIELD_MANAGER},{INSTANCE_LABEL}={}",
+                    rollout.release
+                );
+                let response = self
+                    .http
+                    .delete(self.secrets_url(&rollout.namespace))
+                    .query(&[("labelSelector", selector)])
+                    .bearer_auth(&self.token)
+                    .send()
+                    .await?;
+                if response.status() != StatusCode::NOT_FOUND {
+                    response.error_for_status()?;
+                }
+                generation
+            }
+        };
+        Ok(self.run(rollout, generation))
+    }
+
+    async fn get_run(
+        &self,
+        workflow: CiWorkflow,
+        target: &CiTarget,
+        run_id: RunId,
+    ) -> Result<CiRun, GithubError> {
+        Ok(self.run(self.rollout(workflow, target), run_id.0))
+    }
+
+    #[tracing::instrument(skip_all, fields(run_id = run.id.0), level = "info")]
+    async fn wait_for_success(
+        &self,
+        run: &CiRun,
+        timeout: Duration,
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<RunConclusion, GithubError> {
+        let rollout = run.rollout.as_ref().ok_or(anyhow::anyhow!(
+            "run '{}' is not a rollout of kubernetes",
+            run.name
+        ))?;
+        tracing::info!("waiting for kubernetes rollout '{}'", run.name);
+        poll_until_success(run, timeout, backoff, cancel, || async {
+            let (release, deployment) = tokio::try_join!(
+                self.get_object(&self.release_url(rollout)),
+                self.get_object(&self.deployment_url(rollout)),
+            )?;
+            Ok::<_, GithubError>(rollout_state(
+                rollout,
+                run.id.0,
+                release.as_ref(),
+                deployment.as_ref(),
+            ))
+        })
+        .await
+    }
+
+    async fn cancel(&self, run_id: RunId) -> Result<(), GithubError> {
+        Err(anyhow::anyhow!("rollout {run_id} cannot be cancelled by kubernetes backend").into())
+    }
+
+    async fn fetch_logs(
+        &self,
+        target: &CiTarget,
+        _run_id: RunId,
+        max_bytes: usize,
+    ) -> Result<RunLogs, GithubError> {
+        let rollout = self.rollout(CiWorkflow::Deploy, target);
+        let url = format!("{}/pods", self.namespace_url(&rollout.namespace));
+        let selector = format!("{INSTANCE_LABEL}={}", rollout.release);
+        let pods: Value = self
+            .send(self.http.get(url).query(&[("labelSelector", selector)]))
+            .await?
+            .json()
+            .await?;
+
+        let mut containers: Vec<(String, String)> = pods["items"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|pod| {
+                let pod_name = pod["metadata"]["name"].as_str().unwrap_or_default();
+                pod["spec"]["containers"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(move |container| {
+                        let container = container["name"].as_str()?;
+                        Some((pod_name.to_string(), container.to_string()))
+                    })
+            })
+            .collect();
+        containers.sort();
+
+        let mut logs = String::new();
+        for (pod, container) in containers {
+            let container_logs = self
+                .container_logs(&rollout.namespace, &pod, &container)
+                .await?;
+            logs.push_str(&format!("===== {pod}/{container} =====\n"));
+            logs.push_str(&container_logs);
+            if !logs.ends_with('\n') {
+                logs.push('\n');
+            }
+        }
+        Ok(RunLogs::from_text(&logs, max_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn rollout(workflow: CiWorkflow) -> Rollout {
+        Rollout {
+            workflow,
+            namespace: "instance-1".to_string(),
+            release: "instance-1".to_string(),
+            deployment: "instance-1-blockscout-stack-blockscout".to_string(),
+        }
+    }
+
+    fn release(observed_generation: u64, ready: &str, reason: &str) -> Value {
+        json!({
+            "metadata": {"generation": 2},
+            "status": {
+                "observedGeneration": observed_generation,
+                "conditions": [{"type": "Ready", "status": ready, "reason": reason}],
+            },
+        })
+    }
+
+    fn deployment(updated: u64, available: u64, replicas: u64) -> Value {
+        json!({
+            "metadata": {"generation": 5},
+            "spec": {"replicas": 2},
+            "status": {
+                "observedGeneration": 5,
+                "updatedReplicas": updated,
+                "availableReplicas": available,
+                "replicas": replicas,
+            },
+        })
+    }
+
+    #[test]
+    fn resolved_secrets_are_taken_from_secret() {
+        let client = KubernetesClient::from_settings(&KubernetesSettings {
+            api_url: "https://kubernetes.example.com".to_string(),
+            token: "test-token".to_string(),
+            ca_cert: None,
+            namespace: "{client}".to_string(),
+            deployment: "{client}-blockscout-stack-blockscout".to_string(),
+            chart: HelmChartSettings {
+                name: "blockscout-stack".to_string(),
+                version: None,
+                repository: "blockscout".to_string(),
+                repository_namespace: "flux-system".to_string(),
+            },
+        })
+        .unwrap();
+        let deploy = rollout(CiWorkflow::Deploy);
+        let field = "blockscout.env.ETHEREUM_JSONRPC_HTTP_URL";
+        let secrets = |value: &str| BTreeMap::from([(field.to_string(), value.to_string())]);
+        let values = json!({"blockscout": {"env": {"CHAIN_ID": "77"}}});
+
+        let release =
+            client.helm_release(&deploy, &values, &secrets("https://rpc.example.com/key"));
+        let secret = client.values_secret(&deploy, &secrets("https://rpc.example.com/key"));
+        assert!(
+            !release.to_string().contains("rpc.example.com"),
+            "secret is inlined into release: {release}"
+        );
+        assert_eq!(release["spec"]["values"], values);
+        assert_eq!(
+            release["spec"]["valuesFrom"],
+            json!([{
+                "kind": "Secret",
+                "name": secret["metadata"]["name"],
+                "valuesKey": field,
+                "targetPath": field,
+            }])
+        );
+        assert_eq!(
+            secret["stringData"],
+            json!({field: "https://rpc.example.com/key"})
+        );
+        assert_eq!(secret["metadata"]["labels"][INSTANCE_LABEL], "instance-1");
+
+        // changed secret is rolled out as a new generation of the release
+        let changed =
+            client.helm_release(&deploy, &values, &secrets("https://rpc.example.com/new"));
+        assert_ne!(changed["spec"], release["spec"]);
+        let release = client.helm_release(&deploy, &values, &BTreeMap::new());
+        assert!(release["spec"].get("valuesFrom").is_none());
+    }
+
+    #[test]
+    fn rollout_state_follows_release_and_deployment() {
+        let deploy = rollout(CiWorkflow::Deploy);
+        let state = |release: Option<&Value>, deployment: Option<&Value>| {
+            rollout_state(&deploy, 2, release, deployment)
+        };
+        let reconciled = release(2, "True", "ReconciliationSucceeded");
+
+        // helm controller didn't pick up the new values yet
+        assert_eq!(
+            state(Some(&release(1, "True", "ReconciliationSucceeded")), None),
+            (RunStatus::Queued, None)
+        );
+        assert_eq!(
+            state(Some(&reconciled), None),
+            (RunStatus::InProgress, None)
+        );
+        // old replica is still running
+        assert_eq!(
+            state(Some(&reconciled), Some(&deployment(2, 2, 3))),
+            (RunStatus::InProgress, None)
+        );
+        assert_eq!(
+            state(Some(&reconciled), Some(&deployment(2, 2, 2))),
+            (RunStatus::Completed, Some(RunConclusion::Success))
+        );
+        assert_eq!(
+            state(Some(&release(2, "False", "UpgradeFailed")), None),
+            (RunStatus::Completed, Some(RunConclusion::Failure))
+        );
+        let mut stuck = deployment(1, 1, 2);
+        stuck["status"]["conditions"] = json!([{"type": "Progressing", "status": "False", "reason": "ProgressDeadlineExceeded"}]);
+        assert_eq!(
+            state(Some(&reconciled), Some(&stuck)),
+            (RunStatus::Completed, Some(RunConclusion::TimedOut))
+        );
+
+        let cleanup = rollout(CiWorkflow::Cleanup);
+        assert_eq!(
+            rollout_state(&cleanup, 0, None, Some(&deployment(2, 2, 2))),
+            (RunStatus::InProgress, None)
+        );
+        assert_eq!(
+            rollout_state(&cleanup, 0, None, None),
+            (RunStatus::Completed, Some(RunConclusion::Success))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+mod github;
+mod gitlab;
+mod kubernetes;
+
+pub use gitlab::GitlabClient;
+pub use kubernetes::KubernetesClient;
+
+use crate::{
+    logic::{
+        github::{
+            logs::RunLogs,
+            record_poll,
+            types::{RunConclusion, RunStatus},
+            PollBackoff,
+        },
+        GithubClient, GithubError,
+    },
+    server::{CiBackendType, CiSettings},
+};
+use kubernetes::Rollout;
+use octocrab::models::{workflows::Run, RunId};
+use std::{collections::BTreeMap, fmt::Debug, future::Future, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Live logs are fetched again on every poll, so only their end is fetched
+const MAX_LIVE_LOGS_BYTES: usize = 1024 * 1024;
+
+/// Workflows which are run by ci for every instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiWorkflow {
+    Deploy,
+    Cleanup,
+}
+
+impl CiWorkflow {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CiWorkflow::Deploy => "deploy",
+            CiWorkflow::Cleanup => "cleanup",
+        }
+    }
+}
+
+/// Instance which the workflow is run for. Values are its parsed config,
+/// they have decrypted secrets only in targets of dispatches, so they are never logged
+#[derive(Clone, PartialEq)]
+pub struct CiTarget {
+    /// Slug of the instance
+    pub client: String,
+    /// Kubernetes namespace from the config of the instance, if it is set
+    pub namespace: Option<String>,
+    /// Deployment target from the config of the instance, used only by github backend
+    pub deployment_target: Option<String>,
+    /// Fork of the workflows from the config of the instance, used only by github backend
+    pub workflow_repo: Option<String>,
+    /// Branch or tag to deploy from, used only by github backend
+    pub git_ref: Option<String>,
+    /// Image and tag to deploy instead of the defaults of the workflow,
+    /// used only by github backend
+    pub image: Option<String>,
+    pub tag: Option<String>,
+    pub values: serde_json::Value,
+    /// Resolved secret references of the config keyed by their fields, they are taken
+    /// out of `values` and set only in targets of dispatches
+    pub secrets: BTreeMap<String, String>,
+}
+
+/// Single run of the workflow, it is a pipeline in terms of gitlab
+/// and a rollout of helm release in terms of kubernetes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CiRun {
+    pub id: RunId,
+    pub name: String,
+    /// Set only by kubernetes backend, since rollout is not identified by its id alone
+    pub(crate) rollout: Option<Rollout>,
+    /// Set only by github backend, since runs of deployment targets live in their own repos
+    pub(crate) deployment_target: Option<String>,
+    /// Set only by github backend, same as `deployment_target`
+    pub(crate) workflow_repo: Option<String>,
+}
+
+impl CiRun {
+    pub fn new(id: RunId, name: String) -> Self {
+        Self {
+            id,
+            name,
+            rollout: None,
+            deployment_target: None,
+            workflow_repo: None,
+        }
+    }
+}
+
+impl From<&Run> for CiRun {
+    fn from(run: &Run) -> Self {
+        Self::new(run.id, run.name.clone())
+    }
+}
+
+/// Operations with workflows of instances. Errors of all backends are `GithubError`,
+/// so retries and failures of deployments are handled the same way
+#[async_trait::async_trait]
+pub trait CiBackend: Debug + Send + Sync {
+    /// Dispatches the workflow for the instance and returns its run
+    async fn dispatch(&self, workflow: CiWorkflow, target: &CiTarget)
+        -> Result<CiRun, GithubError>;
+
+    /// Returns run of the workflow dispatched earlier
+    async fn get_run(
+        &self,
+        workflow: CiWorkflow,
+        target: &CiTarget,
+        run_id: RunId,
+    ) -> Result<CiRun, GithubError>;
+
+    /// Waits until the run is completed. Fails if the run is not successful
+    /// or it is not completed in `timeout`
+    async fn wait_for_success(
+        &self,
+        run: &CiRun,
+        timeout: Duration,
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<RunConclusion, GithubError>;
+
+    /// Waits until all runs of the deployment are completed, failing as soon as any of them fails.
+    /// Only github backend can wait for several runs at once
+    async fn wait_for_all_success(
+        &self,
+        runs: &[CiRun],
+        timeout: Duration,
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<RunConclusion>, GithubError> {
+        match runs {
+            [run] => Ok(vec![
+                self.wait_for_success(run, timeout, backoff, cancel).await?,
+            ]),
+            _ => Err(GithubError::Internal(anyhow::anyhow!(
+                "ci backend can't wait for {} workflow runs at once",
+                runs.len()
+            ))),
+        }
+    }
+
+    async fn cancel(&self, run_id: RunId) -> Result<(), GithubError>;
+
+    /// Link to the run in the web interface of the backend, if it can be built
+    fn run_url(
+        &self,
+        _deployment_target: Option<&str>,
+        _workflow_repo: Option<&str>,
+        _run_id: RunId,
+    ) -> Option<String> {
+        None
+    }
+
+    /// Returns the last `max_bytes` of logs of the run with redacted secrets
+    async fn fetch_logs(
+        &self,
+        target: &CiTarget,
+        run_id: RunId,
+        max_bytes: usize,
+    ) -> Result<RunLogs, GithubError>;
+
+    /// Returns logs of the run which are available while it is in progress,
+    /// with redacted secrets. By default it is the end of logs fetched by `fetch_logs`
+    async fn fetch_live_logs(
+        &self,
+        target: &CiTarget,
+        run_id: RunId,
+    ) -> Result<String, GithubError> {
+        let logs = self.fetch_logs(target, run_id, MAX_LIVE_LOGS_BYTES).await?;
+        Ok(logs.text)
+    }
+}
+
+pub fn backend_from_settings(
+    settings: &CiSettings,
+    github: Arc<GithubClient>,
+) -> Result<Arc<dyn CiBackend>, GithubError> {
+    let missing = |backend: &str| {
+        anyhow::anyhow!("{backend} settings should be configured for {backend} ci backend")
+    };
+    match settings.backend {
+        CiBackendType::Github => Ok(github),
+        CiBackendType::Gitlab => {
+            let gitlab = settings.gitlab.as_ref().ok_or(missing("gitlab"))?;
+            Ok(Arc::new(GitlabClient::from_settings(gitlab)?))
+        }
+        CiBackendType::Kubernetes => {
+            let kubernetes = settings.kubernetes.as_ref().ok_or(missing("kubernetes"))?;
+            Ok(Arc::new(KubernetesClient::from_settings(kubernetes)?))
+        }
+    }
+}
+
+/// Checks state of the run with `backoff` until it is completed or `timeout` is reached.
+/// `check` returns conclusion only for completed runs
+async fn poll_until_success<F, Fut>(
+    run: &CiRun,
+    timeout: Duration,
+    backoff: PollBackoff,
+    cancel: &CancellationToken,
+    mut check: F,
+) -> Result<RunConclusion, GithubError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(RunStatus, Option<RunConclusion>), GithubError>>,
+{
+    let started = std::time::Instant::now();
+    let mut attempt = 0;
+    loop {
+        record_poll();
+        let (status, conclusion) = check().await?;
+        match conclusion {
+            Some(conclusion) if conclusion.is_ok() => return Ok(conclusion),
+            Some(conclusion) => {
+                return Err(GithubError::WorkflowFailed {
+                    run_id: run.id,
+                    conclusion,
+                })
+            }
+            None => {}
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= timeout {
+            tracing::warn!(status = ?status, "timed out waiting for '{}'", run.name);
+            return Err(GithubError::WorkflowTimeout {
+                run_id: run.id,
+                status,
+            });
+        }
+        let delay = backoff
+            .jittered_delay(attempt)
+            .min(timeout.saturating_sub(elapsed));
+        attempt = attempt.saturating_add(1);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = cancel.cancelled() => return Err(GithubError::Interrupted),
+        }
+    }
+}
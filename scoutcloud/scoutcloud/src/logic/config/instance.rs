@@ -41,15 +41,20 @@ macro_rules! parse_config_all_vars {
             ChainId,
             ChainName,
             ChainType,
+            DeploymentTarget,
+            GitRef,
             HomeplateBackground,
             HomeplateTextColor,
             IconUrl,
+            Image,
             InstanceUrl,
             LogoUrl,
             NodeType,
             RpcUrl,
             ServerSize,
+            Tag,
             TokenSymbol,
+            WorkflowRepo,
         });
     };
 }
@@ -61,9 +66,7 @@ impl InstanceConfig {
         user_config: UserConfig,
         client_name: impl Into<String>,
     ) -> Result<Self, ConfigError> {
-        let context = ConfigValidationContext {
-            client_name: client_name.into(),
-        };
+        let context = ConfigValidationContext::new(client_name);
 
         let mut parsed_vars = ParsedVars::default();
         let config = user_config.internal;
@@ -152,6 +155,47 @@ impl InstanceConfig {
         })?;
         Ok(url)
     }
+
+    /// Kubernetes namespace of the instance, default namespace of the backend is used if it is not set
+    pub fn namespace(&self) -> Option<&str> {
+        self.raw["namespace"].as_str()
+    }
+
+    /// Deployment target which runs workflows of the instance, see `GithubClient::for_target`
+    pub fn deployment_target(&self) -> Option<&str> {
+        self.raw["deployment_target"].as_str()
+    }
+
+    /// Fork of the workflows which runs them instead of the default repo,
+    /// see `GithubClient::for_workflow_repo`
+    pub fn workflow_repo(&self) -> Option<&str> {
+        self.raw["workflow_repo"].as_str()
+    }
+
+    /// Branch or tag which the deploy workflow is dispatched against by default
+    pub fn git_ref(&self) -> Option<&str> {
+        self.raw["git_ref"].as_str()
+    }
+
+    /// Image which the deploy workflow gets instead of its default one
+    pub fn image(&self) -> Option<&str> {
+        self.raw["image"].as_str()
+    }
+
+    /// Tag of the image which the deploy workflow gets instead of its default one
+    pub fn tag(&self) -> Option<&str> {
+        self.raw["tag"].as_str()
+    }
+
+    /// Blockscout api is served under the instance url, so it is used for health checks
+    pub fn parse_health_url(&self, path: &str) -> Result<Url, ConfigError> {
+        let instance_url = self.parse_instance_url()?;
+        instance_url.join(path).map_err(|e| {
+            ConfigError::Internal(anyhow::anyhow!(
+                "failed to build health url from '{instance_url}' and '{path}': {e}"
+            ))
+        })
+    }
 }
 
 #[cfg(test)]
@@ -193,6 +237,11 @@ mod tests {
             icon_url: Some("http://example.com/icon".parse().unwrap()),
             homeplate_background: Some("#111111".to_string()),
             homeplate_text_color: Some("#222222".to_string()),
+            deployment_target: None,
+            git_ref: None,
+            image: None,
+            tag: None,
+            workflow_repo: None,
         };
         UserConfig { internal }
     }
@@ -258,6 +307,25 @@ mod tests {
         )
     }
 
+    #[test]
+    fn health_url_is_derived_from_instance_url() {
+        let config = InstanceConfig::from_raw(json!({
+            "frontend": {"ingress": {"hostname": "hostname-test.k8s-dev.blockscout.com"}}
+        }));
+        assert_eq!(
+            config.parse_health_url("/api/health").unwrap().as_str(),
+            "https://hostname-test.k8s-dev.blockscout.com/api/health"
+        );
+
+        let config = InstanceConfig::from_raw(json!({
+            "frontend": {"ingress": {"hostname": "http://127.0.0.1:8080"}}
+        }));
+        assert_eq!(
+            config.parse_health_url("/api/health").unwrap().as_str(),
+            "http://127.0.0.1:8080/api/health"
+        );
+    }
+
     #[tokio::test]
     async fn config_empty_parse_works() {
         let server = mock_rpc();
@@ -275,6 +343,11 @@ mod tests {
                 icon_url: None,
                 homeplate_background: None,
                 homeplate_text_color: None,
+                deployment_target: None,
+                git_ref: None,
+                image: None,
+                tag: None,
+                workflow_repo: None,
             },
         };
         let client_name = "test-client";
@@ -1,13 +1,19 @@
 mod instance;
 pub mod macros;
+pub mod preset;
+mod template;
 mod types;
 mod user;
+mod validation;
 pub mod variables;
 
 pub use instance::InstanceConfig;
+pub use template::ConfigTemplate;
 pub use types::{ConfigValidationContext, ParsedVariable, ParsedVariableKey, UserVariable};
 pub use user::UserConfig;
+pub use validation::join_errors;
 
+use crate::logic::secrets::SecretsError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -18,6 +24,9 @@ pub enum ConfigError {
     #[error("missing config")]
     MissingConfig,
 
+    #[error("secrets error: {0}")]
+    Secrets(#[from] SecretsError),
+
     #[error("internal error: {0}")]
     Internal(#[from] anyhow::Error),
 }
@@ -0,0 +1,179 @@
+use super::{join_errors, ConfigError, ConfigTemplate, UserConfig};
+use crate::logic::ConfigValidationContext;
+use lazy_static::lazy_static;
+use scoutcloud_proto::blockscout::scoutcloud::v1::DeployConfigPartialInternal;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+lazy_static! {
+    static ref PRESETS: RwLock<Arc<InstancePresets>> = RwLock::new(Arc::new(
+        InstancePresets::from_settings(&BTreeMap::new()).expect("built-in presets are invalid")
+    ));
+}
+
+/// Presets which are available without any configuration of the service
+fn built_in() -> BTreeMap<String, serde_json::Value> {
+    BTreeMap::from([(
+        "ethereum-mainnet".to_string(),
+        serde_json::json!({
+            "rpc_url": "https://ethereum-rpc.publicnode.com",
+            "server_size": "medium",
+            "chain_type": "ethereum",
+            "node_type": "geth",
+            "chain_id": "1",
+            "chain_name": "Ethereum",
+            "token_symbol": "ETH",
+        }),
+    )])
+}
+
+/// Complete configs of standard instances, so new users can deploy one
+/// without filling out the config. Configs of presets have no placeholders
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstancePresets {
+    presets: BTreeMap<String, ConfigTemplate>,
+}
+
+impl InstancePresets {
+    /// Built-in presets together with `configured` ones, which replace built-in presets
+    /// with the same name. Every preset is validated, so a broken one fails the startup
+    pub fn from_settings(
+        configured: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<Self, ConfigError> {
+        let mut presets = BTreeMap::new();
+        for (name, config) in built_in().into_iter().chain(configured.clone()) {
+            let preset = validate_preset(&name, config).map_err(|errors| {
+                ConfigError::Validation(format!(
+                    "invalid preset `{name}`: {}",
+                    join_errors(&errors)
+                ))
+            })?;
+            presets.insert(name, preset);
+        }
+        Ok(Self { presets })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.presets.keys().map(String::as_str)
+    }
+
+    /// Config of the preset with `overrides` on top of it,
+    /// returns `None` if there is no preset with this name
+    pub fn compose(
+        &self,
+        name: &str,
+        overrides: Option<&DeployConfigPartialInternal>,
+    ) -> Option<Result<UserConfig, ConfigError>> {
+        let preset = self.presets.get(name)?;
+        Some(preset.compose(&BTreeMap::new(), overrides))
+    }
+}
+
+fn validate_preset(
+    name: &str,
+    config: serde_json::Value,
+) -> Result<ConfigTemplate, Vec<ConfigError>> {
+    let is_valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !is_valid_name {
+        return Err(vec![ConfigError::Validation(
+            "name may contain only lowercase letters, digits and dashes".to_string(),
+        )]);
+    }
+    let preset = ConfigTemplate::new(config).map_err(|err| vec![err])?;
+    if !preset.variables().is_empty() {
+        return Err(vec![ConfigError::Validation(
+            "presets can't use variables".to_string(),
+        )]);
+    }
+    let config = preset
+        .compose(&BTreeMap::new(), None)
+        .map_err(|err| vec![err])?;
+    config.validate(&ConfigValidationContext::new(name))?;
+    Ok(preset)
+}
+
+/// Presets of the service, only built-in ones are available until it is initialized
+pub fn init_presets(presets: InstancePresets) {
+    *PRESETS.write().expect("presets lock is poisoned") = Arc::new(presets);
+}
+
+pub fn presets() -> Arc<InstancePresets> {
+    PRESETS.read().expect("presets lock is poisoned").clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn configured_presets_are_validated() {
+        let presets = InstancePresets::from_settings(&BTreeMap::from([(
+            "ethereum-mainnet".to_string(),
+            serde_json::json!({
+                "rpc_url": "https://rpc.example.com",
+                "server_size": "large",
+                "chain_id": "1",
+            }),
+        )]))
+        .unwrap();
+        // configured preset replaces the built-in one
+        assert_eq!(
+            presets.names().collect::<Vec<_>>(),
+            vec!["ethereum-mainnet"]
+        );
+        let config = presets
+            .compose("ethereum-mainnet", None)
+            .unwrap()
+            .unwrap()
+            .internal;
+        assert_eq!(config.server_size, "large");
+        assert_eq!(config.token_symbol, None);
+        assert!(presets.compose("unknown", None).is_none());
+
+        for (name, config, expected) in [
+            (
+                "Mainnet",
+                serde_json::json!({}),
+                "invalid preset `Mainnet`: name may contain only lowercase letters, digits and dashes",
+            ),
+            (
+                "sepolia",
+                serde_json::json!({"chain_name": "${network}"}),
+                "invalid preset `sepolia`: presets can't use variables",
+            ),
+            (
+                "sepolia",
+                serde_json::json!({
+                    "rpc_url": "https://rpc.example.com",
+                    "server_size": "huge",
+                    "chain_id": "11155111",
+                }),
+                "invalid preset `sepolia`: unknown server_size: 'huge'",
+            ),
+        ] {
+            let err =
+                InstancePresets::from_settings(&BTreeMap::from([(name.to_string(), config)]))
+                    .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("failed to validate config: {expected}")
+            );
+        }
+        let err = InstancePresets::from_settings(&BTreeMap::from([(
+            "sepolia".to_string(),
+            serde_json::json!({"chain_id": "11155111"}),
+        )]))
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("config resolved from the template is incomplete"),
+            "unexpected error: {err}"
+        );
+    }
+}
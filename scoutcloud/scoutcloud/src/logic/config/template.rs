@@ -0,0 +1,313 @@
+use super::{ConfigError, UserConfig};
+use crate::logic::json_utils;
+use anyhow::Context;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use scoutcloud_proto::blockscout::scoutcloud::v1::{
+    DeployConfigInternal, DeployConfigPartialInternal,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\$\{([^}]*)\}").unwrap();
+    static ref VARIABLE_NAME: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+/// Partial config shared by near-identical instances. String values may contain
+/// `${variable}` placeholders, which are replaced with values of variables of the instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigTemplate {
+    config: serde_json::Value,
+}
+
+impl ConfigTemplate {
+    /// Checks that the config is a json object and its placeholders are well-formed
+    pub fn new(mut config: serde_json::Value) -> Result<Self, ConfigError> {
+        json_utils::filter_null_values(&mut config);
+        let Some(fields) = config.as_object() else {
+            return Err(validation_error(
+                "config of the template should be a json object",
+            ));
+        };
+        for (field, value) in fields {
+            let Some(value) = value.as_str() else {
+                continue;
+            };
+            if PLACEHOLDER.replace_all(value, "").contains("${") {
+                return Err(validation_error(format!(
+                    "unterminated placeholder in field `{field}` of the template"
+                )));
+            }
+            if let Some(name) = placeholders(value).find(|name| !VARIABLE_NAME.is_match(name)) {
+                return Err(validation_error(format!(
+                    "invalid variable name `{name}` in field `{field}` of the template"
+                )));
+            }
+        }
+        Ok(Self { config })
+    }
+
+    pub fn config(&self) -> &serde_json::Value {
+        &self.config
+    }
+
+    /// Names of variables used by placeholders of the template
+    pub fn variables(&self) -> BTreeSet<String> {
+        self.string_values()
+            .flat_map(placeholders)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Replaces placeholders with `variables`, every variable used by the template
+    /// should be provided. Variables which are not used are ignored
+    pub fn resolve(
+        &self,
+        variables: &BTreeMap<String, String>,
+    ) -> Result<DeployConfigPartialInternal, ConfigError> {
+        let missing: Vec<String> = self
+            .variables()
+            .into_iter()
+            .filter(|name| !variables.contains_key(name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(validation_error(format!(
+                "missing values of template variables: {}",
+                missing.join(", ")
+            )));
+        }
+        let mut config = self.config.clone();
+        if let Some(fields) = config.as_object_mut() {
+            for value in fields.values_mut() {
+                if let serde_json::Value::String(value) = value {
+                    *value = PLACEHOLDER
+                        .replace_all(value, |captures: &Captures| variables[&captures[1]].clone())
+                        .into_owned();
+                }
+            }
+        }
+        let resolved: DeployConfigPartialInternal = serde_json::from_value(config.clone())
+            .map_err(|err| {
+                validation_error(format!("invalid config resolved from the template: {err}"))
+            })?;
+        // unknown fields are dropped by deserialization, so typos would go unnoticed
+        let known = serde_json::to_value(&resolved).context("serializing resolved template")?;
+        if let Some(field) = config.as_object().and_then(|fields| {
+            fields
+                .keys()
+                .find(|field| known.get(field.as_str()).map_or(true, |v| v.is_null()))
+        }) {
+            return Err(validation_error(format!(
+                "unknown field `{field}` in the template"
+            )));
+        }
+        Ok(resolved)
+    }
+
+    /// Config of the instance: the resolved template with `overrides` on top of it,
+    /// so the instance may differ from the template in any field
+    pub fn compose(
+        &self,
+        variables: &BTreeMap<String, String>,
+        overrides: Option<&DeployConfigPartialInternal>,
+    ) -> Result<UserConfig, ConfigError> {
+        let resolved = self.resolve(variables)?;
+        let mut config =
+            serde_json::to_value(&resolved).context("serializing resolved template")?;
+        json_utils::filter_null_values(&mut config);
+        if let Some(overrides) = overrides {
+            let mut overrides = serde_json::to_value(overrides).context("serializing overrides")?;
+            json_utils::filter_null_values(&mut overrides);
+            json_utils::merge(&mut config, &overrides);
+        }
+        let internal: DeployConfigInternal = serde_json::from_value(config).map_err(|err| {
+            validation_error(format!(
+                "config resolved from the template is incomplete: {err}"
+            ))
+        })?;
+        Ok(UserConfig::new(internal))
+    }
+
+    fn string_values(&self) -> impl Iterator<Item = &str> {
+        self.config
+            .as_object()
+            .into_iter()
+            .flat_map(|fields| fields.values())
+            .filter_map(|value| value.as_str())
+    }
+}
+
+fn placeholders(value: &str) -> impl Iterator<Item = &str> {
+    PLACEHOLDER
+        .captures_iter(value)
+        .filter_map(|captures| captures.get(1))
+        .map(|name| name.as_str())
+}
+
+fn validation_error(message: impl Into<String>) -> ConfigError {
+    ConfigError::Validation(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn template() -> ConfigTemplate {
+        ConfigTemplate::new(serde_json::json!({
+            "rpc_url": "https://${network}.rpc.example.com",
+            "server_size": "small",
+            "chain_id": "77",
+            "chain_name": "${project} ${network}",
+            "token_symbol": null,
+        }))
+        .unwrap()
+    }
+
+    fn variables(values: &[(&str, &str)]) -> BTreeMap<String, String> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn overrides(value: serde_json::Value) -> DeployConfigPartialInternal {
+        serde_json::from_value(value).expect("invalid overrides")
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        let template = template();
+        assert_eq!(
+            template.variables(),
+            BTreeSet::from(["network".to_string(), "project".to_string()])
+        );
+
+        let config = template
+            .compose(
+                &variables(&[("network", "testnet"), ("project", "Acme"), ("unused", "x")]),
+                None,
+            )
+            .unwrap()
+            .internal;
+        assert_eq!(
+            config.rpc_url,
+            "https://testnet.rpc.example.com".parse().unwrap()
+        );
+        assert_eq!(config.chain_name.as_deref(), Some("Acme testnet"));
+        assert_eq!(config.server_size, "small");
+        assert_eq!(config.chain_id.as_deref(), Some("77"));
+        assert_eq!(config.token_symbol, None);
+    }
+
+    #[test]
+    fn missing_variables_fail_resolution() {
+        let err = template()
+            .compose(&variables(&[("project", "Acme")]), None)
+            .unwrap_err();
+        assert!(
+            matches!(err, ConfigError::Validation(_)),
+            "unexpected error: {err:?}"
+        );
+        assert_eq!(
+            err.to_string(),
+            "failed to validate config: missing values of template variables: network"
+        );
+
+        let err = template().resolve(&variables(&[])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to validate config: missing values of template variables: network, project"
+        );
+    }
+
+    #[test]
+    fn overrides_are_applied_on_top_of_template() {
+        let variables = variables(&[("network", "mainnet"), ("project", "Acme")]);
+        let config = template()
+            .compose(
+                &variables,
+                Some(&overrides(serde_json::json!({
+                    "server_size": "medium",
+                    "token_symbol": "ACME",
+                    "chain_name": null,
+                }))),
+            )
+            .unwrap()
+            .internal;
+        // overrides win, fields which are not overridden come from the template
+        assert_eq!(config.server_size, "medium");
+        assert_eq!(config.token_symbol.as_deref(), Some("ACME"));
+        assert_eq!(config.chain_name.as_deref(), Some("Acme mainnet"));
+        assert_eq!(
+            config.rpc_url,
+            "https://mainnet.rpc.example.com".parse().unwrap()
+        );
+
+        // placeholders of overrides are taken as is
+        let config = template()
+            .compose(
+                &variables,
+                Some(&overrides(serde_json::json!({"chain_name": "${project}"}))),
+            )
+            .unwrap()
+            .internal;
+        assert_eq!(config.chain_name.as_deref(), Some("${project}"));
+
+        // required fields may be left to overrides
+        let template =
+            ConfigTemplate::new(serde_json::json!({"chain_name": "${project}"})).unwrap();
+        let err = template.compose(&variables, None).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("config resolved from the template is incomplete"),
+            "unexpected error: {err}"
+        );
+        let config = template
+            .compose(
+                &variables,
+                Some(&overrides(serde_json::json!({
+                    "rpc_url": "https://rpc.example.com",
+                    "server_size": "small",
+                }))),
+            )
+            .unwrap()
+            .internal;
+        assert_eq!(config.chain_name.as_deref(), Some("Acme"));
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        for (config, expected) in [
+            (
+                serde_json::json!(["chain_name"]),
+                "config of the template should be a json object",
+            ),
+            (
+                serde_json::json!({"chain_name": "${project"}),
+                "unterminated placeholder in field `chain_name` of the template",
+            ),
+            (
+                serde_json::json!({"chain_name": "${}"}),
+                "invalid variable name `` in field `chain_name` of the template",
+            ),
+            (
+                serde_json::json!({"chain_name": "${project-name}"}),
+                "invalid variable name `project-name` in field `chain_name` of the template",
+            ),
+        ] {
+            let err = ConfigTemplate::new(config).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("failed to validate config: {expected}")
+            );
+        }
+
+        let template = ConfigTemplate::new(serde_json::json!({"chain_nmae": "Acme"})).unwrap();
+        let err = template.resolve(&BTreeMap::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to validate config: unknown field `chain_nmae` in the template"
+        );
+    }
+}
@@ -1,4 +1,11 @@
-use crate::logic::config::ConfigError;
+use crate::logic::{
+    config::{
+        variables::image::{self, ImageRegistries},
+        ConfigError,
+    },
+    url_guard::{self, UrlGuard},
+};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum ParsedVariableKey {
@@ -40,4 +47,19 @@ pub trait UserVariable: Send + Sync {
 #[derive(Clone)]
 pub struct ConfigValidationContext {
     pub client_name: String,
+    /// Urls of the config are checked by it, since the service sends requests to them
+    pub url_guard: Arc<UrlGuard>,
+    /// Custom images of instances should match one of them
+    pub image_registries: Arc<ImageRegistries>,
+}
+
+impl ConfigValidationContext {
+    /// Context with the url guard and allowed images of the service
+    pub fn new(client_name: impl Into<String>) -> Self {
+        Self {
+            client_name: client_name.into(),
+            url_guard: url_guard::guard(),
+            image_registries: image::registries(),
+        }
+    }
 }
@@ -0,0 +1,433 @@
+use super::{variables, UserConfig, UserVariable};
+use crate::logic::{secrets, ConfigError, ConfigValidationContext};
+use url::Url;
+
+// ganache is a local development node, it can't run chains with custom clients
+const GANACHE_INCOMPATIBLE_CHAIN_TYPES: &[&str] =
+    &["polygonedge", "polygonzkevm", "rsk", "filecoin"];
+
+macro_rules! check_variable {
+    ($errors:ident, $context:ident, $var:ident, $value:expr) => {
+        paste::item! {
+            if let Some(value) = $value {
+                if let Err(err) = <variables::[<$var:snake>]::[<$var:camel>] as UserVariable>::new(
+                    value.clone(),
+                    $context,
+                ) {
+                    $errors.push(err);
+                }
+            }
+        }
+    };
+}
+
+impl UserConfig {
+    /// Checks the config without any network requests, so it can be done right before
+    /// dispatching a workflow. Returns all found errors at once
+    pub fn validate(&self, context: &ConfigValidationContext) -> Result<(), Vec<ConfigError>> {
+        let config = &self.internal;
+        let mut errors = vec![];
+
+        if config.chain_id.is_none() {
+            errors.push(validation_error("missing required field `chain_id`"));
+        }
+        if config.server_size.is_empty() {
+            errors.push(validation_error("missing required field `server_size`"));
+        }
+
+        check_variable!(errors, context, ChainId, config.chain_id.as_ref());
+        check_variable!(errors, context, ChainType, config.chain_type.as_ref());
+        check_variable!(errors, context, NodeType, config.node_type.as_ref());
+        check_variable!(errors, context, Image, config.image.as_ref());
+        check_variable!(errors, context, Tag, config.tag.as_ref());
+        check_variable!(errors, context, WorkflowRepo, config.workflow_repo.as_ref());
+        check_variable!(
+            errors,
+            context,
+            ServerSize,
+            Some(&config.server_size).filter(|size| !size.is_empty())
+        );
+
+        // only rpc url may contain credentials, so only it may reference a secret
+        let rpc_url =
+            Some(&config.rpc_url).filter(|url| secrets::parse_reference(url.as_str()).is_none());
+        let urls = [
+            ("rpc_url", rpc_url),
+            ("logo_url", config.logo_url.as_ref()),
+            ("icon_url", config.icon_url.as_ref()),
+        ];
+        for (field, url) in urls {
+            if let Some(err) = url.and_then(|url| check_http_url(context, field, url)) {
+                errors.push(err);
+            }
+        }
+        if let Some(instance_url) = &config.instance_url {
+            if !is_valid_hostname(instance_url) {
+                errors.push(validation_error(format!(
+                    "invalid `instance_url`: '{instance_url}' is not a valid hostname"
+                )));
+            } else if let Some(err) = check_instance_host(context, instance_url) {
+                errors.push(err);
+            }
+        }
+
+        if let (Some("ganache"), Some(chain_type)) =
+            (config.node_type.as_deref(), config.chain_type.as_deref())
+        {
+            if GANACHE_INCOMPATIBLE_CHAIN_TYPES.contains(&chain_type) {
+                errors.push(validation_error(format!(
+                    "`node_type: ganache` can't be used together with `chain_type: {chain_type}`"
+                )));
+            }
+        }
+
+        // repo of the target is chosen by the server, so it can't be replaced by a fork
+        if config.deployment_target.is_some() && config.workflow_repo.is_some() {
+            errors.push(validation_error(
+                "`workflow_repo` can't be used together with `deployment_target`",
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Joins messages of validation errors into one line
+pub fn join_errors(errors: &[ConfigError]) -> String {
+    errors
+        .iter()
+        .map(|err| match err {
+            ConfigError::Validation(message) => message.clone(),
+            err => err.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn validation_error(message: impl Into<String>) -> ConfigError {
+    ConfigError::Validation(message.into())
+}
+
+fn check_http_url(
+    context: &ConfigValidationContext,
+    field: &str,
+    url: &Url,
+) -> Option<ConfigError> {
+    let reason = context.url_guard.check_url(url).err()?;
+    Some(validation_error(format!("invalid `{field}`: {reason}")))
+}
+
+/// Instance is health-checked by its hostname, hostnames without dots
+/// are prefixes of the default domain, so only custom hosts are checked
+fn check_instance_host(context: &ConfigValidationContext, hostname: &str) -> Option<ConfigError> {
+    if !hostname.contains('.') {
+        return None;
+    }
+    let url = Url::parse(&format!("https://{hostname}")).ok()?;
+    let reason = context.url_guard.check_url(&url).err()?;
+    Some(validation_error(format!(
+        "invalid `instance_url`: {reason}"
+    )))
+}
+
+fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::{config::variables::image::ImageRegistries, url_guard::UrlGuard};
+    use pretty_assertions::assert_eq;
+    use scoutcloud_proto::blockscout::scoutcloud::v1::DeployConfigInternal;
+    use std::sync::Arc;
+
+    fn context() -> ConfigValidationContext {
+        ConfigValidationContext {
+            client_name: "test-client".to_string(),
+            url_guard: Default::default(),
+            image_registries: Arc::new(ImageRegistries::new(vec![
+                "ghcr.io/blockscout/*".to_string(),
+                "registry.local:5000/blockscout".to_string(),
+            ])),
+        }
+    }
+
+    fn valid_config() -> DeployConfigInternal {
+        DeployConfigInternal {
+            rpc_url: "https://rpc.example.com".parse().unwrap(),
+            server_size: "small".to_string(),
+            node_type: Some("geth".to_string()),
+            chain_type: Some("ethereum".to_string()),
+            chain_id: Some("77".to_string()),
+            token_symbol: Some("EEE".to_string()),
+            instance_url: Some("hostname-test".to_string()),
+            logo_url: Some("http://example.com/logo".parse().unwrap()),
+            chain_name: Some("chain-test".to_string()),
+            icon_url: Some("http://example.com/icon".parse().unwrap()),
+            homeplate_background: Some("#111111".to_string()),
+            homeplate_text_color: Some("#222222".to_string()),
+            deployment_target: None,
+            git_ref: None,
+            image: None,
+            tag: None,
+            workflow_repo: None,
+        }
+    }
+
+    fn validate(internal: DeployConfigInternal) -> Result<(), String> {
+        UserConfig::new(internal)
+            .validate(&context())
+            .map_err(|errors| join_errors(&errors))
+    }
+
+    #[test]
+    fn valid_config_passes_validation() {
+        assert_eq!(validate(valid_config()), Ok(()));
+
+        let custom_host = DeployConfigInternal {
+            instance_url: Some("explorer.example.com".to_string()),
+            ..valid_config()
+        };
+        assert_eq!(validate(custom_host), Ok(()));
+    }
+
+    #[test]
+    fn rpc_url_may_reference_secret() {
+        let referencing = DeployConfigInternal {
+            rpc_url: "secret://rpc/sepolia".parse().unwrap(),
+            ..valid_config()
+        };
+        assert_eq!(validate(referencing), Ok(()));
+
+        let referencing_logo = DeployConfigInternal {
+            logo_url: Some("secret://logos/main".parse().unwrap()),
+            ..valid_config()
+        };
+        assert_eq!(
+            validate(referencing_logo),
+            Err(
+                "invalid `logo_url`: expected http or https url, got 'secret://logos/main'"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let cases = [
+            (
+                DeployConfigInternal {
+                    chain_id: None,
+                    ..valid_config()
+                },
+                "missing required field `chain_id`",
+            ),
+            (
+                DeployConfigInternal {
+                    chain_id: Some("not-a-number".to_string()),
+                    ..valid_config()
+                },
+                "invalid chain_id",
+            ),
+            (
+                DeployConfigInternal {
+                    rpc_url: "ws://rpc.example.com".parse().unwrap(),
+                    ..valid_config()
+                },
+                "invalid `rpc_url`: expected http or https url, got 'ws://rpc.example.com/'",
+            ),
+            (
+                DeployConfigInternal {
+                    instance_url: Some("bad_host!".to_string()),
+                    ..valid_config()
+                },
+                "invalid `instance_url`: 'bad_host!' is not a valid hostname",
+            ),
+            (
+                DeployConfigInternal {
+                    node_type: Some("ganache".to_string()),
+                    chain_type: Some("rsk".to_string()),
+                    ..valid_config()
+                },
+                "`node_type: ganache` can't be used together with `chain_type: rsk`",
+            ),
+        ];
+        for (config, expected) in cases {
+            assert_eq!(validate(config), Err(expected.to_string()));
+        }
+    }
+
+    #[test]
+    fn urls_of_internal_endpoints_are_rejected() {
+        let public = DeployConfigInternal {
+            rpc_url: "https://rpc.example.com:8545/key".parse().unwrap(),
+            instance_url: Some("explorer.example.com".to_string()),
+            ..valid_config()
+        };
+        assert_eq!(validate(public), Ok(()));
+
+        let cases = [
+            (
+                DeployConfigInternal {
+                    rpc_url: "http://127.0.0.1:8545".parse().unwrap(),
+                    ..valid_config()
+                },
+                "invalid `rpc_url`: url 'http://127.0.0.1:8545/' points to a private, \
+                 loopback or link-local address",
+            ),
+            (
+                DeployConfigInternal {
+                    instance_url: Some("127.0.0.1".to_string()),
+                    ..valid_config()
+                },
+                "invalid `instance_url`: url 'https://127.0.0.1/' points to a private, \
+                 loopback or link-local address",
+            ),
+            (
+                DeployConfigInternal {
+                    logo_url: Some("file:///etc/passwd".parse().unwrap()),
+                    ..valid_config()
+                },
+                "invalid `logo_url`: expected http or https url, got 'file:///etc/passwd'",
+            ),
+        ];
+        for (config, expected) in cases {
+            assert_eq!(validate(config), Err(expected.to_string()));
+        }
+
+        let allowing = ConfigValidationContext {
+            url_guard: Arc::new(UrlGuard::new(vec!["127.0.0.1".to_string()])),
+            ..context()
+        };
+        let local = DeployConfigInternal {
+            rpc_url: "http://127.0.0.1:8545".parse().unwrap(),
+            ..valid_config()
+        };
+        assert!(UserConfig::new(local).validate(&allowing).is_ok());
+    }
+
+    #[test]
+    fn custom_images_should_be_from_allowed_registries() {
+        let image = |image: &str, tag: &str| DeployConfigInternal {
+            image: Some(image.to_string()),
+            tag: Some(tag.to_string()),
+            ..valid_config()
+        };
+        for (allowed, tag) in [
+            ("ghcr.io/blockscout/blockscout", "v6.8.0"),
+            ("ghcr.io/blockscout/frontend", "latest"),
+            ("registry.local:5000/blockscout", "main_1.2-rc"),
+            // empty values mean the defaults of the workflow
+            ("", ""),
+        ] {
+            assert_eq!(validate(image(allowed, tag)), Ok(()), "{allowed}:{tag}");
+        }
+
+        let cases = [
+            (
+                image("docker.io/evil/blockscout", "v6.8.0"),
+                "invalid `image`: 'docker.io/evil/blockscout' is not from an allowed registry",
+            ),
+            (
+                image("ghcr.io/blockscoutx/blockscout", "v6.8.0"),
+                "invalid `image`: 'ghcr.io/blockscoutx/blockscout' is not from an allowed registry",
+            ),
+            (
+                image("registry.local:5000/blockscout-fork", "v6.8.0"),
+                "invalid `image`: 'registry.local:5000/blockscout-fork' \
+                 is not from an allowed registry",
+            ),
+            (
+                image("ghcr.io/blockscout/blockscout:latest", ""),
+                "invalid `image`: 'ghcr.io/blockscout/blockscout:latest' \
+                 is not a valid image name without a tag",
+            ),
+            (
+                image("ghcr.io/blockscout/blockscout", "-v1"),
+                "invalid `tag`: '-v1' is not a valid image tag",
+            ),
+            (
+                image("ghcr.io/blockscout/blockscout", "v1:2"),
+                "invalid `tag`: 'v1:2' is not a valid image tag",
+            ),
+        ];
+        for (config, expected) in cases {
+            assert_eq!(validate(config), Err(expected.to_string()));
+        }
+
+        let without_registries = ConfigValidationContext {
+            image_registries: Default::default(),
+            ..context()
+        };
+        let config = image("ghcr.io/blockscout/blockscout", "v6.8.0");
+        assert!(UserConfig::new(config)
+            .validate(&without_registries)
+            .is_err());
+    }
+
+    #[test]
+    fn workflow_repo_should_be_github_repo() {
+        let with_repo = |repo: &str| DeployConfigInternal {
+            workflow_repo: Some(repo.to_string()),
+            ..valid_config()
+        };
+        for valid in ["power-user/autodeploy", "Power_User/autodeploy.fork"] {
+            assert_eq!(validate(with_repo(valid)), Ok(()), "{valid}");
+        }
+        for invalid in [
+            "autodeploy",
+            "/autodeploy",
+            "power-user/",
+            "a/b/c",
+            "power user/repo",
+        ] {
+            assert_eq!(
+                validate(with_repo(invalid)),
+                Err(format!(
+                    "invalid `workflow_repo`: '{invalid}' is not a github repo like `owner/repo`"
+                ))
+            );
+        }
+
+        let with_target = DeployConfigInternal {
+            deployment_target: Some("eu".to_string()),
+            ..with_repo("power-user/autodeploy")
+        };
+        assert_eq!(
+            validate(with_target),
+            Err("`workflow_repo` can't be used together with `deployment_target`".to_string())
+        );
+    }
+
+    #[test]
+    fn all_errors_are_returned_at_once() {
+        let config = DeployConfigInternal {
+            server_size: "".to_string(),
+            node_type: Some("unknown".to_string()),
+            chain_id: None,
+            logo_url: Some("ftp://example.com/logo".parse().unwrap()),
+            ..valid_config()
+        };
+        let errors = UserConfig::new(config).validate(&context()).unwrap_err();
+        assert_eq!(
+            join_errors(&errors),
+            "missing required field `chain_id`; \
+             missing required field `server_size`; \
+             unknown node_type: 'unknown'; \
+             invalid `logo_url`: expected http or https url, got 'ftp://example.com/logo'"
+        );
+    }
+}
@@ -0,0 +1,3 @@
+use crate::logic::config::macros;
+
+macros::simple_env_var!(DeploymentTarget, String, ConfigPath, "deployment_target");
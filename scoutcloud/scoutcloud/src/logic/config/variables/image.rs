@@ -0,0 +1,115 @@
+use crate::logic::{
+    config::ConfigError, ConfigValidationContext, ParsedVariable, ParsedVariableKey, UserVariable,
+};
+use lazy_static::lazy_static;
+use std::sync::{Arc, RwLock};
+
+const MAX_IMAGE_LENGTH: usize = 255;
+// same as the limit of docker
+const MAX_TAG_LENGTH: usize = 128;
+
+lazy_static! {
+    static ref REGISTRIES: RwLock<Arc<ImageRegistries>> = RwLock::new(Default::default());
+}
+
+/// Patterns of images which instances may be deployed with, like `ghcr.io/blockscout/*`.
+/// Pattern ending with `*` matches every image starting with the rest of it,
+/// other patterns match only the same image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageRegistries {
+    patterns: Vec<String>,
+}
+
+impl ImageRegistries {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self { patterns }
+    }
+
+    pub fn check(&self, image: &str) -> Result<(), ConfigError> {
+        let is_valid = !image.is_empty()
+            && image.len() <= MAX_IMAGE_LENGTH
+            && image.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || "./_-:".contains(c)
+            })
+            // tag and digest are not part of the image, port of the registry is
+            && !image.rsplit('/').next().unwrap_or_default().contains(':');
+        if !is_valid {
+            return Err(ConfigError::Validation(format!(
+                "invalid `image`: '{image}' is not a valid image name without a tag"
+            )));
+        }
+        let is_allowed = self
+            .patterns
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => image.starts_with(prefix),
+                None => image == pattern,
+            });
+        if !is_allowed {
+            return Err(ConfigError::Validation(format!(
+                "invalid `image`: '{image}' is not from an allowed registry"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Allowed images of the service, no custom images are allowed until it is initialized
+pub fn init_registries(registries: ImageRegistries) {
+    *REGISTRIES
+        .write()
+        .expect("image registries lock is poisoned") = Arc::new(registries);
+}
+
+pub fn registries() -> Arc<ImageRegistries> {
+    REGISTRIES
+        .read()
+        .expect("image registries lock is poisoned")
+        .clone()
+}
+
+pub fn check_tag(tag: &str) -> Result<(), ConfigError> {
+    let is_valid = tag.len() <= MAX_TAG_LENGTH
+        && tag
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+    if !is_valid {
+        return Err(ConfigError::Validation(format!(
+            "invalid `tag`: '{tag}' is not a valid image tag"
+        )));
+    }
+    Ok(())
+}
+
+/// Image of blockscout which the deploy workflow gets as an input,
+/// empty image means the default of the workflow
+pub struct Image(String);
+
+#[async_trait::async_trait]
+impl UserVariable for Image {
+    type SourceType = String;
+
+    fn new(v: String, context: &ConfigValidationContext) -> Result<Self, ConfigError> {
+        if !v.is_empty() {
+            context.image_registries.check(&v)?;
+        }
+        Ok(Self(v))
+    }
+
+    async fn build_config_vars(
+        &self,
+        _context: &ConfigValidationContext,
+    ) -> Result<Vec<ParsedVariable>, ConfigError> {
+        if self.0.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![(
+            ParsedVariableKey::ConfigPath("image".to_string()),
+            serde_json::Value::String(self.0.clone()),
+        )])
+    }
+}
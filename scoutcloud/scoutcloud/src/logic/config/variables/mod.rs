@@ -1,12 +1,17 @@
 pub mod chain_id;
 pub mod chain_name;
 pub mod chain_type;
+pub mod deployment_target;
+pub mod git_ref;
 pub mod homeplate_background;
 pub mod homeplate_text_color;
 pub mod icon_url;
+pub mod image;
 pub mod instance_url;
 pub mod logo_url;
 pub mod node_type;
 pub mod rpc_url;
 pub mod server_size;
+pub mod tag;
 pub mod token_symbol;
+pub mod workflow_repo;
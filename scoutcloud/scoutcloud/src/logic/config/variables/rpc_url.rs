@@ -1,5 +1,6 @@
 use crate::logic::{
-    config::ConfigError, ConfigValidationContext, ParsedVariable, ParsedVariableKey, UserVariable,
+    config::ConfigError, secrets, ConfigValidationContext, ParsedVariable, ParsedVariableKey,
+    UserVariable,
 };
 use anyhow::Context;
 use ethers::{prelude::*, providers::Provider, types::BlockNumber};
@@ -20,9 +21,12 @@ impl UserVariable for RpcUrl {
         _config: &ConfigValidationContext,
     ) -> Result<Vec<ParsedVariable>, ConfigError> {
         let mut parsed = vec![];
+        // referenced secret is only resolved for checks, parsed config keeps the reference
+        let is_reference = secrets::parse_reference(self.0.as_str()).is_some();
+        let rpc_url = secrets::resolve_value(self.0.as_str()).await?;
 
         // check json rpc
-        let provider = Provider::<Http>::try_from(self.0.as_str())
+        let provider = Provider::<Http>::try_from(rpc_url.as_str())
             .context("failed to parse url as http")
             .map_err(|e| ConfigError::Validation(e.to_string()))?;
 
@@ -73,6 +77,10 @@ impl UserVariable for RpcUrl {
         };
 
         // check websocket
+        if is_reference {
+            tracing::warn!("`rpc_url` references a secret, skipping websocket configuration");
+            return Ok(parsed);
+        }
         if let Some(ws_url) = get_any_healthy_ws_url(self.0.clone())
             .await
             .map_err(ConfigError::Internal)?
@@ -0,0 +1,33 @@
+use crate::logic::{
+    config::{variables::image, ConfigError},
+    ConfigValidationContext, ParsedVariable, ParsedVariableKey, UserVariable,
+};
+
+/// Tag of the image which the deploy workflow gets as an input,
+/// empty tag means the default of the workflow
+pub struct Tag(String);
+
+#[async_trait::async_trait]
+impl UserVariable for Tag {
+    type SourceType = String;
+
+    fn new(v: String, _context: &ConfigValidationContext) -> Result<Self, ConfigError> {
+        if !v.is_empty() {
+            image::check_tag(&v)?;
+        }
+        Ok(Self(v))
+    }
+
+    async fn build_config_vars(
+        &self,
+        _context: &ConfigValidationContext,
+    ) -> Result<Vec<ParsedVariable>, ConfigError> {
+        if self.0.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(vec![(
+            ParsedVariableKey::ConfigPath("tag".to_string()),
+            serde_json::Value::String(self.0.clone()),
+        )])
+    }
+}
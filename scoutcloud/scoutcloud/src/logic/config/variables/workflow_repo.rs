@@ -0,0 +1,46 @@
+use crate::logic::{
+    config::ConfigError, ConfigValidationContext, ParsedVariable, ParsedVariableKey, UserVariable,
+};
+
+// limits of github for names of users and organizations and for names of repos
+const MAX_OWNER_LENGTH: usize = 39;
+const MAX_REPO_LENGTH: usize = 100;
+
+/// Fork of the deployment workflows as `owner/repo`, which runs workflows of the instance
+/// instead of the default repo. Whether the repo is allowed is checked by the github client,
+/// since the allowlist of repos is a part of its settings
+pub struct WorkflowRepo(String);
+
+#[async_trait::async_trait]
+impl UserVariable for WorkflowRepo {
+    type SourceType = String;
+
+    fn new(v: String, _context: &ConfigValidationContext) -> Result<Self, ConfigError> {
+        let is_valid_name = |name: &str, max_length: usize| {
+            !name.is_empty()
+                && name.len() <= max_length
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+        };
+        let is_valid = v.split_once('/').is_some_and(|(owner, repo)| {
+            is_valid_name(owner, MAX_OWNER_LENGTH) && is_valid_name(repo, MAX_REPO_LENGTH)
+        });
+        if !is_valid {
+            return Err(ConfigError::Validation(format!(
+                "invalid `workflow_repo`: '{v}' is not a github repo like `owner/repo`"
+            )));
+        }
+        Ok(Self(v))
+    }
+
+    async fn build_config_vars(
+        &self,
+        _context: &ConfigValidationContext,
+    ) -> Result<Vec<ParsedVariable>, ConfigError> {
+        Ok(vec![(
+            ParsedVariableKey::ConfigPath("workflow_repo".to_string()),
+            serde_json::Value::String(self.0.clone()),
+        )])
+    }
+}
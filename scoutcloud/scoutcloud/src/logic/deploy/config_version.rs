@@ -0,0 +1,246 @@
+use super::{deployment::Deployment, instance::Instance};
+use crate::logic::{
+    json_utils,
+    secrets::{self, SecretsError, USER_CONFIG_SECRET_FIELDS},
+};
+use scoutcloud_entity as db;
+use sea_orm::{
+    prelude::*, ActiveValue::Set, ConnectionTrait, IntoActiveModel, QueryOrder, QuerySelect,
+};
+
+/// Immutable snapshot of the instance config which the deployment was created from
+#[derive(Clone, Debug)]
+pub struct InstanceConfigVersion {
+    pub model: db::instance_config_versions::Model,
+}
+
+const MASKED_VALUE: &str = "***";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigFieldDiff {
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+impl InstanceConfigVersion {
+    pub async fn snapshot<C>(db: &C, deployment: &Deployment) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let last_version: Option<i32> = db::instance_config_versions::Entity::find()
+            .select_only()
+            .column_as(
+                db::instance_config_versions::Column::Version.max(),
+                "version",
+            )
+            .filter(
+                db::instance_config_versions::Column::InstanceId.eq(deployment.model.instance_id),
+            )
+            .into_tuple::<Option<i32>>()
+            .one(db)
+            .await?
+            .flatten();
+        let model = db::instance_config_versions::ActiveModel {
+            instance_id: Set(deployment.model.instance_id),
+            deployment_id: Set(deployment.model.id),
+            version: Set(last_version.unwrap_or_default() + 1),
+            user_config: Set(deployment.model.user_config.clone()),
+            parsed_config: Set(deployment.model.parsed_config.clone()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+        Ok(Self { model })
+    }
+
+    pub async fn find_by_deployment<C>(
+        db: &C,
+        deployment: &Deployment,
+    ) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let model = db::instance_config_versions::Entity::find()
+            .filter(db::instance_config_versions::Column::DeploymentId.eq(deployment.model.id))
+            .one(db)
+            .await?;
+        Ok(model.map(|model| Self { model }))
+    }
+
+    /// Latest config which was successfully deployed, so the instance can be rolled back to it
+    pub async fn last_known_good<C>(db: &C, instance: &Instance) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let model = db::instance_config_versions::Entity::find()
+            .inner_join(db::deployments::Entity)
+            .filter(db::instance_config_versions::Column::InstanceId.eq(instance.model.id))
+            .filter(db::deployments::Column::StartedAt.is_not_null())
+            .order_by_desc(db::instance_config_versions::Column::Version)
+            .one(db)
+            .await?;
+        Ok(model.map(|model| Self { model }))
+    }
+
+    /// Field level diff of user configs, sorted by field name.
+    /// Values of secret fields are compared, but not returned
+    pub fn diff(&self, other: &Self) -> Result<Vec<ConfigFieldDiff>, SecretsError> {
+        let old = flatten_user_config(&self.model.user_config)?;
+        let new = flatten_user_config(&other.model.user_config)?;
+        let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+        fields.sort();
+        fields.dedup();
+        let diff = fields
+            .into_iter()
+            .filter_map(|field| {
+                let (old_value, new_value) = (old.get(field), new.get(field));
+                let display = |value: &serde_json::Value| {
+                    if USER_CONFIG_SECRET_FIELDS.contains(&field.as_str()) {
+                        MASKED_VALUE.to_string()
+                    } else {
+                        value_to_string(value)
+                    }
+                };
+                (old_value != new_value).then(|| ConfigFieldDiff {
+                    field: field.clone(),
+                    old_value: old_value.map(display),
+                    new_value: new_value.map(display),
+                })
+            })
+            .collect();
+        Ok(diff)
+    }
+}
+
+impl Instance {
+    /// Replaces current config of the instance with the config of `version`
+    pub async fn restore_config_version<C>(
+        &mut self,
+        db: &C,
+        version: &InstanceConfigVersion,
+    ) -> Result<&mut Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut active = self.model.clone().into_active_model();
+        active.user_config = Set(version.model.user_config.clone());
+        active.parsed_config = Set(version.model.parsed_config.clone());
+        self.model = active.update(db).await?;
+        Ok(self)
+    }
+}
+
+fn flatten_user_config(
+    user_config: &serde_json::Value,
+) -> Result<std::collections::BTreeMap<String, serde_json::Value>, SecretsError> {
+    let mut user_config = user_config.clone();
+    secrets::decrypt_fields(&mut user_config, USER_CONFIG_SECRET_FIELDS)?;
+    Ok(json_utils::flatten(&user_config))
+}
+
+fn value_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::{
+            deploy::{handlers::diff_instance_configs, StatusActor},
+            UserToken,
+        },
+        tests_utils,
+    };
+    use pretty_assertions::assert_eq;
+
+    async fn edit_config(db: &DatabaseConnection, instance: &mut Instance, server_size: &str) {
+        let mut user_config = instance.model.user_config.clone();
+        user_config["server_size"] = server_size.into();
+        let mut active = instance.model.clone().into_active_model();
+        active.user_config = Set(user_config);
+        instance.model = active.update(db).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn config_versions_diff_works() {
+        let db = tests_utils::init::test_db("test", "config_versions_diff_works").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(&conn).await.unwrap();
+        let user_token = UserToken::get(conn.as_ref(), 1).await.unwrap();
+
+        let mut instance = Instance::get(conn.as_ref(), 1).await.unwrap();
+        let first = Deployment::try_create(conn.as_ref(), &instance, None, StatusActor::System)
+            .await
+            .unwrap();
+        edit_config(conn.as_ref(), &mut instance, "large").await;
+        let second = Deployment::try_create(conn.as_ref(), &instance, None, StatusActor::System)
+            .await
+            .unwrap();
+
+        let first_version = InstanceConfigVersion::find_by_deployment(conn.as_ref(), &first)
+            .await
+            .unwrap()
+            .expect("config is not snapshotted");
+        let second_version = InstanceConfigVersion::find_by_deployment(conn.as_ref(), &second)
+            .await
+            .unwrap()
+            .expect("config is not snapshotted");
+        assert_eq!(
+            second_version.model.version,
+            first_version.model.version + 1
+        );
+
+        let diff = diff_instance_configs(
+            conn.as_ref(),
+            &first.model.external_id.to_string(),
+            &second.model.external_id.to_string(),
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            (diff.from_version, diff.to_version),
+            (first_version.model.version, second_version.model.version)
+        );
+        let changes: Vec<_> = diff
+            .changes
+            .into_iter()
+            .map(|change| (change.field, change.old_value, change.new_value))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![(
+                "server_size".to_string(),
+                Some("medium".to_string()),
+                Some("large".to_string())
+            )]
+        );
+        assert_eq!(first_version.diff(&first_version).unwrap(), vec![]);
+
+        // only the first deployment was running, so its config is the last known good one
+        db::deployments::ActiveModel {
+            id: Set(first.model.id),
+            started_at: Set(Some(chrono::Utc::now().fixed_offset())),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let last_good = InstanceConfigVersion::last_known_good(conn.as_ref(), &instance)
+            .await
+            .unwrap()
+            .expect("first deployment was running");
+        assert_eq!(last_good.model.id, first_version.model.id);
+        instance
+            .restore_config_version(conn.as_ref(), &last_good)
+            .await
+            .unwrap();
+        assert_eq!(instance.model.user_config["server_size"], "medium");
+    }
+}
@@ -1,34 +1,72 @@
+use super::{
+    labels::{validate_labels, Labels},
+    log_buffer, metrics, Initiator, InstanceConfigVersion, StatusActor, StatusMachine,
+};
 use crate::{
-    logic::{ConfigError, DeployError, Instance, InstanceConfig, UserConfig},
+    logic::{
+        ci::{CiBackend, CiTarget},
+        github::logs::{redact_secrets, RunLogs, MAX_STORED_LOGS_BYTES},
+        notifications::{self, StatusNotification},
+        secrets::{self, PARSED_CONFIG_SECRET_FIELDS, USER_CONFIG_SECRET_FIELDS},
+        ConfigError, DeployError, Instance, InstanceConfig, UserConfig,
+    },
     server::proto,
     uuid_eq,
 };
 
 use db::sea_orm_active_enums::DeploymentStatusType;
+use octocrab::models::RunId;
 use scoutcloud_entity as db;
-use sea_orm::{prelude::*, ActiveValue::Set, ConnectionTrait, IntoActiveModel, NotSet, QueryOrder};
+use sea_orm::{
+    prelude::*,
+    sea_query::{OnConflict, Query},
+    ActiveValue,
+    ActiveValue::Set,
+    Condition, ConnectionTrait, IntoActiveModel, NotSet, QueryOrder, QuerySelect, TransactionTrait,
+};
+use std::time::Duration;
 
 pub struct Deployment {
     pub model: db::deployments::Model,
+    /// Recorded in the status history for every status change made through this value
+    actor: StatusActor,
+    /// Who requested the action, stored on the deployment when it is started or stopped
+    initiator: Option<Initiator>,
 }
 
 // Build functions
 impl Deployment {
     pub fn new(model: db::deployments::Model) -> Self {
-        Deployment { model }
+        Deployment {
+            model,
+            actor: StatusActor::System,
+            initiator: None,
+        }
+    }
+
+    pub fn with_actor(mut self, actor: StatusActor) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    pub fn initiated_by(mut self, initiator: Option<Initiator>) -> Self {
+        self.initiator = initiator;
+        self
     }
 
     pub async fn try_create<C>(
         db: &C,
         instance: &Instance,
         maybe_status: Option<DeploymentStatusType>,
+        actor: StatusActor,
     ) -> Result<Self, DeployError>
     where
-        C: ConnectionTrait,
+        C: ConnectionTrait + TransactionTrait,
     {
         let server_spec = instance.find_server_spec(db).await?.ok_or(anyhow::anyhow!(
             "server spec of the instance was not found in database"
         ))?;
+        let tx = db.begin().await?;
         let model = db::deployments::ActiveModel {
             instance_id: Set(instance.model.id),
             user_config: Set(instance.model.user_config.clone()),
@@ -37,9 +75,13 @@ impl Deployment {
             status: maybe_status.map(Set).unwrap_or(NotSet),
             ..Default::default()
         }
-        .insert(db)
+        .insert(&tx)
         .await?;
-        Ok(Deployment { model })
+        let deployment = Deployment::new(model).with_actor(actor);
+        record_status_change(&tx, &deployment.model, None, &deployment.actor, None, None).await?;
+        InstanceConfigVersion::snapshot(&tx, &deployment).await?;
+        tx.commit().await?;
+        Ok(deployment)
     }
 
     pub async fn get<C>(db: &C, id: i32) -> Result<Self, DbErr>
@@ -51,7 +93,7 @@ impl Deployment {
             .one(db)
             .await?
             .ok_or(DbErr::Custom("no deployment found".into()))?;
-        Ok(Deployment { model })
+        Ok(Deployment::new(model))
     }
 
     pub async fn latest_of_instance<C>(db: &C, instance: &Instance) -> Result<Option<Self>, DbErr>
@@ -62,7 +104,7 @@ impl Deployment {
             .filter(db::deployments::Column::InstanceId.eq(instance.model.id))
             .one(db)
             .await?
-            .map(|model| Deployment { model });
+            .map(Deployment::new);
         Ok(deployment)
     }
 
@@ -74,18 +116,145 @@ impl Deployment {
             .filter(uuid_eq!(db::deployments::Column::ExternalId, uuid.into()))
             .one(db)
             .await?
-            .map(|model| Deployment { model });
+            .map(Deployment::new);
+        Ok(deployment)
+    }
+
+    /// Finds the deployment by its own run or by one of its associated runs
+    pub async fn find_by_run_id<C>(db: &C, run_id: RunId) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let associated = Query::select()
+            .column(db::deployment_workflow_runs::Column::DeploymentId)
+            .from(db::deployment_workflow_runs::Entity)
+            .and_where(db::deployment_workflow_runs::Column::RunId.eq(run_id.0 as i64))
+            .to_owned();
+        let deployment = Self::default_select()
+            .filter(
+                Condition::any()
+                    .add(db::deployments::Column::RunId.eq(run_id.0 as i64))
+                    .add(db::deployments::Column::Id.in_subquery(associated)),
+            )
+            .one(db)
+            .await?
+            .map(Deployment::new);
         Ok(deployment)
     }
+
+    /// Counts deployments of all instances of the user which are not stopped or failed
+    pub async fn count_active_of_user<C>(db: &C, user_id: i32) -> Result<u64, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        db::deployments::Entity::find()
+            .inner_join(db::instances::Entity)
+            .filter(db::instances::Column::CreatorId.eq(user_id))
+            .filter(
+                db::deployments::Column::Status
+                    .is_not_in([DeploymentStatusType::Stopped, DeploymentStatusType::Failed]),
+            )
+            .count(db)
+            .await
+    }
+
+    /// Finds deployment of the user started with `key` after `created_after`
+    pub async fn find_by_idempotency_key<C>(
+        db: &C,
+        user_id: i32,
+        key: &str,
+        created_after: DateTimeWithTimeZone,
+    ) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let model = Self::default_select()
+            .inner_join(db::instances::Entity)
+            .filter(db::instances::Column::CreatorId.eq(user_id))
+            .filter(db::deployments::Column::IdempotencyKey.eq(key))
+            .filter(db::deployments::Column::CreatedAt.gt(created_after))
+            .one(db)
+            .await?;
+        Ok(model.map(Deployment::new))
+    }
+
+    pub async fn find_running_of_user<C>(db: &C, user_id: i32) -> Result<Vec<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let deployments = db::deployments::Entity::find()
+            .inner_join(db::instances::Entity)
+            .filter(db::instances::Column::CreatorId.eq(user_id))
+            .filter(db::deployments::Column::Status.eq(DeploymentStatusType::Running))
+            .order_by_asc(db::deployments::Column::Id)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(Deployment::new)
+            .collect();
+        Ok(deployments)
+    }
+
+    pub async fn find_with_statuses<C>(
+        db: &C,
+        statuses: &[DeploymentStatusType],
+    ) -> Result<Vec<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let deployments = db::deployments::Entity::find()
+            .filter(db::deployments::Column::Status.is_in(statuses.iter().cloned()))
+            .order_by_asc(db::deployments::Column::Id)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(Deployment::new)
+            .collect();
+        Ok(deployments)
+    }
+
+    /// Running deployments which should have been stopped by now
+    pub async fn find_expired<C>(db: &C) -> Result<Vec<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let deployments = db::deployments::Entity::find()
+            .filter(db::deployments::Column::Status.eq(DeploymentStatusType::Running))
+            .filter(db::deployments::Column::ExpiresAt.lte(chrono::Utc::now().fixed_offset()))
+            .order_by_asc(db::deployments::Column::Id)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(Deployment::new)
+            .collect();
+        Ok(deployments)
+    }
+
+    /// Reads the deployment from database again,
+    /// since it could be changed by someone else, for example by webhook
+    pub async fn reload<C>(&mut self, db: &C) -> Result<&mut Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        self.model = Self::get(db, self.model.id).await?.model;
+        Ok(self)
+    }
 }
 
 impl Deployment {
+    /// Soft-deleted deployments are hidden from users and jobs
     pub fn default_select() -> Select<db::deployments::Entity> {
+        Self::select_including_deleted().filter(db::deployments::Column::DeletedAt.is_null())
+    }
+
+    pub fn select_including_deleted() -> Select<db::deployments::Entity> {
         db::deployments::Entity::find().order_by_desc(db::deployments::Column::CreatedAt)
     }
 
     pub fn user_config(&self) -> Result<UserConfig, ConfigError> {
-        UserConfig::from_raw(self.user_config_raw().clone())
+        let mut raw = self.user_config_raw().clone();
+        secrets::decrypt_fields(&mut raw, USER_CONFIG_SECRET_FIELDS)?;
+        UserConfig::from_raw(raw)
     }
 
     pub fn user_config_raw(&self) -> &serde_json::Value {
@@ -96,6 +265,47 @@ impl Deployment {
         InstanceConfig::from_raw(self.model.parsed_config.clone())
     }
 
+    /// Target of checks and logs of workflows, they don't need secrets of the config,
+    /// so secret fields are left as they are stored
+    pub fn ci_target(&self, instance: &Instance) -> CiTarget {
+        let config = self.instance_config();
+        CiTarget {
+            client: instance.model.slug.clone(),
+            namespace: config.namespace().map(str::to_string),
+            deployment_target: config.deployment_target().map(str::to_string),
+            workflow_repo: config.workflow_repo().map(str::to_string),
+            git_ref: self
+                .model
+                .git_ref
+                .clone()
+                .or_else(|| config.git_ref().map(str::to_string)),
+            image: override_or_config(self.model.image.as_deref(), config.image()),
+            tag: override_or_config(self.model.image_tag.as_deref(), config.tag()),
+            values: config.raw,
+            secrets: Default::default(),
+        }
+    }
+
+    /// Workflows are dispatched with the config which the deployment was started with.
+    /// Secrets of the config are decrypted, references to the secret store are resolved
+    /// only here and passed to workflows apart from the values
+    pub async fn dispatch_target(&self, instance: &Instance) -> Result<CiTarget, DeployError> {
+        let mut target = self.ci_target(instance);
+        target.secrets = secrets::resolve_fields(&mut target.values, PARSED_CONFIG_SECRET_FIELDS)
+            .await
+            .map_err(ConfigError::from)?;
+        Ok(target)
+    }
+
+    /// Image and tag which workflows deploy, `None` means the defaults of the workflow
+    pub fn deployed_image(&self) -> (Option<String>, Option<String>) {
+        let config = self.instance_config();
+        (
+            override_or_config(self.model.image.as_deref(), config.image()),
+            override_or_config(self.model.image_tag.as_deref(), config.tag()),
+        )
+    }
+
     pub async fn get_instance<C>(&self, db: &C) -> Result<Instance, DbErr>
     where
         C: ConnectionTrait,
@@ -103,60 +313,799 @@ impl Deployment {
         Instance::get(db, self.model.instance_id).await
     }
 
+    pub fn run_id(&self) -> Option<RunId> {
+        self.model.run_id.map(|id| RunId(id as u64))
+    }
+
+    /// Deployment which is scheduled, being started or stopped still has a workflow to run
+    pub fn has_running_workflow(&self) -> bool {
+        matches!(
+            self.model.status,
+            DeploymentStatusType::Scheduled
+                | DeploymentStatusType::Created
+                | DeploymentStatusType::Pending
+                | DeploymentStatusType::Stopping
+        )
+    }
+
+    /// Deployment is stopped or failed, so nothing runs for it anymore
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.model.status,
+            DeploymentStatusType::Stopped | DeploymentStatusType::Failed
+        )
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.model.deleted_at.is_some()
+    }
+
+    pub fn workflow_timeout(&self) -> Option<Duration> {
+        self.model
+            .workflow_timeout_seconds
+            .map(|secs| Duration::from_secs(secs as u64))
+    }
+
     pub async fn update_status<C>(
         &mut self,
         db: &C,
         status: DeploymentStatusType,
-    ) -> Result<&mut Self, DbErr>
+    ) -> Result<&mut Self, DeployError>
     where
-        C: ConnectionTrait,
+        C: ConnectionTrait + TransactionTrait,
     {
         let mut model = self.model.clone().into_active_model();
+        // pending and stopping are driven by a new workflow run,
+        // so the run of the previous phase should never be resumed
+        let new_phase = matches!(
+            status,
+            DeploymentStatusType::Pending | DeploymentStatusType::Stopping
+        );
+        if new_phase {
+            model.run_id = Set(None);
+            model.terminal_error = Set(false);
+        }
         model.status = Set(status);
-        self.model = model.update(db).await?;
-        Ok(self)
+        self.save(db, model).await
     }
 
-    pub async fn mark_as_error<C>(
+    pub async fn set_run_id<C>(&mut self, db: &C, run_id: RunId) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.run_id = Set(Some(run_id.0 as i64));
+        self.save(db, model).await
+    }
+
+    /// Runs which are dispatched for the current phase of the deployment besides its own run,
+    /// like separate runs of frontend and indexer. They are waited for together with it
+    pub async fn associated_run_ids<C>(&self, db: &C) -> Result<Vec<RunId>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let runs = db::deployment_workflow_runs::Entity::find()
+            .filter(db::deployment_workflow_runs::Column::DeploymentId.eq(self.model.id))
+            .order_by_asc(db::deployment_workflow_runs::Column::Id)
+            .all(db)
+            .await?;
+        Ok(runs
+            .into_iter()
+            .map(|run| RunId(run.run_id as u64))
+            .collect())
+    }
+
+    /// Associations are dropped when the deployment moves to the next phase,
+    /// since runs of the new phase are dispatched again
+    pub async fn associate_run_ids<C>(&self, db: &C, run_ids: &[RunId]) -> Result<(), DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let runs = run_ids
+            .iter()
+            .filter(|run_id| Some(**run_id) != self.run_id())
+            .map(|run_id| db::deployment_workflow_runs::ActiveModel {
+                deployment_id: Set(self.model.id),
+                run_id: Set(run_id.0 as i64),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        if runs.is_empty() {
+            return Ok(());
+        }
+        db::deployment_workflow_runs::Entity::insert_many(runs)
+            .on_conflict(
+                OnConflict::columns([
+                    db::deployment_workflow_runs::Column::DeploymentId,
+                    db::deployment_workflow_runs::Column::RunId,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_workflow_timeout<C>(
+        &mut self,
+        db: &C,
+        timeout: Option<Duration>,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.workflow_timeout_seconds = Set(timeout.map(|timeout| timeout.as_secs() as i32));
+        self.save(db, model).await
+    }
+
+    pub async fn set_expires_at<C>(
+        &mut self,
+        db: &C,
+        expires_at: Option<DateTimeWithTimeZone>,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.expires_at = Set(expires_at);
+        self.save(db, model).await
+    }
+
+    pub fn labels(&self) -> Labels {
+        serde_json::from_value(self.model.labels.clone()).unwrap_or_default()
+    }
+
+    /// Replaces all labels of the deployment
+    pub async fn set_labels<C>(&mut self, db: &C, labels: &Labels) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        validate_labels(labels)?;
+        let mut model = self.model.clone().into_active_model();
+        model.labels = Set(serde_json::json!(labels));
+        self.save(db, model).await
+    }
+
+    /// Branch or tag which the deploy workflow is dispatched against instead of the one from the config
+    pub async fn set_git_ref<C>(
+        &mut self,
+        db: &C,
+        git_ref: Option<&str>,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.git_ref = Set(git_ref.map(str::to_string));
+        self.save(db, model).await
+    }
+
+    /// Number of times the failed start is dispatched again
+    pub async fn set_max_auto_retries<C>(
+        &mut self,
+        db: &C,
+        max_auto_retries: u32,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.max_auto_retries = Set(max_auto_retries as i32);
+        self.save(db, model).await
+    }
+
+    /// Retries of the start of the deployment are not exhausted yet
+    pub fn can_auto_retry(&self) -> bool {
+        self.model.auto_retries < self.model.max_auto_retries
+    }
+
+    pub fn scheduled_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.model
+            .scheduled_at
+            .map(|scheduled_at| scheduled_at.with_timezone(&chrono::Utc))
+    }
+
+    pub async fn set_scheduled_at<C>(
         &mut self,
         db: &C,
-        error: impl Into<String>,
-    ) -> Result<&mut Self, DbErr>
+        scheduled_at: Option<DateTimeWithTimeZone>,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.scheduled_at = Set(scheduled_at);
+        self.save(db, model).await
+    }
+
+    /// Stores the time of the last check of the workflow run. Version of the deployment
+    /// is not changed, so checks don't conflict with updates made by its task
+    pub async fn touch_last_polled_at<C>(db: &C, deployment_id: i32) -> Result<(), DbErr>
     where
         C: ConnectionTrait,
+    {
+        db::deployments::Entity::update_many()
+            .col_expr(
+                db::deployments::Column::LastPolledAt,
+                Expr::current_timestamp().into(),
+            )
+            .filter(db::deployments::Column::Id.eq(deployment_id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Image and tag which the deploy workflow gets instead of the ones from the config,
+    /// empty values make the workflow use its defaults
+    pub async fn set_image<C>(
+        &mut self,
+        db: &C,
+        image: Option<&str>,
+        tag: Option<&str>,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.image = Set(image.map(str::to_string));
+        model.image_tag = Set(tag.map(str::to_string));
+        self.save(db, model).await
+    }
+
+    /// Stores idempotency key of the request which created the deployment.
+    /// Keys of deployments created before `created_after` are expired, so they are released
+    pub async fn set_idempotency_key<C>(
+        &mut self,
+        db: &C,
+        key: &str,
+        fingerprint: &str,
+        created_after: DateTimeWithTimeZone,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        db::deployments::Entity::update_many()
+            .col_expr(
+                db::deployments::Column::IdempotencyKey,
+                Expr::value(Option::<String>::None),
+            )
+            .col_expr(
+                db::deployments::Column::IdempotencyFingerprint,
+                Expr::value(Option::<String>::None),
+            )
+            .filter(db::deployments::Column::InstanceId.eq(self.model.instance_id))
+            .filter(db::deployments::Column::IdempotencyKey.eq(key))
+            .filter(db::deployments::Column::CreatedAt.lte(created_after))
+            .exec(db)
+            .await?;
+        let mut model = self.model.clone().into_active_model();
+        model.idempotency_key = Set(Some(key.to_string()));
+        model.idempotency_fingerprint = Set(Some(fingerprint.to_string()));
+        self.save(db, model).await
+    }
+
+    /// Number of liveness checks in a row the running deployment didn't pass
+    pub async fn set_liveness_failures<C>(
+        &mut self,
+        db: &C,
+        failures: i32,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.liveness_failures = Set(failures);
+        self.save(db, model).await
+    }
+
+    /// Stores the message of the error for users and its code for programmatic handling
+    pub async fn mark_as_error<C>(
+        &mut self,
+        db: &C,
+        error: &DeployError,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
     {
         let mut model = self.model.clone().into_active_model();
-        model.error = Set(Some(error.into()));
+        model.error = Set(Some(error.to_string()));
+        model.error_code = Set(Some(error.code().to_string()));
         model.status = Set(DeploymentStatusType::Failed);
-        self.model = model.update(db).await?;
-        Ok(self)
+        self.finalize(db, model, |model| is_failed_with(model, error))
+            .await
     }
 
-    pub async fn mark_as_finished<C>(&mut self, db: &C) -> Result<&mut Self, DbErr>
+    /// Marks deployment as failed with an error that won't disappear on retry
+    pub async fn mark_as_terminal_error<C>(
+        &mut self,
+        db: &C,
+        error: &DeployError,
+    ) -> Result<&mut Self, DeployError>
     where
-        C: ConnectionTrait,
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        model.error = Set(Some(error.to_string()));
+        model.error_code = Set(Some(error.code().to_string()));
+        model.status = Set(DeploymentStatusType::Failed);
+        model.terminal_error = Set(true);
+        self.finalize(db, model, |model| {
+            is_failed_with(model, error) && model.terminal_error
+        })
+        .await
+    }
+
+    pub async fn mark_as_finished<C>(&mut self, db: &C) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
     {
         let mut model = self.model.clone().into_active_model();
         model.status = Set(DeploymentStatusType::Stopped);
         model.finished_at = Set(Some(chrono::Utc::now().fixed_offset()));
-        self.model = model.update(db).await?;
-        Ok(self)
+        self.finalize(db, model, |model| {
+            model.status == DeploymentStatusType::Stopped
+        })
+        .await
     }
 
     pub async fn mark_as_running<C>(&mut self, db: &C) -> Result<&mut Self, DeployError>
     where
-        C: ConnectionTrait,
+        C: ConnectionTrait + TransactionTrait,
     {
         let instance_url = self.instance_config().parse_instance_url()?;
         let mut model = self.model.clone().into_active_model();
         model.status = Set(DeploymentStatusType::Running);
         model.started_at = Set(Some(chrono::Utc::now().fixed_offset()));
+        model.finished_at = Set(None);
         model.instance_url = Set(Some(instance_url.to_string()));
-        self.model = model.update(db).await?;
+        model.liveness_failures = Set(0);
+        self.save(db, model).await
+    }
+
+    /// Moves the deployment to a final status bypassing validation of the transition,
+    /// so operators can untangle deployments which automation can't handle.
+    /// The change is recorded to the history as forced together with `reason`
+    pub async fn force_status<C>(
+        &mut self,
+        db: &C,
+        status: DeploymentStatusType,
+        reason: &str,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let mut model = self.model.clone().into_active_model();
+        match status {
+            DeploymentStatusType::Failed => {
+                let error = DeployError::Forced(reason.to_string());
+                model.error = Set(Some(error.to_string()));
+                model.error_code = Set(Some(error.code().to_string()));
+                model.terminal_error = Set(true);
+            }
+            DeploymentStatusType::Stopped => {
+                model.finished_at = Set(Some(chrono::Utc::now().fixed_offset()));
+            }
+            status => {
+                return Err(DeployError::InvalidValue(format!(
+                    "only failed and stopped statuses can be forced, got {status:?}"
+                )))
+            }
+        }
+        model.status = Set(status);
+        self.save_unchecked(db, model, Some(reason)).await
+    }
+
+    /// Only failed deployments can be retried
+    pub fn check_can_retry(&self) -> Result<(), DeployError> {
+        if self.model.status != DeploymentStatusType::Failed {
+            return Err(self.invalid_action("retry"));
+        }
+        Ok(())
+    }
+
+    /// Moves failed deployment back to `Created`, so the starting task deploys it again
+    /// with the same config. Results of the failed run are cleared, the history is kept
+    pub async fn reset_for_retry<C>(&mut self, db: &C) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        self.check_can_retry()?;
+        let mut model = self.model.clone().into_active_model();
+        model.status = Set(DeploymentStatusType::Created);
+        model.run_id = Set(None);
+        model.error = Set(None);
+        model.error_code = Set(None);
+        model.terminal_error = Set(false);
+        model.finished_at = Set(None);
+        model.liveness_failures = Set(0);
+        // retried deployment gets a new budget of automatic retries
+        model.auto_retries = Set(0);
+        self.save(db, model).await
+    }
+
+    /// Moves pending deployment with failed deploy workflow back to `Created`
+    /// and counts the attempt, so the starting task dispatches the workflow again
+    pub async fn reset_for_auto_retry<C>(&mut self, db: &C) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        if !self.can_auto_retry() {
+            return Err(self.invalid_action("retry automatically"));
+        }
+        let mut model = self.model.clone().into_active_model();
+        model.status = Set(DeploymentStatusType::Created);
+        model.run_id = Set(None);
+        model.auto_retries = Set(self.model.auto_retries + 1);
+        self.save(db, model).await
+    }
+
+    /// Hides the deployment from users, but keeps it in database, so it can be restored.
+    /// Only finished deployments can be deleted, since jobs don't see deleted ones
+    pub async fn soft_delete<C>(&mut self, db: &C) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        if !self.is_finished() {
+            return Err(self.invalid_action("delete"));
+        }
+        if self.is_deleted() {
+            return Ok(self);
+        }
+        let mut model = self.model.clone().into_active_model();
+        model.deleted_at = Set(Some(chrono::Utc::now().fixed_offset()));
+        self.save(db, model).await
+    }
+
+    /// Checks that no workflow is changing the deployment, so its instance can be moved
+    /// to another owner. The version is bumped, so the transfer conflicts with any
+    /// concurrent change of the status instead of racing with it
+    pub async fn lock_for_transfer<C>(&mut self, db: &C) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        if self.has_running_workflow() {
+            return Err(self.invalid_action("transfer"));
+        }
+        let model = self.model.clone().into_active_model();
+        self.save(db, model).await
+    }
+
+    /// Makes soft-deleted deployment visible again if it was deleted within `grace_period`
+    pub async fn restore<C>(
+        &mut self,
+        db: &C,
+        grace_period: Duration,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let Some(deleted_at) = self.model.deleted_at else {
+            return Ok(self);
+        };
+        // jobs don't track deleted deployments, so the status of an unfinished one is stale
+        // and the restored deployment would look active while nothing runs for it
+        if !self.is_finished() {
+            return Err(self.invalid_action("restore"));
+        }
+        let deleted_for = chrono::Utc::now()
+            .signed_duration_since(deleted_at)
+            .to_std()
+            .unwrap_or_default();
+        if deleted_for > grace_period {
+            return Err(DeployError::RestorePeriodExpired(grace_period));
+        }
+        let mut model = self.model.clone().into_active_model();
+        model.deleted_at = Set(None);
+        self.save(db, model).await
+    }
+
+    fn invalid_action(&self, action: &str) -> DeployError {
+        let status = map_deployment_status(Some(&self.model.status));
+        DeployError::InvalidStateTransition(
+            action.to_string(),
+            serde_plain::to_string(&status).expect("enum should be serializable"),
+        )
+    }
+
+    /// Saves the final status unless the deployment is already `finalized`, e.g. both
+    /// webhook and the task finalize it at once. Then it is neither saved nor notified
+    /// again, and the conflict with the concurrent finalization is not an error
+    async fn finalize<C>(
+        &mut self,
+        db: &C,
+        model: db::deployments::ActiveModel,
+        finalized: impl Fn(&db::deployments::Model) -> bool,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        if finalized(&self.model) {
+            return Ok(self);
+        }
+        match self.save(db, model).await.map(|_| ()) {
+            Err(DeployError::Conflict(id)) => {
+                let actual = Self::get(db, id).await?;
+                if !finalized(&actual.model) {
+                    return Err(DeployError::Conflict(id));
+                }
+                self.model = actual.model;
+            }
+            result => result?,
+        }
+        Ok(self)
+    }
+
+    /// Saves changed fields only if nobody updated the deployment since it was read,
+    /// so concurrent writers, e.g. webhook and deployment task, can't overwrite each other.
+    /// On conflict the deployment should be reloaded to decide what to do
+    async fn save<C>(
+        &mut self,
+        db: &C,
+        mut model: db::deployments::ActiveModel,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        if let ActiveValue::Set(status) = &model.status {
+            if !self.model.status.can_transition_to(status) {
+                return Err(DeployError::InvalidTransition(
+                    self.model.status.clone(),
+                    status.clone(),
+                ));
+            }
+        }
+        self.save_unchecked(db, model, None).await
+    }
+
+    /// Same as `save`, but the transition of the status is not validated.
+    /// Status set with `forced_reason` is recorded as forced even if it stays the same
+    async fn save_unchecked<C>(
+        &mut self,
+        db: &C,
+        mut model: db::deployments::ActiveModel,
+        forced_reason: Option<&str>,
+    ) -> Result<&mut Self, DeployError>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let old_status = self.model.status.clone();
+        let status_changed = matches!(
+            &model.status,
+            ActiveValue::Set(status) if *status != old_status || forced_reason.is_some()
+        );
+        if let (true, Some(initiator), ActiveValue::Set(status)) =
+            (status_changed, &self.initiator, &model.status)
+        {
+            match status {
+                DeploymentStatusType::Pending => {
+                    model.started_by = Set(Some(initiator.to_string()));
+                }
+                DeploymentStatusType::Stopping => {
+                    model.stopped_by = Set(Some(initiator.to_string()));
+                    model.stop_reason = Set(initiator.reason().map(str::to_string));
+                }
+                _ => {}
+            }
+        }
+        let (id, version) = (self.model.id, self.model.version);
+        model.version = Set(version + 1);
+        let update = db::deployments::Entity::update(model)
+            .filter(db::deployments::Column::Version.eq(version));
+        let map_err = |err: DbErr| match err {
+            DbErr::RecordNotUpdated => DeployError::Conflict(id),
+            err => DeployError::Db(err),
+        };
+
+        if status_changed {
+            let tx = db.begin().await?;
+            let updated = update.exec(&tx).await.map_err(map_err)?;
+            // runs of the previous phase are removed together with the status change,
+            // so the new phase is never saved with them
+            if matches!(
+                updated.status,
+                DeploymentStatusType::Pending | DeploymentStatusType::Stopping
+            ) {
+                db::deployment_workflow_runs::Entity::delete_many()
+                    .filter(db::deployment_workflow_runs::Column::DeploymentId.eq(id))
+                    .exec(&tx)
+                    .await?;
+            }
+            record_status_change(
+                &tx,
+                &updated,
+                Some(old_status.clone()),
+                &self.actor,
+                self.initiator.as_ref(),
+                forced_reason,
+            )
+            .await?;
+            tx.commit().await?;
+            self.model = updated;
+            if self.model.status != old_status {
+                metrics::observe_status_change(
+                    &self.model.status,
+                    self.model.error_code.as_deref(),
+                );
+            }
+            if self.is_finished() {
+                log_buffer::discard_tail(self.model.id);
+            }
+            self.notify_status_change(db).await;
+        } else {
+            self.model = update.exec(db).await.map_err(map_err)?;
+        }
         Ok(self)
     }
 }
 
+impl Deployment {
+    /// Reports the new status to slack if it is configured for it.
+    /// Deployment is already saved, so errors are only logged
+    async fn notify_status_change<C>(&self, db: &C)
+    where
+        C: ConnectionTrait,
+    {
+        let Some(notifier) = notifications::notifier_for(&self.model.status) else {
+            return;
+        };
+        let instance = match self.get_instance(db).await {
+            Ok(instance) => instance,
+            Err(err) => {
+                tracing::warn!(
+                    deployment_id = self.model.id,
+                    err = %err,
+                    "failed to find instance of deployment for notification"
+                );
+                return;
+            }
+        };
+        let error = match self.model.status {
+            DeploymentStatusType::Failed => self.model.error.clone(),
+            _ => None,
+        };
+        notifications::spawn_notification(
+            notifier,
+            StatusNotification {
+                deployment_id: self.model.external_id.to_string(),
+                instance_name: instance.model.name,
+                status: self.model.status.clone(),
+                error,
+            },
+        );
+    }
+}
+
+/// Empty override means the default of the workflow even if the config sets the value
+fn override_or_config(value: Option<&str>, config_value: Option<&str>) -> Option<String> {
+    value
+        .or(config_value)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn is_failed_with(model: &db::deployments::Model, error: &DeployError) -> bool {
+    model.status == DeploymentStatusType::Failed
+        && model.error.as_deref() == Some(error.to_string().as_str())
+        && model.error_code.as_deref() == Some(error.code())
+}
+
+/// Should be called in the same transaction as the status update,
+/// so the history never misses a change
+async fn record_status_change<C>(
+    db: &C,
+    model: &db::deployments::Model,
+    old_status: Option<DeploymentStatusType>,
+    actor: &StatusActor,
+    initiator: Option<&Initiator>,
+    forced_reason: Option<&str>,
+) -> Result<(), DbErr>
+where
+    C: ConnectionTrait,
+{
+    db::deployment_status_history::ActiveModel {
+        deployment_id: Set(model.id),
+        old_status: Set(old_status),
+        new_status: Set(model.status.clone()),
+        error: Set(model.error.clone()),
+        actor: Set(actor.to_string()),
+        run_id: Set(model.run_id),
+        forced: Set(forced_reason.is_some()),
+        reason: Set(forced_reason
+            .or_else(|| initiator.and_then(Initiator::reason))
+            .map(str::to_string)),
+        initiator: Set(initiator.map(Initiator::to_string)),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
+
+// Logs of failed workflow runs
+impl Deployment {
+    /// Downloads logs of the workflow run of the deployment and stores the end of them.
+    /// If logs can't be downloaded, the failure is stored instead of them,
+    /// so it is visible why logs of the run are missing
+    pub async fn capture_workflow_logs<C>(
+        &self,
+        db: &C,
+        ci: &dyn CiBackend,
+        run_id: RunId,
+    ) -> Result<(), DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let instance = self.get_instance(db).await?;
+        let result = ci
+            .fetch_logs(&self.ci_target(&instance), run_id, MAX_STORED_LOGS_BYTES)
+            .await;
+        match result {
+            Ok(logs) => {
+                self.save_logs(db, run_id, logs).await?;
+                Ok(())
+            }
+            Err(err) => {
+                self.save_logs_unavailable(db, run_id, &err.to_string())
+                    .await?;
+                Err(err.into())
+            }
+        }
+    }
+
+    pub async fn save_logs<C>(
+        &self,
+        db: &C,
+        run_id: RunId,
+        logs: RunLogs,
+    ) -> Result<db::deployment_logs::Model, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        db::deployment_logs::ActiveModel {
+            deployment_id: Set(self.model.id),
+            run_id: Set(run_id.0 as i64),
+            logs: Set(logs.text),
+            truncated: Set(logs.truncated),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+    }
+
+    pub async fn save_logs_unavailable<C>(
+        &self,
+        db: &C,
+        run_id: RunId,
+        fetch_error: &str,
+    ) -> Result<db::deployment_logs::Model, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        db::deployment_logs::ActiveModel {
+            deployment_id: Set(self.model.id),
+            run_id: Set(run_id.0 as i64),
+            logs: Set(String::new()),
+            fetch_error: Set(Some(redact_secrets(fetch_error))),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+    }
+
+    pub async fn latest_logs<C>(&self, db: &C) -> Result<Option<db::deployment_logs::Model>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        db::deployment_logs::Entity::find()
+            .filter(db::deployment_logs::Column::DeploymentId.eq(self.model.id))
+            .order_by_desc(db::deployment_logs::Column::Id)
+            .one(db)
+            .await
+    }
+}
+
 pub fn map_deployment_status(status: Option<&DeploymentStatusType>) -> proto::DeploymentStatus {
     match status {
         None => proto::DeploymentStatus::NoStatus,
@@ -166,5 +1115,421 @@ pub fn map_deployment_status(status: Option<&DeploymentStatusType>) -> proto::De
         Some(DeploymentStatusType::Failed) => proto::DeploymentStatus::Failed,
         Some(DeploymentStatusType::Stopping) => proto::DeploymentStatus::Stopping,
         Some(DeploymentStatusType::Stopped) => proto::DeploymentStatus::Stopped,
+        Some(DeploymentStatusType::Scheduled) => proto::DeploymentStatus::Scheduled,
+    }
+}
+
+pub fn map_proto_deployment_status(
+    status: proto::DeploymentStatus,
+) -> Option<DeploymentStatusType> {
+    match status {
+        proto::DeploymentStatus::NoStatus => None,
+        proto::DeploymentStatus::Created => Some(DeploymentStatusType::Created),
+        proto::DeploymentStatus::Pending => Some(DeploymentStatusType::Pending),
+        proto::DeploymentStatus::Running => Some(DeploymentStatusType::Running),
+        proto::DeploymentStatus::Failed => Some(DeploymentStatusType::Failed),
+        proto::DeploymentStatus::Stopping => Some(DeploymentStatusType::Stopping),
+        proto::DeploymentStatus::Stopped => Some(DeploymentStatusType::Stopped),
+        proto::DeploymentStatus::Scheduled => Some(DeploymentStatusType::Scheduled),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::notifications::SlackNotifier,
+        server::{NotifiedStatus, SlackSettings},
+        tests_utils,
+    };
+    use httpmock::{Method::POST, MockServer};
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn only_one_of_concurrent_writers_wins() {
+        let db = tests_utils::init::test_db("test", "only_one_of_concurrent_writers_wins").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        Deployment::get(conn.as_ref(), 1)
+            .await
+            .unwrap()
+            .update_status(conn.as_ref(), DeploymentStatusType::Stopping)
+            .await
+            .unwrap();
+
+        // webhook and polling task read the same stopping deployment
+        let mut webhook = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        let mut task = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        let (webhook_result, task_result) = tokio::join!(
+            webhook.mark_as_finished(conn.as_ref()),
+            task.mark_as_error(conn.as_ref(), &DeployError::Cancelled),
+        );
+        let results = [webhook_result.map(|_| ()), task_result.map(|_| ())];
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        let err = results.into_iter().find_map(Result::err).unwrap();
+        assert!(
+            matches!(err, DeployError::Conflict(1)),
+            "unexpected error: {err:?}"
+        );
+
+        let mut winner = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        assert!(
+            matches!(
+                winner.model.status,
+                DeploymentStatusType::Stopped | DeploymentStatusType::Failed
+            ),
+            "unexpected status: {:?}",
+            winner.model.status
+        );
+        // re-read deployment rejects the illegal transition instead of stomping it
+        let err = winner
+            .update_status(conn.as_ref(), DeploymentStatusType::Stopping)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DeployError::InvalidTransition(_, DeploymentStatusType::Stopping)
+            ),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn only_failed_deployment_is_notified() {
+        let db = tests_utils::init::test_db("test", "only_failed_deployment_is_notified").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let server = MockServer::start_async().await;
+        let notifier = SlackNotifier::from_settings(&SlackSettings {
+            webhook_url: server.url("/slack"),
+            channel: None,
+            notify_on: vec![NotifiedStatus::Failed],
+            notify_approaching_timeout: false,
+        })
+        .unwrap();
+        notifications::init_notifier(Some(Arc::new(notifier)));
+
+        let mut stopped = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        let mut failed = Deployment::get(conn.as_ref(), 4).await.unwrap();
+        // other tests could fail their deployments meanwhile, so messages are matched by id
+        let stopped_mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/slack")
+                    .body_contains(stopped.model.external_id.to_string());
+                then.status(200);
+            })
+            .await;
+        let failed_mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/slack")
+                    .body_contains(failed.model.external_id.to_string())
+                    .body_contains("Instance 3")
+                    .body_contains("deployment was cancelled by user");
+                then.status(200);
+            })
+            .await;
+
+        stopped
+            .update_status(conn.as_ref(), DeploymentStatusType::Stopping)
+            .await
+            .unwrap()
+            .mark_as_finished(conn.as_ref())
+            .await
+            .unwrap();
+        failed
+            .mark_as_error(conn.as_ref(), &DeployError::Cancelled)
+            .await
+            .unwrap();
+
+        let start = std::time::Instant::now();
+        while failed_mock.hits_async().await == 0 && start.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        notifications::init_notifier(None);
+        failed_mock.assert_hits_async(1).await;
+        stopped_mock.assert_hits_async(0).await;
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn repeated_error_is_saved_and_notified_once() {
+        let db =
+            tests_utils::init::test_db("test", "repeated_error_is_saved_and_notified_once").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let server = MockServer::start_async().await;
+        let notifier = SlackNotifier::from_settings(&SlackSettings {
+            webhook_url: server.url("/slack"),
+            channel: None,
+            notify_on: vec![NotifiedStatus::Failed],
+            notify_approaching_timeout: false,
+        })
+        .unwrap();
+        notifications::init_notifier(Some(Arc::new(notifier)));
+
+        // webhook and polling task read the same created deployment
+        let mut webhook = Deployment::get(conn.as_ref(), 4).await.unwrap();
+        let mut task = Deployment::get(conn.as_ref(), 4).await.unwrap();
+        let failed_mock = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/slack")
+                    .body_contains(webhook.model.external_id.to_string());
+                then.status(200);
+            })
+            .await;
+
+        webhook
+            .mark_as_error(conn.as_ref(), &DeployError::Cancelled)
+            .await
+            .unwrap();
+        let version = webhook.model.version;
+        task.mark_as_error(conn.as_ref(), &DeployError::Cancelled)
+            .await
+            .unwrap();
+        webhook
+            .mark_as_error(conn.as_ref(), &DeployError::Cancelled)
+            .await
+            .unwrap();
+        assert_eq!(task.model.version, version);
+        assert_eq!(webhook.model.version, version);
+
+        let history = webhook.status_history(conn.as_ref()).await.unwrap();
+        let failures = history
+            .iter()
+            .filter(|change| change.new_status == DeploymentStatusType::Failed)
+            .count();
+        assert_eq!(failures, 1);
+
+        let start = std::time::Instant::now();
+        while failed_mock.hits_async().await == 0 && start.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        // repeated notification would be sent right after the first one
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        notifications::init_notifier(None);
+        failed_mock.assert_hits_async(1).await;
+
+        // another error is still saved
+        let err = DeployError::Unhealthy("health endpoint returned 503".to_string());
+        task.mark_as_error(conn.as_ref(), &err).await.unwrap();
+        assert_eq!(task.model.error_code.as_deref(), Some("unhealthy"));
+    }
+
+    const STOPPED_DEPLOYMENT_ID: i32 = 2;
+    const FAILED_DEPLOYMENT_ID: i32 = 3;
+    const GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+    async fn deployment_ids<C: ConnectionTrait>(
+        db: &C,
+        instance: &Instance,
+        include_deleted: bool,
+    ) -> Vec<i32> {
+        instance
+            .deployments(db, include_deleted)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|deployment| deployment.model.id)
+            .collect()
+    }
+
+    async fn find_including_deleted<C: ConnectionTrait>(db: &C, id: i32) -> Deployment {
+        let model = Deployment::select_including_deleted()
+            .filter(db::deployments::Column::Id.eq(id))
+            .one(db)
+            .await
+            .unwrap()
+            .expect("deployment should exist");
+        Deployment::new(model)
+    }
+
+    #[tokio::test]
+    async fn deleted_deployment_is_not_listed() {
+        let db = tests_utils::init::test_db("test", "deleted_deployment_is_not_listed").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let mut deployment = Deployment::get(conn.as_ref(), STOPPED_DEPLOYMENT_ID)
+            .await
+            .unwrap();
+        let instance = deployment.get_instance(conn.as_ref()).await.unwrap();
+
+        deployment.soft_delete(conn.as_ref()).await.unwrap();
+        assert!(deployment.is_deleted());
+        assert_eq!(
+            deployment_ids(conn.as_ref(), &instance, false).await,
+            vec![FAILED_DEPLOYMENT_ID]
+        );
+        assert_eq!(
+            deployment_ids(conn.as_ref(), &instance, true).await,
+            vec![FAILED_DEPLOYMENT_ID, STOPPED_DEPLOYMENT_ID]
+        );
+        assert!(Deployment::get(conn.as_ref(), STOPPED_DEPLOYMENT_ID)
+            .await
+            .is_err());
+        let uuid = deployment.model.external_id.to_string();
+        assert!(Deployment::find_by_uuid(conn.as_ref(), uuid)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn restored_deployment_is_listed_again() {
+        let db = tests_utils::init::test_db("test", "restored_deployment_is_listed_again").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let mut deployment = Deployment::get(conn.as_ref(), FAILED_DEPLOYMENT_ID)
+            .await
+            .unwrap();
+        let instance = deployment.get_instance(conn.as_ref()).await.unwrap();
+        deployment.soft_delete(conn.as_ref()).await.unwrap();
+
+        deployment
+            .restore(conn.as_ref(), GRACE_PERIOD)
+            .await
+            .unwrap();
+        assert!(!deployment.is_deleted());
+        assert_eq!(
+            deployment_ids(conn.as_ref(), &instance, false).await,
+            vec![FAILED_DEPLOYMENT_ID, STOPPED_DEPLOYMENT_ID]
+        );
+        let latest = Deployment::latest_of_instance(conn.as_ref(), &instance)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.model.id, FAILED_DEPLOYMENT_ID);
+
+        // deployment deleted before the grace period can't be restored
+        let mut deployment = Deployment::get(conn.as_ref(), STOPPED_DEPLOYMENT_ID)
+            .await
+            .unwrap();
+        deployment.soft_delete(conn.as_ref()).await.unwrap();
+        db::deployments::ActiveModel {
+            id: Set(STOPPED_DEPLOYMENT_ID),
+            deleted_at: Set(Some(
+                (chrono::Utc::now() - chrono::Duration::days(2)).fixed_offset(),
+            )),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let mut deployment = find_including_deleted(conn.as_ref(), STOPPED_DEPLOYMENT_ID).await;
+        let err = deployment
+            .restore(conn.as_ref(), GRACE_PERIOD)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::RestorePeriodExpired(_)),
+            "unexpected error: {err:?}"
+        );
+        assert_eq!(
+            deployment_ids(conn.as_ref(), &instance, false).await,
+            vec![FAILED_DEPLOYMENT_ID]
+        );
+    }
+
+    #[tokio::test]
+    async fn unfinished_deployment_is_not_deleted_or_restored() {
+        let db = tests_utils::init::test_db("test", "unfinished_deployment_is_not_deleted").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let mut running = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        let err = running.soft_delete(conn.as_ref()).await.unwrap_err();
+        assert!(
+            matches!(err, DeployError::InvalidStateTransition(ref action, _) if action == "delete"),
+            "unexpected error: {err:?}"
+        );
+        assert!(!Deployment::get(conn.as_ref(), 1)
+            .await
+            .unwrap()
+            .is_deleted());
+
+        // running deployment hidden bypassing the api stays hidden,
+        // since jobs don't track it and its status may be stale
+        db::deployments::ActiveModel {
+            id: Set(1),
+            deleted_at: Set(Some(chrono::Utc::now().fixed_offset())),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let mut deleted = find_including_deleted(conn.as_ref(), 1).await;
+        let err = deleted
+            .restore(conn.as_ref(), GRACE_PERIOD)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::InvalidStateTransition(ref action, _) if action == "restore"),
+            "unexpected error: {err:?}"
+        );
+        assert!(find_including_deleted(conn.as_ref(), 1).await.is_deleted());
+        assert!(Deployment::get(conn.as_ref(), 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn associated_runs_are_dropped_in_next_phase() {
+        let db =
+            tests_utils::init::test_db("test", "associated_runs_are_dropped_in_next_phase").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let mut deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        deployment
+            .set_run_id(conn.as_ref(), RunId(100))
+            .await
+            .unwrap();
+
+        // own run and already associated runs are skipped
+        for run_ids in [vec![RunId(100), RunId(200), RunId(300)], vec![RunId(200)]] {
+            deployment
+                .associate_run_ids(conn.as_ref(), &run_ids)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            deployment.associated_run_ids(conn.as_ref()).await.unwrap(),
+            vec![RunId(200), RunId(300)]
+        );
+        for run_id in [100, 300] {
+            let found = Deployment::find_by_run_id(conn.as_ref(), RunId(run_id))
+                .await
+                .unwrap()
+                .map(|deployment| deployment.model.id);
+            assert_eq!(found, Some(1), "deployment of run {run_id}");
+        }
+
+        deployment
+            .update_status(conn.as_ref(), DeploymentStatusType::Stopping)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.associated_run_ids(conn.as_ref()).await.unwrap(),
+            vec![]
+        );
+        assert!(Deployment::find_by_run_id(conn.as_ref(), RunId(300))
+            .await
+            .unwrap()
+            .is_none());
     }
 }
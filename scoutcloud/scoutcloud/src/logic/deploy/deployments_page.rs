@@ -0,0 +1,330 @@
+use super::deployment::Deployment;
+use crate::logic::DeployError;
+use db::sea_orm_active_enums::DeploymentStatusType;
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, Condition, QueryOrder, QuerySelect};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PAGE_SIZE: u64 = 50;
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+#[derive(Debug, Clone, Default)]
+pub struct DeploymentsFilter {
+    pub status: Option<DeploymentStatusType>,
+    pub creator_id: Option<i32>,
+    pub include_deleted: bool,
+    pub time: DeploymentsTimeFilter,
+}
+
+/// Bounds of creation and update time of deployments. `created_after` and `updated_after`
+/// are inclusive, while `created_before` is exclusive, so consecutive ranges don't overlap
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeploymentsTimeFilter {
+    pub created_after: Option<DateTimeWithTimeZone>,
+    pub created_before: Option<DateTimeWithTimeZone>,
+    pub updated_after: Option<DateTimeWithTimeZone>,
+}
+
+impl DeploymentsTimeFilter {
+    /// Timestamps should be in RFC 3339 format, which always contains the offset
+    pub fn parse(
+        created_after: Option<&str>,
+        created_before: Option<&str>,
+        updated_after: Option<&str>,
+    ) -> Result<Self, DeployError> {
+        let parse = |name: &str, value: Option<&str>| {
+            value
+                .map(|value| {
+                    chrono::DateTime::parse_from_rfc3339(value).map_err(|err| {
+                        DeployError::InvalidValue(format!(
+                            "{name} should be RFC 3339 time, got '{value}': {err}"
+                        ))
+                    })
+                })
+                .transpose()
+        };
+        let filter = Self {
+            created_after: parse("created_after", created_after)?,
+            created_before: parse("created_before", created_before)?,
+            updated_after: parse("updated_after", updated_after)?,
+        };
+        if let (Some(after), Some(before)) = (filter.created_after, filter.created_before) {
+            if after >= before {
+                return Err(DeployError::InvalidValue(
+                    "created_after should be earlier than created_before".to_string(),
+                ));
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn apply<Q: QueryFilter>(&self, mut query: Q) -> Q {
+        use db::deployments::Column;
+
+        if let Some(created_after) = self.created_after {
+            query = query.filter(Column::CreatedAt.gte(created_after));
+        }
+        if let Some(created_before) = self.created_before {
+            query = query.filter(Column::CreatedAt.lt(created_before));
+        }
+        if let Some(updated_after) = self.updated_after {
+            query = query.filter(Column::UpdatedAt.gte(updated_after));
+        }
+        query
+    }
+}
+
+/// Position of the last deployment of the page, clients get it as an opaque token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeploymentsCursor {
+    updated_at: DateTimeWithTimeZone,
+    id: i32,
+}
+
+impl DeploymentsCursor {
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("cursor should be serializable"))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, DeployError> {
+        hex::decode(token)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| DeployError::InvalidValue("invalid page_token".to_string()))
+    }
+}
+
+pub struct DeploymentsPage {
+    pub items: Vec<(Deployment, db::instances::Model)>,
+    pub next_cursor: Option<DeploymentsCursor>,
+}
+
+impl Deployment {
+    /// Most recently updated deployments go first. Keyset pagination is used,
+    /// so the page is found by index regardless of its position
+    pub async fn find_page<C>(
+        db: &C,
+        filter: &DeploymentsFilter,
+        page_size: u64,
+        cursor: Option<&DeploymentsCursor>,
+    ) -> Result<DeploymentsPage, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        use db::deployments::Column;
+
+        let mut query = db::deployments::Entity::find()
+            .find_also_related(db::instances::Entity)
+            .order_by_desc(Column::UpdatedAt)
+            .order_by_desc(Column::Id)
+            // one more deployment to find out if there is a next page
+            .limit(page_size + 1);
+        if let Some(status) = &filter.status {
+            query = query.filter(Column::Status.eq(status.clone()));
+        }
+        if let Some(creator_id) = filter.creator_id {
+            query = query.filter(db::instances::Column::CreatorId.eq(creator_id));
+        }
+        if !filter.include_deleted {
+            query = query.filter(Column::DeletedAt.is_null());
+        }
+        query = filter.time.apply(query);
+        if let Some(cursor) = cursor {
+            query = query.filter(
+                Condition::any()
+                    .add(Column::UpdatedAt.lt(cursor.updated_at))
+                    .add(
+                        Column::UpdatedAt
+                            .eq(cursor.updated_at)
+                            .and(Column::Id.lt(cursor.id)),
+                    ),
+            );
+        }
+
+        let mut rows = query.all(db).await?;
+        let has_next_page = rows.len() as u64 > page_size;
+        rows.truncate(page_size as usize);
+        let items = rows
+            .into_iter()
+            .map(|(deployment, instance)| {
+                let instance = instance.ok_or(DbErr::RecordNotFound(format!(
+                    "instance of deployment {}",
+                    deployment.id
+                )))?;
+                Ok((Deployment::new(deployment), instance))
+            })
+            .collect::<Result<Vec<_>, DbErr>>()?;
+        let next_cursor = items
+            .last()
+            .filter(|_| has_next_page)
+            .map(|(deployment, _)| DeploymentsCursor {
+                updated_at: deployment.model.updated_at,
+                id: deployment.model.id,
+            });
+        Ok(DeploymentsPage { items, next_cursor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use sea_orm::ActiveValue::Set;
+
+    async fn page_ids(
+        db: &DatabaseConnection,
+        filter: &DeploymentsFilter,
+        page_size: u64,
+        cursor: Option<&DeploymentsCursor>,
+    ) -> (Vec<i32>, Option<DeploymentsCursor>) {
+        let page = Deployment::find_page(db, filter, page_size, cursor)
+            .await
+            .unwrap();
+        let ids = page.items.iter().map(|(d, _)| d.model.id).collect();
+        (ids, page.next_cursor)
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn deployments_page_works() {
+        let db = tests_utils::init::test_db("test", "deployments_page_works").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(&conn).await.unwrap();
+
+        // filter by status
+        let running = DeploymentsFilter {
+            status: Some(DeploymentStatusType::Running),
+            ..Default::default()
+        };
+        let (ids, next) = page_ids(conn.as_ref(), &running, 10, None).await;
+        assert_eq!((ids, next), (vec![1], None));
+
+        // filter by creator
+        let of_user = DeploymentsFilter {
+            creator_id: Some(2),
+            ..Default::default()
+        };
+        let (ids, _) = page_ids(conn.as_ref(), &of_user, 10, None).await;
+        assert_eq!(ids, vec![4, 3, 2]);
+
+        // empty result
+        let pending = DeploymentsFilter {
+            status: Some(DeploymentStatusType::Pending),
+            ..Default::default()
+        };
+        let (ids, next) = page_ids(conn.as_ref(), &pending, 10, None).await;
+        assert_eq!((ids, next), (vec![], None));
+
+        // pagination is continuous, even if deployment is updated in between
+        let all = DeploymentsFilter::default();
+        let (first, cursor) = page_ids(conn.as_ref(), &all, 2, None).await;
+        assert_eq!(first, vec![4, 3]);
+        let cursor = DeploymentsCursor::decode(&cursor.unwrap().encode()).unwrap();
+        Deployment::get(conn.as_ref(), 4)
+            .await
+            .unwrap()
+            .update_status(conn.as_ref(), DeploymentStatusType::Pending)
+            .await
+            .unwrap();
+        let (second, cursor) = page_ids(conn.as_ref(), &all, 2, Some(&cursor)).await;
+        assert_eq!((second, cursor), (vec![2, 1], None));
+
+        // updated deployment goes first
+        let (ids, _) = page_ids(conn.as_ref(), &all, 10, None).await;
+        assert_eq!(ids, vec![4, 3, 2, 1]);
+        let (ids, _) = page_ids(conn.as_ref(), &all, 1, None).await;
+        assert_eq!(ids, vec![4]);
+
+        assert!(DeploymentsCursor::decode("not-a-cursor").is_err());
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn deployments_are_filtered_by_time() {
+        let db = tests_utils::init::test_db("test", "deployments_are_filtered_by_time").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(&conn).await.unwrap();
+        let time = |value: &str| chrono::DateTime::parse_from_rfc3339(value).unwrap();
+        // deployment#N is created on the N-th of January, deployment#1 is running
+        for id in 1..=4 {
+            db::deployments::ActiveModel {
+                id: Set(id),
+                created_at: Set(time(&format!("2024-01-0{id}T00:00:00Z"))),
+                ..Default::default()
+            }
+            .update(conn.as_ref())
+            .await
+            .unwrap();
+        }
+        let ids = |time: DeploymentsTimeFilter, status: Option<DeploymentStatusType>| {
+            let conn = conn.clone();
+            async move {
+                let filter = DeploymentsFilter {
+                    status,
+                    time,
+                    ..Default::default()
+                };
+                page_ids(conn.as_ref(), &filter, 10, None).await.0
+            }
+        };
+        let filter = |created_after, created_before, updated_after| {
+            DeploymentsTimeFilter::parse(created_after, created_before, updated_after).unwrap()
+        };
+
+        // lower bound is inclusive, upper bound is exclusive
+        let created_after = filter(Some("2024-01-02T00:00:00Z"), None, None);
+        assert_eq!(ids(created_after.clone(), None).await, vec![4, 3, 2]);
+        let created_before = filter(None, Some("2024-01-03T00:00:00Z"), None);
+        assert_eq!(ids(created_before.clone(), None).await, vec![2, 1]);
+        let both = filter(
+            Some("2024-01-02T00:00:00Z"),
+            Some("2024-01-03T00:00:00Z"),
+            None,
+        );
+        assert_eq!(ids(both, None).await, vec![2]);
+        // offsets of bounds are taken into account
+        let with_offset = filter(Some("2024-01-03T02:00:00+02:00"), None, None);
+        assert_eq!(ids(with_offset, None).await, vec![4, 3]);
+
+        let updated = Deployment::get(conn.as_ref(), 4)
+            .await
+            .unwrap()
+            .update_status(conn.as_ref(), DeploymentStatusType::Pending)
+            .await
+            .unwrap()
+            .model
+            .updated_at;
+        let updated_after = DeploymentsTimeFilter {
+            updated_after: Some(updated),
+            ..Default::default()
+        };
+        assert_eq!(ids(updated_after, None).await, vec![4]);
+        let updated_later = DeploymentsTimeFilter {
+            updated_after: Some(updated + chrono::Duration::microseconds(1)),
+            ..Default::default()
+        };
+        assert_eq!(ids(updated_later, None).await, Vec::<i32>::new());
+
+        // time range is combined with the status
+        let running = Some(DeploymentStatusType::Running);
+        assert_eq!(ids(created_before, running.clone()).await, vec![1]);
+        assert_eq!(ids(created_after, running).await, Vec::<i32>::new());
+
+        for (created_after, created_before, updated_after) in [
+            (Some("yesterday"), None, None),
+            (None, None, Some("2024-01-01")),
+            (
+                Some("2024-01-02T00:00:00Z"),
+                Some("2024-01-02T00:00:00Z"),
+                None,
+            ),
+        ] {
+            let result = DeploymentsTimeFilter::parse(created_after, created_before, updated_after);
+            assert!(
+                matches!(result, Err(DeployError::InvalidValue(_))),
+                "unexpected result: {result:?}"
+            );
+        }
+    }
+}
@@ -1,11 +1,31 @@
 use crate::{
     logic::{
-        users::{user_actions, UserToken},
-        DeployError, GithubClient, Instance, InstanceDeployment, UserConfig,
+        ci::CiBackend,
+        deploy::{
+            deployment::{map_deployment_status, map_proto_deployment_status},
+            deployments_page::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+            log_buffer, log_stream, DeploymentsCursor, DeploymentsFilter, DeploymentsTimeFilter,
+            InstanceDocument, InstancesCursor, LabelSelector, Labels, LogLine, StatusActor,
+            Template,
+        },
+        jobs::{self, JobsRunner},
+        users::{check_deployment_quota, user_actions, AuthError, UserToken},
+        ConfigError, DeployError, Deployment, GithubClient, Instance, InstanceConfigVersion,
+        InstanceDeployment, UserConfig,
     },
-    server::proto,
+    server::{proto, CloneSecretsPolicy},
 };
-use sea_orm::{DatabaseConnection, TransactionTrait};
+use scoutcloud_entity::{
+    deployment_status_history, sea_orm_active_enums::DeploymentStatusType, users,
+};
+use sea_orm::{
+    prelude::DateTimeWithTimeZone, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+
+const MAX_FORCE_REASON_LENGTH: usize = 1000;
 
 pub async fn create_instance(
     db: &DatabaseConnection,
@@ -27,6 +47,160 @@ pub async fn create_instance(
     })
 }
 
+/// Copies the config of an existing instance into a new one without deploying it
+pub async fn clone_instance(
+    db: &DatabaseConnection,
+    github: &GithubClient,
+    request: &proto::CloneInstanceRequestInternal,
+    clone_secrets: CloneSecretsPolicy,
+    creator: &UserToken,
+) -> Result<proto::InstanceInternal, DeployError> {
+    let source = Instance::find_by_uuid(db, &request.instance_id)
+        .await?
+        .ok_or(DeployError::InstanceNotFound(request.instance_id.clone()))?;
+    creator.has_access_to_instance(&source)?;
+    let tx = db.begin().await?;
+    creator.allowed_to_create_instance(&tx).await?;
+    let instance = Instance::try_clone(
+        &tx,
+        &source,
+        &request.name,
+        request.overrides.as_ref(),
+        clone_secrets,
+        creator,
+    )
+    .await?;
+    user_actions::log_clone_instance(&tx, creator, &instance, &source).await?;
+    instance.commit(github, "instance clone").await?;
+    tx.commit().await?;
+
+    proto::InstanceInternal::try_from(InstanceDeployment {
+        instance,
+        deployment: None,
+    })
+}
+
+/// Returns the config of the instance as a portable json document, see `InstanceDocument`
+pub async fn export_instance(
+    db: &DatabaseConnection,
+    instance_uuid: &str,
+    user_token: &UserToken,
+) -> Result<proto::ExportInstanceResponseInternal, DeployError> {
+    let instance = Instance::find_by_uuid(db, instance_uuid)
+        .await?
+        .ok_or(DeployError::InstanceNotFound(instance_uuid.to_string()))?;
+    user_token.has_access_to_instance(&instance)?;
+    let document = InstanceDocument::from_instance(&instance)?;
+    Ok(proto::ExportInstanceResponseInternal {
+        document: document.to_json()?,
+    })
+}
+
+/// Creates a new instance from a document returned by `export_instance` without deploying it
+pub async fn import_instance(
+    db: &DatabaseConnection,
+    github: &GithubClient,
+    request: &proto::ImportInstanceRequestInternal,
+    creator: &UserToken,
+) -> Result<proto::InstanceInternal, DeployError> {
+    let document = InstanceDocument::parse(&request.document)?;
+    let config = document.config(request.overrides.as_ref())?;
+    let name = request.name.as_deref().unwrap_or(&document.name);
+    let tx = db.begin().await?;
+    creator.allowed_to_create_instance(&tx).await?;
+    let instance = Instance::try_create(&tx, name, &config, creator).await?;
+    let config = instance.user_config_raw().clone();
+    user_actions::log_create_instance(&tx, creator, &instance, &config).await?;
+    instance.commit(github, "instance import").await?;
+    tx.commit().await?;
+
+    proto::InstanceInternal::try_from(InstanceDeployment {
+        instance,
+        deployment: None,
+    })
+}
+
+/// Creates an instance which config is resolved from the template without deploying it
+pub async fn create_instance_from_template(
+    db: &DatabaseConnection,
+    github: &GithubClient,
+    request: &proto::CreateInstanceFromTemplateRequestInternal,
+    creator: &UserToken,
+) -> Result<proto::InstanceInternal, DeployError> {
+    let template = Template::find_by_uuid(db, &request.template_id)
+        .await?
+        .ok_or(DeployError::TemplateNotFound(request.template_id.clone()))?;
+    creator.has_access_to_template(&template)?;
+    let tx = db.begin().await?;
+    creator.allowed_to_create_instance(&tx).await?;
+    let instance = Instance::try_create_from_template(
+        &tx,
+        &template,
+        &request.name,
+        &request.variables,
+        request.overrides.as_ref(),
+        creator,
+    )
+    .await?;
+    let config = instance.user_config_raw().clone();
+    user_actions::log_create_instance(&tx, creator, &instance, &config).await?;
+    instance
+        .commit(github, "instance creation from template")
+        .await?;
+    tx.commit().await?;
+
+    proto::InstanceInternal::try_from(InstanceDeployment {
+        instance,
+        deployment: None,
+    })
+}
+
+/// Resolves the config of the instance from the template right away,
+/// after that it is resolved again every time the instance is started
+pub async fn update_instance_template(
+    db: &DatabaseConnection,
+    github: &GithubClient,
+    request: &proto::UpdateInstanceTemplateRequestInternal,
+    user_token: &UserToken,
+) -> Result<proto::InstanceInternal, DeployError> {
+    let template = Template::find_by_uuid(db, &request.template_id)
+        .await?
+        .ok_or(DeployError::TemplateNotFound(request.template_id.clone()))?;
+    user_token.has_access_to_template(&template)?;
+    let tx = db.begin().await?;
+    let mut instance = Instance::find_by_uuid(&tx, &request.instance_id)
+        .await?
+        .ok_or(DeployError::InstanceNotFound(request.instance_id.clone()))?;
+    user_token.has_access_to_instance(&instance)?;
+    let config = template
+        .config()?
+        .compose(&request.variables, request.overrides.as_ref())?;
+    let old_config = instance.user_config_raw().clone();
+    instance.update_config(&tx, config).await?;
+    instance
+        .set_template(
+            &tx,
+            &template,
+            &request.variables,
+            request.overrides.as_ref(),
+        )
+        .await?;
+    user_actions::log_update_config(
+        &tx,
+        user_token,
+        &instance,
+        &old_config,
+        instance.user_config_raw(),
+        false,
+    )
+    .await?;
+    instance.commit(github, "template update").await?;
+    let instance_deployment = InstanceDeployment::from_instance(&tx, instance).await?;
+    tx.commit().await?;
+    proto::InstanceInternal::try_from(instance_deployment)
+}
+
+/// Instances created from a template stop following it once their config is set directly
 pub async fn update_instance_config(
     db: &DatabaseConnection,
     github: &GithubClient,
@@ -41,12 +215,13 @@ pub async fn update_instance_config(
     user_token.has_access_to_instance(&instance)?;
     let old_config = instance.user_config_raw().clone();
     let updated_config = instance.update_config(&tx, config.clone()).await?;
+    instance.detach_template(&tx).await?;
     user_actions::log_update_config(
         &tx,
         user_token,
         &instance,
         &old_config,
-        &updated_config.raw()?,
+        instance.user_config_raw(),
         false,
     )
     .await?;
@@ -70,12 +245,13 @@ pub async fn update_instance_config_partial(
     user_token.has_access_to_instance(&instance)?;
     let old_config = instance.user_config_raw().clone();
     let updated_config = instance.update_config_partial(&tx, config).await?;
+    instance.detach_template(&tx).await?;
     user_actions::log_update_config(
         &tx,
         user_token,
         &instance,
         &old_config,
-        &updated_config.raw()?,
+        instance.user_config_raw(),
         true,
     )
     .await?;
@@ -97,6 +273,24 @@ pub async fn get_instance(
     proto::InstanceInternal::try_from(instance_deployment)
 }
 
+pub async fn update_auto_redeploy(
+    db: &DatabaseConnection,
+    instance_uuid: &str,
+    enabled: bool,
+    user_token: &UserToken,
+) -> Result<proto::InstanceInternal, DeployError> {
+    let tx = db.begin().await?;
+    let mut instance = Instance::find_by_uuid(&tx, instance_uuid)
+        .await?
+        .ok_or(DeployError::InstanceNotFound(instance_uuid.to_string()))?;
+    user_token.has_access_to_instance(&instance)?;
+    instance.set_auto_redeploy(&tx, enabled).await?;
+    user_actions::log_update_auto_redeploy(&tx, user_token, &instance, enabled).await?;
+    let instance_deployment = InstanceDeployment::from_instance(&tx, instance).await?;
+    tx.commit().await?;
+    proto::InstanceInternal::try_from(instance_deployment)
+}
+
 pub async fn list_instances(
     db: &DatabaseConnection,
     user_token: &UserToken,
@@ -111,8 +305,53 @@ pub async fn list_instances(
         .collect::<Result<Vec<_>, _>>()
 }
 
+/// Checks stored configs of instances of all users the same way as before a deployment,
+/// nothing is deployed. Only instances with invalid configs are reported
+pub async fn validate_all_instances(
+    db: &DatabaseConnection,
+    request: &proto::ValidateAllInstancesRequestInternal,
+    user_token: &UserToken,
+) -> Result<proto::ValidateAllInstancesResponseInternal, DeployError> {
+    if !user_token.user.is_superuser {
+        return Err(AuthError::Unauthorized(
+            "only superusers can validate all instances".to_string(),
+        )
+        .into());
+    }
+    let page_size = parse_page_size(request.page_size)?;
+    let cursor = request
+        .page_token
+        .as_deref()
+        .map(InstancesCursor::decode)
+        .transpose()?;
+
+    let page = Instance::find_page(db, page_size, cursor.as_ref()).await?;
+    let items = page
+        .items
+        .iter()
+        .filter_map(|(instance, creator)| {
+            let errors = instance.validate_config().err()?;
+            Some(proto::InstanceValidationReportInternal {
+                instance_id: instance.model.external_id.to_string(),
+                name: instance.model.name.clone(),
+                creator_email: creator.email.clone(),
+                errors: errors.iter().map(ToString::to_string).collect(),
+            })
+        })
+        .collect();
+    Ok(proto::ValidateAllInstancesResponseInternal {
+        items,
+        checked: page.items.len() as u64,
+        next_page_token: page.next_cursor.map(|cursor| cursor.encode()),
+    })
+}
+
+/// Unlike lists of deployments, single deployment is returned
+/// with its status history and a link to its workflow run
 pub async fn get_deployment(
     db: &DatabaseConnection,
+    runner: &JobsRunner,
+    ci: &dyn CiBackend,
     deployment_uuid: &str,
     user_token: &UserToken,
 ) -> Result<proto::DeploymentInternal, DeployError> {
@@ -120,11 +359,30 @@ pub async fn get_deployment(
         .await?
         .ok_or(DeployError::DeploymentNotFound)?;
     user_token.has_access_to_instance(&result.instance)?;
-    proto::DeploymentInternal::try_from(result)
+    let deployment = result
+        .deployment
+        .as_ref()
+        .ok_or(DeployError::DeploymentNotFound)?;
+    let history = deployment.status_history(db).await?;
+    let estimated_completion_at = deployment
+        .estimated_completion_at(db, &history)
+        .await?
+        .map(|time| time.fixed_offset().to_string());
+    let status_changes = history.into_iter().map(map_status_change).collect();
+    let config = deployment.instance_config();
+    let run_url = deployment
+        .run_id()
+        .and_then(|run_id| ci.run_url(config.deployment_target(), config.workflow_repo(), run_id));
+    let mut internal = with_queue_position(db, runner, result).await?;
+    internal.run_url = run_url;
+    internal.status_changes = status_changes;
+    internal.estimated_completion_at = estimated_completion_at;
+    Ok(internal)
 }
 
 pub async fn get_current_deployment(
     db: &DatabaseConnection,
+    runner: &JobsRunner,
     instance_uuid: &str,
     user_token: &UserToken,
 ) -> Result<proto::DeploymentInternal, DeployError> {
@@ -132,21 +390,1483 @@ pub async fn get_current_deployment(
         .await?
         .ok_or(DeployError::InstanceNotFound(instance_uuid.to_string()))?;
     user_token.has_access_to_instance(&result.instance)?;
-    proto::DeploymentInternal::try_from(result)
+    with_queue_position(db, runner, result).await
+}
+
+/// Deployment which waits for its task is shown with its place in the queue
+async fn with_queue_position(
+    db: &DatabaseConnection,
+    runner: &JobsRunner,
+    result: InstanceDeployment,
+) -> Result<proto::DeploymentInternal, DeployError> {
+    let waiting_id = result
+        .deployment
+        .as_ref()
+        // task of the scheduled deployment waits for its time, not for other tasks
+        .filter(|deployment| {
+            deployment.has_running_workflow()
+                && deployment.model.status != DeploymentStatusType::Scheduled
+        })
+        .map(|deployment| deployment.model.id);
+    let mut internal = proto::DeploymentInternal::try_from(result)?;
+    if let Some(deployment_id) = waiting_id {
+        if let Some(position) = runner.queue_position(db, deployment_id).await? {
+            internal.queue_position = Some(position.position);
+            internal.eta_seconds = position.eta.map(|eta| eta.as_secs());
+        }
+    }
+    Ok(internal)
 }
 
 pub async fn list_deployments(
     db: &DatabaseConnection,
     instance_uuid: &str,
+    include_deleted: bool,
+    label_selector: Option<&str>,
+    time: &DeploymentsTimeFilter,
     user_token: &UserToken,
 ) -> Result<Vec<proto::DeploymentInternal>, DeployError> {
+    let selector = label_selector
+        .map(LabelSelector::parse)
+        .transpose()?
+        .unwrap_or_default();
     let instance = Instance::find_by_uuid(db, instance_uuid)
         .await?
         .ok_or(DeployError::InstanceNotFound(instance_uuid.to_string()))?;
     user_token.has_access_to_instance(&instance)?;
-    let deployments = InstanceDeployment::find_deployments_of_instance(db, &instance).await?;
+    let deployments = InstanceDeployment::find_deployments_of_instance(
+        db,
+        &instance,
+        include_deleted,
+        &selector,
+        time,
+    )
+    .await?;
     deployments
         .into_iter()
         .map(proto::DeploymentInternal::try_from)
         .collect::<Result<Vec<_>, _>>()
 }
+
+/// Superusers see deployments of all users, while other users see only their own ones
+pub async fn list_all_deployments(
+    db: &DatabaseConnection,
+    request: &proto::ListAllDeploymentsRequestInternal,
+    user_token: &UserToken,
+) -> Result<proto::ListAllDeploymentsResponseInternal, DeployError> {
+    let page_size = parse_page_size(request.page_size)?;
+    let cursor = request
+        .page_token
+        .as_deref()
+        .map(DeploymentsCursor::decode)
+        .transpose()?;
+    let creator_id = match &request.user_email {
+        Some(email) => match user_token.find_accessible_user_id(db, email).await? {
+            Some(user_id) => Some(user_id),
+            None => {
+                return Ok(proto::ListAllDeploymentsResponseInternal {
+                    items: vec![],
+                    next_page_token: None,
+                })
+            }
+        },
+        None if user_token.user.is_superuser => None,
+        None => Some(user_token.user.id),
+    };
+    let filter = DeploymentsFilter {
+        status: map_proto_deployment_status(request.status),
+        creator_id,
+        include_deleted: request.include_deleted.unwrap_or_default(),
+        time: DeploymentsTimeFilter::parse(
+            request.created_after.as_deref(),
+            request.created_before.as_deref(),
+            request.updated_after.as_deref(),
+        )?,
+    };
+
+    let page = Deployment::find_page(db, &filter, page_size, cursor.as_ref()).await?;
+    let items = page
+        .items
+        .into_iter()
+        .map(|(deployment, instance)| proto::DeploymentSummaryInternal {
+            deployment_id: deployment.model.external_id.to_string(),
+            instance_id: instance.external_id.to_string(),
+            instance_name: instance.name,
+            status: map_deployment_status(Some(&deployment.model.status)),
+            error: deployment.model.error,
+            error_code: deployment.model.error_code,
+            created_at: deployment.model.created_at.to_string(),
+            started_at: deployment.model.started_at.map(|t| t.to_string()),
+            finished_at: deployment.model.finished_at.map(|t| t.to_string()),
+            updated_at: deployment.model.updated_at.to_string(),
+            deleted_at: deployment.model.deleted_at.map(|t| t.to_string()),
+        })
+        .collect();
+    Ok(proto::ListAllDeploymentsResponseInternal {
+        items,
+        next_page_token: page.next_cursor.map(|cursor| cursor.encode()),
+    })
+}
+
+fn parse_page_size(page_size: Option<u32>) -> Result<u64, DeployError> {
+    match page_size.map(u64::from) {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(size) if (1..=MAX_PAGE_SIZE).contains(&size) => Ok(size),
+        Some(size) => Err(DeployError::InvalidValue(format!(
+            "page_size should be between 1 and {MAX_PAGE_SIZE}, got {size}"
+        ))),
+    }
+}
+
+/// Finished deployment is hidden from users, but can be restored during the grace period
+pub async fn delete_deployment(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentInternal, DeployError> {
+    let tx = db.begin().await?;
+    let mut result = InstanceDeployment::find_by_deployment_uuid(&tx, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result
+        .deployment
+        .as_mut()
+        .ok_or(DeployError::DeploymentNotFound)?;
+    deployment.soft_delete(&tx).await?;
+    user_actions::log_delete_deployment(&tx, user_token, &result.instance, deployment).await?;
+    tx.commit().await?;
+    proto::DeploymentInternal::try_from(result)
+}
+
+pub async fn restore_deployment(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    grace_period: Duration,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentInternal, DeployError> {
+    let tx = db.begin().await?;
+    let mut result =
+        InstanceDeployment::find_by_deployment_uuid_including_deleted(&tx, deployment_uuid)
+            .await?
+            .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result
+        .deployment
+        .as_mut()
+        .ok_or(DeployError::DeploymentNotFound)?;
+    deployment.restore(&tx, grace_period).await?;
+    user_actions::log_restore_deployment(&tx, user_token, &result.instance, deployment).await?;
+    tx.commit().await?;
+    proto::DeploymentInternal::try_from(result)
+}
+
+pub async fn update_deployment_labels(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    labels: &Labels,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentInternal, DeployError> {
+    let tx = db.begin().await?;
+    let mut result = InstanceDeployment::find_by_deployment_uuid(&tx, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result
+        .deployment
+        .as_mut()
+        .ok_or(DeployError::DeploymentNotFound)?;
+    deployment.set_labels(&tx, labels).await?;
+    user_actions::log_update_deployment_labels(&tx, user_token, &result.instance, deployment)
+        .await?;
+    tx.commit().await?;
+    proto::DeploymentInternal::try_from(result)
+}
+
+/// Moves the instance of the deployment to `new_owner_email` together with all its
+/// deployments, since deployments are owned through their instances
+pub async fn transfer_deployment(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    new_owner_email: &str,
+    default_quota: u64,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentInternal, DeployError> {
+    if !user_token.user.is_superuser {
+        return Err(AuthError::Unauthorized(
+            "only superusers can transfer deployments".to_string(),
+        )
+        .into());
+    }
+    let tx = db.begin().await?;
+    let mut result = InstanceDeployment::find_by_deployment_uuid(&tx, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    let deployment = result
+        .deployment
+        .as_mut()
+        .ok_or(DeployError::DeploymentNotFound)?;
+    deployment.lock_for_transfer(&tx).await?;
+    let new_owner = users::Entity::find()
+        .filter(users::Column::Email.eq(new_owner_email))
+        .one(&tx)
+        .await?
+        .ok_or_else(|| DeployError::InvalidValue(format!("user '{new_owner_email}' not found")))?;
+
+    let from_user_id = result.instance.model.creator_id;
+    if from_user_id != new_owner.id {
+        let moved_active = result
+            .instance
+            .deployments(&tx, false)
+            .await?
+            .iter()
+            .filter(|deployment| !deployment.is_finished())
+            .count() as u64;
+        if moved_active > 0 {
+            check_deployment_quota(&tx, &new_owner, default_quota, moved_active).await?;
+        }
+        result.instance.transfer_to(&tx, &new_owner).await?;
+        let deployment = result
+            .deployment
+            .as_ref()
+            .ok_or(DeployError::DeploymentNotFound)?;
+        user_actions::log_transfer_deployment(
+            &tx,
+            user_token,
+            &result.instance,
+            deployment,
+            from_user_id,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    proto::DeploymentInternal::try_from(result)
+}
+
+/// Moves stuck deployment to `Failed` or `Stopped` bypassing validation of the transition.
+/// Polls of the task which still waits for the workflow of the deployment are stopped,
+/// so the task doesn't finalize the deployment once more
+pub async fn force_set_status(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    status: proto::DeploymentStatus,
+    reason: &str,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentInternal, DeployError> {
+    if !user_token.user.is_superuser {
+        return Err(AuthError::Unauthorized(
+            "only superusers can force status of deployments".to_string(),
+        )
+        .into());
+    }
+    let new_status = match map_proto_deployment_status(status) {
+        Some(status @ (DeploymentStatusType::Failed | DeploymentStatusType::Stopped)) => status,
+        _ => {
+            return Err(DeployError::InvalidValue(format!(
+                "only FAILED and STOPPED statuses can be forced, got {}",
+                serde_plain::to_string(&status).expect("enum should be serializable")
+            )))
+        }
+    };
+    let reason = reason.trim();
+    if reason.is_empty() || reason.len() > MAX_FORCE_REASON_LENGTH {
+        return Err(DeployError::InvalidValue(format!(
+            "reason should be from 1 to {MAX_FORCE_REASON_LENGTH} characters long"
+        )));
+    }
+    let InstanceDeployment {
+        instance,
+        deployment,
+    } = InstanceDeployment::find_by_deployment_uuid(db, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    let mut deployment = deployment
+        .ok_or(DeployError::DeploymentNotFound)?
+        .with_actor(StatusActor::User(user_token.user.id));
+    let old_status = deployment.model.status.clone();
+
+    let tx = db.begin().await?;
+    deployment.force_status(&tx, new_status, reason).await?;
+    user_actions::log_force_set_status(
+        &tx,
+        user_token,
+        &instance,
+        &deployment,
+        &old_status,
+        reason,
+    )
+    .await?;
+    tx.commit().await?;
+    let stopped_polls = jobs::stop_polls(deployment.model.id);
+    tracing::warn!(
+        deployment_id = deployment.model.id,
+        old_status =? old_status,
+        new_status =? deployment.model.status,
+        user_id = user_token.user.id,
+        stopped_polls,
+        reason,
+        "status of deployment was forced"
+    );
+    proto::DeploymentInternal::try_from(InstanceDeployment {
+        instance,
+        deployment: Some(deployment),
+    })
+}
+
+pub async fn get_deployment_logs(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentLogsInternal, DeployError> {
+    let result = InstanceDeployment::find_by_deployment_uuid(db, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result.deployment.ok_or(DeployError::DeploymentNotFound)?;
+    let logs = deployment
+        .latest_logs(db)
+        .await?
+        .ok_or(DeployError::DeploymentLogsNotFound)?;
+    Ok(proto::DeploymentLogsInternal {
+        deployment_id: deployment.model.external_id.to_string(),
+        run_id: logs.run_id.to_string(),
+        logs: logs.logs,
+        truncated: logs.truncated,
+        created_at: logs.created_at.to_string(),
+        fetch_error: logs.fetch_error,
+    })
+}
+
+pub async fn stream_deployment_logs(
+    db: Arc<DatabaseConnection>,
+    ci: Arc<dyn CiBackend>,
+    deployment_uuid: &str,
+    user_token: &UserToken,
+    poll_interval: Duration,
+) -> Result<mpsc::Receiver<Result<LogLine, DeployError>>, DeployError> {
+    let result = InstanceDeployment::find_by_deployment_uuid(db.as_ref(), deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result.deployment.ok_or(DeployError::DeploymentNotFound)?;
+    Ok(log_stream::stream_logs(
+        db,
+        ci,
+        result.instance,
+        deployment,
+        poll_interval,
+    ))
+}
+
+/// Most recent lines of logs of the workflow run, without downloading logs from ci.
+/// The tail is empty if the deployment is finished or its workflow is polled elsewhere
+pub async fn get_log_tail(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentLogTailInternal, DeployError> {
+    let result = InstanceDeployment::find_by_deployment_uuid(db, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result.deployment.ok_or(DeployError::DeploymentNotFound)?;
+    let lines = log_buffer::log_tail(deployment.model.id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|line| proto::DeploymentLogLineInternal {
+            run_id: line.run_id.to_string(),
+            line_number: line.number,
+            text: line.text,
+        })
+        .collect();
+    Ok(proto::DeploymentLogTailInternal {
+        deployment_id: deployment.model.external_id.to_string(),
+        lines,
+    })
+}
+
+pub async fn get_deployment_status_history(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentStatusHistoryInternal, DeployError> {
+    let result = InstanceDeployment::find_by_deployment_uuid(db, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result.deployment.ok_or(DeployError::DeploymentNotFound)?;
+    let items = deployment
+        .status_history(db)
+        .await?
+        .into_iter()
+        .map(map_status_change)
+        .collect();
+    Ok(proto::DeploymentStatusHistoryInternal {
+        deployment_id: deployment.model.external_id.to_string(),
+        items,
+    })
+}
+
+fn map_status_change(
+    change: deployment_status_history::Model,
+) -> proto::DeploymentStatusChangeInternal {
+    proto::DeploymentStatusChangeInternal {
+        old_status: map_deployment_status(change.old_status.as_ref()),
+        new_status: map_deployment_status(Some(&change.new_status)),
+        error: change.error,
+        actor: change.actor,
+        created_at: change.created_at.to_string(),
+        forced: change.forced,
+        reason: change.reason,
+        initiator: change.initiator,
+    }
+}
+
+pub async fn diff_instance_configs(
+    db: &DatabaseConnection,
+    from_deployment_uuid: &str,
+    to_deployment_uuid: &str,
+    user_token: &UserToken,
+) -> Result<proto::DiffInstanceConfigsResponseInternal, DeployError> {
+    let from = find_config_version(db, from_deployment_uuid, user_token).await?;
+    let to = find_config_version(db, to_deployment_uuid, user_token).await?;
+    let changes = from
+        .diff(&to)
+        .map_err(ConfigError::from)?
+        .into_iter()
+        .map(|diff| proto::ConfigFieldDiffInternal {
+            field: diff.field,
+            old_value: diff.old_value,
+            new_value: diff.new_value,
+        })
+        .collect();
+    Ok(proto::DiffInstanceConfigsResponseInternal {
+        from_version: from.model.version,
+        to_version: to.model.version,
+        changes,
+    })
+}
+
+/// Deployments may belong to different instances and be in any status,
+/// fields which don't apply to the status of the deployment are not set
+pub async fn compare_deployments(
+    db: &DatabaseConnection,
+    first_deployment_uuid: &str,
+    second_deployment_uuid: &str,
+    user_token: &UserToken,
+) -> Result<proto::CompareDeploymentsResponseInternal, DeployError> {
+    let now = chrono::Utc::now().fixed_offset();
+    let first = compared_deployment(db, first_deployment_uuid, user_token, now).await?;
+    let second = compared_deployment(db, second_deployment_uuid, user_token, now).await?;
+    let differences = [
+        ("status", first.status != second.status),
+        (
+            "config_version",
+            first.config_version != second.config_version,
+        ),
+        ("image", first.image != second.image),
+        ("tag", first.tag != second.tag),
+        (
+            "last_error_code",
+            first.last_error_code != second.last_error_code,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, differs)| *differs)
+    .map(|(field, _)| field.to_string())
+    .collect();
+    Ok(proto::CompareDeploymentsResponseInternal {
+        first: Some(first),
+        second: Some(second),
+        differences,
+    })
+}
+
+async fn compared_deployment(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    user_token: &UserToken,
+    now: DateTimeWithTimeZone,
+) -> Result<proto::ComparedDeploymentInternal, DeployError> {
+    let result = InstanceDeployment::find_by_deployment_uuid(db, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result.deployment.ok_or(DeployError::DeploymentNotFound)?;
+    let config_version = InstanceConfigVersion::find_by_deployment(db, &deployment)
+        .await?
+        .map(|version| version.model.version);
+    let (image, tag) = deployment.deployed_image();
+    let model = deployment.model;
+    let uptime_seconds = match (&model.status, model.started_at) {
+        (DeploymentStatusType::Running, Some(started_at)) => {
+            Some((now - started_at).num_seconds().max(0) as u64)
+        }
+        _ => None,
+    };
+    Ok(proto::ComparedDeploymentInternal {
+        deployment_id: model.external_id.to_string(),
+        instance_id: result.instance.model.external_id.to_string(),
+        instance_name: result.instance.model.name,
+        status: map_deployment_status(Some(&model.status)),
+        config_version,
+        image,
+        tag,
+        uptime_seconds,
+        started_at: model.started_at.map(|at| at.to_string()),
+        finished_at: model.finished_at.map(|at| at.to_string()),
+        last_error: model.error,
+        last_error_code: model.error_code,
+    })
+}
+
+async fn find_config_version(
+    db: &DatabaseConnection,
+    deployment_uuid: &str,
+    user_token: &UserToken,
+) -> Result<InstanceConfigVersion, DeployError> {
+    let result = InstanceDeployment::find_by_deployment_uuid(db, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&result.instance)?;
+    let deployment = result.deployment.ok_or(DeployError::DeploymentNotFound)?;
+    let version = InstanceConfigVersion::find_by_deployment(db, &deployment)
+        .await?
+        .ok_or(anyhow::anyhow!("deployment has no config snapshot"))?;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use blockscout_service_launcher::test_database::TestDbGuard;
+    use httpmock::{Method::POST, MockServer};
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+    use std::collections::BTreeMap;
+
+    const SOURCE_INSTANCE_ID: i32 = 2;
+    const SOURCE_OWNER_TOKEN_ID: i32 = 2;
+
+    fn mock_rpc() -> MockServer {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .json_body_partial(r#"{"method": "eth_chainId"}"#);
+            then.status(200).json_body(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": "0x1"
+            }));
+        });
+        server
+    }
+
+    fn overrides(value: serde_json::Value) -> proto::DeployConfigPartialInternal {
+        serde_json::from_value(value).expect("invalid overrides")
+    }
+
+    /// Points rpc of the source instance to the mocked node, so its config can be parsed
+    async fn set_source_rpc_url(db: &DatabaseConnection, rpc_url: &str) -> Instance {
+        let model = scoutcloud_entity::instances::ActiveModel {
+            id: Set(SOURCE_INSTANCE_ID),
+            user_config: Set(serde_json::json!({
+                "rpc_url": rpc_url,
+                "node_type": "geth",
+                "chain_type": "ethereum",
+                "server_size": "medium",
+                "chain_name": "Mainnet",
+            })),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .unwrap();
+        Instance::new(model)
+    }
+
+    #[tokio::test]
+    async fn clone_instance_applies_overrides() {
+        let db = tests_utils::init::test_db("test", "clone_instance_applies_overrides").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let (github, repo) = tests_utils::init::test_github_client().await;
+        let _handles = repo.build_handles();
+        let rpc = mock_rpc();
+        let source = set_source_rpc_url(conn.as_ref(), &rpc.url("/")).await;
+        let user_token = UserToken::get(conn.as_ref(), SOURCE_OWNER_TOKEN_ID)
+            .await
+            .unwrap();
+        let request = proto::CloneInstanceRequestInternal {
+            instance_id: source.model.external_id.to_string(),
+            name: "Testnet twin".to_string(),
+            overrides: Some(overrides(serde_json::json!({"chain_name": "Testnet"}))),
+        };
+
+        let instance = clone_instance(
+            conn.as_ref(),
+            &github,
+            &request,
+            CloneSecretsPolicy::Copy,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert_eq!(instance.name, "Testnet twin");
+        assert_eq!(instance.slug, "testnet-twin");
+        assert_eq!(instance.deployment_id, None);
+        let config = instance.config.unwrap();
+        assert_eq!(config.chain_name.as_deref(), Some("Testnet"));
+        assert_eq!(config.rpc_url.to_string(), rpc.url("/"));
+        assert_eq!(config.server_size, "medium");
+
+        // the source instance is untouched
+        let source = Instance::get(conn.as_ref(), SOURCE_INSTANCE_ID)
+            .await
+            .unwrap();
+        assert_eq!(
+            source.user_config().unwrap().internal.chain_name.as_deref(),
+            Some("Mainnet")
+        );
+
+        // secrets should be provided in overrides if they are not copied
+        let request = proto::CloneInstanceRequestInternal {
+            name: "Testnet twin 2".to_string(),
+            ..request
+        };
+        let result = clone_instance(
+            conn.as_ref(),
+            &github,
+            &request,
+            CloneSecretsPolicy::Reprompt,
+            &user_token,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(DeployError::InvalidValue(ref err)) if err.contains("rpc_url")),
+            "unexpected result: {:?}",
+            result.err()
+        );
+    }
+
+    #[tokio::test]
+    async fn instance_follows_its_template() {
+        let db = tests_utils::init::test_db("test", "instance_follows_its_template").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let (github, repo) = tests_utils::init::test_github_client().await;
+        let _handles = repo.build_handles();
+        let rpc = mock_rpc();
+        let user_token = UserToken::get(conn.as_ref(), SOURCE_OWNER_TOKEN_ID)
+            .await
+            .unwrap();
+        let template = crate::logic::deploy::create_config_template(
+            conn.as_ref(),
+            &proto::CreateConfigTemplateRequestInternal {
+                name: "testnets".to_string(),
+                config: serde_json::json!({
+                    "rpc_url": rpc.url("/"),
+                    "server_size": "small",
+                    "chain_name": "${network} testnet",
+                })
+                .to_string(),
+            },
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert_eq!(template.variables, vec!["network".to_string()]);
+
+        let variables = BTreeMap::from([("network".to_string(), "Sepolia".to_string())]);
+        let request = proto::CreateInstanceFromTemplateRequestInternal {
+            template_id: template.template_id.clone(),
+            name: "Sepolia".to_string(),
+            variables: variables.clone(),
+            overrides: Some(overrides(serde_json::json!({"server_size": "medium"}))),
+        };
+        let instance = create_instance_from_template(conn.as_ref(), &github, &request, &user_token)
+            .await
+            .unwrap();
+        let config = instance.config.unwrap();
+        assert_eq!(config.chain_name.as_deref(), Some("Sepolia testnet"));
+        assert_eq!(config.server_size, "medium");
+
+        // changes of the template are picked up on the next start,
+        // variables used by the new config should be provided by the instance
+        crate::logic::deploy::update_config_template(
+            conn.as_ref(),
+            &proto::UpdateConfigTemplateRequestInternal {
+                template_id: template.template_id.clone(),
+                config: serde_json::json!({
+                    "rpc_url": rpc.url("/"),
+                    "server_size": "small",
+                    "chain_name": "${network} testnet (${region})",
+                })
+                .to_string(),
+            },
+            &user_token,
+        )
+        .await
+        .unwrap();
+        let stored = Instance::find_by_uuid(conn.as_ref(), &instance.instance_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let err = stored.template_config(conn.as_ref()).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("missing values of template variables: region"),
+            "unexpected error: {err}"
+        );
+
+        let mut variables = variables;
+        variables.insert("region".to_string(), "EU".to_string());
+        let request = proto::UpdateInstanceTemplateRequestInternal {
+            instance_id: instance.instance_id.clone(),
+            template_id: template.template_id.clone(),
+            variables,
+            overrides: None,
+        };
+        let updated = update_instance_template(conn.as_ref(), &github, &request, &user_token)
+            .await
+            .unwrap();
+        let config = updated.config.unwrap();
+        assert_eq!(config.chain_name.as_deref(), Some("Sepolia testnet (EU)"));
+        assert_eq!(config.server_size, "small");
+
+        // config set directly detaches the instance from the template
+        update_instance_config_partial(
+            conn.as_ref(),
+            &github,
+            &instance.instance_id,
+            &overrides(serde_json::json!({"chain_name": "Sepolia"})),
+            &user_token,
+        )
+        .await
+        .unwrap();
+        let stored = Instance::find_by_uuid(conn.as_ref(), &instance.instance_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.model.template_id, None);
+        assert!(stored
+            .template_config(conn.as_ref())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn exported_instance_is_imported() {
+        let db = tests_utils::init::test_db("test", "exported_instance_is_imported").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let (github, repo) = tests_utils::init::test_github_client().await;
+        let handles = repo.build_handles();
+        let rpc = mock_rpc();
+        let source = set_source_rpc_url(conn.as_ref(), &rpc.url("/")).await;
+        let user_token = UserToken::get(conn.as_ref(), SOURCE_OWNER_TOKEN_ID)
+            .await
+            .unwrap();
+
+        let exported = export_instance(
+            conn.as_ref(),
+            &source.model.external_id.to_string(),
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert!(
+            !exported.document.contains(&rpc.url("/")),
+            "rpc url is exported: {}",
+            exported.document
+        );
+
+        let request = proto::ImportInstanceRequestInternal {
+            document: exported.document,
+            name: Some("Mainnet copy".to_string()),
+            overrides: None,
+        };
+        let result = import_instance(conn.as_ref(), &github, &request, &user_token).await;
+        assert!(
+            matches!(result, Err(DeployError::InvalidValue(ref err)) if err.contains("rpc_url")),
+            "unexpected result: {:?}",
+            result.err()
+        );
+        handles.assert_hits("new_commit", 0);
+
+        let request = proto::ImportInstanceRequestInternal {
+            overrides: Some(overrides(serde_json::json!({"rpc_url": rpc.url("/")}))),
+            ..request
+        };
+        let imported = import_instance(conn.as_ref(), &github, &request, &user_token)
+            .await
+            .unwrap();
+        assert_eq!(imported.name, "Mainnet copy");
+        assert_eq!(imported.deployment_id, None);
+        let imported = Instance::find_by_uuid(conn.as_ref(), &imported.instance_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            imported.user_config().unwrap().raw().unwrap(),
+            source.user_config().unwrap().raw().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn clone_instance_rejects_existing_name() {
+        let db = tests_utils::init::test_db("test", "clone_instance_rejects_existing_name").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let (github, repo) = tests_utils::init::test_github_client().await;
+        let handles = repo.build_handles();
+        let rpc = mock_rpc();
+        let source = set_source_rpc_url(conn.as_ref(), &rpc.url("/")).await;
+        let user_token = UserToken::get(conn.as_ref(), SOURCE_OWNER_TOKEN_ID)
+            .await
+            .unwrap();
+        let request = proto::CloneInstanceRequestInternal {
+            instance_id: source.model.external_id.to_string(),
+            // the user already has `Instance 3`
+            name: "instance 3".to_string(),
+            overrides: Some(overrides(serde_json::json!({"rpc_url": rpc.url("/")}))),
+        };
+
+        let result = clone_instance(
+            conn.as_ref(),
+            &github,
+            &request,
+            CloneSecretsPolicy::Reprompt,
+            &user_token,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(DeployError::DuplicateName(ref name)) if name == "instance 3"),
+            "unexpected result: {:?}",
+            result.err()
+        );
+        handles.assert_hits("new_commit", 0);
+        let instances = Instance::find_all(conn.as_ref(), &user_token)
+            .await
+            .unwrap();
+        assert_eq!(instances.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn validate_all_instances_reports_only_invalid_configs() {
+        let db = tests_utils::init::test_db("test", "validate_all_instances_reports_invalid").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        scoutcloud_entity::users::ActiveModel {
+            id: Set(1),
+            is_superuser: Set(true),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        // config of instance#2 is left without `chain_id`
+        for (id, config) in [
+            (1, serde_json::json!({"chain_id": "77"})),
+            (
+                3,
+                serde_json::json!({"chain_id": "77", "node_type": "unknown"}),
+            ),
+        ] {
+            let instance = Instance::get(conn.as_ref(), id).await.unwrap();
+            let mut user_config = instance.user_config_raw().clone();
+            crate::logic::json_utils::merge(&mut user_config, &config);
+            scoutcloud_entity::instances::ActiveModel {
+                id: Set(id),
+                user_config: Set(user_config),
+                ..Default::default()
+            }
+            .update(conn.as_ref())
+            .await
+            .unwrap();
+        }
+        let superuser = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        let validate = |page_size, page_token| {
+            let request = proto::ValidateAllInstancesRequestInternal {
+                page_size: Some(page_size),
+                page_token,
+            };
+            let conn = conn.clone();
+            let superuser = superuser.clone();
+            async move {
+                validate_all_instances(conn.as_ref(), &request, &superuser)
+                    .await
+                    .unwrap()
+            }
+        };
+        let reported = |response: &proto::ValidateAllInstancesResponseInternal| {
+            response
+                .items
+                .iter()
+                .map(|item| {
+                    (
+                        item.name.clone(),
+                        item.creator_email.clone(),
+                        item.errors.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = validate(2, None).await;
+        assert_eq!(first.checked, 2);
+        assert_eq!(
+            reported(&first),
+            vec![(
+                "Instance 2".to_string(),
+                "user2@example.com".to_string(),
+                vec!["failed to validate config: missing required field `chain_id`".to_string()],
+            )]
+        );
+        let second = validate(2, first.next_page_token.clone()).await;
+        assert_eq!(second.checked, 1);
+        assert_eq!(second.next_page_token, None);
+        assert_eq!(
+            reported(&second),
+            vec![(
+                "Instance 3".to_string(),
+                "user2@example.com".to_string(),
+                vec!["failed to validate config: unknown node_type: 'unknown'".to_string()],
+            )]
+        );
+
+        let user = UserToken::get(conn.as_ref(), 2).await.unwrap();
+        let request = proto::ValidateAllInstancesRequestInternal {
+            page_size: None,
+            page_token: None,
+        };
+        let result = validate_all_instances(conn.as_ref(), &request, &user).await;
+        assert!(
+            matches!(result, Err(DeployError::Auth(AuthError::Unauthorized(_)))),
+            "unexpected result: {:?}",
+            result.err()
+        );
+    }
+
+    async fn transfer_test_case(name: &str) -> (TestDbGuard, UserToken) {
+        let db = tests_utils::init::test_db("test", name).await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        scoutcloud_entity::users::ActiveModel {
+            id: Set(1),
+            is_superuser: Set(true),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let superuser = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        (db, superuser)
+    }
+
+    async fn deployment_uuid(db: &DatabaseConnection, id: i32) -> String {
+        Deployment::get(db, id)
+            .await
+            .unwrap()
+            .model
+            .external_id
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn transfer_deployment_moves_instance_to_new_owner() {
+        let (db, superuser) = transfer_test_case("transfer_deployment_moves_instance").await;
+        let conn = db.client();
+        // stopped deployment#2 of instance#2 of user#2
+        let uuid = deployment_uuid(conn.as_ref(), 2).await;
+
+        let result = transfer_deployment(conn.as_ref(), &uuid, "user1@example.com", 0, &superuser)
+            .await
+            .unwrap();
+        assert_eq!(result.deployment_id, uuid);
+        let instance = Instance::get(conn.as_ref(), 2).await.unwrap();
+        assert_eq!(instance.model.creator_id, 1);
+        let action = scoutcloud_entity::user_actions::Entity::find()
+            .filter(scoutcloud_entity::user_actions::Column::Action.eq("transfer_deployment"))
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .expect("transfer should be recorded");
+        assert_eq!(action.instance_id, Some(2));
+        assert_eq!(action.data["from_user_id"], 2);
+        assert_eq!(action.data["to_user_id"], 1);
+
+        let user = UserToken::get(conn.as_ref(), 2).await.unwrap();
+        let result = transfer_deployment(conn.as_ref(), &uuid, "user2@example.com", 0, &user).await;
+        assert!(
+            matches!(result, Err(DeployError::Auth(AuthError::Unauthorized(_)))),
+            "unexpected result: {:?}",
+            result.err()
+        );
+    }
+
+    #[tokio::test]
+    async fn transfer_deployment_checks_quota_of_new_owner() {
+        let (db, superuser) = transfer_test_case("transfer_deployment_checks_quota").await;
+        let conn = db.client();
+        scoutcloud_entity::users::ActiveModel {
+            id: Set(2),
+            quota: Set(Some(0)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        // running deployment#1 of instance#1 of user#1
+        let uuid = deployment_uuid(conn.as_ref(), 1).await;
+
+        let result =
+            transfer_deployment(conn.as_ref(), &uuid, "user2@example.com", 10, &superuser).await;
+        assert!(
+            matches!(
+                result,
+                Err(DeployError::Auth(AuthError::QuotaExceeded { quota: 0, .. }))
+            ),
+            "unexpected result: {:?}",
+            result.err()
+        );
+        let instance = Instance::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(instance.model.creator_id, 1);
+    }
+
+    #[tokio::test]
+    async fn transfer_deployment_rejects_deployment_in_transition() {
+        let (db, superuser) = transfer_test_case("transfer_deployment_rejects_transition").await;
+        let conn = db.client();
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(4),
+            status: Set(DeploymentStatusType::Pending),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let uuid = deployment_uuid(conn.as_ref(), 4).await;
+
+        let result =
+            transfer_deployment(conn.as_ref(), &uuid, "user1@example.com", 10, &superuser).await;
+        assert!(
+            matches!(
+                result,
+                Err(DeployError::InvalidStateTransition(ref action, _)) if action == "transfer"
+            ),
+            "unexpected result: {:?}",
+            result.err()
+        );
+        let instance = Instance::get(conn.as_ref(), 3).await.unwrap();
+        assert_eq!(instance.model.creator_id, 2);
+    }
+
+    #[tokio::test]
+    async fn forced_status_is_recorded_in_history() {
+        let (db, superuser) = transfer_test_case("forced_status_is_recorded_in_history").await;
+        let conn = db.client();
+        // deployment#4 is stuck in stopping, which can't move to failed by itself
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(4),
+            status: Set(DeploymentStatusType::Stopping),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let uuid = deployment_uuid(conn.as_ref(), 4).await;
+
+        let result = force_set_status(
+            conn.as_ref(),
+            &uuid,
+            proto::DeploymentStatus::Failed,
+            " cleanup workflow was deleted ",
+            &superuser,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.status, proto::DeploymentStatus::Failed);
+        assert_eq!(result.error_code.as_deref(), Some("forced"));
+        let deployment = Deployment::get(conn.as_ref(), 4).await.unwrap();
+        assert!(deployment.model.terminal_error);
+        let change = deployment
+            .status_history(conn.as_ref())
+            .await
+            .unwrap()
+            .pop()
+            .expect("forced change should be recorded");
+        assert_eq!(
+            (
+                change.old_status,
+                change.new_status,
+                change.actor.as_str(),
+                change.forced,
+                change.reason.as_deref()
+            ),
+            (
+                Some(DeploymentStatusType::Stopping),
+                DeploymentStatusType::Failed,
+                "user:1",
+                true,
+                Some("cleanup workflow was deleted")
+            )
+        );
+        let action = scoutcloud_entity::user_actions::Entity::find()
+            .filter(scoutcloud_entity::user_actions::Column::Action.eq("force_set_status"))
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .expect("forced status should be recorded");
+        assert_eq!(action.token_id, superuser.token.id);
+        assert_eq!(action.data["old_status"], "stopping");
+        assert_eq!(action.data["new_status"], "failed");
+        assert_eq!(action.data["reason"], "cleanup workflow was deleted");
+
+        let result = force_set_status(
+            conn.as_ref(),
+            &uuid,
+            proto::DeploymentStatus::Running,
+            "instance is fine",
+            &superuser,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(DeployError::InvalidValue(_))),
+            "unexpected result: {:?}",
+            result.err()
+        );
+    }
+
+    #[tokio::test]
+    async fn only_superusers_can_force_status() {
+        let (db, _superuser) = transfer_test_case("only_superusers_can_force_status").await;
+        let conn = db.client();
+        let uuid = deployment_uuid(conn.as_ref(), 4).await;
+        // owner of the deployment is not enough
+        let owner = UserToken::get(conn.as_ref(), 2).await.unwrap();
+
+        let result = force_set_status(
+            conn.as_ref(),
+            &uuid,
+            proto::DeploymentStatus::Stopped,
+            "stuck",
+            &owner,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(DeployError::Auth(AuthError::Unauthorized(_)))),
+            "unexpected result: {:?}",
+            result.err()
+        );
+        let deployment = Deployment::get(conn.as_ref(), 4).await.unwrap();
+        assert!(deployment
+            .status_history(conn.as_ref())
+            .await
+            .unwrap()
+            .iter()
+            .all(|change| !change.forced));
+    }
+
+    #[tokio::test]
+    async fn deployments_are_filtered_by_labels() {
+        let db = tests_utils::init::test_db("test", "deployments_are_filtered_by_labels").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        // deployments #2 and #3 of instance#2
+        for (id, labels) in [
+            (2, serde_json::json!({"team": "indexing", "env": "staging"})),
+            (
+                3,
+                serde_json::json!({"team": "indexing", "env": "production"}),
+            ),
+        ] {
+            let labels = serde_json::from_value(labels).unwrap();
+            Deployment::get(conn.as_ref(), id)
+                .await
+                .unwrap()
+                .set_labels(conn.as_ref(), &labels)
+                .await
+                .unwrap();
+        }
+        let instance = Instance::get(conn.as_ref(), 2).await.unwrap();
+        let instance_id = instance.model.external_id.to_string();
+        let user_token = UserToken::get(conn.as_ref(), 2).await.unwrap();
+        let list = |selector: Option<&'static str>| {
+            let conn = conn.clone();
+            let instance_id = instance_id.clone();
+            let user_token = user_token.clone();
+            async move {
+                let mut ids = list_deployments(
+                    conn.as_ref(),
+                    &instance_id,
+                    false,
+                    selector,
+                    &Default::default(),
+                    &user_token,
+                )
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|deployment| deployment.deployment_id)
+                .collect::<Vec<_>>();
+                ids.sort();
+                ids
+            }
+        };
+        let uuids = |ids: &[i32]| {
+            let conn = conn.clone();
+            let ids = ids.to_vec();
+            async move {
+                let mut uuids = vec![];
+                for id in ids {
+                    uuids.push(deployment_uuid(conn.as_ref(), id).await);
+                }
+                uuids.sort();
+                uuids
+            }
+        };
+
+        assert_eq!(list(None).await, uuids(&[2, 3]).await);
+        assert_eq!(list(Some("team=indexing")).await, uuids(&[2, 3]).await);
+        assert_eq!(list(Some("env=staging")).await, uuids(&[2]).await);
+        assert_eq!(
+            list(Some("team=indexing,env=production")).await,
+            uuids(&[3]).await
+        );
+        assert_eq!(
+            list(Some("team=explorer,env=staging")).await,
+            uuids(&[]).await
+        );
+
+        let result = list_deployments(
+            conn.as_ref(),
+            &instance_id,
+            false,
+            Some("team"),
+            &Default::default(),
+            &user_token,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(DeployError::InvalidValue(_))),
+            "unexpected result: {:?}",
+            result.err()
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn get_deployment_returns_run_details() {
+        let (db, github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("get_deployment_returns_run_details").await;
+        let conn = db.client();
+        // running deployment#1 of instance#1 of user#1
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(1),
+            user_config: Set(serde_json::json!({
+                "rpc_url": "https://sepolia.drpc.org/",
+                "server_size": "medium",
+                "node_type": "geth",
+                "chain_type": "ethereum",
+            })),
+            run_id: Set(Some(8819501642)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        for (old_status, new_status) in [
+            (None, DeploymentStatusType::Pending),
+            (
+                Some(DeploymentStatusType::Pending),
+                DeploymentStatusType::Running,
+            ),
+        ] {
+            deployment_status_history::ActiveModel {
+                deployment_id: Set(1),
+                old_status: Set(old_status),
+                new_status: Set(new_status),
+                actor: Set("system".to_string()),
+                ..Default::default()
+            }
+            .insert(conn.as_ref())
+            .await
+            .unwrap();
+        }
+        Deployment::touch_last_polled_at(conn.as_ref(), 1)
+            .await
+            .unwrap();
+        let user_token = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        let uuid = deployment_uuid(conn.as_ref(), 1).await;
+
+        let deployment =
+            get_deployment(conn.as_ref(), &runner, github.as_ref(), &uuid, &user_token)
+                .await
+                .unwrap();
+        assert_eq!(
+            deployment.status,
+            map_deployment_status(Some(&DeploymentStatusType::Running))
+        );
+        assert_eq!(deployment.run_id.as_deref(), Some("8819501642"));
+        let run_url = url::Url::parse(deployment.run_url.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            run_url.path(),
+            "/test-owner/test-repo/actions/runs/8819501642"
+        );
+        assert!(deployment.last_polled_at.is_some());
+        let statuses = deployment
+            .status_changes
+            .iter()
+            .map(|change| change.new_status)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [DeploymentStatusType::Pending, DeploymentStatusType::Running]
+                .iter()
+                .map(|status| map_deployment_status(Some(status)))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// Superuser#1 sees deployments of all instances
+    async fn compare_test_case(name: &str) -> (TestDbGuard, UserToken) {
+        let db = tests_utils::init::test_db("test", name).await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        scoutcloud_entity::users::ActiveModel {
+            id: Set(1),
+            is_superuser: Set(true),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let user_token = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        (db, user_token)
+    }
+
+    #[tokio::test]
+    async fn running_deployments_are_compared() {
+        let (db, user_token) = compare_test_case("running_deployments_are_compared").await;
+        let conn = db.client();
+        // deployment#1 gets its tag from the config, deployment#2 overrides the image
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(1),
+            parsed_config: Set(serde_json::json!({"tag": "v2"})),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(2),
+            status: Set(DeploymentStatusType::Running),
+            image: Set(Some("blockscout/blockscout-custom".to_string())),
+            image_tag: Set(Some("v2".to_string())),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        for id in [1, 2] {
+            let deployment = Deployment::get(conn.as_ref(), id).await.unwrap();
+            InstanceConfigVersion::snapshot(conn.as_ref(), &deployment)
+                .await
+                .unwrap();
+        }
+
+        let response = compare_deployments(
+            conn.as_ref(),
+            &deployment_uuid(conn.as_ref(), 1).await,
+            &deployment_uuid(conn.as_ref(), 2).await,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        let (first, second) = (response.first.unwrap(), response.second.unwrap());
+        for deployment in [&first, &second] {
+            assert_eq!(
+                deployment.status,
+                map_deployment_status(Some(&DeploymentStatusType::Running))
+            );
+            assert_eq!(deployment.config_version, Some(1));
+            assert_eq!(deployment.tag.as_deref(), Some("v2"));
+            assert_eq!(deployment.last_error, None);
+        }
+        assert_eq!(first.image, None);
+        assert_eq!(
+            second.image.as_deref(),
+            Some("blockscout/blockscout-custom")
+        );
+        // deployments were started 4 and 3 hours ago
+        let hour = 60 * 60;
+        assert!((4 * hour..5 * hour).contains(&first.uptime_seconds.unwrap()));
+        assert!((3 * hour..4 * hour).contains(&second.uptime_seconds.unwrap()));
+        assert_eq!(response.differences, vec!["image"]);
+    }
+
+    #[tokio::test]
+    async fn running_deployment_is_compared_with_failed_one() {
+        let (db, user_token) =
+            compare_test_case("running_deployment_is_compared_with_failed_one").await;
+        let conn = db.client();
+        let error = DeployError::Unhealthy("instance doesn't respond".to_string());
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(3),
+            error: Set(Some(error.to_string())),
+            error_code: Set(Some(error.code().to_string())),
+            finished_at: Set(Some(chrono::Utc::now().fixed_offset())),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+
+        let response = compare_deployments(
+            conn.as_ref(),
+            &deployment_uuid(conn.as_ref(), 1).await,
+            &deployment_uuid(conn.as_ref(), 3).await,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        let (running, failed) = (response.first.unwrap(), response.second.unwrap());
+        assert!(running.uptime_seconds.is_some());
+        assert_eq!(running.finished_at, None);
+        assert_eq!(running.last_error_code, None);
+        assert_eq!(
+            failed.status,
+            map_deployment_status(Some(&DeploymentStatusType::Failed))
+        );
+        assert_eq!(failed.uptime_seconds, None);
+        assert!(failed.finished_at.is_some());
+        assert_eq!(failed.last_error, Some(error.to_string()));
+        assert_eq!(failed.last_error_code.as_deref(), Some("unhealthy"));
+        // neither deployment has a config snapshot
+        assert_eq!(
+            (running.config_version, failed.config_version),
+            (None, None)
+        );
+        assert_eq!(response.differences, vec!["status", "last_error_code"]);
+    }
+}
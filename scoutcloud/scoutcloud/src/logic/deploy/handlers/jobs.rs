@@ -0,0 +1,29 @@
+use crate::{
+    logic::{
+        jobs::JobsRunner,
+        users::{user_actions, AuthError, UserToken},
+        DeployError,
+    },
+    server::proto,
+};
+use sea_orm::DatabaseConnection;
+
+/// Pauses or resumes tasks which deploy and stop instances, e.g. for maintenance of github
+pub async fn update_jobs_pause(
+    db: &DatabaseConnection,
+    runner: &JobsRunner,
+    paused: bool,
+    user_token: &UserToken,
+) -> Result<proto::JobsStatusInternal, DeployError> {
+    if !user_token.user.is_superuser {
+        return Err(
+            AuthError::Unauthorized("only superusers can pause jobs runner".to_string()).into(),
+        );
+    }
+    runner.set_paused(paused).await;
+    user_actions::log_update_jobs_pause(db, user_token, paused).await?;
+    Ok(proto::JobsStatusInternal {
+        paused: runner.pause_state().await.is_paused(),
+        running_tasks: runner.running_tasks().await as u64,
+    })
+}
@@ -1,5 +1,15 @@
 mod crud;
+mod jobs;
+mod reconcile;
+mod templates;
 mod update_status;
+mod usage;
+mod webhook;
 
 pub use crud::*;
+pub use jobs::*;
+pub use reconcile::*;
+pub use templates::*;
 pub use update_status::*;
+pub use usage::*;
+pub use webhook::*;
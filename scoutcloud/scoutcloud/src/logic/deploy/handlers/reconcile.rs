@@ -0,0 +1,382 @@
+use crate::{
+    logic::{
+        ci::{CiBackend, CiWorkflow},
+        deploy::{deployment::map_deployment_status, StatusActor},
+        github::{types::RunConclusion, PollBackoff},
+        jobs::JobsRunner,
+        users::{user_actions, AuthError, UserToken},
+        DeployError, Deployment, GithubError, Instance,
+    },
+    server::proto,
+};
+use octocrab::models::RunId;
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::DatabaseConnection;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// State of the workflow run of a deployment, checked once without waiting for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RunState {
+    InProgress,
+    Completed(RunConclusion),
+    Missing,
+}
+
+impl RunState {
+    fn name(&self) -> String {
+        match self {
+            RunState::InProgress => "in_progress".to_string(),
+            RunState::Completed(conclusion) => {
+                serde_plain::to_string(conclusion).expect("enum should be serializable")
+            }
+            RunState::Missing => "missing".to_string(),
+        }
+    }
+
+    /// Status the deployment should have according to its run, `None` if it matches.
+    /// Running deployment whose deploy run is gone is not a drift:
+    /// old runs are removed by github, but the instance keeps running
+    fn expected_status(&self, recorded: &DeploymentStatusType) -> Option<DeploymentStatusType> {
+        use DeploymentStatusType::*;
+        match (recorded, self) {
+            (Pending, RunState::Completed(conclusion)) if conclusion.is_ok() => Some(Running),
+            (Stopping, RunState::Completed(conclusion)) if conclusion.is_ok() => Some(Stopped),
+            (Pending | Running | Stopping, RunState::Completed(conclusion))
+                if !conclusion.is_ok() =>
+            {
+                Some(Failed)
+            }
+            (Pending | Stopping, RunState::Missing) => Some(Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Compares statuses of deployments which wait for workflow runs or are running
+/// with the actual state of their runs. Deployments are only read unless `fix` is set,
+/// and deployments handled by tasks right now are skipped, so the tasks are never raced.
+/// Fixes are saved only if the deployment wasn't changed since it was checked
+pub async fn reconcile_deployments(
+    db: &DatabaseConnection,
+    runner: &JobsRunner,
+    ci: &dyn CiBackend,
+    fix: bool,
+    user_token: &UserToken,
+) -> Result<proto::ReconcileDeploymentsResponseInternal, DeployError> {
+    if !user_token.user.is_superuser {
+        return Err(AuthError::Unauthorized(
+            "only superusers can reconcile deployments".to_string(),
+        )
+        .into());
+    }
+    let deployments = Deployment::find_with_statuses(
+        db,
+        &[
+            DeploymentStatusType::Pending,
+            DeploymentStatusType::Running,
+            DeploymentStatusType::Stopping,
+        ],
+    )
+    .await?;
+    let (mut checked, mut skipped) = (0, 0);
+    let (mut drifts, mut errors) = (vec![], vec![]);
+    for deployment in deployments {
+        let Some(run_id) = deployment.run_id() else {
+            continue;
+        };
+        if deployment.is_deleted() {
+            continue;
+        }
+        if runner
+            .queue_position(db, deployment.model.id)
+            .await?
+            .is_some()
+        {
+            skipped += 1;
+            continue;
+        }
+        let instance = deployment.get_instance(db).await?;
+        let state = match check_run(ci, &deployment, &instance, run_id).await {
+            Ok(state) => state,
+            Err(err) => {
+                errors.push(format!(
+                    "deployment {}: {err}",
+                    deployment.model.external_id
+                ));
+                continue;
+            }
+        };
+        checked += 1;
+        let Some(expected) = state.expected_status(&deployment.model.status) else {
+            continue;
+        };
+        tracing::warn!(
+            deployment_id = deployment.model.id,
+            run_id = run_id.0,
+            recorded_status = ?deployment.model.status,
+            expected_status = ?expected,
+            run_state = state.name(),
+            "status of deployment doesn't match its workflow run"
+        );
+        let mut drift = proto::DeploymentDriftInternal {
+            deployment_id: deployment.model.external_id.to_string(),
+            instance_id: instance.model.external_id.to_string(),
+            run_id: run_id.to_string(),
+            recorded_status: map_deployment_status(Some(&deployment.model.status)),
+            expected_status: map_deployment_status(Some(&expected)),
+            run_state: state.name(),
+            fixed: false,
+            error: None,
+        };
+        if fix {
+            let mut deployment = deployment.with_actor(StatusActor::User(user_token.user.id));
+            match fix_status(db, &mut deployment, run_id, &state, &expected).await {
+                Ok(()) => drift.fixed = true,
+                Err(err) => drift.error = Some(err.to_string()),
+            }
+        }
+        drifts.push(drift);
+    }
+    if fix {
+        let fixed: Vec<_> = drifts
+            .iter()
+            .filter(|drift| drift.fixed)
+            .map(|drift| drift.deployment_id.as_str())
+            .collect();
+        user_actions::log_reconcile_deployments(db, user_token, &fixed).await?;
+    }
+    Ok(proto::ReconcileDeploymentsResponseInternal {
+        checked,
+        skipped,
+        drifts,
+        errors,
+    })
+}
+
+async fn check_run(
+    ci: &dyn CiBackend,
+    deployment: &Deployment,
+    instance: &Instance,
+    run_id: RunId,
+) -> Result<RunState, DeployError> {
+    let workflow = match deployment.model.status {
+        DeploymentStatusType::Stopping => CiWorkflow::Cleanup,
+        _ => CiWorkflow::Deploy,
+    };
+    let target = deployment.ci_target(instance);
+    let run = match ci.get_run(workflow, &target, run_id).await {
+        Ok(run) => run,
+        Err(err) if err.is_not_found() => return Ok(RunState::Missing),
+        Err(err) => return Err(err.into()),
+    };
+    // zero timeout checks the run once instead of waiting for it
+    let result = ci
+        .wait_for_success(
+            &run,
+            Duration::ZERO,
+            PollBackoff::from_initial(Duration::ZERO),
+            &CancellationToken::new(),
+        )
+        .await;
+    match result {
+        Ok(conclusion) => Ok(RunState::Completed(conclusion)),
+        Err(GithubError::WorkflowFailed { conclusion, .. }) => Ok(RunState::Completed(conclusion)),
+        Err(GithubError::WorkflowTimeout { .. } | GithubError::NoRunnerAvailable { .. }) => {
+            Ok(RunState::InProgress)
+        }
+        Err(GithubError::RunDisappeared(_)) => Ok(RunState::Missing),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn fix_status(
+    db: &DatabaseConnection,
+    deployment: &mut Deployment,
+    run_id: RunId,
+    state: &RunState,
+    expected: &DeploymentStatusType,
+) -> Result<(), DeployError> {
+    match (expected, state) {
+        (DeploymentStatusType::Running, _) => {
+            deployment.mark_as_running(db).await?;
+        }
+        (DeploymentStatusType::Stopped, _) => {
+            deployment.mark_as_finished(db).await?;
+        }
+        (DeploymentStatusType::Failed, RunState::Completed(conclusion)) => {
+            let error = DeployError::from_conclusion(run_id, conclusion.clone());
+            deployment.mark_as_terminal_error(db, &error).await?;
+        }
+        (DeploymentStatusType::Failed, _) => {
+            let error = DeployError::RunDisappeared { run_id };
+            deployment.mark_as_terminal_error(db, &error).await?;
+        }
+        (status, _) => {
+            return Err(DeployError::Internal(anyhow::anyhow!(
+                "deployment can't be reconciled to {status:?}"
+            )))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils::{
+        self,
+        ci::{FakeCi, FakeRun},
+    };
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity as db;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+
+    const SUCCEEDED_RUN_ID: RunId = RunId(100);
+    const IN_PROGRESS_RUN_ID: RunId = RunId(200);
+    const FAILED_RUN_ID: RunId = RunId(300);
+
+    /// Backend which reports runs as completed or in progress depending on their ids
+    fn fake_ci() -> FakeCi {
+        FakeCi::default()
+            .with_default_run(FakeRun::Disappears)
+            .with_run(SUCCEEDED_RUN_ID, FakeRun::Succeeds)
+            .with_run(IN_PROGRESS_RUN_ID, FakeRun::TimesOut)
+            .with_run(FAILED_RUN_ID, FakeRun::Fails)
+    }
+
+    async fn set_status(
+        conn: &DatabaseConnection,
+        deployment_id: i32,
+        status: DeploymentStatusType,
+        run_id: RunId,
+    ) {
+        db::deployments::ActiveModel {
+            id: Set(deployment_id),
+            status: Set(status),
+            run_id: Set(Some(run_id.0 as i64)),
+            ..Default::default()
+        }
+        .update(conn)
+        .await
+        .unwrap();
+    }
+
+    async fn status_of(conn: &DatabaseConnection, deployment_id: i32) -> DeploymentStatusType {
+        Deployment::get(conn, deployment_id)
+            .await
+            .unwrap()
+            .model
+            .status
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn deployments_are_reconciled_with_their_runs() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("deployments_are_reconciled_with_their_runs")
+                .await;
+        let conn = db.client();
+        db::users::ActiveModel {
+            id: Set(1),
+            is_superuser: Set(true),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let user_token = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        // statuses of deployments#1 and #4 match their runs
+        set_status(&conn, 1, DeploymentStatusType::Running, SUCCEEDED_RUN_ID).await;
+        set_status(&conn, 4, DeploymentStatusType::Pending, IN_PROGRESS_RUN_ID).await;
+        // cleanup of deployment#2 has finished and deploy of deployment#3 has failed
+        set_status(&conn, 2, DeploymentStatusType::Stopping, SUCCEEDED_RUN_ID).await;
+        set_status(&conn, 3, DeploymentStatusType::Pending, FAILED_RUN_ID).await;
+        let ci = fake_ci();
+
+        let report = reconcile_deployments(&conn, &runner, &ci, false, &user_token)
+            .await
+            .unwrap();
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.skipped, 0);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        let drifts: Vec<_> = report
+            .drifts
+            .iter()
+            .map(|drift| {
+                (
+                    drift.recorded_status,
+                    drift.expected_status,
+                    drift.run_state.as_str(),
+                    drift.fixed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            drifts,
+            vec![
+                (
+                    proto::DeploymentStatus::Stopping,
+                    proto::DeploymentStatus::Stopped,
+                    "success",
+                    false
+                ),
+                (
+                    proto::DeploymentStatus::Pending,
+                    proto::DeploymentStatus::Failed,
+                    "failure",
+                    false
+                ),
+            ]
+        );
+        // report alone changes nothing
+        assert_eq!(status_of(&conn, 2).await, DeploymentStatusType::Stopping);
+        assert_eq!(status_of(&conn, 3).await, DeploymentStatusType::Pending);
+
+        let report = reconcile_deployments(&conn, &runner, &ci, true, &user_token)
+            .await
+            .unwrap();
+        assert_eq!(report.drifts.len(), 2);
+        assert!(
+            report.drifts.iter().all(|drift| drift.fixed),
+            "{:?}",
+            report.drifts
+        );
+        for (id, expected) in [
+            (1, DeploymentStatusType::Running),
+            (2, DeploymentStatusType::Stopped),
+            (3, DeploymentStatusType::Failed),
+            (4, DeploymentStatusType::Pending),
+        ] {
+            assert_eq!(
+                status_of(&conn, id).await,
+                expected,
+                "unexpected status of deployment {id}"
+            );
+        }
+        let failed = Deployment::get(conn.as_ref(), 3).await.unwrap();
+        assert_eq!(failed.model.error_code.as_deref(), Some("workflow_failed"));
+
+        let report = reconcile_deployments(&conn, &runner, &ci, true, &user_token)
+            .await
+            .unwrap();
+        assert_eq!(report.checked, 2);
+        assert!(report.drifts.is_empty(), "{:?}", report.drifts);
+        assert!(ci.dispatched().is_empty());
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn only_superusers_can_reconcile_deployments() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("only_superusers_can_reconcile_deployments")
+                .await;
+        let conn = db.client();
+        let user_token = UserToken::get(conn.as_ref(), 2).await.unwrap();
+        let result = reconcile_deployments(&conn, &runner, &fake_ci(), true, &user_token).await;
+        assert!(
+            matches!(result, Err(DeployError::Auth(AuthError::Unauthorized(_)))),
+            "unexpected result: {result:?}"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{
+    logic::{deploy::Template, users::UserToken, ConfigTemplate, DeployError},
+    server::proto,
+};
+use sea_orm::DatabaseConnection;
+
+pub async fn create_config_template(
+    db: &DatabaseConnection,
+    request: &proto::CreateConfigTemplateRequestInternal,
+    creator: &UserToken,
+) -> Result<proto::ConfigTemplateInternal, DeployError> {
+    let config = parse_template_config(&request.config)?;
+    let template = Template::try_create(db, &request.name, &config, creator).await?;
+    proto::ConfigTemplateInternal::try_from(template)
+}
+
+/// Instances which reference the template are not changed until they are started again
+pub async fn update_config_template(
+    db: &DatabaseConnection,
+    request: &proto::UpdateConfigTemplateRequestInternal,
+    user_token: &UserToken,
+) -> Result<proto::ConfigTemplateInternal, DeployError> {
+    let mut template = Template::find_by_uuid(db, &request.template_id)
+        .await?
+        .ok_or(DeployError::TemplateNotFound(request.template_id.clone()))?;
+    user_token.has_access_to_template(&template)?;
+    let config = parse_template_config(&request.config)?;
+    template.update_config(db, &config).await?;
+    proto::ConfigTemplateInternal::try_from(template)
+}
+
+pub async fn list_config_templates(
+    db: &DatabaseConnection,
+    user_token: &UserToken,
+) -> Result<Vec<proto::ConfigTemplateInternal>, DeployError> {
+    Template::find_all(db, user_token)
+        .await?
+        .into_iter()
+        .map(proto::ConfigTemplateInternal::try_from)
+        .collect()
+}
+
+fn parse_template_config(config: &str) -> Result<ConfigTemplate, DeployError> {
+    let config = serde_json::from_str(config)
+        .map_err(|err| DeployError::InvalidValue(format!("invalid config of template: {err}")))?;
+    Ok(ConfigTemplate::new(config)?)
+}
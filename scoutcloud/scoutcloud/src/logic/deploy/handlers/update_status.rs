@@ -1,42 +1,538 @@
 use crate::{
     logic::{
-        deploy::deployment::map_deployment_status,
+        config::{join_errors, preset, variables::image, ConfigError},
+        deploy::{
+            deployment::map_deployment_status, validate_labels, Initiator, Labels, StatusActor,
+        },
+        github::Workflow,
         jobs::JobsRunner,
         users::{user_actions, UserToken},
-        DeployError, Deployment, Instance, InstanceDeployment,
+        DeployError, Deployment, GithubClient, GithubError, Instance, InstanceDeployment,
     },
     server::proto,
 };
 
 use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
-use sea_orm::DatabaseConnection;
+use sea_orm::{prelude::DateTimeWithTimeZone, DatabaseConnection, SqlErr, TransactionTrait};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, time::Duration};
+
+/// Repeated starting requests with the same key return the deployment
+/// created by the first one instead of creating a new one
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
 
 const MIN_HOURS_DEPLOY: u64 = 12;
+const MIN_WORKFLOW_TIMEOUT_SECONDS: u32 = 60;
+const MAX_WORKFLOW_TIMEOUT_SECONDS: u32 = 60 * 60;
+const MIN_TTL_SECONDS: u32 = 60 * 60;
+const MAX_TTL_SECONDS: u32 = 90 * 24 * 60 * 60;
+const IDEMPOTENCY_KEY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+const MAX_GIT_REF_LENGTH: usize = 255;
+const MAX_AUTO_RETRIES: u32 = 5;
+
+#[derive(Default)]
+struct ActionOptions {
+    workflow_timeout: Option<Duration>,
+    ttl: Option<Duration>,
+    labels: Labels,
+    git_ref: Option<String>,
+    /// Empty values mean the defaults of the workflow even if the config sets them
+    image: Option<String>,
+    tag: Option<String>,
+    /// Set only for times in the future
+    scheduled_at: Option<DateTimeWithTimeZone>,
+    max_auto_retries: u32,
+    idempotency_key: Option<IdempotencyKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IdempotencyKey {
+    key: String,
+    /// Hash of the request, so the key can't be reused for a different request
+    fingerprint: String,
+}
 
 pub async fn update_instance_status(
     db: &DatabaseConnection,
     runner: &JobsRunner,
-    instance_uuid: &str,
-    action: &proto::UpdateInstanceAction,
+    request: &proto::UpdateInstanceStatusRequestInternal,
+    idempotency_key: Option<&str>,
+    default_quota: u64,
     user_token: &UserToken,
 ) -> Result<proto::UpdateInstanceStatusResponseInternal, DeployError> {
+    let (image, tag) = parse_image(
+        &request.action,
+        request.image.as_deref(),
+        request.tag.as_deref(),
+    )?;
+    let options = ActionOptions {
+        workflow_timeout: parse_workflow_timeout(request.workflow_timeout_seconds)?,
+        ttl: parse_ttl(&request.action, request.ttl_seconds)?,
+        labels: parse_labels(&request.action, &request.labels)?,
+        git_ref: parse_git_ref(&request.action, request.git_ref.as_deref())?,
+        image,
+        tag,
+        scheduled_at: parse_scheduled_at(&request.action, request.scheduled_at.as_deref())?,
+        max_auto_retries: parse_max_auto_retries(&request.action, request.max_auto_retries)?,
+        idempotency_key: parse_idempotency_key(request, idempotency_key)?,
+    };
+    if let Some(key) = &options.idempotency_key {
+        let created_after = idempotency_window_start()?;
+        if let Some(deployment) =
+            Deployment::find_by_idempotency_key(db, user_token.user.id, &key.key, created_after)
+                .await?
+        {
+            if deployment.model.idempotency_fingerprint.as_ref() != Some(&key.fingerprint) {
+                return Err(DeployError::IdempotencyKeyConflict(key.key.clone()));
+            }
+            return Ok(proto::UpdateInstanceStatusResponseInternal {
+                status: map_deployment_status(Some(&deployment.model.status)),
+                deployment_id: deployment.model.external_id.to_string(),
+                dry_run: None,
+            });
+        }
+    }
+    let instance_uuid = &request.instance_id;
     let instance = InstanceDeployment::find_by_instance_uuid(db, instance_uuid)
         .await?
         .ok_or(DeployError::InstanceNotFound(instance_uuid.to_string()))?;
     user_token.has_access_to_instance(&instance.instance)?;
-    let result = handle_instance_action(db, runner, instance, action, user_token).await?;
+    let result = handle_instance_action(
+        db,
+        runner,
+        instance,
+        &request.action,
+        &options,
+        default_quota,
+        user_token,
+    )
+    .await?;
     Ok(result)
 }
 
+/// Performs the checks of starting the instance and resolves inputs of the deploy workflow.
+/// Problems which would reject the request are reported in the result,
+/// no deployment is created and nothing is dispatched
+pub async fn dry_run_instance_status(
+    db: &DatabaseConnection,
+    github: &GithubClient,
+    request: &proto::UpdateInstanceStatusRequestInternal,
+    default_quota: u64,
+    user_token: &UserToken,
+) -> Result<proto::UpdateInstanceStatusResponseInternal, DeployError> {
+    if !matches!(request.action, proto::UpdateInstanceAction::Start) {
+        return Err(DeployError::InvalidValue(
+            "dry_run can be set only when starting an instance".to_string(),
+        ));
+    }
+    parse_workflow_timeout(request.workflow_timeout_seconds)?;
+    parse_ttl(&request.action, request.ttl_seconds)?;
+    parse_labels(&request.action, &request.labels)?;
+    let git_ref = parse_git_ref(&request.action, request.git_ref.as_deref())?;
+    let (image, tag) = parse_image(
+        &request.action,
+        request.image.as_deref(),
+        request.tag.as_deref(),
+    )?;
+    parse_scheduled_at(&request.action, request.scheduled_at.as_deref())?;
+    parse_max_auto_retries(&request.action, request.max_auto_retries)?;
+    let instance_uuid = &request.instance_id;
+    let InstanceDeployment {
+        instance,
+        deployment,
+    } = InstanceDeployment::find_by_instance_uuid(db, instance_uuid)
+        .await?
+        .ok_or(DeployError::InstanceNotFound(instance_uuid.to_string()))?;
+    user_token.has_access_to_instance(&instance)?;
+
+    let current_status = map_deployment_status(deployment.as_ref().map(|d| &d.model.status));
+    let mut errors = vec![];
+    let mut warnings = vec![];
+    if let Err(err) = check_transition(&request.action, current_status) {
+        errors.push(err.to_string());
+    }
+    let validated = match instance.template_config(db).await {
+        Ok(Some(config)) => {
+            if config.raw()? != instance.user_config()?.raw()? {
+                warnings.push(
+                    "config of the instance will be updated from its template when it is started"
+                        .to_string(),
+                );
+            }
+            instance.validate_user_config(&config)
+        }
+        Ok(None) => instance.validate_config(),
+        Err(DeployError::Config(err)) => Err(vec![err]),
+        Err(err) => return Err(err),
+    };
+    if let Err(config_errors) = validated {
+        errors.extend(config_errors.iter().map(ToString::to_string));
+    }
+    match check_start_allowed(db, &instance, default_quota, user_token).await {
+        Ok(()) => {}
+        Err(DeployError::Auth(err)) => errors.push(err.to_string()),
+        Err(err) => return Err(err),
+    }
+    let mut workflow = instance.deploy_workflow();
+    if git_ref.is_some() {
+        workflow = workflow.with_git_ref(git_ref);
+    }
+    if let Some(image) = image {
+        workflow.image = Some(image).filter(|image| !image.is_empty());
+    }
+    if let Some(tag) = tag {
+        workflow.tag = Some(tag).filter(|tag| !tag.is_empty());
+    }
+    let parsed_config = instance.parsed_config();
+    let target = github.for_instance(
+        parsed_config.deployment_target(),
+        parsed_config.workflow_repo(),
+    );
+    let workflow_id = target
+        .as_deref()
+        .unwrap_or(github)
+        .workflow_files()
+        .deploy
+        .clone();
+    let resolved = match target {
+        Ok(github) => workflow.resolve_inputs(&github).await,
+        Err(err) => Err(err),
+    };
+    let inputs = match resolved {
+        Ok(inputs) => inputs,
+        Err(
+            err @ (GithubError::InvalidWorkflowInputs { .. }
+            | GithubError::WorkflowDrift { .. }
+            | GithubError::UnknownDeploymentTarget(_)
+            | GithubError::RepoNotAllowed(_)
+            | GithubError::GitRefNotFound(_)),
+        ) => {
+            errors.push(err.to_string());
+            workflow.inputs()
+        }
+        Err(err) => {
+            warnings.push(format!(
+                "inputs were not checked against the workflow file: {err}"
+            ));
+            workflow.inputs()
+        }
+    };
+
+    Ok(proto::UpdateInstanceStatusResponseInternal {
+        status: current_status,
+        deployment_id: String::new(),
+        dry_run: Some(proto::DryRunResultInternal {
+            workflow: workflow_id,
+            workflow_inputs: inputs
+                .iter()
+                .map(|(name, value)| proto::WorkflowInputInternal {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            errors,
+            warnings,
+        }),
+    })
+}
+
+/// Enqueues stopping of every running deployment from the request. Deployments which
+/// can't be stopped are reported in the response and don't affect the others
+pub async fn batch_stop(
+    db: &DatabaseConnection,
+    runner: &JobsRunner,
+    request: &proto::BatchStopRequestInternal,
+    user_token: &UserToken,
+) -> Result<proto::BatchStopResponseInternal, DeployError> {
+    let mut deployment_ids = request.deployment_ids.clone();
+    if let Some(email) = &request.user_email {
+        if let Some(user_id) = user_token.find_accessible_user_id(db, email).await? {
+            let running = Deployment::find_running_of_user(db, user_id).await?;
+            deployment_ids.extend(running.iter().map(|d| d.model.external_id.to_string()));
+        }
+    }
+    let mut seen = HashSet::new();
+    deployment_ids.retain(|id| seen.insert(id.clone()));
+
+    let mut results = Vec::with_capacity(deployment_ids.len());
+    let mut enqueued = vec![];
+    for deployment_id in deployment_ids {
+        let status = batch_stop_deployment(db, &deployment_id, user_token, &mut enqueued).await?;
+        results.push(proto::BatchStopResultInternal {
+            deployment_id,
+            status,
+        });
+    }
+    // deployments are stopped by a single task, so they don't wait for each other in the queue
+    if !enqueued.is_empty() {
+        runner
+            .insert_group_stopping_task(enqueued, Initiator::User(user_token.user.id))
+            .await?;
+    }
+    Ok(proto::BatchStopResponseInternal { results })
+}
+
+/// Ids of deployments which should be stopped are added to `enqueued`
+async fn batch_stop_deployment(
+    db: &DatabaseConnection,
+    deployment_id: &str,
+    user_token: &UserToken,
+    enqueued: &mut Vec<i32>,
+) -> Result<proto::BatchStopStatus, DeployError> {
+    let found = InstanceDeployment::find_by_deployment_uuid(db, deployment_id).await?;
+    let (instance, deployment) = match found {
+        Some(InstanceDeployment {
+            instance,
+            deployment: Some(deployment),
+        }) if user_token.has_access_to_instance(&instance).is_ok() => (instance, deployment),
+        // deployments of other users are indistinguishable from missing ones
+        _ => return Ok(proto::BatchStopStatus::NotFound),
+    };
+    if deployment.model.status != DeploymentStatusType::Running {
+        return Ok(proto::BatchStopStatus::SkippedWrongState);
+    }
+    user_actions::log_stop_instance(db, user_token, &instance, &deployment).await?;
+    enqueued.push(deployment.model.id);
+    Ok(proto::BatchStopStatus::Enqueued)
+}
+
+fn parse_ttl(
+    action: &proto::UpdateInstanceAction,
+    seconds: Option<u32>,
+) -> Result<Option<Duration>, DeployError> {
+    match seconds {
+        None => Ok(None),
+        Some(_) if !matches!(action, proto::UpdateInstanceAction::Start) => {
+            Err(DeployError::InvalidValue(
+                "ttl_seconds can be set only when starting an instance".to_string(),
+            ))
+        }
+        Some(seconds) if (MIN_TTL_SECONDS..=MAX_TTL_SECONDS).contains(&seconds) => {
+            Ok(Some(Duration::from_secs(seconds as u64)))
+        }
+        Some(seconds) => Err(DeployError::InvalidValue(format!(
+            "ttl_seconds should be between {MIN_TTL_SECONDS} and {MAX_TTL_SECONDS}, got {seconds}"
+        ))),
+    }
+}
+
+fn parse_labels(
+    action: &proto::UpdateInstanceAction,
+    labels: &Labels,
+) -> Result<Labels, DeployError> {
+    if labels.is_empty() {
+        return Ok(Labels::new());
+    }
+    if !matches!(action, proto::UpdateInstanceAction::Start) {
+        return Err(DeployError::InvalidValue(
+            "labels can be set only when starting an instance".to_string(),
+        ));
+    }
+    validate_labels(labels)?;
+    Ok(labels.clone())
+}
+
+/// Existence of the ref is checked by github right before the deploy workflow is dispatched
+fn parse_git_ref(
+    action: &proto::UpdateInstanceAction,
+    git_ref: Option<&str>,
+) -> Result<Option<String>, DeployError> {
+    let Some(git_ref) = git_ref else {
+        return Ok(None);
+    };
+    if !matches!(action, proto::UpdateInstanceAction::Start) {
+        return Err(DeployError::InvalidValue(
+            "git_ref can be set only when starting an instance".to_string(),
+        ));
+    }
+    if git_ref.is_empty()
+        || git_ref.len() > MAX_GIT_REF_LENGTH
+        || git_ref.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(DeployError::InvalidValue(format!(
+            "git_ref should be from 1 to {MAX_GIT_REF_LENGTH} characters long without spaces"
+        )));
+    }
+    Ok(Some(git_ref.to_string()))
+}
+
+/// Empty image or tag is allowed, it resets the value of the config to the workflow default
+fn parse_image(
+    action: &proto::UpdateInstanceAction,
+    image: Option<&str>,
+    tag: Option<&str>,
+) -> Result<(Option<String>, Option<String>), DeployError> {
+    if image.is_none() && tag.is_none() {
+        return Ok((None, None));
+    }
+    if !matches!(action, proto::UpdateInstanceAction::Start) {
+        return Err(DeployError::InvalidValue(
+            "image and tag can be set only when starting an instance".to_string(),
+        ));
+    }
+    let invalid = |err: ConfigError| DeployError::InvalidValue(join_errors(&[err]));
+    if let Some(image) = image.filter(|image| !image.is_empty()) {
+        image::registries().check(image).map_err(invalid)?;
+    }
+    if let Some(tag) = tag.filter(|tag| !tag.is_empty()) {
+        image::check_tag(tag).map_err(invalid)?;
+    }
+    Ok((image.map(str::to_string), tag.map(str::to_string)))
+}
+
+/// Returns `None` for times in the past, so such deployments are started right away
+fn parse_scheduled_at(
+    action: &proto::UpdateInstanceAction,
+    scheduled_at: Option<&str>,
+) -> Result<Option<DateTimeWithTimeZone>, DeployError> {
+    let Some(scheduled_at) = scheduled_at else {
+        return Ok(None);
+    };
+    if !matches!(action, proto::UpdateInstanceAction::Start) {
+        return Err(DeployError::InvalidValue(
+            "scheduled_at can be set only when starting an instance".to_string(),
+        ));
+    }
+    let scheduled_at = chrono::DateTime::parse_from_rfc3339(scheduled_at).map_err(|err| {
+        DeployError::InvalidValue(format!(
+            "scheduled_at should be RFC 3339 time, got '{scheduled_at}': {err}"
+        ))
+    })?;
+    Ok((scheduled_at > chrono::Utc::now()).then_some(scheduled_at))
+}
+
+fn parse_max_auto_retries(
+    action: &proto::UpdateInstanceAction,
+    max_auto_retries: Option<u32>,
+) -> Result<u32, DeployError> {
+    match max_auto_retries {
+        None => Ok(0),
+        Some(_) if !matches!(action, proto::UpdateInstanceAction::Start) => {
+            Err(DeployError::InvalidValue(
+                "max_auto_retries can be set only when starting an instance".to_string(),
+            ))
+        }
+        Some(retries) if retries <= MAX_AUTO_RETRIES => Ok(retries),
+        Some(retries) => Err(DeployError::InvalidValue(format!(
+            "max_auto_retries should be at most {MAX_AUTO_RETRIES}, got {retries}"
+        ))),
+    }
+}
+
+/// Only starting creates a new deployment, so keys of other actions are ignored
+fn parse_idempotency_key(
+    request: &proto::UpdateInstanceStatusRequestInternal,
+    key: Option<&str>,
+) -> Result<Option<IdempotencyKey>, DeployError> {
+    let key = match key {
+        Some(key) if matches!(request.action, proto::UpdateInstanceAction::Start) => key,
+        _ => return Ok(None),
+    };
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(DeployError::InvalidValue(format!(
+            "idempotency key should be from 1 to {MAX_IDEMPOTENCY_KEY_LENGTH} characters long"
+        )));
+    }
+    let mut canonical = serde_json::json!({
+        "instance_id": request.instance_id,
+        "action": serde_plain::to_string(&request.action).expect("enum should be serializable"),
+        "workflow_timeout_seconds": request.workflow_timeout_seconds,
+        "ttl_seconds": request.ttl_seconds,
+    });
+    // labels are hashed only when present, so fingerprints of unlabeled requests are stable
+    if !request.labels.is_empty() {
+        canonical["labels"] = serde_json::json!(request.labels);
+    }
+    if let Some(git_ref) = &request.git_ref {
+        canonical["git_ref"] = serde_json::json!(git_ref);
+    }
+    if let Some(image) = &request.image {
+        canonical["image"] = serde_json::json!(image);
+    }
+    if let Some(tag) = &request.tag {
+        canonical["tag"] = serde_json::json!(tag);
+    }
+    if let Some(scheduled_at) = &request.scheduled_at {
+        canonical["scheduled_at"] = serde_json::json!(scheduled_at);
+    }
+    if let Some(max_auto_retries) = request.max_auto_retries {
+        canonical["max_auto_retries"] = serde_json::json!(max_auto_retries);
+    }
+    Ok(Some(IdempotencyKey {
+        key: key.to_string(),
+        fingerprint: hex::encode(Sha256::digest(canonical.to_string())),
+    }))
+}
+
+fn idempotency_window_start() -> Result<DateTimeWithTimeZone, DeployError> {
+    let window =
+        chrono::Duration::from_std(IDEMPOTENCY_KEY_WINDOW).map_err(|e| anyhow::anyhow!(e))?;
+    Ok((chrono::Utc::now() - window).fixed_offset())
+}
+
+fn parse_workflow_timeout(seconds: Option<u32>) -> Result<Option<Duration>, DeployError> {
+    match seconds {
+        None => Ok(None),
+        Some(seconds)
+            if (MIN_WORKFLOW_TIMEOUT_SECONDS..=MAX_WORKFLOW_TIMEOUT_SECONDS).contains(&seconds) =>
+        {
+            Ok(Some(Duration::from_secs(seconds as u64)))
+        }
+        Some(seconds) => Err(DeployError::InvalidValue(format!(
+            "workflow_timeout_seconds should be between {MIN_WORKFLOW_TIMEOUT_SECONDS} and {MAX_WORKFLOW_TIMEOUT_SECONDS}, got {seconds}"
+        ))),
+    }
+}
+
 async fn handle_instance_action(
     db: &DatabaseConnection,
     runner: &JobsRunner,
     instance: InstanceDeployment,
     action: &proto::UpdateInstanceAction,
+    options: &ActionOptions,
+    default_quota: u64,
     user_token: &UserToken,
 ) -> Result<proto::UpdateInstanceStatusResponseInternal, DeployError> {
+    let workflow_timeout = options.workflow_timeout;
     let current_status =
         map_deployment_status(instance.deployment.as_ref().map(|d| &d.model.status));
+    check_transition(action, current_status)?;
+
+    let deployment = match action {
+        proto::UpdateInstanceAction::Start => {
+            start_instance(
+                db,
+                runner,
+                &instance.instance,
+                options,
+                default_quota,
+                user_token,
+            )
+            .await?
+        }
+        proto::UpdateInstanceAction::Finish => {
+            stop_instance(db, runner, &instance.instance, workflow_timeout, user_token).await?
+        }
+        proto::UpdateInstanceAction::Restart => {
+            restart_instance(db, runner, &instance.instance, workflow_timeout, user_token).await?
+        }
+        proto::UpdateInstanceAction::Cancel => {
+            cancel_instance(db, runner, &instance.instance, user_token).await?
+        }
+    };
+
+    Ok(proto::UpdateInstanceStatusResponseInternal {
+        status: map_deployment_status(Some(&deployment.model.status)),
+        deployment_id: deployment.model.external_id.to_string(),
+        dry_run: None,
+    })
+}
+
+fn check_transition(
+    action: &proto::UpdateInstanceAction,
+    current_status: proto::DeploymentStatus,
+) -> Result<(), DeployError> {
     let allowed_statuses = match &action {
         proto::UpdateInstanceAction::Start => vec![
             proto::DeploymentStatus::NoStatus,
@@ -46,53 +542,318 @@ async fn handle_instance_action(
         proto::UpdateInstanceAction::Finish | proto::UpdateInstanceAction::Restart => {
             vec![proto::DeploymentStatus::Running]
         }
+        proto::UpdateInstanceAction::Cancel => vec![
+            proto::DeploymentStatus::Scheduled,
+            proto::DeploymentStatus::Created,
+            proto::DeploymentStatus::Pending,
+        ],
     };
-
     if !allowed_statuses.contains(&current_status) {
         return Err(DeployError::InvalidStateTransition(
             serde_plain::to_string(action).expect("enum should be serializable"),
             serde_plain::to_string(&current_status).expect("enum should be serializable"),
         ));
     }
+    Ok(())
+}
 
-    let deployment = match action {
-        proto::UpdateInstanceAction::Start => {
-            start_instance(db, runner, &instance.instance, user_token).await?
-        }
-        proto::UpdateInstanceAction::Finish => {
-            stop_instance(db, runner, &instance.instance, user_token).await?
+/// Checks quota and balance of the user before a new deployment of the instance is created
+async fn check_start_allowed(
+    db: &DatabaseConnection,
+    instance: &Instance,
+    default_quota: u64,
+    user_token: &UserToken,
+) -> Result<(), DeployError> {
+    user_token
+        .allowed_to_start_deployment(db, default_quota)
+        .await?;
+    let spec = instance.find_server_spec(db).await?.ok_or(anyhow::anyhow!(
+        "server spec of the instance was not found in database"
+    ))?;
+    user_token
+        .allowed_to_deploy_for_hours(MIN_HOURS_DEPLOY, &spec)
+        .await?;
+    Ok(())
+}
+
+async fn start_instance(
+    db: &DatabaseConnection,
+    runner: &JobsRunner,
+    instance: &Instance,
+    options: &ActionOptions,
+    default_quota: u64,
+    user_token: &UserToken,
+) -> Result<Deployment, DeployError> {
+    // instances created from a template are deployed with its current config
+    let template_config = instance.template_config(db).await?;
+    match &template_config {
+        Some(config) => instance.validate_user_config(config),
+        None => instance.validate_config(),
+    }
+    .map_err(DeployError::InvalidConfig)?;
+    check_start_allowed(db, instance, default_quota, user_token).await?;
+    runner.check_queue_depth(db).await?;
+    runner.check_actions_budget(instance).await?;
+    // deployment is stored together with its idempotency key,
+    // so concurrent requests with the same key can't create two deployments
+    let tx = db.begin().await?;
+    let mut instance = instance.clone();
+    // config before the template is kept to revert it if it can't be committed
+    let mut config_before_template = None;
+    if let Some(config) = template_config {
+        let old_config = instance.user_config_raw().clone();
+        let old_user_config = instance.user_config()?;
+        if instance.apply_template_config(&tx, config).await? {
+            user_actions::log_update_config(
+                &tx,
+                user_token,
+                &instance,
+                &old_config,
+                instance.user_config_raw(),
+                false,
+            )
+            .await?;
+            config_before_template = Some(old_user_config);
         }
-        proto::UpdateInstanceAction::Restart => Err(anyhow::anyhow!(
-            "restart not implemented yet, use start and finish instead"
-        ))?,
+    }
+    let instance = &instance;
+    let status = match options.scheduled_at {
+        Some(_) => DeploymentStatusType::Scheduled,
+        None => DeploymentStatusType::Created,
     };
+    let mut deployment = Deployment::try_create(
+        &tx,
+        instance,
+        Some(status),
+        StatusActor::User(user_token.user.id),
+    )
+    .await?;
+    deployment
+        .set_workflow_timeout(&tx, options.workflow_timeout)
+        .await?;
+    if let Some(ttl) = options.ttl {
+        // ttl of the scheduled deployment starts at the scheduled time
+        let ttl = chrono::Duration::from_std(ttl).map_err(|e| anyhow::anyhow!(e))?;
+        let started_at = options
+            .scheduled_at
+            .unwrap_or_else(|| chrono::Utc::now().fixed_offset());
+        deployment
+            .set_expires_at(&tx, Some(started_at + ttl))
+            .await?;
+    }
+    if options.scheduled_at.is_some() {
+        deployment
+            .set_scheduled_at(&tx, options.scheduled_at)
+            .await?;
+    }
+    if !options.labels.is_empty() {
+        deployment.set_labels(&tx, &options.labels).await?;
+    }
+    if let Some(git_ref) = &options.git_ref {
+        deployment.set_git_ref(&tx, Some(git_ref)).await?;
+    }
+    if options.image.is_some() || options.tag.is_some() {
+        deployment
+            .set_image(&tx, options.image.as_deref(), options.tag.as_deref())
+            .await?;
+    }
+    if options.max_auto_retries > 0 {
+        deployment
+            .set_max_auto_retries(&tx, options.max_auto_retries)
+            .await?;
+    }
+    if let Some(key) = &options.idempotency_key {
+        deployment
+            .set_idempotency_key(&tx, &key.key, &key.fingerprint, idempotency_window_start()?)
+            .await
+            .map_err(|err| match err {
+                DeployError::Db(ref db_err)
+                    if matches!(db_err.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) =>
+                {
+                    DeployError::IdempotencyKeyConflict(key.key.clone())
+                }
+                err => err,
+            })?;
+    }
+    user_actions::log_start_instance(&tx, user_token, instance, &deployment).await?;
+    tx.commit().await?;
+    // github is written only once the start is stored, and the stored config
+    // is reverted if github rejects it, so the repo and database don't diverge
+    if let Some(old_config) = config_before_template {
+        if let Err(err) = runner
+            .commit_config(instance, "config update from template")
+            .await
+        {
+            let mut instance = instance.clone();
+            instance.update_config(db, old_config).await?;
+            deployment.mark_as_error(db, &err).await?;
+            return Err(err);
+        }
+    }
+    runner
+        .insert_starting_task(&deployment, Initiator::User(user_token.user.id))
+        .await?;
+    Ok(deployment)
+}
 
-    Ok(proto::UpdateInstanceStatusResponseInternal {
-        status: map_deployment_status(Some(&deployment.model.status)),
-        deployment_id: deployment.model.external_id.to_string(),
+/// Creates an instance with the config of the preset and starts it right away.
+/// Start of the instance is checked before it is created, so a rejected start
+/// doesn't leave behind an instance which the user never got an id of
+pub async fn deploy_preset(
+    db: &DatabaseConnection,
+    github: &GithubClient,
+    runner: &JobsRunner,
+    request: &proto::DeployPresetRequestInternal,
+    default_quota: u64,
+    user_token: &UserToken,
+) -> Result<proto::InstanceInternal, DeployError> {
+    let presets = preset::presets();
+    let config = presets
+        .compose(&request.preset_name, request.overrides.as_ref())
+        .ok_or_else(|| DeployError::PresetNotFound {
+            name: request.preset_name.clone(),
+            available: presets.names().map(str::to_string).collect(),
+        })??;
+    let name = request.name.as_deref().unwrap_or(&request.preset_name);
+    let tx = db.begin().await?;
+    user_token.allowed_to_create_instance(&tx).await?;
+    let instance = Instance::try_create(&tx, name, &config.internal, user_token).await?;
+    // overrides may break the preset, such instances are not kept
+    instance
+        .validate_config()
+        .map_err(DeployError::InvalidConfig)?;
+    check_start_allowed(db, &instance, default_quota, user_token).await?;
+    runner.check_queue_depth(db).await?;
+    runner.check_actions_budget(&instance).await?;
+    let config = instance.user_config_raw().clone();
+    user_actions::log_create_instance(&tx, user_token, &instance, &config).await?;
+    instance
+        .commit(github, "instance creation from preset")
+        .await?;
+    tx.commit().await?;
+
+    let deployment = start_instance(
+        db,
+        runner,
+        &instance,
+        &ActionOptions::default(),
+        default_quota,
+        user_token,
+    )
+    .await?;
+    proto::InstanceInternal::try_from(InstanceDeployment {
+        instance,
+        deployment: Some(deployment),
     })
 }
 
-async fn start_instance(
+/// Starts failed deployment again in place, so it keeps its id and status history.
+/// Workflows deploy the config committed for the instance, so the deployment can be
+/// retried only while it is the latest one and the config is unchanged since its creation
+pub async fn retry_deployment(
+    db: &DatabaseConnection,
+    runner: &JobsRunner,
+    deployment_uuid: &str,
+    default_quota: u64,
+    user_token: &UserToken,
+) -> Result<proto::DeploymentInternal, DeployError> {
+    let InstanceDeployment {
+        instance,
+        deployment,
+    } = InstanceDeployment::find_by_deployment_uuid(db, deployment_uuid)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    user_token.has_access_to_instance(&instance)?;
+    let mut deployment = deployment
+        .ok_or(DeployError::DeploymentNotFound)?
+        .with_actor(StatusActor::User(user_token.user.id));
+    deployment.check_can_retry()?;
+    let latest = Deployment::latest_of_instance(db, &instance).await?;
+    if latest.map(|latest| latest.model.id) != Some(deployment.model.id) {
+        return Err(DeployError::InvalidValue(
+            "only the latest deployment of the instance can be retried".to_string(),
+        ));
+    }
+    if deployment.model.parsed_config != instance.model.parsed_config {
+        return Err(DeployError::InvalidValue(
+            "config of the instance was changed after the deployment was created, \
+             start the instance to deploy the new config"
+                .to_string(),
+        ));
+    }
+    check_start_allowed(db, &instance, default_quota, user_token).await?;
+    runner.check_queue_depth(db).await?;
+    runner.check_actions_budget(&instance).await?;
+
+    let tx = db.begin().await?;
+    deployment.reset_for_retry(&tx).await?;
+    user_actions::log_retry_deployment(&tx, user_token, &instance, &deployment).await?;
+    tx.commit().await?;
+    runner
+        .insert_starting_task(&deployment, Initiator::User(user_token.user.id))
+        .await?;
+    proto::DeploymentInternal::try_from(InstanceDeployment {
+        instance,
+        deployment: Some(deployment),
+    })
+}
+
+async fn stop_instance(
     db: &DatabaseConnection,
     runner: &JobsRunner,
     instance: &Instance,
+    workflow_timeout: Option<Duration>,
     user_token: &UserToken,
 ) -> Result<Deployment, DeployError> {
-    let spec = instance.find_server_spec(db).await?.ok_or(anyhow::anyhow!(
-        "server spec of the instance was not found in database"
-    ))?;
-    user_token
-        .allowed_to_deploy_for_hours(MIN_HOURS_DEPLOY, &spec)
+    let mut deployment = Deployment::latest_of_instance(db, instance)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    // timeout of the previous request is kept if the new one doesn't set it
+    if workflow_timeout.is_some() {
+        deployment
+            .set_workflow_timeout(db, workflow_timeout)
+            .await?;
+    }
+    user_actions::log_stop_instance(db, user_token, instance, &deployment).await?;
+    runner
+        .insert_stopping_task(&deployment, Initiator::User(user_token.user.id))
         .await?;
-    let deployment =
-        Deployment::try_create(db, instance, Some(DeploymentStatusType::Created)).await?;
-    user_actions::log_start_instance(db, user_token, instance, &deployment).await?;
-    runner.insert_starting_task(deployment.model.id).await?;
     Ok(deployment)
 }
 
-async fn stop_instance(
+async fn restart_instance(
+    db: &DatabaseConnection,
+    runner: &JobsRunner,
+    instance: &Instance,
+    workflow_timeout: Option<Duration>,
+    user_token: &UserToken,
+) -> Result<Deployment, DeployError> {
+    // validate before the deployment is stopped, so it is not left stopped
+    instance
+        .validate_config()
+        .map_err(DeployError::InvalidConfig)?;
+    let mut deployment = Deployment::latest_of_instance(db, instance)
+        .await?
+        .ok_or(DeployError::DeploymentNotFound)?;
+    // the latest deployment is read again, so it could be changed since the caller checked it
+    check_transition(
+        &proto::UpdateInstanceAction::Restart,
+        map_deployment_status(Some(&deployment.model.status)),
+    )?;
+    // timeout of the previous request is kept if the new one doesn't set it
+    if workflow_timeout.is_some() {
+        deployment
+            .set_workflow_timeout(db, workflow_timeout)
+            .await?;
+    }
+    user_actions::log_restart_instance(db, user_token, instance, &deployment).await?;
+    runner
+        .insert_restart_task(deployment.model.id, Initiator::User(user_token.user.id))
+        .await?;
+    Ok(deployment)
+}
+
+async fn cancel_instance(
     db: &DatabaseConnection,
     runner: &JobsRunner,
     instance: &Instance,
@@ -101,7 +862,1019 @@ async fn stop_instance(
     let deployment = Deployment::latest_of_instance(db, instance)
         .await?
         .ok_or(DeployError::DeploymentNotFound)?;
-    user_actions::log_stop_instance(db, user_token, instance, &deployment).await?;
-    runner.insert_stopping_task(deployment.model.id).await?;
+    user_actions::log_cancel_instance(db, user_token, instance, &deployment).await?;
+    runner.insert_cancel_task(deployment.model.id).await?;
     Ok(deployment)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::{
+            deploy::{get_deployment, update_deployment_labels},
+            github::{ActionsBudget, MockedGithubRepo},
+            jobs::{global, DispatchLimit},
+            users::AuthError,
+            GithubClient,
+        },
+        tests_utils,
+    };
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity::{self as db, sea_orm_active_enums::FangTaskState};
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter};
+    use std::sync::Arc;
+
+    #[test]
+    fn parse_workflow_timeout_works() {
+        assert_eq!(parse_workflow_timeout(None).unwrap(), None);
+        assert_eq!(
+            parse_workflow_timeout(Some(60)).unwrap(),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            parse_workflow_timeout(Some(3600)).unwrap(),
+            Some(Duration::from_secs(3600))
+        );
+        for invalid in [0, 59, 3601] {
+            let err = parse_workflow_timeout(Some(invalid)).unwrap_err();
+            assert!(
+                matches!(err, DeployError::InvalidValue(_)),
+                "unexpected error for {invalid}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_ttl_works() {
+        let start = proto::UpdateInstanceAction::Start;
+        assert_eq!(parse_ttl(&start, None).unwrap(), None);
+        assert_eq!(
+            parse_ttl(&start, Some(24 * 60 * 60)).unwrap(),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        for invalid in [0, MIN_TTL_SECONDS - 1, MAX_TTL_SECONDS + 1] {
+            let err = parse_ttl(&start, Some(invalid)).unwrap_err();
+            assert!(
+                matches!(err, DeployError::InvalidValue(_)),
+                "unexpected error for {invalid}: {err:?}"
+            );
+        }
+        let err =
+            parse_ttl(&proto::UpdateInstanceAction::Finish, Some(MIN_TTL_SECONDS)).unwrap_err();
+        assert!(
+            matches!(err, DeployError::InvalidValue(_)),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    /// Makes instance#2 with failed deployment#3 startable by its owner
+    async fn startable_instance(conn: &DatabaseConnection) -> (UserToken, String) {
+        db::users::ActiveModel {
+            id: Set(2),
+            is_superuser: Set(true),
+            ..Default::default()
+        }
+        .update(conn)
+        .await
+        .unwrap();
+        let instance = db::instances::ActiveModel {
+            id: Set(2),
+            user_config: Set(serde_json::json!({
+                "rpc_url": "https://sepolia.drpc.org/",
+                "server_size": "medium",
+                "node_type": "geth",
+                "chain_type": "ethereum",
+                "chain_id": "11155111",
+            })),
+            ..Default::default()
+        }
+        .update(conn)
+        .await
+        .unwrap();
+        let user_token = UserToken::get(conn, 2).await.unwrap();
+        (user_token, instance.external_id.to_string())
+    }
+
+    fn start_request(
+        instance_id: &str,
+        ttl_seconds: Option<u32>,
+    ) -> proto::UpdateInstanceStatusRequestInternal {
+        proto::UpdateInstanceStatusRequestInternal {
+            instance_id: instance_id.to_string(),
+            action: proto::UpdateInstanceAction::Start,
+            workflow_timeout_seconds: None,
+            ttl_seconds,
+            dry_run: false,
+            labels: Default::default(),
+            git_ref: None,
+            image: None,
+            tag: None,
+            scheduled_at: None,
+            max_auto_retries: None,
+        }
+    }
+
+    async fn count_deployments(conn: &DatabaseConnection) -> usize {
+        db::deployments::Entity::find()
+            .all(conn)
+            .await
+            .unwrap()
+            .len()
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn repeated_start_with_idempotency_key_returns_same_deployment() {
+        let (db, _github, _repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "repeated_start_with_idempotency_key_returns_same_deployment",
+        )
+        .await;
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        let request = start_request(&instance_id, None);
+
+        let first = update_instance_status(
+            conn.as_ref(),
+            &runner,
+            &request,
+            Some("key-1"),
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        let deployments = count_deployments(conn.as_ref()).await;
+        let replay = update_instance_status(
+            conn.as_ref(),
+            &runner,
+            &request,
+            Some("key-1"),
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert_eq!(replay.deployment_id, first.deployment_id);
+        assert_eq!(count_deployments(conn.as_ref()).await, deployments);
+
+        // without the key the request is handled as usual and fails,
+        // since the instance is already started
+        let err = update_instance_status(conn.as_ref(), &runner, &request, None, 5, &user_token)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::InvalidStateTransition(_, _)),
+            "unexpected error: {err:?}"
+        );
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn labels_are_set_when_starting_and_edited_after() {
+        let (db, github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("labels_are_set_when_starting").await;
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        let labels = Labels::from([
+            ("team".to_string(), "indexing".to_string()),
+            ("env".to_string(), "staging".to_string()),
+        ]);
+        let request = proto::UpdateInstanceStatusRequestInternal {
+            labels: labels.clone(),
+            ..start_request(&instance_id, None)
+        };
+
+        let response =
+            update_instance_status(conn.as_ref(), &runner, &request, None, 5, &user_token)
+                .await
+                .unwrap();
+        let deployment = get_deployment(
+            conn.as_ref(),
+            &runner,
+            github.as_ref(),
+            &response.deployment_id,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert_eq!(deployment.labels, labels);
+
+        let edited = Labels::from([("team".to_string(), "explorer".to_string())]);
+        let deployment =
+            update_deployment_labels(conn.as_ref(), &response.deployment_id, &edited, &user_token)
+                .await
+                .unwrap();
+        assert_eq!(deployment.labels, edited);
+
+        let stop = proto::UpdateInstanceStatusRequestInternal {
+            action: proto::UpdateInstanceAction::Finish,
+            ..request
+        };
+        let err = update_instance_status(conn.as_ref(), &runner, &stop, None, 5, &user_token)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::InvalidValue(_)),
+            "unexpected error: {err:?}"
+        );
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn start_is_rejected_while_queue_is_full() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("start_is_rejected_while_queue_is_full").await;
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        global::DISPATCH_LIMIT
+            .init(Arc::new(
+                DispatchLimit::default().with_max_pending_tasks(Some(2)),
+            ))
+            .await
+            .unwrap();
+        // tasks of other instances are stuck in the queue until github is back
+        for deployment_id in [1, 4] {
+            tests_utils::db::insert_fang_task(
+                conn.as_ref(),
+                "StoppingTask",
+                deployment_id,
+                FangTaskState::New,
+                chrono::Duration::hours(1),
+            )
+            .await;
+        }
+        let request = start_request(&instance_id, None);
+
+        let err = update_instance_status(conn.as_ref(), &runner, &request, None, 5, &user_token)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::Overloaded),
+            "unexpected error: {err:?}"
+        );
+
+        db::fang_tasks::Entity::delete_many()
+            .exec(conn.as_ref())
+            .await
+            .unwrap();
+        update_instance_status(conn.as_ref(), &runner, &request, None, 5, &user_token)
+            .await
+            .unwrap();
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+    }
+
+    /// Replaces github client of the runner with one which requires 100 remaining minutes
+    async fn init_actions_budget(repo: &MockedGithubRepo) {
+        let github = GithubClient::try_from(repo)
+            .unwrap()
+            .with_actions_budget(ActionsBudget::new(100, false, Duration::from_secs(60)));
+        global::GITHUB.init(Arc::new(github)).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn start_proceeds_with_sufficient_actions_budget() {
+        let (db, _github, repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "start_proceeds_with_sufficient_actions_budget",
+        )
+        .await;
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        init_actions_budget(&repo).await;
+        let billing = repo.mock_actions_billing(2000, 1500);
+        let deployments = count_deployments(conn.as_ref()).await;
+
+        update_instance_status(
+            conn.as_ref(),
+            &runner,
+            &start_request(&instance_id, None),
+            None,
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert_eq!(count_deployments(conn.as_ref()).await, deployments + 1);
+        assert_eq!(billing.hits(), 1);
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn start_is_rejected_with_depleted_actions_budget() {
+        let (db, _github, repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "start_is_rejected_with_depleted_actions_budget",
+        )
+        .await;
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        init_actions_budget(&repo).await;
+        repo.mock_actions_billing(2000, 1950);
+        let deployments = count_deployments(conn.as_ref()).await;
+
+        let err = update_instance_status(
+            conn.as_ref(),
+            &runner,
+            &start_request(&instance_id, None),
+            None,
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                DeployError::ActionsBudgetExhausted {
+                    remaining_minutes: 50,
+                    min_remaining_minutes: 100,
+                }
+            ),
+            "unexpected error: {err:?}"
+        );
+        assert_eq!(err.code(), "actions_budget_exhausted");
+        assert_eq!(count_deployments(conn.as_ref()).await, deployments);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn failed_deployment_is_retried_in_place() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("failed_deployment_is_retried_in_place").await;
+        let conn = db.client();
+        let (user_token, _) = startable_instance(conn.as_ref()).await;
+        let instance = db::instances::Entity::find_by_id(2)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        let failed = db::deployments::ActiveModel {
+            id: Set(3),
+            user_config: Set(instance.user_config),
+            parsed_config: Set(instance.parsed_config),
+            run_id: Set(Some(1)),
+            error: Set(Some("workflow failed".to_string())),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let deployments = count_deployments(conn.as_ref()).await;
+
+        let deployment = retry_deployment(
+            conn.as_ref(),
+            &runner,
+            &failed.external_id.to_string(),
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        assert_eq!(deployment.deployment_id, failed.external_id.to_string());
+        assert_eq!(deployment.status, proto::DeploymentStatus::Created);
+        assert_eq!(deployment.error, None);
+        assert_eq!(count_deployments(conn.as_ref()).await, deployments);
+        let retried = db::deployments::Entity::find_by_id(3)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(retried.run_id, None);
+        let history = db::deployment_status_history::Entity::find()
+            .filter(db::deployment_status_history::Column::DeploymentId.eq(3))
+            .all(conn.as_ref())
+            .await
+            .unwrap();
+        assert!(history.iter().any(|change| {
+            change.old_status == Some(DeploymentStatusType::Failed)
+                && change.new_status == DeploymentStatusType::Created
+        }));
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn running_deployment_is_not_retried() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("running_deployment_is_not_retried").await;
+        let conn = db.client();
+        let (user_token, _) = startable_instance(conn.as_ref()).await;
+        let running = db::deployments::Entity::find_by_id(1)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let err = retry_deployment(
+            conn.as_ref(),
+            &runner,
+            &running.external_id.to_string(),
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(err, DeployError::InvalidStateTransition(ref action, _) if action == "retry"),
+            "unexpected error: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn idempotency_key_of_different_request_is_rejected() {
+        let (db, _github, _repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "idempotency_key_of_different_request_is_rejected",
+        )
+        .await;
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        update_instance_status(
+            conn.as_ref(),
+            &runner,
+            &start_request(&instance_id, None),
+            Some("key-1"),
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap();
+        let deployments = count_deployments(conn.as_ref()).await;
+
+        let instance_3 = db::instances::Entity::find_by_id(3)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        for request in [
+            start_request(&instance_id, Some(MIN_TTL_SECONDS)),
+            start_request(&instance_3.external_id.to_string(), None),
+        ] {
+            let err = update_instance_status(
+                conn.as_ref(),
+                &runner,
+                &request,
+                Some("key-1"),
+                5,
+                &user_token,
+            )
+            .await
+            .unwrap_err();
+            assert!(
+                matches!(err, DeployError::IdempotencyKeyConflict(ref key) if key == "key-1"),
+                "unexpected error: {err:?}"
+            );
+        }
+        assert_eq!(count_deployments(conn.as_ref()).await, deployments);
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+    }
+
+    fn scheduled_start_request(
+        instance_id: &str,
+        scheduled_in: chrono::Duration,
+    ) -> proto::UpdateInstanceStatusRequestInternal {
+        proto::UpdateInstanceStatusRequestInternal {
+            scheduled_at: Some((chrono::Utc::now() + scheduled_in).to_rfc3339()),
+            ..start_request(instance_id, None)
+        }
+    }
+
+    async fn find_deployment(conn: &DatabaseConnection, deployment_uuid: &str) -> Deployment {
+        InstanceDeployment::find_by_deployment_uuid(conn, deployment_uuid)
+            .await
+            .unwrap()
+            .unwrap()
+            .deployment
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn scheduled_deployment_is_started_at_scheduled_time() {
+        let (db, _github, repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "scheduled_deployment_is_started_at_scheduled_time",
+        )
+        .await;
+        let handles = repo.build_handles();
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        let request = scheduled_start_request(&instance_id, chrono::Duration::seconds(3));
+
+        let response =
+            update_instance_status(conn.as_ref(), &runner, &request, None, 5, &user_token)
+                .await
+                .unwrap();
+        assert_eq!(response.status, proto::DeploymentStatus::Scheduled);
+        let deployment = find_deployment(conn.as_ref(), &response.deployment_id).await;
+        assert!(deployment.model.scheduled_at.is_some());
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let deployment = find_deployment(conn.as_ref(), &response.deployment_id).await;
+        assert_eq!(deployment.model.status, DeploymentStatusType::Scheduled);
+        handles.assert_hits("dispatch_deploy_yaml", 0);
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+        let history = db::deployment_status_history::Entity::find()
+            .filter(db::deployment_status_history::Column::DeploymentId.eq(deployment.model.id))
+            .all(conn.as_ref())
+            .await
+            .unwrap();
+        assert!(history.iter().any(|change| {
+            change.old_status == Some(DeploymentStatusType::Scheduled)
+                && change.new_status == DeploymentStatusType::Created
+        }));
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn scheduled_deployment_is_cancelled_before_it_starts() {
+        let (db, _github, repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "scheduled_deployment_is_cancelled_before_it_starts",
+        )
+        .await;
+        let handles = repo.build_handles();
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        let request = scheduled_start_request(&instance_id, chrono::Duration::seconds(3));
+        let response =
+            update_instance_status(conn.as_ref(), &runner, &request, None, 5, &user_token)
+                .await
+                .unwrap();
+
+        let cancel = proto::UpdateInstanceStatusRequestInternal {
+            action: proto::UpdateInstanceAction::Cancel,
+            ..start_request(&instance_id, None)
+        };
+        update_instance_status(conn.as_ref(), &runner, &cancel, None, 5, &user_token)
+            .await
+            .unwrap();
+
+        // starting task still fires at the scheduled time, but skips the cancelled deployment
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = find_deployment(conn.as_ref(), &response.deployment_id).await;
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert_eq!(deployment.model.error_code.as_deref(), Some("cancelled"));
+        handles.assert_hits("dispatch_deploy_yaml", 0);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn deployment_scheduled_in_the_past_is_started_right_away() {
+        let (db, _github, repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "deployment_scheduled_in_the_past_is_started_right_away",
+        )
+        .await;
+        let handles = repo.build_handles();
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        let request = scheduled_start_request(&instance_id, chrono::Duration::hours(-1));
+
+        let response =
+            update_instance_status(conn.as_ref(), &runner, &request, None, 5, &user_token)
+                .await
+                .unwrap();
+        assert_eq!(response.status, proto::DeploymentStatus::Created);
+        let deployment = find_deployment(conn.as_ref(), &response.deployment_id).await;
+        assert_eq!(deployment.model.scheduled_at, None);
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+
+    #[test]
+    fn scheduled_at_is_parsed() {
+        let start = proto::UpdateInstanceAction::Start;
+        assert_eq!(parse_scheduled_at(&start, None).unwrap(), None);
+        assert_eq!(
+            parse_scheduled_at(&start, Some("2020-01-01T00:00:00Z")).unwrap(),
+            None
+        );
+        let future = (chrono::Utc::now() + chrono::Duration::days(1)).fixed_offset();
+        assert_eq!(
+            parse_scheduled_at(&start, Some(&future.to_rfc3339())).unwrap(),
+            Some(future)
+        );
+        for (action, scheduled_at) in [
+            (start, "tomorrow"),
+            (proto::UpdateInstanceAction::Finish, "2020-01-01T00:00:00Z"),
+        ] {
+            assert!(matches!(
+                parse_scheduled_at(&action, Some(scheduled_at)),
+                Err(DeployError::InvalidValue(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn max_auto_retries_is_parsed() {
+        let start = proto::UpdateInstanceAction::Start;
+        assert_eq!(parse_max_auto_retries(&start, None).unwrap(), 0);
+        assert_eq!(parse_max_auto_retries(&start, Some(2)).unwrap(), 2);
+        for (action, retries) in [
+            (start, MAX_AUTO_RETRIES + 1),
+            (proto::UpdateInstanceAction::Finish, 1),
+        ] {
+            assert!(matches!(
+                parse_max_auto_retries(&action, Some(retries)),
+                Err(DeployError::InvalidValue(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn dry_run_resolves_inputs_without_deploying() {
+        let (db, github, repo, _runner) =
+            tests_utils::init::jobs_runner_test_case("dry_run_resolves_inputs_without_deploying")
+                .await;
+        let handles = repo.build_handles();
+        let conn = db.client();
+        let (user_token, instance_id) = startable_instance(conn.as_ref()).await;
+        let deployments = count_deployments(conn.as_ref()).await;
+        let request = proto::UpdateInstanceStatusRequestInternal {
+            dry_run: true,
+            ..start_request(&instance_id, None)
+        };
+
+        let response = dry_run_instance_status(conn.as_ref(), &github, &request, 5, &user_token)
+            .await
+            .unwrap();
+        assert_eq!(response.status, proto::DeploymentStatus::Failed);
+        assert_eq!(response.deployment_id, "");
+        let result = response.dry_run.expect("dry run result should be set");
+        assert_eq!(result.workflow, "deploy.yaml");
+        assert_eq!(
+            result.workflow_inputs,
+            vec![proto::WorkflowInputInternal {
+                name: "client".to_string(),
+                value: "instance-2".to_string(),
+            }]
+        );
+        assert_eq!(result.errors, Vec::<String>::new());
+        assert_eq!(result.warnings, Vec::<String>::new());
+
+        db::instances::ActiveModel {
+            id: Set(2),
+            user_config: Set(serde_json::json!({
+                "rpc_url": "ws://sepolia.drpc.org/",
+                "server_size": "medium",
+                "node_type": "geth",
+                "chain_type": "ethereum",
+            })),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let result = dry_run_instance_status(conn.as_ref(), &github, &request, 5, &user_token)
+            .await
+            .unwrap()
+            .dry_run
+            .unwrap();
+        assert_eq!(result.errors.len(), 2, "{:?}", result.errors);
+        assert!(result.errors[0].contains("missing required field `chain_id`"));
+        assert!(result.errors[1].contains("rpc_url"));
+
+        assert_eq!(count_deployments(conn.as_ref()).await, deployments);
+        handles.assert_hits("dispatch_deploy_yaml", 0);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn preset_is_deployed_with_overrides() {
+        let (db, github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("preset_is_deployed_with_overrides").await;
+        let conn = db.client();
+        let (user_token, _) = startable_instance(conn.as_ref()).await;
+        let instances = db::instances::Entity::find()
+            .all(conn.as_ref())
+            .await
+            .unwrap()
+            .len();
+        let request = proto::DeployPresetRequestInternal {
+            preset_name: "ethereum-mainnet".to_string(),
+            name: None,
+            overrides: Some(
+                serde_json::from_value(serde_json::json!({
+                    "rpc_url": "https://rpc.example.com",
+                }))
+                .unwrap(),
+            ),
+        };
+
+        let instance = deploy_preset(conn.as_ref(), &github, &runner, &request, 5, &user_token)
+            .await
+            .unwrap();
+        assert_eq!(instance.name, "ethereum-mainnet");
+        assert_eq!(instance.deployment_status, proto::DeploymentStatus::Created);
+        let config = instance.config.expect("config should be set");
+        assert_eq!(config.rpc_url, "https://rpc.example.com".parse().unwrap());
+        assert_eq!(config.server_size, "medium");
+        assert_eq!(config.chain_id.as_deref(), Some("1"));
+        assert_eq!(config.token_symbol.as_deref(), Some("ETH"));
+
+        let err = deploy_preset(
+            conn.as_ref(),
+            &github,
+            &runner,
+            &proto::DeployPresetRequestInternal {
+                preset_name: "unknown".to_string(),
+                ..request
+            },
+            5,
+            &user_token,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                DeployError::PresetNotFound { name, available }
+                    if name == "unknown" && available == &vec!["ethereum-mainnet".to_string()]
+            ),
+            "unexpected error: {err:?}"
+        );
+        let created = db::instances::Entity::find()
+            .all(conn.as_ref())
+            .await
+            .unwrap()
+            .len();
+        assert_eq!(created, instances + 1);
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn parse_idempotency_key_works() {
+        let request = start_request("instance", None);
+        assert_eq!(parse_idempotency_key(&request, None).unwrap(), None);
+        let key = parse_idempotency_key(&request, Some("key"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.key, "key");
+        // the same request has the same fingerprint
+        assert_eq!(
+            parse_idempotency_key(&request, Some("other"))
+                .unwrap()
+                .unwrap()
+                .fingerprint,
+            key.fingerprint
+        );
+        assert_ne!(
+            parse_idempotency_key(&start_request("instance", Some(3600)), Some("key"))
+                .unwrap()
+                .unwrap()
+                .fingerprint,
+            key.fingerprint
+        );
+
+        let finish = proto::UpdateInstanceStatusRequestInternal {
+            action: proto::UpdateInstanceAction::Finish,
+            ..start_request("instance", None)
+        };
+        assert_eq!(parse_idempotency_key(&finish, Some("key")).unwrap(), None);
+        for invalid in ["".to_string(), "k".repeat(MAX_IDEMPOTENCY_KEY_LENGTH + 1)] {
+            let err = parse_idempotency_key(&request, Some(&invalid)).unwrap_err();
+            assert!(
+                matches!(err, DeployError::InvalidValue(_)),
+                "unexpected error: {err:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn batch_stop_works() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("batch_stop_works").await;
+        let conn = db.client();
+        let user_token = UserToken::get(conn.as_ref(), 2).await.unwrap();
+        let mut external_ids = vec![];
+        for id in 1..=4 {
+            if id > 2 {
+                db::deployments::ActiveModel {
+                    id: Set(id),
+                    status: Set(DeploymentStatusType::Running),
+                    ..Default::default()
+                }
+                .update(conn.as_ref())
+                .await
+                .unwrap();
+            }
+            let deployment = Deployment::get(conn.as_ref(), id).await.unwrap();
+            external_ids.push(deployment.model.external_id.to_string());
+        }
+
+        let request = proto::BatchStopRequestInternal {
+            deployment_ids: vec![
+                external_ids[2].clone(),
+                external_ids[1].clone(),
+                "bogus".to_string(),
+                // deployment of other user
+                external_ids[0].clone(),
+                external_ids[2].clone(),
+            ],
+            // adds running deployment 4 of the user
+            user_email: Some("user2@example.com".to_string()),
+        };
+        let response = batch_stop(conn.as_ref(), &runner, &request, &user_token)
+            .await
+            .unwrap();
+        let results: Vec<_> = response
+            .results
+            .into_iter()
+            .map(|r| (r.deployment_id, r.status))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (external_ids[2].clone(), proto::BatchStopStatus::Enqueued),
+                (
+                    external_ids[1].clone(),
+                    proto::BatchStopStatus::SkippedWrongState
+                ),
+                ("bogus".to_string(), proto::BatchStopStatus::NotFound),
+                (external_ids[0].clone(), proto::BatchStopStatus::NotFound),
+                (external_ids[3].clone(), proto::BatchStopStatus::Enqueued),
+            ]
+        );
+
+        let request = proto::BatchStopRequestInternal {
+            deployment_ids: vec![],
+            user_email: Some("user1@example.com".to_string()),
+        };
+        let err = batch_stop(conn.as_ref(), &runner, &request, &user_token)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::Auth(AuthError::Unauthorized(_))),
+            "unexpected error: {err:?}"
+        );
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        for (id, expected) in [
+            (1, DeploymentStatusType::Running),
+            (2, DeploymentStatusType::Stopped),
+            (3, DeploymentStatusType::Stopped),
+            (4, DeploymentStatusType::Stopped),
+        ] {
+            let deployment = Deployment::get(conn.as_ref(), id).await.unwrap();
+            assert_eq!(
+                deployment.model.status, expected,
+                "unexpected status of deployment {id}. error: {:?}",
+                deployment.model.error
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stop_without_timeout_keeps_stored_timeout() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stop_without_timeout_keeps_stored_timeout")
+                .await;
+        let _handles = repo.build_handles();
+        let conn = db.client();
+        let user_token = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        let instance = Instance::get(conn.as_ref(), 1).await.unwrap();
+        db::deployments::ActiveModel {
+            id: Set(1),
+            workflow_timeout_seconds: Set(Some(900)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+
+        let deployment = stop_instance(conn.as_ref(), &runner, &instance, None, &user_token)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.workflow_timeout(),
+            Some(Duration::from_secs(900))
+        );
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(deployment.model.workflow_timeout_seconds, Some(900));
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn restart_of_not_running_deployment_is_rejected() {
+        let (db, _github, _repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "restart_of_not_running_deployment_is_rejected",
+        )
+        .await;
+        let conn = db.client();
+        // latest deployment#3 of instance#2 is failed
+        let (user_token, _) = startable_instance(conn.as_ref()).await;
+        let instance = Instance::get(conn.as_ref(), 2).await.unwrap();
+
+        let err = restart_instance(conn.as_ref(), &runner, &instance, None, &user_token)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::InvalidStateTransition(_, _)),
+            "unexpected error: {err:?}"
+        );
+        let tasks = db::fang_tasks::Entity::find()
+            .all(conn.as_ref())
+            .await
+            .unwrap();
+        assert!(
+            tasks
+                .iter()
+                .all(|task| task.metadata["type"] != "RestartTask"),
+            "restart task was enqueued"
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn rejected_preset_start_keeps_no_instance() {
+        let (db, github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("rejected_preset_start_keeps_no_instance")
+                .await;
+        let handles = repo.build_handles();
+        let conn = db.client();
+        let (user_token, _) = startable_instance(conn.as_ref()).await;
+        global::DISPATCH_LIMIT
+            .init(Arc::new(
+                DispatchLimit::default().with_max_pending_tasks(Some(1)),
+            ))
+            .await
+            .unwrap();
+        tests_utils::db::insert_fang_task(
+            conn.as_ref(),
+            "StoppingTask",
+            1,
+            FangTaskState::New,
+            chrono::Duration::hours(1),
+        )
+        .await;
+        let instances = db::instances::Entity::find()
+            .all(conn.as_ref())
+            .await
+            .unwrap()
+            .len();
+        let request = proto::DeployPresetRequestInternal {
+            preset_name: "ethereum-mainnet".to_string(),
+            name: None,
+            overrides: None,
+        };
+
+        let err = deploy_preset(conn.as_ref(), &github, &runner, &request, 5, &user_token)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, DeployError::Overloaded),
+            "unexpected error: {err:?}"
+        );
+        let created = db::instances::Entity::find()
+            .all(conn.as_ref())
+            .await
+            .unwrap()
+            .len();
+        assert_eq!(created, instances);
+        handles.assert_hits("new_commit", 0);
+
+        db::fang_tasks::Entity::delete_many()
+            .exec(conn.as_ref())
+            .await
+            .unwrap();
+    }
+}
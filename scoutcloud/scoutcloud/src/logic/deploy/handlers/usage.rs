@@ -0,0 +1,39 @@
+use crate::{
+    logic::{deploy::TimeRange, users::UserToken, DeployError, Deployment},
+    server::proto,
+};
+use sea_orm::DatabaseConnection;
+
+/// Sums running time of deployments within the range. Ongoing deployments are counted up to now
+pub async fn get_usage(
+    db: &DatabaseConnection,
+    request: &proto::GetUsageRequestInternal,
+    user_token: &UserToken,
+) -> Result<proto::UsageInternal, DeployError> {
+    let range = TimeRange::parse(&request.from, &request.to)?;
+    let user_id = match &request.user_email {
+        Some(email) => user_token.find_accessible_user_id(db, email).await?,
+        None => Some(user_token.user.id),
+    };
+    let Some(user_id) = user_id else {
+        return Ok(proto::UsageInternal {
+            instance_hours: 0.0,
+            deployments: vec![],
+        });
+    };
+
+    let usage = Deployment::usage_of_user(db, user_id, &range, chrono::Utc::now()).await?;
+    let deployments: Vec<_> = usage
+        .iter()
+        .map(|usage| proto::DeploymentUsageInternal {
+            deployment_id: usage.deployment.model.external_id.to_string(),
+            instance_id: usage.instance.external_id.to_string(),
+            instance_name: usage.instance.name.clone(),
+            instance_hours: usage.instance_hours(),
+        })
+        .collect();
+    Ok(proto::UsageInternal {
+        instance_hours: deployments.iter().map(|d| d.instance_hours).sum(),
+        deployments,
+    })
+}
@@ -0,0 +1,181 @@
+use crate::logic::{
+    deploy::StatusActor,
+    github::webhook::{notify_workflow_run_completed, WorkflowRunEvent},
+    jobs, DeployError, Deployment,
+};
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::DatabaseConnection;
+
+/// Moves the deployment waiting for the completed workflow run to the next status.
+/// Deployment tasks keep polling the run, so missed or failed deliveries are still handled
+/// by them. Successfully deployed instance is marked as running only by the starting task,
+/// since it has to pass health check first. Finalized deployment is not polled any longer:
+/// the poll of its task is stopped right away instead of running to the next check.
+/// Returns id of the affected deployment.
+pub async fn handle_workflow_run_event(
+    db: &DatabaseConnection,
+    event: &WorkflowRunEvent,
+) -> Result<Option<i32>, DeployError> {
+    if event.action != "completed" {
+        return Ok(None);
+    }
+    let run_id = event.workflow_run.id;
+    let Some(conclusion) = event.completed_conclusion()? else {
+        return Ok(None);
+    };
+    let Some(deployment) = Deployment::find_by_run_id(db, run_id).await? else {
+        tracing::debug!(run_id =? run_id, "no deployment found for workflow run");
+        return Ok(None);
+    };
+    let mut deployment = deployment.with_actor(StatusActor::Webhook);
+
+    match (&deployment.model.status, conclusion.is_ok()) {
+        (DeploymentStatusType::Pending, true) => {
+            tracing::info!(
+                run_id =? run_id,
+                deployment_id = deployment.model.id,
+                "deploy workflow run succeeded, waking up starting task"
+            );
+            notify_workflow_run_completed(run_id);
+            return Ok(Some(deployment.model.id));
+        }
+        (DeploymentStatusType::Stopping, true) => {
+            deployment.mark_as_finished(db).await?;
+        }
+        // starting task dispatches the failed start again while retries are left
+        (DeploymentStatusType::Pending, false) if deployment.can_auto_retry() => {
+            tracing::info!(
+                run_id =? run_id,
+                deployment_id = deployment.model.id,
+                "deploy workflow run failed, waking up starting task to retry it"
+            );
+            notify_workflow_run_completed(run_id);
+            return Ok(Some(deployment.model.id));
+        }
+        (DeploymentStatusType::Pending | DeploymentStatusType::Stopping, false) => {
+            let error = DeployError::from_conclusion(run_id, conclusion);
+            deployment.mark_as_terminal_error(db, &error).await?;
+        }
+        (status, _) => {
+            // already handled by deployment task
+            tracing::debug!(
+                run_id =? run_id,
+                status =? status,
+                "deployment is not waiting for workflow run"
+            );
+            return Ok(None);
+        }
+    };
+    let stopped_polls = jobs::stop_polls(deployment.model.id);
+    tracing::info!(
+        run_id =? run_id,
+        deployment_id = deployment.model.id,
+        status =? deployment.model.status,
+        stopped_polls,
+        "updated deployment status from workflow run webhook"
+    );
+    Ok(Some(deployment.model.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+
+    fn event(run_id: u64, status: &str, conclusion: Option<&str>) -> WorkflowRunEvent {
+        let body = serde_json::json!({
+            "action": status,
+            "workflow_run": {
+                "id": run_id,
+                "status": status,
+                "conclusion": conclusion,
+            },
+        });
+        WorkflowRunEvent::parse(body.to_string().as_bytes()).unwrap()
+    }
+
+    async fn set_status(
+        conn: &DatabaseConnection,
+        deployment_id: i32,
+        status: DeploymentStatusType,
+        run_id: i64,
+    ) {
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(deployment_id),
+            status: Set(status),
+            run_id: Set(Some(run_id)),
+            ..Default::default()
+        }
+        .update(conn)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn completed_workflow_run_event_transitions_deployment() {
+        let db = tests_utils::init::test_db("test", "completed_workflow_run_event").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(&conn).await.unwrap();
+
+        let (pending_id, stopping_id) = (4, 1);
+        set_status(&conn, pending_id, DeploymentStatusType::Pending, 100).await;
+        set_status(&conn, stopping_id, DeploymentStatusType::Stopping, 200).await;
+
+        let in_progress = event(100, "in_progress", None);
+        assert_eq!(
+            handle_workflow_run_event(&conn, &in_progress)
+                .await
+                .unwrap(),
+            None
+        );
+        let unknown_run = event(300, "completed", Some("success"));
+        assert_eq!(
+            handle_workflow_run_event(&conn, &unknown_run)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // deployed instance is left to the starting task, which checks its health
+        let deployed = event(100, "completed", Some("success"));
+        assert_eq!(
+            handle_workflow_run_event(&conn, &deployed).await.unwrap(),
+            Some(pending_id)
+        );
+        let deployment = Deployment::get(conn.as_ref(), pending_id).await.unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Pending);
+        assert!(deployment.model.started_at.is_none());
+
+        let cleaned_up = event(200, "completed", Some("success"));
+        assert_eq!(
+            handle_workflow_run_event(&conn, &cleaned_up).await.unwrap(),
+            Some(stopping_id)
+        );
+        let deployment = Deployment::get(conn.as_ref(), stopping_id).await.unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Stopped);
+
+        // second delivery of the same event does nothing
+        assert_eq!(
+            handle_workflow_run_event(&conn, &cleaned_up).await.unwrap(),
+            None
+        );
+        set_status(&conn, stopping_id, DeploymentStatusType::Stopping, 200).await;
+
+        let cleanup_failed = event(200, "completed", Some("failure"));
+        assert_eq!(
+            handle_workflow_run_event(&conn, &cleanup_failed)
+                .await
+                .unwrap(),
+            Some(stopping_id)
+        );
+        let deployment = Deployment::get(conn.as_ref(), stopping_id).await.unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert!(deployment.model.terminal_error);
+        assert_eq!(
+            deployment.model.error_code.as_deref(),
+            Some("workflow_failed")
+        );
+    }
+}
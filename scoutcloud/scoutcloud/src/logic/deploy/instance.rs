@@ -1,19 +1,30 @@
-use super::deployment::Deployment;
+use super::{
+    deployment::Deployment, deployments_page::DeploymentsTimeFilter, labels::LabelSelector,
+    template::Template,
+};
 use crate::{
     logic::{
-        github::{CleanupWorkflow, DeployWorkflow, Workflow},
-        ConfigError, DeployError, GithubClient, InstanceConfig, UserConfig, UserToken,
+        ci::{CiBackend, CiRun, CiTarget, CiWorkflow},
+        github::{DeployWorkflow, Workflow},
+        json_utils,
+        secrets::{self, PARSED_CONFIG_SECRET_FIELDS, USER_CONFIG_SECRET_FIELDS},
+        ConfigError, ConfigValidationContext, DeployError, GithubClient, InstanceConfig,
+        UserConfig, UserToken,
     },
-    server::proto,
+    server::{proto, CloneSecretsPolicy},
     uuid_eq,
 };
+use anyhow::Context;
 use scoutcloud_entity as db;
 use sea_orm::{
     prelude::*, ActiveModelTrait, ActiveValue::Set, IntoActiveModel, QueryOrder, QuerySelect,
+    SqlErr,
 };
+use std::collections::BTreeMap;
 
 const MAX_LIMIT: u64 = 50;
-const MAX_TRY_GITHUB: u8 = 10;
+const MAX_SLUG_SUFFIX: u32 = 100;
+const CREATOR_NAME_INDEX: &str = "instances_creator_id_name_key";
 
 #[derive(Clone)]
 pub struct Instance {
@@ -38,6 +49,27 @@ impl Instance {
         Ok(this)
     }
 
+    /// Names are compared trimmed and case-insensitively, the same way as by the unique index
+    pub async fn find_by_name<C>(
+        db: &C,
+        creator: &UserToken,
+        name: &str,
+    ) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let instance = db::instances::Entity::find()
+            .filter(db::instances::Column::CreatorId.eq(creator.user.id))
+            .filter(Expr::cust_with_values(
+                r#"lower(trim("instances"."name")) = $1"#,
+                [name.trim().to_lowercase()],
+            ))
+            .one(db)
+            .await?
+            .map(Self::new);
+        Ok(instance)
+    }
+
     pub async fn find_all<C>(db: &C, user_token: &UserToken) -> Result<Vec<Self>, DbErr>
     where
         C: ConnectionTrait,
@@ -88,44 +120,208 @@ impl Instance {
     where
         C: ConnectionTrait,
     {
-        let slug = slug::slugify(name);
-        if slug.len() > 255 {
-            return Err(DeployError::InvalidValue("name is too long".to_string()));
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(DeployError::InvalidValue("name is empty".to_string()));
         }
-        if let Some(instance) = db::instances::Entity::find()
-            .filter(db::instances::Column::Slug.eq(&slug))
-            .one(db)
-            .await?
-        {
-            return Err(DeployError::InstanceExists(instance.slug));
+        if Self::find_by_name(db, creator, name).await?.is_some() {
+            return Err(DeployError::DuplicateName(name.to_string()));
         }
+        let slug = unique_slug(db, name).await?;
 
         let user_config = UserConfig::new(config.clone());
         let parsed_config =
             InstanceConfig::try_from_user_with_defaults(user_config.clone(), &slug).await?;
 
+        let (user_config, parsed_config) =
+            encrypt_configs(user_config.raw()?, parsed_config.raw().to_owned())?;
         let model = db::instances::ActiveModel {
             creator_id: Set(creator.user.id),
             name: Set(name.to_string()),
             slug: Set(slug),
-            user_config: Set(user_config.raw()?),
-            parsed_config: Set(parsed_config.raw().to_owned()),
+            user_config: Set(user_config),
+            parsed_config: Set(parsed_config),
             ..Default::default()
         }
         .insert(db)
-        .await?;
+        .await
+        .map_err(|err| match err.sql_err() {
+            // concurrent request created the instance after the check
+            Some(SqlErr::UniqueConstraintViolation(message))
+                if message.contains(CREATOR_NAME_INDEX) =>
+            {
+                DeployError::DuplicateName(name.to_string())
+            }
+            _ => DeployError::Db(err),
+        })?;
 
         Ok(Instance { model })
     }
+
+    /// Creates a new instance with the config of `source` merged with `overrides`.
+    /// The clone is not deployed
+    pub async fn try_clone<C>(
+        db: &C,
+        source: &Instance,
+        name: &str,
+        overrides: Option<&proto::DeployConfigPartialInternal>,
+        clone_secrets: CloneSecretsPolicy,
+        creator: &UserToken,
+    ) -> Result<Self, DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        if clone_secrets == CloneSecretsPolicy::Reprompt {
+            let provided = overrides
+                .map(serde_json::to_value)
+                .transpose()
+                .context("serializing overrides")?
+                .unwrap_or_default();
+            if let Some(field) = USER_CONFIG_SECRET_FIELDS
+                .iter()
+                .find(|field| provided.get(field).map_or(true, |value| value.is_null()))
+            {
+                return Err(DeployError::InvalidValue(format!(
+                    "secret `{field}` is not copied from the source instance, \
+                    it should be provided in overrides"
+                )));
+            }
+        }
+        let mut config = source.user_config()?;
+        if let Some(overrides) = overrides {
+            config = config.with_merged_partial(overrides)?;
+        }
+        Self::try_create(db, name, &config.internal, creator).await
+    }
+
+    /// Creates a new instance which references `template`, its config is the template
+    /// resolved with `variables` and merged with `overrides`. The instance is not deployed
+    pub async fn try_create_from_template<C>(
+        db: &C,
+        template: &Template,
+        name: &str,
+        variables: &BTreeMap<String, String>,
+        overrides: Option<&proto::DeployConfigPartialInternal>,
+        creator: &UserToken,
+    ) -> Result<Self, DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let config = template.config()?.compose(variables, overrides)?;
+        let mut instance = Self::try_create(db, name, &config.internal, creator).await?;
+        instance
+            .set_template(db, template, variables, overrides)
+            .await?;
+        Ok(instance)
+    }
 }
 
 impl Instance {
+    /// Makes the instance reference `template`, so its config is resolved from the template
+    /// every time it is started. The current config is not changed
+    pub async fn set_template<C>(
+        &mut self,
+        db: &C,
+        template: &Template,
+        variables: &BTreeMap<String, String>,
+        overrides: Option<&proto::DeployConfigPartialInternal>,
+    ) -> Result<(), DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let mut overrides = overrides
+            .map(serde_json::to_value)
+            .transpose()
+            .context("serializing overrides")?
+            .unwrap_or_else(|| serde_json::json!({}));
+        json_utils::filter_null_values(&mut overrides);
+        secrets::encrypt_fields(&mut overrides, USER_CONFIG_SECRET_FIELDS)
+            .map_err(ConfigError::from)?;
+        let mut active = self.model.clone().into_active_model();
+        active.template_id = Set(Some(template.model.id));
+        active.template_variables =
+            Set(serde_json::to_value(variables).context("serializing template variables")?);
+        active.template_overrides = Set(overrides);
+        self.model = active.update(db).await?;
+        Ok(())
+    }
+
     pub fn user_config_raw(&self) -> &serde_json::Value {
         &self.model.user_config
     }
 
     pub fn user_config(&self) -> Result<UserConfig, ConfigError> {
-        UserConfig::from_raw(self.user_config_raw().clone())
+        let mut raw = self.user_config_raw().clone();
+        secrets::decrypt_fields(&mut raw, USER_CONFIG_SECRET_FIELDS)?;
+        UserConfig::from_raw(raw)
+    }
+
+    /// Checks stored config before a workflow is dispatched,
+    /// so a bad config is rejected right away instead of failing in CI
+    pub fn validate_config(&self) -> Result<(), Vec<ConfigError>> {
+        let config = self.user_config().map_err(|err| vec![err])?;
+        self.validate_user_config(&config)
+    }
+
+    /// Same checks as `validate_config` for a config which is not stored yet
+    pub fn validate_user_config(&self, config: &UserConfig) -> Result<(), Vec<ConfigError>> {
+        let context = ConfigValidationContext::new(self.model.slug.clone());
+        config.validate(&context)
+    }
+
+    /// Current config of the template of the instance resolved with variables and overrides
+    /// of the instance. Returns `None` if the instance doesn't reference a template
+    pub async fn template_config<C>(&self, db: &C) -> Result<Option<UserConfig>, DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let Some(template_id) = self.model.template_id else {
+            return Ok(None);
+        };
+        let template = Template::get(db, template_id).await?;
+        let variables: BTreeMap<String, String> =
+            serde_json::from_value(self.model.template_variables.clone())
+                .context("parsing template variables")?;
+        let mut overrides = self.model.template_overrides.clone();
+        secrets::decrypt_fields(&mut overrides, USER_CONFIG_SECRET_FIELDS)
+            .map_err(ConfigError::from)?;
+        let overrides: proto::DeployConfigPartialInternal =
+            serde_json::from_value(overrides).context("parsing template overrides")?;
+        let config = template.config()?.compose(&variables, Some(&overrides))?;
+        Ok(Some(config))
+    }
+
+    /// Stores `config` resolved from the template if it differs from the current one.
+    /// Returns true if the config was changed, so it should be committed again
+    pub async fn apply_template_config<C>(
+        &mut self,
+        db: &C,
+        config: UserConfig,
+    ) -> Result<bool, DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        if config.raw()? == self.user_config()?.raw()? {
+            return Ok(false);
+        }
+        self.update_config(db, config).await?;
+        Ok(true)
+    }
+
+    /// Config set directly is not overwritten by the template on the next start
+    pub async fn detach_template<C>(&mut self, db: &C) -> Result<(), DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if self.model.template_id.is_none() {
+            return Ok(());
+        }
+        let mut active = self.model.clone().into_active_model();
+        active.template_id = Set(None);
+        active.template_variables = Set(serde_json::json!({}));
+        active.template_overrides = Set(serde_json::json!({}));
+        self.model = active.update(db).await?;
+        Ok(())
     }
 
     pub fn parsed_config(&self) -> InstanceConfig {
@@ -142,7 +338,18 @@ impl Instance {
         action_name: &str,
     ) -> Result<(), DeployError> {
         let file_name = get_filename(&self.model.slug);
-        let content = self.parsed_config().to_yaml()?;
+        // the only place where secrets of parsed config are needed in plaintext.
+        // References to the secret store are committed unresolved,
+        // workflows get their values only when they are dispatched
+        let mut parsed_config = self.parsed_config();
+        // config is committed to the repo which runs workflows of the instance
+        let github = github.for_instance(
+            parsed_config.deployment_target(),
+            parsed_config.workflow_repo(),
+        )?;
+        secrets::decrypt_fields(&mut parsed_config.raw, PARSED_CONFIG_SECRET_FIELDS)
+            .map_err(ConfigError::from)?;
+        let content = parsed_config.to_yaml()?;
         github
             .create_or_update_file(&file_name, &content, action_name)
             .await?;
@@ -191,6 +398,7 @@ impl Instance {
     where
         C: ConnectionTrait,
     {
+        let (user_config, parsed_config) = encrypt_configs(user_config, parsed_config)?;
         let mut active = self.model.clone().into_active_model();
         active.user_config = Set(user_config);
         active.parsed_config = Set(parsed_config);
@@ -198,17 +406,86 @@ impl Instance {
         Ok(model)
     }
 
-    pub async fn deployments<C>(&self, db: &C) -> Result<Vec<Deployment>, DbErr>
+    pub async fn set_auto_redeploy<C>(&mut self, db: &C, enabled: bool) -> Result<(), DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let mut active = self.model.clone().into_active_model();
+        active.auto_redeploy = Set(enabled);
+        self.model = active.update(db).await?;
+        Ok(())
+    }
+
+    /// Makes `new_owner` the creator of the instance, names of instances are unique
+    /// per user, so the new owner must not have an instance with the same name
+    pub async fn transfer_to<C>(
+        &mut self,
+        db: &C,
+        new_owner: &db::users::Model,
+    ) -> Result<(), DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let mut active = self.model.clone().into_active_model();
+        active.creator_id = Set(new_owner.id);
+        self.model = active.update(db).await.map_err(|err| match err.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(message))
+                if message.contains(CREATOR_NAME_INDEX) =>
+            {
+                DeployError::DuplicateName(self.model.name.clone())
+            }
+            _ => DeployError::Db(err),
+        })?;
+        Ok(())
+    }
+
+    pub async fn deployments<C>(
+        &self,
+        db: &C,
+        include_deleted: bool,
+    ) -> Result<Vec<Deployment>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        self.deployments_matching(
+            db,
+            include_deleted,
+            &LabelSelector::default(),
+            &DeploymentsTimeFilter::default(),
+        )
+        .await
+    }
+
+    /// Deployments which have all labels of the `selector`
+    pub async fn deployments_matching<C>(
+        &self,
+        db: &C,
+        include_deleted: bool,
+        selector: &LabelSelector,
+        time: &DeploymentsTimeFilter,
+    ) -> Result<Vec<Deployment>, DbErr>
     where
         C: ConnectionTrait,
     {
-        let deployments = Deployment::default_select()
+        let mut select = if include_deleted {
+            Deployment::select_including_deleted()
+        } else {
+            Deployment::default_select()
+        };
+        if !selector.is_empty() {
+            select = select.filter(Expr::cust_with_values(
+                r#""deployments"."labels" @> $1"#,
+                [selector.to_json()],
+            ));
+        }
+        let deployments = time
+            .apply(select)
             .filter(db::deployments::Column::InstanceId.eq(self.model.id))
             .limit(MAX_LIMIT)
             .all(db)
             .await?
             .into_iter()
-            .map(|model| Deployment { model })
+            .map(Deployment::new)
             .collect();
         Ok(deployments)
     }
@@ -229,45 +506,151 @@ impl Instance {
     }
 }
 
-// Starting and stopping instance using github api
+// Starting and stopping instance using ci backend
 impl Instance {
-    pub async fn deploy_via_github(
+    pub fn deploy_workflow(&self) -> DeployWorkflow {
+        let config = self.parsed_config();
+        DeployWorkflow::new(self.model.slug.clone())
+            .with_git_ref(config.git_ref().map(str::to_string))
+            .with_image(
+                config.image().map(str::to_string),
+                config.tag().map(str::to_string),
+            )
+    }
+
+    #[tracing::instrument(skip_all, fields(instance_id = %self.model.external_id), level = "info")]
+    pub async fn deploy_via_ci(
         &self,
-        github: &GithubClient,
-    ) -> Result<octocrab::models::workflows::Run, DeployError> {
-        let run = DeployWorkflow::new(self.model.slug.clone())
-            .run_and_get_latest_with_mutex(github, MAX_TRY_GITHUB)
-            .await?
-            .ok_or(anyhow::anyhow!("no instance workflow found after running"))?;
+        ci: &dyn CiBackend,
+        target: &CiTarget,
+    ) -> Result<CiRun, DeployError> {
+        let run = ci.dispatch(CiWorkflow::Deploy, target).await?;
         tracing::info!(
             instance_uuid =? self.model.external_id,
             run_id =? run.id,
-            run_status =? run.status,
-            "triggered github deploy workflow"
+            "triggered deploy workflow"
         );
         Ok(run)
     }
 
-    pub async fn cleanup_via_github(
+    #[tracing::instrument(skip_all, fields(instance_id = %self.model.external_id), level = "info")]
+    pub async fn cleanup_via_ci(
         &self,
-        github: &GithubClient,
-    ) -> Result<octocrab::models::workflows::Run, DeployError> {
-        let run = CleanupWorkflow::new(self.model.slug.clone())
-            .run_and_get_latest_with_mutex(github, MAX_TRY_GITHUB)
-            .await?
-            .ok_or(anyhow::anyhow!(
-                "no cleanup instance workflow found after running"
-            ))?;
+        ci: &dyn CiBackend,
+        target: &CiTarget,
+    ) -> Result<CiRun, DeployError> {
+        let run = ci.dispatch(CiWorkflow::Cleanup, target).await?;
         tracing::info!(
             instance_id =? self.model.external_id,
             run_id =? run.id,
-            run_status =? run.status,
-            "triggered github cleanup workflow"
+            "triggered cleanup workflow"
         );
         Ok(run)
     }
 }
 
+fn encrypt_configs(
+    mut user_config: serde_json::Value,
+    mut parsed_config: serde_json::Value,
+) -> Result<(serde_json::Value, serde_json::Value), ConfigError> {
+    secrets::encrypt_fields(&mut user_config, USER_CONFIG_SECRET_FIELDS)?;
+    secrets::encrypt_fields(&mut parsed_config, PARSED_CONFIG_SECRET_FIELDS)?;
+    Ok((user_config, parsed_config))
+}
+
+/// Slug is used in names of github files and k8s resources, so it is unique among all users.
+/// Instances of different users with the same name get a numeric suffix
+async fn unique_slug<C>(db: &C, name: &str) -> Result<String, DeployError>
+where
+    C: ConnectionTrait,
+{
+    let base = slug::slugify(name);
+    for n in 1..=MAX_SLUG_SUFFIX {
+        let slug = match n {
+            1 => base.clone(),
+            n => format!("{base}-{n}"),
+        };
+        if slug.len() > 255 {
+            return Err(DeployError::InvalidValue("name is too long".to_string()));
+        }
+        let taken = db::instances::Entity::find()
+            .filter(db::instances::Column::Slug.eq(&slug))
+            .one(db)
+            .await?
+            .is_some();
+        if !taken {
+            return Ok(slug);
+        }
+    }
+    Err(DeployError::InstanceExists(base))
+}
+
 fn get_filename(slug: &str) -> String {
     format!("values-{}.yaml", slug)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use httpmock::{Method::POST, MockServer};
+    use pretty_assertions::assert_eq;
+
+    fn mock_rpc() -> MockServer {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST)
+                .path("/")
+                .json_body_partial(r#"{"method": "eth_chainId"}"#);
+            then.status(200).json_body(serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": "0x1"
+            }));
+        });
+        server
+    }
+
+    fn config(rpc: &MockServer) -> proto::DeployConfigInternal {
+        serde_json::from_value(serde_json::json!({
+            "rpc_url": rpc.url("/"),
+            "server_size": "small",
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn instance_names_are_unique_per_user() {
+        let db = tests_utils::init::test_db("test", "instance_names_are_unique_per_user").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let rpc = mock_rpc();
+        let user1 = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        let user2 = UserToken::get(conn.as_ref(), 2).await.unwrap();
+
+        let created = Instance::try_create(conn.as_ref(), "  Mainnet  ", &config(&rpc), &user1)
+            .await
+            .unwrap();
+        assert_eq!(created.model.name, "Mainnet");
+        assert_eq!(created.model.slug, "mainnet");
+
+        for duplicate in ["Mainnet", " mainnet", "MAINNET "] {
+            let result =
+                Instance::try_create(conn.as_ref(), duplicate, &config(&rpc), &user1).await;
+            assert!(
+                matches!(result, Err(DeployError::DuplicateName(ref name)) if name == duplicate.trim()),
+                "'{duplicate}' should be rejected, got {:?}",
+                result.err()
+            );
+        }
+
+        let other = Instance::try_create(conn.as_ref(), "mainnet", &config(&rpc), &user2)
+            .await
+            .unwrap();
+        assert_eq!(other.model.creator_id, user2.user.id);
+        assert_eq!(other.model.name, "mainnet");
+        assert_eq!(other.model.slug, "mainnet-2");
+    }
+}
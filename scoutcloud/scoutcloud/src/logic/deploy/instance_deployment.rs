@@ -1,12 +1,13 @@
 use crate::{
     logic::{
-        deploy::deployment::map_deployment_status, DeployError, Deployment, Instance, UserToken,
+        deploy::{deployment::map_deployment_status, DeploymentsTimeFilter, LabelSelector},
+        DeployError, Deployment, Instance, UserToken,
     },
     server::proto,
     uuid_eq,
 };
 use scoutcloud_entity as db;
-use sea_orm::{ConnectionTrait, DbErr, LoaderTrait, QueryFilter, QuerySelect};
+use sea_orm::{ConnectionTrait, DbErr, LoaderTrait, QueryFilter, QuerySelect, Select};
 
 pub struct InstanceDeployment {
     pub instance: Instance,
@@ -46,7 +47,30 @@ impl InstanceDeployment {
     where
         C: ConnectionTrait,
     {
-        let (deployment, instance) = match Deployment::default_select()
+        Self::find_by_deployment_uuid_in(db, Deployment::default_select(), deployment_uuid).await
+    }
+
+    /// Soft-deleted deployment is found as well
+    pub async fn find_by_deployment_uuid_including_deleted<C>(
+        db: &C,
+        deployment_uuid: &str,
+    ) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let select = Deployment::select_including_deleted();
+        Self::find_by_deployment_uuid_in(db, select, deployment_uuid).await
+    }
+
+    async fn find_by_deployment_uuid_in<C>(
+        db: &C,
+        select: Select<db::deployments::Entity>,
+        deployment_uuid: &str,
+    ) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let (deployment, instance) = match select
             .filter(uuid_eq!(
                 db::deployments::Column::ExternalId,
                 deployment_uuid
@@ -85,7 +109,7 @@ impl InstanceDeployment {
             .map(|(instance, mut deployments)| {
                 Ok(InstanceDeployment {
                     instance: Instance { model: instance },
-                    deployment: deployments.pop().map(|model| Deployment { model }),
+                    deployment: deployments.pop().map(Deployment::new),
                 })
             })
             .collect()
@@ -94,11 +118,16 @@ impl InstanceDeployment {
     pub async fn find_deployments_of_instance<C>(
         db: &C,
         instance: &Instance,
+        include_deleted: bool,
+        selector: &LabelSelector,
+        time: &DeploymentsTimeFilter,
     ) -> Result<Vec<Self>, DeployError>
     where
         C: ConnectionTrait,
     {
-        let deployments = instance.deployments(db).await?;
+        let deployments = instance
+            .deployments_matching(db, include_deleted, selector, time)
+            .await?;
         Ok(deployments
             .into_iter()
             .map(|d| InstanceDeployment {
@@ -124,6 +153,7 @@ impl TryFrom<InstanceDeployment> for proto::InstanceInternal {
             config: Some(user_config.internal),
             deployment_id: deployment.as_ref().map(|d| d.model.external_id.to_string()),
             deployment_status: map_deployment_status(deployment.as_ref().map(|d| &d.model.status)),
+            auto_redeploy: instance.model.auto_redeploy,
         };
         Ok(proto_instance)
     }
@@ -141,12 +171,29 @@ impl TryFrom<InstanceDeployment> for proto::DeploymentInternal {
             instance_id: instance.model.external_id.to_string(),
             status: map_deployment_status(Some(&deployment.model.status)),
             error: deployment.model.error,
+            error_code: deployment.model.error_code,
             created_at: deployment.model.created_at.to_string(),
             started_at: deployment.model.started_at.map(|t| t.to_string()),
             finished_at: deployment.model.finished_at.map(|t| t.to_string()),
             config: Some(config.internal),
             blockscout_url: deployment.model.instance_url,
             total_cost: deployment.model.total_cost.to_string(),
+            expires_at: deployment.model.expires_at.map(|t| t.to_string()),
+            deleted_at: deployment.model.deleted_at.map(|t| t.to_string()),
+            labels: deployment.labels(),
+            queue_position: None,
+            eta_seconds: None,
+            estimated_completion_at: None,
+            run_id: deployment.run_id().map(|run_id| run_id.to_string()),
+            run_url: None,
+            last_polled_at: deployment.model.last_polled_at.map(|t| t.to_string()),
+            status_changes: vec![],
+            scheduled_at: deployment.model.scheduled_at.map(|t| t.to_string()),
+            auto_retries: deployment.model.auto_retries as u32,
+            max_auto_retries: deployment.model.max_auto_retries as u32,
+            started_by: deployment.model.started_by,
+            stopped_by: deployment.model.stopped_by,
+            stop_reason: deployment.model.stop_reason,
         })
     }
 }
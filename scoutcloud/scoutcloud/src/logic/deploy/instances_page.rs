@@ -0,0 +1,76 @@
+use super::instance::Instance;
+use crate::logic::DeployError;
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, QueryOrder, QuerySelect};
+use serde::{Deserialize, Serialize};
+
+/// Position of the last instance of the page, clients get it as an opaque token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstancesCursor {
+    id: i32,
+}
+
+impl InstancesCursor {
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("cursor should be serializable"))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, DeployError> {
+        hex::decode(token)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| DeployError::InvalidValue("invalid page_token".to_string()))
+    }
+}
+
+pub struct InstancesPage {
+    /// Instances together with their creators
+    pub items: Vec<(Instance, db::users::Model)>,
+    pub next_cursor: Option<InstancesCursor>,
+}
+
+impl Instance {
+    /// Instances of all users, the oldest instances go first. Ids never change,
+    /// so instances created during pagination are found on the last pages
+    pub async fn find_page<C>(
+        db: &C,
+        page_size: u64,
+        cursor: Option<&InstancesCursor>,
+    ) -> Result<InstancesPage, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        use db::instances::Column;
+
+        let mut query = db::instances::Entity::find()
+            .find_also_related(db::users::Entity)
+            .order_by_asc(Column::Id)
+            // one more instance to find out if there is a next page
+            .limit(page_size + 1);
+        if let Some(cursor) = cursor {
+            query = query.filter(Column::Id.gt(cursor.id));
+        }
+
+        let mut rows = query.all(db).await?;
+        let has_next_page = rows.len() as u64 > page_size;
+        rows.truncate(page_size as usize);
+        let items = rows
+            .into_iter()
+            .map(|(instance, creator)| {
+                let creator = creator.ok_or(DbErr::RecordNotFound(format!(
+                    "creator of instance {}",
+                    instance.id
+                )))?;
+                Ok((Instance::new(instance), creator))
+            })
+            .collect::<Result<Vec<_>, DbErr>>()?;
+        let next_cursor =
+            items
+                .last()
+                .filter(|_| has_next_page)
+                .map(|(instance, _)| InstancesCursor {
+                    id: instance.model.id,
+                });
+        Ok(InstancesPage { items, next_cursor })
+    }
+}
@@ -0,0 +1,106 @@
+use crate::logic::DeployError;
+use std::collections::BTreeMap;
+
+const MAX_LABELS: usize = 32;
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Free-form key/value labels of a deployment, e.g. `team=indexing`
+pub type Labels = BTreeMap<String, String>;
+
+/// Keys are used in selectors, so they can't contain `=` and `,`
+pub fn validate_labels(labels: &Labels) -> Result<(), DeployError> {
+    if labels.len() > MAX_LABELS {
+        return Err(DeployError::InvalidValue(format!(
+            "deployment can have at most {MAX_LABELS} labels, got {}",
+            labels.len()
+        )));
+    }
+    for (key, value) in labels {
+        if !is_valid_key(key) {
+            return Err(DeployError::InvalidValue(format!(
+                "invalid label key '{key}': it should be from 1 to {MAX_LABEL_LENGTH} characters \
+                 long and contain only letters, digits, '-', '_', '.' and '/'"
+            )));
+        }
+        if value.len() > MAX_LABEL_LENGTH || value.contains(',') {
+            return Err(DeployError::InvalidValue(format!(
+                "invalid value of label '{key}': it should be at most {MAX_LABEL_LENGTH} \
+                 characters long and can't contain ','"
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= MAX_LABEL_LENGTH
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+}
+
+/// Comma separated `key=value` pairs, e.g. `team=indexing,env=staging`.
+/// Deployment matches the selector if it has all of its labels
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector {
+    labels: Labels,
+}
+
+impl LabelSelector {
+    pub fn parse(selector: &str) -> Result<Self, DeployError> {
+        let mut labels = Labels::new();
+        for pair in selector
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (key, value) = pair.split_once('=').ok_or_else(|| {
+                DeployError::InvalidValue(format!(
+                    "invalid label selector '{pair}': expected 'key=value'"
+                ))
+            })?;
+            let (key, value) = (key.trim(), value.trim());
+            if let Some(other) = labels.insert(key.to_string(), value.to_string()) {
+                if other != value {
+                    return Err(DeployError::InvalidValue(format!(
+                        "label '{key}' is selected with different values"
+                    )));
+                }
+            }
+        }
+        validate_labels(&labels)?;
+        Ok(Self { labels })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Object which is contained by labels of matching deployments
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(self.labels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn selector_is_parsed() {
+        let selector = LabelSelector::parse(" team=indexing, env=staging ,").unwrap();
+        assert_eq!(
+            selector.to_json(),
+            serde_json::json!({"team": "indexing", "env": "staging"})
+        );
+        assert!(LabelSelector::parse("").unwrap().is_empty());
+        for invalid in ["team", "team=a,team=b", "=value", "te am=a"] {
+            assert!(
+                LabelSelector::parse(invalid).is_err(),
+                "selector '{invalid}' should be invalid"
+            );
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use super::log_stream::{LogLine, LogTail};
+use crate::logic::ci::{CiBackend, CiTarget};
+use lazy_static::lazy_static;
+use octocrab::models::RunId;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Most recent lines of logs kept in memory for every deployment
+pub const MAX_TAIL_LINES: usize = 500;
+
+lazy_static! {
+    static ref BUFFERS: Mutex<HashMap<i32, LogBuffer>> = Default::default();
+}
+
+/// Ring buffer of the latest lines of logs of the current run of the deployment
+#[derive(Debug)]
+struct LogBuffer {
+    run_id: RunId,
+    tail: LogTail,
+    lines: VecDeque<LogLine>,
+}
+
+impl LogBuffer {
+    fn new(run_id: RunId) -> Self {
+        Self {
+            run_id,
+            tail: LogTail::default(),
+            lines: VecDeque::with_capacity(MAX_TAIL_LINES),
+        }
+    }
+
+    fn push(&mut self, text: &str, complete: bool) {
+        for text in self.tail.new_lines(text, complete) {
+            if self.lines.len() == MAX_TAIL_LINES {
+                self.lines.pop_front();
+            }
+            let number = self.tail.next_number();
+            self.lines.push_back(LogLine {
+                run_id: self.run_id,
+                number,
+                text,
+            });
+        }
+    }
+}
+
+/// Appends new lines of logs of the run to the tail of the deployment.
+/// Logs of the next run of the deployment replace the tail of the previous one
+pub fn record_logs(deployment_id: i32, run_id: RunId, text: &str, complete: bool) {
+    let mut buffers = BUFFERS.lock().expect("log buffers lock is poisoned");
+    let buffer = buffers
+        .entry(deployment_id)
+        .or_insert_with(|| LogBuffer::new(run_id));
+    if buffer.run_id != run_id {
+        *buffer = LogBuffer::new(run_id);
+    }
+    buffer.push(text, complete);
+}
+
+/// Lines of the tail of the deployment from the oldest one. Tails are kept only
+/// by the process which polls the workflow, so other replicas return `None`
+pub fn log_tail(deployment_id: i32) -> Option<Vec<LogLine>> {
+    BUFFERS
+        .lock()
+        .expect("log buffers lock is poisoned")
+        .get(&deployment_id)
+        .map(|buffer| buffer.lines.iter().cloned().collect())
+}
+
+/// Called once the deployment is finished, its logs are stored separately
+pub fn discard_tail(deployment_id: i32) -> bool {
+    BUFFERS
+        .lock()
+        .expect("log buffers lock is poisoned")
+        .remove(&deployment_id)
+        .is_some()
+}
+
+/// Fetches live logs of the run into the tail of the deployment every `interval`
+/// until `wait` is completed and returns its output. Lines written after
+/// the last fetch are not added to the tail
+pub async fn follow_logs<F: Future>(
+    ci: &dyn CiBackend,
+    target: &CiTarget,
+    deployment_id: i32,
+    run_id: RunId,
+    interval: Duration,
+    wait: F,
+) -> F::Output {
+    tokio::pin!(wait);
+    loop {
+        let fetch = async {
+            tokio::time::sleep(interval).await;
+            ci.fetch_live_logs(target, run_id).await
+        };
+        tokio::select! {
+            output = &mut wait => return output,
+            logs = fetch => match logs {
+                Ok(text) => record_logs(deployment_id, run_id, &text, false),
+                // logs of the run may be not available yet
+                Err(err) => tracing::debug!(
+                    deployment_id,
+                    run_id = %run_id,
+                    "failed to fetch live logs for the tail: {}",
+                    err
+                ),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn texts(lines: &[LogLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn tail_keeps_most_recent_lines() {
+        let deployment_id = 3001;
+        record_logs(deployment_id, RunId(1), "first\nsecond\nunfinished", false);
+        let tail = log_tail(deployment_id).unwrap();
+        assert_eq!(texts(&tail), vec!["first", "second"]);
+        assert_eq!(tail[1].number, 2);
+
+        // logs are fetched again on every poll, only new lines are added
+        let logs: String = ["first", "second"]
+            .into_iter()
+            .map(str::to_string)
+            .chain((0..MAX_TAIL_LINES).map(|i| format!("line {i}")))
+            .map(|line| format!("{line}\n"))
+            .collect();
+        record_logs(deployment_id, RunId(1), &logs, false);
+        let tail = log_tail(deployment_id).unwrap();
+        assert_eq!(tail.len(), MAX_TAIL_LINES);
+        // older lines are evicted past the cap
+        assert_eq!(tail[0].text, "line 0");
+        assert_eq!(tail[0].number, 3);
+        let last = tail.last().unwrap();
+        assert_eq!(last.text, format!("line {}", MAX_TAIL_LINES - 1));
+        assert_eq!(last.number, MAX_TAIL_LINES as u64 + 2);
+
+        // tail of the next run starts from scratch
+        record_logs(deployment_id, RunId(2), "restarted\n", false);
+        let tail = log_tail(deployment_id).unwrap();
+        assert_eq!(texts(&tail), vec!["restarted"]);
+        assert_eq!((tail[0].run_id, tail[0].number), (RunId(2), 1));
+
+        assert!(discard_tail(deployment_id));
+        assert_eq!(log_tail(deployment_id), None);
+        assert!(!discard_tail(deployment_id));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::logic::{ci::CiBackend, DeployError, Deployment, Instance};
+use octocrab::models::RunId;
+use sea_orm::DatabaseConnection;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc;
+
+pub const DEFAULT_LOGS_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Lines which are not received by the client yet are kept in memory up to this number,
+/// polling waits for the client after that
+const MAX_BUFFERED_LINES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub run_id: RunId,
+    /// Number of the line in logs of the run, starting from 1
+    pub number: u64,
+    pub text: String,
+}
+
+/// Remembers which lines of the run were already streamed. Some backends return
+/// only the end of logs, so the window of lines may move between polls
+#[derive(Debug, Default)]
+pub(super) struct LogTail {
+    sent: u64,
+    window_len: usize,
+    last_line: Option<String>,
+}
+
+impl LogTail {
+    /// Returns lines of `text` which were not streamed yet. Unfinished last line
+    /// may still grow, so it is returned only when logs are `complete`
+    pub(super) fn new_lines(&mut self, text: &str, complete: bool) -> Vec<String> {
+        let lines: Vec<&str> = text
+            .split_inclusive('\n')
+            .filter(|line| complete || line.ends_with('\n'))
+            .map(|line| line.trim_end_matches(['\n', '\r']))
+            .collect();
+        let start = match &self.last_line {
+            None => 0,
+            Some(last) if lines.get(self.window_len.wrapping_sub(1)) == Some(&last.as_str()) => {
+                self.window_len
+            }
+            Some(last) => lines
+                .iter()
+                .rposition(|line| line == last)
+                .map(|position| position + 1)
+                .unwrap_or(0),
+        };
+        let new_lines: Vec<String> = lines[start..].iter().map(|line| line.to_string()).collect();
+        if !new_lines.is_empty() {
+            self.window_len = lines.len();
+            self.last_line = lines.last().map(|line| line.to_string());
+        }
+        new_lines
+    }
+
+    pub(super) fn next_number(&mut self) -> u64 {
+        self.sent += 1;
+        self.sent
+    }
+}
+
+/// Streams new lines of logs of the deployment workflows until the deployment reaches
+/// a terminal status. Polling is stopped as soon as the receiver is dropped
+pub fn stream_logs(
+    db: Arc<DatabaseConnection>,
+    ci: Arc<dyn CiBackend>,
+    instance: Instance,
+    deployment: Deployment,
+    poll_interval: Duration,
+) -> mpsc::Receiver<Result<LogLine, DeployError>> {
+    let (sender, receiver) = mpsc::channel(MAX_BUFFERED_LINES);
+    tokio::spawn(async move {
+        let result = poll_logs(
+            db.as_ref(),
+            ci.as_ref(),
+            &instance,
+            deployment,
+            poll_interval,
+            &sender,
+        )
+        .await;
+        if let Err(err) = result {
+            let _ = sender.send(Err(err)).await;
+        }
+    });
+    receiver
+}
+
+async fn poll_logs(
+    db: &DatabaseConnection,
+    ci: &dyn CiBackend,
+    instance: &Instance,
+    mut deployment: Deployment,
+    poll_interval: Duration,
+    sender: &mpsc::Sender<Result<LogLine, DeployError>>,
+) -> Result<(), DeployError> {
+    let target = deployment.ci_target(instance);
+    let mut tail: Option<(RunId, LogTail)> = None;
+    loop {
+        deployment.reload(db).await?;
+        let finished = !deployment.has_running_workflow();
+        if let Some(run_id) = deployment.run_id() {
+            // restart dispatches the next run, its logs are streamed from the beginning
+            if tail.as_ref().map(|(id, _)| *id) != Some(run_id) {
+                tail = Some((run_id, LogTail::default()));
+            }
+            let (_, run_tail) = tail.as_mut().expect("tail is set above");
+            match ci.fetch_live_logs(&target, run_id).await {
+                Ok(text) => {
+                    for text in run_tail.new_lines(&text, finished) {
+                        let line = LogLine {
+                            run_id,
+                            number: run_tail.next_number(),
+                            text,
+                        };
+                        if sender.send(Ok(line)).await.is_err() {
+                            tracing::debug!("client of logs stream disconnected");
+                            return Ok(());
+                        }
+                    }
+                }
+                // logs of the run may be not available yet
+                Err(err) => tracing::warn!(
+                    deployment_id = deployment.model.id,
+                    run_id = %run_id,
+                    "failed to fetch live logs: {}",
+                    err
+                ),
+            }
+        }
+        if finished {
+            return Ok(());
+        }
+        tokio::select! {
+            _ = sender.closed() => {
+                tracing::debug!("client of logs stream disconnected");
+                return Ok(());
+            }
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::{
+            ci::{CiRun, CiTarget, CiWorkflow},
+            github::{logs::RunLogs, types::RunConclusion, PollBackoff},
+            GithubError,
+        },
+        tests_utils,
+    };
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+    use std::sync::Mutex;
+    use tokio_util::sync::CancellationToken;
+
+    const PENDING_DEPLOYMENT_ID: i32 = 4;
+
+    /// Returns the next chunk of logs on every fetch, the deployment is finished
+    /// once all chunks are returned
+    #[derive(Debug)]
+    struct ChunkedLogs {
+        db: Arc<DatabaseConnection>,
+        chunks: Mutex<Vec<&'static str>>,
+        logs: Mutex<String>,
+    }
+
+    #[async_trait::async_trait]
+    impl CiBackend for ChunkedLogs {
+        async fn dispatch(&self, _: CiWorkflow, _: &CiTarget) -> Result<CiRun, GithubError> {
+            Err(anyhow::anyhow!("logs stream never dispatches workflows").into())
+        }
+
+        async fn get_run(
+            &self,
+            _: CiWorkflow,
+            _: &CiTarget,
+            run_id: RunId,
+        ) -> Result<CiRun, GithubError> {
+            Ok(CiRun::new(run_id, "fake".to_string()))
+        }
+
+        async fn wait_for_success(
+            &self,
+            _: &CiRun,
+            _: Duration,
+            _: PollBackoff,
+            _: &CancellationToken,
+        ) -> Result<RunConclusion, GithubError> {
+            Err(anyhow::anyhow!("logs stream never waits for runs").into())
+        }
+
+        async fn cancel(&self, _: RunId) -> Result<(), GithubError> {
+            Ok(())
+        }
+
+        async fn fetch_logs(
+            &self,
+            _: &CiTarget,
+            _: RunId,
+            _: usize,
+        ) -> Result<RunLogs, GithubError> {
+            Ok(RunLogs::from_text("", 0))
+        }
+
+        async fn fetch_live_logs(&self, _: &CiTarget, _: RunId) -> Result<String, GithubError> {
+            let chunk = {
+                let mut chunks = self.chunks.lock().unwrap();
+                (!chunks.is_empty()).then(|| chunks.remove(0))
+            };
+            if self.chunks.lock().unwrap().is_empty() {
+                let mut deployment = Deployment::get(self.db.as_ref(), PENDING_DEPLOYMENT_ID)
+                    .await
+                    .unwrap();
+                deployment.mark_as_running(self.db.as_ref()).await.unwrap();
+            }
+            let mut logs = self.logs.lock().unwrap();
+            logs.push_str(chunk.unwrap_or_default());
+            Ok(logs.clone())
+        }
+    }
+
+    #[test]
+    fn tail_returns_only_new_lines() {
+        let mut tail = LogTail::default();
+        assert_eq!(tail.new_lines("first\nsec", false), vec!["first"]);
+        assert_eq!(tail.new_lines("first\nsec", false), Vec::<String>::new());
+        assert_eq!(
+            tail.new_lines("first\nsecond\nthird\n", false),
+            vec!["second", "third"]
+        );
+        // window of the last lines moved forward
+        assert_eq!(
+            tail.new_lines("third\nfourth\nfifth", true),
+            vec!["fourth", "fifth"]
+        );
+    }
+
+    #[tokio::test]
+    async fn lines_are_streamed_in_order_until_deployment_is_finished() {
+        let db = tests_utils::init::test_db("test", "logs_are_streamed_until_finished").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(PENDING_DEPLOYMENT_ID),
+            status: Set(DeploymentStatusType::Pending),
+            run_id: Set(Some(1)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), PENDING_DEPLOYMENT_ID)
+            .await
+            .unwrap();
+        let instance = deployment.get_instance(conn.as_ref()).await.unwrap();
+        let ci = Arc::new(ChunkedLogs {
+            db: conn.clone(),
+            chunks: Mutex::new(vec![
+                "",
+                "===== deploy =====\nhelm upgr",
+                "ade --install\n",
+                "deployed\ndone",
+            ]),
+            logs: Default::default(),
+        });
+
+        let mut receiver = stream_logs(conn, ci, instance, deployment, Duration::from_millis(50));
+        let mut lines = vec![];
+        while let Some(line) = receiver.recv().await {
+            let line = line.unwrap();
+            assert_eq!(line.run_id, RunId(1));
+            lines.push((line.number, line.text));
+        }
+        assert_eq!(
+            lines,
+            vec![
+                (1, "===== deploy =====".to_string()),
+                (2, "helm upgrade --install".to_string()),
+                (3, "deployed".to_string()),
+                (4, "done".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn polling_stops_when_client_disconnects() {
+        let db = tests_utils::init::test_db("test", "logs_stream_client_disconnects").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(PENDING_DEPLOYMENT_ID),
+            status: Set(DeploymentStatusType::Pending),
+            run_id: Set(Some(1)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), PENDING_DEPLOYMENT_ID)
+            .await
+            .unwrap();
+        let instance = deployment.get_instance(conn.as_ref()).await.unwrap();
+        let ci = Arc::new(ChunkedLogs {
+            db: conn.clone(),
+            chunks: Mutex::new(vec!["first\n"; 100]),
+            logs: Default::default(),
+        });
+
+        let mut receiver = stream_logs(
+            conn,
+            ci.clone(),
+            instance,
+            deployment,
+            Duration::from_millis(20),
+        );
+        assert_eq!(receiver.recv().await.unwrap().unwrap().text, "first");
+        drop(receiver);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let remaining = ci.chunks.lock().unwrap().len();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(ci.chunks.lock().unwrap().len(), remaining);
+        assert!(remaining > 0);
+    }
+}
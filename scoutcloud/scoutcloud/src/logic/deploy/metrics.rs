@@ -0,0 +1,30 @@
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+
+lazy_static! {
+    /// Failure rate of deployments over a window is the share of failed ones, e.g.
+    /// `sum(rate(scoutcloud_deployment_results_total{status="failed"}[1h]))
+    /// / sum(rate(scoutcloud_deployment_results_total[1h]))`
+    pub static ref DEPLOYMENT_RESULTS: IntCounterVec = register_int_counter_vec!(
+        "scoutcloud_deployment_results_total",
+        "number of deployments which became running, stopped or failed, \
+         failed ones are labeled with the code of their error",
+        &["status", "error_code"],
+    )
+    .unwrap();
+}
+
+/// Counts the deployment which entered the new status, only statuses
+/// which the failure rate is computed from are counted
+pub(super) fn observe_status_change(status: &DeploymentStatusType, error_code: Option<&str>) {
+    let (status, error_code) = match status {
+        DeploymentStatusType::Running => ("running", ""),
+        DeploymentStatusType::Stopped => ("stopped", ""),
+        DeploymentStatusType::Failed => ("failed", error_code.unwrap_or("unknown")),
+        _ => return,
+    };
+    DEPLOYMENT_RESULTS
+        .with_label_values(&[status, error_code])
+        .inc();
+}
@@ -1,16 +1,49 @@
-use crate::logic::{AuthError, ConfigError, GithubError};
+use crate::logic::{
+    config::join_errors,
+    github::types::{RunConclusion, RunStatus},
+    AuthError, ConfigError, GithubError,
+};
+use octocrab::models::RunId;
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
 use sea_orm::DbErr;
+use std::time::Duration;
 use thiserror::Error;
 
+mod config_version;
 mod deployment;
+mod deployments_page;
 mod handlers;
 mod instance;
 mod instance_deployment;
+mod instance_document;
+mod instances_page;
+mod labels;
+mod log_buffer;
+mod log_stream;
+pub(crate) mod metrics;
+mod status_history;
+mod status_machine;
+mod template;
+mod timeline;
+mod usage;
 
+pub use config_version::{ConfigFieldDiff, InstanceConfigVersion};
 pub use deployment::Deployment;
+pub use deployments_page::{
+    DeploymentsCursor, DeploymentsFilter, DeploymentsPage, DeploymentsTimeFilter,
+};
 pub use handlers::*;
 pub use instance::Instance;
 pub use instance_deployment::InstanceDeployment;
+pub use instance_document::{InstanceDocument, INSTANCE_DOCUMENT_VERSION};
+pub use instances_page::{InstancesCursor, InstancesPage};
+pub use labels::{validate_labels, LabelSelector, Labels};
+pub use log_buffer::follow_logs;
+pub use log_stream::{LogLine, DEFAULT_LOGS_POLL_INTERVAL};
+pub use status_history::{Initiator, StatusActor};
+pub use status_machine::StatusMachine;
+pub use template::Template;
+pub use usage::{DeploymentUsage, TimeRange};
 
 #[derive(Error, Debug)]
 pub enum DeployError {
@@ -19,19 +52,400 @@ pub enum DeployError {
     #[error("config error: {0}")]
     Config(#[from] ConfigError),
     #[error("github error: {0}")]
-    Github(#[from] GithubError),
+    Github(GithubError),
+    #[error("github api rate limit exceeded")]
+    GithubRateLimited,
+    #[error("github workflow run {run_id} failed. conclusion={conclusion:?}")]
+    WorkflowFailed {
+        run_id: RunId,
+        conclusion: RunConclusion,
+    },
+    #[error("github workflow run {run_id} was cancelled before it completed")]
+    WorkflowCancelled { run_id: RunId },
+    #[error("github workflow run {run_id} exceeded the time limit of github actions")]
+    WorkflowTimedOut { run_id: RunId },
+    #[error("github workflow run {run_id} failed to start, the workflow file is probably invalid")]
+    WorkflowStartupFailure { run_id: RunId },
+    #[error("timed out waiting for github workflow run {run_id}. status={status:?}")]
+    WorkflowTimeout { run_id: RunId, status: RunStatus },
+    #[error(
+        "github workflow run {run_id} was not picked up by any runner in {} seconds. \
+         Check that runners of the repo are online and not busy, then start the deployment again",
+        .queued_for.as_secs()
+    )]
+    NoRunnerAvailable { run_id: RunId, queued_for: Duration },
+    #[error(
+        "github workflow run {run_id} disappeared while waiting for it: it was deleted \
+         or the github app lost access to the repo. Check the repo and start the deployment again"
+    )]
+    RunDisappeared { run_id: RunId },
+    #[error(
+        "github actions minutes are running out: {remaining_minutes} minutes left, \
+         at least {min_remaining_minutes} are required to deploy. Try again in the next \
+         billing cycle or ask the administrator to raise the limit"
+    )]
+    ActionsBudgetExhausted {
+        remaining_minutes: u64,
+        min_remaining_minutes: u64,
+    },
+    #[error("app unhealthy: {0}")]
+    Unhealthy(String),
+    #[error("watchdog timeout: deployment was {status:?} for more than {} seconds", .timeout.as_secs())]
+    WatchdogTimeout {
+        status: DeploymentStatusType,
+        timeout: Duration,
+    },
+    #[error("deployment was cancelled by user")]
+    Cancelled,
+    #[error("status was forced by administrator: {0}")]
+    Forced(String),
     #[error("instance with name `{0}` already exists")]
     InstanceExists(String),
+    #[error("you already have an instance named `{0}`")]
+    DuplicateName(String),
     #[error("instance with id `{0}` not found")]
     InstanceNotFound(String),
+    #[error("you already have a config template named `{0}`")]
+    DuplicateTemplateName(String),
+    #[error("config template with id `{0}` not found")]
+    TemplateNotFound(String),
+    #[error("preset `{name}` not found, available presets: {}", .available.join(", "))]
+    PresetNotFound {
+        name: String,
+        available: Vec<String>,
+    },
     #[error("deployment not found")]
     DeploymentNotFound,
+    #[error("logs of deployment not found")]
+    DeploymentLogsNotFound,
+    #[error("invalid config: {}", join_errors(.0))]
+    InvalidConfig(Vec<ConfigError>),
     #[error("invalid action `{0}` for instance in state `{1}`")]
     InvalidStateTransition(String, String),
     #[error("invalid value: {0}")]
     InvalidValue(String),
+    #[error("idempotency key `{0}` is already used by another request")]
+    IdempotencyKeyConflict(String),
+    #[error("deployment was deleted more than {} seconds ago and can't be restored", .0.as_secs())]
+    RestorePeriodExpired(Duration),
+    #[error("deployment {0} was updated concurrently")]
+    Conflict(i32),
+    #[error("deployment can't change status from `{0:?}` to `{1:?}`")]
+    InvalidTransition(DeploymentStatusType, DeploymentStatusType),
+    #[error("interrupted by shutdown")]
+    Interrupted,
+    #[error("service is temporarily unavailable, retry in {0:?}")]
+    Unavailable(Duration),
+    #[error("too many deployments are waiting in the queue, retry later")]
+    Overloaded,
     #[error("db error: {0}")]
     Db(#[from] DbErr),
     #[error("internal error: {0}")]
     Internal(#[from] anyhow::Error),
 }
+
+impl DeployError {
+    /// Returns true if the failed operation may succeed when retried,
+    /// so the task should be retried instead of failing the deployment
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DeployError::Github(err) => err.is_retryable(),
+            DeployError::GithubRateLimited => true,
+            DeployError::Db(_) => true,
+            // the task is retried with the actual state of the deployment
+            DeployError::Conflict(_) => true,
+            DeployError::Unavailable(_) => true,
+            DeployError::Overloaded => true,
+            DeployError::Auth(_)
+            | DeployError::Config(_)
+            | DeployError::WorkflowFailed { .. }
+            | DeployError::WorkflowCancelled { .. }
+            | DeployError::WorkflowTimedOut { .. }
+            | DeployError::WorkflowStartupFailure { .. }
+            | DeployError::WorkflowTimeout { .. }
+            | DeployError::NoRunnerAvailable { .. }
+            | DeployError::RunDisappeared { .. }
+            | DeployError::ActionsBudgetExhausted { .. }
+            | DeployError::Unhealthy(_)
+            | DeployError::WatchdogTimeout { .. }
+            | DeployError::Cancelled
+            | DeployError::Forced(_)
+            | DeployError::InvalidConfig(_)
+            | DeployError::InstanceExists(_)
+            | DeployError::DuplicateName(_)
+            | DeployError::InstanceNotFound(_)
+            | DeployError::DuplicateTemplateName(_)
+            | DeployError::TemplateNotFound(_)
+            | DeployError::PresetNotFound { .. }
+            | DeployError::DeploymentNotFound
+            | DeployError::DeploymentLogsNotFound
+            | DeployError::InvalidStateTransition(_, _)
+            | DeployError::InvalidValue(_)
+            | DeployError::IdempotencyKeyConflict(_)
+            | DeployError::RestorePeriodExpired(_)
+            | DeployError::InvalidTransition(_, _)
+            | DeployError::Interrupted
+            | DeployError::Internal(_) => false,
+        }
+    }
+
+    /// Machine-readable kind of the error, stored as `error_code` of failed deployments.
+    /// Codes are part of the api, so existing ones should never be changed
+    pub fn code(&self) -> &'static str {
+        match self {
+            DeployError::Auth(_) => "auth",
+            DeployError::Config(_) | DeployError::InvalidConfig(_) => "config_invalid",
+            DeployError::Github(_) => "github",
+            DeployError::GithubRateLimited => "github_rate_limited",
+            DeployError::WorkflowFailed { .. } => "workflow_failed",
+            DeployError::WorkflowCancelled { .. } => "workflow_cancelled",
+            DeployError::WorkflowTimedOut { .. } => "workflow_timed_out",
+            DeployError::WorkflowStartupFailure { .. } => "workflow_startup_failure",
+            DeployError::WorkflowTimeout { .. } => "workflow_timeout",
+            DeployError::NoRunnerAvailable { .. } => "no_runner_available",
+            DeployError::RunDisappeared { .. } => "run_disappeared",
+            DeployError::ActionsBudgetExhausted { .. } => "actions_budget_exhausted",
+            DeployError::Unhealthy(_) => "unhealthy",
+            DeployError::WatchdogTimeout { .. } => "watchdog_timeout",
+            DeployError::Cancelled => "cancelled",
+            DeployError::Forced(_) => "forced",
+            DeployError::InstanceExists(_) => "instance_exists",
+            DeployError::DuplicateName(_) => "duplicate_name",
+            DeployError::InstanceNotFound(_) => "instance_not_found",
+            DeployError::DuplicateTemplateName(_) => "duplicate_template_name",
+            DeployError::TemplateNotFound(_) => "template_not_found",
+            DeployError::PresetNotFound { .. } => "preset_not_found",
+            DeployError::DeploymentNotFound => "deployment_not_found",
+            DeployError::DeploymentLogsNotFound => "deployment_logs_not_found",
+            DeployError::InvalidStateTransition(_, _) => "invalid_state_transition",
+            DeployError::InvalidValue(_) => "invalid_value",
+            DeployError::IdempotencyKeyConflict(_) => "idempotency_key_conflict",
+            DeployError::RestorePeriodExpired(_) => "restore_period_expired",
+            DeployError::Conflict(_) => "conflict",
+            DeployError::InvalidTransition(_, _) => "invalid_transition",
+            DeployError::Interrupted => "interrupted",
+            DeployError::Unavailable(_) => "unavailable",
+            DeployError::Overloaded => "overloaded",
+            DeployError::Db(_) => "db",
+            DeployError::Internal(_) => "internal",
+        }
+    }
+
+    /// Returns true if the task was stopped at a safe point because of shutdown,
+    /// so the deployment should be left as is and resumed after restart
+    pub fn is_interrupted(&self) -> bool {
+        matches!(
+            self,
+            DeployError::Interrupted | DeployError::Github(GithubError::Interrupted)
+        )
+    }
+
+    /// Returns true if github workflow run failed or timed out,
+    /// so its logs may explain what happened
+    pub fn is_workflow_failure(&self) -> bool {
+        self.failed_run_id().is_some()
+    }
+
+    /// Run which failed the workflow of the deployment. It is not always the own run
+    /// of the deployment, since the deployment may wait for several runs
+    pub fn failed_run_id(&self) -> Option<RunId> {
+        match self {
+            DeployError::WorkflowFailed { run_id, .. }
+            | DeployError::WorkflowCancelled { run_id }
+            | DeployError::WorkflowTimedOut { run_id }
+            | DeployError::WorkflowStartupFailure { run_id }
+            | DeployError::WorkflowTimeout { run_id, .. } => Some(*run_id),
+            _ => None,
+        }
+    }
+
+    /// Failures of the deploy workflow which may pass if it is dispatched again.
+    /// Transient errors are retried by the task itself, so they are not included.
+    /// Cancelled run was stopped on purpose and invalid workflow fails again,
+    /// so they are not retried either
+    pub fn is_auto_retryable(&self) -> bool {
+        let is_final = matches!(
+            self,
+            DeployError::WorkflowCancelled { .. } | DeployError::WorkflowStartupFailure { .. }
+        );
+        (self.is_workflow_failure() && !is_final)
+            || matches!(self, DeployError::NoRunnerAvailable { .. })
+    }
+
+    /// Error of the completed run with unsuccessful `conclusion`. Conclusions
+    /// which users handle differently get their own variants
+    pub fn from_conclusion(run_id: RunId, conclusion: RunConclusion) -> Self {
+        match conclusion {
+            RunConclusion::Cancelled => DeployError::WorkflowCancelled { run_id },
+            RunConclusion::TimedOut => DeployError::WorkflowTimedOut { run_id },
+            RunConclusion::StartupFailure => DeployError::WorkflowStartupFailure { run_id },
+            conclusion => DeployError::WorkflowFailed { run_id, conclusion },
+        }
+    }
+}
+
+impl From<GithubError> for DeployError {
+    /// Failures which callers handle differently get their own variants,
+    /// other github errors are kept as is
+    fn from(err: GithubError) -> Self {
+        if let Some(remaining) = err.circuit_open_for() {
+            return DeployError::Unavailable(remaining);
+        }
+        if err.is_rate_limited() {
+            return DeployError::GithubRateLimited;
+        }
+        match err {
+            GithubError::WorkflowFailed { run_id, conclusion } => {
+                DeployError::from_conclusion(run_id, conclusion)
+            }
+            GithubError::WorkflowTimeout { run_id, status } => {
+                DeployError::WorkflowTimeout { run_id, status }
+            }
+            GithubError::NoRunnerAvailable { run_id, queued_for } => {
+                DeployError::NoRunnerAvailable { run_id, queued_for }
+            }
+            GithubError::RunDisappeared(run_id) => DeployError::RunDisappeared { run_id },
+            GithubError::ActionsBudgetExhausted {
+                remaining_minutes,
+                min_remaining_minutes,
+                ..
+            } => DeployError::ActionsBudgetExhausted {
+                remaining_minutes,
+                min_remaining_minutes,
+            },
+            // target in the config of the instance is rejected like other invalid values
+            err @ (GithubError::UnknownDeploymentTarget(_)
+            | GithubError::RepoNotAllowed(_)
+            | GithubError::GitRefNotFound(_)) => DeployError::InvalidValue(err.to_string()),
+            err => DeployError::Github(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn github_errors_are_mapped_to_typed_variants() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let mut handles = mock.build_handles();
+        mock.override_rate_limited(&mut handles, "single_run_cleanup_yaml", 0);
+        let err: DeployError = client
+            .get_workflow_run(RunId(8819501307))
+            .await
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, DeployError::GithubRateLimited), "{err:?}");
+        assert_eq!(err.code(), "github_rate_limited");
+        assert!(err.is_retryable());
+
+        let run_id = RunId(1);
+        let err: DeployError = GithubError::WorkflowFailed {
+            run_id,
+            conclusion: RunConclusion::Failure,
+        }
+        .into();
+        assert!(
+            matches!(
+                err,
+                DeployError::WorkflowFailed {
+                    run_id: RunId(1),
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        assert_eq!(err.code(), "workflow_failed");
+        assert!(err.is_workflow_failure() && !err.is_retryable());
+
+        let err: DeployError = GithubError::WorkflowTimeout {
+            run_id,
+            status: RunStatus::Queued,
+        }
+        .into();
+        assert!(
+            matches!(err, DeployError::WorkflowTimeout { .. }),
+            "{err:?}"
+        );
+        assert_eq!(err.code(), "workflow_timeout");
+        assert!(err.is_workflow_failure() && !err.is_retryable());
+
+        let err: DeployError = GithubError::NoRunnerAvailable {
+            run_id,
+            queued_for: Duration::from_secs(600),
+        }
+        .into();
+        assert!(
+            matches!(err, DeployError::NoRunnerAvailable { .. }),
+            "{err:?}"
+        );
+        assert_eq!(err.code(), "no_runner_available");
+        assert!(!err.is_workflow_failure() && !err.is_retryable());
+        assert!(err.to_string().contains("600 seconds"), "{err}");
+
+        let err: DeployError = GithubError::CircuitOpen(Duration::from_secs(5)).into();
+        assert!(matches!(err, DeployError::Unavailable(_)), "{err:?}");
+        assert_eq!(err.code(), "unavailable");
+
+        let err: DeployError = GithubError::Interrupted.into();
+        assert!(err.is_interrupted(), "{err:?}");
+        assert_eq!(err.code(), "github");
+    }
+
+    #[test]
+    fn run_conclusions_are_mapped_to_errors() {
+        let run_id = RunId(7);
+        for (conclusion, code, message, auto_retryable) in [
+            (
+                "failure",
+                "workflow_failed",
+                "github workflow run 7 failed. conclusion=Failure",
+                true,
+            ),
+            (
+                "action_required",
+                "workflow_failed",
+                "github workflow run 7 failed. conclusion=ActionRequired",
+                true,
+            ),
+            (
+                "cancelled",
+                "workflow_cancelled",
+                "github workflow run 7 was cancelled before it completed",
+                false,
+            ),
+            (
+                "timed_out",
+                "workflow_timed_out",
+                "github workflow run 7 exceeded the time limit of github actions",
+                true,
+            ),
+            (
+                "startup_failure",
+                "workflow_startup_failure",
+                "github workflow run 7 failed to start, the workflow file is probably invalid",
+                false,
+            ),
+        ] {
+            let conclusion = RunConclusion::try_from_str(conclusion).unwrap();
+            let err: DeployError = GithubError::WorkflowFailed {
+                run_id,
+                conclusion: conclusion.clone(),
+            }
+            .into();
+            assert_eq!(err.code(), code, "{conclusion:?}");
+            assert_eq!(err.to_string(), message);
+            assert_eq!(err.failed_run_id(), Some(run_id));
+            assert!(err.is_workflow_failure() && !err.is_retryable(), "{err:?}");
+            assert_eq!(err.is_auto_retryable(), auto_retryable, "{err:?}");
+            // errors built from webhooks are the same
+            assert_eq!(
+                DeployError::from_conclusion(run_id, conclusion).to_string(),
+                message
+            );
+        }
+    }
+}
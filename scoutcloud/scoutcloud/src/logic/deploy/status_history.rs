@@ -0,0 +1,193 @@
+use super::deployment::Deployment;
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, ConnectionTrait, QueryOrder};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Who changed the status of a deployment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusActor {
+    User(i32),
+    Task(&'static str),
+    Webhook,
+    System,
+}
+
+impl fmt::Display for StatusActor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusActor::User(id) => write!(f, "user:{id}"),
+            StatusActor::Task(name) => write!(f, "task:{name}"),
+            StatusActor::Webhook => write!(f, "webhook"),
+            StatusActor::System => write!(f, "system"),
+        }
+    }
+}
+
+/// Who requested the action performed by a task, while `StatusActor` is the one
+/// performing it. Stored on the deployment and recorded in the history of its status
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Initiator {
+    User(i32),
+    /// Actions of the service itself, e.g. stop of expired deployments
+    System {
+        reason: String,
+    },
+}
+
+impl Initiator {
+    pub fn system(reason: impl Into<String>) -> Self {
+        Initiator::System {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Initiator::User(_) => None,
+            Initiator::System { reason } => Some(reason),
+        }
+    }
+}
+
+impl fmt::Display for Initiator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Initiator::User(id) => write!(f, "user:{id}"),
+            Initiator::System { .. } => write!(f, "system"),
+        }
+    }
+}
+
+impl Deployment {
+    /// Status changes of the deployment from the oldest to the newest
+    pub async fn status_history<C>(
+        &self,
+        db: &C,
+    ) -> Result<Vec<db::deployment_status_history::Model>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        db::deployment_status_history::Entity::find()
+            .filter(db::deployment_status_history::Column::DeploymentId.eq(self.model.id))
+            .order_by_asc(db::deployment_status_history::Column::CreatedAt)
+            .order_by_asc(db::deployment_status_history::Column::Id)
+            .all(db)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+
+    #[test]
+    fn actors_are_formatted() {
+        assert_eq!(StatusActor::User(7).to_string(), "user:7");
+        assert_eq!(StatusActor::Task("starting").to_string(), "task:starting");
+        assert_eq!(StatusActor::Webhook.to_string(), "webhook");
+        assert_eq!(StatusActor::System.to_string(), "system");
+        assert_eq!(Initiator::User(7).to_string(), "user:7");
+        assert_eq!(
+            Initiator::system("deployment is expired").to_string(),
+            "system"
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn history_records_full_start_stop_cycle() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("history_records_full_start_stop_cycle").await;
+        let conn = db.client();
+
+        let not_started_deployment_id = 4;
+        let deployment = Deployment::get(conn.as_ref(), not_started_deployment_id)
+            .await
+            .unwrap();
+        runner
+            .insert_starting_task(&deployment, Initiator::User(1))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        runner
+            .insert_stopping_task(&deployment, Initiator::User(2))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), not_started_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        let history: Vec<_> = deployment
+            .status_history(conn.as_ref())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.old_status, change.new_status, change.actor))
+            .collect();
+        use DeploymentStatusType::*;
+        assert_eq!(
+            history,
+            vec![
+                (Some(Created), Pending, "task:starting".to_string()),
+                (Some(Pending), Running, "task:starting".to_string()),
+                (Some(Running), Stopping, "task:stopping".to_string()),
+                (Some(Stopping), Stopped, "task:stopping".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn user_initiated_stop_records_the_user() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("user_initiated_stop_records_the_user").await;
+        let conn = db.client();
+
+        let running_deployment_id = 1;
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        runner
+            .insert_stopping_task(&deployment, Initiator::User(7))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Stopped);
+        assert_eq!(deployment.model.stopped_by.as_deref(), Some("user:7"));
+        assert_eq!(deployment.model.stop_reason, None);
+        let history: Vec<_> = deployment
+            .status_history(conn.as_ref())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.actor, change.initiator, change.reason))
+            .collect();
+        let by_user = (
+            "task:stopping".to_string(),
+            Some("user:7".to_string()),
+            None,
+        );
+        assert_eq!(history, vec![by_user.clone(), by_user]);
+    }
+}
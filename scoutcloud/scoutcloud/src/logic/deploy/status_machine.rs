@@ -0,0 +1,85 @@
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+
+/// Lifecycle of a deployment. `DeploymentStatusType` is generated from the db schema,
+/// so the valid edges are implemented as an extension trait
+pub trait StatusMachine {
+    /// Statuses the deployment can move to from the current one
+    fn next_statuses(&self) -> &'static [DeploymentStatusType];
+
+    /// Saving the deployment with unchanged status is always allowed
+    fn can_transition_to(&self, next: &DeploymentStatusType) -> bool;
+}
+
+impl StatusMachine for DeploymentStatusType {
+    /// Failed deployment can only be retried in place, otherwise a new deployment is created
+    /// to start the instance again. Workflow which failed to be dispatched reverts
+    /// the deployment to its previous status. Scheduled deployment becomes created
+    /// once its time comes, or fails if it is cancelled before that
+    fn next_statuses(&self) -> &'static [DeploymentStatusType] {
+        use DeploymentStatusType::*;
+        match self {
+            Created => &[Pending, Failed],
+            Pending => &[Running, Failed, Created, Stopped],
+            Running => &[Stopping, Failed],
+            Stopping => &[Stopped, Failed, Running],
+            Stopped => &[Pending, Failed],
+            Failed => &[Created],
+            Scheduled => &[Created, Failed],
+        }
+    }
+
+    fn can_transition_to(&self, next: &DeploymentStatusType) -> bool {
+        self == next || self.next_statuses().contains(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use sea_orm::Iterable;
+
+    #[test]
+    fn transition_matrix_is_exhaustive() {
+        use DeploymentStatusType::*;
+        // rows are current statuses, columns are next statuses
+        let columns = [
+            Created, Pending, Running, Stopping, Stopped, Failed, Scheduled,
+        ];
+        let matrix = [
+            (Created, [true, true, false, false, false, true, false]),
+            (Pending, [true, true, true, false, true, true, false]),
+            (Running, [false, false, true, true, false, true, false]),
+            (Stopping, [false, false, true, true, true, true, false]),
+            (Stopped, [false, true, false, false, true, true, false]),
+            (Failed, [true, false, false, false, false, true, false]),
+            (Scheduled, [true, false, false, false, false, true, true]),
+        ];
+        assert_eq!(
+            DeploymentStatusType::iter().count(),
+            columns.len(),
+            "new status must be added to the matrix"
+        );
+
+        for (from, row) in matrix {
+            for (to, expected) in columns.iter().zip(row) {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    expected,
+                    "{from:?} -> {to:?} should be {}",
+                    if expected { "allowed" } else { "rejected" }
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn next_statuses_are_distinct_from_current() {
+        for status in DeploymentStatusType::iter() {
+            assert!(
+                !status.next_statuses().contains(&status),
+                "{status:?} lists itself as next status"
+            );
+        }
+    }
+}
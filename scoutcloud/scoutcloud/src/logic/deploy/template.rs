@@ -0,0 +1,145 @@
+use crate::{
+    logic::{
+        secrets::{self, USER_CONFIG_SECRET_FIELDS},
+        ConfigError, ConfigTemplate, DeployError, UserToken,
+    },
+    server::proto,
+    uuid_eq,
+};
+use anyhow::Context;
+use scoutcloud_entity as db;
+use sea_orm::{
+    prelude::*, ActiveModelTrait, ActiveValue::Set, IntoActiveModel, QueryOrder, QuerySelect,
+    SqlErr,
+};
+
+const MAX_LIMIT: u64 = 50;
+const CREATOR_NAME_INDEX: &str = "config_templates_creator_id_name_key";
+
+/// Config template stored for the user, see `ConfigTemplate`
+#[derive(Clone)]
+pub struct Template {
+    pub model: db::config_templates::Model,
+}
+
+impl Template {
+    pub fn new(model: db::config_templates::Model) -> Self {
+        Template { model }
+    }
+
+    pub async fn find_by_uuid<C>(db: &C, uuid: &str) -> Result<Option<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let this = db::config_templates::Entity::find()
+            .filter(uuid_eq!(db::config_templates::Column::ExternalId, uuid))
+            .one(db)
+            .await?
+            .map(Self::new);
+        Ok(this)
+    }
+
+    pub async fn find_all<C>(db: &C, user_token: &UserToken) -> Result<Vec<Self>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let templates = user_token
+            .user
+            .find_related(db::config_templates::Entity)
+            .order_by_desc(db::config_templates::Column::CreatedAt)
+            .limit(MAX_LIMIT)
+            .all(db)
+            .await?
+            .into_iter()
+            .map(Self::new)
+            .collect();
+        Ok(templates)
+    }
+
+    pub async fn get<C>(db: &C, id: i32) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let model = db::config_templates::Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or(DbErr::Custom("no config template found".into()))?;
+        Ok(Self::new(model))
+    }
+
+    pub async fn try_create<C>(
+        db: &C,
+        name: &str,
+        config: &ConfigTemplate,
+        creator: &UserToken,
+    ) -> Result<Self, DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(DeployError::InvalidValue("name is empty".to_string()));
+        }
+        let model = db::config_templates::ActiveModel {
+            creator_id: Set(creator.user.id),
+            name: Set(name.to_string()),
+            config: Set(encrypt_config(config)?),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .map_err(|err| match err.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(message))
+                if message.contains(CREATOR_NAME_INDEX) =>
+            {
+                DeployError::DuplicateTemplateName(name.to_string())
+            }
+            _ => DeployError::Db(err),
+        })?;
+        Ok(Self::new(model))
+    }
+
+    /// Instances which reference the template pick up the new config when they are started
+    pub async fn update_config<C>(
+        &mut self,
+        db: &C,
+        config: &ConfigTemplate,
+    ) -> Result<(), DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let mut active = self.model.clone().into_active_model();
+        active.config = Set(encrypt_config(config)?);
+        active.updated_at = Set(chrono::Utc::now().fixed_offset());
+        self.model = active.update(db).await?;
+        Ok(())
+    }
+
+    pub fn config(&self) -> Result<ConfigTemplate, ConfigError> {
+        let mut raw = self.model.config.clone();
+        secrets::decrypt_fields(&mut raw, USER_CONFIG_SECRET_FIELDS)?;
+        ConfigTemplate::new(raw)
+    }
+}
+
+impl TryFrom<Template> for proto::ConfigTemplateInternal {
+    type Error = DeployError;
+
+    fn try_from(template: Template) -> Result<Self, Self::Error> {
+        let config = template.config()?;
+        Ok(proto::ConfigTemplateInternal {
+            template_id: template.model.external_id.to_string(),
+            name: template.model.name.clone(),
+            config: serde_json::to_string(config.config()).context("serializing template")?,
+            variables: config.variables().into_iter().collect(),
+            created_at: template.model.created_at.to_string(),
+            updated_at: template.model.updated_at.to_string(),
+        })
+    }
+}
+
+fn encrypt_config(config: &ConfigTemplate) -> Result<serde_json::Value, ConfigError> {
+    let mut raw = config.config().clone();
+    secrets::encrypt_fields(&mut raw, USER_CONFIG_SECRET_FIELDS)?;
+    Ok(raw)
+}
@@ -0,0 +1,236 @@
+use super::deployment::Deployment;
+use chrono::{DateTime, Utc};
+use db::sea_orm_active_enums::DeploymentStatusType;
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, ConnectionTrait, QueryOrder, QuerySelect};
+use std::{collections::HashMap, time::Duration};
+
+/// Number of the latest successful deploys the estimation is based on
+const RECENT_DEPLOYS: u64 = 20;
+
+impl Deployment {
+    /// Rough estimation of the time when the pending deployment becomes running,
+    /// based on the median duration of recent successful deploys of the same server spec.
+    /// Deploys which were faster than the time the deployment is already pending for
+    /// are ignored, so the estimation moves forward while the workflow is running.
+    /// Returns `None` if there is no such history, since any guess would be misleading
+    pub async fn estimated_completion_at<C>(
+        &self,
+        db: &C,
+        history: &[db::deployment_status_history::Model],
+    ) -> Result<Option<DateTime<Utc>>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if self.model.status != DeploymentStatusType::Pending {
+            return Ok(None);
+        }
+        let Some(pending_since) = history
+            .iter()
+            .rev()
+            .find(|change| change.new_status == DeploymentStatusType::Pending)
+            .map(|change| change.created_at.with_timezone(&Utc))
+        else {
+            return Ok(None);
+        };
+        let durations =
+            recent_deploy_durations(db, self.model.server_spec_id, self.model.id).await?;
+        let now = Utc::now();
+        let elapsed = (now - pending_since).to_std().unwrap_or_default();
+        let estimation = estimate_remaining(durations, elapsed)
+            .and_then(|remaining| chrono::Duration::from_std(remaining).ok())
+            .map(|remaining| now + remaining);
+        Ok(estimation)
+    }
+}
+
+/// Time from `Pending` to `Running` of the latest successful deploys of the server spec,
+/// except deploys of `exclude_deployment_id`
+async fn recent_deploy_durations<C>(
+    db: &C,
+    server_spec_id: i32,
+    exclude_deployment_id: i32,
+) -> Result<Vec<Duration>, DbErr>
+where
+    C: ConnectionTrait,
+{
+    let finished = db::deployment_status_history::Entity::find()
+        .inner_join(db::deployments::Entity)
+        .filter(db::deployments::Column::ServerSpecId.eq(server_spec_id))
+        .filter(db::deployments::Column::Id.ne(exclude_deployment_id))
+        .filter(db::deployment_status_history::Column::OldStatus.eq(DeploymentStatusType::Pending))
+        .filter(db::deployment_status_history::Column::NewStatus.eq(DeploymentStatusType::Running))
+        .order_by_desc(db::deployment_status_history::Column::CreatedAt)
+        .limit(RECENT_DEPLOYS)
+        .all(db)
+        .await?;
+    let mut started: HashMap<i32, Vec<DateTimeWithTimeZone>> = HashMap::new();
+    for change in db::deployment_status_history::Entity::find()
+        .filter(
+            db::deployment_status_history::Column::DeploymentId
+                .is_in(finished.iter().map(|change| change.deployment_id)),
+        )
+        .filter(db::deployment_status_history::Column::NewStatus.eq(DeploymentStatusType::Pending))
+        .all(db)
+        .await?
+    {
+        started
+            .entry(change.deployment_id)
+            .or_default()
+            .push(change.created_at);
+    }
+    let durations = finished
+        .iter()
+        .filter_map(|change| {
+            // the latest start before the deployment became running
+            let start = started
+                .get(&change.deployment_id)?
+                .iter()
+                .filter(|start| **start <= change.created_at)
+                .max()?;
+            (change.created_at - *start).to_std().ok()
+        })
+        .collect();
+    Ok(durations)
+}
+
+/// Median of `durations` which are longer than `elapsed`, minus `elapsed`
+fn estimate_remaining(mut durations: Vec<Duration>, elapsed: Duration) -> Option<Duration> {
+    durations.retain(|duration| *duration > elapsed);
+    if durations.is_empty() {
+        return None;
+    }
+    durations.sort();
+    let middle = durations.len() / 2;
+    let median = if durations.len() % 2 == 0 {
+        (durations[middle - 1] + durations[middle]) / 2
+    } else {
+        durations[middle]
+    };
+    Some(median - elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn remaining_time_is_median_of_longer_deploys() {
+        let durations = vec![minutes(4), minutes(10), minutes(6), minutes(8)];
+        assert_eq!(
+            estimate_remaining(durations.clone(), minutes(0)),
+            Some(minutes(7))
+        );
+        assert_eq!(
+            estimate_remaining(durations.clone(), minutes(5)),
+            Some(minutes(3))
+        );
+        assert_eq!(
+            estimate_remaining(durations.clone(), minutes(9)),
+            Some(minutes(1))
+        );
+        assert_eq!(estimate_remaining(durations, minutes(10)), None);
+        assert_eq!(estimate_remaining(vec![], minutes(0)), None);
+    }
+
+    async fn insert_change(
+        db: &DatabaseConnection,
+        deployment_id: i32,
+        old_status: Option<DeploymentStatusType>,
+        new_status: DeploymentStatusType,
+        at: DateTime<Utc>,
+    ) {
+        db::deployment_status_history::ActiveModel {
+            deployment_id: Set(deployment_id),
+            old_status: Set(old_status),
+            new_status: Set(new_status),
+            actor: Set("system".to_string()),
+            created_at: Set(at.fixed_offset()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn completion_is_estimated_from_recent_deploys() {
+        let db =
+            tests_utils::init::test_db("test", "completion_is_estimated_from_recent_deploys").await;
+        let conn = db.client();
+        let pending_id = 4;
+        db::deployments::ActiveModel {
+            id: Set(pending_id),
+            status: Set(DeploymentStatusType::Pending),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), pending_id).await.unwrap();
+        let now = Utc::now();
+        insert_change(
+            conn.as_ref(),
+            pending_id,
+            Some(DeploymentStatusType::Created),
+            DeploymentStatusType::Pending,
+            now - chrono::Duration::minutes(2),
+        )
+        .await;
+        let history = deployment.status_history(conn.as_ref()).await.unwrap();
+
+        let estimation = deployment
+            .estimated_completion_at(conn.as_ref(), &history)
+            .await
+            .unwrap();
+        assert_eq!(estimation, None, "estimated without history");
+
+        // other deployments of the same server spec were deployed in 8, 10 and 12 minutes
+        for (deployment_id, minutes) in [(1, 8), (2, 10), (3, 12)] {
+            db::deployments::ActiveModel {
+                id: Set(deployment_id),
+                server_spec_id: Set(deployment.model.server_spec_id),
+                ..Default::default()
+            }
+            .update(conn.as_ref())
+            .await
+            .unwrap();
+            let started_at = now - chrono::Duration::days(1);
+            insert_change(
+                conn.as_ref(),
+                deployment_id,
+                Some(DeploymentStatusType::Created),
+                DeploymentStatusType::Pending,
+                started_at,
+            )
+            .await;
+            insert_change(
+                conn.as_ref(),
+                deployment_id,
+                Some(DeploymentStatusType::Pending),
+                DeploymentStatusType::Running,
+                started_at + chrono::Duration::minutes(minutes),
+            )
+            .await;
+        }
+
+        let estimation = deployment
+            .estimated_completion_at(conn.as_ref(), &history)
+            .await
+            .unwrap()
+            .expect("completion should be estimated");
+        // pending for 2 minutes, so around 8 minutes are left
+        let remaining = (estimation - Utc::now()).num_seconds();
+        assert!(
+            (7 * 60..=8 * 60).contains(&remaining),
+            "unexpected remaining time: {remaining} seconds"
+        );
+    }
+}
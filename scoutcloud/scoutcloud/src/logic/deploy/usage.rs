@@ -0,0 +1,309 @@
+use super::deployment::Deployment;
+use crate::logic::DeployError;
+use chrono::{DateTime, Duration, Utc};
+use db::sea_orm_active_enums::DeploymentStatusType;
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, QueryOrder};
+use std::collections::HashMap;
+
+/// Half-open range `[from, to)`. Boundaries are compared as absolute instants,
+/// so the offset of the caller and daylight saving time changes within the range
+/// don't affect the result: a day with DST switch has 23 or 25 hours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl TimeRange {
+    /// Timestamps should be in RFC 3339 format, which always contains the offset
+    pub fn parse(from: &str, to: &str) -> Result<Self, DeployError> {
+        let parse = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|err| DeployError::InvalidValue(format!("invalid time '{value}': {err}")))
+        };
+        let range = Self {
+            from: parse(from)?,
+            to: parse(to)?,
+        };
+        if range.from >= range.to {
+            return Err(DeployError::InvalidValue(format!(
+                "time range is empty: '{from}' is not earlier than '{to}'"
+            )));
+        }
+        Ok(range)
+    }
+
+    fn overlap(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Duration {
+        let start = start.max(self.from);
+        let end = end.min(self.to);
+        if end > start {
+            end - start
+        } else {
+            Duration::zero()
+        }
+    }
+}
+
+/// Period of time when the deployment was running, `end` is not set while it is still running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunningPeriod {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+pub struct DeploymentUsage {
+    pub deployment: Deployment,
+    pub instance: db::instances::Model,
+    pub running_time: Duration,
+}
+
+impl DeploymentUsage {
+    pub fn instance_hours(&self) -> f64 {
+        duration_to_hours(self.running_time)
+    }
+}
+
+fn duration_to_hours(duration: Duration) -> f64 {
+    duration.num_milliseconds() as f64 / Duration::hours(1).num_milliseconds() as f64
+}
+
+impl Deployment {
+    /// Running time of deployments of the user within the range.
+    /// Deployments which didn't run within the range are skipped
+    pub async fn usage_of_user<C>(
+        db: &C,
+        user_id: i32,
+        range: &TimeRange,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<DeploymentUsage>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let rows = db::deployments::Entity::find()
+            .find_also_related(db::instances::Entity)
+            .filter(db::instances::Column::CreatorId.eq(user_id))
+            .filter(db::deployments::Column::StartedAt.lt(range.to))
+            .order_by_asc(db::deployments::Column::Id)
+            .all(db)
+            .await?;
+        let ids: Vec<i32> = rows.iter().map(|(deployment, _)| deployment.id).collect();
+        let mut histories: HashMap<i32, Vec<_>> = HashMap::new();
+        for change in db::deployment_status_history::Entity::find()
+            .filter(db::deployment_status_history::Column::DeploymentId.is_in(ids))
+            .order_by_asc(db::deployment_status_history::Column::CreatedAt)
+            .order_by_asc(db::deployment_status_history::Column::Id)
+            .all(db)
+            .await?
+        {
+            histories
+                .entry(change.deployment_id)
+                .or_default()
+                .push(change);
+        }
+
+        let mut usage = vec![];
+        for (deployment, instance) in rows {
+            let instance = instance.ok_or(DbErr::RecordNotFound(format!(
+                "instance of deployment {}",
+                deployment.id
+            )))?;
+            let deployment = Deployment::new(deployment);
+            let periods = match histories.get(&deployment.model.id) {
+                Some(history) => running_periods(history),
+                None => deployment.legacy_running_period().into_iter().collect(),
+            };
+            let running_time = periods
+                .iter()
+                .map(|period| range.overlap(period.start, period.end.unwrap_or(now)))
+                .fold(Duration::zero(), |total, time| total + time);
+            if running_time > Duration::zero() {
+                usage.push(DeploymentUsage {
+                    deployment,
+                    instance,
+                    running_time,
+                });
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Deployments created before status history was recorded have only start and finish time
+    fn legacy_running_period(&self) -> Option<RunningPeriod> {
+        let start = self.model.started_at?.with_timezone(&Utc);
+        let end = match self.model.status {
+            DeploymentStatusType::Running | DeploymentStatusType::Stopping => None,
+            _ => Some(
+                self.model
+                    .finished_at
+                    .unwrap_or(self.model.updated_at)
+                    .with_timezone(&Utc),
+            ),
+        };
+        Some(RunningPeriod { start, end })
+    }
+}
+
+/// Deployment is running from the transition into `Running` until it is `Stopped` or `Failed`,
+/// so the time spent on stopping is billed as well.
+/// History should be ordered from the oldest change to the newest
+pub fn running_periods(history: &[db::deployment_status_history::Model]) -> Vec<RunningPeriod> {
+    let mut periods = vec![];
+    let mut current: Option<RunningPeriod> = None;
+    for change in history {
+        let time = change.created_at.with_timezone(&Utc);
+        match change.new_status {
+            DeploymentStatusType::Running if current.is_none() => {
+                current = Some(RunningPeriod {
+                    start: time,
+                    end: None,
+                })
+            }
+            DeploymentStatusType::Stopped | DeploymentStatusType::Failed => {
+                if let Some(mut period) = current.take() {
+                    period.end = Some(time);
+                    periods.push(period);
+                }
+            }
+            _ => {}
+        }
+    }
+    periods.extend(current);
+    periods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+
+    fn time(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    async fn record_change(
+        db: &DatabaseConnection,
+        deployment_id: i32,
+        new_status: DeploymentStatusType,
+        at: &str,
+    ) {
+        db::deployment_status_history::ActiveModel {
+            deployment_id: Set(deployment_id),
+            new_status: Set(new_status),
+            actor: Set("system".to_string()),
+            created_at: Set(DateTime::parse_from_rfc3339(at).unwrap()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    /// History of default deployments is empty, their start time should match the new history
+    async fn set_started_at(db: &DatabaseConnection, deployment_id: i32, at: &str) {
+        db::deployments::ActiveModel {
+            id: Set(deployment_id),
+            started_at: Set(Some(DateTime::parse_from_rfc3339(at).unwrap())),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .unwrap();
+    }
+
+    fn hours(usage: &[DeploymentUsage]) -> Vec<(i32, f64)> {
+        usage
+            .iter()
+            .map(|usage| (usage.deployment.model.id, usage.instance_hours()))
+            .collect()
+    }
+
+    #[test]
+    fn time_range_is_validated() {
+        let range =
+            TimeRange::parse("2024-03-31T00:00:00+01:00", "2024-04-01T00:00:00+02:00").unwrap();
+        // clocks go forward in Europe on 31 march
+        assert_eq!(range.to - range.from, Duration::hours(23));
+        assert!(TimeRange::parse("2024-03-31T00:00:00", "2024-04-01T00:00:00Z").is_err());
+        assert!(TimeRange::parse("2024-04-01T00:00:00Z", "2024-04-01T02:00:00+02:00").is_err());
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn usage_of_completed_deployment() {
+        let db = tests_utils::init::test_db("test", "usage_of_completed_deployment").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let user_id = 2;
+        let stopped_deployment_id = 2;
+        use DeploymentStatusType::*;
+        for (status, at) in [
+            (Pending, "2024-03-30T22:00:00Z"),
+            (Running, "2024-03-30T22:30:00Z"),
+            (Stopping, "2024-03-31T03:00:00Z"),
+            (Stopped, "2024-03-31T03:30:00Z"),
+        ] {
+            record_change(&conn, stopped_deployment_id, status, at).await;
+        }
+        set_started_at(&conn, stopped_deployment_id, "2024-03-30T22:30:00Z").await;
+        let now = time("2024-04-02T00:00:00Z");
+
+        let range = TimeRange::parse("2024-03-30T00:00:00Z", "2024-04-01T00:00:00Z").unwrap();
+        let usage = Deployment::usage_of_user(conn.as_ref(), user_id, &range, now)
+            .await
+            .unwrap();
+        // stopping time is included, deployment#3 of the user was started after the range
+        assert_eq!(hours(&usage), vec![(stopped_deployment_id, 5.0)]);
+
+        // local midnight of the DST switch day in Europe/Berlin is 23:00 UTC
+        let range =
+            TimeRange::parse("2024-03-31T00:00:00+01:00", "2024-03-31T04:00:00+02:00").unwrap();
+        let usage = Deployment::usage_of_user(conn.as_ref(), user_id, &range, now)
+            .await
+            .unwrap();
+        assert_eq!(hours(&usage), vec![(stopped_deployment_id, 3.0)]);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn usage_of_running_deployment_counts_until_now() {
+        let db = tests_utils::init::test_db("test", "usage_of_running_deployment_counts_until_now")
+            .await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let user_id = 1;
+        let running_deployment_id = 1;
+        use DeploymentStatusType::*;
+        for (status, at) in [
+            (Running, "2024-05-01T10:00:00Z"),
+            (Stopping, "2024-05-01T11:00:00Z"),
+            // stop failed, deployment is still running
+            (Running, "2024-05-01T11:15:00Z"),
+        ] {
+            record_change(&conn, running_deployment_id, status, at).await;
+        }
+        set_started_at(&conn, running_deployment_id, "2024-05-01T10:00:00Z").await;
+        let now = time("2024-05-01T14:00:00Z");
+
+        let range = TimeRange::parse("2024-05-01T00:00:00Z", "2024-05-02T00:00:00Z").unwrap();
+        let usage = Deployment::usage_of_user(conn.as_ref(), user_id, &range, now)
+            .await
+            .unwrap();
+        assert_eq!(hours(&usage), vec![(running_deployment_id, 4.0)]);
+
+        let range = TimeRange::parse("2024-05-01T13:30:00Z", "2024-05-02T00:00:00Z").unwrap();
+        let usage = Deployment::usage_of_user(conn.as_ref(), user_id, &range, now)
+            .await
+            .unwrap();
+        assert_eq!(hours(&usage), vec![(running_deployment_id, 0.5)]);
+    }
+}
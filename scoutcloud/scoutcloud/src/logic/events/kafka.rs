@@ -0,0 +1,66 @@
+use super::{DeploymentEvent, EventProducer, EventsError};
+use crate::server::EventsSettings;
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig,
+};
+use std::fmt;
+
+pub struct KafkaProducer {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaProducer {
+    /// Doesn't connect to brokers, so it succeeds even if they are unavailable
+    pub fn new(settings: &EventsSettings) -> Result<Self, EventsError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &settings.brokers)
+            .set(
+                "message.timeout.ms",
+                settings.delivery_timeout.as_millis().to_string(),
+            )
+            // retries of the client should not reorder or duplicate events
+            .set("enable.idempotence", "true")
+            .create()?;
+        Ok(Self {
+            producer,
+            topic: settings.topic.clone(),
+        })
+    }
+}
+
+impl fmt::Debug for KafkaProducer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaProducer")
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait::async_trait]
+impl EventProducer for KafkaProducer {
+    async fn send(&self, events: &[DeploymentEvent]) -> Result<(), EventsError> {
+        // all events are enqueued before waiting for deliveries, so the client batches them.
+        // deployment id is the key, so events of a deployment keep their order in the partition
+        let mut deliveries = Vec::with_capacity(events.len());
+        for event in events {
+            let payload = serde_json::to_vec(event)?;
+            let record = FutureRecord::to(&self.topic)
+                .key(&event.deployment_id)
+                .payload(&payload);
+            let delivery = self
+                .producer
+                .send_result(record)
+                .map_err(|(err, _)| EventsError::Kafka(err))?;
+            deliveries.push(delivery);
+        }
+        for delivery in deliveries {
+            delivery
+                .await
+                .map_err(|_| EventsError::DeliveryCancelled)?
+                .map_err(|(err, _)| EventsError::Kafka(err))?;
+        }
+        Ok(())
+    }
+}
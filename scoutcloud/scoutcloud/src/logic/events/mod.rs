@@ -0,0 +1,104 @@
+mod kafka;
+
+pub use kafka::KafkaProducer;
+
+use crate::server::EventsSettings;
+use scoutcloud_entity as db;
+use sea_orm::{ActiveEnum, DbErr};
+use serde::Serialize;
+use std::{fmt::Debug, sync::Arc};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum EventsError {
+    #[error("kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+    #[error("delivery of event was cancelled")]
+    DeliveryCancelled,
+    #[error("failed to serialize event: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("db error: {0}")]
+    Db(#[from] DbErr),
+}
+
+/// Status change of a deployment published to the platform event stream
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeploymentEvent {
+    /// Id of the status change. Events are delivered at least once,
+    /// so consumers can use it to skip duplicates
+    pub event_id: i32,
+    pub deployment_id: String,
+    pub instance_id: String,
+    pub user_id: i32,
+    /// Workflow run of the deployment at the time of the change
+    pub run_id: Option<i64>,
+    pub old_status: Option<String>,
+    pub new_status: String,
+    /// Time of the status change in RFC 3339 format
+    pub timestamp: String,
+}
+
+impl DeploymentEvent {
+    pub fn new(
+        change: &db::deployment_status_history::Model,
+        deployment: &db::deployments::Model,
+        instance: &db::instances::Model,
+    ) -> Self {
+        Self {
+            event_id: change.id,
+            deployment_id: deployment.external_id.to_string(),
+            instance_id: instance.external_id.to_string(),
+            user_id: instance.creator_id,
+            run_id: change.run_id,
+            old_status: change.old_status.as_ref().map(|status| status.to_value()),
+            new_status: change.new_status.to_value(),
+            timestamp: change.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait EventProducer: Debug + Send + Sync {
+    /// Publishes events in the given order. On error some of them could be
+    /// published already, they are sent again by the next attempt
+    async fn send(&self, events: &[DeploymentEvent]) -> Result<(), EventsError>;
+}
+
+/// Producer of deployment events, events are not exported if it is not configured
+#[derive(Debug, Default)]
+pub struct EventsExport {
+    pub producer: Option<Arc<dyn EventProducer>>,
+    pub batch_size: u64,
+}
+
+impl EventsExport {
+    pub fn from_settings(settings: Option<&EventsSettings>) -> Result<Self, EventsError> {
+        let Some(settings) = settings else {
+            return Ok(Self::default());
+        };
+        Ok(Self {
+            producer: Some(Arc::new(KafkaProducer::new(settings)?)),
+            batch_size: settings.batch_size,
+        })
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockProducer {
+    pub events: std::sync::Mutex<Vec<DeploymentEvent>>,
+    /// Simulates unavailable broker
+    pub fail: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl EventProducer for MockProducer {
+    async fn send(&self, events: &[DeploymentEvent]) -> Result<(), EventsError> {
+        if self.fail.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(rdkafka::error::KafkaError::Canceled.into());
+        }
+        self.events.lock().unwrap().extend_from_slice(events);
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use super::GithubError;
+use crate::server::RepoAllowlistSettings;
+use std::collections::BTreeSet;
+
+/// Repos which workflows may be dispatched to. It is checked before every dispatch,
+/// so instances of a misconfigured deployment target never reach an arbitrary repo
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoAllowlist {
+    /// `owner/repo` in lowercase, since names of github repos are case-insensitive
+    repos: BTreeSet<String>,
+    deny_if_empty: bool,
+}
+
+impl RepoAllowlist {
+    /// Fails on entries which are not `owner/repo`, so typos are found on startup
+    pub fn from_settings(settings: &RepoAllowlistSettings) -> Result<Self, GithubError> {
+        let repos = settings
+            .repos
+            .iter()
+            .map(|full_name| match full_name.split_once('/') {
+                Some((owner, repo))
+                    if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') =>
+                {
+                    Ok(full_name.to_lowercase())
+                }
+                _ => Err(GithubError::Internal(anyhow::anyhow!(
+                    "invalid repo `{full_name}` in the allowlist of repos, expected `owner/repo`"
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            repos,
+            deny_if_empty: settings.deny_if_empty,
+        })
+    }
+
+    pub fn check(&self, owner: &str, repo: &str) -> Result<(), GithubError> {
+        let full_name = format!("{owner}/{repo}");
+        let allowed = if self.repos.is_empty() {
+            !self.deny_if_empty
+        } else {
+            self.repos.contains(&full_name.to_lowercase())
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(GithubError::RepoNotAllowed(full_name))
+        }
+    }
+
+    /// Repos chosen by users, like forks of the workflows, should be listed explicitly,
+    /// even if the allowlist is empty and allows all repos
+    pub fn check_listed(&self, owner: &str, repo: &str) -> Result<(), GithubError> {
+        let full_name = format!("{owner}/{repo}");
+        if self.repos.contains(&full_name.to_lowercase()) {
+            Ok(())
+        } else {
+            Err(GithubError::RepoNotAllowed(full_name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(repos: &[&str], deny_if_empty: bool) -> Result<RepoAllowlist, GithubError> {
+        RepoAllowlist::from_settings(&RepoAllowlistSettings {
+            repos: repos.iter().map(|repo| repo.to_string()).collect(),
+            deny_if_empty,
+        })
+    }
+
+    #[test]
+    fn only_listed_repos_are_allowed() {
+        let allowlist =
+            allowlist(&["blockscout/autodeploy", "Blockscout/Autodeploy-EU"], true).unwrap();
+        allowlist.check("blockscout", "autodeploy").unwrap();
+        allowlist.check("blockscout", "autodeploy-eu").unwrap();
+        let err = allowlist.check("blockscout", "other").unwrap_err();
+        assert!(
+            matches!(&err, GithubError::RepoNotAllowed(repo) if repo == "blockscout/other"),
+            "{err:?}"
+        );
+        assert!(!err.is_retryable());
+        allowlist
+            .check("attacker", "autodeploy")
+            .expect_err("repo of other owner should be rejected");
+    }
+
+    #[test]
+    fn empty_allowlist_follows_its_policy() {
+        allowlist(&[], false)
+            .unwrap()
+            .check("blockscout", "autodeploy")
+            .unwrap();
+        let err = allowlist(&[], true)
+            .unwrap()
+            .check("blockscout", "autodeploy")
+            .unwrap_err();
+        assert!(matches!(err, GithubError::RepoNotAllowed(_)), "{err:?}");
+    }
+
+    #[test]
+    fn chosen_repos_should_be_listed() {
+        let listed = allowlist(&["power-user/autodeploy"], false).unwrap();
+        listed.check_listed("Power-User", "autodeploy").unwrap();
+        listed
+            .check_listed("blockscout", "autodeploy")
+            .expect_err("repo which is not listed should be rejected");
+        let err = allowlist(&[], false)
+            .unwrap()
+            .check_listed("power-user", "autodeploy")
+            .unwrap_err();
+        assert!(matches!(err, GithubError::RepoNotAllowed(_)), "{err:?}");
+    }
+
+    #[test]
+    fn malformed_entries_are_rejected() {
+        for repo in ["autodeploy", "/autodeploy", "blockscout/", "blockscout/a/b"] {
+            allowlist(&[repo], false).expect_err(&format!("`{repo}` should be rejected"));
+        }
+    }
+}
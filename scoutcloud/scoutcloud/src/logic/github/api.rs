@@ -1,7 +1,7 @@
-use super::{types, GithubClient, GithubError};
+use super::{types, DeclaredInputs, GithubClient, GithubError, InputsError, WorkflowFile};
 use anyhow::Context;
 use chrono::Utc;
-use octocrab::{models as octo_types, models::RunId, Page};
+use octocrab::{models as octo_types, models::RunId, FromResponse, Page};
 use serde::Serialize;
 use tracing::instrument;
 
@@ -44,167 +44,351 @@ impl GithubClient {
         Ok(())
     }
 
+    #[instrument(skip_all, level = "info")]
     pub async fn get_latest_commit(
         &self,
     ) -> Result<octocrab::models::repos::RepoCommit, GithubError> {
-        let latest_commit = self
-            .client
-            .commits(self.owner.clone(), self.repo.clone())
-            .get(self.default_branch_name.clone())
-            .await?;
-        Ok(latest_commit)
+        self.guarded(async {
+            let latest_commit = self
+                .client()
+                .await?
+                .commits(self.owner.clone(), self.repo.clone())
+                .get(self.default_branch_name.clone())
+                .await?;
+            Ok(latest_commit)
+        })
+        .await
     }
 
+    /// Checks that the client is authenticated and has access to the repository
+    #[instrument(skip_all, level = "info")]
+    pub async fn check_repo_access(&self) -> Result<(), GithubError> {
+        self.guarded(async {
+            let url = format!("/repos/{}/{}", self.owner, self.repo);
+            let client = self.client().await?;
+            let response = self
+                .send_get_with_retry(|| client._get(url.clone()))
+                .await?;
+            octocrab::map_github_error(response).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    #[instrument(skip_all, level = "info")]
     pub async fn run_workflow<P: Serialize>(
         &self,
         workflow_id: impl Into<String>,
         _ref: impl Into<String>,
         inputs: P,
     ) -> Result<(), GithubError> {
-        let workflow_dispatch = types::WorkflowDispatchRequest {
-            _ref: _ref.into(),
-            inputs,
-        };
-        self.client
-            ._post(
-                format!(
-                    "/repos/{owner}/{repo}/actions/workflows/{workflow_id}/dispatches",
+        self.guarded(async {
+            let workflow_dispatch = types::WorkflowDispatchRequest {
+                _ref: _ref.into(),
+                inputs,
+            };
+            let url = format!(
+                "/repos/{owner}/{repo}/actions/workflows/{workflow_id}/dispatches",
+                owner = self.owner,
+                repo = self.repo,
+                workflow_id = workflow_id.into()
+            );
+            let client = self.client().await?;
+            let response = self
+                .send_with_rate_limit_retry(|| client._post(url.clone(), Some(&workflow_dispatch)))
+                .await?;
+            octocrab::map_github_error(response).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Fails with `GitRefNotFound` if there is no branch, tag or commit with such name
+    #[instrument(skip_all, fields(git_ref = git_ref), level = "info")]
+    pub async fn check_git_ref_exists(&self, git_ref: &str) -> Result<(), GithubError> {
+        self.guarded(async {
+            let url = format!(
+                "/repos/{owner}/{repo}/commits/{git_ref}",
+                owner = self.owner,
+                repo = self.repo,
+            );
+            let client = self.client().await?;
+            let response = self
+                .send_get_with_retry(|| client._get(url.clone()))
+                .await?;
+            // github responds with 422 if there is no commit which the ref points to
+            if matches!(response.status().as_u16(), 404 | 422) {
+                return Err(GithubError::GitRefNotFound(git_ref.to_string()));
+            }
+            octocrab::map_github_error(response).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Reads sha and inputs declared in the workflow file on `git_ref`,
+    /// or on the default branch if it is not set
+    #[instrument(skip_all, level = "info")]
+    pub async fn get_workflow_file(
+        &self,
+        workflow_id: &str,
+        git_ref: Option<&str>,
+    ) -> Result<WorkflowFile, GithubError> {
+        let content = self
+            .guarded(async {
+                let url = format!(
+                    "/repos/{owner}/{repo}/contents/.github/workflows/{workflow_id}?ref={branch}",
                     owner = self.owner,
                     repo = self.repo,
-                    workflow_id = workflow_id.into()
-                ),
-                Some(&workflow_dispatch),
-            )
+                    branch = git_ref.unwrap_or(&self.default_branch_name),
+                );
+                let client = self.client().await?;
+                let response = self
+                    .send_get_with_retry(|| client._get(url.clone()))
+                    .await?;
+                let content = octo_types::repos::Content::from_response(
+                    octocrab::map_github_error(response).await?,
+                )
+                .await?;
+                Ok(content)
+            })
             .await?;
-        Ok(())
+        let invalid = |source| GithubError::InvalidWorkflowInputs {
+            workflow: workflow_id.to_string(),
+            source,
+        };
+        let yaml = content.decoded_content().ok_or_else(|| {
+            invalid(InputsError::InvalidWorkflowFile(
+                "workflow file has no content".to_string(),
+            ))
+        })?;
+        let declared_inputs = DeclaredInputs::from_workflow_yaml(&yaml).map_err(invalid)?;
+        Ok(WorkflowFile {
+            sha: content.sha,
+            declared_inputs,
+        })
     }
 
+    #[instrument(skip_all, level = "info")]
     pub async fn get_workflow_runs(
         &self,
         workflow_id: impl Into<String>,
     ) -> Result<Vec<octo_types::workflows::Run>, GithubError> {
-        let runs = self
-            .client
-            .workflows(self.owner.clone(), self.repo.clone())
-            .list_runs(workflow_id)
-            .send()
-            .await?
-            .take_items();
-        Ok(runs)
+        self.guarded(async {
+            let runs = self
+                .client()
+                .await?
+                .workflows(self.owner.clone(), self.repo.clone())
+                .list_runs(workflow_id)
+                .send()
+                .await?
+                .take_items();
+            Ok(runs)
+        })
+        .await
     }
 
+    #[instrument(skip_all, level = "info")]
     pub async fn get_latest_workflow_run(
         &self,
         workflow_id: impl Into<String>,
         created_from: Option<chrono::DateTime<Utc>>,
     ) -> Result<Option<octo_types::workflows::Run>, GithubError> {
-        let workflow_id = workflow_id.into();
-        let url = format!(
-            "/repos/{owner}/{repo}/actions/workflows/{workflow_id}/runs",
-            owner = self.owner,
-            repo = self.repo,
-            workflow_id = workflow_id
-        );
-        let params = types::WorkflowRunsListRequest {
-            created: created_from.map(|from| format!(">={}", from.to_rfc3339())),
-            page: Some(1u32),
-            per_page: Some(1u8),
-        };
-        let mut pages: Page<octo_types::workflows::Run> =
-            self.client.get(url, Some(&params)).await?;
+        self.guarded(async {
+            let workflow_id = workflow_id.into();
+            let url = format!(
+                "/repos/{owner}/{repo}/actions/workflows/{workflow_id}/runs",
+                owner = self.owner,
+                repo = self.repo,
+                workflow_id = workflow_id
+            );
+            let params = types::WorkflowRunsListRequest {
+                created: created_from.map(|from| format!(">={}", from.to_rfc3339())),
+                page: Some(1u32),
+                per_page: Some(1u8),
+            };
+            let query = serde_urlencoded::to_string(&params)
+                .map_err(|err| GithubError::Internal(err.into()))?;
+            let uri = format!("{url}?{query}");
+            let client = self.client().await?;
+            let response = self
+                .send_get_with_retry(|| client._get(uri.clone()))
+                .await?;
+            let mut pages = Page::<octo_types::workflows::Run>::from_response(
+                octocrab::map_github_error(response).await?,
+            )
+            .await?;
 
-        Ok(pages.take_items().into_iter().next())
+            Ok(pages.take_items().into_iter().next())
+        })
+        .await
     }
 
+    /// Sent as a conditional request if the run was fetched before,
+    /// so unchanged run is not downloaded and parsed again
+    #[instrument(skip_all, fields(run_id = tracing::field::Empty), level = "info")]
     pub async fn get_workflow_run(
         &self,
         run_id: impl Into<RunId>,
     ) -> Result<octo_types::workflows::Run, GithubError> {
-        let run = self
-            .client
-            .workflows(self.owner.clone(), self.repo.clone())
-            .get(run_id.into())
+        let run_id = run_id.into();
+        tracing::Span::current().record("run_id", run_id.0);
+        self.guarded(async {
+            let url = format!(
+                "/repos/{owner}/{repo}/actions/runs/{run_id}",
+                owner = self.owner,
+                repo = self.repo,
+            );
+            let key = self.run_cache_key(run_id);
+            let tagged = self.run_etags.get(&key);
+            let mut headers = http::HeaderMap::new();
+            if let Some(etag) = tagged
+                .as_ref()
+                .and_then(|(etag, _)| http::HeaderValue::from_str(etag).ok())
+            {
+                headers.insert(http::header::IF_NONE_MATCH, etag);
+            }
+            let client = self.client().await?;
+            let response = self
+                .send_get_with_retry(|| {
+                    client._get_with_headers(url.clone(), Some(headers.clone()))
+                })
+                .await?;
+            if response.status() == http::StatusCode::NOT_MODIFIED {
+                if let Some((_, run)) = tagged {
+                    self.run_etags.touch(&key);
+                    return Ok(run);
+                }
+            }
+            let etag = response
+                .headers()
+                .get(http::header::ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .map(str::to_string);
+            let run = octo_types::workflows::Run::from_response(
+                octocrab::map_github_error(response).await?,
+            )
             .await?;
-        Ok(run)
+            if let Some(etag) = etag {
+                self.run_etags.insert(key, etag, run.clone());
+            }
+            Ok(run)
+        })
+        .await
     }
 
+    #[instrument(skip_all, fields(run_id = tracing::field::Empty), level = "info")]
+    pub async fn cancel_workflow_run(&self, run_id: impl Into<RunId>) -> Result<(), GithubError> {
+        let run_id = run_id.into();
+        tracing::Span::current().record("run_id", run_id.0);
+        self.guarded(async {
+            self.client()
+                .await?
+                .actions()
+                .cancel_workflow_run(self.owner.clone(), self.repo.clone(), run_id)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    #[instrument(skip_all, level = "info")]
     async fn create_blob(&self, content: &str) -> Result<types::CreateBlobResponse, GithubError> {
-        let blob: types::CreateBlobResponse = self
-            .client
-            .post(
-                format!(
-                    "/repos/{owner}/{repo}/git/blobs",
-                    owner = self.owner,
-                    repo = self.repo
-                ),
-                Some(&types::CreateBlobRequest::with_default_encoding(content)),
-            )
-            .await?;
-        Ok(blob)
+        self.guarded(async {
+            let blob: types::CreateBlobResponse = self
+                .client()
+                .await?
+                .post(
+                    format!(
+                        "/repos/{owner}/{repo}/git/blobs",
+                        owner = self.owner,
+                        repo = self.repo
+                    ),
+                    Some(&types::CreateBlobRequest::with_default_encoding(content)),
+                )
+                .await?;
+            Ok(blob)
+        })
+        .await
     }
 
+    #[instrument(skip_all, level = "info")]
     async fn create_tree(
         &self,
         base_tree: &str,
         path: &str,
         blob_sha: &str,
     ) -> Result<types::CreateTreeResponse, GithubError> {
-        let tree: types::CreateTreeResponse = self
-            .client
-            .post(
-                format!(
-                    "/repos/{owner}/{repo}/git/trees",
-                    owner = self.owner,
-                    repo = self.repo
-                ),
-                Some(&types::CreateTreeRequest::with_single_blob(
-                    base_tree, path, blob_sha,
-                )),
-            )
-            .await?;
-        Ok(tree)
+        self.guarded(async {
+            let tree: types::CreateTreeResponse = self
+                .client()
+                .await?
+                .post(
+                    format!(
+                        "/repos/{owner}/{repo}/git/trees",
+                        owner = self.owner,
+                        repo = self.repo
+                    ),
+                    Some(&types::CreateTreeRequest::with_single_blob(
+                        base_tree, path, blob_sha,
+                    )),
+                )
+                .await?;
+            Ok(tree)
+        })
+        .await
     }
 
+    #[instrument(skip_all, level = "info")]
     async fn create_commit(
         &self,
         tree_sha: String,
         message: String,
         parent_sha: String,
     ) -> Result<types::CreateCommitResponse, GithubError> {
-        let commit = self
-            .client
-            .post(
-                format!(
-                    "/repos/{owner}/{repo}/git/commits",
-                    owner = self.owner,
-                    repo = self.repo
-                ),
-                Some(&types::CreateCommitRequest {
-                    tree: tree_sha,
-                    message,
-                    parents: vec![parent_sha],
-                }),
-            )
-            .await?;
-        Ok(commit)
+        self.guarded(async {
+            let commit = self
+                .client()
+                .await?
+                .post(
+                    format!(
+                        "/repos/{owner}/{repo}/git/commits",
+                        owner = self.owner,
+                        repo = self.repo
+                    ),
+                    Some(&types::CreateCommitRequest {
+                        tree: tree_sha,
+                        message,
+                        parents: vec![parent_sha],
+                    }),
+                )
+                .await?;
+            Ok(commit)
+        })
+        .await
     }
 
+    #[instrument(skip_all, level = "info")]
     async fn update_branch(&self, commit_sha: &str) -> Result<(), GithubError> {
-        let _: serde_json::Value = self
-            .client
-            .patch(
-                format!(
-                    "/repos/{owner}/{repo}/git/refs/heads/{branch}",
-                    owner = self.owner,
-                    repo = self.repo,
-                    branch = self.default_branch_name
-                ),
-                Some(&types::UpdateBranchRequest {
-                    sha: commit_sha.to_string(),
-                }),
-            )
-            .await?;
-        Ok(())
+        self.guarded(async {
+            let _: serde_json::Value = self
+                .client()
+                .await?
+                .patch(
+                    format!(
+                        "/repos/{owner}/{repo}/git/refs/heads/{branch}",
+                        owner = self.owner,
+                        repo = self.repo,
+                        branch = self.default_branch_name
+                    ),
+                    Some(&types::UpdateBranchRequest {
+                        sha: commit_sha.to_string(),
+                    }),
+                )
+                .await?;
+            Ok(())
+        })
+        .await
     }
 
     fn build_commit_message(msg: &str) -> String {
@@ -0,0 +1,169 @@
+use super::{request::RequestOptions, types, GithubError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use octocrab::Octocrab;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+/// Installation token is refreshed if it expires in less than this margin,
+/// so requests that are sent right now don't fail because of expired token
+const INSTALLATION_TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone)]
+pub(super) enum GithubAuth {
+    PersonalToken(Octocrab),
+    AppInstallation(Arc<InstallationTokenCache>),
+}
+
+impl GithubAuth {
+    pub async fn client(&self) -> Result<Octocrab, GithubError> {
+        match self {
+            GithubAuth::PersonalToken(client) => Ok(client.clone()),
+            GithubAuth::AppInstallation(cache) => cache.client().await,
+        }
+    }
+}
+
+/// Mints short-lived installation tokens of github app
+/// and keeps client authenticated with the latest one
+#[derive(Debug)]
+pub(super) struct InstallationTokenCache {
+    app_client: Octocrab,
+    installation_id: u64,
+    base_uri: Option<String>,
+    request: RequestOptions,
+    refresh_margin: Duration,
+    cached: Mutex<Option<InstallationClient>>,
+}
+
+#[derive(Debug)]
+struct InstallationClient {
+    client: Octocrab,
+    expires_at: DateTime<Utc>,
+}
+
+fn expires_soon(expires_at: DateTime<Utc>, now: DateTime<Utc>, margin: Duration) -> bool {
+    let deadline = chrono::Duration::from_std(margin)
+        .ok()
+        .and_then(|margin| now.checked_add_signed(margin));
+    !matches!(deadline, Some(deadline) if deadline < expires_at)
+}
+
+impl InstallationTokenCache {
+    pub fn new(
+        app_id: u64,
+        installation_id: u64,
+        private_key: &str,
+        base_uri: Option<&str>,
+        request: RequestOptions,
+    ) -> Result<Self, GithubError> {
+        let key = jsonwebtoken::EncodingKey::from_rsa_pem(private_key.as_bytes())
+            .context("invalid github app private key")?;
+        let app_client = request.app_client(app_id, key, base_uri)?;
+        Ok(Self {
+            app_client,
+            installation_id,
+            base_uri: base_uri.map(str::to_string),
+            request,
+            refresh_margin: INSTALLATION_TOKEN_REFRESH_MARGIN,
+            cached: Mutex::new(None),
+        })
+    }
+
+    pub async fn client(&self) -> Result<Octocrab, GithubError> {
+        let mut cached = self.cached.lock().await;
+        match cached.as_ref() {
+            Some(cached) if !expires_soon(cached.expires_at, Utc::now(), self.refresh_margin) => {
+                Ok(cached.client.clone())
+            }
+            _ => {
+                let fresh = self.mint_installation_client().await?;
+                let client = fresh.client.clone();
+                *cached = Some(fresh);
+                Ok(client)
+            }
+        }
+    }
+
+    async fn mint_installation_client(&self) -> Result<InstallationClient, GithubError> {
+        let token: types::InstallationTokenResponse = self
+            .app_client
+            .post(
+                format!(
+                    "/app/installations/{installation_id}/access_tokens",
+                    installation_id = self.installation_id
+                ),
+                None::<&()>,
+            )
+            .await?;
+        tracing::info!(
+            installation_id = self.installation_id,
+            expires_at =? token.expires_at,
+            "minted new github app installation token"
+        );
+        let client = self
+            .request
+            .personal_token_client(token.token, self.base_uri.as_deref())?;
+        Ok(InstallationClient {
+            client,
+            expires_at: token.expires_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::github::{MockedGithubRepo, TEST_APP_PRIVATE_KEY};
+
+    fn test_cache(mock: &MockedGithubRepo) -> InstallationTokenCache {
+        InstallationTokenCache::new(
+            1,
+            12345,
+            TEST_APP_PRIVATE_KEY,
+            Some(mock.server.base_url().as_str()),
+            RequestOptions::default(),
+        )
+        .expect("failed to create installation token cache")
+    }
+
+    #[test]
+    fn installation_token_expires_soon() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let margin = Duration::from_secs(300);
+        let in_secs = |secs: i64| now + chrono::Duration::seconds(secs);
+
+        assert!(!expires_soon(in_secs(3600), now, margin));
+        assert!(!expires_soon(in_secs(301), now, margin));
+        assert!(expires_soon(in_secs(300), now, margin));
+        assert!(expires_soon(in_secs(60), now, margin));
+        assert!(expires_soon(in_secs(-60), now, margin));
+        assert!(expires_soon(in_secs(3600), now, Duration::MAX));
+    }
+
+    #[tokio::test]
+    async fn installation_token_is_cached_until_expiry() {
+        let mock = MockedGithubRepo::default();
+        let handles = mock.build_handles();
+        let cache = test_cache(&mock);
+
+        cache.client().await.expect("failed to get client");
+        cache.client().await.expect("failed to get client");
+        handles.assert_hits("app_installation_token", 1);
+    }
+
+    #[tokio::test]
+    async fn installation_token_is_refreshed_before_expiry() {
+        let mock = MockedGithubRepo::default();
+        let mut handles = mock.build_handles();
+        let expires_at = Utc::now() + chrono::Duration::seconds(60);
+        mock.override_response(&mut handles, "app_installation_token", |token| {
+            token["expires_at"] = expires_at.to_rfc3339().into();
+        });
+        let cache = test_cache(&mock);
+
+        cache.client().await.expect("failed to get client");
+        cache.client().await.expect("failed to get client");
+        handles.assert_hits("app_installation_token", 2);
+    }
+}
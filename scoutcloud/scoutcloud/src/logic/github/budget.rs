@@ -0,0 +1,175 @@
+use super::{GithubClient, GithubError};
+use crate::server::ActionsBudgetSettings;
+use octocrab::FromResponse;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tracing::instrument;
+
+/// Usage of github actions minutes of the owner in the current billing cycle
+#[derive(Deserialize, Debug, Clone)]
+pub struct ActionsBilling {
+    pub total_minutes_used: u64,
+    pub included_minutes: u64,
+}
+
+impl ActionsBilling {
+    pub fn remaining_minutes(&self) -> u64 {
+        self.included_minutes
+            .saturating_sub(self.total_minutes_used)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedBilling {
+    billing: ActionsBilling,
+    fetched_at: Instant,
+}
+
+/// Rejects deploys while the owner of the repo is running out of github actions minutes,
+/// so deployments fail fast instead of waiting for workflows which github never starts.
+/// Billing of every owner is cached for `cache_ttl` to not query it on every deploy
+#[derive(Debug)]
+pub struct ActionsBudget {
+    min_remaining_minutes: u64,
+    warn_only: bool,
+    cache_ttl: Duration,
+    // lock is held during the request, so concurrent deploys share a single request
+    cache: tokio::sync::Mutex<HashMap<String, CachedBilling>>,
+}
+
+impl ActionsBudget {
+    pub fn new(min_remaining_minutes: u64, warn_only: bool, cache_ttl: Duration) -> Self {
+        Self {
+            min_remaining_minutes,
+            warn_only,
+            cache_ttl,
+            cache: Default::default(),
+        }
+    }
+
+    pub fn from_settings(settings: &ActionsBudgetSettings) -> Self {
+        Self::new(
+            settings.min_remaining_minutes,
+            settings.warn_only,
+            settings.cache_ttl,
+        )
+    }
+}
+
+impl GithubClient {
+    /// Fails with `ActionsBudgetExhausted` if the owner of the repo has fewer remaining
+    /// actions minutes than configured. Billing is checked only if the budget is configured,
+    /// failed billing requests are logged and don't block deploys
+    pub async fn check_actions_budget(&self) -> Result<(), GithubError> {
+        let Some(budget) = self.actions_budget.as_deref() else {
+            return Ok(());
+        };
+        let billing = {
+            let mut cache = budget.cache.lock().await;
+            match cache.get(&self.owner) {
+                Some(cached) if cached.fetched_at.elapsed() < budget.cache_ttl => {
+                    cached.billing.clone()
+                }
+                _ => match self.get_actions_billing().await {
+                    Ok(billing) => {
+                        cache.insert(
+                            self.owner.clone(),
+                            CachedBilling {
+                                billing: billing.clone(),
+                                fetched_at: Instant::now(),
+                            },
+                        );
+                        billing
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            owner = %self.owner,
+                            err = ?err,
+                            "failed to get github actions billing, budget is not checked"
+                        );
+                        return Ok(());
+                    }
+                },
+            }
+        };
+
+        let remaining_minutes = billing.remaining_minutes();
+        if remaining_minutes >= budget.min_remaining_minutes {
+            return Ok(());
+        }
+        if budget.warn_only {
+            tracing::warn!(
+                owner = %self.owner,
+                remaining_minutes = remaining_minutes,
+                min_remaining_minutes = budget.min_remaining_minutes,
+                "github actions minutes are running out, deploying anyway"
+            );
+            return Ok(());
+        }
+        Err(GithubError::ActionsBudgetExhausted {
+            owner: self.owner.clone(),
+            remaining_minutes,
+            min_remaining_minutes: budget.min_remaining_minutes,
+        })
+    }
+
+    /// Requires read access to billing of the organization which owns the repo
+    #[instrument(skip_all, level = "info")]
+    pub async fn get_actions_billing(&self) -> Result<ActionsBilling, GithubError> {
+        self.guarded(async {
+            let url = format!("/orgs/{owner}/settings/billing/actions", owner = self.owner);
+            let client = self.client().await?;
+            let response = self
+                .send_get_with_retry(|| client._get(url.clone()))
+                .await?;
+            let billing =
+                ActionsBilling::from_response(octocrab::map_github_error(response).await?).await?;
+            Ok(billing)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn billing_is_cached() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client =
+            client.with_actions_budget(ActionsBudget::new(100, false, Duration::from_secs(60)));
+        let billing = mock.mock_actions_billing(2000, 1950);
+
+        for _ in 0..3 {
+            let err = client.check_actions_budget().await.unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    GithubError::ActionsBudgetExhausted {
+                        remaining_minutes: 50,
+                        min_remaining_minutes: 100,
+                        ..
+                    }
+                ),
+                "unexpected error: {err:?}"
+            );
+        }
+        assert_eq!(billing.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn exhausted_budget_is_only_reported_in_warn_only_mode() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client =
+            client.with_actions_budget(ActionsBudget::new(100, true, Duration::from_secs(60)));
+        mock.mock_actions_billing(2000, 2500);
+
+        client.check_actions_budget().await.unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use super::{GithubClient, GithubError};
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Requests are sent, `failures` is the number of failed requests in a row
+    Closed { failures: u32 },
+    /// Requests are rejected without sending until the cooldown is over
+    Open { until: Instant },
+    /// Single probe request is sent to check if github is recovered,
+    /// other requests are rejected while it is in flight
+    HalfOpen { probing: bool },
+}
+
+/// Stops sending requests to github during its outage, so tasks fail fast
+/// and retry later instead of flooding github once it recovers.
+/// Only failures which may disappear on retry are counted, like server errors or timeouts
+#[derive(Debug)]
+pub(super) struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+/// Permission to send a request. Probe which was dropped without the result,
+/// for example because the task was cancelled, lets the next request probe instead
+pub(super) struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    is_probe: bool,
+    recorded: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("circuit breaker lock is poisoned")
+    }
+
+    /// Returns error with the remaining cooldown if the request should not be sent
+    fn try_acquire_at(&self, now: Instant) -> Result<Permit<'_>, GithubError> {
+        let mut state = self.state();
+        let is_probe = match *state {
+            State::Closed { .. } => false,
+            State::Open { until } if now < until => {
+                return Err(GithubError::CircuitOpen(until - now));
+            }
+            State::Open { .. } | State::HalfOpen { probing: false } => {
+                tracing::info!("github circuit breaker is half-open, probing github api");
+                *state = State::HalfOpen { probing: true };
+                true
+            }
+            State::HalfOpen { probing: true } => {
+                return Err(GithubError::CircuitOpen(Duration::ZERO));
+            }
+        };
+        Ok(Permit {
+            breaker: self,
+            is_probe,
+            recorded: false,
+        })
+    }
+
+    fn record_at(&self, success: bool, now: Instant) {
+        let mut state = self.state();
+        *state = match (*state, success) {
+            (State::Closed { .. } | State::HalfOpen { .. }, true) => State::Closed { failures: 0 },
+            (State::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (State::Closed { .. } | State::HalfOpen { .. }, false) => {
+                tracing::warn!(
+                    cooldown = ?self.cooldown,
+                    "github api is unavailable, circuit breaker is open"
+                );
+                State::Open {
+                    until: now + self.cooldown,
+                }
+            }
+            // request was sent before the breaker was opened by another one
+            (state @ State::Open { .. }, _) => state,
+        };
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN)
+    }
+}
+
+impl Permit<'_> {
+    fn record_at(mut self, success: bool, now: Instant) {
+        self.recorded = true;
+        self.breaker.record_at(success, now);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.is_probe && !self.recorded {
+            let mut state = self.breaker.state();
+            if *state == (State::HalfOpen { probing: true }) {
+                *state = State::HalfOpen { probing: false };
+            }
+        }
+    }
+}
+
+fn is_outage(result: &Result<impl Sized, GithubError>) -> bool {
+    matches!(result, Err(err) if err.is_retryable())
+}
+
+impl GithubClient {
+    /// Sends single request to github through the circuit breaker,
+    /// request is not sent at all while the breaker is open
+    pub(super) async fn guarded<T, Fut>(&self, request: Fut) -> Result<T, GithubError>
+    where
+        Fut: Future<Output = Result<T, GithubError>>,
+    {
+        let permit = self.breaker.try_acquire_at(Instant::now())?;
+        let result = request.await;
+        permit.record_at(!is_outage(&result), Instant::now());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use octocrab::models::RunId;
+    use pretty_assertions::assert_eq;
+
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    fn fail(breaker: &CircuitBreaker, now: Instant) {
+        breaker.try_acquire_at(now).unwrap().record_at(false, now);
+    }
+
+    fn remaining(result: Result<Permit<'_>, GithubError>) -> Option<Duration> {
+        match result {
+            Err(GithubError::CircuitOpen(remaining)) => Some(remaining),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => None,
+        }
+    }
+
+    #[test]
+    fn breaker_goes_through_all_states() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        let start = Instant::now();
+
+        // success resets the counter of failures in a row
+        fail(&breaker, start);
+        fail(&breaker, start);
+        breaker
+            .try_acquire_at(start)
+            .unwrap()
+            .record_at(true, start);
+        assert_eq!(*breaker.state(), State::Closed { failures: 0 });
+        fail(&breaker, start);
+        fail(&breaker, start);
+        assert_eq!(*breaker.state(), State::Closed { failures: 2 });
+
+        fail(&breaker, start);
+        assert_eq!(
+            *breaker.state(),
+            State::Open {
+                until: start + COOLDOWN
+            }
+        );
+        let later = start + Duration::from_secs(10);
+        assert_eq!(
+            remaining(breaker.try_acquire_at(later)),
+            Some(Duration::from_secs(20))
+        );
+
+        let after_cooldown = start + COOLDOWN;
+        let probe = breaker.try_acquire_at(after_cooldown).unwrap();
+        assert_eq!(*breaker.state(), State::HalfOpen { probing: true });
+        // only one probe is sent at once
+        assert_eq!(
+            remaining(breaker.try_acquire_at(after_cooldown)),
+            Some(Duration::ZERO)
+        );
+        probe.record_at(true, after_cooldown);
+        assert_eq!(*breaker.state(), State::Closed { failures: 0 });
+        assert_eq!(remaining(breaker.try_acquire_at(after_cooldown)), None);
+    }
+
+    #[test]
+    fn failed_probe_opens_breaker_again() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        let start = Instant::now();
+        fail(&breaker, start);
+
+        let after_cooldown = start + COOLDOWN;
+        fail(&breaker, after_cooldown);
+        assert_eq!(
+            *breaker.state(),
+            State::Open {
+                until: after_cooldown + COOLDOWN
+            }
+        );
+    }
+
+    #[test]
+    fn dropped_probe_allows_another_probe() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        let start = Instant::now();
+        fail(&breaker, start);
+
+        let after_cooldown = start + COOLDOWN;
+        drop(breaker.try_acquire_at(after_cooldown).unwrap());
+        assert_eq!(*breaker.state(), State::HalfOpen { probing: false });
+        assert_eq!(remaining(breaker.try_acquire_at(after_cooldown)), None);
+    }
+
+    #[tokio::test]
+    async fn open_breaker_does_not_send_requests() {
+        let (client, mock) = crate::tests_utils::init::test_github_client().await;
+        let client = client.with_circuit_breaker(2, COOLDOWN);
+        let mut handles = mock.build_handles();
+        let run_id = RunId(8819501307);
+
+        // github is up, such errors are not counted
+        mock.override_status(&mut handles, "single_run_cleanup_yaml", 404);
+        for _ in 0..3 {
+            let err = client.get_workflow_run(run_id).await.unwrap_err();
+            assert!(matches!(err, GithubError::Octocrab(_)), "{err}");
+        }
+        handles.assert_hits("single_run_cleanup_yaml", 3);
+
+        mock.override_status(&mut handles, "single_run_cleanup_yaml", 503);
+        for _ in 0..2 {
+            let err = client.get_workflow_run(run_id).await.unwrap_err();
+            assert!(matches!(err, GithubError::Octocrab(_)), "{err}");
+        }
+        let err = client.get_workflow_run(run_id).await.unwrap_err();
+        assert!(matches!(err, GithubError::CircuitOpen(_)), "{err}");
+        handles.assert_hits("single_run_cleanup_yaml", 2);
+    }
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InputsError {
+    #[error("unknown inputs: {0:?}")]
+    Unknown(Vec<String>),
+    #[error("missing required inputs: {0:?}")]
+    Missing(Vec<String>),
+    #[error("failed to parse workflow file: {0}")]
+    InvalidWorkflowFile(String),
+}
+
+/// Inputs of `workflow_dispatch` event. Github silently ignores unknown inputs
+/// and uses empty values for missing ones, so inputs are validated
+/// against the declaration in the workflow file before the dispatch
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct WorkflowInputs(BTreeMap<String, String>);
+
+impl WorkflowInputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.0.insert(name.into(), value.to_string());
+        self
+    }
+
+    /// Input is not sent if `value` is not set, so the default of the workflow is used
+    pub fn with_optional(self, name: impl Into<String>, value: Option<impl ToString>) -> Self {
+        match value {
+            Some(value) => self.with(name, value),
+            None => self,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn validate(&self, declared: &DeclaredInputs) -> Result<(), InputsError> {
+        let unknown: Vec<String> = self
+            .0
+            .keys()
+            .filter(|name| !declared.0.contains_key(*name))
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(InputsError::Unknown(unknown));
+        }
+        let missing: Vec<String> = declared
+            .required()
+            .filter(|name| !self.0.contains_key(*name))
+            .map(str::to_string)
+            .collect();
+        if !missing.is_empty() {
+            return Err(InputsError::Missing(missing));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct DeclaredInput {
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub default: Option<serde_yaml::Value>,
+}
+
+/// Inputs declared in `on.workflow_dispatch.inputs` of the workflow file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeclaredInputs(BTreeMap<String, DeclaredInput>);
+
+impl DeclaredInputs {
+    pub fn from_workflow_yaml(content: &str) -> Result<Self, InputsError> {
+        let invalid = |err: serde_yaml::Error| InputsError::InvalidWorkflowFile(err.to_string());
+        let workflow: serde_yaml::Value = serde_yaml::from_str(content).map_err(invalid)?;
+        let inputs = workflow
+            .get("on")
+            .and_then(|on| on.get("workflow_dispatch"))
+            .and_then(|dispatch| dispatch.get("inputs"))
+            .cloned()
+            .unwrap_or(serde_yaml::Value::Null);
+        if inputs.is_null() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_value(inputs).map(Self).map_err(invalid)
+    }
+
+    /// Required inputs without default value should be always provided
+    fn required(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .filter(|(_, input)| input.required && input.default.is_none())
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn names(&self) -> BTreeSet<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const WORKFLOW: &str = r#"
+name: Deploy instance
+on:
+  workflow_dispatch:
+    inputs:
+      client:
+        description: Name of the client
+        required: true
+        type: string
+      image_tag:
+        required: true
+        default: latest
+      debug:
+        type: boolean
+jobs:
+  deploy:
+    runs-on: ubuntu-latest
+"#;
+
+    fn declared() -> DeclaredInputs {
+        DeclaredInputs::from_workflow_yaml(WORKFLOW).unwrap()
+    }
+
+    #[test]
+    fn declared_inputs_are_parsed() {
+        assert_eq!(
+            declared().names(),
+            BTreeSet::from(["client", "debug", "image_tag"])
+        );
+        let no_inputs = "on:\n  workflow_dispatch:\njobs: {}\n";
+        assert_eq!(
+            DeclaredInputs::from_workflow_yaml(no_inputs).unwrap(),
+            DeclaredInputs::default()
+        );
+    }
+
+    #[test]
+    fn valid_inputs_pass() {
+        let inputs = WorkflowInputs::new()
+            .with("client", "instance-1")
+            .with_optional("debug", Some(true))
+            .with_optional("image_tag", None::<String>);
+        inputs.validate(&declared()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&inputs).unwrap(),
+            serde_json::json!({"client": "instance-1", "debug": "true"})
+        );
+    }
+
+    #[test]
+    fn missing_required_input_is_rejected() {
+        let inputs = WorkflowInputs::new().with("debug", false);
+        assert_eq!(
+            inputs.validate(&declared()),
+            Err(InputsError::Missing(vec!["client".to_string()]))
+        );
+    }
+
+    #[test]
+    fn unknown_input_is_rejected() {
+        let inputs = WorkflowInputs::new()
+            .with("client", "instance-1")
+            .with("clinet", "instance-1");
+        assert_eq!(
+            inputs.validate(&declared()),
+            Err(InputsError::Unknown(vec!["clinet".to_string()]))
+        );
+    }
+}
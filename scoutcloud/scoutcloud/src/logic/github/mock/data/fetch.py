@@ -32,6 +32,10 @@ def write_response(filename, url, method, response, override_body=None):
     with open(filename, 'w') as f:
         f.write(json.dumps(data, indent=2))
 
+# get the repository
+r = requests.get(host, headers=headers)
+write_response('repo.json', host, 'GET', r)
+
 # get all commits
 url = host + '/commits'
 r = requests.get(url, headers=headers)
@@ -54,6 +58,11 @@ for workflow in workflows:
     workflow_id = workflow['path'].split('/')[-1]
     workflow_debug_name = workflow_id.replace('.', '_')
 
+    # get workflow file with declared inputs
+    url = host + f'/contents/.github/workflows/{workflow_id}'
+    r = requests.get(url, headers=headers, params={"ref": "main"})
+    write_response(f"workflow_file_{workflow_debug_name}.json", url, 'GET', r)
+
     # dispatch a workflow
     url = host + f'/actions/workflows/{workflow_id}/dispatches'
     r = requests.post(url, headers=headers, json={"ref":"main", "inputs":{"client": CLIENT}})
@@ -103,3 +112,8 @@ commit_sha = r.json()['sha']
 url = host + '/git/refs/heads/main'
 r = requests.patch(url, headers=headers, json={"sha": commit_sha})
 write_response('update_main.json', url, 'PATCH', r)
+
+# note: `cancel_run_deploy_yaml.json` is not fetched, since github cancels only unfinished runs.
+# it is written by hand and contains empty `202 Accepted` response
+# note: `app_installation_token.json` is not fetched, since it requires github app private key.
+# it is written by hand from github docs example, tests override `expires_at` if needed
@@ -1,3 +1,4 @@
+use super::WorkflowFiles;
 use httpmock::{Method, Mock, MockServer};
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -35,55 +36,260 @@ impl GithubMockedHandles<'_> {
     }
 
     pub fn with_name(&self, name: &str) -> &Mock {
-        let name = if !name.ends_with(".json") {
-            name.to_string() + ".json"
-        } else {
-            name.to_string()
-        };
+        let name = case_filename(name);
         self.0
             .get(&name)
             .unwrap_or_else(|| panic!("provided name '{name}' should be in handles map"))
     }
 }
 
+fn case_filename(name: &str) -> String {
+    if !name.ends_with(".json") {
+        name.to_string() + ".json"
+    } else {
+        name.to_string()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct MockCase {
     filename: String,
     url: String,
     method: Method,
     status: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Partial json body which requests should contain to match the case
+    #[serde(default)]
+    body: Option<serde_json::Value>,
     response: serde_json::Value,
 }
 
+fn find_case(name: &str) -> MockCase {
+    let filename = case_filename(name);
+    MOCK_CASES
+        .iter()
+        .map(|case_raw| serde_json::from_str::<MockCase>(case_raw).expect("invalid json"))
+        .find(|case| case.filename == filename)
+        .unwrap_or_else(|| panic!("provided name '{filename}' should be in mock cases"))
+}
+
+/// Private key of github app used in tests, generated only for them
+#[cfg(test)]
+pub const TEST_APP_PRIVATE_KEY: &str = include_str!("data/test_app_private_key.pem");
+
+const MOCK_CASES: &[&str] = &[
+    include_str!("data/repo.json"),
+    include_str!("data/commits.json"),
+    include_str!("data/main.json"),
+    include_str!("data/new_blob.json"),
+    include_str!("data/new_commit.json"),
+    include_str!("data/new_tree.json"),
+    include_str!("data/workflows.json"),
+    include_str!("data/workflow_file_cleanup_yaml.json"),
+    include_str!("data/workflow_file_deploy_yaml.json"),
+    include_str!("data/update_main.json"),
+    include_str!("data/dispatch_cleanup_yaml.json"),
+    include_str!("data/dispatch_deploy_yaml.json"),
+    include_str!("data/runs_cleanup_yaml.json"),
+    include_str!("data/runs_deploy_yaml.json"),
+    include_str!("data/single_run_cleanup_yaml.json"),
+    include_str!("data/single_run_deploy_yaml.json"),
+    include_str!("data/cancel_run_deploy_yaml.json"),
+    include_str!("data/app_installation_token.json"),
+];
+
 impl MockedGithubRepo {
     pub fn build_handles(&self) -> GithubMockedHandles {
+        self.build_handles_of_repo(&self.repo)
+    }
+
+    /// Mocks another repo of the owner on the same server, like a repo of deployment target
+    pub fn build_handles_of_repo(&self, repo: &str) -> GithubMockedHandles {
+        self.build_handles_with_workflow_files(repo, &Default::default())
+    }
+
+    /// Mocks the repo under another owner on the same server, like a fork of the workflows
+    pub fn build_handles_of_fork(&self, owner: &str) -> GithubMockedHandles {
+        self.build_handles_of(owner, &self.repo, &Default::default())
+    }
+
+    /// Mocks the repo with workflow files named after `files`.
+    /// Names of the handles stay the same, like `dispatch_deploy_yaml`
+    pub fn build_handles_with_workflow_files(
+        &self,
+        repo: &str,
+        files: &WorkflowFiles,
+    ) -> GithubMockedHandles {
+        self.build_handles_of(&self.owner, repo, files)
+    }
+
+    fn build_handles_of(
+        &self,
+        owner: &str,
+        repo: &str,
+        files: &WorkflowFiles,
+    ) -> GithubMockedHandles {
         let mut handles = HashMap::new();
-        for case_raw in [
-            include_str!("data/commits.json"),
-            include_str!("data/main.json"),
-            include_str!("data/new_blob.json"),
-            include_str!("data/new_commit.json"),
-            include_str!("data/new_tree.json"),
-            include_str!("data/workflows.json"),
-            include_str!("data/update_main.json"),
-            include_str!("data/dispatch_cleanup_yaml.json"),
-            include_str!("data/dispatch_deploy_yaml.json"),
-            include_str!("data/runs_cleanup_yaml.json"),
-            include_str!("data/runs_deploy_yaml.json"),
-            include_str!("data/single_run_cleanup_yaml.json"),
-            include_str!("data/single_run_deploy_yaml.json"),
-        ] {
-            let case: MockCase = serde_json::from_str(case_raw).expect("invalid json");
-            let url = case
+        for case_raw in MOCK_CASES {
+            let mut case: MockCase = serde_json::from_str(case_raw).expect("invalid json");
+            case.url = case
                 .url
-                .replace("{owner}", &self.owner)
-                .replace("{repo}", &self.repo);
-            let mock = self.server.mock(|when, then| {
-                when.method(case.method).path(&url);
-                then.status(case.status).json_body(case.response);
-            });
-            handles.insert(case.filename, mock);
+                .replace("/deploy.yaml", &format!("/{}", files.deploy))
+                .replace("/cleanup.yaml", &format!("/{}", files.cleanup));
+            let filename = case.filename.clone();
+            handles.insert(filename, self.mock_case(case, owner, repo));
         }
         GithubMockedHandles(handles)
     }
+
+    /// Replaces mocked response of the case `name` with modified one,
+    /// for example to make workflow run look unfinished
+    pub fn override_response<'a>(
+        &'a self,
+        handles: &mut GithubMockedHandles<'a>,
+        name: &str,
+        modify: impl FnOnce(&mut serde_json::Value),
+    ) {
+        self.override_case(handles, name, |case| modify(&mut case.response));
+    }
+
+    /// Replaces mocked response of the case `name` with github error with given status
+    pub fn override_status<'a>(
+        &'a self,
+        handles: &mut GithubMockedHandles<'a>,
+        name: &str,
+        status: u16,
+    ) {
+        self.override_case(handles, name, |case| {
+            case.status = status;
+            case.response = serde_json::json!({
+                "message": format!("mocked error with status {status}"),
+                "documentation_url": "https://docs.github.com/rest",
+            });
+        });
+    }
+
+    /// Replaces mocked response of the case `name` with rate limit error,
+    /// that asks to retry after `retry_after_secs`
+    pub fn override_rate_limited<'a>(
+        &'a self,
+        handles: &mut GithubMockedHandles<'a>,
+        name: &str,
+        retry_after_secs: u64,
+    ) {
+        self.override_case(handles, name, |case| {
+            case.status = 429;
+            case.headers
+                .insert("retry-after".to_string(), retry_after_secs.to_string());
+            case.response = serde_json::json!({
+                "message": "You have exceeded a secondary rate limit",
+                "documentation_url": "https://docs.github.com/rest",
+            });
+        });
+    }
+
+    /// Replaces mocked response of the case `name` with modified one tagged with `etag`,
+    /// conditional requests with the etag get `304 Not Modified` without a run in the body.
+    /// Returns the mock of not modified responses
+    pub fn override_etag<'a>(
+        &'a self,
+        handles: &mut GithubMockedHandles<'a>,
+        name: &str,
+        etag: &str,
+        modify: impl FnOnce(&mut serde_json::Value),
+    ) -> Mock<'a> {
+        let url = self.case_url(&find_case(name), &self.owner, &self.repo);
+        // mock registered first is matched first, so it goes before the case
+        let not_modified = self.server.mock(|when, then| {
+            when.method(Method::GET)
+                .path(url)
+                .header("if-none-match", etag);
+            then.status(304).body("not a workflow run");
+        });
+        self.override_case(handles, name, |case| {
+            case.headers.insert("etag".to_string(), etag.to_string());
+            modify(&mut case.response);
+        });
+        not_modified
+    }
+
+    /// Replaces mocked case `name` with one which matches only requests containing `body`,
+    /// for example to check the ref which workflow is dispatched against
+    pub fn expect_json_body<'a>(
+        &'a self,
+        handles: &mut GithubMockedHandles<'a>,
+        name: &str,
+        body: serde_json::Value,
+    ) {
+        self.override_case(handles, name, |case| case.body = Some(body));
+    }
+
+    /// Mocks commit which `git_ref` points to, so the ref exists in the repo
+    pub fn mock_git_ref(&self, git_ref: &str) -> Mock {
+        let url = format!("/repos/{}/{}/commits/{git_ref}", self.owner, self.repo);
+        self.server.mock(|when, then| {
+            when.method(Method::GET).path(url);
+            then.status(200).json_body(
+                serde_json::json!({ "sha": "91b4d82a2ca1e9714b257b9d2a00370aa58077b0" }),
+            );
+        })
+    }
+
+    /// Mocks billing of github actions of the owner of the repo
+    pub fn mock_actions_billing(&self, included_minutes: u64, total_minutes_used: u64) -> Mock {
+        let url = format!("/orgs/{}/settings/billing/actions", self.owner);
+        self.server.mock(|when, then| {
+            when.method(Method::GET).path(url);
+            then.status(200).json_body(serde_json::json!({
+                "total_minutes_used": total_minutes_used,
+                "total_paid_minutes_used": 0,
+                "included_minutes": included_minutes,
+                "minutes_used_breakdown": { "UBUNTU": total_minutes_used },
+            }));
+        })
+    }
+
+    /// Restores original mocked response of the case `name`
+    pub fn reset_response<'a>(&'a self, handles: &mut GithubMockedHandles<'a>, name: &str) {
+        self.override_case(handles, name, |_| {});
+    }
+
+    fn override_case<'a>(
+        &'a self,
+        handles: &mut GithubMockedHandles<'a>,
+        name: &str,
+        modify: impl FnOnce(&mut MockCase),
+    ) {
+        let filename = case_filename(name);
+        let mut case = find_case(name);
+        modify(&mut case);
+        if let Some(mut old) = handles.0.remove(&filename) {
+            old.delete();
+        }
+        handles
+            .0
+            .insert(filename, self.mock_case(case, &self.owner, &self.repo));
+    }
+
+    fn case_url(&self, case: &MockCase, owner: &str, repo: &str) -> String {
+        case.url.replace("{owner}", owner).replace("{repo}", repo)
+    }
+
+    fn mock_case(&self, case: MockCase, owner: &str, repo: &str) -> Mock {
+        let url = self.case_url(&case, owner, repo);
+        self.server.mock(|when, then| {
+            let when = when.method(case.method).path(&url);
+            if let Some(body) = &case.body {
+                when.json_body_partial(body.to_string());
+            }
+            let then = case
+                .headers
+                .iter()
+                .fold(then.status(case.status), |then, (name, value)| {
+                    then.header(name, value)
+                });
+            then.json_body(case.response);
+        })
+    }
 }
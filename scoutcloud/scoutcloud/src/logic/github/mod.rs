@@ -1,11 +1,33 @@
+mod allowlist;
 mod api;
+mod auth;
+mod budget;
+mod circuit_breaker;
+mod inputs;
+pub mod logs;
 mod mock;
+mod pins;
+mod rate_limit;
+mod request;
+mod run_cache;
 pub(crate) mod types;
+pub mod webhook;
 mod workflows;
 
+pub use allowlist::RepoAllowlist;
+pub use budget::{ActionsBilling, ActionsBudget};
+pub use inputs::*;
 pub use mock::*;
+pub use pins::WorkflowPins;
 pub use workflows::*;
 
+use crate::server::{DeploymentTargetSettings, GithubAppSettings, GithubSettings};
+use auth::{GithubAuth, InstallationTokenCache};
+use circuit_breaker::CircuitBreaker;
+use octocrab::models::RunId;
+use request::RequestOptions;
+use run_cache::{RunEtags, WorkflowRunCache, DEFAULT_RUN_CACHE_TTL};
+use std::{borrow::Cow, collections::BTreeMap, sync::Arc, time::Duration};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,18 +36,188 @@ pub enum GithubError {
     Octocrab(#[from] octocrab::Error),
     #[error("failed to create file: {0}")]
     CreatingFile(anyhow::Error),
-    #[error("github workflow error: {0}")]
-    GithubWorkflow(anyhow::Error),
+    #[error("github workflow run {run_id} failed. conclusion={conclusion:?}")]
+    WorkflowFailed {
+        run_id: RunId,
+        conclusion: types::RunConclusion,
+    },
+    #[error("timed out waiting for github workflow run {run_id}. status={status:?}")]
+    WorkflowTimeout {
+        run_id: RunId,
+        status: types::RunStatus,
+    },
+    #[error(
+        "github workflow run {run_id} stayed queued for {queued_for:?}, no runner picked it up"
+    )]
+    NoRunnerAvailable { run_id: RunId, queued_for: Duration },
+    #[error("waiting for github workflow was interrupted by shutdown")]
+    Interrupted,
+    #[error("github api is unavailable, requests are paused for {0:?}")]
+    CircuitOpen(Duration),
+    #[error("invalid inputs of github workflow {workflow}: {source}")]
+    InvalidWorkflowInputs {
+        workflow: String,
+        source: InputsError,
+    },
+    #[error(
+        "workflow file `{workflow}` was changed in the repo: pinned sha is {expected}, \
+         but the file has sha {actual}. Review the change and update the pinned sha"
+    )]
+    WorkflowDrift {
+        workflow: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("invalid github api base url `{0}`: {1}")]
+    InvalidBaseUrl(String, String),
+    #[error("deployment target `{0}` is not configured")]
+    UnknownDeploymentTarget(String),
+    #[error("repo `{0}` is not in the allowlist of repos, its workflows can't be dispatched")]
+    RepoNotAllowed(String),
+    #[error("git ref `{0}` was not found in the repo")]
+    GitRefNotFound(String),
+    #[error("github workflow run {0} is not found anymore")]
+    RunDisappeared(RunId),
+    #[error(
+        "github actions of `{owner}` have {remaining_minutes} minutes left, \
+         but at least {min_remaining_minutes} minutes are required to deploy"
+    )]
+    ActionsBudgetExhausted {
+        owner: String,
+        remaining_minutes: u64,
+        min_remaining_minutes: u64,
+    },
+    /// Request to other ci backend, like gitlab
+    #[error("ci request failed: {0}")]
+    Request(#[from] reqwest::Error),
     #[error("internal error: {0}")]
     Internal(#[from] anyhow::Error),
 }
 
+impl GithubError {
+    /// Network failures and server-side errors of github may disappear on retry,
+    /// while errors like missing repository or workflow will never succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            GithubError::Octocrab(err) => is_retryable_octocrab_error(err),
+            GithubError::CreatingFile(err) => err
+                .chain()
+                .find_map(|err| err.downcast_ref::<GithubError>())
+                .is_some_and(GithubError::is_retryable),
+            GithubError::CircuitOpen(_) => true,
+            GithubError::Request(err) => {
+                err.is_timeout()
+                    || err.is_connect()
+                    || err
+                        .status()
+                        .is_some_and(|status| status.is_server_error() || status.as_u16() == 429)
+            }
+            GithubError::WorkflowFailed { .. }
+            | GithubError::WorkflowTimeout { .. }
+            | GithubError::NoRunnerAvailable { .. }
+            | GithubError::Interrupted
+            | GithubError::InvalidWorkflowInputs { .. }
+            | GithubError::WorkflowDrift { .. }
+            | GithubError::InvalidBaseUrl(_, _)
+            | GithubError::UnknownDeploymentTarget(_)
+            | GithubError::RepoNotAllowed(_)
+            | GithubError::GitRefNotFound(_)
+            | GithubError::RunDisappeared(_)
+            | GithubError::ActionsBudgetExhausted { .. }
+            | GithubError::Internal(_) => false,
+        }
+    }
+
+    /// Github responded that the requested object doesn't exist or is not accessible
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            GithubError::Octocrab(octocrab::Error::GitHub { source, .. })
+                if source.status_code.as_u16() == 404
+        )
+    }
+
+    /// Request was not answered in time, see `GithubRequestSettings`
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            GithubError::Octocrab(err) => {
+                std::iter::successors(Some(err as &dyn std::error::Error), |err| err.source())
+                    .filter_map(|err| err.downcast_ref::<std::io::Error>())
+                    .any(|err| err.kind() == std::io::ErrorKind::TimedOut)
+            }
+            GithubError::Request(err) => err.is_timeout(),
+            _ => false,
+        }
+    }
+
+    /// Github responds with 429 on secondary rate limit and with 403 on primary one.
+    /// Such responses were already retried, see `send_with_rate_limit_retry`
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            GithubError::Octocrab(octocrab::Error::GitHub { source, .. }) => {
+                let status = source.status_code.as_u16();
+                status == 429
+                    || (status == 403 && source.message.to_lowercase().contains("rate limit"))
+            }
+            GithubError::CreatingFile(err) => err
+                .chain()
+                .find_map(|err| err.downcast_ref::<GithubError>())
+                .is_some_and(GithubError::is_rate_limited),
+            GithubError::Request(err) => err.status().is_some_and(|status| status.as_u16() == 429),
+            _ => false,
+        }
+    }
+
+    /// Returns remaining cooldown if the request was not sent because github is unavailable
+    pub fn circuit_open_for(&self) -> Option<Duration> {
+        match self {
+            GithubError::CircuitOpen(remaining) => Some(*remaining),
+            GithubError::CreatingFile(err) => err
+                .chain()
+                .find_map(|err| err.downcast_ref::<GithubError>())
+                .and_then(GithubError::circuit_open_for),
+            _ => None,
+        }
+    }
+}
+
+fn is_retryable_octocrab_error(err: &octocrab::Error) -> bool {
+    match err {
+        octocrab::Error::GitHub { source, .. } => {
+            // 429 is returned when secondary rate limit is exceeded
+            source.status_code.is_server_error() || source.status_code.as_u16() == 429
+        }
+        octocrab::Error::Hyper { .. } | octocrab::Error::Service { .. } => true,
+        _ => false,
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GithubClient {
-    client: octocrab::Octocrab,
+    auth: GithubAuth,
+    run_cache: Arc<WorkflowRunCache>,
+    run_etags: Arc<RunEtags>,
+    breaker: Arc<CircuitBreaker>,
+    request: RequestOptions,
     owner: String,
     repo: String,
     default_branch_name: String,
+    /// Label of the region served by the repo, set only for deployment targets
+    region: Option<String>,
+    /// Base url of the web interface, runs are linked to it
+    web_url: String,
+    workflow_pins: Arc<WorkflowPins>,
+    /// Shared with clients of deployment targets, which are added after it
+    repo_allowlist: Arc<RepoAllowlist>,
+    workflow_files: Arc<WorkflowFiles>,
+    /// Runs which stay queued for longer fail early, queued runs are waited for
+    /// like other unfinished runs if it is not set
+    queued_timeout: Option<Duration>,
+    /// Deploys are not checked against remaining actions minutes if it is not set
+    actions_budget: Option<Arc<ActionsBudget>>,
+    /// Clients of repos of deployment targets, they share auth and circuit breaker
+    /// with this client
+    targets: Arc<BTreeMap<String, GithubClient>>,
 }
 
 impl GithubClient {
@@ -35,36 +227,330 @@ impl GithubClient {
         repo: String,
         default_branch_name: Option<String>,
         uri: Option<&str>,
-    ) -> Result<Self, octocrab::Error> {
-        let mut builder = octocrab::Octocrab::builder();
-        if let Some(uri) = uri {
-            builder = builder.base_uri(uri)?;
-        }
-        let client = builder.personal_token(token).build()?;
-        Ok(Self {
-            client,
+    ) -> Result<Self, GithubError> {
+        Self::with_personal_token(
+            token,
+            owner,
+            repo,
+            default_branch_name,
+            uri,
+            RequestOptions::default(),
+        )
+    }
+
+    fn with_personal_token(
+        token: String,
+        owner: String,
+        repo: String,
+        default_branch_name: Option<String>,
+        uri: Option<&str>,
+        request: RequestOptions,
+    ) -> Result<Self, GithubError> {
+        let uri = uri.map(validate_base_url).transpose()?;
+        let client = request.personal_token_client(token, uri.as_deref())?;
+        Ok(Self::with_auth(
+            GithubAuth::PersonalToken(client),
+            request,
+            owner,
+            repo,
+            default_branch_name,
+            web_url(uri.as_deref()),
+        ))
+    }
+
+    /// Creates client authenticated as github app installation.
+    /// Installation tokens are minted on demand and refreshed before expiry
+    pub fn new_app_installation(
+        app_id: u64,
+        installation_id: u64,
+        private_key: &str,
+        owner: String,
+        repo: String,
+        default_branch_name: Option<String>,
+        uri: Option<&str>,
+    ) -> Result<Self, GithubError> {
+        let app = GithubAppSettings {
+            app_id,
+            installation_id,
+            private_key: private_key.to_string(),
+        };
+        Self::with_app_installation(
+            &app,
+            owner,
+            repo,
+            default_branch_name,
+            uri,
+            RequestOptions::default(),
+        )
+    }
+
+    fn with_app_installation(
+        app: &GithubAppSettings,
+        owner: String,
+        repo: String,
+        default_branch_name: Option<String>,
+        uri: Option<&str>,
+        request: RequestOptions,
+    ) -> Result<Self, GithubError> {
+        let uri = uri.map(validate_base_url).transpose()?;
+        let cache = InstallationTokenCache::new(
+            app.app_id,
+            app.installation_id,
+            &app.private_key,
+            uri.as_deref(),
+            request,
+        )?;
+        Ok(Self::with_auth(
+            GithubAuth::AppInstallation(Arc::new(cache)),
+            request,
+            owner,
+            repo,
+            default_branch_name,
+            web_url(uri.as_deref()),
+        ))
+    }
+
+    fn with_auth(
+        auth: GithubAuth,
+        request: RequestOptions,
+        owner: String,
+        repo: String,
+        default_branch_name: Option<String>,
+        web_url: String,
+    ) -> Self {
+        Self {
+            auth,
+            run_cache: Arc::new(WorkflowRunCache::new(DEFAULT_RUN_CACHE_TTL)),
+            run_etags: Default::default(),
+            breaker: Default::default(),
+            request,
             owner,
             repo,
             default_branch_name: default_branch_name.unwrap_or("main".to_string()),
+            region: None,
+            web_url,
+            workflow_pins: Default::default(),
+            repo_allowlist: Default::default(),
+            workflow_files: Default::default(),
+            queued_timeout: None,
+            actions_budget: None,
+            targets: Default::default(),
+        }
+    }
+
+    /// Uses github app if it is configured, otherwise falls back to personal access token
+    pub fn from_settings(settings: &GithubSettings) -> Result<Self, GithubError> {
+        let request = RequestOptions::from_settings(&settings.request);
+        let client = match (&settings.app, &settings.token) {
+            (Some(app), _) => Self::with_app_installation(
+                app,
+                settings.owner.clone(),
+                settings.repo.clone(),
+                settings.branch.clone(),
+                settings.base_url.as_deref(),
+                request,
+            ),
+            (None, Some(token)) => Self::with_personal_token(
+                token.clone(),
+                settings.owner.clone(),
+                settings.repo.clone(),
+                settings.branch.clone(),
+                settings.base_url.as_deref(),
+                request,
+            ),
+            (None, None) => Err(GithubError::Internal(anyhow::anyhow!(
+                "either github token or github app should be configured"
+            ))),
+        }?;
+        let breaker = &settings.circuit_breaker;
+        let pins = WorkflowPins::new(
+            settings.pinned_workflows.clone(),
+            settings.allow_workflow_drift,
+        );
+        let mut client = client
+            .with_circuit_breaker(breaker.failure_threshold, breaker.cooldown)
+            .with_workflow_pins(pins)
+            .with_repo_allowlist(RepoAllowlist::from_settings(&settings.repo_allowlist)?)
+            .with_workflow_files(WorkflowFiles::from_settings(&settings.workflow_files))
+            .with_queued_timeout(settings.queued_timeout);
+        if let Some(budget) = &settings.actions_budget {
+            client = client.with_actions_budget(ActionsBudget::from_settings(budget));
+        }
+        Ok(client.with_deployment_targets(&settings.deployment_targets))
+    }
+
+    /// Adds clients of repos of deployment targets, see `for_target`.
+    /// They are created with the current auth, cache and circuit breaker of the client,
+    /// workflows of every repo are pinned separately. Targets without their own workflow
+    /// files use the files of the client
+    pub fn with_deployment_targets(
+        mut self,
+        targets: &BTreeMap<String, DeploymentTargetSettings>,
+    ) -> Self {
+        let clients = targets
+            .iter()
+            .map(|(name, target)| {
+                let client = Self {
+                    owner: target.owner.clone().unwrap_or_else(|| self.owner.clone()),
+                    repo: target.repo.clone(),
+                    default_branch_name: target.branch.clone().unwrap_or("main".to_string()),
+                    region: target.region.clone(),
+                    workflow_pins: Arc::new(WorkflowPins::new(
+                        target.pinned_workflows.clone(),
+                        self.workflow_pins.allow_drift(),
+                    )),
+                    workflow_files: target
+                        .workflow_files
+                        .as_ref()
+                        .map(|files| Arc::new(WorkflowFiles::from_settings(files)))
+                        .unwrap_or_else(|| self.workflow_files.clone()),
+                    targets: Default::default(),
+                    ..self.clone()
+                };
+                (name.clone(), client)
+            })
+            .collect();
+        self.targets = Arc::new(clients);
+        self
+    }
+
+    /// Returns client of the repo which runs workflows of the deployment target,
+    /// instances without target are deployed by the client itself
+    pub fn for_target(&self, target: Option<&str>) -> Result<&GithubClient, GithubError> {
+        match target {
+            None => Ok(self),
+            Some(name) => self
+                .targets
+                .get(name)
+                .ok_or_else(|| GithubError::UnknownDeploymentTarget(name.to_string())),
+        }
+    }
+
+    /// Returns client of a fork of the workflows, `owner/repo` from the config of the instance.
+    /// It is accessed with the same credentials as the client, only repos which are listed
+    /// in the allowlist may be used. Workflows of the fork differ from the pinned ones by design,
+    /// so they are not pinned, and its actions minutes are spent by the owner of the fork
+    pub fn for_workflow_repo(&self, full_name: &str) -> Result<GithubClient, GithubError> {
+        let Some((owner, repo)) = full_name.split_once('/') else {
+            return Err(GithubError::RepoNotAllowed(full_name.to_string()));
+        };
+        self.repo_allowlist.check_listed(owner, repo)?;
+        Ok(Self {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            region: None,
+            workflow_pins: Arc::new(WorkflowPins::new(
+                Default::default(),
+                self.workflow_pins.allow_drift(),
+            )),
+            actions_budget: None,
+            targets: Default::default(),
+            ..self.clone()
         })
     }
 
-    pub fn from_settings(
-        settings: &crate::server::GithubSettings,
-    ) -> Result<Self, octocrab::Error> {
-        Self::new(
-            settings.token.clone(),
-            settings.owner.clone(),
-            settings.repo.clone(),
-            settings.branch.clone(),
-            None,
+    /// Returns client of the repo which runs workflows of the instance:
+    /// its fork of the workflows if it is set, otherwise the repo of its deployment target
+    pub fn for_instance(
+        &self,
+        deployment_target: Option<&str>,
+        workflow_repo: Option<&str>,
+    ) -> Result<Cow<'_, GithubClient>, GithubError> {
+        match workflow_repo {
+            Some(full_name) => self.for_workflow_repo(full_name).map(Cow::Owned),
+            None => self.for_target(deployment_target).map(Cow::Borrowed),
+        }
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    /// Link to the run in github actions of the repo
+    pub fn run_url(&self, run_id: RunId) -> String {
+        format!(
+            "{}/{}/{}/actions/runs/{run_id}",
+            self.web_url, self.owner, self.repo
         )
     }
+
+    pub fn with_workflow_pins(mut self, pins: WorkflowPins) -> Self {
+        self.workflow_pins = Arc::new(pins);
+        self
+    }
+
+    pub fn with_repo_allowlist(mut self, allowlist: RepoAllowlist) -> Self {
+        self.repo_allowlist = Arc::new(allowlist);
+        self
+    }
+
+    pub fn with_workflow_files(mut self, files: WorkflowFiles) -> Self {
+        self.workflow_files = Arc::new(files);
+        self
+    }
+
+    pub fn workflow_files(&self) -> &WorkflowFiles {
+        &self.workflow_files
+    }
+
+    pub fn with_queued_timeout(mut self, queued_timeout: Option<Duration>) -> Self {
+        self.queued_timeout = queued_timeout;
+        self
+    }
+
+    /// Zero `ttl` disables caching of workflow runs
+    pub fn with_run_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.run_cache = Arc::new(WorkflowRunCache::new(ttl));
+        self
+    }
+
+    /// Breaker is opened after `failure_threshold` failed requests in a row
+    /// and stays open for `cooldown`. It is shared by all clones of the client
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.breaker = Arc::new(CircuitBreaker::new(failure_threshold, cooldown));
+        self
+    }
+
+    /// Budget and its cache are shared by all clones of the client and by clients
+    /// of deployment targets, which are added after it
+    pub fn with_actions_budget(mut self, budget: ActionsBudget) -> Self {
+        self.actions_budget = Some(Arc::new(budget));
+        self
+    }
+
+    async fn client(&self) -> Result<octocrab::Octocrab, GithubError> {
+        self.auth.client().await
+    }
+}
+
+/// Checks that api url is absolute http url, so misconfiguration is found on startup
+/// instead of the first deployment. Returns url without trailing slash
+fn validate_base_url(uri: &str) -> Result<String, GithubError> {
+    let invalid = |reason: &str| GithubError::InvalidBaseUrl(uri.to_string(), reason.to_string());
+    let url = url::Url::parse(uri).map_err(|err| invalid(&err.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid("scheme should be http or https"));
+    }
+    if url.host_str().unwrap_or_default().is_empty() {
+        return Err(invalid("host is missing"));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid("query and fragment are not allowed"));
+    }
+    Ok(uri.trim_end_matches('/').to_string())
+}
+
+/// Api of github enterprise server is served under `/api/v3` of its web interface
+fn web_url(base_url: Option<&str>) -> String {
+    match base_url {
+        Some(url) => url.strip_suffix("/api/v3").unwrap_or(url).to_string(),
+        None => "https://github.com".to_string(),
+    }
 }
 
 #[cfg(test)]
 impl TryFrom<&MockedGithubRepo> for GithubClient {
-    type Error = octocrab::Error;
+    type Error = GithubError;
 
     fn try_from(mock: &MockedGithubRepo) -> Result<Self, Self::Error> {
         Self::new(
@@ -76,3 +562,123 @@ impl TryFrom<&MockedGithubRepo> for GithubClient {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(token: Option<&str>, app: Option<GithubAppSettings>) -> GithubSettings {
+        GithubSettings {
+            token: token.map(str::to_string),
+            app,
+            owner: "test-owner".to_string(),
+            repo: "test-repo".to_string(),
+            branch: None,
+            base_url: None,
+            webhook_secret: None,
+            circuit_breaker: Default::default(),
+            request: Default::default(),
+            deployment_targets: Default::default(),
+            repo_allowlist: Default::default(),
+            pinned_workflows: Default::default(),
+            workflow_files: Default::default(),
+            queued_timeout: None,
+            allow_workflow_drift: false,
+            actions_budget: None,
+        }
+    }
+
+    fn app_settings() -> GithubAppSettings {
+        GithubAppSettings {
+            app_id: 1,
+            installation_id: 12345,
+            private_key: TEST_APP_PRIVATE_KEY.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn from_settings_falls_back_to_personal_token() {
+        let client = GithubClient::from_settings(&settings(Some("test-token"), None)).unwrap();
+        assert!(matches!(client.auth, GithubAuth::PersonalToken(_)));
+
+        let client =
+            GithubClient::from_settings(&settings(Some("test-token"), Some(app_settings())))
+                .unwrap();
+        assert!(matches!(client.auth, GithubAuth::AppInstallation(_)));
+
+        GithubClient::from_settings(&settings(None, None))
+            .expect_err("client without credentials should not be created");
+    }
+
+    #[tokio::test]
+    async fn malformed_base_url_is_rejected() {
+        for base_url in [
+            "not a url",
+            "ftp://github.example.com",
+            "https://",
+            "http://host/?q=1",
+        ] {
+            let settings = GithubSettings {
+                base_url: Some(base_url.to_string()),
+                ..settings(Some("test-token"), None)
+            };
+            let result = GithubClient::from_settings(&settings);
+            assert!(
+                matches!(result, Err(GithubError::InvalidBaseUrl(_, _))),
+                "base url '{base_url}' should be rejected"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_base_url_is_used_for_stop_cycle() {
+        let mock = MockedGithubRepo::default();
+        let handles = mock.build_handles();
+        // enterprise api is usually served under `/api/v3`, but mock serves repos at root
+        let settings = GithubSettings {
+            base_url: Some(format!("http://localhost:{}/", mock.server.port())),
+            ..settings(Some("test-token"), None)
+        };
+        let client = GithubClient::from_settings(&settings).unwrap();
+
+        let run = CleanupWorkflow::new("test-client".to_string())
+            .run_and_get_latest_with_mutex(&client, 5)
+            .await
+            .unwrap()
+            .expect("no workflows returned");
+        client
+            .wait_for_success_workflow(
+                &crate::logic::ci::CiRun::from(&run),
+                Duration::from_secs(5),
+                PollBackoff::from_initial(Duration::from_millis(100)),
+                &tokio_util::sync::CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        handles.assert_hits("runs_cleanup_yaml", 1);
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    async fn app_installation_client_works() {
+        let mock = MockedGithubRepo::default();
+        let handles = mock.build_handles();
+        let app = app_settings();
+        let client = GithubClient::new_app_installation(
+            app.app_id,
+            app.installation_id,
+            &app.private_key,
+            mock.owner.clone(),
+            mock.repo.clone(),
+            Some(mock.default_main_branch.clone()),
+            Some(mock.server.base_url().as_str()),
+        )
+        .unwrap();
+
+        client.get_workflow_run(RunId(8819501307)).await.unwrap();
+        client.get_workflow_run(RunId(8819501307)).await.unwrap();
+        handles.assert_hits("app_installation_token", 1);
+        handles.assert_hits("single_run_cleanup_yaml", 2);
+    }
+}
@@ -0,0 +1,46 @@
+use super::GithubError;
+use std::collections::BTreeMap;
+
+/// Expected blob shas of workflow files of the repo. Workflows are dispatched only
+/// while their files match the pinned shas, so changes of workflows in the repo
+/// are reviewed before deployments use them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkflowPins {
+    shas: BTreeMap<String, String>,
+    allow_drift: bool,
+}
+
+impl WorkflowPins {
+    /// Changed files are only reported in logs if `allow_drift` is set
+    pub fn new(shas: BTreeMap<String, String>, allow_drift: bool) -> Self {
+        Self { shas, allow_drift }
+    }
+
+    pub fn allow_drift(&self) -> bool {
+        self.allow_drift
+    }
+
+    /// Workflows without pinned sha are not checked
+    pub fn check(&self, workflow: &str, actual_sha: &str) -> Result<(), GithubError> {
+        let Some(expected) = self.shas.get(workflow) else {
+            return Ok(());
+        };
+        if expected == actual_sha {
+            return Ok(());
+        }
+        if self.allow_drift {
+            tracing::warn!(
+                workflow = workflow,
+                expected_sha = expected,
+                actual_sha = actual_sha,
+                "workflow file differs from the pinned one, dispatching it anyway"
+            );
+            return Ok(());
+        }
+        Err(GithubError::WorkflowDrift {
+            workflow: workflow.to_string(),
+            expected: expected.clone(),
+            actual: actual_sha.to_string(),
+        })
+    }
+}
@@ -0,0 +1,150 @@
+use super::{GithubClient, GithubError};
+use chrono::{DateTime, Utc};
+use http::{HeaderMap, StatusCode};
+use std::{future::Future, time::Duration};
+
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+const MAX_RATE_LIMIT_DELAY: Duration = Duration::from_secs(120);
+// github docs suggest to wait at least one minute if there is no hint in headers
+const DEFAULT_RATE_LIMIT_DELAY: Duration = Duration::from_secs(60);
+
+/// Returns how long to wait before the next request,
+/// if `response` says that primary or secondary rate limit is exceeded.
+/// See https://docs.github.com/en/rest/using-the-rest-api/best-practices-for-using-the-rest-api#handle-rate-limit-errors-appropriately
+pub(super) fn rate_limit_delay<B>(
+    response: &http::Response<B>,
+    now: DateTime<Utc>,
+) -> Option<Duration> {
+    let headers = response.headers();
+    let retry_after = header_value::<u64>(headers, "retry-after").map(Duration::from_secs);
+    let limit_exhausted = header_value::<u64>(headers, "x-ratelimit-remaining") == Some(0);
+    let reset_after = header_value::<i64>(headers, "x-ratelimit-reset")
+        .map(|reset| Duration::from_secs(reset.saturating_sub(now.timestamp()).max(0) as u64));
+
+    let is_rate_limited = match response.status() {
+        StatusCode::TOO_MANY_REQUESTS => true,
+        // 403 is also returned for missing permissions, so rely on headers only
+        StatusCode::FORBIDDEN => retry_after.is_some() || limit_exhausted,
+        _ => false,
+    };
+    if !is_rate_limited {
+        return None;
+    }
+    let delay = retry_after
+        .or(reset_after.filter(|_| limit_exhausted))
+        .unwrap_or(DEFAULT_RATE_LIMIT_DELAY);
+    Some(delay)
+}
+
+fn header_value<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+impl GithubClient {
+    /// Sends request built by `send` and repeats it while github responds with rate limit error,
+    /// but not more than `MAX_RATE_LIMIT_RETRIES` times.
+    /// Last response is returned as is, so the caller should map github errors itself
+    pub(super) async fn send_with_rate_limit_retry<B, F, Fut>(
+        &self,
+        mut send: F,
+    ) -> Result<http::Response<B>, GithubError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<http::Response<B>, octocrab::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let response = send().await?;
+            match rate_limit_delay(&response, Utc::now()) {
+                Some(delay)
+                    if attempt < MAX_RATE_LIMIT_RETRIES && delay <= MAX_RATE_LIMIT_DELAY =>
+                {
+                    attempt += 1;
+                    tracing::warn!(
+                        status = response.status().as_u16(),
+                        delay =? delay,
+                        attempt = attempt,
+                        "github rate limit exceeded, retrying after delay"
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, headers: &[(&str, &str)]) -> http::Response<()> {
+        let mut builder = http::Response::builder().status(status);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn rate_limit_delay_uses_headers() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let cases = [
+            (response(200, &[]), None),
+            (response(200, &[("retry-after", "5")]), None),
+            (response(403, &[]), None),
+            (response(404, &[("x-ratelimit-remaining", "0")]), None),
+            (response(429, &[]), Some(DEFAULT_RATE_LIMIT_DELAY)),
+            (
+                response(429, &[("retry-after", "1")]),
+                Some(Duration::from_secs(1)),
+            ),
+            (
+                response(403, &[("retry-after", "7")]),
+                Some(Duration::from_secs(7)),
+            ),
+            (
+                response(
+                    403,
+                    &[
+                        ("x-ratelimit-remaining", "0"),
+                        ("x-ratelimit-reset", "1700000030"),
+                    ],
+                ),
+                Some(Duration::from_secs(30)),
+            ),
+            (
+                response(
+                    429,
+                    &[
+                        ("x-ratelimit-remaining", "0"),
+                        ("x-ratelimit-reset", "1699999990"),
+                    ],
+                ),
+                Some(Duration::ZERO),
+            ),
+            (
+                response(
+                    403,
+                    &[
+                        ("x-ratelimit-remaining", "10"),
+                        ("x-ratelimit-reset", "1700000030"),
+                    ],
+                ),
+                None,
+            ),
+        ];
+        for (response, expected) in cases {
+            assert_eq!(
+                rate_limit_delay(&response, now),
+                expected,
+                "status={}, headers={:?}",
+                response.status(),
+                response.headers()
+            );
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::{GithubClient, GithubError};
+use crate::server::GithubRequestSettings;
+use jsonwebtoken::EncodingKey;
+use octocrab::{models::AppId, Octocrab};
+use std::{future::Future, time::Duration};
+
+const GET_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Limits of single http requests to github api. Waiting for workflows is limited
+/// separately, so a hung connection doesn't block the task until the workflow timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct RequestOptions {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    /// Idempotent GET requests are repeated after timeouts and network failures
+    /// up to this number of times, other requests are sent once
+    pub max_get_retries: u32,
+}
+
+impl RequestOptions {
+    pub fn from_settings(settings: &GithubRequestSettings) -> Self {
+        Self {
+            connect_timeout: settings.connect_timeout,
+            read_timeout: settings.read_timeout,
+            max_get_retries: settings.max_get_retries,
+        }
+    }
+
+    pub fn personal_token_client(
+        &self,
+        token: String,
+        base_uri: Option<&str>,
+    ) -> Result<Octocrab, GithubError> {
+        let mut builder = Octocrab::builder()
+            .set_connect_timeout(Some(self.connect_timeout))
+            .set_read_timeout(Some(self.read_timeout));
+        if let Some(uri) = base_uri {
+            builder = builder.base_uri(uri)?;
+        }
+        Ok(builder.personal_token(token).build()?)
+    }
+
+    pub fn app_client(
+        &self,
+        app_id: u64,
+        key: EncodingKey,
+        base_uri: Option<&str>,
+    ) -> Result<Octocrab, GithubError> {
+        let mut builder = Octocrab::builder()
+            .set_connect_timeout(Some(self.connect_timeout))
+            .set_read_timeout(Some(self.read_timeout));
+        if let Some(uri) = base_uri {
+            builder = builder.base_uri(uri)?;
+        }
+        Ok(builder.app(AppId(app_id), key).build()?)
+    }
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        Self::from_settings(&Default::default())
+    }
+}
+
+/// Response was not received at all, so the request is safe to repeat if it is idempotent
+pub(super) fn is_network_failure(err: &GithubError) -> bool {
+    matches!(
+        err,
+        GithubError::Octocrab(octocrab::Error::Hyper { .. } | octocrab::Error::Service { .. })
+    )
+}
+
+impl GithubClient {
+    /// Sends idempotent GET request built by `send` and repeats it after timeouts
+    /// and network failures, but not more than `max_get_retries` times.
+    /// Rate limit errors are retried by `send_with_rate_limit_retry` as usual
+    pub(super) async fn send_get_with_retry<B, F, Fut>(
+        &self,
+        mut send: F,
+    ) -> Result<http::Response<B>, GithubError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<http::Response<B>, octocrab::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match self.send_with_rate_limit_retry(&mut send).await {
+                Err(err) if attempt < self.request.max_get_retries && is_network_failure(&err) => {
+                    attempt += 1;
+                    tracing::warn!(
+                        attempt = attempt,
+                        "github request failed: {}, retrying",
+                        err
+                    );
+                    tokio::time::sleep(GET_RETRY_DELAY * attempt).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::{github::MockedGithubRepo, DeployError},
+        server::GithubSettings,
+    };
+    use octocrab::models::RunId;
+    use pretty_assertions::assert_eq;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn hung_request_times_out_and_is_retried() {
+        let mock = MockedGithubRepo::default();
+        let hung = mock.server.mock(|when, then| {
+            when.method(httpmock::Method::GET)
+                .path("/repos/test-owner/test-repo/actions/runs/1");
+            then.status(200).delay(Duration::from_secs(30));
+        });
+        let settings = GithubSettings {
+            token: Some(mock.token.clone()),
+            app: None,
+            owner: mock.owner.clone(),
+            repo: mock.repo.clone(),
+            branch: Some(mock.default_main_branch.clone()),
+            base_url: Some(mock.server.base_url()),
+            webhook_secret: None,
+            circuit_breaker: Default::default(),
+            request: GithubRequestSettings {
+                connect_timeout: Duration::from_secs(1),
+                read_timeout: Duration::from_millis(200),
+                max_get_retries: 2,
+            },
+            deployment_targets: Default::default(),
+            repo_allowlist: Default::default(),
+            pinned_workflows: Default::default(),
+            workflow_files: Default::default(),
+            queued_timeout: None,
+            allow_workflow_drift: false,
+            actions_budget: None,
+        };
+        let client = GithubClient::from_settings(&settings).unwrap();
+
+        let started = Instant::now();
+        let err = client
+            .get_workflow_run(RunId(1))
+            .await
+            .expect_err("hung request should time out");
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(err.is_timeout(), "{err:?}");
+        assert_eq!(hung.hits(), 3);
+        assert!(DeployError::from(err).is_retryable());
+    }
+}
@@ -0,0 +1,300 @@
+use super::{types::RunStatus, GithubClient, GithubError};
+use octocrab::models::{workflows::Run, RunId};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_RUN_CACHE_TTL: Duration = Duration::from_secs(2);
+// completed runs never change, but there is no reason to keep them forever
+const COMPLETED_RUN_RETENTION: Duration = Duration::from_secs(10 * 60);
+// runs which are not polled anymore are forgotten together with their etags
+const ETAG_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+type RunKey = (String, RunId);
+type RunSlot = Arc<tokio::sync::Mutex<Option<CachedRun>>>;
+
+/// Short-lived cache of workflow runs shared by all pollers of the client.
+/// Every run has its own lock, so concurrent pollers of the same run
+/// wait for a single request instead of sending their own.
+#[derive(Debug)]
+pub(super) struct WorkflowRunCache {
+    ttl: Duration,
+    runs: Mutex<HashMap<RunKey, RunSlot>>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedRun {
+    run: Run,
+    fetched_at: Instant,
+    completed: bool,
+}
+
+impl CachedRun {
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        // completed run is final, so it is never stale
+        self.completed || self.fetched_at.elapsed() < ttl
+    }
+
+    fn is_retained(&self, ttl: Duration) -> bool {
+        let retention = if self.completed {
+            COMPLETED_RUN_RETENTION
+        } else {
+            ttl
+        };
+        self.fetched_at.elapsed() < retention
+    }
+}
+
+impl WorkflowRunCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            runs: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    fn slot(&self, key: RunKey) -> RunSlot {
+        let ttl = self.ttl;
+        let mut runs = self.runs.lock().expect("run cache lock is poisoned");
+        runs.retain(|_, slot| {
+            // keep slots which are in use right now
+            Arc::strong_count(slot) > 1
+                || slot.try_lock().map_or(true, |cached| {
+                    cached
+                        .as_ref()
+                        .is_some_and(|cached| cached.is_retained(ttl))
+                })
+        });
+        runs.entry(key).or_default().clone()
+    }
+
+    /// Forgets the run, so the next request fetches it from github.
+    /// Completed run is kept, since it can't change anymore
+    pub fn invalidate(&self, key: &RunKey) {
+        let mut runs = self.runs.lock().expect("run cache lock is poisoned");
+        let is_completed = runs.get(key).is_some_and(|slot| {
+            slot.try_lock()
+                .is_ok_and(|cached| cached.as_ref().is_some_and(|c| c.completed))
+        });
+        if !is_completed {
+            runs.remove(key);
+        }
+    }
+}
+
+/// Last seen `ETag` of every polled run together with the run itself,
+/// so repeated polls are sent as conditional requests and `304 Not Modified`
+/// responses reuse the last parsed run.
+/// Unlike `WorkflowRunCache`, etags are kept even if the cache is disabled
+#[derive(Debug, Default)]
+pub(super) struct RunEtags {
+    runs: Mutex<HashMap<RunKey, EtaggedRun>>,
+}
+
+#[derive(Debug, Clone)]
+struct EtaggedRun {
+    etag: String,
+    run: Run,
+    seen_at: Instant,
+}
+
+impl RunEtags {
+    pub fn get(&self, key: &RunKey) -> Option<(String, Run)> {
+        let runs = self.runs.lock().expect("run etags lock is poisoned");
+        runs.get(key)
+            .map(|tagged| (tagged.etag.clone(), tagged.run.clone()))
+    }
+
+    pub fn insert(&self, key: RunKey, etag: String, run: Run) {
+        let mut runs = self.runs.lock().expect("run etags lock is poisoned");
+        runs.retain(|_, tagged| tagged.seen_at.elapsed() < ETAG_RETENTION);
+        runs.insert(
+            key,
+            EtaggedRun {
+                etag,
+                run,
+                seen_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Marks the run as seen, when github reported that it is not modified
+    pub fn touch(&self, key: &RunKey) {
+        let mut runs = self.runs.lock().expect("run etags lock is poisoned");
+        if let Some(tagged) = runs.get_mut(key) {
+            tagged.seen_at = Instant::now();
+        }
+    }
+}
+
+impl GithubClient {
+    /// Same as `get_workflow_run`, but reuses the run fetched by other pollers
+    /// during the last `DEFAULT_RUN_CACHE_TTL`
+    pub async fn get_workflow_run_cached(&self, run_id: RunId) -> Result<Run, GithubError> {
+        if !self.run_cache.is_enabled() {
+            return self.get_workflow_run(run_id).await;
+        }
+        let slot = self.run_cache.slot(self.run_cache_key(run_id));
+        let mut cached = slot.lock().await;
+        if let Some(cached) = cached.as_ref().filter(|c| c.is_fresh(self.run_cache.ttl)) {
+            return Ok(cached.run.clone());
+        }
+        let run = self.get_workflow_run(run_id).await?;
+        let completed = RunStatus::try_from_str(&run.status)?.is_completed();
+        *cached = Some(CachedRun {
+            run: run.clone(),
+            fetched_at: Instant::now(),
+            completed,
+        });
+        Ok(run)
+    }
+
+    pub(super) fn invalidate_cached_run(&self, run_id: RunId) {
+        self.run_cache.invalidate(&self.run_cache_key(run_id));
+    }
+
+    pub(super) fn run_cache_key(&self, run_id: RunId) -> RunKey {
+        (format!("{}/{}", self.owner, self.repo), run_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::{ci::CiRun, github::PollBackoff},
+        tests_utils,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    const RUN_ID: RunId = RunId(8819501307);
+
+    async fn count_requests_of_concurrent_pollers(cache_ttl: Duration) -> usize {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client = client.with_run_cache_ttl(cache_ttl);
+        let mut handles = mock.build_handles();
+        mock.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+        let run = CiRun::from(&client.get_workflow_run(RUN_ID).await.unwrap());
+        let backoff = PollBackoff::new(Duration::from_millis(50), 1.0, Duration::from_millis(50))
+            .with_jitter(0.0);
+
+        let mut pollers = tokio::task::JoinSet::new();
+        for _ in 0..10 {
+            let (client, run) = (client.clone(), run.clone());
+            pollers.spawn(async move {
+                client
+                    .wait_for_success_workflow(
+                        &run,
+                        Duration::from_millis(300),
+                        backoff,
+                        &CancellationToken::new(),
+                    )
+                    .await
+            });
+        }
+        while let Some(result) = pollers.join_next().await {
+            let result = result.expect("poller panicked");
+            assert!(
+                matches!(result, Err(GithubError::WorkflowTimeout { .. })),
+                "expected timeout error, got {result:?}"
+            );
+        }
+        // exclude initial `get_workflow_run`
+        handles.with_name("single_run_cleanup_yaml").hits() - 1
+    }
+
+    #[tokio::test]
+    async fn run_cache_reduces_requests_of_concurrent_pollers() {
+        let without_cache = count_requests_of_concurrent_pollers(Duration::ZERO).await;
+        let with_cache = count_requests_of_concurrent_pollers(DEFAULT_RUN_CACHE_TTL).await;
+        // every poller checks the run around 7 times during timeout
+        assert!(
+            without_cache >= 10 * 5,
+            "unexpected number of requests without cache: {without_cache}"
+        );
+        // all checks happen within ttl
+        assert_eq!(with_cache, 1, "unexpected number of requests with cache");
+    }
+
+    #[tokio::test]
+    async fn run_cache_never_serves_stale_status_after_completion() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client = client.with_run_cache_ttl(Duration::from_secs(60));
+        let mut handles = mock.build_handles();
+        mock.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+
+        let run = client.get_workflow_run_cached(RUN_ID).await.unwrap();
+        assert_eq!(run.status, "in_progress");
+        let run = client.get_workflow_run_cached(RUN_ID).await.unwrap();
+        assert_eq!(run.status, "in_progress");
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+
+        // run is completed, and webhook reported it
+        mock.reset_response(&mut handles, "single_run_cleanup_yaml");
+        client.invalidate_cached_run(RUN_ID);
+        let run = client.get_workflow_run_cached(RUN_ID).await.unwrap();
+        assert_eq!(run.status, "completed");
+
+        // github returns outdated status, but completed run is final
+        mock.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+        client.invalidate_cached_run(RUN_ID);
+        let run = client.get_workflow_run_cached(RUN_ID).await.unwrap();
+        assert_eq!(run.status, "completed");
+        handles.assert_hits("single_run_cleanup_yaml", 0);
+    }
+
+    #[tokio::test]
+    async fn not_modified_run_is_not_parsed_again() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client = client.with_run_cache_ttl(Duration::ZERO);
+        let mut handles = mock.build_handles();
+        let not_modified =
+            mock.override_etag(&mut handles, "single_run_cleanup_yaml", "\"v1\"", |run| {
+                run["status"] = "in_progress".into();
+                run["conclusion"] = serde_json::Value::Null;
+            });
+
+        let run = client.get_workflow_run(RUN_ID).await.unwrap();
+        assert_eq!(run.status, "in_progress");
+        // body of 304 is not a run, so parsing it would fail
+        let run = client.get_workflow_run(RUN_ID).await.unwrap();
+        assert_eq!(run.status, "in_progress");
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+        not_modified.assert_hits(1);
+
+        let backoff = PollBackoff::new(Duration::from_millis(50), 1.0, Duration::from_millis(50))
+            .with_jitter(0.0);
+        let result = client
+            .wait_for_success_workflow(
+                &CiRun::from(&run),
+                Duration::from_millis(300),
+                backoff,
+                &CancellationToken::new(),
+            )
+            .await;
+        assert!(
+            matches!(result, Err(GithubError::WorkflowTimeout { .. })),
+            "expected timeout error, got {result:?}"
+        );
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+        assert!(
+            not_modified.hits() > 2,
+            "run should be polled with conditional requests"
+        );
+    }
+}
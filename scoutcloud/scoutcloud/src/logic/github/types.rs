@@ -74,6 +74,12 @@ pub struct WorkflowDispatchRequest<P: Serialize + ?Sized> {
     pub inputs: P,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct InstallationTokenResponse {
+    pub token: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[derive(Serialize, Debug)]
 pub struct WorkflowRunsListRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -84,6 +90,26 @@ pub struct WorkflowRunsListRequest {
     pub page: Option<u32>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct WorkflowJobsResponse {
+    pub jobs: Vec<WorkflowJob>,
+}
+
+/// Job of the workflow run. Jobs have more statuses than runs, like `pending`,
+/// so the status is kept as is
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorkflowJob {
+    pub id: u64,
+    pub name: String,
+    pub status: String,
+}
+
+impl WorkflowJob {
+    pub fn is_completed(&self) -> bool {
+        self.status == "completed"
+    }
+}
+
 // https://github.com/octokit/webhooks.net/blob/aaeeebd41d7ff49a3253146a5e54d0410e6b4ad0/src/Octokit.Webhooks/Models/WorkflowRunStatus.cs#L4
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -104,6 +130,11 @@ impl RunStatus {
     pub fn is_completed(&self) -> bool {
         matches!(self, Self::Completed)
     }
+
+    /// Run is not picked up by a runner yet
+    pub fn is_queued(&self) -> bool {
+        matches!(self, Self::Requested | Self::Queued)
+    }
 }
 
 // https://github.com/octokit/webhooks.net/blob/aaeeebd41d7ff49a3253146a5e54d0410e6b4ad0/src/Octokit.Webhooks/Models/WorkflowRunConclusion.cs
@@ -118,6 +149,7 @@ pub enum RunConclusion {
     ActionRequired,
     Stale,
     Skipped,
+    StartupFailure,
 }
 
 impl RunConclusion {
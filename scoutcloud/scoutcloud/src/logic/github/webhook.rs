@@ -0,0 +1,164 @@
+use super::types::{RunConclusion, RunStatus};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use octocrab::models::RunId;
+use serde::Deserialize;
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+pub const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+pub const EVENT_HEADER: &str = "X-GitHub-Event";
+pub const WORKFLOW_RUN_EVENT: &str = "workflow_run";
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+lazy_static! {
+    // waiters of workflow runs subscribe to it, so they don't have to wait for the next poll
+    static ref COMPLETED_RUNS: broadcast::Sender<RunId> = broadcast::channel(64).0;
+}
+
+#[derive(Error, Debug)]
+pub enum WebhookError {
+    #[error("missing webhook signature")]
+    MissingSignature,
+    #[error("invalid webhook signature")]
+    InvalidSignature,
+    #[error("invalid webhook payload: {0}")]
+    InvalidPayload(#[from] serde_json::Error),
+}
+
+/// Checks `X-Hub-Signature-256` header of github webhook delivery.
+/// See https://docs.github.com/en/webhooks/using-webhooks/validating-webhook-deliveries
+pub fn verify_signature(
+    secret: &str,
+    body: &[u8],
+    signature: Option<&str>,
+) -> Result<(), WebhookError> {
+    let signature = signature.ok_or(WebhookError::MissingSignature)?;
+    let signature = signature
+        .strip_prefix(SIGNATURE_PREFIX)
+        .and_then(|hex_signature| hex::decode(hex_signature).ok())
+        .ok_or(WebhookError::InvalidSignature)?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+    // constant-time comparison
+    mac.verify_slice(&signature)
+        .map_err(|_| WebhookError::InvalidSignature)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorkflowRunEvent {
+    pub action: String,
+    pub workflow_run: WorkflowRunPayload,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WorkflowRunPayload {
+    pub id: RunId,
+    pub status: String,
+    pub conclusion: Option<String>,
+}
+
+impl WorkflowRunEvent {
+    pub fn parse(body: &[u8]) -> Result<Self, WebhookError> {
+        Ok(serde_json::from_slice(body)?)
+    }
+
+    /// Returns conclusion of the run if it is completed
+    pub fn completed_conclusion(&self) -> Result<Option<RunConclusion>, anyhow::Error> {
+        let status = RunStatus::try_from_str(&self.workflow_run.status)?;
+        if !status.is_completed() {
+            return Ok(None);
+        }
+        self.workflow_run
+            .conclusion
+            .as_ref()
+            .map(RunConclusion::try_from_str)
+            .transpose()
+    }
+}
+
+/// Wakes up everyone who waits for the run, so the run is checked right now
+pub fn notify_workflow_run_completed(run_id: RunId) {
+    // error means that nobody is waiting
+    let _ = COMPLETED_RUNS.send(run_id);
+}
+
+pub(super) fn subscribe_completed_runs() -> broadcast::Receiver<RunId> {
+    COMPLETED_RUNS.subscribe()
+}
+
+/// Resolves when any of `run_ids` is reported as completed.
+/// Lagged receiver resolves too, since the runs could be among missed ones
+pub(super) async fn wait_completed_run(
+    receiver: &mut broadcast::Receiver<RunId>,
+    run_ids: &[RunId],
+) {
+    loop {
+        match receiver.recv().await {
+            Ok(completed) if run_ids.contains(&completed) => return,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(_)) => return,
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    // example from github docs
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn valid_signature_is_accepted() {
+        verify_signature(SECRET, BODY, Some(SIGNATURE)).expect("signature should be valid");
+    }
+
+    #[test]
+    fn invalid_signature_is_rejected() {
+        let wrong_body = verify_signature(SECRET, b"Hello, World?", Some(SIGNATURE));
+        assert!(matches!(wrong_body, Err(WebhookError::InvalidSignature)));
+        let wrong_secret = verify_signature("wrong secret", BODY, Some(SIGNATURE));
+        assert!(matches!(wrong_secret, Err(WebhookError::InvalidSignature)));
+        let without_prefix =
+            verify_signature(SECRET, BODY, SIGNATURE.strip_prefix(SIGNATURE_PREFIX));
+        assert!(matches!(
+            without_prefix,
+            Err(WebhookError::InvalidSignature)
+        ));
+        let not_hex = verify_signature(SECRET, BODY, Some("sha256=not-hex"));
+        assert!(matches!(not_hex, Err(WebhookError::InvalidSignature)));
+    }
+
+    #[test]
+    fn missing_signature_is_rejected() {
+        let result = verify_signature(SECRET, BODY, None);
+        assert!(matches!(result, Err(WebhookError::MissingSignature)));
+    }
+
+    #[test]
+    fn workflow_run_event_is_parsed() {
+        let body = serde_json::json!({
+            "action": "completed",
+            "workflow_run": {
+                "id": 8819501642u64,
+                "name": "Deploy",
+                "status": "completed",
+                "conclusion": "success",
+            },
+            "repository": {},
+        });
+        let event = WorkflowRunEvent::parse(body.to_string().as_bytes()).unwrap();
+        assert_eq!(event.workflow_run.id, RunId(8819501642));
+        assert_eq!(
+            event.completed_conclusion().unwrap(),
+            Some(RunConclusion::Success)
+        );
+    }
+}
@@ -1,22 +1,102 @@
-use super::{types::RunStatus, GithubClient, GithubError};
-use crate::logic::github::types::RunConclusion;
+use super::{types::RunStatus, webhook, DeclaredInputs, GithubClient, GithubError, WorkflowInputs};
+use crate::{
+    logic::{ci::CiRun, github::types::RunConclusion},
+    server::WorkflowFilesSettings,
+};
 use chrono::Utc;
 use lazy_static::lazy_static;
-use octocrab::models::workflows::Run;
+use octocrab::models::{workflows::Run, RunId};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 lazy_static! {
     static ref GITHUB_WORKFLOW_MUTEX: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
 }
 
+/// Workflow file on the branch which the workflow is dispatched against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowFile {
+    /// Blob sha of the file, it changes with every change of the file
+    pub sha: String,
+    pub declared_inputs: DeclaredInputs,
+}
+
+/// Names of workflow files in `.github/workflows` of the repo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowFiles {
+    /// Starts the instance
+    pub deploy: String,
+    /// Stops the instance
+    pub cleanup: String,
+}
+
+impl Default for WorkflowFiles {
+    fn default() -> Self {
+        Self::from_settings(&Default::default())
+    }
+}
+
+impl WorkflowFiles {
+    pub fn from_settings(settings: &WorkflowFilesSettings) -> Self {
+        Self {
+            deploy: settings.deploy.clone(),
+            cleanup: settings.cleanup.clone(),
+        }
+    }
+}
+
 #[async_trait::async_trait]
-pub trait Workflow: Serialize + Send + Sync {
-    fn id() -> &'static str;
+pub trait Workflow: Send + Sync {
+    /// File name of the workflow in the repo of the client
+    fn id(client: &GithubClient) -> &str;
+
+    fn inputs(&self) -> WorkflowInputs;
 
+    /// Branch or tag which the workflow is dispatched against,
+    /// the default branch of the repo is used if it is not set
+    fn git_ref(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns inputs of the workflow if they match the declaration in the workflow file
+    /// and the file matches its pinned sha. Repos outside of the allowlist are rejected
+    /// before any request to them
+    async fn resolve_inputs(&self, client: &GithubClient) -> Result<WorkflowInputs, GithubError> {
+        client.repo_allowlist.check(&client.owner, &client.repo)?;
+        let inputs = self.inputs();
+        if let Some(git_ref) = self.git_ref() {
+            client.check_git_ref_exists(git_ref).await?;
+        }
+        let file = client
+            .get_workflow_file(Self::id(client), self.git_ref())
+            .await?;
+        client.workflow_pins.check(Self::id(client), &file.sha)?;
+        inputs.validate(&file.declared_inputs).map_err(|source| {
+            GithubError::InvalidWorkflowInputs {
+                workflow: Self::id(client).to_string(),
+                source,
+            }
+        })?;
+        Ok(inputs)
+    }
+
+    /// Dispatches the workflow only if its inputs match the declaration in the workflow file
     async fn run(&self, client: &GithubClient) -> Result<(), GithubError> {
+        let inputs = self.resolve_inputs(client).await?;
+        let git_ref = self.git_ref().unwrap_or(&client.default_branch_name);
         client
-            .run_workflow(Self::id(), &client.default_branch_name, self)
+            .run_workflow(Self::id(client), git_ref, &inputs)
             .await
     }
     async fn get_latest_run(
@@ -24,7 +104,7 @@ pub trait Workflow: Serialize + Send + Sync {
         created_from: Option<chrono::DateTime<Utc>>,
     ) -> Result<Option<Run>, GithubError> {
         client
-            .get_latest_workflow_run(Self::id(), created_from)
+            .get_latest_workflow_run(Self::id(client), created_from)
             .await
     }
 
@@ -52,20 +132,81 @@ pub trait Workflow: Serialize + Send + Sync {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct DeployWorkflow {
     pub client: String,
+    #[serde(default)]
+    pub git_ref: Option<String>,
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Resolved secret references of the config keyed by their fields
+    #[serde(skip)]
+    pub secrets: BTreeMap<String, String>,
+}
+
+impl std::fmt::Debug for DeployWorkflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeployWorkflow")
+            .field("client", &self.client)
+            .field("git_ref", &self.git_ref)
+            .field("image", &self.image)
+            .field("tag", &self.tag)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Workflow for DeployWorkflow {
-    fn id() -> &'static str {
-        "deploy.yaml"
+    fn id(client: &GithubClient) -> &str {
+        &client.workflow_files.deploy
+    }
+
+    fn inputs(&self) -> WorkflowInputs {
+        WorkflowInputs::new()
+            .with("client", &self.client)
+            .with_optional("image", self.image.as_ref())
+            .with_optional("tag", self.tag.as_ref())
+            .with_optional(
+                "secrets",
+                (!self.secrets.is_empty()).then(|| serde_json::json!(self.secrets)),
+            )
+    }
+
+    fn git_ref(&self) -> Option<&str> {
+        self.git_ref.as_deref()
     }
 }
 
 impl DeployWorkflow {
     pub fn new(client: String) -> Self {
-        Self { client }
+        Self {
+            client,
+            git_ref: None,
+            image: None,
+            tag: None,
+            secrets: Default::default(),
+        }
+    }
+
+    pub fn with_git_ref(mut self, git_ref: Option<String>) -> Self {
+        self.git_ref = git_ref;
+        self
+    }
+
+    /// Image and tag are sent only if they are set,
+    /// so the workflow uses its defaults and workflows without such inputs keep working
+    pub fn with_image(mut self, image: Option<String>, tag: Option<String>) -> Self {
+        self.image = image;
+        self.tag = tag;
+        self
+    }
+
+    /// Secrets are sent as json object only if the config references them, so workflows
+    /// without such input keep working. The workflow should mask them with `::add-mask::`
+    pub fn with_secrets(mut self, secrets: BTreeMap<String, String>) -> Self {
+        self.secrets = secrets;
+        self
     }
 }
 
@@ -75,8 +216,12 @@ pub struct CleanupWorkflow {
 }
 
 impl Workflow for CleanupWorkflow {
-    fn id() -> &'static str {
-        "cleanup.yaml"
+    fn id(client: &GithubClient) -> &str {
+        &client.workflow_files.cleanup
+    }
+
+    fn inputs(&self) -> WorkflowInputs {
+        WorkflowInputs::new().with("client", &self.client)
     }
 }
 
@@ -86,79 +231,271 @@ impl CleanupWorkflow {
     }
 }
 
+const DEFAULT_BACKOFF_MULTIPLIER: f64 = 1.5;
+const DEFAULT_BACKOFF_MAX_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_BACKOFF_JITTER: f64 = 0.1;
+
+/// Delays between checks of workflow run status.
+/// First delay is `initial`, every next one is multiplied by `multiplier`
+/// until it reaches `max`. Every delay is randomly shifted by up to `jitter`
+/// fraction of it, so runs that were started together are not checked together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PollBackoff {
+    pub initial: Duration,
+    pub multiplier: f64,
+    pub max: Duration,
+    pub jitter: f64,
+}
+
+impl PollBackoff {
+    pub fn new(initial: Duration, multiplier: f64, max: Duration) -> Self {
+        Self {
+            initial,
+            multiplier,
+            max,
+            jitter: DEFAULT_BACKOFF_JITTER,
+        }
+    }
+
+    pub fn from_initial(initial: Duration) -> Self {
+        Self::new(
+            initial,
+            DEFAULT_BACKOFF_MULTIPLIER,
+            DEFAULT_BACKOFF_MAX_INTERVAL,
+        )
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delay before the check number `attempt + 1`, without jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.min(i32::MAX as u32) as i32);
+        Duration::try_from_secs_f64(self.initial.as_secs_f64() * factor)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    pub fn jittered_delay(&self, attempt: u32) -> Duration {
+        let delay = self.delay(attempt);
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+        let jitter = self.jitter.min(1.0);
+        let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+        delay.mul_f64(factor)
+    }
+}
+
+#[derive(Clone)]
+struct PollObserver {
+    count: Arc<AtomicU32>,
+    on_poll: Arc<dyn Fn() + Send + Sync>,
+}
+
+tokio::task_local! {
+    static POLL_OBSERVER: PollObserver;
+}
+
+/// Runs `run` and counts checks of run statuses made by it in any backend,
+/// `on_poll` is called after every check
+pub async fn count_polls<F: Future>(
+    on_poll: impl Fn() + Send + Sync + 'static,
+    run: F,
+) -> (F::Output, u32) {
+    let observer = PollObserver {
+        count: Arc::new(AtomicU32::new(0)),
+        on_poll: Arc::new(on_poll),
+    };
+    let count = observer.count.clone();
+    let output = POLL_OBSERVER.scope(observer, run).await;
+    (output, count.load(Ordering::Relaxed))
+}
+
+/// Checks outside of `count_polls` are not counted
+pub(crate) fn record_poll() {
+    let _ = POLL_OBSERVER.try_with(|observer| {
+        observer.count.fetch_add(1, Ordering::Relaxed);
+        (observer.on_poll)();
+    });
+}
+
 impl GithubClient {
+    #[tracing::instrument(skip_all, fields(run_id = run.id.0), level = "info")]
     pub async fn wait_for_success_workflow(
         &self,
-        run: &Run,
+        run: &CiRun,
         timeout: Duration,
-        sleep_between: Duration,
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
     ) -> Result<RunConclusion, GithubError> {
-        let (status, conclusion) = self
-            .wait_for_completed_status_with_timeout(run, timeout, sleep_between)
+        let conclusions = self
+            .wait_for_success_workflows(std::slice::from_ref(run), timeout, backoff, cancel)
             .await?;
-        let run_name_debug = run.name.to_string();
-
-        if status.is_completed() {
-            match conclusion {
-                Some(conclusion) if conclusion.is_ok() => {
-                    tracing::info!(conclusion = ?conclusion, "'{run_name_debug}' deploy completed");
-                    Ok(conclusion)
-                }
-                Some(conclusion) => Err(GithubError::GithubWorkflow(anyhow::anyhow!(
-                    "failed to start '{run_name_debug}'. conclusion={conclusion:?}"
-                ))),
-                None => Err(GithubError::Internal(anyhow::anyhow!(
-                    "no final result for workflow"
-                ))),
-            }
-        } else {
-            Err(GithubError::GithubWorkflow(anyhow::anyhow!(
-                "timed out waiting for '{run_name_debug}' deploy. status={status:?}"
+        conclusions
+            .into_iter()
+            .next()
+            .ok_or(GithubError::Internal(anyhow::anyhow!(
+                "no final result for workflow"
             )))
-        }
     }
 
-    async fn wait_for_completed_status_with_timeout(
+    /// Waits until all `runs` are completed successfully, like coordinated runs
+    /// of frontend, backend and indexer of the same instance. Fails as soon as any run fails,
+    /// without waiting for the rest, and the error points to the failed run.
+    /// Returns conclusions in the order of `runs`
+    pub async fn wait_for_success_workflows(
         &self,
-        run: &Run,
+        runs: &[CiRun],
         timeout: Duration,
-        sleep_between: Duration,
-    ) -> Result<(RunStatus, Option<RunConclusion>), GithubError> {
+        backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<RunConclusion>, GithubError> {
         tracing::info!(
-            run_id = run.id.to_string(),
-            "waiting for github workflow run '{}'",
-            run.name
+            run_ids = ?runs.iter().map(|run| run.id.0).collect::<Vec<_>>(),
+            "waiting for github workflow runs"
         );
         let now = std::time::Instant::now();
+        let mut attempt = 0;
+        let mut completed_runs = webhook::subscribe_completed_runs();
+        let mut conclusions: Vec<Option<RunConclusion>> = vec![None; runs.len()];
         loop {
-            let run = self.get_workflow_run(run.id).await?;
-            let status = RunStatus::try_from_str(&run.status)?;
-            if now.elapsed() >= timeout || status.is_completed() {
-                let conclusion = run
-                    .conclusion
-                    .as_ref()
-                    .map(RunConclusion::try_from_str)
-                    .transpose()?;
-                return Ok((status, conclusion));
+            // completed runs are not checked again
+            let mut in_progress = vec![];
+            for (run, conclusion) in runs.iter().zip(conclusions.iter_mut()) {
+                if conclusion.is_some() {
+                    continue;
+                }
+                let (status, completed) = self.check_run(run, attempt, now.elapsed()).await?;
+                if status.is_completed() {
+                    // one failed run fails all of them, so the rest are not waited for
+                    *conclusion = Some(completed_conclusion(run, completed)?);
+                } else {
+                    in_progress.push((run, status));
+                }
+            }
+            let Some((first_run, first_status)) = in_progress.first() else {
+                return Ok(conclusions.into_iter().flatten().collect());
+            };
+            let elapsed = now.elapsed();
+            if elapsed >= timeout {
+                tracing::warn!(
+                    status = ?first_status,
+                    "timed out waiting for '{}' deploy",
+                    first_run.name
+                );
+                return Err(GithubError::WorkflowTimeout {
+                    run_id: first_run.id,
+                    status: first_status.clone(),
+                });
+            }
+            // never sleep past the timeouts, so the last check happens right at them
+            let queued_timeout = self
+                .queued_timeout
+                .filter(|_| in_progress.iter().any(|(_, status)| status.is_queued()));
+            let deadline = queued_timeout.map_or(timeout, |queued| queued.min(timeout));
+            let delay = backoff
+                .jittered_delay(attempt)
+                .min(deadline.saturating_sub(elapsed));
+            attempt = attempt.saturating_add(1);
+            let waited: Vec<RunId> = in_progress.iter().map(|(run, _)| run.id).collect();
+            // sleeping between checks is the only safe point to stop waiting,
+            // since the workflows are already dispatched and their runs are known
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                // webhook reported that a run is completed, so check runs right now
+                _ = webhook::wait_completed_run(&mut completed_runs, &waited) => {
+                    for run_id in waited {
+                        self.invalidate_cached_run(run_id);
+                    }
+                }
+                _ = cancel.cancelled() => return Err(GithubError::Interrupted),
             }
-            tokio::time::sleep(sleep_between).await;
         }
     }
+
+    /// Returns status of the run and its conclusion if it is completed.
+    /// Fails if the run stays queued for longer than `queued_timeout`
+    async fn check_run(
+        &self,
+        run: &CiRun,
+        attempt: u32,
+        elapsed: Duration,
+    ) -> Result<(RunStatus, Option<RunConclusion>), GithubError> {
+        let poll = tracing::info_span!("poll_workflow_run", run_id = run.id.0, attempt);
+        record_poll();
+        let run = self
+            .get_workflow_run_cached(run.id)
+            .instrument(poll)
+            .await
+            .map_err(|err| {
+                // the run was found when it was dispatched, so it was deleted since then
+                // or the app lost access to the repo. Polling it again won't help
+                if err.is_not_found() {
+                    GithubError::RunDisappeared(run.id)
+                } else {
+                    err
+                }
+            })?;
+        let status = RunStatus::try_from_str(&run.status)?;
+        if let Some(queued_timeout) = self.queued_timeout.filter(|_| status.is_queued()) {
+            if elapsed >= queued_timeout {
+                tracing::warn!(
+                    queued_for = ?elapsed,
+                    "run '{}' is not picked up by any runner",
+                    run.name
+                );
+                return Err(GithubError::NoRunnerAvailable {
+                    run_id: run.id,
+                    queued_for: elapsed,
+                });
+            }
+        }
+        let conclusion = run
+            .conclusion
+            .as_ref()
+            .map(RunConclusion::try_from_str)
+            .transpose()?;
+        Ok((status, conclusion))
+    }
+}
+
+/// Conclusion of the completed run if it is successful
+fn completed_conclusion(
+    run: &CiRun,
+    conclusion: Option<RunConclusion>,
+) -> Result<RunConclusion, GithubError> {
+    match conclusion {
+        Some(conclusion) if conclusion.is_ok() => {
+            tracing::info!(conclusion = ?conclusion, "'{}' deploy completed", run.name);
+            Ok(conclusion)
+        }
+        Some(conclusion) => Err(GithubError::WorkflowFailed {
+            run_id: run.id,
+            conclusion,
+        }),
+        None => Err(GithubError::Internal(anyhow::anyhow!(
+            "no final result for workflow"
+        ))),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests_utils;
+    use crate::{logic::github::WorkflowPins, tests_utils};
 
     #[tokio::test]
     async fn run_and_get_workflow_works() {
         let (client, mock) = tests_utils::init::test_github_client().await;
         let handles = mock.build_handles();
 
-        let deploy = DeployWorkflow {
-            client: "test-client".to_string(),
-        };
+        let deploy = DeployWorkflow::new("test-client".to_string());
         let run = deploy
             .run_and_get_latest_with_mutex(&client, 5)
             .await
@@ -172,6 +509,7 @@ mod tests {
             run.name
         );
 
+        handles.assert_hits("workflow_file_deploy_yaml", 1);
         handles.assert_hits("dispatch_deploy_yaml", 1);
         handles.assert_hits("runs_deploy_yaml", 1);
         handles.assert_hits("dispatch_cleanup_yaml", 0);
@@ -182,4 +520,477 @@ mod tests {
             .expect("get workflow runs");
         handles.assert_hits("runs_cleanup_yaml", 1);
     }
+
+    #[tokio::test]
+    async fn workflow_with_mismatched_inputs_is_not_dispatched() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let mut handles = mock.build_handles();
+        // workflow declares only required `instance` input
+        mock.override_response(&mut handles, "workflow_file_deploy_yaml", |response| {
+            response["content"] = serde_json::json!(
+                "b246CiAgd29ya2Zsb3dfZGlzcGF0Y2g6CiAgICBpbnB1dHM6CiAgICAgIGluc3RhbmNlOgogICAgICAgIHJlcXVpcmVkOiB0cnVlCg=="
+            );
+        });
+
+        let err = DeployWorkflow::new("test-client".to_string())
+            .run(&client)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                GithubError::InvalidWorkflowInputs {
+                    source: crate::logic::github::InputsError::Unknown(unknown),
+                    ..
+                } if unknown == &["client".to_string()]
+            ),
+            "unexpected error: {err}"
+        );
+        assert!(!err.is_retryable());
+        handles.assert_hits("dispatch_deploy_yaml", 0);
+    }
+
+    #[tokio::test]
+    async fn configured_workflow_files_are_dispatched() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let files = WorkflowFiles {
+            deploy: "start.yml".to_string(),
+            cleanup: "teardown.yml".to_string(),
+        };
+        let default_handles = mock.build_handles();
+        let handles = mock.build_handles_with_workflow_files(&mock.repo, &files);
+        let client = client.with_workflow_files(files);
+
+        DeployWorkflow::new("test-client".to_string())
+            .run(&client)
+            .await
+            .expect("deploy workflow should be dispatched");
+        handles.assert_hits("workflow_file_deploy_yaml", 1);
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+        handles.assert_hits("dispatch_cleanup_yaml", 0);
+
+        CleanupWorkflow::new("test-client".to_string())
+            .run(&client)
+            .await
+            .expect("cleanup workflow should be dispatched");
+        handles.assert_hits("workflow_file_cleanup_yaml", 1);
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+
+        default_handles.assert_hits("dispatch_deploy_yaml", 0);
+        default_handles.assert_hits("dispatch_cleanup_yaml", 0);
+    }
+
+    const DEPLOY_YAML_SHA: &str = "0de54d50be02a94d1d74c52423309d613556cf3d";
+
+    fn deploy_pin(sha: &str) -> WorkflowPins {
+        WorkflowPins::new([("deploy.yaml".to_string(), sha.to_string())].into(), false)
+    }
+
+    #[tokio::test]
+    async fn workflow_with_pinned_sha_is_dispatched() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let handles = mock.build_handles();
+        let client = client.with_workflow_pins(deploy_pin(DEPLOY_YAML_SHA));
+
+        DeployWorkflow::new("test-client".to_string())
+            .run(&client)
+            .await
+            .expect("workflow should be dispatched");
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    async fn deploy_workflow_is_dispatched_against_git_ref() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let mut handles = mock.build_handles();
+        mock.expect_json_body(
+            &mut handles,
+            "dispatch_deploy_yaml",
+            serde_json::json!({ "ref": "release-1.2" }),
+        );
+        let git_ref = mock.mock_git_ref("release-1.2");
+
+        DeployWorkflow::new("test-client".to_string())
+            .with_git_ref(Some("release-1.2".to_string()))
+            .run(&client)
+            .await
+            .expect("workflow should be dispatched");
+        git_ref.assert_hits(1);
+        handles.assert_hits("workflow_file_deploy_yaml", 1);
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    async fn deploy_workflow_passes_custom_image_tag() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let mut handles = mock.build_handles();
+        // workflow declares required `client` and optional `image` and `tag` inputs
+        mock.override_response(&mut handles, "workflow_file_deploy_yaml", |response| {
+            response["content"] = serde_json::json!(
+                "b246CiAgd29ya2Zsb3dfZGlzcGF0Y2g6CiAgICBpbnB1dHM6CiAgICAgIGNsaWVudDoKICAgICAgICByZXF1aXJlZDogdHJ1ZQogICAgICBpbWFnZToKICAgICAgICByZXF1aXJlZDogZmFsc2UKICAgICAgdGFnOgogICAgICAgIHJlcXVpcmVkOiBmYWxzZQo="
+            );
+        });
+        // image is not set, so the workflow default is used
+        mock.expect_json_body(
+            &mut handles,
+            "dispatch_deploy_yaml",
+            serde_json::json!({ "inputs": { "client": "test-client", "tag": "v6.8.0" } }),
+        );
+
+        DeployWorkflow::new("test-client".to_string())
+            .with_image(None, Some("v6.8.0".to_string()))
+            .run(&client)
+            .await
+            .expect("workflow should be dispatched");
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+
+    #[test]
+    fn deploy_workflow_sends_secrets_only_if_config_references_them() {
+        let deploy = DeployWorkflow::new("test-client".to_string());
+        assert_eq!(
+            deploy.inputs(),
+            WorkflowInputs::new().with("client", "test-client")
+        );
+
+        let deploy = deploy.with_secrets(BTreeMap::from([(
+            "blockscout.env.ETHEREUM_JSONRPC_HTTP_URL".to_string(),
+            "https://rpc.example.com/key".to_string(),
+        )]));
+        assert_eq!(
+            deploy.inputs(),
+            WorkflowInputs::new().with("client", "test-client").with(
+                "secrets",
+                r#"{"blockscout.env.ETHEREUM_JSONRPC_HTTP_URL":"https://rpc.example.com/key"}"#
+            )
+        );
+        assert!(!format!("{deploy:?}").contains("rpc.example.com"));
+    }
+
+    #[tokio::test]
+    async fn deploy_workflow_with_unknown_git_ref_is_not_dispatched() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let handles = mock.build_handles();
+
+        let err = DeployWorkflow::new("test-client".to_string())
+            .with_git_ref(Some("no-such-branch".to_string()))
+            .run(&client)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, GithubError::GitRefNotFound(git_ref) if git_ref == "no-such-branch"),
+            "unexpected error: {err}"
+        );
+        assert!(!err.is_retryable());
+        handles.assert_hits("workflow_file_deploy_yaml", 0);
+        handles.assert_hits("dispatch_deploy_yaml", 0);
+    }
+
+    #[tokio::test]
+    async fn changed_workflow_file_is_not_dispatched() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let handles = mock.build_handles();
+        let pinned = "1111111111111111111111111111111111111111";
+        let client = client.with_workflow_pins(deploy_pin(pinned));
+
+        let err = DeployWorkflow::new("test-client".to_string())
+            .run(&client)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                GithubError::WorkflowDrift { workflow, expected, actual }
+                    if workflow == "deploy.yaml" && expected == pinned && actual == DEPLOY_YAML_SHA
+            ),
+            "unexpected error: {err}"
+        );
+        let message = err.to_string();
+        assert!(
+            message.contains(pinned) && message.contains(DEPLOY_YAML_SHA),
+            "{message}"
+        );
+        assert!(!err.is_retryable());
+        handles.assert_hits("dispatch_deploy_yaml", 0);
+
+        // changes are only reported when drift is allowed
+        let client = client.with_workflow_pins(WorkflowPins::new(
+            [("deploy.yaml".to_string(), pinned.to_string())].into(),
+            true,
+        ));
+        DeployWorkflow::new("test-client".to_string())
+            .run(&client)
+            .await
+            .expect("workflow should be dispatched");
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    async fn run_workflow_retries_after_rate_limit() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let mut handles = mock.build_handles();
+        mock.override_rate_limited(&mut handles, "dispatch_cleanup_yaml", 1);
+
+        let cleanup = CleanupWorkflow::new("test-client".to_string());
+        let started = std::time::Instant::now();
+        let (run, _) = tokio::join!(cleanup.run_and_get_latest_with_mutex(&client, 5), async {
+            // github stops rate limiting after the first request
+            while handles
+                .with_name("dispatch_cleanup_yaml")
+                .hits_async()
+                .await
+                == 0
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            mock.reset_response(&mut handles, "dispatch_cleanup_yaml");
+        });
+        let run = run
+            .expect("run and get workflow")
+            .expect("no workflows returned");
+
+        assert_eq!(run.id, RunId(8819501307));
+        assert!(
+            started.elapsed() >= Duration::from_secs(1),
+            "retry-after header was not respected"
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        handles.assert_hits("runs_cleanup_yaml", 1);
+    }
+
+    #[test]
+    fn poll_backoff_grows_up_to_max() {
+        let backoff =
+            PollBackoff::new(Duration::from_secs(5), 2.0, Duration::from_secs(60)).with_jitter(0.0);
+        let delays: Vec<_> = (0..6).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [5, 10, 20, 40, 60, 60].map(Duration::from_secs).to_vec()
+        );
+        assert_eq!(backoff.jittered_delay(2), Duration::from_secs(20));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(60));
+
+        let fixed = PollBackoff::new(Duration::from_secs(5), 1.0, Duration::from_secs(60));
+        assert_eq!(fixed.delay(10), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn poll_backoff_jitter_is_bounded() {
+        let backoff = PollBackoff::new(Duration::from_secs(10), 2.0, Duration::from_secs(60))
+            .with_jitter(0.1);
+        for attempt in 0..10 {
+            let expected = backoff.delay(attempt);
+            let delay = backoff.jittered_delay(attempt);
+            assert!(
+                delay >= expected.mul_f64(0.9) && delay <= expected.mul_f64(1.1),
+                "delay {delay:?} is too far from {expected:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_for_workflow_backs_off_until_timeout() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        // every check should reach github
+        let client = client.with_run_cache_ttl(Duration::ZERO);
+        let mut handles = mock.build_handles();
+        mock.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+        let run = client.get_workflow_run(RunId(8819501307)).await.unwrap();
+
+        // checks at 0, 20, 60, 140, 240 and 300 (timeout) ms
+        let timeout = Duration::from_millis(300);
+        let backoff = PollBackoff::new(Duration::from_millis(20), 2.0, Duration::from_millis(100))
+            .with_jitter(0.0);
+        let started = std::time::Instant::now();
+        let result = client
+            .wait_for_success_workflow(
+                &CiRun::from(&run),
+                timeout,
+                backoff,
+                &CancellationToken::new(),
+            )
+            .await;
+        let elapsed = started.elapsed();
+
+        assert!(
+            matches!(result, Err(GithubError::WorkflowTimeout { .. })),
+            "expected timeout error, got {result:?}"
+        );
+        assert!(elapsed >= timeout, "returned before timeout: {elapsed:?}");
+        assert!(
+            elapsed < timeout + Duration::from_millis(200),
+            "overslept timeout: {elapsed:?}"
+        );
+        // one more hit is the initial `get_workflow_run`
+        let hits = handles.with_name("single_run_cleanup_yaml").hits();
+        assert!(
+            (3..=7).contains(&hits),
+            "unexpected number of checks: {hits}"
+        );
+    }
+
+    #[tokio::test]
+    async fn run_which_stays_queued_fails_early() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client = client
+            .with_run_cache_ttl(Duration::ZERO)
+            .with_queued_timeout(Some(Duration::from_millis(300)));
+        let mut handles = mock.build_handles();
+        mock.override_response(&mut handles, "single_run_deploy_yaml", |run| {
+            run["status"] = "queued".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+        let run = client.get_workflow_run(RunId(8819501642)).await.unwrap();
+
+        let timeout = Duration::from_secs(10);
+        let started = std::time::Instant::now();
+        let result = client
+            .wait_for_success_workflow(
+                &CiRun::from(&run),
+                timeout,
+                PollBackoff::from_initial(Duration::from_millis(50)),
+                &CancellationToken::new(),
+            )
+            .await;
+        let elapsed = started.elapsed();
+
+        assert!(
+            matches!(
+                result,
+                Err(GithubError::NoRunnerAvailable {
+                    run_id: RunId(8819501642),
+                    queued_for,
+                }) if queued_for >= Duration::from_millis(300)
+            ),
+            "expected early failure, got {result:?}"
+        );
+        assert!(
+            elapsed < Duration::from_secs(1),
+            "waited for the main timeout: {elapsed:?}"
+        );
+
+        // once the run is picked up, only the main timeout applies
+        mock.override_response(&mut handles, "single_run_deploy_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+        let result = client
+            .wait_for_success_workflow(
+                &CiRun::from(&run),
+                Duration::from_millis(600),
+                PollBackoff::from_initial(Duration::from_millis(50)),
+                &CancellationToken::new(),
+            )
+            .await;
+        assert!(
+            matches!(result, Err(GithubError::WorkflowTimeout { .. })),
+            "expected timeout error, got {result:?}"
+        );
+    }
+
+    // deploy and cleanup runs of the mock play coordinated runs of the same instance
+    fn coordinated_runs() -> Vec<CiRun> {
+        vec![
+            CiRun::new(RunId(8819501642), "Deploy backend".to_string()),
+            CiRun::new(RunId(8819501307), "Deploy indexer".to_string()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn all_coordinated_runs_should_succeed() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let handles = mock.build_handles();
+
+        let conclusions = client
+            .wait_for_success_workflows(
+                &coordinated_runs(),
+                Duration::from_secs(10),
+                PollBackoff::from_initial(Duration::from_millis(50)),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(conclusions, vec![RunConclusion::Success; 2]);
+        handles.assert_hits("single_run_deploy_yaml", 1);
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    async fn failed_coordinated_run_fails_all_at_once() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client = client.with_run_cache_ttl(Duration::ZERO);
+        let mut handles = mock.build_handles();
+        mock.override_response(&mut handles, "single_run_deploy_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+        mock.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["conclusion"] = "failure".into();
+        });
+
+        let started = std::time::Instant::now();
+        let result = client
+            .wait_for_success_workflows(
+                &coordinated_runs(),
+                Duration::from_secs(10),
+                PollBackoff::from_initial(Duration::from_millis(50)),
+                &CancellationToken::new(),
+            )
+            .await;
+        let elapsed = started.elapsed();
+
+        assert!(
+            matches!(
+                result,
+                Err(GithubError::WorkflowFailed {
+                    run_id: RunId(8819501307),
+                    conclusion: RunConclusion::Failure,
+                })
+            ),
+            "expected failure of the indexer run, got {result:?}"
+        );
+        assert!(
+            elapsed < Duration::from_secs(1),
+            "waited for the run in progress: {elapsed:?}"
+        );
+        handles.assert_hits("single_run_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    async fn completed_coordinated_run_is_not_checked_again() {
+        let (client, mock) = tests_utils::init::test_github_client().await;
+        let client = client.with_run_cache_ttl(Duration::ZERO);
+        let mut handles = mock.build_handles();
+        mock.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+
+        let result = client
+            .wait_for_success_workflows(
+                &coordinated_runs(),
+                Duration::from_millis(300),
+                PollBackoff::from_initial(Duration::from_millis(50)).with_jitter(0.0),
+                &CancellationToken::new(),
+            )
+            .await;
+
+        assert!(
+            matches!(
+                result,
+                Err(GithubError::WorkflowTimeout {
+                    run_id: RunId(8819501307),
+                    status: RunStatus::InProgress,
+                })
+            ),
+            "expected timeout of the indexer run, got {result:?}"
+        );
+        handles.assert_hits("single_run_deploy_yaml", 1);
+        let hits = handles.with_name("single_run_cleanup_yaml").hits();
+        assert!(hits >= 3, "run in progress was checked only {hits} times");
+    }
 }
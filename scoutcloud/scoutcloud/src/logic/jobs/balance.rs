@@ -154,7 +154,7 @@ impl UnpaidDeployment {
         let expense_amount = self.expense_amount();
         let expense = db::balance_expenses::ActiveModel {
             user_id: Set(self.creator_id),
-            deployment_id: Set(self.deployment_id),
+            deployment_id: Set(Some(self.deployment_id)),
             hours: Set(hours),
             expense_amount: Set(expense_amount),
             ..Default::default()
@@ -207,7 +207,7 @@ mod tests {
         // fully pay for deployment#2
         scoutcloud_entity::balance_expenses::ActiveModel {
             user_id: Set(1),
-            deployment_id: Set(2),
+            deployment_id: Set(Some(2)),
             hours: Set(4),
             expense_amount: Set(Default::default()), // doesn't matter in this test
             ..Default::default()
@@ -220,7 +220,7 @@ mod tests {
         let paid_hours = 2;
         scoutcloud_entity::balance_expenses::ActiveModel {
             user_id: Set(2),
-            deployment_id: Set(1),
+            deployment_id: Set(Some(1)),
             hours: Set(paid_hours),
             expense_amount: Set(Default::default()),
             ..Default::default()
@@ -0,0 +1,184 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::{global, shutdown};
+use crate::logic::{
+    deploy::StatusActor, github::types::RunStatus, DeployError, Deployment, GithubClient,
+};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::DatabaseConnection;
+
+const ACTOR: StatusActor = StatusActor::Task("cancel");
+
+#[derive(fang::serde::Serialize, fang::serde::Deserialize, Debug)]
+#[serde(crate = "fang::serde")]
+pub struct CancelTask {
+    deployment_id: i32,
+}
+
+impl CancelTask {
+    pub fn from_deployment_id(deployment_id: i32) -> Self {
+        Self { deployment_id }
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for CancelTask {
+    #[tracing::instrument(err(Debug), skip(_client), level = "info")]
+    async fn run(&self, _client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let _guard = shutdown::register_running_task().await?;
+        let db = global::DATABASE.get().await;
+
+        let mut deployment = Deployment::get(db.as_ref(), self.deployment_id)
+            .await
+            .map_err(DeployError::Db)?
+            .with_actor(ACTOR);
+
+        match deployment.model.status {
+            // starting task was not executed yet, so it will skip failed deployment
+            DeploymentStatusType::Created | DeploymentStatusType::Scheduled => {
+                deployment
+                    .mark_as_error(db.as_ref(), &DeployError::Cancelled)
+                    .await?;
+            }
+            DeploymentStatusType::Pending if deployment.model.run_id.is_some() => {
+                let github = global::get_github_client(&deployment.instance_config())
+                    .await
+                    .map_err(DeployError::from)?;
+                self.github_cancel(db.as_ref(), &github, &mut deployment)
+                    .await?;
+            }
+            DeploymentStatusType::Pending => {
+                // deploy workflow is being dispatched right now, retry later
+                return Err(DeployError::Internal(anyhow::anyhow!(
+                    "deploy workflow run of deployment '{}' is not known yet",
+                    self.deployment_id
+                ))
+                .into());
+            }
+            DeploymentStatusType::Running
+            | DeploymentStatusType::Stopping
+            | DeploymentStatusType::Stopped
+            | DeploymentStatusType::Failed => {
+                tracing::warn!(
+                    "cannot cancel deployment '{}': invalid state '{:?}'",
+                    self.deployment_id,
+                    deployment.model.status,
+                );
+            }
+        };
+
+        Ok(())
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        None
+    }
+}
+
+impl CancelTask {
+    async fn github_cancel(
+        &self,
+        db: &DatabaseConnection,
+        github: &GithubClient,
+        deployment: &mut Deployment,
+    ) -> Result<(), DeployError> {
+        let run_id = deployment
+            .run_id()
+            .ok_or(anyhow::anyhow!("deployment has no stored run_id"))?;
+        let run = github.get_workflow_run(run_id).await?;
+        if RunStatus::try_from_str(&run.status)?.is_completed() {
+            tracing::info!(
+                run_id =? run_id,
+                "deploy workflow is already completed, nothing to cancel"
+            );
+            return Ok(());
+        }
+        github.cancel_workflow_run(run_id).await?;
+        deployment
+            .mark_as_error(db, &DeployError::Cancelled)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+
+    async fn set_pending(conn: &DatabaseConnection, deployment_id: i32, run_id: i64) {
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(deployment_id),
+            status: Set(DeploymentStatusType::Pending),
+            run_id: Set(Some(run_id)),
+            ..Default::default()
+        }
+        .update(conn)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn cancel_task_cancels_pending_deployment() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("cancel_task_cancels_pending_deployment")
+                .await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_response(&mut handles, "single_run_deploy_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+
+        let not_started_deployment_id = 4;
+        set_pending(conn.as_ref(), not_started_deployment_id, 8819501642).await;
+        runner
+            .insert_task(&CancelTask::from_deployment_id(not_started_deployment_id))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), not_started_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert_eq!(
+            deployment.model.error.as_deref(),
+            Some("deployment was cancelled by user")
+        );
+        assert_eq!(deployment.model.error_code.as_deref(), Some("cancelled"));
+        handles.assert_hits("single_run_deploy_yaml", 1);
+        handles.assert_hits("cancel_run_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn cancel_task_ignores_completed_run() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("cancel_task_ignores_completed_run").await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+
+        let not_started_deployment_id = 4;
+        set_pending(conn.as_ref(), not_started_deployment_id, 8819501642).await;
+        runner
+            .insert_task(&CancelTask::from_deployment_id(not_started_deployment_id))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), not_started_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Pending);
+        assert_eq!(deployment.model.error, None);
+        handles.assert_hits("single_run_deploy_yaml", 1);
+        handles.assert_hits("cancel_run_deploy_yaml", 0);
+    }
+}
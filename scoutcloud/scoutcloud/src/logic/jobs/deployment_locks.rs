@@ -0,0 +1,82 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OwnedMutexGuard;
+
+type LockSlot = Arc<tokio::sync::Mutex<()>>;
+
+lazy_static! {
+    static ref LOCKS: Mutex<HashMap<i32, LockSlot>> = Default::default();
+}
+
+/// Advisory lock of the deployment, held by tasks of this process while they
+/// move the deployment between statuses and dispatch its workflow.
+/// The lock is released on drop
+#[derive(Debug)]
+pub struct DeploymentLock {
+    deployment_id: i32,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for DeploymentLock {
+    fn drop(&mut self) {
+        let mut locks = LOCKS.lock().expect("deployment locks lock is poisoned");
+        drop(self.guard.take());
+        // waiters hold their own references, so the slot is kept for them
+        if locks
+            .get(&self.deployment_id)
+            .is_some_and(|slot| Arc::strong_count(slot) == 1)
+        {
+            locks.remove(&self.deployment_id);
+        }
+    }
+}
+
+/// Waits until other tasks release the lock of the deployment
+pub async fn lock(deployment_id: i32) -> DeploymentLock {
+    let slot = LOCKS
+        .lock()
+        .expect("deployment locks lock is poisoned")
+        .entry(deployment_id)
+        .or_default()
+        .clone();
+    DeploymentLock {
+        deployment_id,
+        guard: Some(slot.lock_owned().await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn is_registered(deployment_id: i32) -> bool {
+        LOCKS.lock().unwrap().contains_key(&deployment_id)
+    }
+
+    #[tokio::test]
+    async fn lock_is_exclusive_per_deployment() {
+        let first = lock(2001).await;
+        // locks of other deployments are independent
+        let other = lock(2002).await;
+
+        let waiter = tokio::spawn(lock(2001));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiter.is_finished(), "lock was acquired twice");
+
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .expect("lock was not released")
+            .unwrap();
+        assert!(is_registered(2001));
+        drop(second);
+        assert!(!is_registered(2001));
+
+        drop(other);
+        assert!(!is_registered(2002));
+    }
+}
@@ -0,0 +1,193 @@
+use super::global;
+use crate::{logic::DeployError, server::DispatchLimitSettings};
+use chrono::{DateTime, Utc};
+use fang::{AsyncQueueable, AsyncRunnable, FangError};
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits the number of tasks which dispatch github workflows and wait for them at once.
+/// The limit is shared by all types of the tasks
+#[derive(Debug)]
+pub struct DispatchLimit {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    retry_delay: Duration,
+    max_pending_tasks: Option<u64>,
+}
+
+impl DispatchLimit {
+    pub fn new(max_concurrent: usize, retry_delay: Duration) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            retry_delay,
+            max_pending_tasks: None,
+        }
+    }
+
+    pub fn with_max_pending_tasks(mut self, max_pending_tasks: Option<u64>) -> Self {
+        self.max_pending_tasks = max_pending_tasks;
+        self
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    pub fn max_pending_tasks(&self) -> Option<u64> {
+        self.max_pending_tasks
+    }
+
+    pub fn from_settings(settings: &DispatchLimitSettings) -> Self {
+        Self::new(settings.max_concurrent, settings.retry_delay)
+            .with_max_pending_tasks(settings.max_pending_tasks)
+    }
+
+    fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.semaphore.clone().try_acquire_owned().ok()
+    }
+
+    /// Waits until the limit allows to run one more github phase
+    pub(super) async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore of dispatch limit is never closed")
+    }
+}
+
+impl Default for DispatchLimit {
+    fn default() -> Self {
+        Self::from_settings(&Default::default())
+    }
+}
+
+/// Runs github phase of the task if the limit allows it and the runner is not paused.
+/// Otherwise the task is not run, but its copy built by `postponed` is scheduled by fang,
+/// so the worker is not blocked
+pub(super) async fn run_with_dispatch_limit<T, Fut>(
+    client: &dyn AsyncQueueable,
+    postponed: impl FnOnce(DateTime<Utc>) -> T,
+    run: Fut,
+) -> Result<(), FangError>
+where
+    T: AsyncRunnable,
+    Fut: Future<Output = Result<(), FangError>>,
+{
+    let limit = global::DISPATCH_LIMIT.get().await.clone();
+    let paused = global::PAUSE.get().await.is_paused();
+    let permit = if paused { None } else { limit.try_acquire() };
+    let Some(_permit) = permit else {
+        return postpone(client, postponed, &limit, paused).await;
+    };
+    run.await
+}
+
+/// Runs the task if the runner is not paused, otherwise it is postponed like
+/// in `run_with_dispatch_limit`. The task runs several github phases at once,
+/// so it acquires the limit for each of them by itself
+pub(super) async fn run_unless_paused<T, Fut>(
+    client: &dyn AsyncQueueable,
+    postponed: impl FnOnce(DateTime<Utc>) -> T,
+    run: Fut,
+) -> Result<(), FangError>
+where
+    T: AsyncRunnable,
+    Fut: Future<Output = Result<(), FangError>>,
+{
+    let limit = global::DISPATCH_LIMIT.get().await.clone();
+    if global::PAUSE.get().await.is_paused() {
+        return postpone(client, postponed, &limit, true).await;
+    }
+    run.await
+}
+
+async fn postpone<T: AsyncRunnable>(
+    client: &dyn AsyncQueueable,
+    postponed: impl FnOnce(DateTime<Utc>) -> T,
+    limit: &DispatchLimit,
+    paused: bool,
+) -> Result<(), FangError> {
+    let retry_delay = chrono::Duration::from_std(limit.retry_delay)
+        .map_err(|err| DeployError::Internal(err.into()))?;
+    let scheduled_at = Utc::now() + retry_delay;
+    if paused {
+        tracing::info!(scheduled_at = %scheduled_at, "jobs runner is paused, task is postponed");
+    } else {
+        tracing::info!(
+            scheduled_at = %scheduled_at,
+            "too many github workflows are dispatched at once, task is postponed"
+        );
+    }
+    client.schedule_task(&postponed(scheduled_at)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use fang::{typetag, Scheduled};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Pretends to dispatch a workflow and wait for it
+    #[derive(fang::serde::Serialize, fang::serde::Deserialize, Debug, Clone)]
+    #[serde(crate = "fang::serde")]
+    struct DispatchingTask {
+        scheduled_at: Option<DateTime<Utc>>,
+    }
+
+    #[typetag::serde]
+    #[fang::async_trait]
+    impl AsyncRunnable for DispatchingTask {
+        async fn run(&self, client: &dyn AsyncQueueable) -> Result<(), FangError> {
+            let postponed = |scheduled_at| Self {
+                scheduled_at: Some(scheduled_at),
+                ..self.clone()
+            };
+            run_with_dispatch_limit(client, postponed, async {
+                let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+                MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                RUNNING.fetch_sub(1, Ordering::SeqCst);
+                FINISHED.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .await
+        }
+
+        fn cron(&self) -> Option<Scheduled> {
+            self.scheduled_at.map(Scheduled::ScheduleOnce)
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn dispatches_are_limited() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("dispatches_are_limited").await;
+        global::DISPATCH_LIMIT
+            .init(Arc::new(DispatchLimit::new(2, Duration::from_millis(200))))
+            .await
+            .unwrap();
+
+        for _ in 0..5 {
+            runner
+                .insert_task(&DispatchingTask { scheduled_at: None })
+                .await
+                .unwrap();
+        }
+        tests_utils::db::wait_for_empty_fang_tasks(db.client())
+            .await
+            .unwrap();
+        assert_eq!(FINISHED.load(Ordering::SeqCst), 5);
+        assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 2);
+    }
+}
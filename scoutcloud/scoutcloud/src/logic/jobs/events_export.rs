@@ -0,0 +1,231 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::global;
+use crate::logic::{
+    events::{DeploymentEvent, EventProducer, EventsError},
+    DeployError,
+};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, sea_query::Expr, QueryOrder, QuerySelect};
+use std::collections::HashMap;
+use tracing::instrument;
+
+/// Publishes status changes of deployments which were not exported yet.
+/// Changes stay in the status history until the producer acknowledges them,
+/// so events are delayed, not lost, while brokers are unavailable
+#[derive(fang::serde::Serialize, fang::serde::Deserialize)]
+#[serde(crate = "fang::serde")]
+pub struct EventsExportTask {
+    schedule: Option<String>,
+}
+
+impl Default for EventsExportTask {
+    fn default() -> Self {
+        Self {
+            schedule: Some("*/5 * * * * *".to_string()),
+        }
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for EventsExportTask {
+    #[instrument(err(Debug), skip(self, _client), level = "debug")]
+    async fn run(&self, _client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let events = global::EVENTS.get().await.clone();
+        let Some(producer) = events.producer.as_ref() else {
+            return Ok(());
+        };
+        let db = global::DATABASE.get().await;
+        match export_events(db.as_ref(), producer.as_ref(), events.batch_size).await {
+            Ok(_) => {}
+            Err(EventsError::Db(err)) => return Err(DeployError::Db(err).into()),
+            Err(err) => tracing::warn!(err = %err, "failed to publish deployment events"),
+        }
+        Ok(())
+    }
+
+    fn uniq(&self) -> bool {
+        true
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.schedule
+            .as_ref()
+            .map(|s| Scheduled::CronPattern(s.clone()))
+    }
+}
+
+/// Publishes not exported status changes in order of their ids and marks them as exported.
+/// Returns the number of published events
+async fn export_events<C>(
+    db: &C,
+    producer: &dyn EventProducer,
+    batch_size: u64,
+) -> Result<u64, EventsError>
+where
+    C: ConnectionTrait,
+{
+    let mut exported = 0;
+    loop {
+        let changes = db::deployment_status_history::Entity::find()
+            .find_also_related(db::deployments::Entity)
+            .filter(db::deployment_status_history::Column::ExportedAt.is_null())
+            .order_by_asc(db::deployment_status_history::Column::Id)
+            .limit(batch_size)
+            .all(db)
+            .await?;
+        if changes.is_empty() {
+            break;
+        }
+        let selected = changes.len() as u64;
+        let instance_ids: Vec<i32> = changes
+            .iter()
+            .filter_map(|(_, deployment)| deployment.as_ref().map(|d| d.instance_id))
+            .collect();
+        let instances: HashMap<i32, db::instances::Model> = db::instances::Entity::find()
+            .filter(db::instances::Column::Id.is_in(instance_ids))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|instance| (instance.id, instance))
+            .collect();
+
+        let ids: Vec<i32> = changes.iter().map(|(change, _)| change.id).collect();
+        let events: Vec<DeploymentEvent> = changes
+            .iter()
+            .filter_map(|(change, deployment)| {
+                let deployment = deployment.as_ref()?;
+                let instance = instances.get(&deployment.instance_id)?;
+                Some(DeploymentEvent::new(change, deployment, instance))
+            })
+            .collect();
+        producer.send(&events).await?;
+        db::deployment_status_history::Entity::update_many()
+            .col_expr(
+                db::deployment_status_history::Column::ExportedAt,
+                Expr::current_timestamp().into(),
+            )
+            .filter(db::deployment_status_history::Column::Id.is_in(ids))
+            .exec(db)
+            .await?;
+        exported += events.len() as u64;
+        if selected < batch_size {
+            break;
+        }
+    }
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::{deploy::StatusActor, events::MockProducer, Deployment, Instance},
+        tests_utils,
+    };
+    use db::sea_orm_active_enums::DeploymentStatusType;
+    use octocrab::models::RunId;
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+    use std::sync::atomic::Ordering;
+
+    const INSTANCE_ID: i32 = 3;
+    const USER_ID: i32 = 2;
+
+    fn statuses(events: &[DeploymentEvent]) -> Vec<(Option<&str>, &str, Option<i64>)> {
+        events
+            .iter()
+            .map(|event| {
+                (
+                    event.old_status.as_deref(),
+                    event.new_status.as_str(),
+                    event.run_id,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn start_stop_cycle_is_exported() {
+        let db = tests_utils::init::test_db("test", "start_stop_cycle_is_exported").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let instance = db::instances::ActiveModel {
+            id: Set(INSTANCE_ID),
+            parsed_config: Set(
+                serde_json::json!({"frontend": {"ingress": {"hostname": "instance.example.com"}}}),
+            ),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let producer = MockProducer::default();
+
+        let mut deployment = Deployment::try_create(
+            conn.as_ref(),
+            &Instance::new(instance.clone()),
+            None,
+            StatusActor::System,
+        )
+        .await
+        .unwrap();
+        deployment
+            .update_status(conn.as_ref(), DeploymentStatusType::Pending)
+            .await
+            .unwrap();
+        deployment
+            .set_run_id(conn.as_ref(), RunId(1))
+            .await
+            .unwrap();
+        deployment.mark_as_running(conn.as_ref()).await.unwrap();
+
+        // broker is unavailable, events are kept until it is back
+        producer.fail.store(true, Ordering::SeqCst);
+        export_events(conn.as_ref(), &producer, 2)
+            .await
+            .unwrap_err();
+        producer.fail.store(false, Ordering::SeqCst);
+
+        deployment
+            .update_status(conn.as_ref(), DeploymentStatusType::Stopping)
+            .await
+            .unwrap();
+        deployment
+            .set_run_id(conn.as_ref(), RunId(2))
+            .await
+            .unwrap();
+        deployment.mark_as_finished(conn.as_ref()).await.unwrap();
+
+        let exported = export_events(conn.as_ref(), &producer, 2).await.unwrap();
+        assert_eq!(exported, 5);
+        let events = producer.events.lock().unwrap().clone();
+        assert_eq!(
+            statuses(&events),
+            vec![
+                (None, "created", None),
+                (Some("created"), "pending", None),
+                (Some("pending"), "running", Some(1)),
+                (Some("running"), "stopping", None),
+                (Some("stopping"), "stopped", Some(2)),
+            ]
+        );
+        let deployment_id = deployment.model.external_id.to_string();
+        let instance_id = instance.external_id.to_string();
+        for event in &events {
+            assert_eq!(event.deployment_id, deployment_id);
+            assert_eq!(event.instance_id, instance_id);
+            assert_eq!(event.user_id, USER_ID);
+        }
+
+        // every change is exported once
+        let exported = export_events(conn.as_ref(), &producer, 2).await.unwrap();
+        assert_eq!(exported, 0);
+        assert_eq!(producer.events.lock().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,127 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::{global, StoppingTask};
+use crate::logic::{deploy::Initiator, DeployError, Deployment};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use tracing::instrument;
+
+/// Stops running deployments with `expires_at` in the past.
+/// Deployments which are already stopping or stopped are skipped
+#[derive(fang::serde::Serialize, fang::serde::Deserialize)]
+#[serde(crate = "fang::serde")]
+pub struct ExpiryReaperTask {
+    schedule: Option<String>,
+}
+
+impl Default for ExpiryReaperTask {
+    fn default() -> Self {
+        Self {
+            schedule: Some("0 */5 * * * *".to_string()),
+        }
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for ExpiryReaperTask {
+    #[instrument(err(Debug), skip(self, client), level = "info")]
+    async fn run(&self, client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let db = global::DATABASE.get().await;
+        let expired = Deployment::find_expired(db.as_ref())
+            .await
+            .map_err(DeployError::Db)?;
+        for deployment in expired {
+            tracing::info!(
+                deployment_id = deployment.model.id,
+                expires_at = ?deployment.model.expires_at,
+                "deployment is expired. stopping deployment",
+            );
+            client
+                .insert_task(
+                    &StoppingTask::from_deployment(&deployment)
+                        .with_initiator(Initiator::system("deployment is expired")),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn uniq(&self) -> bool {
+        true
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.schedule
+            .as_ref()
+            .map(|s| Scheduled::CronPattern(s.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn expiry_reaper_stops_expired_deployments() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("expiry_reaper_stops_expired_deployments")
+                .await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+        let expired_at = Some((chrono::Utc::now() - chrono::Duration::hours(1)).fixed_offset());
+
+        // deployment#1 is running, deployment#2 is already stopped
+        for id in [1, 2] {
+            Deployment::get(conn.as_ref(), id)
+                .await
+                .unwrap()
+                .set_expires_at(conn.as_ref(), expired_at)
+                .await
+                .unwrap();
+        }
+        let expired: Vec<i32> = Deployment::find_expired(conn.as_ref())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|d| d.model.id)
+            .collect();
+        assert_eq!(expired, vec![1]);
+
+        runner
+            .insert_task(&ExpiryReaperTask { schedule: None })
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        assert_eq!(deployment.model.stopped_by.as_deref(), Some("system"));
+        assert_eq!(
+            deployment.model.stop_reason.as_deref(),
+            Some("deployment is expired")
+        );
+        let history = deployment.status_history(conn.as_ref()).await.unwrap();
+        assert!(!history.is_empty());
+        for change in history {
+            assert_eq!(change.actor, "task:stopping");
+            assert_eq!(change.initiator.as_deref(), Some("system"));
+            assert_eq!(change.reason.as_deref(), Some("deployment is expired"));
+        }
+        assert!(Deployment::find_expired(conn.as_ref())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
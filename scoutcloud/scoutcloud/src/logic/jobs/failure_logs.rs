@@ -0,0 +1,22 @@
+use crate::logic::{ci::CiBackend, DeployError, Deployment};
+use sea_orm::DatabaseConnection;
+
+/// Stores logs of the failed workflow run, so users don't have to look for them in github.
+/// Deployment is failed anyway, so errors are only reported
+pub(super) async fn capture_failure_logs(
+    db: &DatabaseConnection,
+    ci: &dyn CiBackend,
+    deployment: &Deployment,
+    err: &DeployError,
+) {
+    let Some(run_id) = err.failed_run_id() else {
+        return;
+    };
+    if let Err(err) = deployment.capture_workflow_logs(db, ci, run_id).await {
+        tracing::warn!(
+            deployment_id = deployment.model.id,
+            "failed to capture logs of failed workflow run: {:?}",
+            err
+        );
+    }
+}
@@ -1,13 +1,17 @@
-use crate::logic::GithubClient;
+use super::{dispatch_limit::DispatchLimit, pause::Pause, shutdown::Shutdown};
+use crate::{
+    logic::{ci::CiBackend, events::EventsExport, GithubClient, GithubError, InstanceConfig},
+    server::{HealthCheckSettings, RetentionSettings, WorkflowSettings},
+};
 use sea_orm::DatabaseConnection;
-use std::{fmt::Debug, sync::Arc};
+use std::{borrow::Cow, fmt::Debug, sync::Arc};
 use tokio::sync::{OnceCell, RwLock, RwLockReadGuard};
 
-pub struct Global<T> {
+pub struct Global<T: ?Sized> {
     cell: OnceCell<RwLock<Arc<T>>>,
 }
 
-impl<T: Debug + Send + Sync + 'static> Global<T> {
+impl<T: Debug + Send + Sync + ?Sized + 'static> Global<T> {
     pub const fn new() -> Self {
         Self {
             cell: OnceCell::const_new(),
@@ -31,3 +35,53 @@ impl<T: Debug + Send + Sync + 'static> Global<T> {
 pub static DATABASE: Global<DatabaseConnection> = Global::new();
 
 pub static GITHUB: Global<GithubClient> = Global::new();
+
+/// Returns client of the repo which runs workflows of the instance, it is the default repo
+/// for instances without deployment target and fork of the workflows
+pub async fn get_github_client(config: &InstanceConfig) -> Result<GithubClient, GithubError> {
+    GITHUB
+        .get()
+        .await
+        .for_instance(config.deployment_target(), config.workflow_repo())
+        .map(Cow::into_owned)
+}
+
+/// Backend which runs deploy and cleanup workflows, it is github by default
+pub static CI: Global<dyn CiBackend> = Global::new();
+
+pub static SHUTDOWN: Global<Shutdown> = Global::new();
+
+pub static HEALTH_CHECK: Global<HealthCheckSettings> = Global::new();
+
+pub static RETENTION: Global<RetentionSettings> = Global::new();
+
+pub static EVENTS: Global<EventsExport> = Global::new();
+
+pub static DISPATCH_LIMIT: Global<DispatchLimit> = Global::new();
+
+pub static PAUSE: Global<Pause> = Global::new();
+
+/// Tasks are built in sync code, so the settings are not stored in `Global`
+static WORKFLOWS: std::sync::RwLock<WorkflowSettings> = std::sync::RwLock::new(WorkflowSettings {
+    start_timeout: None,
+    stop_timeout: None,
+    check_interval: None,
+    start_check_interval: None,
+    stop_check_interval: None,
+    auto_retry_delay: None,
+    timeout_warning_percent: None,
+});
+
+pub fn init_workflows(settings: WorkflowSettings) {
+    *WORKFLOWS
+        .write()
+        .expect("workflow settings lock is poisoned") = settings;
+}
+
+/// Workflow settings of the runner, empty until the runner is started
+pub fn workflows() -> WorkflowSettings {
+    WORKFLOWS
+        .read()
+        .expect("workflow settings lock is poisoned")
+        .clone()
+}
@@ -0,0 +1,211 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use crate::logic::{
+    deploy::{Initiator, StatusActor},
+    jobs::{
+        dispatch_limit::run_unless_paused, global, metrics, shutdown, task_runs::record_task_run,
+        StoppingTask,
+    },
+    DeployError, Deployment,
+};
+use chrono::{DateTime, Utc};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use std::sync::Arc;
+use tokio::{sync::Semaphore, task::JoinSet};
+use tracing::Instrument;
+
+const DEFAULT_MAX_CONCURRENT: usize = 4;
+// with default exponential backoff of fang it is around half an hour of retries
+const MAX_RETRIES: i32 = 10;
+const ACTOR: StatusActor = StatusActor::Task("group_stopping");
+
+/// Stops several deployments within one task, up to `max_concurrent` of them at once.
+/// Every deployment is stopped and marked like by its own `StoppingTask`, so failure
+/// of one deployment doesn't abort the others. The task is retried if some deployments
+/// failed with retryable errors, already stopped ones are skipped by the retry
+#[derive(fang::serde::Serialize, fang::serde::Deserialize, Debug, Clone)]
+#[serde(crate = "fang::serde")]
+pub struct GroupStoppingTask {
+    deployment_ids: Vec<i32>,
+    max_concurrent: usize,
+    /// Set when the task was postponed because the runner is paused
+    #[serde(default)]
+    scheduled_at: Option<DateTime<Utc>>,
+    /// Who requested the stop of the deployments
+    #[serde(default)]
+    initiator: Option<Initiator>,
+}
+
+impl GroupStoppingTask {
+    pub fn from_deployment_ids(deployment_ids: Vec<i32>) -> Self {
+        Self {
+            deployment_ids,
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            scheduled_at: None,
+            initiator: None,
+        }
+    }
+
+    pub fn with_initiator(mut self, initiator: Initiator) -> Self {
+        self.initiator = Some(initiator);
+        self
+    }
+
+    /// Deployments are stopped within the global dispatch limit as well
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent.max(1);
+        self
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for GroupStoppingTask {
+    #[tracing::instrument(
+        err(Debug),
+        skip(client),
+        fields(deployment_ids = ?self.deployment_ids),
+        level = "info"
+    )]
+    async fn run(&self, client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let postponed = |scheduled_at| Self {
+            scheduled_at: Some(scheduled_at),
+            ..self.clone()
+        };
+        run_unless_paused(
+            client,
+            postponed,
+            metrics::observe_task_run("group_stopping", self.run_task()),
+        )
+        .await
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.scheduled_at.map(Scheduled::ScheduleOnce)
+    }
+
+    fn max_retries(&self) -> i32 {
+        MAX_RETRIES
+    }
+}
+
+impl GroupStoppingTask {
+    async fn run_task(&self) -> Result<(), FangError> {
+        let _guard = shutdown::register_running_task().await?;
+        let db = global::DATABASE.get().await.clone();
+        let ci = global::CI.get().await.clone();
+        let limit = global::DISPATCH_LIMIT.get().await.clone();
+        let group = Arc::new(Semaphore::new(self.max_concurrent.max(1)));
+
+        let mut stops = JoinSet::new();
+        for &deployment_id in &self.deployment_ids {
+            let (db, ci, limit, group) = (db.clone(), ci.clone(), limit.clone(), group.clone());
+            let initiator = self.initiator.clone();
+            let stop = async move {
+                let _group_permit = group
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore of the group is never closed");
+                let _permit = limit.acquire().await;
+                let result = record_task_run("stopping", deployment_id, async {
+                    let deployment = Deployment::get(db.as_ref(), deployment_id)
+                        .await
+                        .map_err(DeployError::Db)?
+                        .with_actor(ACTOR)
+                        .initiated_by(initiator);
+                    StoppingTask::from_deployment(&deployment)
+                        .stop(db.as_ref(), ci.as_ref(), deployment)
+                        .await?;
+                    Ok(())
+                })
+                .await;
+                (deployment_id, result)
+            };
+            stops.spawn(stop.instrument(tracing::info_span!("stop", deployment_id)));
+        }
+
+        let mut failures = vec![];
+        while let Some(stopped) = stops.join_next().await {
+            match stopped {
+                Ok((_, Ok(()))) => {}
+                Ok((deployment_id, Err(err))) => {
+                    failures.push(format!("deployment {deployment_id}: {}", err.description))
+                }
+                Err(err) => failures.push(format!("stop of deployment panicked: {err}")),
+            }
+        }
+        if failures.is_empty() {
+            return Ok(());
+        }
+        tracing::warn!(
+            failed = failures.len(),
+            total = self.deployment_ids.len(),
+            "failed to stop some deployments, task will be retried"
+        );
+        Err(FangError {
+            description: format!("failed to stop deployments: {}", failures.join("; ")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::ci::CiWorkflow,
+        tests_utils::{self, ci::FakeCi},
+    };
+    use octocrab::models::RunId;
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn failed_deployment_does_not_abort_group() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("failed_deployment_does_not_abort_group")
+                .await;
+        let conn = db.client();
+        // deployment 3 belongs to `instance-2`
+        let ci = Arc::new(FakeCi::default().with_failing_client("instance-2"));
+        global::CI.init(ci.clone()).await.unwrap();
+        for id in [3, 4] {
+            scoutcloud_entity::deployments::ActiveModel {
+                id: Set(id),
+                status: Set(DeploymentStatusType::Running),
+                ..Default::default()
+            }
+            .update(conn.as_ref())
+            .await
+            .unwrap();
+        }
+
+        let task = GroupStoppingTask::from_deployment_ids(vec![1, 3, 4]).with_max_concurrent(2);
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        for (id, expected) in [
+            (1, DeploymentStatusType::Stopped),
+            (3, DeploymentStatusType::Failed),
+            (4, DeploymentStatusType::Stopped),
+        ] {
+            let deployment = Deployment::get(conn.as_ref(), id).await.unwrap();
+            assert_eq!(
+                deployment.model.status, expected,
+                "unexpected status of deployment {id}. error: {:?}",
+                deployment.model.error
+            );
+        }
+        let failed = Deployment::get(conn.as_ref(), 3).await.unwrap();
+        assert!(failed.model.terminal_error);
+        let failing_dispatch = ci
+            .dispatched()
+            .iter()
+            .position(|dispatched| *dispatched == (CiWorkflow::Cleanup, "instance-2".to_string()))
+            .expect("workflow of instance-2 was not dispatched");
+        assert_eq!(failed.run_id(), Some(RunId(failing_dispatch as u64 + 1)));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{logic::url_guard::UrlGuard, server::HealthCheckSettings};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+const MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum HealthCheckError {
+    /// Instance didn't become healthy in time, contains the last observed problem
+    Unhealthy(String),
+    Interrupted,
+}
+
+/// Urls of instances come from their configs, so requests are checked by `guard`
+pub(super) fn build_client(
+    settings: &HealthCheckSettings,
+    guard: &UrlGuard,
+) -> Result<reqwest::Client, String> {
+    guard
+        .client_builder()
+        .timeout(settings.interval.min(MAX_REQUEST_TIMEOUT))
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+/// Performs single request to the health endpoint, returns the observed problem on failure
+pub(super) async fn check_health(
+    client: &reqwest::Client,
+    guard: &UrlGuard,
+    url: &Url,
+) -> Result<(), String> {
+    guard
+        .check_url(url)
+        .map_err(|reason| format!("health endpoint is not allowed: {reason}"))?;
+    match client.get(url.clone()).send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("health endpoint returned {}", response.status())),
+        Err(err) => Err(format!("health endpoint is not reachable: {err}")),
+    }
+}
+
+/// Polls the health endpoint of the instance until it responds with success status
+pub(super) async fn wait_until_healthy(
+    url: &Url,
+    settings: &HealthCheckSettings,
+    guard: &UrlGuard,
+    cancel: &CancellationToken,
+) -> Result<(), HealthCheckError> {
+    let client = build_client(settings, guard).map_err(HealthCheckError::Unhealthy)?;
+    let deadline = tokio::time::Instant::now() + settings.timeout;
+    loop {
+        let problem = match check_health(&client, guard, url).await {
+            Ok(()) => return Ok(()),
+            Err(problem) => problem,
+        };
+        tracing::debug!(url = %url, problem = %problem, "instance is not healthy yet");
+        if tokio::time::Instant::now() + settings.interval > deadline {
+            return Err(HealthCheckError::Unhealthy(format!(
+                "{problem}, gave up after {:?}",
+                settings.timeout
+            )));
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(settings.interval) => {}
+            _ = cancel.cancelled() => return Err(HealthCheckError::Interrupted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::url_guard::local_guard;
+    use httpmock::{Method::GET, MockServer};
+    use pretty_assertions::assert_eq;
+
+    fn settings(timeout_ms: u64) -> HealthCheckSettings {
+        HealthCheckSettings {
+            enabled: true,
+            path: "/api/health".to_string(),
+            timeout: Duration::from_millis(timeout_ms),
+            interval: Duration::from_millis(50),
+        }
+    }
+
+    #[tokio::test]
+    async fn waits_until_instance_is_healthy() {
+        let server = MockServer::start_async().await;
+        let health = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/health");
+                then.status(200).body(r#"{"healthy": true}"#);
+            })
+            .await;
+        let url = server.url("/api/health").parse().unwrap();
+
+        let result = wait_until_healthy(
+            &url,
+            &settings(1000),
+            &local_guard(),
+            &CancellationToken::new(),
+        )
+        .await;
+        assert_eq!(result, Ok(()));
+        health.assert_hits_async(1).await;
+    }
+
+    #[tokio::test]
+    async fn unhealthy_instance_times_out() {
+        let server = MockServer::start_async().await;
+        let health = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/health");
+                then.status(503);
+            })
+            .await;
+        let url = server.url("/api/health").parse().unwrap();
+
+        let result = wait_until_healthy(
+            &url,
+            &settings(200),
+            &local_guard(),
+            &CancellationToken::new(),
+        )
+        .await;
+        assert_eq!(
+            result,
+            Err(HealthCheckError::Unhealthy(
+                "health endpoint returned 503 Service Unavailable, gave up after 200ms".to_string()
+            ))
+        );
+        assert!(health.hits_async().await > 1);
+    }
+
+    #[tokio::test]
+    async fn waiting_is_interrupted_by_shutdown() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/health");
+                then.status(503);
+            })
+            .await;
+        let url = server.url("/api/health").parse().unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let result = wait_until_healthy(&url, &settings(10_000), &local_guard(), &cancel).await;
+        assert_eq!(result, Err(HealthCheckError::Interrupted));
+    }
+
+    #[tokio::test]
+    async fn private_instance_is_not_requested() {
+        let server = MockServer::start_async().await;
+        let health = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/health");
+                then.status(200);
+            })
+            .await;
+        let url: Url = server.url("/api/health").parse().unwrap();
+
+        let result = wait_until_healthy(
+            &url,
+            &settings(100),
+            &UrlGuard::default(),
+            &CancellationToken::new(),
+        )
+        .await;
+        assert_eq!(
+            result,
+            Err(HealthCheckError::Unhealthy(format!(
+                "health endpoint is not allowed: url '{url}' points to a private, \
+                 loopback or link-local address, gave up after 100ms"
+            )))
+        );
+        health.assert_hits_async(0).await;
+    }
+}
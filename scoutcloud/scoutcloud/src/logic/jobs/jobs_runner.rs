@@ -1,36 +1,83 @@
-use crate::logic::{
-    jobs::{balance::CheckBalanceTask, StartingTask, StoppingTask},
-    DeployError, GithubClient,
+use crate::{
+    logic::{
+        ci::CiBackend,
+        deploy::Initiator,
+        events::EventsExport,
+        jobs::{
+            balance::CheckBalanceTask, events_export::EventsExportTask, expiry::ExpiryReaperTask,
+            liveness::LivenessTask, retention::RetentionTask, stuck::StuckDeploymentTask,
+            webhook_delivery::WebhookDeliveryTask, CancelTask, DispatchLimit, GroupStoppingTask,
+            Pause, QueuePosition, RestartTask, StartingTask, StoppingTask,
+        },
+        DeployError, Deployment, GithubClient, Instance,
+    },
+    server::JobsSettings,
 };
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use fang::{
     asynk::async_queue::AsyncQueue, AsyncQueueable, AsyncRunnable, AsyncWorkerPool, FangError,
     SleepParams,
 };
-use sea_orm::DatabaseConnection;
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbErr, TransactionTrait};
 use std::{sync::Arc, time::Duration};
 use tokio::sync::Mutex;
 
 pub struct JobsRunner {
     queue: Mutex<AsyncQueue>,
+    started_at: DateTime<Utc>,
 }
 
 impl JobsRunner {
     pub async fn default_start(
         scoutcloud_db: Arc<DatabaseConnection>,
         github: Arc<GithubClient>,
+        ci: Arc<dyn CiBackend>,
+        jobs: &JobsSettings,
         fang_db_url: &str,
+        fang_max_pool_size: u32,
     ) -> Result<Self, anyhow::Error> {
         // it's important to init global values before starting the runner
         // because runner will use global variables since fang doesn't support context
         super::global::DATABASE
-            .init(scoutcloud_db)
+            .init(scoutcloud_db.clone())
             .await
             .expect("database already initialized");
         super::global::GITHUB
             .init(github)
             .await
             .expect("github client already initialized");
+        super::global::CI
+            .init(ci)
+            .await
+            .expect("ci backend already initialized");
+        super::global::SHUTDOWN
+            .init(Default::default())
+            .await
+            .expect("shutdown already initialized");
+        super::global::HEALTH_CHECK
+            .init(Arc::new(jobs.health_check.clone()))
+            .await
+            .expect("health check settings already initialized");
+        super::global::RETENTION
+            .init(Arc::new(jobs.retention.clone()))
+            .await
+            .expect("retention settings already initialized");
+        super::global::DISPATCH_LIMIT
+            .init(Arc::new(DispatchLimit::from_settings(&jobs.dispatch_limit)))
+            .await
+            .expect("dispatch limit already initialized");
+        super::global::PAUSE
+            .init(Default::default())
+            .await
+            .expect("pause already initialized");
+        super::global::init_workflows(jobs.workflows.clone());
+        let events = EventsExport::from_settings(jobs.events.as_ref())
+            .context("creating events producer")?;
+        super::global::EVENTS
+            .init(Arc::new(events))
+            .await
+            .expect("events producer already initialized");
 
         let sleep_params = SleepParams {
             sleep_period: Duration::from_secs(1),
@@ -38,18 +85,31 @@ impl JobsRunner {
             min_sleep_period: Duration::from_secs(1),
             sleep_step: Duration::from_secs(1),
         };
-        let runner = Self::start_pool(fang_db_url, sleep_params).await?;
+        let runner = Self::start_pool(
+            fang_db_url,
+            fang_max_pool_size,
+            jobs.number_of_workers(),
+            sleep_params,
+        )
+        .await?;
         runner.schedule_tasks().await?;
+        runner
+            .resume_interrupted_deployments(scoutcloud_db.as_ref(), jobs.remove_stale_tasks)
+            .await
+            .context("resuming interrupted deployments")?;
         Ok(runner)
     }
 
     pub async fn start_pool(
         db_url: &str,
+        max_pool_size: u32,
+        number_of_workers: u32,
         sleep_params: SleepParams,
     ) -> Result<Self, anyhow::Error> {
-        tracing::info!("start jobs runners pool");
-
-        let max_pool_size: u32 = 10;
+        tracing::info!(number_of_workers, "start jobs runners pool");
+        let started_at = Utc::now();
+        // every worker holds a connection while it polls the queue
+        let max_pool_size = max_pool_size.max(number_of_workers);
 
         let mut queue = AsyncQueue::builder()
             .uri(db_url)
@@ -61,7 +121,7 @@ impl JobsRunner {
             .context("connecting to fang database")?;
 
         let mut pool: AsyncWorkerPool<AsyncQueue> = AsyncWorkerPool::builder()
-            .number_of_workers(max_pool_size)
+            .number_of_workers(number_of_workers)
             .sleep_params(sleep_params)
             .retention_mode(fang::RetentionMode::RemoveFinished)
             .queue(queue.clone())
@@ -70,23 +130,135 @@ impl JobsRunner {
 
         let queue = Mutex::new(queue);
 
-        Ok(Self { queue })
+        Ok(Self { queue, started_at })
     }
 
     pub async fn schedule_tasks(&self) -> Result<(), anyhow::Error> {
         let queue = self.queue.lock().await;
         queue.schedule_task(&CheckBalanceTask::default()).await?;
+        queue.schedule_task(&ExpiryReaperTask::default()).await?;
+        queue.schedule_task(&StuckDeploymentTask::default()).await?;
+        queue.schedule_task(&WebhookDeliveryTask::default()).await?;
+        queue.schedule_task(&LivenessTask::default()).await?;
+        queue.schedule_task(&RetentionTask::default()).await?;
+        queue.schedule_task(&EventsExportTask::default()).await?;
         Ok(())
     }
 
-    pub async fn insert_starting_task(&self, deployment_id: i32) -> Result<(), anyhow::Error> {
-        self.insert_task(&StartingTask::from_deployment_id(deployment_id))
+    pub async fn insert_starting_task(
+        &self,
+        deployment: &Deployment,
+        initiator: Initiator,
+    ) -> Result<(), anyhow::Error> {
+        self.insert_task(&StartingTask::from_deployment(deployment).with_initiator(initiator))
+            .await
+    }
+
+    pub async fn insert_stopping_task(
+        &self,
+        deployment: &Deployment,
+        initiator: Initiator,
+    ) -> Result<(), anyhow::Error> {
+        self.insert_task(&StoppingTask::from_deployment(deployment).with_initiator(initiator))
+            .await
+    }
+
+    /// Deployments are stopped concurrently within a single task
+    pub async fn insert_group_stopping_task(
+        &self,
+        deployment_ids: Vec<i32>,
+        initiator: Initiator,
+    ) -> Result<(), anyhow::Error> {
+        self.insert_task(
+            &GroupStoppingTask::from_deployment_ids(deployment_ids).with_initiator(initiator),
+        )
+        .await
+    }
+
+    pub async fn insert_restart_task(
+        &self,
+        deployment_id: i32,
+        initiator: Initiator,
+    ) -> Result<(), anyhow::Error> {
+        self.insert_task(&RestartTask::from_deployment_id(deployment_id).with_initiator(initiator))
             .await
     }
 
-    pub async fn insert_stopping_task(&self, deployment_id: i32) -> Result<(), anyhow::Error> {
-        self.insert_task(&StoppingTask::from_deployment_id(deployment_id))
+    pub async fn insert_cancel_task(&self, deployment_id: i32) -> Result<(), anyhow::Error> {
+        self.insert_task(&CancelTask::from_deployment_id(deployment_id))
+            .await
+    }
+
+    /// Tasks left `in_progress` by the previous process are removed only with `remove_stale_tasks`
+    pub async fn resume_interrupted_deployments<C>(
+        &self,
+        db: &C,
+        remove_stale_tasks: bool,
+    ) -> Result<Vec<i32>, anyhow::Error>
+    where
+        C: ConnectionTrait + TransactionTrait,
+    {
+        let queue = self.queue.lock().await;
+        let stale_before = remove_stale_tasks.then_some(self.started_at);
+        super::resume::resume_interrupted_deployments(db, &*queue, stale_before).await
+    }
+
+    /// Returns place of the task of the deployment in the queue,
+    /// or `None` if the deployment has no task to run
+    pub async fn queue_position<C>(
+        &self,
+        db: &C,
+        deployment_id: i32,
+    ) -> Result<Option<QueuePosition>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let max_concurrent = super::global::DISPATCH_LIMIT.get().await.max_concurrent();
+        super::queue::queue_position(db, deployment_id, max_concurrent).await
+    }
+
+    /// Rejects new deploys while too many tasks are waiting to dispatch workflows,
+    /// tasks which are already in the queue are not affected
+    pub async fn check_queue_depth<C>(&self, db: &C) -> Result<(), DeployError>
+    where
+        C: ConnectionTrait,
+    {
+        let Some(max_pending) = super::global::DISPATCH_LIMIT
+            .get()
             .await
+            .max_pending_tasks()
+        else {
+            return Ok(());
+        };
+        let pending = super::queue::pending_dispatching_tasks(db).await?;
+        if pending >= max_pending {
+            tracing::warn!(
+                pending = pending,
+                max_pending = max_pending,
+                "too many tasks are waiting in the queue, new deploy is rejected"
+            );
+            return Err(DeployError::Overloaded);
+        }
+        Ok(())
+    }
+
+    /// Rejects new deploys of the instance while github actions of the owner of its repo
+    /// are running out of minutes, see `ActionsBudget`
+    pub async fn check_actions_budget(&self, instance: &Instance) -> Result<(), DeployError> {
+        let github = super::global::get_github_client(&instance.parsed_config()).await?;
+        github.check_actions_budget().await?;
+        Ok(())
+    }
+
+    /// Commits the config of the instance with the github client of the runner,
+    /// for configs which are changed right before the instance is deployed
+    pub async fn commit_config(
+        &self,
+        instance: &Instance,
+        action_name: &str,
+    ) -> Result<(), DeployError> {
+        let github = super::global::GITHUB.get().await.clone();
+        instance.commit(github.as_ref(), action_name).await
     }
 
     pub async fn insert_task(&self, task: &dyn AsyncRunnable) -> Result<(), anyhow::Error> {
@@ -95,6 +267,33 @@ impl JobsRunner {
         Ok(())
     }
 
+    /// Stops starting new tasks and waits up to `grace_period` for running tasks,
+    /// which stop waiting for github workflows at the next safe point.
+    /// Returns false if some tasks are still running after the grace period
+    pub async fn shutdown(&self, grace_period: Duration) -> bool {
+        tracing::info!("shutting down jobs runner");
+        let shutdown = super::global::SHUTDOWN.get().await.clone();
+        shutdown.shutdown(grace_period).await
+    }
+
+    /// Paused runner keeps tasks which dispatch github workflows in the queue,
+    /// running tasks are allowed to finish
+    pub async fn set_paused(&self, paused: bool) {
+        let was_paused = self.pause_state().await.set_paused(paused);
+        if was_paused != paused {
+            tracing::info!(paused = paused, "jobs runner pause is changed");
+        }
+    }
+
+    pub async fn pause_state(&self) -> Arc<Pause> {
+        super::global::PAUSE.get().await.clone()
+    }
+
+    /// Number of tasks which are running right now
+    pub async fn running_tasks(&self) -> usize {
+        super::global::SHUTDOWN.get().await.running_tasks()
+    }
+
     pub fn queue(&self) -> &Mutex<AsyncQueue> {
         &self.queue
     }
@@ -107,3 +306,50 @@ impl From<DeployError> for FangError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use fang::{typetag, Scheduled};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(fang::serde::Serialize, fang::serde::Deserialize, Debug)]
+    #[serde(crate = "fang::serde")]
+    struct SleepingTask {}
+
+    #[typetag::serde]
+    #[fang::async_trait]
+    impl AsyncRunnable for SleepingTask {
+        async fn run(&self, _client: &dyn AsyncQueueable) -> Result<(), FangError> {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn cron(&self) -> Option<Scheduled> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn configured_number_of_workers_is_used() {
+        let db = tests_utils::init::test_db("test", "configured_number_of_workers_is_used").await;
+        let runner = tests_utils::init::test_jobs_runner_with_workers(&db, 3).await;
+
+        for _ in 0..9 {
+            runner.insert_task(&SleepingTask {}).await.unwrap();
+        }
+        tests_utils::db::wait_for_empty_fang_tasks(db.client())
+            .await
+            .unwrap();
+        assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,266 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::{
+    global,
+    health_check::{build_client, check_health},
+    RestartTask,
+};
+use crate::{
+    logic::{
+        deploy::{Initiator, StatusActor},
+        url_guard::{self, UrlGuard},
+        DeployError, Deployment,
+    },
+    server::HealthCheckSettings,
+};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::{ConnectionTrait, TransactionTrait};
+use tracing::instrument;
+
+const DEFAULT_FAILURE_THRESHOLD: i32 = 3;
+const ACTOR: StatusActor = StatusActor::Task("liveness");
+
+/// Health-checks running deployments. After `failure_threshold` failed checks in a row
+/// the deployment is restarted if its instance has `auto_redeploy` enabled,
+/// otherwise it is marked as failed
+#[derive(fang::serde::Serialize, fang::serde::Deserialize)]
+#[serde(crate = "fang::serde")]
+pub struct LivenessTask {
+    schedule: Option<String>,
+    failure_threshold: i32,
+}
+
+impl Default for LivenessTask {
+    fn default() -> Self {
+        Self {
+            schedule: Some("0 */1 * * * *".to_string()),
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+        }
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for LivenessTask {
+    #[instrument(err(Debug), skip(self, client), level = "info")]
+    async fn run(&self, client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let settings = global::HEALTH_CHECK.get().await.clone();
+        if !settings.enabled {
+            return Ok(());
+        }
+        let db = global::DATABASE.get().await;
+        let to_restart = check_liveness(
+            db.as_ref(),
+            &settings,
+            &url_guard::guard(),
+            self.failure_threshold,
+        )
+        .await?;
+        for deployment_id in to_restart {
+            client
+                .insert_task(
+                    &RestartTask::from_deployment_id(deployment_id)
+                        .with_initiator(Initiator::system("deployment failed liveness checks")),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn uniq(&self) -> bool {
+        true
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.schedule
+            .as_ref()
+            .map(|s| Scheduled::CronPattern(s.clone()))
+    }
+}
+
+/// Checks every running deployment once and updates its counter of failures in a row.
+/// Returns ids of deployments which should be restarted
+async fn check_liveness<C>(
+    db: &C,
+    settings: &HealthCheckSettings,
+    guard: &UrlGuard,
+    failure_threshold: i32,
+) -> Result<Vec<i32>, DeployError>
+where
+    C: ConnectionTrait + TransactionTrait,
+{
+    let client = build_client(settings, guard).map_err(|err| anyhow::anyhow!(err))?;
+    let running = Deployment::find_with_statuses(db, &[DeploymentStatusType::Running]).await?;
+    let mut to_restart = vec![];
+    for deployment in running {
+        let mut deployment = deployment.with_actor(ACTOR);
+        let result = match deployment
+            .instance_config()
+            .parse_health_url(&settings.path)
+        {
+            Ok(url) => check_health(&client, guard, &url).await,
+            Err(err) => Err(err.to_string()),
+        };
+        let problem = match result {
+            Ok(()) => {
+                if deployment.model.liveness_failures > 0 {
+                    deployment.set_liveness_failures(db, 0).await?;
+                }
+                continue;
+            }
+            Err(problem) => problem,
+        };
+        let failures = deployment.model.liveness_failures + 1;
+        tracing::warn!(
+            deployment_id = deployment.model.id,
+            failures = failures,
+            problem = %problem,
+            "running deployment is not healthy",
+        );
+        if failures < failure_threshold {
+            deployment.set_liveness_failures(db, failures).await?;
+            continue;
+        }
+        let instance = deployment.get_instance(db).await?;
+        if instance.model.auto_redeploy {
+            tracing::info!(
+                deployment_id = deployment.model.id,
+                "auto redeploy is enabled. restarting deployment",
+            );
+            deployment.set_liveness_failures(db, 0).await?;
+            to_restart.push(deployment.model.id);
+        } else {
+            let error = DeployError::Unhealthy(format!("{problem} ({failures} checks in a row)"));
+            deployment.mark_as_error(db, &error).await?;
+        }
+    }
+    Ok(to_restart)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{logic::url_guard::local_guard, tests_utils};
+    use blockscout_service_launcher::test_database::TestDbGuard;
+    use httpmock::{Method::GET, Mock, MockServer};
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set, DatabaseConnection};
+    use std::time::Duration;
+
+    const RUNNING_DEPLOYMENT_ID: i32 = 1;
+    const THRESHOLD: i32 = 3;
+
+    fn settings() -> HealthCheckSettings {
+        HealthCheckSettings {
+            enabled: true,
+            path: "/api/health".to_string(),
+            timeout: Duration::from_secs(1),
+            interval: Duration::from_millis(100),
+        }
+    }
+
+    async fn init(test_name: &str, auto_redeploy: bool) -> (TestDbGuard, MockServer) {
+        let db = tests_utils::init::test_db("test", test_name).await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let server = MockServer::start_async().await;
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(RUNNING_DEPLOYMENT_ID),
+            parsed_config: Set(
+                serde_json::json!({"frontend": {"ingress": {"hostname": server.base_url()}}}),
+            ),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        let mut instance = Deployment::get(conn.as_ref(), RUNNING_DEPLOYMENT_ID)
+            .await
+            .unwrap()
+            .get_instance(conn.as_ref())
+            .await
+            .unwrap();
+        instance
+            .set_auto_redeploy(conn.as_ref(), auto_redeploy)
+            .await
+            .unwrap();
+        (db, server)
+    }
+
+    async fn mock_health(server: &MockServer, status: u16) -> Mock<'_> {
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/health");
+                then.status(status);
+            })
+            .await
+    }
+
+    async fn check(conn: &DatabaseConnection, times: usize) -> Vec<i32> {
+        let mut to_restart = vec![];
+        for _ in 0..times {
+            to_restart = check_liveness(conn, &settings(), &local_guard(), THRESHOLD)
+                .await
+                .unwrap();
+        }
+        to_restart
+    }
+
+    async fn deployment(conn: &DatabaseConnection) -> Deployment {
+        Deployment::get(conn, RUNNING_DEPLOYMENT_ID).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn transient_failure_is_ignored() {
+        let (db, server) = init("liveness_transient_failure_is_ignored", true).await;
+        let conn = db.client();
+
+        let unhealthy = mock_health(&server, 503).await;
+        assert_eq!(check(&conn, 2).await, Vec::<i32>::new());
+        assert_eq!(deployment(&conn).await.model.liveness_failures, 2);
+        unhealthy.delete_async().await;
+
+        mock_health(&server, 200).await;
+        assert_eq!(check(&conn, 1).await, Vec::<i32>::new());
+        let deployment = deployment(&conn).await;
+        assert_eq!(deployment.model.status, DeploymentStatusType::Running);
+        assert_eq!(deployment.model.liveness_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn sustained_failure_restarts_deployment() {
+        let (db, server) = init("liveness_sustained_failure_restarts_deployment", true).await;
+        let conn = db.client();
+
+        mock_health(&server, 503).await;
+        assert_eq!(check(&conn, 2).await, Vec::<i32>::new());
+        assert_eq!(check(&conn, 1).await, vec![RUNNING_DEPLOYMENT_ID]);
+        let deployment = deployment(&conn).await;
+        assert_eq!(deployment.model.status, DeploymentStatusType::Running);
+        assert_eq!(deployment.model.liveness_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn sustained_failure_fails_deployment_without_auto_redeploy() {
+        let (db, server) = init("liveness_sustained_failure_fails_deployment", false).await;
+        let conn = db.client();
+
+        mock_health(&server, 503).await;
+        assert_eq!(check(&conn, THRESHOLD as usize).await, Vec::<i32>::new());
+        let deployment = deployment(&conn).await;
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert_eq!(deployment.model.error_code.as_deref(), Some("unhealthy"));
+        let error = deployment.model.error.unwrap_or_default();
+        assert!(
+            error.starts_with("app unhealthy: health endpoint returned 503"),
+            "unexpected error: {error}"
+        );
+        assert!(
+            error.ends_with("(3 checks in a row)"),
+            "unexpected error: {error}"
+        );
+    }
+}
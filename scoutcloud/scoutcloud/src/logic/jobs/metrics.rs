@@ -0,0 +1,172 @@
+use crate::logic::GithubError;
+use fang::FangError;
+use lazy_static::lazy_static;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use std::{future::Future, panic::AssertUnwindSafe, task::Poll, time::Instant};
+
+lazy_static! {
+    pub static ref WORKFLOW_WAIT_TIME: HistogramVec = register_histogram_vec!(
+        "scoutcloud_workflow_wait_time_seconds",
+        "time of waiting for github workflow to complete",
+        &["task", "outcome"],
+        vec![10.0, 30.0, 60.0, 120.0, 180.0, 300.0, 450.0, 600.0, 900.0, 1200.0, 1800.0, 3600.0],
+    )
+    .unwrap();
+    pub static ref DEPLOYMENT_OUTCOMES: IntCounterVec = register_int_counter_vec!(
+        "scoutcloud_deployment_outcomes_total",
+        "number of succeeded, failed and timed out workflows of deployments",
+        &["task", "outcome"],
+    )
+    .unwrap();
+    pub static ref WORKFLOWS_APPROACHING_TIMEOUT: IntCounterVec = register_int_counter_vec!(
+        "scoutcloud_workflows_approaching_timeout_total",
+        "number of workflows which were still running close to their timeout",
+        &["task"],
+    )
+    .unwrap();
+    pub static ref TASK_RUNS: IntCounterVec = register_int_counter_vec!(
+        "scoutcloud_task_runs_total",
+        "number of finished runs of jobs, including failed and panicked ones",
+        &["task", "result"],
+    )
+    .unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkflowOutcome {
+    Succeeded,
+    Failed,
+    TimedOut,
+    // waiting will be continued by the retry or after restart
+    Retried,
+    Interrupted,
+}
+
+impl WorkflowOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkflowOutcome::Succeeded => "succeeded",
+            WorkflowOutcome::Failed => "failed",
+            WorkflowOutcome::TimedOut => "timed_out",
+            WorkflowOutcome::Retried => "retried",
+            WorkflowOutcome::Interrupted => "interrupted",
+        }
+    }
+
+    fn from_result<T>(result: &Result<T, GithubError>) -> Self {
+        match result {
+            Ok(_) => WorkflowOutcome::Succeeded,
+            Err(GithubError::Interrupted) => WorkflowOutcome::Interrupted,
+            Err(err) if err.is_retryable() => WorkflowOutcome::Retried,
+            Err(GithubError::WorkflowTimeout { .. }) => WorkflowOutcome::TimedOut,
+            Err(_) => WorkflowOutcome::Failed,
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        matches!(
+            self,
+            WorkflowOutcome::Succeeded | WorkflowOutcome::Failed | WorkflowOutcome::TimedOut
+        )
+    }
+}
+
+/// Measures waiting for the workflow and counts its outcome
+pub(super) async fn observe_workflow_wait<T>(
+    task: &'static str,
+    wait: impl Future<Output = Result<T, GithubError>>,
+) -> Result<T, GithubError> {
+    let started_at = Instant::now();
+    let result = wait.await;
+    let elapsed = started_at.elapsed();
+    let outcome = WorkflowOutcome::from_result(&result);
+    WORKFLOW_WAIT_TIME
+        .with_label_values(&[task, outcome.as_str()])
+        .observe(elapsed.as_secs_f64());
+    if outcome.is_final() {
+        DEPLOYMENT_OUTCOMES
+            .with_label_values(&[task, outcome.as_str()])
+            .inc();
+    }
+    result
+}
+
+/// Counts the result of the run of the task. Panic is counted as well
+/// and then propagated further
+pub(super) async fn observe_task_run(
+    task: &'static str,
+    run: impl Future<Output = Result<(), FangError>>,
+) -> Result<(), FangError> {
+    let mut run = std::pin::pin!(run);
+    let result = std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(AssertUnwindSafe(|| run.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(result)) => Poll::Ready(Ok(result)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    })
+    .await;
+    match result {
+        Ok(result) => {
+            let label = if result.is_ok() { "ok" } else { "error" };
+            TASK_RUNS.with_label_values(&[task, label]).inc();
+            result
+        }
+        Err(panic) => {
+            TASK_RUNS.with_label_values(&[task, "panicked"]).inc();
+            std::panic::resume_unwind(panic)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::github::types::{RunConclusion, RunStatus};
+    use octocrab::models::RunId;
+
+    #[test]
+    fn workflow_outcome_works() {
+        let run_id = RunId(1);
+        let cases = [
+            (Ok(()), WorkflowOutcome::Succeeded),
+            (
+                Err(GithubError::WorkflowFailed {
+                    run_id,
+                    conclusion: RunConclusion::Failure,
+                }),
+                WorkflowOutcome::Failed,
+            ),
+            (
+                Err(GithubError::WorkflowTimeout {
+                    run_id,
+                    status: RunStatus::InProgress,
+                }),
+                WorkflowOutcome::TimedOut,
+            ),
+            (Err(GithubError::Interrupted), WorkflowOutcome::Interrupted),
+        ];
+        for (result, expected) in cases {
+            assert_eq!(
+                WorkflowOutcome::from_result(&result),
+                expected,
+                "unexpected outcome of {result:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn panicked_task_run_is_counted() {
+        let panicked = || {
+            TASK_RUNS
+                .with_label_values(&["test_panic", "panicked"])
+                .get()
+        };
+        let before = panicked();
+        let handle = tokio::spawn(observe_task_run("test_panic", async {
+            panic!("task panicked");
+        }));
+        assert!(handle.await.is_err());
+        assert_eq!(panicked(), before + 1);
+    }
+}
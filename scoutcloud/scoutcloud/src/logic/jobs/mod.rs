@@ -1,9 +1,39 @@
 mod balance;
+mod cancel;
+mod deployment_locks;
+mod dispatch_limit;
+mod events_export;
+mod expiry;
+mod failure_logs;
 pub(crate) mod global;
+mod group_stopping;
+mod health_check;
 mod jobs_runner;
+mod liveness;
+mod metrics;
+mod pause;
+mod polls;
+mod queue;
+mod readable_duration;
+mod restart;
+mod resume;
+mod retention;
+mod shutdown;
 mod starting;
 mod stopping;
+mod stuck;
+mod task_runs;
+mod timeout_warning;
+mod webhook_delivery;
 
+pub use cancel::CancelTask;
+pub use dispatch_limit::DispatchLimit;
+pub use group_stopping::GroupStoppingTask;
 pub use jobs_runner::JobsRunner;
+pub use pause::Pause;
+pub use polls::stop_polls;
+pub use queue::QueuePosition;
+pub use restart::RestartTask;
+pub use shutdown::Shutdown;
 pub use starting::StartingTask;
 pub use stopping::StoppingTask;
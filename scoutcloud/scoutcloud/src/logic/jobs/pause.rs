@@ -0,0 +1,20 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Pauses processing of tasks which dispatch github workflows, e.g. during maintenance.
+/// Paused tasks stay in the queue and are postponed until the runner is resumed,
+/// tasks which are running already are not affected
+#[derive(Debug, Default)]
+pub struct Pause {
+    paused: AtomicBool,
+}
+
+impl Pause {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns the previous state
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.swap(paused, Ordering::SeqCst)
+    }
+}
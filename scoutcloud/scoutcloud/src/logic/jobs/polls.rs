@@ -0,0 +1,127 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio_util::sync::CancellationToken;
+
+lazy_static! {
+    static ref POLLS: Mutex<HashMap<i32, Vec<PollEntry>>> = Default::default();
+}
+
+static NEXT_POLL_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+struct PollEntry {
+    id: u64,
+    token: CancellationToken,
+    finalized: Arc<AtomicBool>,
+}
+
+/// Registration of the task which polls workflow run of the deployment.
+/// The webhook or the task which finalized the deployment stops the poll through it,
+/// so the task doesn't run to its next check and doesn't finalize the deployment again
+#[derive(Debug)]
+pub struct PollGuard {
+    deployment_id: i32,
+    id: u64,
+    token: CancellationToken,
+    finalized: Arc<AtomicBool>,
+}
+
+impl PollGuard {
+    /// Cancelled on shutdown or when the deployment is finalized by somebody else
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Returns true if the poll was stopped because the deployment is already finalized,
+    /// so the task should leave the deployment as it is
+    pub fn is_finalized(&self) -> bool {
+        self.finalized.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for PollGuard {
+    fn drop(&mut self) {
+        let mut polls = POLLS.lock().expect("polls lock is poisoned");
+        if let Some(entries) = polls.get_mut(&self.deployment_id) {
+            entries.retain(|entry| entry.id != self.id);
+            if entries.is_empty() {
+                polls.remove(&self.deployment_id);
+            }
+        }
+    }
+}
+
+/// Registers the poll of the deployment, its token is cancelled on `shutdown` as well
+pub fn register_poll(deployment_id: i32, shutdown: &CancellationToken) -> PollGuard {
+    let guard = PollGuard {
+        deployment_id,
+        id: NEXT_POLL_ID.fetch_add(1, Ordering::SeqCst),
+        token: shutdown.child_token(),
+        finalized: Default::default(),
+    };
+    POLLS
+        .lock()
+        .expect("polls lock is poisoned")
+        .entry(deployment_id)
+        .or_default()
+        .push(PollEntry {
+            id: guard.id,
+            token: guard.token.clone(),
+            finalized: guard.finalized.clone(),
+        });
+    guard
+}
+
+/// Stops in-flight polls of the finalized deployment. Returns number of stopped polls
+pub fn stop_polls(deployment_id: i32) -> usize {
+    let entries = POLLS
+        .lock()
+        .expect("polls lock is poisoned")
+        .remove(&deployment_id)
+        .unwrap_or_default();
+    for entry in &entries {
+        entry.finalized.store(true, Ordering::SeqCst);
+        entry.token.cancel();
+    }
+    entries.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_polls_of_finalized_deployment_are_stopped() {
+        let shutdown = CancellationToken::new();
+        let first = register_poll(1001, &shutdown);
+        let second = register_poll(1001, &shutdown);
+        let other = register_poll(1002, &shutdown);
+
+        assert_eq!(stop_polls(1001), 2);
+        for poll in [&first, &second] {
+            assert!(poll.token().is_cancelled());
+            assert!(poll.is_finalized());
+        }
+        assert!(!other.token().is_cancelled());
+        // already stopped polls are not stopped again
+        assert_eq!(stop_polls(1001), 0);
+
+        drop(other);
+        assert_eq!(stop_polls(1002), 0);
+    }
+
+    #[test]
+    fn shutdown_is_not_finalization() {
+        let shutdown = CancellationToken::new();
+        let poll = register_poll(1003, &shutdown);
+        shutdown.cancel();
+        assert!(poll.token().is_cancelled());
+        assert!(!poll.is_finalized());
+    }
+}
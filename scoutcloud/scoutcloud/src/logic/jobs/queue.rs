@@ -0,0 +1,204 @@
+use super::metrics::{WorkflowOutcome, WORKFLOW_WAIT_TIME};
+use chrono::Utc;
+use db::sea_orm_active_enums::FangTaskState;
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, ConnectionTrait, QueryOrder};
+use std::time::Duration;
+
+/// Place of the task of the deployment in the queue of fang
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePosition {
+    /// Number of tasks of the same type which are run or scheduled before the task
+    /// plus one. Zero means that the task is running right now
+    pub position: u64,
+    /// Rough estimation of the time until the workflow of the deployment is completed,
+    /// unknown until workflows of such tasks were waited for
+    pub eta: Option<Duration>,
+}
+
+/// Finds the task of the deployment among tasks that are going to be executed
+/// or are executing right now. `max_concurrent` tasks wait for workflows at once
+pub async fn queue_position<C>(
+    db: &C,
+    deployment_id: i32,
+    max_concurrent: usize,
+) -> Result<Option<QueuePosition>, DbErr>
+where
+    C: ConnectionTrait,
+{
+    let tasks = db::fang_tasks::Entity::find()
+        .filter(db::fang_tasks::Column::State.is_in([
+            FangTaskState::New,
+            FangTaskState::Retried,
+            FangTaskState::InProgress,
+        ]))
+        .order_by_asc(db::fang_tasks::Column::ScheduledAt)
+        .order_by_asc(db::fang_tasks::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let is_deployment_task =
+        |task: &db::fang_tasks::Model| task_deployment_ids(&task.metadata).contains(&deployment_id);
+    let Some(index) = tasks
+        .iter()
+        .position(|task| is_deployment_task(task) && task.state == FangTaskState::InProgress)
+        .or_else(|| tasks.iter().position(is_deployment_task))
+    else {
+        return Ok(None);
+    };
+    let task = &tasks[index];
+    let task_type = task_type(&task.metadata);
+    let average = task_type.and_then(average_workflow_wait);
+
+    if task.state == FangTaskState::InProgress {
+        let running_for = Utc::now()
+            .signed_duration_since(task.updated_at)
+            .to_std()
+            .unwrap_or_default();
+        return Ok(Some(QueuePosition {
+            position: 0,
+            eta: average.map(|average| average.saturating_sub(running_for)),
+        }));
+    }
+    let ahead = tasks
+        .iter()
+        .enumerate()
+        .filter(|(other_index, other)| {
+            task_type(&other.metadata) == task_type
+                && (other.state == FangTaskState::InProgress || *other_index < index)
+        })
+        .count();
+    // tasks ahead are waited for in batches of `max_concurrent`, then the task itself
+    let batches = (ahead / max_concurrent.max(1) + 1) as u32;
+    Ok(Some(QueuePosition {
+        position: ahead as u64 + 1,
+        eta: average.map(|average| average * batches),
+    }))
+}
+
+/// Tasks which are limited by the dispatch limit
+const DISPATCHING_TASKS: [&str; 4] = [
+    "StartingTask",
+    "StoppingTask",
+    "RestartTask",
+    "GroupStoppingTask",
+];
+
+/// Number of tasks which wait to dispatch workflows, including postponed ones
+pub async fn pending_dispatching_tasks<C>(db: &C) -> Result<u64, DbErr>
+where
+    C: ConnectionTrait,
+{
+    let tasks = db::fang_tasks::Entity::find()
+        .filter(db::fang_tasks::Column::State.is_in([FangTaskState::New, FangTaskState::Retried]))
+        .all(db)
+        .await?;
+    let pending = tasks
+        .iter()
+        .filter(|task| {
+            task_type(&task.metadata)
+                .is_some_and(|task_type| DISPATCHING_TASKS.contains(&task_type))
+        })
+        .count();
+    Ok(pending as u64)
+}
+
+fn task_type(metadata: &serde_json::Value) -> Option<&str> {
+    metadata.get("type")?.as_str()
+}
+
+/// Ids of deployments of the task, group tasks have several of them
+pub(super) fn task_deployment_ids(metadata: &serde_json::Value) -> Vec<i32> {
+    if let Some(id) = metadata.get("deployment_id").and_then(|id| id.as_i64()) {
+        return vec![id as i32];
+    }
+    metadata
+        .get("deployment_ids")
+        .and_then(|ids| ids.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|id| id.as_i64())
+        .map(|id| id as i32)
+        .collect()
+}
+
+/// Average time of waiting for workflows of the task type since the start of the process,
+/// waits interrupted by retries or shutdown are not finished, so they are not counted
+fn average_workflow_wait(task_type: &str) -> Option<Duration> {
+    let task = match task_type {
+        "StartingTask" => "starting",
+        "StoppingTask" | "GroupStoppingTask" => "stopping",
+        _ => return None,
+    };
+    let (sum, count) = [
+        WorkflowOutcome::Succeeded,
+        WorkflowOutcome::Failed,
+        WorkflowOutcome::TimedOut,
+    ]
+    .into_iter()
+    .filter_map(|outcome| {
+        WORKFLOW_WAIT_TIME
+            .get_metric_with_label_values(&[task, outcome.as_str()])
+            .ok()
+    })
+    .fold((0.0, 0), |(sum, count), histogram| {
+        (
+            sum + histogram.get_sample_sum(),
+            count + histogram.get_sample_count(),
+        )
+    });
+    (count > 0).then(|| Duration::from_secs_f64(sum / count as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils::{self, db::insert_fang_task};
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn positions_follow_order_of_tasks() {
+        let db = tests_utils::init::test_db("test", "positions_follow_order_of_tasks").await;
+        let conn = db.client();
+        let minutes = chrono::Duration::minutes;
+        insert_fang_task(
+            conn.as_ref(),
+            "StartingTask",
+            1,
+            FangTaskState::InProgress,
+            minutes(-10),
+        )
+        .await;
+        for deployment_id in 2..=5 {
+            insert_fang_task(
+                conn.as_ref(),
+                "StartingTask",
+                deployment_id,
+                FangTaskState::New,
+                minutes(deployment_id as i64 - 10),
+            )
+            .await;
+        }
+        // tasks of other types don't hold back starting tasks
+        insert_fang_task(
+            conn.as_ref(),
+            "StoppingTask",
+            6,
+            FangTaskState::New,
+            minutes(-20),
+        )
+        .await;
+
+        let mut positions = vec![];
+        for deployment_id in 1..=5 {
+            let position = queue_position(conn.as_ref(), deployment_id, 2)
+                .await
+                .unwrap()
+                .expect("task of deployment should be found");
+            positions.push(position.position);
+        }
+        assert_eq!(positions, vec![0, 2, 3, 4, 5]);
+        let stopping = queue_position(conn.as_ref(), 6, 2).await.unwrap().unwrap();
+        assert_eq!(stopping.position, 1);
+        assert_eq!(queue_position(conn.as_ref(), 7, 2).await.unwrap(), None);
+    }
+}
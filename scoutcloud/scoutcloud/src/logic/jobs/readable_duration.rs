@@ -0,0 +1,93 @@
+//! Durations in payloads of tasks written as seconds, e.g. `"300s"` or `"0.25s"`,
+//! so they are readable in the `fang_tasks` table. Tasks enqueued before
+//! are still deserialized from the default `{"secs": .., "nanos": ..}` form
+
+use fang::serde::{de::Error, Deserialize, Deserializer, Serializer};
+use std::time::Duration;
+
+const NANOS_DIGITS: usize = 9;
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(duration))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(crate = "fang::serde", untagged)]
+    enum Repr {
+        Readable(String),
+        Legacy(Duration),
+    }
+
+    match Repr::deserialize(deserializer)? {
+        Repr::Readable(value) => parse(&value).ok_or_else(|| {
+            D::Error::custom(format!(
+                "invalid duration `{value}`, expected seconds like `300s` or `0.25s`"
+            ))
+        }),
+        Repr::Legacy(duration) => Ok(duration),
+    }
+}
+
+/// Fraction of a second is written without trailing zeros, so it is exact up to nanoseconds
+fn format(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    match duration.subsec_nanos() {
+        0 => format!("{secs}s"),
+        nanos => {
+            let fraction = format!("{nanos:0NANOS_DIGITS$}");
+            format!("{secs}.{}s", fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+fn parse(value: &str) -> Option<Duration> {
+    let value = value.strip_suffix('s')?;
+    let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let (secs, nanos) = match value.split_once('.') {
+        Some((secs, fraction)) if is_digits(fraction) && fraction.len() <= NANOS_DIGITS => {
+            (secs, format!("{fraction:0<NANOS_DIGITS$}").parse().ok()?)
+        }
+        Some(_) => return None,
+        None => (value, 0),
+    };
+    if !is_digits(secs) {
+        return None;
+    }
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn durations_are_written_as_seconds() {
+        for (duration, expected) in [
+            (Duration::from_secs(300), "300s"),
+            (Duration::ZERO, "0s"),
+            (Duration::from_millis(250), "0.25s"),
+            (Duration::new(5, 1), "5.000000001s"),
+            (
+                Duration::new(u64::MAX, 999_999_999),
+                "18446744073709551615.999999999s",
+            ),
+        ] {
+            assert_eq!(format(&duration), expected);
+            assert_eq!(parse(expected), Some(duration));
+        }
+        for invalid in [
+            "300",
+            "s",
+            ".5s",
+            "1.s",
+            "-1s",
+            "1.0000000001s",
+            "1e3s",
+            "5 s",
+        ] {
+            assert_eq!(parse(invalid), None, "`{invalid}` should be rejected");
+        }
+    }
+}
@@ -0,0 +1,335 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::{
+    dispatch_limit::run_with_dispatch_limit, failure_logs::capture_failure_logs, global, metrics,
+    shutdown, StartingTask, StoppingTask,
+};
+use crate::logic::{
+    deploy::{Initiator, StatusActor},
+    DeployError, Deployment,
+};
+use chrono::{DateTime, Utc};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::DatabaseConnection;
+
+// with default exponential backoff of fang it is around half an hour of retries
+const MAX_RETRIES: i32 = 10;
+const ACTOR: StatusActor = StatusActor::Task("restart");
+
+/// Stops the deployment and starts it again within one task, so nobody
+/// can interfere between the two phases.
+/// Statuses go `Running -> Stopping -> Stopped -> Pending -> Running`.
+/// Retry after a retryable error continues from the phase the previous attempt stopped at.
+#[derive(fang::serde::Serialize, fang::serde::Deserialize, Debug, Clone)]
+#[serde(crate = "fang::serde")]
+pub struct RestartTask {
+    deployment_id: i32,
+    /// Set when the task was postponed because of the dispatch limit
+    #[serde(default)]
+    scheduled_at: Option<DateTime<Utc>>,
+    /// Who requested the restart, recorded for both stop and start of the deployment
+    #[serde(default)]
+    initiator: Option<Initiator>,
+}
+
+impl RestartTask {
+    pub fn from_deployment_id(deployment_id: i32) -> Self {
+        Self {
+            deployment_id,
+            scheduled_at: None,
+            initiator: None,
+        }
+    }
+
+    pub fn with_initiator(mut self, initiator: Initiator) -> Self {
+        self.initiator = Some(initiator);
+        self
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for RestartTask {
+    #[tracing::instrument(
+        err(Debug),
+        skip(client),
+        fields(deployment_id = self.deployment_id),
+        level = "info"
+    )]
+    async fn run(&self, client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        // the whole restart takes one place, since its workflows are run one after another
+        let postponed = |scheduled_at| Self {
+            scheduled_at: Some(scheduled_at),
+            ..self.clone()
+        };
+        run_with_dispatch_limit(
+            client,
+            postponed,
+            metrics::observe_task_run("restart", self.run_task()),
+        )
+        .await
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.scheduled_at.map(Scheduled::ScheduleOnce)
+    }
+
+    fn max_retries(&self) -> i32 {
+        MAX_RETRIES
+    }
+}
+
+impl RestartTask {
+    async fn run_task(&self) -> Result<(), FangError> {
+        let _guard = shutdown::register_running_task().await?;
+        let db = global::DATABASE.get().await;
+        let ci = global::CI.get().await;
+
+        let mut deployment = Deployment::get(db.as_ref(), self.deployment_id)
+            .await
+            .map_err(DeployError::Db)?
+            .with_actor(ACTOR)
+            .initiated_by(self.initiator.clone());
+        let instance = deployment
+            .get_instance(db.as_ref())
+            .await
+            .map_err(DeployError::Db)?;
+
+        let is_running = deployment.model.status == DeploymentStatusType::Running;
+        if !is_running
+            && !self
+                .is_left_by_previous_attempt(db.as_ref(), &deployment)
+                .await?
+        {
+            tracing::warn!(
+                "cannot restart deployment '{}': invalid state '{:?}'",
+                self.deployment_id,
+                deployment.model.status,
+            );
+            return Ok(());
+        }
+
+        let has_run = deployment.model.run_id.is_some();
+        let stopping = StoppingTask::from_deployment(&deployment);
+        let result = match deployment.model.status {
+            DeploymentStatusType::Running => {
+                stopping
+                    .github_stop_and_wait(db.as_ref(), ci.as_ref(), &instance, &mut deployment)
+                    .await
+            }
+            DeploymentStatusType::Stopping => {
+                stopping
+                    .github_resume_and_wait(db.as_ref(), ci.as_ref(), &instance, &mut deployment)
+                    .await
+            }
+            _ => Ok(()),
+        }
+        .map_err(|err| (err, "failed to stop deployment"));
+        let result = match result {
+            Ok(()) => {
+                let starting = StartingTask::from_deployment(&deployment);
+                match deployment.model.status {
+                    DeploymentStatusType::Pending if has_run => {
+                        starting
+                            .github_resume_and_wait(
+                                db.as_ref(),
+                                ci.as_ref(),
+                                &instance,
+                                &mut deployment,
+                            )
+                            .await
+                    }
+                    _ => {
+                        starting
+                            .github_deploy_and_wait(
+                                db.as_ref(),
+                                ci.as_ref(),
+                                &instance,
+                                &mut deployment,
+                            )
+                            .await
+                    }
+                }
+                .map_err(|err| (err, "stopped deployment, but failed to start it"))
+            }
+            Err(err) => Err(err),
+        };
+        let result = match result {
+            // deployment is left in stopping or pending state with known run,
+            // so it will be resumed after restart
+            Err((err, _)) if err.is_interrupted() => {
+                tracing::info!("stopped restarting deployment because of shutdown");
+                return Ok(());
+            }
+            // deployment is left in the phase which the retry continues from
+            Err((err, phase)) if err.is_retryable() => {
+                tracing::warn!(
+                    "failed to restart deployment, task will be retried: {phase}: {err:?}"
+                );
+                return Err(err.into());
+            }
+            Err((err, phase)) => {
+                capture_failure_logs(db.as_ref(), ci.as_ref(), &deployment, &err).await;
+                Err((err, phase))
+            }
+            Ok(()) => Ok(()),
+        };
+
+        if let Err((err, phase)) = result {
+            tracing::error!("failed to restart deployment: {phase}: {err}");
+            deployment.mark_as_error(db.as_ref(), &err).await?;
+        };
+
+        Ok(())
+    }
+
+    /// Deployment which is not running can be restarted only by a retry of the task,
+    /// when the previous attempt changed its status last. Otherwise somebody else
+    /// stopped or started it after the restart was requested
+    async fn is_left_by_previous_attempt(
+        &self,
+        db: &DatabaseConnection,
+        deployment: &Deployment,
+    ) -> Result<bool, DeployError> {
+        let has_run = deployment.model.run_id.is_some();
+        let is_resumable = match deployment.model.status {
+            DeploymentStatusType::Stopping | DeploymentStatusType::Pending => has_run,
+            DeploymentStatusType::Stopped => true,
+            _ => false,
+        };
+        if !is_resumable {
+            return Ok(false);
+        }
+        let history = deployment
+            .status_history(db)
+            .await
+            .map_err(DeployError::Db)?;
+        Ok(history
+            .last()
+            .is_some_and(|change| change.actor == ACTOR.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use sea_orm::EntityTrait;
+    use std::time::Duration;
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn restart_task_works() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("restart_task_works").await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+
+        let running_deployment_id = 1;
+        let before = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        runner
+            .insert_task(&RestartTask::from_deployment_id(running_deployment_id))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Running,
+            "deployment is not running. error: {:?}",
+            deployment.model.error
+        );
+        // time while the deployment was stopped is not included
+        assert!(deployment.model.started_at > before.model.started_at);
+        assert_eq!(deployment.model.finished_at, None);
+        assert_eq!(deployment.run_id().map(|id| id.0), Some(8819501642));
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+        handles.assert_hits("single_run_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn restart_task_reports_start_failure() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("restart_task_reports_start_failure").await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_response(&mut handles, "single_run_deploy_yaml", |run| {
+            run["conclusion"] = "failure".into();
+        });
+
+        let running_deployment_id = 1;
+        runner
+            .insert_task(&RestartTask::from_deployment_id(running_deployment_id))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert_eq!(
+            deployment.model.error_code.as_deref(),
+            Some("workflow_failed")
+        );
+        let error = deployment.model.error.unwrap_or_default();
+        assert!(
+            error.starts_with("github workflow run 8819501642 failed. conclusion=Failure"),
+            "unexpected error: {error}"
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn restart_task_retries_on_server_error() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("restart_task_retries_on_server_error").await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_status(&mut handles, "single_run_deploy_yaml", 503);
+
+        let running_deployment_id = 1;
+        runner
+            .insert_task(&RestartTask::from_deployment_id(running_deployment_id))
+            .await
+            .unwrap();
+        let task = tests_utils::db::wait_until_some_with_timeout(
+            conn.clone(),
+            Duration::from_secs(20),
+            Duration::from_millis(500),
+            |conn| async move {
+                scoutcloud_entity::fang_tasks::Entity::find()
+                    .one(conn.as_ref())
+                    .await
+                    .unwrap()
+                    .filter(|task| task.retries > 0)
+            },
+        )
+        .await
+        .expect("task should be retried");
+
+        assert!(task.error_message.is_some());
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        // retry continues waiting for the dispatched deploy workflow
+        assert_eq!(deployment.model.status, DeploymentStatusType::Pending);
+        assert_eq!(deployment.run_id().map(|id| id.0), Some(8819501642));
+        assert_eq!(deployment.model.error, None);
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        handles.assert_hits("dispatch_deploy_yaml", 1);
+    }
+}
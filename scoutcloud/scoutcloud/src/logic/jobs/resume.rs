@@ -0,0 +1,310 @@
+use super::{queue::task_deployment_ids, StartingTask, StoppingTask};
+use crate::logic::{deploy::StatusActor, Deployment};
+use chrono::{DateTime, Utc};
+use db::sea_orm_active_enums::{DeploymentStatusType, FangTaskState};
+use fang::{AsyncQueueable, AsyncRunnable};
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, Condition, ConnectionTrait, TransactionTrait};
+use std::collections::HashSet;
+
+const ACTOR: StatusActor = StatusActor::Task("resume");
+
+/// Enqueues tasks for deployments that were left in the middle of a github workflow,
+/// for example because scoutcloud was restarted while waiting for it.
+/// Enqueued tasks continue waiting for the stored `run_id` instead of dispatching
+/// a new workflow. Deployments without stored `run_id` were interrupted before their
+/// workflow was saved, so they are reverted to the previous status and the workflow
+/// is dispatched again. It is safe to call it multiple times: deployment that already has
+/// its task in the queue is skipped.
+///
+/// `in_progress` tasks which were not touched after `stale_before` are considered to belong
+/// to the previous process, which will never finish them, so they are removed. It is opt-in,
+/// since tasks of other replicas and long tasks which don't touch their rows look the same.
+///
+/// Returns ids of deployments for which a task was enqueued.
+pub async fn resume_interrupted_deployments<C>(
+    db: &C,
+    queue: &dyn AsyncQueueable,
+    stale_before: Option<DateTime<Utc>>,
+) -> Result<Vec<i32>, anyhow::Error>
+where
+    C: ConnectionTrait + TransactionTrait,
+{
+    let queued = queued_tasks(db, stale_before).await?;
+    let deployments = Deployment::default_select()
+        .filter(
+            Condition::any()
+                .add(db::deployments::Column::Status.eq(DeploymentStatusType::Pending))
+                .add(db::deployments::Column::Status.eq(DeploymentStatusType::Stopping)),
+        )
+        .all(db)
+        .await?;
+
+    let mut resumed = vec![];
+    for model in deployments {
+        let mut deployment = Deployment::new(model).with_actor(ACTOR);
+        let deployment_id = deployment.model.id;
+        let Some(task) = resume_task(&deployment) else {
+            continue;
+        };
+        let keys = task_keys(&serde_json::to_value(task.as_ref())?);
+        if keys.iter().any(|key| queued.contains(key)) {
+            tracing::debug!(
+                deployment_id = deployment_id,
+                "deployment already has a task in the queue, skip"
+            );
+            continue;
+        }
+        if deployment.run_id().is_none() {
+            let Some(previous_status) = previous_status(&deployment.model.status) else {
+                continue;
+            };
+            tracing::warn!(
+                deployment_id = deployment_id,
+                status =? deployment.model.status,
+                "deployment has no stored run_id, its workflow will be dispatched again"
+            );
+            deployment.update_status(db, previous_status).await?;
+        }
+        queue.insert_task(task.as_ref()).await?;
+        tracing::info!(
+            deployment_id = deployment_id,
+            status =? deployment.model.status,
+            "resumed interrupted deployment"
+        );
+        resumed.push(deployment_id);
+    }
+    Ok(resumed)
+}
+
+fn resume_task(deployment: &Deployment) -> Option<Box<dyn AsyncRunnable>> {
+    match deployment.model.status {
+        DeploymentStatusType::Pending => Some(Box::new(StartingTask::from_deployment(deployment))),
+        DeploymentStatusType::Stopping => Some(Box::new(StoppingTask::from_deployment(deployment))),
+        _ => None,
+    }
+}
+
+/// Status which the task dispatches the workflow of the current status from
+fn previous_status(status: &DeploymentStatusType) -> Option<DeploymentStatusType> {
+    match status {
+        DeploymentStatusType::Pending => Some(DeploymentStatusType::Created),
+        DeploymentStatusType::Stopping => Some(DeploymentStatusType::Running),
+        _ => None,
+    }
+}
+
+/// Returns keys of tasks that are going to be executed (or are executing right now)
+/// and removes stale `in_progress` tasks of the previous process if `stale_before` is set.
+async fn queued_tasks<C>(
+    db: &C,
+    stale_before: Option<DateTime<Utc>>,
+) -> Result<HashSet<(String, i32)>, DbErr>
+where
+    C: ConnectionTrait,
+{
+    let tasks = db::fang_tasks::Entity::find()
+        .filter(
+            Condition::any()
+                .add(db::fang_tasks::Column::State.eq(FangTaskState::New))
+                .add(db::fang_tasks::Column::State.eq(FangTaskState::Retried))
+                .add(db::fang_tasks::Column::State.eq(FangTaskState::InProgress)),
+        )
+        .all(db)
+        .await?;
+
+    let (stale, active): (Vec<_>, Vec<_>) = tasks.into_iter().partition(|task| {
+        task.state == FangTaskState::InProgress
+            && stale_before.is_some_and(|stale_before| task.updated_at < stale_before)
+    });
+    if !stale.is_empty() {
+        tracing::warn!(
+            count = stale.len(),
+            "removing in_progress tasks of the previous process"
+        );
+        db::fang_tasks::Entity::delete_many()
+            .filter(db::fang_tasks::Column::Id.is_in(stale.into_iter().map(|task| task.id)))
+            .exec(db)
+            .await?;
+    }
+
+    Ok(active
+        .iter()
+        .flat_map(|task| task_keys(&task.metadata))
+        .collect())
+}
+
+/// Deployments of group stopping tasks are keyed like ones of their own stopping tasks
+fn task_keys(metadata: &serde_json::Value) -> Vec<(String, i32)> {
+    let Some(task_type) = metadata
+        .get("type")
+        .and_then(|task_type| task_type.as_str())
+    else {
+        return vec![];
+    };
+    let task_type = match task_type {
+        "GroupStoppingTask" => "StoppingTask",
+        task_type => task_type,
+    };
+    task_deployment_ids(metadata)
+        .into_iter()
+        .map(|deployment_id| (task_type.to_string(), deployment_id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{logic::Deployment, tests_utils};
+    use scoutcloud_entity::sea_orm_active_enums::{DeploymentStatusType, FangTaskState};
+    use sea_orm::{prelude::*, ActiveValue::Set};
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn resume_stopping_after_restart() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("resume_stopping_after_restart").await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+
+        // deployment was left in stopping state with already dispatched cleanup workflow
+        let running_deployment_id = 1;
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(running_deployment_id),
+            status: Set(DeploymentStatusType::Stopping),
+            run_id: Set(Some(8819501307)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+
+        let resumed = runner
+            .resume_interrupted_deployments(conn.as_ref(), false)
+            .await
+            .unwrap();
+        assert_eq!(resumed, vec![running_deployment_id]);
+        let resumed = runner
+            .resume_interrupted_deployments(conn.as_ref(), false)
+            .await
+            .unwrap();
+        assert!(resumed.is_empty(), "deployment was enqueued twice");
+
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 0);
+        handles.assert_hits("single_run_cleanup_yaml", 2);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_without_run_is_dispatched_again() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_without_run_is_dispatched_again")
+                .await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+
+        // scoutcloud was restarted before the run of the cleanup workflow was stored
+        let running_deployment_id = 1;
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(running_deployment_id),
+            status: Set(DeploymentStatusType::Stopping),
+            run_id: Set(None),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+
+        let resumed = runner
+            .resume_interrupted_deployments(conn.as_ref(), false)
+            .await
+            .unwrap();
+        assert_eq!(resumed, vec![running_deployment_id]);
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stale_tasks_are_removed_only_if_enabled() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stale_tasks_are_removed_only_if_enabled")
+                .await;
+        let conn = db.client();
+        let _handles = repo.build_handles();
+
+        let running_deployment_id = 1;
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(running_deployment_id),
+            status: Set(DeploymentStatusType::Stopping),
+            run_id: Set(Some(8819501307)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        // task could be run by another replica, which doesn't touch its row
+        tests_utils::db::insert_fang_task(
+            conn.as_ref(),
+            "StoppingTask",
+            running_deployment_id,
+            FangTaskState::InProgress,
+            chrono::Duration::zero(),
+        )
+        .await;
+        scoutcloud_entity::fang_tasks::Entity::update_many()
+            .col_expr(
+                scoutcloud_entity::fang_tasks::Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().fixed_offset() - chrono::Duration::hours(1)),
+            )
+            .exec(conn.as_ref())
+            .await
+            .unwrap();
+
+        let resumed = runner
+            .resume_interrupted_deployments(conn.as_ref(), false)
+            .await
+            .unwrap();
+        assert!(resumed.is_empty(), "deployment of the task was resumed");
+        let tasks = scoutcloud_entity::fang_tasks::Entity::find()
+            .count(conn.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(tasks, 1, "in_progress task was removed");
+
+        let resumed = runner
+            .resume_interrupted_deployments(conn.as_ref(), true)
+            .await
+            .unwrap();
+        assert_eq!(resumed, vec![running_deployment_id]);
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Stopped);
+    }
+}
@@ -0,0 +1,206 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::global;
+use crate::logic::DeployError;
+use chrono::{DateTime, Utc};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use scoutcloud_entity as db;
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::{prelude::*, sea_query::Expr, QueryOrder, QuerySelect};
+use tracing::instrument;
+
+const FINISHED_STATUSES: [DeploymentStatusType; 2] =
+    [DeploymentStatusType::Stopped, DeploymentStatusType::Failed];
+
+/// Deletes finished deployments older than the retention period.
+/// Logs, status history and webhook deliveries are deleted by cascade,
+/// balance expenses are kept without the reference to the deployment
+#[derive(fang::serde::Serialize, fang::serde::Deserialize)]
+#[serde(crate = "fang::serde")]
+pub struct RetentionTask {
+    schedule: Option<String>,
+}
+
+impl Default for RetentionTask {
+    fn default() -> Self {
+        Self {
+            schedule: Some("0 0 * * * *".to_string()),
+        }
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for RetentionTask {
+    #[instrument(err(Debug), skip(self, _client), level = "info")]
+    async fn run(&self, _client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let settings = global::RETENTION.get().await.clone();
+        if !settings.enabled {
+            return Ok(());
+        }
+        let period = chrono::Duration::from_std(settings.period)
+            .map_err(|err| DeployError::Internal(err.into()))?;
+        let db = global::DATABASE.get().await;
+        let purged =
+            purge_finished_deployments(db.as_ref(), Utc::now() - period, settings.batch_size)
+                .await
+                .map_err(DeployError::Db)?;
+        if purged > 0 {
+            tracing::info!(purged = purged, "purged old finished deployments");
+        }
+        Ok(())
+    }
+
+    fn uniq(&self) -> bool {
+        true
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.schedule
+            .as_ref()
+            .map(|s| Scheduled::CronPattern(s.clone()))
+    }
+}
+
+/// Deletes stopped and failed deployments which were not updated since `older_than`,
+/// except the latest deployment of every instance. Deployments are deleted in batches
+/// of `batch_size`, so every statement holds locks for a short time only
+async fn purge_finished_deployments<C>(
+    db: &C,
+    older_than: DateTime<Utc>,
+    batch_size: u64,
+) -> Result<u64, DbErr>
+where
+    C: ConnectionTrait,
+{
+    let mut purged = 0;
+    loop {
+        let ids: Vec<i32> = db::deployments::Entity::find()
+            .select_only()
+            .column(db::deployments::Column::Id)
+            .filter(db::deployments::Column::Status.is_in(FINISHED_STATUSES))
+            .filter(db::deployments::Column::UpdatedAt.lt(older_than))
+            .filter(Expr::cust(
+                r#""deployments"."id" <> (
+                    SELECT "latest"."id" FROM "deployments" AS "latest"
+                    WHERE "latest"."instance_id" = "deployments"."instance_id"
+                    ORDER BY "latest"."created_at" DESC, "latest"."id" DESC
+                    LIMIT 1
+                )"#,
+            ))
+            .order_by_asc(db::deployments::Column::Id)
+            .limit(batch_size)
+            .into_tuple()
+            .all(db)
+            .await?;
+        if ids.is_empty() {
+            break;
+        }
+        let selected = ids.len() as u64;
+        // status is checked again in case the deployment was started in the meantime
+        let result = db::deployments::Entity::delete_many()
+            .filter(db::deployments::Column::Id.is_in(ids))
+            .filter(db::deployments::Column::Status.is_in(FINISHED_STATUSES))
+            .exec(db)
+            .await?;
+        purged += result.rows_affected;
+        if selected < batch_size {
+            break;
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set, DatabaseConnection};
+
+    const RUNNING_DEPLOYMENT_ID: i32 = 1;
+    const OLD_DEPLOYMENT_ID: i32 = 2;
+    const LATEST_DEPLOYMENT_ID: i32 = 3;
+
+    async fn set_updated_at(db: &DatabaseConnection, deployment_id: i32, days_ago: i64) {
+        // updated_at is changed by trigger only on status change
+        db::deployments::ActiveModel {
+            id: Set(deployment_id),
+            updated_at: Set((Utc::now() - chrono::Duration::days(days_ago)).fixed_offset()),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .unwrap();
+    }
+
+    async fn existing(db: &DatabaseConnection) -> Vec<i32> {
+        db::deployments::Entity::find()
+            .order_by_asc(db::deployments::Column::Id)
+            .all(db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|deployment| deployment.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn old_finished_deployments_are_purged() {
+        let db = tests_utils::init::test_db("test", "old_finished_deployments_are_purged").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        db::deployment_status_history::ActiveModel {
+            deployment_id: Set(OLD_DEPLOYMENT_ID),
+            new_status: Set(DeploymentStatusType::Stopped),
+            actor: Set("system".to_string()),
+            ..Default::default()
+        }
+        .insert(conn.as_ref())
+        .await
+        .unwrap();
+        let expense = db::balance_expenses::ActiveModel {
+            deployment_id: Set(Some(OLD_DEPLOYMENT_ID)),
+            user_id: Set(2),
+            hours: Set(4),
+            expense_amount: Set(Default::default()),
+            ..Default::default()
+        }
+        .insert(conn.as_ref())
+        .await
+        .unwrap();
+        set_updated_at(&conn, OLD_DEPLOYMENT_ID, 100).await;
+        set_updated_at(&conn, RUNNING_DEPLOYMENT_ID, 100).await;
+        let older_than = Utc::now() - chrono::Duration::days(90);
+
+        let purged = purge_finished_deployments(conn.as_ref(), older_than, 1)
+            .await
+            .unwrap();
+        assert_eq!(purged, 1);
+        // recent failed deployment, running and created ones are kept
+        assert_eq!(existing(&conn).await, vec![1, 3, 4]);
+        let history = db::deployment_status_history::Entity::find()
+            .filter(db::deployment_status_history::Column::DeploymentId.eq(OLD_DEPLOYMENT_ID))
+            .all(conn.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 0);
+        let expense = db::balance_expenses::Entity::find_by_id(expense.id)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .expect("expense should be kept");
+        assert_eq!(expense.deployment_id, None);
+
+        // the latest deployment of the instance is kept even if it is old
+        set_updated_at(&conn, LATEST_DEPLOYMENT_ID, 100).await;
+        let purged = purge_finished_deployments(conn.as_ref(), older_than, 1)
+            .await
+            .unwrap();
+        assert_eq!(purged, 0);
+        assert_eq!(existing(&conn).await, vec![1, 3, 4]);
+    }
+}
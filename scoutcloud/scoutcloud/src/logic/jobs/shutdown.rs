@@ -0,0 +1,86 @@
+use super::global;
+use fang::FangError;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Coordinates graceful shutdown of the jobs runner.
+/// fang doesn't allow to stop its workers, so tasks register themselves while running,
+/// refuse to start after shutdown has begun and check the token at safe points.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    running_tasks: AtomicUsize,
+}
+
+impl Shutdown {
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn running_tasks(&self) -> usize {
+        self.running_tasks.load(Ordering::SeqCst)
+    }
+
+    /// Returns `None` if shutdown has already begun, so the task should not start
+    pub fn register_task(self: &Arc<Self>) -> Option<RunningTaskGuard> {
+        self.running_tasks.fetch_add(1, Ordering::SeqCst);
+        let guard = RunningTaskGuard(self.clone());
+        if self.is_started() {
+            return None;
+        }
+        Some(guard)
+    }
+
+    /// Cancels the token and waits for running tasks to finish.
+    /// Returns false if some tasks are still running after `grace_period`
+    pub async fn shutdown(&self, grace_period: Duration) -> bool {
+        self.token.cancel();
+        let started = Instant::now();
+        loop {
+            let running = self.running_tasks();
+            if running == 0 {
+                return true;
+            }
+            if started.elapsed() >= grace_period {
+                tracing::warn!(
+                    running_tasks = running,
+                    "jobs are still running after grace period"
+                );
+                return false;
+            }
+            tokio::time::sleep(DRAIN_CHECK_INTERVAL).await;
+        }
+    }
+}
+
+/// Registers currently running task in the global shutdown coordinator
+pub(crate) async fn register_running_task() -> Result<RunningTaskGuard, FangError> {
+    global::SHUTDOWN
+        .get()
+        .await
+        .register_task()
+        .ok_or(FangError {
+            description: "jobs runner is shutting down".to_string(),
+        })
+}
+
+#[derive(Debug)]
+pub struct RunningTaskGuard(Arc<Shutdown>);
+
+impl Drop for RunningTaskGuard {
+    fn drop(&mut self) {
+        self.0.running_tasks.fetch_sub(1, Ordering::SeqCst);
+    }
+}
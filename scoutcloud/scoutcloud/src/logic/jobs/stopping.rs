@@ -1,6 +1,17 @@
 #![allow(clippy::blocks_in_conditions)]
 
-use crate::logic::{jobs::global, DeployError, Deployment, GithubClient, Instance};
+use crate::logic::{
+    ci::{CiBackend, CiRun, CiTarget, CiWorkflow},
+    deploy::{follow_logs, Initiator, StatusActor, DEFAULT_LOGS_POLL_INTERVAL},
+    github::PollBackoff,
+    jobs::{
+        deployment_locks, dispatch_limit::run_with_dispatch_limit,
+        failure_logs::capture_failure_logs, global, metrics, polls, readable_duration, shutdown,
+        task_runs::record_task_run, timeout_warning::warn_before_timeout,
+    },
+    DeployError, Deployment, Instance,
+};
+use chrono::{DateTime, Utc};
 use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
 use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
 use sea_orm::DatabaseConnection;
@@ -8,54 +19,171 @@ use std::time::Duration;
 
 const DEFAULT_WORKFLOW_TIMEOUT: Duration = Duration::from_secs(10 * 60);
 const DEFAULT_WORKFLOW_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// with default exponential backoff of fang it is around half an hour of retries
+const MAX_RETRIES: i32 = 10;
+const DEFAULT_WORKFLOW_BACKOFF_MULTIPLIER: f64 = 1.5;
+const DEFAULT_WORKFLOW_MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const ACTOR: StatusActor = StatusActor::Task("stopping");
 
-#[derive(fang::serde::Serialize, fang::serde::Deserialize, Debug)]
+#[derive(fang::serde::Serialize, fang::serde::Deserialize, Debug, Clone)]
 #[serde(crate = "fang::serde")]
 pub struct StoppingTask {
     deployment_id: i32,
+    #[serde(with = "readable_duration")]
     workflow_timeout: Duration,
+    #[serde(with = "readable_duration")]
     workflow_check_interval: Duration,
+    // defaults are needed to deserialize tasks that were enqueued before these fields existed
+    #[serde(default = "default_workflow_backoff_multiplier")]
+    workflow_backoff_multiplier: f64,
+    #[serde(
+        default = "default_workflow_max_check_interval",
+        with = "readable_duration"
+    )]
+    workflow_max_check_interval: Duration,
+    /// Set when the task was postponed because of the dispatch limit
+    #[serde(default)]
+    scheduled_at: Option<DateTime<Utc>>,
+    /// Who requested the stop, not known for tasks resumed after restart
+    #[serde(default)]
+    initiator: Option<Initiator>,
     #[cfg(test)]
     database_url: Option<String>,
 }
 
+fn default_workflow_backoff_multiplier() -> f64 {
+    DEFAULT_WORKFLOW_BACKOFF_MULTIPLIER
+}
+
+fn default_workflow_max_check_interval() -> Duration {
+    DEFAULT_WORKFLOW_MAX_CHECK_INTERVAL
+}
+
+/// Configured timeout of cleanup workflows, the built-in one if it is not configured
+pub(super) fn default_workflow_timeout() -> Duration {
+    global::workflows()
+        .stop_timeout
+        .unwrap_or(DEFAULT_WORKFLOW_TIMEOUT)
+}
+
 impl StoppingTask {
     pub fn from_deployment_id(deployment_id: i32) -> Self {
+        let workflows = global::workflows();
         Self {
             deployment_id,
-            workflow_timeout: DEFAULT_WORKFLOW_TIMEOUT,
-            workflow_check_interval: DEFAULT_WORKFLOW_CHECK_INTERVAL,
+            workflow_timeout: default_workflow_timeout(),
+            workflow_check_interval: workflows
+                .stop_check_interval
+                .or(workflows.check_interval)
+                .unwrap_or(DEFAULT_WORKFLOW_CHECK_INTERVAL),
+            workflow_backoff_multiplier: DEFAULT_WORKFLOW_BACKOFF_MULTIPLIER,
+            workflow_max_check_interval: DEFAULT_WORKFLOW_MAX_CHECK_INTERVAL,
+            scheduled_at: None,
+            initiator: None,
             #[cfg(test)]
             database_url: None,
         }
     }
+
+    /// Uses workflow timeout stored in the deployment, so every run of the task
+    /// for this deployment waits for the workflow the same time
+    pub fn from_deployment(deployment: &Deployment) -> Self {
+        let mut task = Self::from_deployment_id(deployment.model.id);
+        if let Some(timeout) = deployment.workflow_timeout() {
+            task.workflow_timeout = timeout;
+        }
+        task
+    }
+
+    pub fn with_initiator(mut self, initiator: Initiator) -> Self {
+        self.initiator = Some(initiator);
+        self
+    }
 }
 
 #[typetag::serde]
 #[fang::async_trait]
 impl AsyncRunnable for StoppingTask {
-    #[tracing::instrument(err(Debug), skip(_client), level = "info")]
-    async fn run(&self, _client: &dyn AsyncQueueable) -> Result<(), FangError> {
+    #[tracing::instrument(
+        err(Debug),
+        skip(client),
+        fields(deployment_id = self.deployment_id),
+        level = "info"
+    )]
+    async fn run(&self, client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let postponed = |scheduled_at| Self {
+            scheduled_at: Some(scheduled_at),
+            ..self.clone()
+        };
+        run_with_dispatch_limit(
+            client,
+            postponed,
+            metrics::observe_task_run(
+                "stopping",
+                record_task_run("stopping", self.deployment_id, self.run_task()),
+            ),
+        )
+        .await
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.scheduled_at.map(Scheduled::ScheduleOnce)
+    }
+
+    fn max_retries(&self) -> i32 {
+        MAX_RETRIES
+    }
+}
+
+impl StoppingTask {
+    async fn run_task(&self) -> Result<(), FangError> {
+        let _guard = shutdown::register_running_task().await?;
         let db = global::DATABASE.get().await;
-        let github = global::GITHUB.get().await;
+        let ci = global::CI.get().await;
 
-        let mut deployment = Deployment::get(db.as_ref(), self.deployment_id)
+        let deployment = Deployment::get(db.as_ref(), self.deployment_id)
             .await
-            .map_err(DeployError::Db)?;
-        let instance = deployment
-            .get_instance(db.as_ref())
-            .await
-            .map_err(DeployError::Db)?;
+            .map_err(DeployError::Db)?
+            .with_actor(ACTOR)
+            .initiated_by(self.initiator.clone());
+        self.stop(db.as_ref(), ci.as_ref(), deployment).await?;
+        Ok(())
+    }
+
+    /// Stops the deployment and marks it as failed if stopping failed for good.
+    /// Only retryable errors are returned, so the caller can retry the stop later
+    pub(super) async fn stop(
+        &self,
+        db: &DatabaseConnection,
+        ci: &dyn CiBackend,
+        mut deployment: Deployment,
+    ) -> Result<(), DeployError> {
+        let instance = deployment.get_instance(db).await.map_err(DeployError::Db)?;
+        // waits for the starting task which is dispatching the deploy workflow right now
+        let lock = deployment_locks::lock(deployment.model.id).await;
+        deployment.reload(db).await?;
 
-        // todo: save run_id to database and if deployment in stopping state, watch for it
         let result = match deployment.model.status {
             DeploymentStatusType::Running => {
-                self.github_stop_and_wait(db.as_ref(), github.as_ref(), &instance, &mut deployment)
+                // starting task doesn't touch running deployments
+                drop(lock);
+                self.github_stop_and_wait(db, ci, &instance, &mut deployment)
+                    .await
+            }
+            // cleanup workflow was already dispatched by previous run of this task,
+            // so we just continue waiting for it instead of dispatching a new one
+            DeploymentStatusType::Stopping if deployment.model.run_id.is_some() => {
+                drop(lock);
+                self.github_resume_and_wait(db, ci, &instance, &mut deployment)
                     .await
             }
-            DeploymentStatusType::Created
+            // deploy workflow is still running, so it is cancelled instead of cleanup
+            DeploymentStatusType::Pending => self.cancel_start(db, ci, &mut deployment).await,
+            // starting task is about to dispatch the deploy workflow,
+            // so the stop is retried when it can be cancelled
+            DeploymentStatusType::Created => Err(DeployError::Conflict(deployment.model.id)),
+            DeploymentStatusType::Scheduled
             | DeploymentStatusType::Failed
-            | DeploymentStatusType::Pending
             | DeploymentStatusType::Stopped
             | DeploymentStatusType::Stopping => {
                 tracing::warn!(
@@ -68,45 +196,272 @@ impl AsyncRunnable for StoppingTask {
         };
 
         if let Err(err) = result {
+            if err.is_interrupted() {
+                tracing::info!(
+                    "stopped waiting for workflow because of shutdown, deployment will be resumed after restart"
+                );
+                return Ok(());
+            }
+            if err.is_retryable() {
+                tracing::warn!("failed to stop deployment, task will be retried: {:?}", err);
+                return Err(err);
+            }
             tracing::error!("failed to stop deployment: {:?}", err);
-            deployment
-                .mark_as_error(db.as_ref(), format!("failed to stop deployment: {}", err))
-                .await
-                .map_err(DeployError::Db)?;
+            capture_failure_logs(db, ci, &deployment, &err).await;
+            deployment.mark_as_terminal_error(db, &err).await?;
         };
 
         Ok(())
     }
 
-    fn cron(&self) -> Option<Scheduled> {
-        None
-    }
-}
-
-impl StoppingTask {
-    async fn github_stop_and_wait(
+    #[tracing::instrument(
+        skip_all,
+        fields(deployment_id = deployment.model.id, run_id = tracing::field::Empty),
+        level = "info"
+    )]
+    pub(super) async fn github_stop_and_wait(
         &self,
         db: &DatabaseConnection,
-        github: &GithubClient,
+        ci: &dyn CiBackend,
         instance: &Instance,
         deployment: &mut Deployment,
     ) -> Result<(), DeployError> {
+        let target = deployment.ci_target(instance);
         deployment
             .update_status(db, DeploymentStatusType::Stopping)
             .await?;
-        let run = instance.cleanup_via_github(github).await?;
-        github
-            .wait_for_success_workflow(&run, self.workflow_timeout, self.workflow_check_interval)
-            .await?;
-        deployment.mark_as_finished(db).await?;
+        let run = match instance.cleanup_via_ci(ci, &target).await {
+            Ok(run) => run,
+            Err(err) => {
+                // workflow was not dispatched, so retry of the task should dispatch it again
+                deployment
+                    .update_status(db, DeploymentStatusType::Running)
+                    .await?;
+                return Err(err);
+            }
+        };
+        tracing::Span::current().record("run_id", run.id.0);
+        deployment.set_run_id(db, run.id).await?;
+        self.wait_and_mark_as_finished(db, ci, &target, &run, deployment)
+            .await
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(deployment_id = deployment.model.id, run_id = tracing::field::Empty),
+        level = "info"
+    )]
+    pub(super) async fn github_resume_and_wait(
+        &self,
+        db: &DatabaseConnection,
+        ci: &dyn CiBackend,
+        instance: &Instance,
+        deployment: &mut Deployment,
+    ) -> Result<(), DeployError> {
+        let run_id = deployment
+            .run_id()
+            .ok_or(anyhow::anyhow!("deployment has no stored run_id"))?;
+        tracing::Span::current().record("run_id", run_id.0);
+        tracing::info!(
+            run_id =? run_id,
+            "deployment is already stopping, resuming waiting for cleanup workflow"
+        );
+        let target = deployment.ci_target(instance);
+        let run = ci.get_run(CiWorkflow::Cleanup, &target, run_id).await?;
+        self.wait_and_mark_as_finished(db, ci, &target, &run, deployment)
+            .await
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(deployment_id = deployment.model.id, run_id = tracing::field::Empty),
+        level = "info"
+    )]
+    async fn cancel_start(
+        &self,
+        db: &DatabaseConnection,
+        ci: &dyn CiBackend,
+        deployment: &mut Deployment,
+    ) -> Result<(), DeployError> {
+        // starting task stores run id under the deployment lock, so it is missing
+        // only if the task was interrupted before the dispatch finished
+        let Some(run_id) = deployment.run_id() else {
+            tracing::info!("deploy workflow was not dispatched, deployment is cancelled");
+            deployment
+                .mark_as_error(db, &DeployError::Cancelled)
+                .await?;
+            return Ok(());
+        };
+        tracing::Span::current().record("run_id", run_id.0);
+        tracing::info!(run_id =? run_id, "cancelling deploy workflow of pending deployment");
+        ci.cancel(run_id).await?;
+        // starting task could already mark the deployment
+        deployment.reload(db).await?;
+        if deployment.model.status == DeploymentStatusType::Pending {
+            deployment.mark_as_finished(db).await?;
+            // starting task leaves the stopped deployment as it is
+            polls::stop_polls(deployment.model.id);
+        }
+        Ok(())
+    }
+
+    async fn wait_and_mark_as_finished(
+        &self,
+        db: &DatabaseConnection,
+        ci: &dyn CiBackend,
+        target: &CiTarget,
+        run: &CiRun,
+        deployment: &mut Deployment,
+    ) -> Result<(), DeployError> {
+        let shutdown = global::SHUTDOWN.get().await.clone();
+        let poll = polls::register_poll(deployment.model.id, shutdown.token());
+        let waited = metrics::observe_workflow_wait(
+            "stopping",
+            warn_before_timeout(
+                "stopping",
+                deployment,
+                self.workflow_timeout,
+                follow_logs(
+                    ci,
+                    target,
+                    deployment.model.id,
+                    run.id,
+                    DEFAULT_LOGS_POLL_INTERVAL,
+                    ci.wait_for_success(
+                        run,
+                        self.workflow_timeout,
+                        self.workflow_backoff(),
+                        poll.token(),
+                    ),
+                ),
+            ),
+        )
+        .await;
+        if poll.is_finalized() {
+            tracing::info!("deployment was finalized by webhook, stopped waiting for workflow");
+            return Ok(());
+        }
+        waited?;
+        // webhook could already mark the deployment
+        deployment.reload(db).await?;
+        if deployment.model.status == DeploymentStatusType::Stopping {
+            deployment.mark_as_finished(db).await?;
+        }
         Ok(())
     }
+
+    fn workflow_backoff(&self) -> PollBackoff {
+        PollBackoff::new(
+            self.workflow_check_interval,
+            self.workflow_backoff_multiplier,
+            self.workflow_max_check_interval,
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests_utils;
+    use crate::{
+        logic::{
+            deploy::handle_workflow_run_event,
+            github::webhook::WorkflowRunEvent,
+            jobs::{DispatchLimit, StartingTask},
+        },
+        tests_utils::{
+            self,
+            ci::{FakeCi, FakeRun},
+        },
+    };
+    use octocrab::models::RunId;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set, EntityTrait};
+    use std::sync::Arc;
+
+    #[test]
+    #[serial_test::serial]
+    fn configured_workflow_defaults_are_used() {
+        let settings: crate::server::WorkflowSettings = serde_json::from_value(serde_json::json!({
+            "stop_timeout": 180,
+            "check_interval": 2,
+        }))
+        .unwrap();
+        global::init_workflows(settings);
+        let configured = StoppingTask::from_deployment_id(1);
+        global::init_workflows(Default::default());
+        let built_in = StoppingTask::from_deployment_id(1);
+
+        assert_eq!(configured.workflow_timeout, Duration::from_secs(180));
+        assert_eq!(configured.workflow_check_interval, Duration::from_secs(2));
+        assert_eq!(built_in.workflow_timeout, DEFAULT_WORKFLOW_TIMEOUT);
+        assert_eq!(
+            built_in.workflow_check_interval,
+            DEFAULT_WORKFLOW_CHECK_INTERVAL
+        );
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn stop_defaults_are_independent_of_start_ones() {
+        let settings: crate::server::WorkflowSettings = serde_json::from_value(serde_json::json!({
+            "start_timeout": 3600,
+            "stop_timeout": 120,
+            "check_interval": 5,
+            "start_check_interval": 30,
+            "stop_check_interval": 1,
+        }))
+        .unwrap();
+        global::init_workflows(settings);
+        let stopping = StoppingTask::from_deployment_id(1);
+        global::init_workflows(Default::default());
+
+        assert_eq!(stopping.workflow_timeout, Duration::from_secs(120));
+        assert_eq!(stopping.workflow_check_interval, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn durations_of_task_are_readable() {
+        let task = StoppingTask {
+            deployment_id: 1,
+            workflow_timeout: Duration::from_secs(600),
+            workflow_check_interval: Duration::from_millis(2500),
+            workflow_backoff_multiplier: 1.5,
+            workflow_max_check_interval: Duration::new(60, 1),
+            scheduled_at: None,
+            initiator: None,
+            database_url: None,
+        };
+        let value = serde_json::to_value(&task as &dyn AsyncRunnable).unwrap();
+        assert_eq!(value["type"], "StoppingTask");
+        assert_eq!(value["workflow_timeout"], "600s");
+        assert_eq!(value["workflow_check_interval"], "2.5s");
+        assert_eq!(value["workflow_max_check_interval"], "60.000000001s");
+
+        let restored: StoppingTask = serde_json::from_value(value).unwrap();
+        assert_eq!(restored.workflow_timeout, task.workflow_timeout);
+        assert_eq!(
+            restored.workflow_check_interval,
+            task.workflow_check_interval
+        );
+        assert_eq!(
+            restored.workflow_max_check_interval,
+            task.workflow_max_check_interval
+        );
+
+        // tasks enqueued before are stored with the default representation
+        let legacy: StoppingTask = serde_json::from_value(serde_json::json!({
+            "deployment_id": 1,
+            "workflow_timeout": {"secs": 600, "nanos": 0},
+            "workflow_check_interval": {"secs": 2, "nanos": 500_000_000},
+            "database_url": null,
+        }))
+        .unwrap();
+        assert_eq!(legacy.workflow_timeout, task.workflow_timeout);
+        assert_eq!(legacy.workflow_check_interval, task.workflow_check_interval);
+        assert_eq!(
+            legacy.workflow_max_check_interval,
+            DEFAULT_WORKFLOW_MAX_CHECK_INTERVAL
+        );
+    }
 
     #[tokio::test]
     #[serial_test::serial]
@@ -121,6 +476,10 @@ mod tests {
             deployment_id: running_deployment_id,
             workflow_timeout: Duration::from_secs(10),
             workflow_check_interval: Duration::from_secs(5),
+            workflow_backoff_multiplier: 2.0,
+            workflow_max_check_interval: Duration::from_secs(60),
+            scheduled_at: None,
+            initiator: None,
             database_url: Some(db.db_url().to_string()),
         };
         runner.insert_task(&task).await.unwrap();
@@ -141,4 +500,734 @@ mod tests {
         handles.assert_hits("runs_cleanup_yaml", 1);
         handles.assert_hits("single_run_cleanup_yaml", 1);
     }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_run_is_recorded() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_run_is_recorded").await;
+        let conn = db.client();
+
+        let mut task = StoppingTask::from_deployment_id(1);
+        task.workflow_timeout = Duration::from_secs(10);
+        task.database_url = Some(db.db_url().to_string());
+        let before = chrono::Utc::now();
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let runs = scoutcloud_entity::task_runs::Entity::find()
+            .all(conn.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(runs.len(), 1, "unexpected task runs: {runs:?}");
+        let run = &runs[0];
+        assert_eq!(run.task_type, "stopping");
+        assert_eq!(run.deployment_id, 1);
+        assert_eq!(run.run_id, Some(8819501307));
+        assert_eq!(run.outcome, "ok");
+        assert_eq!(run.error, None);
+        assert!(run.poll_count >= 1, "workflow was not polled: {run:?}");
+        assert!(run.started_at >= before, "unexpected start: {run:?}");
+        assert!(run.finished_at >= run.started_at, "unexpected end: {run:?}");
+        assert!(
+            run.finished_at <= chrono::Utc::now(),
+            "unexpected end: {run:?}"
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn paused_runner_keeps_stopping_task_until_resume() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("paused_runner_keeps_stopping_task").await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+        global::DISPATCH_LIMIT
+            .init(Arc::new(DispatchLimit::new(10, Duration::from_millis(200))))
+            .await
+            .unwrap();
+
+        runner.set_paused(true).await;
+        let mut task = StoppingTask::from_deployment_id(1);
+        task.workflow_timeout = Duration::from_secs(10);
+        task.database_url = Some(db.db_url().to_string());
+        runner.insert_task(&task).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Running);
+        handles.assert_hits("dispatch_cleanup_yaml", 0);
+
+        runner.set_paused(false).await;
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_records_metrics() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_records_metrics").await;
+        let conn = db.client();
+        let succeeded = || {
+            metrics::DEPLOYMENT_OUTCOMES
+                .with_label_values(&["stopping", "succeeded"])
+                .get()
+        };
+        let successful_runs = || {
+            metrics::TASK_RUNS
+                .with_label_values(&["stopping", "ok"])
+                .get()
+        };
+        let waits = || {
+            metrics::WORKFLOW_WAIT_TIME
+                .with_label_values(&["stopping", "succeeded"])
+                .get_sample_count()
+        };
+        let before = (succeeded(), successful_runs(), waits());
+
+        let mut task = StoppingTask::from_deployment_id(1);
+        task.workflow_timeout = Duration::from_secs(10);
+        task.database_url = Some(db.db_url().to_string());
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Stopped);
+
+        assert_eq!(
+            (succeeded(), successful_runs(), waits()),
+            (before.0 + 1, before.1 + 1, before.2 + 1)
+        );
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_spans_have_deployment_id() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_spans_have_deployment_id")
+                .await;
+        let capture = tests_utils::span_capture::SpanCapture::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(capture.clone()));
+
+        // run the task in this thread, so spans are captured by the subscriber above
+        let mut task = StoppingTask::from_deployment_id(1);
+        task.workflow_timeout = Duration::from_secs(10);
+        task.database_url = Some(db.db_url().to_string());
+        let queue = runner.queue().lock().await;
+        task.run(&*queue).await.unwrap();
+
+        for name in [
+            "run",
+            "github_stop_and_wait",
+            "cleanup_via_ci",
+            "wait_for_success_workflow",
+        ] {
+            let span = capture
+                .find(name)
+                .unwrap_or_else(|| panic!("span `{name}` was not captured"));
+            assert_eq!(
+                span.fields.get("deployment_id").map(String::as_str),
+                Some("1"),
+                "unexpected fields of span `{name}`: {:?}",
+                span.fields
+            );
+        }
+        for name in ["github_stop_and_wait", "wait_for_success_workflow"] {
+            let span = capture.find(name).unwrap();
+            assert_eq!(
+                span.fields.get("run_id").map(String::as_str),
+                Some("8819501307"),
+                "unexpected fields of span `{name}`: {:?}",
+                span.fields
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_spans_are_exported() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_spans_are_exported").await;
+        let exporter = tests_utils::span_export::InMemorySpanExporter::default();
+        let (subscriber, provider) =
+            tests_utils::span_export::exporting_subscriber(exporter.clone());
+        let guard = tracing::subscriber::set_default(subscriber);
+
+        // run the task in this thread, so spans are exported by the subscriber above
+        let mut task = StoppingTask::from_deployment_id(1);
+        task.workflow_timeout = Duration::from_secs(10);
+        task.database_url = Some(db.db_url().to_string());
+        let queue = runner.queue().lock().await;
+        task.run(&*queue).await.unwrap();
+        drop(guard);
+        drop(provider);
+
+        let spans = exporter.spans();
+        let find = |name: &str| {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("span `{name}` was not exported"))
+        };
+        let parent_name = |name: &str| {
+            let parent_id = find(name).parent_span_id;
+            spans
+                .iter()
+                .find(|span| span.span_id == parent_id)
+                .map(|span| span.name.as_str())
+        };
+        let run = find("run");
+        let stop = find("github_stop_and_wait");
+        let wait = find("wait_for_success_workflow");
+        let poll = find("poll_workflow_run");
+        assert_eq!(parent_name("github_stop_and_wait"), Some("run"));
+        assert_eq!(parent_name("cleanup_via_ci"), Some("github_stop_and_wait"));
+        assert_eq!(parent_name("run_workflow"), Some("cleanup_via_ci"));
+        assert_eq!(
+            parent_name("wait_for_success_workflow"),
+            Some("github_stop_and_wait")
+        );
+        assert_eq!(
+            parent_name("poll_workflow_run"),
+            Some("wait_for_success_workflow")
+        );
+        assert_eq!(parent_name("get_workflow_run"), Some("poll_workflow_run"));
+
+        for span in [run, stop] {
+            assert_eq!(
+                span.attributes.get("deployment_id").map(String::as_str),
+                Some("1"),
+                "unexpected attributes of span `{}`: {:?}",
+                span.name,
+                span.attributes
+            );
+        }
+        for span in [stop, wait, poll, find("get_workflow_run")] {
+            assert_eq!(
+                span.attributes.get("run_id").map(String::as_str),
+                Some("8819501307"),
+                "unexpected attributes of span `{}`: {:?}",
+                span.name,
+                span.attributes
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_resumes_existing_run() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_resumes_existing_run").await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+
+        let running_deployment_id = 1;
+        let task = StoppingTask {
+            deployment_id: running_deployment_id,
+            workflow_timeout: Duration::from_secs(10),
+            workflow_check_interval: Duration::from_secs(5),
+            workflow_backoff_multiplier: 2.0,
+            workflow_max_check_interval: Duration::from_secs(60),
+            scheduled_at: None,
+            initiator: None,
+            database_url: Some(db.db_url().to_string()),
+        };
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        let run_id = deployment.run_id().expect("run_id should be saved");
+        assert_eq!(run_id.0, 8819501307);
+
+        // simulate scoutcloud restart in the middle of stopping:
+        // deployment is still `stopping` and the task is executed again
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(running_deployment_id),
+            status: Set(DeploymentStatusType::Stopping),
+            finished_at: Set(None),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        assert_eq!(deployment.run_id(), Some(run_id));
+        // no new workflow was dispatched, only the stored run was fetched
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+        handles.assert_hits("runs_cleanup_yaml", 1);
+        handles.assert_hits("single_run_cleanup_yaml", 3);
+    }
+
+    async fn set_stopping_with_run(conn: &DatabaseConnection, deployment_id: i32) {
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(deployment_id),
+            status: Set(DeploymentStatusType::Stopping),
+            run_id: Set(Some(8819501307)),
+            ..Default::default()
+        }
+        .update(conn)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_cancels_pending_deployment() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_cancels_pending_deployment")
+                .await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+
+        let not_started_deployment_id = 4;
+        scoutcloud_entity::deployments::ActiveModel {
+            id: Set(not_started_deployment_id),
+            status: Set(DeploymentStatusType::Pending),
+            run_id: Set(Some(8819501642)),
+            ..Default::default()
+        }
+        .update(conn.as_ref())
+        .await
+        .unwrap();
+        runner
+            .insert_task(&StoppingTask::from_deployment_id(not_started_deployment_id))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), not_started_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        assert!(deployment.model.finished_at.is_some());
+        handles.assert_hits("cancel_run_deploy_yaml", 1);
+        handles.assert_hits("dispatch_cleanup_yaml", 0);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_fails_on_not_found_run() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_fails_on_not_found_run").await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_status(&mut handles, "single_run_cleanup_yaml", 404);
+
+        let running_deployment_id = 1;
+        set_stopping_with_run(conn.as_ref(), running_deployment_id).await;
+        runner
+            .insert_task(&StoppingTask::from_deployment_id(running_deployment_id))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert!(deployment.model.terminal_error);
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn failed_stop_is_counted_with_error_code() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("failed_stop_is_counted_with_error_code")
+                .await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_status(&mut handles, "single_run_cleanup_yaml", 404);
+        let results = |status: &str, error_code: &str| {
+            crate::logic::deploy::metrics::DEPLOYMENT_RESULTS
+                .with_label_values(&[status, error_code])
+                .get()
+        };
+        let counted = || {
+            (
+                results("failed", "github"),
+                results("failed", "unknown"),
+                results("stopped", ""),
+            )
+        };
+        let before = counted();
+
+        let running_deployment_id = 1;
+        set_stopping_with_run(conn.as_ref(), running_deployment_id).await;
+        runner
+            .insert_task(&StoppingTask::from_deployment_id(running_deployment_id))
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert_eq!(deployment.model.error_code.as_deref(), Some("github"));
+        assert_eq!(counted(), (before.0 + 1, before.1, before.2));
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_fails_when_run_disappears() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_fails_when_run_disappears")
+                .await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+
+        let running_deployment_id = 1;
+        let mut task = StoppingTask::from_deployment_id(running_deployment_id);
+        task.workflow_timeout = Duration::from_secs(60);
+        task.workflow_check_interval = Duration::from_millis(200);
+        task.workflow_backoff_multiplier = 1.0;
+        task.database_url = Some(db.db_url().to_string());
+        runner.insert_task(&task).await.unwrap();
+        // the run is deleted after it was seen in progress twice
+        while handles
+            .with_name("single_run_cleanup_yaml")
+            .hits_async()
+            .await
+            < 2
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        repo.override_status(&mut handles, "single_run_cleanup_yaml", 404);
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert!(deployment.model.terminal_error);
+        assert_eq!(
+            deployment.model.error_code.as_deref(),
+            Some("run_disappeared")
+        );
+        let error = deployment.model.error.unwrap_or_default();
+        assert!(
+            error.contains("8819501307") && error.contains("start the deployment again"),
+            "{error}"
+        );
+        // the task is not retried, so the run is requested only once after it disappeared
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_retries_on_server_error() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_retries_on_server_error").await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_status(&mut handles, "single_run_cleanup_yaml", 503);
+
+        let running_deployment_id = 1;
+        set_stopping_with_run(conn.as_ref(), running_deployment_id).await;
+        runner
+            .insert_task(&StoppingTask::from_deployment_id(running_deployment_id))
+            .await
+            .unwrap();
+        let task = tests_utils::db::wait_until_some_with_timeout(
+            conn.clone(),
+            Duration::from_secs(20),
+            Duration::from_millis(500),
+            |conn| async move {
+                scoutcloud_entity::fang_tasks::Entity::find()
+                    .one(conn.as_ref())
+                    .await
+                    .unwrap()
+                    .filter(|task| task.retries > 0)
+            },
+        )
+        .await
+        .expect("task should be retried");
+
+        assert!(task.error_message.is_some());
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Stopping);
+        assert!(!deployment.model.terminal_error);
+        assert_eq!(deployment.model.error, None);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_shutdown_leaves_resumable_state() {
+        let (db, _github, repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "stopping_task_shutdown_leaves_resumable_state",
+        )
+        .await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+
+        let running_deployment_id = 1;
+        runner
+            .insert_task(&StoppingTask::from_deployment_id(running_deployment_id))
+            .await
+            .unwrap();
+        // run_id is saved right after the dispatch, so the task is polling now
+        tests_utils::db::wait_until_some_with_timeout(
+            conn.clone(),
+            Duration::from_secs(20),
+            Duration::from_millis(100),
+            |conn| async move {
+                Deployment::get(conn.as_ref(), running_deployment_id)
+                    .await
+                    .unwrap()
+                    .run_id()
+            },
+        )
+        .await
+        .expect("workflow should be dispatched");
+
+        let drained = runner.shutdown(Duration::from_secs(5)).await;
+        assert!(drained, "task should stop polling on shutdown");
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Stopping);
+        assert_eq!(deployment.run_id().map(|id| id.0), Some(8819501307));
+        assert_eq!(deployment.model.error, None);
+
+        // simulate restart of scoutcloud, meanwhile the workflow has finished
+        global::SHUTDOWN.init(Default::default()).await.unwrap();
+        repo.override_response(&mut handles, "single_run_cleanup_yaml", |_| {});
+        runner
+            .resume_interrupted_deployments(conn.as_ref(), false)
+            .await
+            .unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn webhook_stops_poll_of_finalized_deployment() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("webhook_stops_poll_of_finalized_deployment")
+                .await;
+        let conn = db.client();
+        let mut handles = repo.build_handles();
+        repo.override_response(&mut handles, "single_run_cleanup_yaml", |run| {
+            run["status"] = "in_progress".into();
+            run["conclusion"] = serde_json::Value::Null;
+        });
+
+        let running_deployment_id = 1;
+        let mut task = StoppingTask::from_deployment_id(running_deployment_id);
+        // without the webhook the task would sleep until the next check for a minute
+        task.workflow_timeout = Duration::from_secs(120);
+        task.workflow_check_interval = Duration::from_secs(60);
+        task.database_url = Some(db.db_url().to_string());
+        runner.insert_task(&task).await.unwrap();
+        while handles
+            .with_name("single_run_cleanup_yaml")
+            .hits_async()
+            .await
+            < 1
+        {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let body = serde_json::json!({
+            "action": "completed",
+            "workflow_run": { "id": 8819501307u64, "status": "completed", "conclusion": "failure" },
+        });
+        let event = WorkflowRunEvent::parse(body.to_string().as_bytes()).unwrap();
+        let started_at = std::time::Instant::now();
+        assert_eq!(
+            handle_workflow_run_event(&conn, &event).await.unwrap(),
+            Some(running_deployment_id)
+        );
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+        assert!(
+            started_at.elapsed() < Duration::from_secs(10),
+            "poll didn't exit early: {:?}",
+            started_at.elapsed()
+        );
+
+        let deployment = Deployment::get(conn.as_ref(), running_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(deployment.model.status, DeploymentStatusType::Failed);
+        assert_eq!(
+            deployment.model.error_code.as_deref(),
+            Some("workflow_failed")
+        );
+        let history = deployment.status_history(conn.as_ref()).await.unwrap();
+        let finalizations: Vec<_> = history
+            .iter()
+            .filter(|change| change.old_status == Some(DeploymentStatusType::Stopping))
+            .map(|change| (change.new_status.clone(), change.actor.as_str()))
+            .collect();
+        assert_eq!(
+            finalizations,
+            vec![(DeploymentStatusType::Failed, "webhook")]
+        );
+        // the run was not checked again after the webhook
+        handles.assert_hits("single_run_cleanup_yaml", 1);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stopping_task_works_with_other_ci_backend() {
+        let (db, _github, repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stopping_task_works_with_other_ci_backend")
+                .await;
+        let conn = db.client();
+        let handles = repo.build_handles();
+        let ci = Arc::new(FakeCi::default());
+        global::CI.init(ci.clone()).await.unwrap();
+
+        let mut task = StoppingTask::from_deployment_id(1);
+        task.database_url = Some(db.db_url().to_string());
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        assert_eq!(deployment.run_id(), Some(RunId(1)));
+        assert_eq!(
+            ci.dispatched(),
+            vec![(CiWorkflow::Cleanup, "instance-1".to_string())]
+        );
+        handles.assert_hits("dispatch_cleanup_yaml", 0);
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stop_right_after_start_cancels_deploy_workflow() {
+        let (db, _github, _repo, runner) = tests_utils::init::jobs_runner_test_case(
+            "stop_right_after_start_cancels_deploy_workflow",
+        )
+        .await;
+        let conn = db.client();
+        // workflows are dispatched slowly and never finish, so the deployment
+        // is pending until its workflow is cancelled
+        let ci = Arc::new(
+            FakeCi::default()
+                .with_dispatch_delay(Duration::from_millis(500))
+                .with_default_run(FakeRun::Hangs),
+        );
+        global::CI.init(ci.clone()).await.unwrap();
+
+        // both tasks are picked up at once, so the stop comes either before
+        // the dispatch or in the middle of it
+        let not_started_deployment_id = 4;
+        runner
+            .insert_task(&StartingTask::from_deployment_id(not_started_deployment_id))
+            .await
+            .unwrap();
+        let mut task = StoppingTask::from_deployment_id(not_started_deployment_id);
+        task.database_url = Some(db.db_url().to_string());
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        let deployment = Deployment::get(conn.as_ref(), not_started_deployment_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            deployment.model.status,
+            DeploymentStatusType::Stopped,
+            "deployment is not stopped. error: {:?}",
+            deployment.model.error
+        );
+        // the only dispatched workflow was cancelled, nothing is left running
+        assert_eq!(
+            ci.dispatched(),
+            vec![(CiWorkflow::Deploy, "instance-3".to_string())]
+        );
+        assert_eq!(ci.cancelled(), vec![RunId(1)]);
+        let history = deployment.status_history(conn.as_ref()).await.unwrap();
+        let last = history.last().expect("status history is empty");
+        assert_eq!(
+            (last.old_status.clone(), last.new_status.clone()),
+            (
+                Some(DeploymentStatusType::Pending),
+                DeploymentStatusType::Stopped
+            )
+        );
+        assert_eq!(last.actor, "stopping");
+    }
 }
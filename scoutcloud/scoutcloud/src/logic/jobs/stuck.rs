@@ -0,0 +1,152 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::{global, readable_duration, starting, stopping};
+use crate::logic::{deploy::StatusActor, DeployError, Deployment};
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use std::time::Duration;
+use tracing::instrument;
+
+// failed task is retried by fang with backoff, so the workflow
+// can be legitimately waited for longer than its timeout
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+const ACTOR: StatusActor = StatusActor::Task("stuck");
+
+/// Fails deployments which are `Pending` or `Stopping` for longer than workflow timeout
+/// plus grace period, e.g. if github silently dropped the workflow run
+#[derive(fang::serde::Serialize, fang::serde::Deserialize)]
+#[serde(crate = "fang::serde")]
+pub struct StuckDeploymentTask {
+    schedule: Option<String>,
+    #[serde(with = "readable_duration")]
+    grace_period: Duration,
+}
+
+impl Default for StuckDeploymentTask {
+    fn default() -> Self {
+        Self {
+            schedule: Some("0 */10 * * * *".to_string()),
+            grace_period: DEFAULT_GRACE_PERIOD,
+        }
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for StuckDeploymentTask {
+    #[instrument(err(Debug), skip(self, _client), level = "info")]
+    async fn run(&self, _client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let db = global::DATABASE.get().await;
+        let in_progress = Deployment::find_with_statuses(
+            db.as_ref(),
+            &[
+                DeploymentStatusType::Pending,
+                DeploymentStatusType::Stopping,
+            ],
+        )
+        .await
+        .map_err(DeployError::Db)?;
+        let now = chrono::Utc::now();
+        for deployment in in_progress {
+            let mut deployment = deployment.with_actor(ACTOR);
+            let timeout = self.watchdog_timeout(&deployment);
+            let is_stuck = now
+                .signed_duration_since(deployment.model.updated_at)
+                .to_std()
+                .is_ok_and(|stuck_for| stuck_for > timeout);
+            if !is_stuck {
+                continue;
+            }
+            tracing::warn!(
+                deployment_id = deployment.model.id,
+                status = ?deployment.model.status,
+                updated_at = %deployment.model.updated_at,
+                "deployment is stuck. marking deployment as failed",
+            );
+            let error = DeployError::WatchdogTimeout {
+                status: deployment.model.status.clone(),
+                timeout,
+            };
+            deployment.mark_as_error(db.as_ref(), &error).await?;
+        }
+        Ok(())
+    }
+
+    fn uniq(&self) -> bool {
+        true
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.schedule
+            .as_ref()
+            .map(|s| Scheduled::CronPattern(s.clone()))
+    }
+}
+
+impl StuckDeploymentTask {
+    fn watchdog_timeout(&self, deployment: &Deployment) -> Duration {
+        let workflow_timeout =
+            deployment
+                .workflow_timeout()
+                .unwrap_or(match deployment.model.status {
+                    DeploymentStatusType::Stopping => stopping::default_workflow_timeout(),
+                    _ => starting::default_workflow_timeout(),
+                });
+        workflow_timeout + self.grace_period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set, IntoActiveModel};
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn stuck_deployments_are_failed() {
+        let (db, _github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("stuck_deployments_are_failed").await;
+        let conn = db.client();
+
+        // deployment#4 is pending and nothing happened to it for hours
+        let mut stuck = Deployment::get(conn.as_ref(), 4).await.unwrap();
+        stuck
+            .update_status(conn.as_ref(), DeploymentStatusType::Pending)
+            .await
+            .unwrap();
+        let mut model = stuck.model.clone().into_active_model();
+        model.updated_at = Set((chrono::Utc::now() - chrono::Duration::hours(3)).fixed_offset());
+        model.update(conn.as_ref()).await.unwrap();
+
+        // deployment#1 has just started stopping
+        let mut healthy = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        healthy
+            .update_status(conn.as_ref(), DeploymentStatusType::Stopping)
+            .await
+            .unwrap();
+
+        let task = StuckDeploymentTask {
+            schedule: None,
+            grace_period: DEFAULT_GRACE_PERIOD,
+        };
+        runner.insert_task(&task).await.unwrap();
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
+
+        stuck.reload(conn.as_ref()).await.unwrap();
+        assert_eq!(stuck.model.status, DeploymentStatusType::Failed);
+        assert_eq!(stuck.model.error_code.as_deref(), Some("watchdog_timeout"));
+        let error = stuck.model.error.clone().unwrap_or_default();
+        assert!(
+            error.starts_with("watchdog timeout"),
+            "unexpected error: {error}"
+        );
+
+        healthy.reload(conn.as_ref()).await.unwrap();
+        assert_eq!(healthy.model.status, DeploymentStatusType::Stopping);
+        assert_eq!(healthy.model.error, None);
+    }
+}
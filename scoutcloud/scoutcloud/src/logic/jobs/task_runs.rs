@@ -0,0 +1,58 @@
+use super::global;
+use crate::logic::{github::count_polls, Deployment};
+use chrono::Utc;
+use fang::FangError;
+use scoutcloud_entity::task_runs;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+use std::future::Future;
+
+/// Runs the task and saves the result of the execution to `task_runs`, so slow
+/// and retried deployments can be investigated. Every check of the workflow run
+/// is stored as `last_polled_at` of the deployment. Failures to save them are only logged
+pub(super) async fn record_task_run(
+    task: &'static str,
+    deployment_id: i32,
+    run: impl Future<Output = Result<(), FangError>>,
+) -> Result<(), FangError> {
+    let db = global::DATABASE.get().await.clone();
+    let on_poll = {
+        let db = db.clone();
+        move || {
+            let db = db.clone();
+            tokio::spawn(async move {
+                if let Err(err) = Deployment::touch_last_polled_at(db.as_ref(), deployment_id).await
+                {
+                    tracing::warn!(err = ?err, "failed to save time of the last poll");
+                }
+            });
+        }
+    };
+    let started_at = Utc::now();
+    let (result, poll_count) = count_polls(on_poll, run).await;
+    let finished_at = Utc::now();
+
+    // run id is stored by the task itself once the workflow is dispatched
+    let run_id = Deployment::get(db.as_ref(), deployment_id)
+        .await
+        .ok()
+        .and_then(|deployment| deployment.model.run_id);
+    let (outcome, error) = match &result {
+        Ok(()) => ("ok", None),
+        Err(err) => ("error", Some(err.description.clone())),
+    };
+    let record = task_runs::ActiveModel {
+        task_type: Set(task.to_string()),
+        deployment_id: Set(deployment_id),
+        run_id: Set(run_id),
+        started_at: Set(started_at.fixed_offset()),
+        finished_at: Set(finished_at.fixed_offset()),
+        poll_count: Set(poll_count.try_into().unwrap_or(i32::MAX)),
+        outcome: Set(outcome.to_string()),
+        error: Set(error),
+        ..Default::default()
+    };
+    if let Err(err) = record.insert(db.as_ref()).await {
+        tracing::warn!(err = ?err, "failed to save run of the task");
+    }
+    result
+}
@@ -0,0 +1,152 @@
+use super::{global, metrics::WORKFLOWS_APPROACHING_TIMEOUT};
+use crate::logic::{
+    notifications::{self, TimeoutWarning},
+    Deployment,
+};
+use std::{future::Future, time::Duration};
+
+/// Waits for the workflow of the deployment and reports once that it is approaching
+/// `timeout`, if waiting takes longer than the configured percent of it.
+/// So somebody can look into the workflow before the task gives up on it
+pub(super) async fn warn_before_timeout<T>(
+    task: &'static str,
+    deployment: &Deployment,
+    timeout: Duration,
+    wait: impl Future<Output = T>,
+) -> T {
+    let percent = global::workflows().timeout_warning_percent;
+    let Some(warn_after) = warning_delay(timeout, percent) else {
+        return wait.await;
+    };
+    let mut wait = std::pin::pin!(wait);
+    tokio::select! {
+        output = &mut wait => return output,
+        _ = tokio::time::sleep(warn_after) => {}
+    }
+    report(TimeoutWarning {
+        deployment_id: deployment.model.external_id.to_string(),
+        task,
+        remaining: timeout.saturating_sub(warn_after),
+    });
+    wait.await
+}
+
+/// Percents outside of `1..100` disable the warning, since it would be reported
+/// right away or never
+fn warning_delay(timeout: Duration, percent: Option<u8>) -> Option<Duration> {
+    let percent = percent.filter(|percent| (1..100).contains(percent))?;
+    Some(timeout.mul_f64(f64::from(percent) / 100.0))
+}
+
+fn report(warning: TimeoutWarning) {
+    WORKFLOWS_APPROACHING_TIMEOUT
+        .with_label_values(&[warning.task])
+        .inc();
+    tracing::warn!(
+        deployment_id = warning.deployment_id,
+        task = warning.task,
+        remaining = ?warning.remaining,
+        "workflow is approaching its timeout"
+    );
+    if let Some(notifier) = notifications::timeout_warning_notifier() {
+        notifications::spawn_timeout_warning(notifier, warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::notifications::SlackNotifier,
+        server::{SlackSettings, WorkflowSettings},
+        tests_utils,
+    };
+    use httpmock::{Method::POST, MockServer};
+    use pretty_assertions::assert_eq;
+    use std::{sync::Arc, time::Instant};
+
+    const TASK: &str = "test_timeout_warning";
+
+    fn reported() -> u64 {
+        WORKFLOWS_APPROACHING_TIMEOUT
+            .with_label_values(&[TASK])
+            .get()
+    }
+
+    #[test]
+    fn warning_delay_is_share_of_timeout() {
+        let timeout = Duration::from_secs(20 * 60);
+        assert_eq!(
+            warning_delay(timeout, Some(80)),
+            Some(Duration::from_secs(16 * 60))
+        );
+        for percent in [None, Some(0), Some(100), Some(150)] {
+            assert_eq!(warning_delay(timeout, percent), None, "{percent:?}");
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn approaching_timeout_is_reported_once() {
+        let db = tests_utils::init::test_db("test", "approaching_timeout_is_reported_once").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let deployment = Deployment::get(conn.as_ref(), 1).await.unwrap();
+        let server = MockServer::start_async().await;
+        let slack = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/slack")
+                    .body_contains(deployment.model.external_id.to_string())
+                    .body_contains("approaching its timeout");
+                then.status(200);
+            })
+            .await;
+        let notifier = SlackNotifier::from_settings(&SlackSettings {
+            webhook_url: server.url("/slack"),
+            channel: None,
+            notify_on: vec![],
+            notify_approaching_timeout: true,
+        })
+        .unwrap();
+        notifications::init_notifier(Some(Arc::new(notifier)));
+        global::init_workflows(WorkflowSettings {
+            timeout_warning_percent: Some(50),
+            ..Default::default()
+        });
+        let before = reported();
+
+        // workflow is checked every 20ms until its timeout,
+        // every check remembers how many times the warning was reported
+        let timeout = Duration::from_millis(300);
+        let checks = warn_before_timeout(TASK, &deployment, timeout, async {
+            let started = Instant::now();
+            let mut checks = vec![];
+            while started.elapsed() < timeout {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                checks.push(reported() - before);
+            }
+            checks
+        })
+        .await;
+        global::init_workflows(Default::default());
+
+        assert_eq!(checks.first(), Some(&0), "reported too early: {checks:?}");
+        assert_eq!(
+            checks.last(),
+            Some(&1),
+            "not reported before timeout: {checks:?}"
+        );
+        assert!(checks.iter().all(|reported| *reported <= 1), "{checks:?}");
+        assert_eq!(reported() - before, 1);
+
+        let start = Instant::now();
+        while slack.hits_async().await == 0 && start.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        notifications::init_notifier(None);
+        slack.assert_hits_async(1).await;
+    }
+}
@@ -0,0 +1,576 @@
+#![allow(clippy::blocks_in_conditions)]
+
+use super::{global, readable_duration};
+use crate::logic::{
+    url_guard::{self, UrlGuard},
+    DeployError,
+};
+use anyhow::Context;
+use fang::{typetag, AsyncQueueable, AsyncRunnable, FangError, Scheduled};
+use hmac::{Hmac, Mac};
+use scoutcloud_entity as db;
+use sea_orm::{prelude::*, ActiveValue::Set, IntoActiveModel, QueryOrder, QuerySelect};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{collections::HashMap, time::Duration};
+use tracing::instrument;
+use url::Url;
+
+pub const SIGNATURE_HEADER: &str = "X-Scoutcloud-Signature";
+pub const DELIVERY_HEADER: &str = "X-Scoutcloud-Delivery";
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+const DEFAULT_MAX_ATTEMPTS: i32 = 8;
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+const BATCH_SIZE: u64 = 100;
+
+/// Sends status changes of deployments, recorded by the database trigger, to webhooks
+/// of their owners. Failed deliveries are retried with exponential backoff and
+/// dead-lettered after `max_attempts`
+#[derive(fang::serde::Serialize, fang::serde::Deserialize)]
+#[serde(crate = "fang::serde")]
+pub struct WebhookDeliveryTask {
+    schedule: Option<String>,
+    max_attempts: i32,
+    #[serde(with = "readable_duration")]
+    request_timeout: Duration,
+}
+
+impl Default for WebhookDeliveryTask {
+    fn default() -> Self {
+        Self {
+            schedule: Some("*/10 * * * * *".to_string()),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+}
+
+#[typetag::serde]
+#[fang::async_trait]
+impl AsyncRunnable for WebhookDeliveryTask {
+    #[instrument(err(Debug), skip(self, _client), level = "debug")]
+    async fn run(&self, _client: &dyn AsyncQueueable) -> Result<(), FangError> {
+        let db = global::DATABASE.get().await;
+        let guard = url_guard::guard();
+        let client = guard
+            .client_builder()
+            .timeout(self.request_timeout)
+            .build()
+            .context("building webhook client")
+            .map_err(DeployError::Internal)?;
+        deliver_pending_webhooks(db.as_ref(), &client, &guard, self.max_attempts)
+            .await
+            .map_err(DeployError::Db)?;
+        Ok(())
+    }
+
+    fn uniq(&self) -> bool {
+        true
+    }
+
+    fn cron(&self) -> Option<Scheduled> {
+        self.schedule
+            .as_ref()
+            .map(|s| Scheduled::CronPattern(s.clone()))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct StatusChangePayload {
+    pub deployment_id: String,
+    pub instance_id: String,
+    pub old_status: String,
+    pub new_status: String,
+    /// Time of the status change in RFC 3339 format
+    pub timestamp: String,
+}
+
+impl StatusChangePayload {
+    fn new(
+        delivery: &db::webhook_deliveries::Model,
+        deployment: &db::deployments::Model,
+        instance: &db::instances::Model,
+    ) -> Self {
+        Self {
+            deployment_id: deployment.external_id.to_string(),
+            instance_id: instance.external_id.to_string(),
+            old_status: delivery.old_status.to_value(),
+            new_status: delivery.new_status.to_value(),
+            timestamp: delivery.created_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Signs the body the same way github signs its webhooks,
+/// so receivers can verify it with any existing github webhook library
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(body);
+    format!(
+        "{SIGNATURE_PREFIX}{}",
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Deliveries are sent one by one in order of status changes, but a retried delivery
+/// can arrive after the newer ones, so receivers should rely on `timestamp`.
+/// Urls are checked by the guard once more, since they could be set before it was configured
+async fn deliver_pending_webhooks<C>(
+    db: &C,
+    client: &reqwest::Client,
+    guard: &UrlGuard,
+    max_attempts: i32,
+) -> Result<(), DbErr>
+where
+    C: ConnectionTrait,
+{
+    let now = chrono::Utc::now().fixed_offset();
+    let pending = db::webhook_deliveries::Entity::find()
+        .filter(db::webhook_deliveries::Column::DeliveredAt.is_null())
+        .filter(db::webhook_deliveries::Column::DeadAt.is_null())
+        .filter(db::webhook_deliveries::Column::NextAttemptAt.lte(now))
+        .order_by_asc(db::webhook_deliveries::Column::Id)
+        .limit(BATCH_SIZE)
+        .find_also_related(db::users::Entity)
+        .all(db)
+        .await?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let deployment_ids: Vec<i32> = pending
+        .iter()
+        .map(|(delivery, _)| delivery.deployment_id)
+        .collect();
+    let deployments: HashMap<_, _> = db::deployments::Entity::find()
+        .filter(db::deployments::Column::Id.is_in(deployment_ids))
+        .find_also_related(db::instances::Entity)
+        .all(db)
+        .await?
+        .into_iter()
+        .filter_map(|(deployment, instance)| Some((deployment.id, (deployment, instance?))))
+        .collect();
+
+    for (delivery, user) in pending {
+        let Some((url, secret)) = user.and_then(|user| user.webhook_url.zip(user.webhook_secret))
+        else {
+            // webhook was disabled after the status change
+            mark_as_dead(db, delivery, "webhook is disabled").await?;
+            continue;
+        };
+        let url = match Url::parse(&url)
+            .map_err(|err| err.to_string())
+            .and_then(|url| {
+                guard.check_url(&url)?;
+                Ok(url)
+            }) {
+            Ok(url) => url,
+            Err(reason) => {
+                mark_as_dead(
+                    db,
+                    delivery,
+                    &format!("webhook url is not allowed: {reason}"),
+                )
+                .await?;
+                continue;
+            }
+        };
+        let (deployment, instance) =
+            deployments
+                .get(&delivery.deployment_id)
+                .ok_or(DbErr::RecordNotFound(format!(
+                    "deployment of webhook delivery {} not found",
+                    delivery.id
+                )))?;
+        let payload = StatusChangePayload::new(&delivery, deployment, instance);
+        match send_webhook(client, &url, &secret, delivery.id, &payload).await {
+            Ok(()) => {
+                let attempts = delivery.attempts + 1;
+                let mut model = delivery.into_active_model();
+                model.attempts = Set(attempts);
+                model.delivered_at = Set(Some(now));
+                model.last_error = Set(None);
+                model.update(db).await?;
+            }
+            Err(err) => mark_as_failed(db, delivery, &err, max_attempts).await?,
+        }
+    }
+    Ok(())
+}
+
+async fn send_webhook(
+    client: &reqwest::Client,
+    url: &Url,
+    secret: &str,
+    delivery_id: i32,
+    payload: &StatusChangePayload,
+) -> Result<(), anyhow::Error> {
+    let body = serde_json::to_vec(payload)?;
+    let response = client
+        .post(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign_payload(secret, &body))
+        .header(DELIVERY_HEADER, delivery_id.to_string())
+        .body(body)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("webhook receiver responded with status {status}");
+    }
+    Ok(())
+}
+
+async fn mark_as_failed<C>(
+    db: &C,
+    delivery: db::webhook_deliveries::Model,
+    err: &anyhow::Error,
+    max_attempts: i32,
+) -> Result<(), DbErr>
+where
+    C: ConnectionTrait,
+{
+    let attempts = delivery.attempts + 1;
+    if attempts >= max_attempts {
+        tracing::warn!(
+            delivery_id = delivery.id,
+            user_id = delivery.user_id,
+            attempts,
+            "webhook delivery failed too many times: {err:#}"
+        );
+        let mut model = delivery.into_active_model();
+        model.attempts = Set(attempts);
+        model.last_error = Set(Some(format!("{err:#}")));
+        model.dead_at = Set(Some(chrono::Utc::now().fixed_offset()));
+        model.update(db).await?;
+        return Ok(());
+    }
+    tracing::info!(
+        delivery_id = delivery.id,
+        attempts,
+        "webhook delivery failed, will be retried: {err:#}"
+    );
+    let delay = chrono::Duration::from_std(retry_delay(attempts)).expect("delay is small");
+    let mut model = delivery.into_active_model();
+    model.attempts = Set(attempts);
+    model.last_error = Set(Some(format!("{err:#}")));
+    model.next_attempt_at = Set((chrono::Utc::now() + delay).fixed_offset());
+    model.update(db).await?;
+    Ok(())
+}
+
+async fn mark_as_dead<C>(
+    db: &C,
+    delivery: db::webhook_deliveries::Model,
+    reason: &str,
+) -> Result<(), DbErr>
+where
+    C: ConnectionTrait,
+{
+    let mut model = delivery.into_active_model();
+    model.last_error = Set(Some(reason.to_string()));
+    model.dead_at = Set(Some(chrono::Utc::now().fixed_offset()));
+    model.update(db).await?;
+    Ok(())
+}
+
+fn retry_delay(attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 31) as u32;
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(exponent))
+        .min(RETRY_MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        logic::{github::webhook::verify_signature, url_guard::local_guard},
+        tests_utils,
+    };
+    use httpmock::{Method::POST, MockServer};
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+
+    const SECRET: &str = "webhook-secret";
+
+    async fn set_webhook(db: &DatabaseConnection, user_id: i32, url: &str) {
+        db::users::ActiveModel {
+            id: Set(user_id),
+            webhook_url: Set(Some(url.to_string())),
+            webhook_secret: Set(Some(SECRET.to_string())),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .unwrap();
+    }
+
+    async fn stop_deployment(db: &DatabaseConnection, deployment_id: i32) {
+        db::deployments::ActiveModel {
+            id: Set(deployment_id),
+            status: Set(DeploymentStatusType::Stopped),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+        .unwrap();
+    }
+
+    async fn deliveries(db: &DatabaseConnection) -> Vec<db::webhook_deliveries::Model> {
+        db::webhook_deliveries::Entity::find()
+            .order_by_asc(db::webhook_deliveries::Column::Id)
+            .all(db)
+            .await
+            .unwrap()
+    }
+
+    async fn make_deliveries_due(db: &DatabaseConnection) {
+        db::webhook_deliveries::Entity::update_many()
+            .col_expr(
+                db::webhook_deliveries::Column::NextAttemptAt,
+                Expr::current_timestamp().into(),
+            )
+            .exec(db)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn signature_matches_github_algorithm() {
+        // example from https://docs.github.com/en/webhooks/using-webhooks/validating-webhook-deliveries
+        assert_eq!(
+            sign_payload("It's a Secret to Everybody", b"Hello, World!"),
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+        let body = br#"{"deployment_id":"1"}"#;
+        verify_signature(SECRET, body, Some(&sign_payload(SECRET, body))).unwrap();
+        assert!(verify_signature("other", body, Some(&sign_payload(SECRET, body))).is_err());
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially() {
+        let delays: Vec<u64> = [1, 2, 3, 8, 100]
+            .into_iter()
+            .map(|attempts| retry_delay(attempts).as_secs())
+            .collect();
+        assert_eq!(delays, vec![30, 60, 120, 3600, 3600]);
+    }
+
+    #[tokio::test]
+    async fn status_change_is_delivered_to_webhook() {
+        let db = tests_utils::init::test_db("test", "status_change_is_delivered_to_webhook").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let server = MockServer::start_async().await;
+        set_webhook(conn.as_ref(), 1, &server.url("/hook")).await;
+
+        // deployment#1 belongs to user#1, deployment#3 belongs to user#2 without webhook
+        stop_deployment(conn.as_ref(), 1).await;
+        stop_deployment(conn.as_ref(), 3).await;
+        let pending = deliveries(conn.as_ref()).await;
+        assert_eq!(pending.len(), 1);
+        let delivery = &pending[0];
+        assert_eq!(
+            (
+                delivery.deployment_id,
+                &delivery.old_status,
+                &delivery.new_status
+            ),
+            (
+                1,
+                &DeploymentStatusType::Running,
+                &DeploymentStatusType::Stopped
+            )
+        );
+
+        let deployment = db::deployments::Entity::find_by_id(1)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        let instance = db::instances::Entity::find_by_id(deployment.instance_id)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        let body =
+            serde_json::to_string(&StatusChangePayload::new(delivery, &deployment, &instance))
+                .unwrap();
+        let hook = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/hook")
+                    .header(SIGNATURE_HEADER, sign_payload(SECRET, body.as_bytes()))
+                    .header(DELIVERY_HEADER, delivery.id.to_string())
+                    .json_body_partial(
+                        serde_json::json!({
+                            "deployment_id": deployment.external_id.to_string(),
+                            "old_status": "running",
+                            "new_status": "stopped",
+                        })
+                        .to_string(),
+                    );
+                then.status(200);
+            })
+            .await;
+
+        deliver_pending_webhooks(
+            conn.as_ref(),
+            &reqwest::Client::new(),
+            &local_guard(),
+            DEFAULT_MAX_ATTEMPTS,
+        )
+        .await
+        .unwrap();
+        hook.assert_hits_async(1).await;
+        let delivery = deliveries(conn.as_ref()).await.remove(0);
+        assert!(delivery.delivered_at.is_some());
+        assert_eq!(delivery.attempts, 1);
+
+        // delivered webhooks are not sent again
+        make_deliveries_due(conn.as_ref()).await;
+        deliver_pending_webhooks(
+            conn.as_ref(),
+            &reqwest::Client::new(),
+            &local_guard(),
+            DEFAULT_MAX_ATTEMPTS,
+        )
+        .await
+        .unwrap();
+        hook.assert_hits_async(1).await;
+    }
+
+    #[tokio::test]
+    async fn failed_webhook_delivery_is_retried() {
+        let db = tests_utils::init::test_db("test", "failed_webhook_delivery_is_retried").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let server = MockServer::start_async().await;
+        set_webhook(conn.as_ref(), 1, &server.url("/hook")).await;
+        stop_deployment(conn.as_ref(), 1).await;
+        let client = reqwest::Client::new();
+
+        let mut failing = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/hook");
+                then.status(500);
+            })
+            .await;
+        deliver_pending_webhooks(conn.as_ref(), &client, &local_guard(), 3)
+            .await
+            .unwrap();
+        failing.assert_hits_async(1).await;
+        let delivery = deliveries(conn.as_ref()).await.remove(0);
+        assert_eq!(delivery.attempts, 1);
+        assert!(delivery.delivered_at.is_none() && delivery.dead_at.is_none());
+        assert!(delivery.next_attempt_at > chrono::Utc::now());
+        assert_eq!(
+            delivery.last_error.as_deref(),
+            Some("webhook receiver responded with status 500 Internal Server Error")
+        );
+
+        // retry is not due yet
+        deliver_pending_webhooks(conn.as_ref(), &client, &local_guard(), 3)
+            .await
+            .unwrap();
+        failing.assert_hits_async(1).await;
+
+        failing.delete_async().await;
+        let hook = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/hook");
+                then.status(204);
+            })
+            .await;
+        make_deliveries_due(conn.as_ref()).await;
+        deliver_pending_webhooks(conn.as_ref(), &client, &local_guard(), 3)
+            .await
+            .unwrap();
+        hook.assert_hits_async(1).await;
+        let delivery = deliveries(conn.as_ref()).await.remove(0);
+        assert_eq!(delivery.attempts, 2);
+        assert!(delivery.delivered_at.is_some());
+        assert_eq!(delivery.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn webhook_delivery_is_dead_lettered() {
+        let db = tests_utils::init::test_db("test", "webhook_delivery_is_dead_lettered").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let server = MockServer::start_async().await;
+        set_webhook(conn.as_ref(), 1, &server.url("/hook")).await;
+        stop_deployment(conn.as_ref(), 1).await;
+        let client = reqwest::Client::new();
+        let failing = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/hook");
+                then.status(500);
+            })
+            .await;
+
+        for _ in 0..3 {
+            deliver_pending_webhooks(conn.as_ref(), &client, &local_guard(), 2)
+                .await
+                .unwrap();
+            make_deliveries_due(conn.as_ref()).await;
+        }
+        // dead delivery is not retried anymore
+        failing.assert_hits_async(2).await;
+        let delivery = deliveries(conn.as_ref()).await.remove(0);
+        assert_eq!(delivery.attempts, 2);
+        assert!(delivery.dead_at.is_some());
+        assert!(delivery.delivered_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn webhook_to_loopback_is_not_delivered() {
+        let db = tests_utils::init::test_db("test", "webhook_to_loopback_is_not_delivered").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let server = MockServer::start_async().await;
+        let hook = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/hook");
+                then.status(200);
+            })
+            .await;
+        // url could be stored before the guard was configured
+        set_webhook(conn.as_ref(), 1, &server.url("/hook")).await;
+        stop_deployment(conn.as_ref(), 1).await;
+
+        deliver_pending_webhooks(
+            conn.as_ref(),
+            &reqwest::Client::new(),
+            &UrlGuard::default(),
+            DEFAULT_MAX_ATTEMPTS,
+        )
+        .await
+        .unwrap();
+        hook.assert_hits_async(0).await;
+        let delivery = deliveries(conn.as_ref()).await.remove(0);
+        assert!(delivery.dead_at.is_some());
+        assert!(delivery.delivered_at.is_none());
+        assert_eq!(
+            delivery.last_error,
+            Some(format!(
+                "webhook url is not allowed: url '{}' points to a private, \
+                 loopback or link-local address",
+                server.url("/hook")
+            ))
+        );
+    }
+}
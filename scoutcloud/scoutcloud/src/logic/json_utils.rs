@@ -1,4 +1,5 @@
 use json_dotpath::DotPaths;
+use std::collections::BTreeMap;
 
 pub fn update_json_by_path(
     json: &mut serde_json::Value,
@@ -32,3 +33,32 @@ pub fn filter_null_values(json: &mut serde_json::Value) {
         }
     }
 }
+
+/// Maps dot separated paths of all non-object values to these values
+pub fn flatten(json: &serde_json::Value) -> BTreeMap<String, serde_json::Value> {
+    fn flatten_into(
+        prefix: Option<&str>,
+        json: &serde_json::Value,
+        result: &mut BTreeMap<String, serde_json::Value>,
+    ) {
+        match json {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    let path = match prefix {
+                        Some(prefix) => format!("{prefix}.{key}"),
+                        None => key.clone(),
+                    };
+                    flatten_into(Some(&path), value, result);
+                }
+            }
+            serde_json::Value::Null => {}
+            value => {
+                result.insert(prefix.unwrap_or_default().to_string(), value.clone());
+            }
+        }
+    }
+
+    let mut result = BTreeMap::new();
+    flatten_into(None, json, &mut result);
+    result
+}
@@ -1,14 +1,21 @@
+pub mod ci;
 mod config;
 mod db_utils;
 pub mod deploy;
+pub mod events;
 pub mod github;
 pub mod jobs;
 mod json_utils;
+pub mod notifications;
+pub mod secrets;
+pub mod url_guard;
 pub mod users;
 
 pub use config::{
-    ConfigError, ConfigValidationContext, InstanceConfig, ParsedVariable, ParsedVariableKey,
-    UserConfig, UserVariable,
+    preset::{self, InstancePresets},
+    variables::image::{self, ImageRegistries},
+    ConfigError, ConfigTemplate, ConfigValidationContext, InstanceConfig, ParsedVariable,
+    ParsedVariableKey, UserConfig, UserVariable,
 };
 pub use deploy::{DeployError, Deployment, Instance, InstanceDeployment};
 pub use github::{GithubClient, GithubError};
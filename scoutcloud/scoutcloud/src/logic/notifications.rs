@@ -0,0 +1,230 @@
+use crate::server::{NotifiedStatus, SlackSettings};
+use lazy_static::lazy_static;
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use serde::Serialize;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref NOTIFIER: RwLock<Option<Arc<SlackNotifier>>> = RwLock::new(None);
+}
+
+/// Deployment which entered one of the notified statuses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusNotification {
+    pub deployment_id: String,
+    pub instance_name: String,
+    pub status: DeploymentStatusType,
+    pub error: Option<String>,
+}
+
+/// Workflow of the deployment which is still running close to its timeout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeoutWarning {
+    pub deployment_id: String,
+    /// Task which waits for the workflow, like `starting`
+    pub task: &'static str,
+    /// Time left until the task gives up waiting
+    pub remaining: Duration,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SlackMessage {
+    pub text: String,
+    /// Overrides the default channel of the webhook
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+/// Posts status changes of deployments to slack incoming webhook
+#[derive(Debug)]
+pub struct SlackNotifier {
+    client: reqwest::Client,
+    webhook_url: String,
+    channel: Option<String>,
+    notify_on: Vec<NotifiedStatus>,
+    notify_approaching_timeout: bool,
+}
+
+impl SlackNotifier {
+    pub fn from_settings(settings: &SlackSettings) -> Result<Self, anyhow::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            client,
+            webhook_url: settings.webhook_url.clone(),
+            channel: settings.channel.clone(),
+            notify_on: settings.notify_on.clone(),
+            notify_approaching_timeout: settings.notify_approaching_timeout,
+        })
+    }
+
+    pub fn should_notify(&self, status: &DeploymentStatusType) -> bool {
+        self.notify_on
+            .iter()
+            .any(|notified| notified.matches(status))
+    }
+
+    pub fn format_message(&self, notification: &StatusNotification) -> SlackMessage {
+        let (emoji, title) = match notification.status {
+            DeploymentStatusType::Failed => (":red_circle:", "Deployment failed"),
+            DeploymentStatusType::Stopped => (":white_circle:", "Deployment stopped"),
+            DeploymentStatusType::Running => (":large_green_circle:", "Deployment is running"),
+            _ => (":large_blue_circle:", "Deployment status changed"),
+        };
+        let mut text = format!(
+            "{emoji} *{title}*\n*Instance:* {}\n*Deployment:* `{}`",
+            notification.instance_name, notification.deployment_id
+        );
+        if let Some(error) = &notification.error {
+            text.push_str(&format!("\n*Error:* {error}"));
+        }
+        SlackMessage {
+            text,
+            channel: self.channel.clone(),
+        }
+    }
+
+    pub fn format_timeout_warning(&self, warning: &TimeoutWarning) -> SlackMessage {
+        let text = format!(
+            ":large_yellow_circle: *Workflow is approaching its timeout*\n\
+            *Deployment:* `{}`\n*Task:* {}\n*Remaining:* {}s",
+            warning.deployment_id,
+            warning.task,
+            warning.remaining.as_secs()
+        );
+        SlackMessage {
+            text,
+            channel: self.channel.clone(),
+        }
+    }
+
+    async fn send(&self, message: &SlackMessage) -> Result<(), reqwest::Error> {
+        self.client
+            .post(&self.webhook_url)
+            .json(message)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub fn init_notifier(notifier: Option<Arc<SlackNotifier>>) {
+    *NOTIFIER.write().expect("notifications lock is poisoned") = notifier;
+}
+
+/// Returns the notifier only if deployments entering `status` should be reported
+pub fn notifier_for(status: &DeploymentStatusType) -> Option<Arc<SlackNotifier>> {
+    NOTIFIER
+        .read()
+        .expect("notifications lock is poisoned")
+        .clone()
+        .filter(|notifier| notifier.should_notify(status))
+}
+
+/// Returns the notifier only if workflows approaching their timeout should be reported
+pub fn timeout_warning_notifier() -> Option<Arc<SlackNotifier>> {
+    NOTIFIER
+        .read()
+        .expect("notifications lock is poisoned")
+        .clone()
+        .filter(|notifier| notifier.notify_approaching_timeout)
+}
+
+/// Sends the notification in a separate task, so slack outage doesn't affect deployments
+pub fn spawn_notification(notifier: Arc<SlackNotifier>, notification: StatusNotification) {
+    let message = notifier.format_message(&notification);
+    spawn_message(notifier, notification.deployment_id, message);
+}
+
+pub fn spawn_timeout_warning(notifier: Arc<SlackNotifier>, warning: TimeoutWarning) {
+    let message = notifier.format_timeout_warning(&warning);
+    spawn_message(notifier, warning.deployment_id, message);
+}
+
+fn spawn_message(notifier: Arc<SlackNotifier>, deployment_id: String, message: SlackMessage) {
+    tokio::spawn(async move {
+        if let Err(err) = notifier.send(&message).await {
+            tracing::warn!(
+                deployment_id,
+                err = %err,
+                "failed to send slack notification"
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn notifier(notify_on: Vec<NotifiedStatus>) -> SlackNotifier {
+        SlackNotifier::from_settings(&SlackSettings {
+            webhook_url: "http://localhost/slack".to_string(),
+            channel: Some("#scoutcloud-alerts".to_string()),
+            notify_on,
+            notify_approaching_timeout: false,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn failure_message_is_formatted() {
+        let notification = StatusNotification {
+            deployment_id: "0b0b5f8e-5b1e-4b6f-9d0c-0a5f6e8f6c11".to_string(),
+            instance_name: "Instance 1".to_string(),
+            status: DeploymentStatusType::Failed,
+            error: Some("app unhealthy: health endpoint returned 503".to_string()),
+        };
+        let message = notifier(vec![NotifiedStatus::Failed]).format_message(&notification);
+        assert_eq!(
+            message.text,
+            ":red_circle: *Deployment failed*\n\
+            *Instance:* Instance 1\n\
+            *Deployment:* `0b0b5f8e-5b1e-4b6f-9d0c-0a5f6e8f6c11`\n\
+            *Error:* app unhealthy: health endpoint returned 503"
+        );
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({"text": message.text, "channel": "#scoutcloud-alerts"})
+        );
+    }
+
+    #[test]
+    fn timeout_warning_is_formatted() {
+        let warning = TimeoutWarning {
+            deployment_id: "0b0b5f8e-5b1e-4b6f-9d0c-0a5f6e8f6c11".to_string(),
+            task: "starting",
+            remaining: Duration::from_secs(240),
+        };
+        let message = notifier(vec![]).format_timeout_warning(&warning);
+        assert_eq!(
+            message.text,
+            ":large_yellow_circle: *Workflow is approaching its timeout*\n\
+            *Deployment:* `0b0b5f8e-5b1e-4b6f-9d0c-0a5f6e8f6c11`\n\
+            *Task:* starting\n\
+            *Remaining:* 240s"
+        );
+    }
+
+    #[test]
+    fn only_configured_statuses_are_notified() {
+        let settings: SlackSettings =
+            serde_json::from_value(serde_json::json!({"webhook_url": "http://localhost/slack"}))
+                .unwrap();
+        let default = notifier(settings.notify_on);
+        assert!(default.should_notify(&DeploymentStatusType::Failed));
+        assert!(!default.should_notify(&DeploymentStatusType::Stopped));
+
+        let stops_only = notifier(vec![NotifiedStatus::Stopped]);
+        assert!(stops_only.should_notify(&DeploymentStatusType::Stopped));
+        assert!(!stops_only.should_notify(&DeploymentStatusType::Failed));
+    }
+}
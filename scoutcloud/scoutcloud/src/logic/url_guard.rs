@@ -0,0 +1,266 @@
+use lazy_static::lazy_static;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, RwLock},
+};
+use url::{Host, Url};
+
+// same as the default policy of reqwest
+const MAX_REDIRECTS: usize = 10;
+
+lazy_static! {
+    static ref GUARD: RwLock<Arc<UrlGuard>> = RwLock::new(Default::default());
+}
+
+/// Protects requests to urls from configs of instances, like health checks,
+/// and to webhooks of users from reaching internal endpoints: only http and https urls of public addresses are allowed.
+/// Hosts from `allowed_hosts` may point to any address, e.g. to instances in the local network.
+/// Hostnames are checked once more when they are resolved right before the request,
+/// so a host can't pass validation and then be rebound to an internal address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UrlGuard {
+    allowed_hosts: Vec<String>,
+}
+
+impl UrlGuard {
+    pub fn new(allowed_hosts: Vec<String>) -> Self {
+        let allowed_hosts = allowed_hosts
+            .into_iter()
+            .map(|host| host.trim().to_lowercase())
+            .collect();
+        Self { allowed_hosts }
+    }
+
+    /// Checks the scheme and the host of the url without resolving it
+    pub fn check_url(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("expected http or https url, got '{url}'"));
+        }
+        let Some(host) = url.host().filter(|host| host != &Host::Domain("")) else {
+            return Err(format!("url '{url}' has no host"));
+        };
+        if url
+            .host_str()
+            .is_some_and(|host| self.is_allowed_host(host))
+        {
+            return Ok(());
+        }
+        let is_public = match host {
+            Host::Domain(domain) => {
+                let domain = domain.trim_end_matches('.').to_lowercase();
+                domain != "localhost" && !domain.ends_with(".localhost")
+            }
+            Host::Ipv4(ip) => is_public_ip(IpAddr::V4(ip)),
+            Host::Ipv6(ip) => is_public_ip(IpAddr::V6(ip)),
+        };
+        if !is_public {
+            return Err(format!(
+                "url '{url}' points to a private, loopback or link-local address"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Client which resolves hostnames only to public addresses
+    /// and follows only redirects to allowed urls
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        let guard = self.clone();
+        let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match guard.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(reason) => attempt.error(format!("redirect is not allowed: {reason}")),
+            }
+        });
+        reqwest::Client::builder()
+            .dns_resolver(Arc::new(PublicResolver {
+                guard: self.clone(),
+            }))
+            .redirect(redirect)
+    }
+
+    fn is_allowed_host(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+}
+
+/// Guard of the service, it allows only public addresses until it is initialized
+pub fn init_guard(guard: UrlGuard) {
+    *GUARD.write().expect("url guard lock is poisoned") = Arc::new(guard);
+}
+
+pub fn guard() -> Arc<UrlGuard> {
+    GUARD.read().expect("url guard lock is poisoned").clone()
+}
+
+/// Guard of tests, mocked servers listen on loopback
+#[cfg(test)]
+pub fn local_guard() -> UrlGuard {
+    UrlGuard::new(vec!["127.0.0.1".to_string()])
+}
+
+/// Addresses which requests to instances may be sent to, see `std::net::IpAddr::is_global`
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped().or_else(|| nat64_ipv4(ip)) {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+/// IPv4 address embedded into the well-known NAT64 prefix `64:ff9b::/96`,
+/// which is translated to that address on NAT64 networks
+fn nat64_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => {
+            let [a, b] = high.to_be_bytes();
+            let [c, d] = low.to_be_bytes();
+            Some(Ipv4Addr::new(a, b, c, d))
+        }
+        _ => None,
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    // 0.0.0.0/8 is "this network", 100.64.0.0/10 is shared address space of carriers
+    let is_reserved = first == 0 || (first == 100 && (second & 0b1100_0000) == 64);
+    !(is_reserved
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast())
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    // fc00::/7 is unique local, fe80::/10 is link-local
+    let is_unique_local = (first & 0xfe00) == 0xfc00;
+    let is_link_local = (first & 0xffc0) == 0xfe80;
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || is_unique_local
+        || is_link_local)
+}
+
+/// Resolves hostnames like the system resolver, but drops non-public addresses
+/// of hosts which are not allowed explicitly
+struct PublicResolver {
+    guard: UrlGuard,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowed = self.guard.is_allowed_host(name.as_str());
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+            let addrs: Vec<SocketAddr> = addrs
+                .into_iter()
+                .filter(|addr| allowed || is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!(
+                    "host '{host}' resolves only to private, loopback or link-local addresses"
+                )
+                .into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+    use pretty_assertions::assert_eq;
+
+    fn check(guard: &UrlGuard, url: &str) -> Result<(), String> {
+        guard.check_url(&url.parse().unwrap())
+    }
+
+    #[test]
+    fn only_public_http_urls_are_allowed() {
+        let guard = UrlGuard::default();
+        assert_eq!(
+            check(&guard, "https://explorer.example.com/api/health"),
+            Ok(())
+        );
+        assert_eq!(check(&guard, "http://8.8.8.8:8545"), Ok(()));
+        assert_eq!(check(&guard, "http://[64:ff9b::808:808]/"), Ok(()));
+        for url in [
+            "http://127.0.0.1:8080/api/health",
+            "http://localhost/api/health",
+            "http://10.0.0.1",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.1.1",
+            "http://0.0.0.0",
+            "http://[::1]/",
+            "http://[fe80::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[64:ff9b::a9fe:a9fe]/",
+            "http://[64:ff9b::7f00:1]/",
+        ] {
+            assert_eq!(
+                check(&guard, url),
+                Err(format!(
+                    "url '{}' points to a private, loopback or link-local address",
+                    Url::parse(url).unwrap()
+                ))
+            );
+        }
+        assert_eq!(
+            check(&guard, "file:///etc/passwd"),
+            Err("expected http or https url, got 'file:///etc/passwd'".to_string())
+        );
+    }
+
+    #[test]
+    fn allowed_hosts_may_be_private() {
+        let guard = UrlGuard::new(vec!["127.0.0.1".to_string(), "Node.Local".to_string()]);
+        assert_eq!(check(&guard, "http://127.0.0.1:8080/api/health"), Ok(()));
+        assert_eq!(check(&guard, "http://node.local/"), Ok(()));
+        assert!(check(&guard, "http://10.0.0.1").is_err());
+        assert!(check(&guard, "file:///etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn hostnames_are_checked_when_resolved() {
+        let server = MockServer::start_async().await;
+        let health = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/api/health");
+                then.status(200);
+            })
+            .await;
+        // addresses are checked by the client itself, not only by `check_url`
+        let url = format!("http://localhost:{}/api/health", server.port());
+
+        let client = UrlGuard::default().client_builder().build().unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(err.is_connect(), "unexpected error: {err:?}");
+        health.assert_hits_async(0).await;
+
+        let client = UrlGuard::new(vec!["localhost".to_string()])
+            .client_builder()
+            .build()
+            .unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert!(response.status().is_success());
+        health.assert_hits_async(1).await;
+    }
+}
@@ -1,9 +1,13 @@
-use crate::{logic::Instance, uuid_eq};
+use super::Scope;
+use crate::{
+    logic::{deploy::Template, Deployment, Instance},
+    uuid_eq,
+};
 use scoutcloud_entity::{auth_tokens, server_specs, users};
 use sea_orm::{
     prelude::*, sea_query::Expr, ActiveModelTrait, ActiveValue::Set, ColumnTrait, QueryFilter,
 };
-use std::ops::Sub;
+use std::{ops::Sub, time::Duration};
 use thiserror::Error;
 use tonic::codegen::http::HeaderMap;
 
@@ -20,8 +24,14 @@ pub enum AuthError {
     NotFound,
     #[error("unauthorized: {0}")]
     Unauthorized(String),
+    #[error("permission denied: token doesn't have '{0}' scope")]
+    MissingScope(Scope),
     #[error("insufficient balance")]
     InsufficientBalance,
+    #[error("deployment quota exceeded: {active} of {quota} deployments are active, stop some of them first")]
+    QuotaExceeded { active: u64, quota: u64 },
+    #[error("too many requests, retry after {}s", retry_after.as_secs_f64().ceil())]
+    RateLimited { retry_after: Duration },
     #[error("internal error: {0}")]
     Internal(#[from] anyhow::Error),
     #[error("db error: {0}")]
@@ -85,6 +95,28 @@ impl UserToken {
         Ok(Self { user, token })
     }
 
+    /// Scopes stored with the token, unknown values are ignored
+    pub fn scopes(&self) -> Vec<Scope> {
+        self.token
+            .scopes
+            .iter()
+            .filter_map(|scope| scope.parse().ok())
+            .collect()
+    }
+
+    /// Checks that the token allows the action, superusers are restricted by scopes as well
+    pub fn require_scope(&self, scope: Scope) -> Result<(), AuthError> {
+        if self
+            .scopes()
+            .into_iter()
+            .any(|granted| scope.is_granted_by(granted))
+        {
+            Ok(())
+        } else {
+            Err(AuthError::MissingScope(scope))
+        }
+    }
+
     pub fn has_access_to_instance(&self, instance: &Instance) -> Result<(), AuthError> {
         if self.user.is_superuser || instance.model.creator_id == self.user.id {
             Ok(())
@@ -93,6 +125,16 @@ impl UserToken {
         }
     }
 
+    pub fn has_access_to_template(&self, template: &Template) -> Result<(), AuthError> {
+        if self.user.is_superuser || template.model.creator_id == self.user.id {
+            Ok(())
+        } else {
+            Err(AuthError::Unauthorized(
+                "no access to the template".to_string(),
+            ))
+        }
+    }
+
     pub async fn allowed_to_create_instance<C>(&self, db: &C) -> Result<(), AuthError>
     where
         C: ConnectionTrait,
@@ -112,6 +154,44 @@ impl UserToken {
         Ok(())
     }
 
+    /// Checks that one more deployment fits into the quota of the user.
+    /// Quota of the user overrides `default_quota`
+    pub async fn allowed_to_start_deployment<C>(
+        &self,
+        db: &C,
+        default_quota: u64,
+    ) -> Result<(), AuthError>
+    where
+        C: ConnectionTrait,
+    {
+        check_deployment_quota(db, &self.user, default_quota, 1).await
+    }
+
+    /// Finds id of the user by email. Only superusers have access to other users,
+    /// so `Unauthorized` is returned for others
+    pub async fn find_accessible_user_id<C>(
+        &self,
+        db: &C,
+        email: &str,
+    ) -> Result<Option<i32>, AuthError>
+    where
+        C: ConnectionTrait,
+    {
+        if email == self.user.email {
+            return Ok(Some(self.user.id));
+        }
+        if !self.user.is_superuser {
+            return Err(AuthError::Unauthorized(
+                "only superusers have access to other users".to_string(),
+            ));
+        }
+        let user = users::Entity::find()
+            .filter(users::Column::Email.eq(email))
+            .one(db)
+            .await?;
+        Ok(user.map(|user| user.id))
+    }
+
     pub async fn allowed_to_deploy_for_hours(
         &self,
         hours: u64,
@@ -132,12 +212,13 @@ impl UserToken {
         Ok(())
     }
 
-    pub async fn create<C>(db: &C, user_id: i32) -> Result<Self, AuthError>
+    pub async fn create<C>(db: &C, user_id: i32, scopes: &[Scope]) -> Result<Self, AuthError>
     where
         C: ConnectionTrait,
     {
         let token = auth_tokens::ActiveModel {
             user_id: Set(user_id),
+            scopes: Set(scopes.iter().map(|scope| scope.to_string()).collect()),
             ..Default::default()
         }
         .insert(db)
@@ -157,3 +238,97 @@ impl UserToken {
         Ok(())
     }
 }
+
+/// Checks that `new_active` more active deployments fit into the quota of the user.
+/// Quota of the user overrides `default_quota`, superusers have no quota
+pub async fn check_deployment_quota<C>(
+    db: &C,
+    user: &users::Model,
+    default_quota: u64,
+    new_active: u64,
+) -> Result<(), AuthError>
+where
+    C: ConnectionTrait,
+{
+    if user.is_superuser {
+        return Ok(());
+    }
+    let quota = user
+        .quota
+        .map(|quota| quota.max(0) as u64)
+        .unwrap_or(default_quota);
+    let active = Deployment::count_active_of_user(db, user.id).await?;
+    if active + new_active > quota {
+        return Err(AuthError::QuotaExceeded { active, quota });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{logic::DeployError, tests_utils};
+    use sea_orm::IntoActiveModel;
+
+    async fn set_quota<C: ConnectionTrait>(db: &C, user_token: &mut UserToken, quota: Option<i32>) {
+        let mut user = user_token.user.clone().into_active_model();
+        user.quota = Set(quota);
+        user_token.user = user.update(db).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn deployment_quota_works() {
+        let db = tests_utils::init::test_db("test", "deployment_quota_works").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(&conn).await.unwrap();
+        // user 2 has one created deployment, the rest are stopped or failed
+        let mut user_token = UserToken::get(conn.as_ref(), 2).await.unwrap();
+
+        // under limit
+        user_token
+            .allowed_to_start_deployment(conn.as_ref(), 2)
+            .await
+            .expect("deployment should fit into quota");
+
+        // at limit
+        let err = user_token
+            .allowed_to_start_deployment(conn.as_ref(), 1)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                AuthError::QuotaExceeded {
+                    active: 1,
+                    quota: 1
+                }
+            ),
+            "unexpected error: {err:?}"
+        );
+
+        // quota of the user overrides default one
+        set_quota(conn.as_ref(), &mut user_token, Some(2)).await;
+        user_token
+            .allowed_to_start_deployment(conn.as_ref(), 1)
+            .await
+            .expect("deployment should fit into user quota");
+        set_quota(conn.as_ref(), &mut user_token, Some(1)).await;
+        user_token
+            .allowed_to_start_deployment(conn.as_ref(), 10)
+            .await
+            .unwrap_err();
+
+        // failed deployment frees quota
+        Deployment::get(conn.as_ref(), 4)
+            .await
+            .unwrap()
+            .mark_as_error(conn.as_ref(), &DeployError::Cancelled)
+            .await
+            .unwrap();
+        user_token
+            .allowed_to_start_deployment(conn.as_ref(), 1)
+            .await
+            .expect("failed deployment should free quota");
+    }
+}
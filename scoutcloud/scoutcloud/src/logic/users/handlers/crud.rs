@@ -17,6 +17,7 @@ pub async fn get_profile(
         balance: user_token.user.balance.to_string(),
         created_at: user_token.user.created_at.to_string(),
         recent_actions,
+        webhook_url: user_token.user.webhook_url.clone(),
     };
     Ok(profile)
 }
@@ -75,7 +76,7 @@ mod test {
         scoutcloud_entity::balance_expenses::ActiveModel {
             user_id: Set(user_token.user.id),
             expense_amount: Set(Decimal::new(25, 0)),
-            deployment_id: Set(1),
+            deployment_id: Set(Some(1)),
             hours: Set(1),
             ..Default::default()
         }
@@ -1,3 +1,5 @@
 mod crud;
+mod webhook;
 
 pub use crud::get_profile;
+pub use webhook::update_webhook;
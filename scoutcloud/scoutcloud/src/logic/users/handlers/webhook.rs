@@ -0,0 +1,69 @@
+use crate::{
+    logic::{url_guard, DeployError, UserToken},
+    server::proto,
+};
+use rand::Rng;
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, DatabaseConnection, IntoActiveModel};
+
+/// Sets webhook which receives status changes of deployments of the user.
+/// New secret is generated every time, so leaked secret can be rotated by setting the same url
+pub async fn update_webhook(
+    db: &DatabaseConnection,
+    request: &proto::UpdateWebhookRequestInternal,
+    user_token: &UserToken,
+) -> Result<proto::UpdateWebhookResponseInternal, DeployError> {
+    if let Some(url) = &request.url {
+        url_guard::guard().check_url(url).map_err(|reason| {
+            DeployError::InvalidValue(format!("invalid webhook url: {reason}"))
+        })?;
+    }
+    let secret = request.url.as_ref().map(|_| generate_secret());
+    let mut model = user_token.user.clone().into_active_model();
+    model.webhook_url = Set(request.url.as_ref().map(|url| url.to_string()));
+    model.webhook_secret = Set(secret.clone());
+    let user = model.update(db).await?;
+    Ok(proto::UpdateWebhookResponseInternal {
+        url: user.webhook_url,
+        secret,
+    })
+}
+
+fn generate_secret() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn webhook_to_loopback_is_rejected() {
+        let db = tests_utils::init::test_db("test", "webhook_to_loopback_is_rejected").await;
+        let conn = db.client();
+        tests_utils::mock::insert_default_data(conn.as_ref())
+            .await
+            .unwrap();
+        let user_token = UserToken::get(conn.as_ref(), 1).await.unwrap();
+
+        // 127.0.0.1 is avoided, since tests of tasks allow it in the global guard
+        for url in [
+            "http://localhost:8080/hook",
+            "http://169.254.169.254/latest",
+        ] {
+            let request = proto::UpdateWebhookRequestInternal {
+                url: Some(url.parse().unwrap()),
+            };
+            let err = update_webhook(conn.as_ref(), &request, &user_token)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, DeployError::InvalidValue(_)),
+                "unexpected error: {err:?}"
+            );
+        }
+        let user_token = UserToken::get(conn.as_ref(), 1).await.unwrap();
+        assert_eq!(user_token.user.webhook_url, None);
+    }
+}
@@ -1,6 +1,10 @@
 mod auth;
 mod handlers;
+mod rate_limit;
+mod scope;
 pub mod user_actions;
 
 pub use auth::*;
 pub use handlers::*;
+pub use rate_limit::*;
+pub use scope::*;
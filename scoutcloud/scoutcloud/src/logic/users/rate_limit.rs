@@ -0,0 +1,150 @@
+use super::{AuthError, Scope, UserToken};
+use crate::server::{BucketSettings, RateLimitSettings};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Storage of token buckets. State is kept in memory of the process by default,
+/// shared storage lets several replicas of the server use the same limits
+#[async_trait::async_trait]
+pub trait RateLimitStore: Debug + Send + Sync {
+    /// Takes single token from the bucket `key`.
+    /// Returns time until the next token is available if the bucket is empty
+    async fn try_acquire(&self, key: &str, limit: &BucketSettings) -> Result<(), Duration>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl InMemoryRateLimitStore {
+    fn try_acquire_at(
+        &self,
+        key: &str,
+        limit: &BucketSettings,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let capacity = f64::from(limit.burst.max(1));
+        let per_second = f64::from(limit.per_minute.max(1)) / 60.0;
+        let mut buckets = self.buckets.lock().expect("rate limit lock is poisoned");
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * per_second).min(capacity);
+        bucket.updated_at = bucket.updated_at.max(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn try_acquire(&self, key: &str, limit: &BucketSettings) -> Result<(), Duration> {
+        self.try_acquire_at(key, limit, Instant::now())
+    }
+}
+
+/// Throttles requests of every api key. Requests which require write scope
+/// take tokens from a separate stricter bucket, so reads don't use up the budget of writes
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self::with_store(settings, Arc::new(InMemoryRateLimitStore::default()))
+    }
+
+    pub fn with_store(settings: RateLimitSettings, store: Arc<dyn RateLimitStore>) -> Self {
+        Self { settings, store }
+    }
+
+    pub async fn check(&self, user_token: &UserToken, scope: Scope) -> Result<(), AuthError> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+        let (kind, limit) = if scope.is_write() {
+            ("write", &self.settings.write)
+        } else {
+            ("read", &self.settings.read)
+        };
+        let key = format!("{}:{kind}", user_token.token.id);
+        self.store
+            .try_acquire(&key, limit)
+            .await
+            .map_err(|retry_after| AuthError::RateLimited { retry_after })
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const LIMIT: BucketSettings = BucketSettings {
+        burst: 3,
+        per_minute: 60,
+    };
+
+    #[test]
+    fn exceeding_bucket_is_rejected() {
+        let store = InMemoryRateLimitStore::default();
+        let now = Instant::now();
+        for _ in 0..LIMIT.burst {
+            assert_eq!(store.try_acquire_at("1:write", &LIMIT, now), Ok(()));
+        }
+        assert_eq!(
+            store.try_acquire_at("1:write", &LIMIT, now),
+            Err(Duration::from_secs(1))
+        );
+        // buckets of other keys are not affected
+        assert_eq!(store.try_acquire_at("1:read", &LIMIT, now), Ok(()));
+        assert_eq!(store.try_acquire_at("2:write", &LIMIT, now), Ok(()));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let store = InMemoryRateLimitStore::default();
+        let start = Instant::now();
+        for _ in 0..LIMIT.burst {
+            store.try_acquire_at("1:write", &LIMIT, start).unwrap();
+        }
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(store.try_acquire_at("1:write", &LIMIT, later), Ok(()));
+        assert_eq!(
+            store.try_acquire_at("1:write", &LIMIT, later),
+            Err(Duration::from_millis(500))
+        );
+
+        // bucket is not filled above the burst
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..LIMIT.burst {
+            assert_eq!(store.try_acquire_at("1:write", &LIMIT, much_later), Ok(()));
+        }
+        assert!(store.try_acquire_at("1:write", &LIMIT, much_later).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::{fmt, str::FromStr};
+
+/// Permission carried by an api key. Write scope of a resource grants read access to it as well
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    InstancesRead,
+    InstancesWrite,
+    DeploymentsRead,
+    DeploymentsWrite,
+    UsersRead,
+    UsersWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [
+        Scope::InstancesRead,
+        Scope::InstancesWrite,
+        Scope::DeploymentsRead,
+        Scope::DeploymentsWrite,
+        Scope::UsersRead,
+        Scope::UsersWrite,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::InstancesRead => "instances:read",
+            Scope::InstancesWrite => "instances:write",
+            Scope::DeploymentsRead => "deployments:read",
+            Scope::DeploymentsWrite => "deployments:write",
+            Scope::UsersRead => "users:read",
+            Scope::UsersWrite => "users:write",
+        }
+    }
+
+    /// Write scopes are required by requests which change something
+    pub fn is_write(&self) -> bool {
+        self.read_scope().is_some()
+    }
+
+    /// Whether the key with `granted` scope may perform actions which require `self`
+    pub fn is_granted_by(&self, granted: Scope) -> bool {
+        granted == *self || granted.read_scope() == Some(*self)
+    }
+
+    fn read_scope(&self) -> Option<Scope> {
+        match self {
+            Scope::InstancesWrite => Some(Scope::InstancesRead),
+            Scope::DeploymentsWrite => Some(Scope::DeploymentsRead),
+            Scope::UsersWrite => Some(Scope::UsersRead),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("unknown scope '{s}'"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn write_scope_grants_read_access() {
+        assert!(Scope::DeploymentsRead.is_granted_by(Scope::DeploymentsWrite));
+        assert!(!Scope::DeploymentsWrite.is_granted_by(Scope::DeploymentsRead));
+        assert!(!Scope::InstancesRead.is_granted_by(Scope::DeploymentsWrite));
+        for scope in Scope::ALL {
+            assert_eq!(scope.as_str().parse::<Scope>(), Ok(scope));
+        }
+        assert!("deployments:admin".parse::<Scope>().is_err());
+    }
+}
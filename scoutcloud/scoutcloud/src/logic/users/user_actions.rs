@@ -1,5 +1,6 @@
 use crate::logic::{Deployment, Instance, UserToken};
-use sea_orm::{ActiveModelTrait, ActiveValue::Set, ConnectionTrait, NotSet};
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::{ActiveEnum, ActiveModelTrait, ActiveValue::Set, ConnectionTrait, NotSet};
 use serde::Serialize;
 use serde_json::json;
 use serde_plain::derive_display_from_serialize;
@@ -9,10 +10,22 @@ use std::fmt::Display;
 #[serde(rename_all = "snake_case")]
 pub enum UserActionType {
     CreateInstance,
+    CloneInstance,
     UpdateInstanceConfig,
     UpdateInstanceConfigPartial,
     StartInstance,
     StopInstance,
+    RestartInstance,
+    RetryDeployment,
+    CancelInstance,
+    UpdateAutoRedeploy,
+    DeleteDeployment,
+    RestoreDeployment,
+    UpdateDeploymentLabels,
+    TransferDeployment,
+    UpdateJobsPause,
+    ReconcileDeployments,
+    ForceSetStatus,
 }
 derive_display_from_serialize!(UserActionType);
 
@@ -66,6 +79,28 @@ pub(crate) async fn log_create_instance(
     Ok(())
 }
 
+pub(crate) async fn log_clone_instance(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    source: &Instance,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::CloneInstance,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "source_instance_uuid": source.model.external_id,
+            "config": instance.user_config_raw(),
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
 pub(crate) async fn log_update_config(
     db: &impl ConnectionTrait,
     user_token: &UserToken,
@@ -136,3 +171,233 @@ pub(crate) async fn log_stop_instance(
     .await?;
     Ok(())
 }
+
+pub(crate) async fn log_restart_instance(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::RestartInstance,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_cancel_instance(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::CancelInstance,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_update_auto_redeploy(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    enabled: bool,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::UpdateAutoRedeploy,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "enabled": enabled,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_retry_deployment(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::RetryDeployment,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_delete_deployment(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::DeleteDeployment,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_restore_deployment(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::RestoreDeployment,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_update_deployment_labels(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::UpdateDeploymentLabels,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+            "labels": deployment.model.labels,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_transfer_deployment(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+    from_user_id: i32,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::TransferDeployment,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+            "from_user_id": from_user_id,
+            "to_user_id": instance.model.creator_id,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_force_set_status(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    instance: &Instance,
+    deployment: &Deployment,
+    old_status: &DeploymentStatusType,
+    reason: &str,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::ForceSetStatus,
+        Some(instance.model.id),
+        Some(json!({
+            "instance_slug": instance.model.slug,
+            "instance_uuid": instance.model.external_id,
+            "deployment_uuid": deployment.model.external_id,
+            "old_status": old_status.to_value(),
+            "new_status": deployment.model.status.to_value(),
+            "reason": reason,
+        })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_update_jobs_pause(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    paused: bool,
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::UpdateJobsPause,
+        None,
+        Some(json!({ "paused": paused })),
+    )
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn log_reconcile_deployments(
+    db: &impl ConnectionTrait,
+    user_token: &UserToken,
+    fixed_deployment_uuids: &[&str],
+) -> Result<(), sea_orm::DbErr> {
+    log_user_action(
+        db,
+        user_token,
+        UserActionType::ReconcileDeployments,
+        None,
+        Some(json!({ "fixed_deployment_uuids": fixed_deployment_uuids })),
+    )
+    .await?;
+    Ok(())
+}
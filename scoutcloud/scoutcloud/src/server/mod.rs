@@ -1,6 +1,7 @@
 mod run;
 mod services;
 mod settings;
+mod telemetry;
 
 pub use run::run;
 pub use scoutcloud_proto::blockscout::scoutcloud::v1 as proto;
@@ -1,27 +1,41 @@
 use crate::{
-    logic::{jobs::JobsRunner, GithubClient},
+    logic::{
+        ci,
+        image::{self, ImageRegistries},
+        jobs::JobsRunner,
+        notifications::{self, SlackNotifier},
+        preset::{self, InstancePresets},
+        secrets::{self, AesGcmCipher, SecretCipher},
+        url_guard::{self, UrlGuard},
+        users::RateLimiter,
+        GithubClient,
+    },
     server::{
         proto::{
             health_actix::route_health, health_server::HealthServer,
             scoutcloud_actix::route_scoutcloud,
         },
-        services::{HealthService, ScoutcloudService},
+        services::{
+            route_github_webhook, route_probes, GithubWebhookService, HealthService, ProbesService,
+            ScoutcloudService,
+        },
         settings::Settings,
+        telemetry,
     },
 };
 use blockscout_service_launcher::{database, launcher, launcher::LaunchSettings};
 use migration::Migrator;
 use scoutcloud_proto::blockscout::scoutcloud::v1::scoutcloud_server::ScoutcloudServer;
-use sea_orm::ConnectOptions;
 use std::sync::Arc;
-use tracing::Level;
 
 const SERVICE_NAME: &str = "scoutcloud";
 
 #[derive(Clone)]
 struct Router {
     health: Arc<HealthService>,
+    probes: Arc<ProbesService>,
     scoutcloud: Arc<ScoutcloudService>,
+    github_webhook: Option<Arc<GithubWebhookService>>,
 }
 
 impl Router {
@@ -36,27 +50,22 @@ impl launcher::HttpRouter for Router {
     fn register_routes(&self, service_config: &mut actix_web::web::ServiceConfig) {
         service_config
             .configure(|config| route_health(config, self.health.clone()))
+            .configure(|config| route_probes(config, self.probes.clone()))
             .configure(|config| route_scoutcloud(config, self.scoutcloud.clone()));
+        if let Some(github_webhook) = &self.github_webhook {
+            service_config.configure(|config| route_github_webhook(config, github_webhook.clone()));
+        }
     }
 }
 
 pub async fn run(settings: Settings) -> Result<(), anyhow::Error> {
-    blockscout_service_launcher::tracing::init_logs(
-        SERVICE_NAME,
-        &settings.tracing,
-        &settings.jaeger,
-        Some(tracing_subscriber::filter::filter_fn(move |metadata| {
-            ["sqlx::query"]
-                .iter()
-                .all(|&target| metadata.level().ge(&Level::INFO) && metadata.target() != target)
-        })),
-    )?;
+    telemetry::init_tracing(SERVICE_NAME, &settings)?;
 
     let health = Arc::new(HealthService::default());
 
-    let connect_options = ConnectOptions::new(settings.database.connect.clone().url())
-        .sqlx_logging_level(::tracing::log::LevelFilter::Debug)
-        .to_owned();
+    let connect_options = settings
+        .database_pool
+        .connect_options(settings.database.connect.clone().url());
 
     let db_connection = Arc::new(
         database::initialize_postgres::<Migrator>(
@@ -67,18 +76,71 @@ pub async fn run(settings: Settings) -> Result<(), anyhow::Error> {
         .await?,
     );
 
+    let cipher = match &settings.secrets {
+        Some(secrets_settings) => {
+            let cipher: Arc<dyn SecretCipher> =
+                Arc::new(AesGcmCipher::from_settings(secrets_settings)?);
+            Some(cipher)
+        }
+        None => None,
+    };
+    secrets::init_cipher(cipher.clone());
+    secrets::init_store(secrets::store_from_settings(
+        &settings.secret_store,
+        db_connection.clone(),
+        cipher,
+    )?);
+    url_guard::init_guard(UrlGuard::new(
+        settings.instances.allowed_private_hosts.clone(),
+    ));
+    image::init_registries(ImageRegistries::new(
+        settings.instances.allowed_image_registries.clone(),
+    ));
+    // presets are validated against the url guard and image registries above
+    preset::init_presets(InstancePresets::from_settings(&settings.instances.presets)?);
+    if let Some(slack_settings) = &settings.slack {
+        let notifier = SlackNotifier::from_settings(slack_settings)?;
+        notifications::init_notifier(Some(Arc::new(notifier)));
+    }
     let github = Arc::new(GithubClient::from_settings(&settings.github)?);
+    let ci = ci::backend_from_settings(&settings.ci, github.clone())?;
     let runner = JobsRunner::default_start(
         db_connection.clone(),
         github.clone(),
+        ci.clone(),
+        &settings.jobs,
         &settings.database.connect.url(),
+        settings.database_pool.max_connections,
     )
     .await?;
     let runner = Arc::new(runner);
 
-    let scoutcloud = Arc::new(ScoutcloudService::new(db_connection, github, runner));
+    let github_webhook = settings
+        .github
+        .webhook_secret
+        .clone()
+        .map(|secret| Arc::new(GithubWebhookService::new(db_connection.clone(), secret)));
+    let probes = Arc::new(ProbesService::new(
+        db_connection.clone(),
+        github.clone(),
+        runner.pause_state().await,
+    ));
+    let scoutcloud = Arc::new(ScoutcloudService::new(
+        db_connection,
+        github,
+        ci,
+        runner.clone(),
+        settings.quota.clone(),
+        settings.instances.clone(),
+        RateLimiter::new(settings.rate_limit.clone()),
+    ));
 
-    let router = Router { health, scoutcloud };
+    let router = Router {
+        health,
+        probes,
+        scoutcloud,
+        github_webhook,
+    };
 
     let grpc_router = router.grpc_router();
     let http_router = router;
@@ -89,5 +151,32 @@ pub async fn run(settings: Settings) -> Result<(), anyhow::Error> {
         metrics: settings.metrics,
     };
 
-    launcher::launch(&launch_settings, http_router, grpc_router).await
+    let result = tokio::select! {
+        result = launcher::launch(&launch_settings, http_router, grpc_router) => result,
+        _ = shutdown_signal() => {
+            tracing::info!("received shutdown signal");
+            Ok(())
+        }
+    };
+    runner.shutdown(settings.jobs.shutdown_grace_period).await;
+    telemetry::shutdown_tracing();
+    result
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = ctrl_c.await;
+    }
 }
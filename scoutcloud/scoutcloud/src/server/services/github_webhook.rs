@@ -0,0 +1,65 @@
+use crate::logic::{
+    deploy,
+    github::webhook::{
+        verify_signature, WorkflowRunEvent, EVENT_HEADER, SIGNATURE_HEADER, WORKFLOW_RUN_EVENT,
+    },
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sea_orm::DatabaseConnection;
+use std::sync::Arc;
+
+const GITHUB_WEBHOOK_PATH: &str = "/api/v1/github/webhook";
+
+/// Receives `workflow_run` events of github, so deployments don't wait for the next poll
+pub struct GithubWebhookService {
+    db: Arc<DatabaseConnection>,
+    secret: String,
+}
+
+impl GithubWebhookService {
+    pub fn new(db: Arc<DatabaseConnection>, secret: String) -> Self {
+        Self { db, secret }
+    }
+}
+
+pub fn route_github_webhook(config: &mut web::ServiceConfig, service: Arc<GithubWebhookService>) {
+    config
+        .app_data(web::Data::from(service))
+        .route(GITHUB_WEBHOOK_PATH, web::post().to(github_webhook));
+}
+
+async fn github_webhook(
+    service: web::Data<GithubWebhookService>,
+    request: HttpRequest,
+    body: web::Bytes,
+) -> HttpResponse {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    if let Err(err) = verify_signature(&service.secret, &body, header(SIGNATURE_HEADER)) {
+        tracing::warn!("rejected github webhook: {}", err);
+        return HttpResponse::Unauthorized().body(err.to_string());
+    }
+    // github sends `ping` event after webhook creation, and we don't subscribe to others
+    if header(EVENT_HEADER) != Some(WORKFLOW_RUN_EVENT) {
+        return HttpResponse::NoContent().finish();
+    }
+    let event = match WorkflowRunEvent::parse(&body) {
+        Ok(event) => event,
+        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+    };
+    match deploy::handle_workflow_run_event(service.db.as_ref(), &event).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(err) => {
+            tracing::error!(
+                run_id =? event.workflow_run.id,
+                "failed to handle workflow run event: {:?}",
+                err
+            );
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,5 +1,9 @@
+mod github_webhook;
 mod health;
+mod probes;
 mod scoutcloud;
 
+pub use github_webhook::{route_github_webhook, GithubWebhookService};
 pub use health::HealthService;
+pub use probes::{route_probes, ProbesService};
 pub use scoutcloud::ScoutcloudService;
@@ -1,32 +1,54 @@
 use crate::{
     logic,
     logic::{
+        ci::CiBackend,
+        deploy::DEFAULT_LOGS_POLL_INTERVAL,
         jobs::JobsRunner,
-        users::{AuthError, UserToken},
+        users::{AuthError, RateLimiter, Scope, UserToken},
         ConfigError, DeployError, GithubClient,
     },
-    server::proto::{scoutcloud_server::Scoutcloud, *},
+    server::{
+        proto::{scoutcloud_server::Scoutcloud, *},
+        InstancesSettings, QuotaSettings,
+    },
 };
 use convert_trait::TryConvert;
 
 use sea_orm::{ConnectionTrait, DatabaseConnection};
-use std::sync::Arc;
+use std::{pin::Pin, sync::Arc};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 
 use tonic::{Code, Request, Response, Status};
 
 pub struct ScoutcloudService {
     db: Arc<DatabaseConnection>,
     github: Arc<GithubClient>,
+    ci: Arc<dyn CiBackend>,
     jobs: Arc<JobsRunner>,
+    quota: QuotaSettings,
+    instances: InstancesSettings,
+    rate_limiter: RateLimiter,
 }
 
 impl ScoutcloudService {
     pub fn new(
         db: Arc<DatabaseConnection>,
         github: Arc<GithubClient>,
+        ci: Arc<dyn CiBackend>,
         jobs: Arc<JobsRunner>,
+        quota: QuotaSettings,
+        instances: InstancesSettings,
+        rate_limiter: RateLimiter,
     ) -> Self {
-        Self { db, github, jobs }
+        Self {
+            db,
+            github,
+            ci,
+            jobs,
+            quota,
+            instances,
+            rate_limiter,
+        }
     }
 }
 
@@ -41,14 +63,24 @@ macro_rules! get_config {
     };
 }
 
+type DeploymentLogLinesStream =
+    Pin<Box<dyn Stream<Item = Result<DeploymentLogLine, Status>> + Send + 'static>>;
+
 #[async_trait::async_trait]
 impl Scoutcloud for ScoutcloudService {
+    type StreamDeploymentLogsStream = DeploymentLogLinesStream;
+
     async fn create_instance(
         &self,
         request: Request<CreateInstanceRequest>,
     ) -> Result<Response<CreateInstanceResponse>, Status> {
-        let (request, user_token): (CreateInstanceRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+        let (request, user_token): (CreateInstanceRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesWrite,
+        )
+        .await?;
         let config = get_config!(&request)?;
         let result = logic::deploy::create_instance(
             self.db.as_ref(),
@@ -64,12 +96,160 @@ impl Scoutcloud for ScoutcloudService {
         ))
     }
 
+    async fn clone_instance(
+        &self,
+        request: Request<CloneInstanceRequest>,
+    ) -> Result<Response<Instance>, Status> {
+        let (request, user_token): (CloneInstanceRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesWrite,
+        )
+        .await?;
+        let internal = logic::deploy::clone_instance(
+            self.db.as_ref(),
+            self.github.as_ref(),
+            &request,
+            self.instances.clone_secrets,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Instance::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn export_instance(
+        &self,
+        request: Request<ExportInstanceRequest>,
+    ) -> Result<Response<ExportInstanceResponse>, Status> {
+        let (request, user_token): (ExportInstanceRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesRead,
+        )
+        .await?;
+        let internal =
+            logic::deploy::export_instance(self.db.as_ref(), &request.instance_id, &user_token)
+                .await
+                .map_err(map_deploy_error)?;
+        let result = ExportInstanceResponse::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn import_instance(
+        &self,
+        request: Request<ImportInstanceRequest>,
+    ) -> Result<Response<Instance>, Status> {
+        let (request, user_token): (ImportInstanceRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesWrite,
+        )
+        .await?;
+        let internal = logic::deploy::import_instance(
+            self.db.as_ref(),
+            self.github.as_ref(),
+            &request,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Instance::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn create_instance_from_template(
+        &self,
+        request: Request<CreateInstanceFromTemplateRequest>,
+    ) -> Result<Response<Instance>, Status> {
+        let (request, user_token): (CreateInstanceFromTemplateRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesWrite,
+            )
+            .await?;
+        let internal = logic::deploy::create_instance_from_template(
+            self.db.as_ref(),
+            self.github.as_ref(),
+            &request,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Instance::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn update_instance_template(
+        &self,
+        request: Request<UpdateInstanceTemplateRequest>,
+    ) -> Result<Response<Instance>, Status> {
+        let (request, user_token): (UpdateInstanceTemplateRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesWrite,
+            )
+            .await?;
+        let internal = logic::deploy::update_instance_template(
+            self.db.as_ref(),
+            self.github.as_ref(),
+            &request,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Instance::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn deploy_preset(
+        &self,
+        request: Request<DeployPresetRequest>,
+    ) -> Result<Response<Instance>, Status> {
+        let (request, user_token): (DeployPresetRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesWrite,
+        )
+        .await?;
+        // the instance is deployed right away
+        user_token
+            .require_scope(Scope::DeploymentsWrite)
+            .map_err(map_auth_error)?;
+        let internal = logic::deploy::deploy_preset(
+            self.db.as_ref(),
+            self.github.as_ref(),
+            self.jobs.as_ref(),
+            &request,
+            self.quota.max_active_deployments_per_user,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Instance::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
     async fn update_config(
         &self,
         request: Request<UpdateConfigRequest>,
     ) -> Result<Response<UpdateConfigResponse>, Status> {
-        let (request, user_token): (UpdateConfigRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+        let (request, user_token): (UpdateConfigRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesWrite,
+        )
+        .await?;
         let config = get_config!(&request)?;
         let updated_config = logic::deploy::update_instance_config(
             self.db.as_ref(),
@@ -93,7 +273,13 @@ impl Scoutcloud for ScoutcloudService {
         request: Request<UpdateConfigPartialRequest>,
     ) -> Result<Response<UpdateConfigResponse>, Status> {
         let (request, user_token): (UpdateConfigPartialRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesWrite,
+            )
+            .await?;
         let config = get_config!(&request)?;
         let updated_config = logic::deploy::update_instance_config_partial(
             self.db.as_ref(),
@@ -116,30 +302,83 @@ impl Scoutcloud for ScoutcloudService {
         &self,
         request: Request<UpdateInstanceStatusRequest>,
     ) -> Result<Response<UpdateInstanceStatusResponse>, Status> {
+        let idempotency_key = request
+            .metadata()
+            .get(logic::deploy::IDEMPOTENCY_KEY_HEADER)
+            .map(|value| value.to_str().map(str::to_string))
+            .transpose()
+            .map_err(|_| Status::invalid_argument("invalid idempotency-key header"))?;
         let (request, user_token): (UpdateInstanceStatusRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
 
-        let result = logic::deploy::update_instance_status(
+        let result = if request.dry_run {
+            logic::deploy::dry_run_instance_status(
+                self.db.as_ref(),
+                self.github.as_ref(),
+                &request,
+                self.quota.max_active_deployments_per_user,
+                &user_token,
+            )
+            .await
+        } else {
+            logic::deploy::update_instance_status(
+                self.db.as_ref(),
+                self.jobs.as_ref(),
+                &request,
+                idempotency_key.as_deref(),
+                self.quota.max_active_deployments_per_user,
+                &user_token,
+            )
+            .await
+        }
+        .map_err(map_deploy_error)?;
+
+        Ok(Response::new(
+            UpdateInstanceStatusResponse::try_convert(result).map_err(map_convert_error)?,
+        ))
+    }
+
+    async fn update_auto_redeploy(
+        &self,
+        request: Request<UpdateAutoRedeployRequest>,
+    ) -> Result<Response<Instance>, Status> {
+        let (request, user_token): (UpdateAutoRedeployRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesWrite,
+            )
+            .await?;
+        let internal = logic::deploy::update_auto_redeploy(
             self.db.as_ref(),
-            self.jobs.as_ref(),
             &request.instance_id,
-            &request.action,
+            request.enabled,
             &user_token,
         )
         .await
         .map_err(map_deploy_error)?;
-
-        Ok(Response::new(
-            UpdateInstanceStatusResponse::try_convert(result).map_err(map_convert_error)?,
-        ))
+        let result = Instance::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
     }
 
     async fn get_instance(
         &self,
         request: Request<GetInstanceRequest>,
     ) -> Result<Response<Instance>, Status> {
-        let (request, user_token): (GetInstanceRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+        let (request, user_token): (GetInstanceRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesRead,
+        )
+        .await?;
         let internal =
             logic::deploy::get_instance(self.db.as_ref(), &request.instance_id, &user_token)
                 .await
@@ -152,8 +391,13 @@ impl Scoutcloud for ScoutcloudService {
         &self,
         request: Request<ListInstancesRequest>,
     ) -> Result<Response<ListInstancesResponse>, Status> {
-        let (_, user_token): (ListInstancesRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+        let (_, user_token): (ListInstancesRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesRead,
+        )
+        .await?;
         let items = logic::deploy::list_instances(self.db.as_ref(), &user_token)
             .await
             .map_err(map_deploy_error)?;
@@ -166,16 +410,109 @@ impl Scoutcloud for ScoutcloudService {
             .map(Response::new)
     }
 
-    async fn get_deployment(
+    async fn validate_all_instances(
         &self,
-        request: Request<GetDeploymentRequest>,
-    ) -> Result<Response<Deployment>, Status> {
-        let (request, user_token): (GetDeploymentRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+        request: Request<ValidateAllInstancesRequest>,
+    ) -> Result<Response<ValidateAllInstancesResponse>, Status> {
+        let (request, user_token): (ValidateAllInstancesRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesRead,
+            )
+            .await?;
+        let result = logic::deploy::validate_all_instances(self.db.as_ref(), &request, &user_token)
+            .await
+            .map_err(map_deploy_error)?;
+        Ok(Response::new(
+            ValidateAllInstancesResponse::try_convert(result).map_err(map_convert_error)?,
+        ))
+    }
+
+    async fn create_config_template(
+        &self,
+        request: Request<CreateConfigTemplateRequest>,
+    ) -> Result<Response<ConfigTemplate>, Status> {
+        let (request, user_token): (CreateConfigTemplateRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesWrite,
+            )
+            .await?;
         let internal =
-            logic::deploy::get_deployment(self.db.as_ref(), &request.deployment_id, &user_token)
+            logic::deploy::create_config_template(self.db.as_ref(), &request, &user_token)
                 .await
                 .map_err(map_deploy_error)?;
+        let result = ConfigTemplate::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn update_config_template(
+        &self,
+        request: Request<UpdateConfigTemplateRequest>,
+    ) -> Result<Response<ConfigTemplate>, Status> {
+        let (request, user_token): (UpdateConfigTemplateRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesWrite,
+            )
+            .await?;
+        let internal =
+            logic::deploy::update_config_template(self.db.as_ref(), &request, &user_token)
+                .await
+                .map_err(map_deploy_error)?;
+        let result = ConfigTemplate::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn list_config_templates(
+        &self,
+        request: Request<ListConfigTemplatesRequest>,
+    ) -> Result<Response<ListConfigTemplatesResponse>, Status> {
+        let (_, user_token): (ListConfigTemplatesRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::InstancesRead,
+        )
+        .await?;
+        let items = logic::deploy::list_config_templates(self.db.as_ref(), &user_token)
+            .await
+            .map_err(map_deploy_error)?;
+
+        items
+            .into_iter()
+            .map(|internal| ConfigTemplate::try_convert(internal).map_err(map_convert_error))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|items| ListConfigTemplatesResponse { items })
+            .map(Response::new)
+    }
+
+    async fn get_deployment(
+        &self,
+        request: Request<GetDeploymentRequest>,
+    ) -> Result<Response<Deployment>, Status> {
+        let (request, user_token): (GetDeploymentRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::DeploymentsRead,
+        )
+        .await?;
+        let internal = logic::deploy::get_deployment(
+            self.db.as_ref(),
+            self.jobs.as_ref(),
+            self.ci.as_ref(),
+            &request.deployment_id,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
         let result = Deployment::try_convert(internal).map_err(map_convert_error)?;
         Ok(Response::new(result))
     }
@@ -185,9 +522,16 @@ impl Scoutcloud for ScoutcloudService {
         request: Request<GetCurrentDeploymentRequest>,
     ) -> Result<Response<Deployment>, Status> {
         let (request, user_token): (GetCurrentDeploymentRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsRead,
+            )
+            .await?;
         let internal = logic::deploy::get_current_deployment(
             self.db.as_ref(),
+            self.jobs.as_ref(),
             &request.instance_id,
             &user_token,
         )
@@ -202,11 +546,29 @@ impl Scoutcloud for ScoutcloudService {
         request: Request<ListDeploymentsRequest>,
     ) -> Result<Response<ListDeploymentsResponse>, Status> {
         let (request, user_token): (ListDeploymentsRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
-        let items =
-            logic::deploy::list_deployments(self.db.as_ref(), &request.instance_id, &user_token)
-                .await
-                .map_err(map_deploy_error)?;
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsRead,
+            )
+            .await?;
+        let time = logic::deploy::DeploymentsTimeFilter::parse(
+            request.created_after.as_deref(),
+            request.created_before.as_deref(),
+            request.updated_after.as_deref(),
+        )
+        .map_err(map_deploy_error)?;
+        let items = logic::deploy::list_deployments(
+            self.db.as_ref(),
+            &request.instance_id,
+            request.include_deleted.unwrap_or_default(),
+            request.label_selector.as_deref(),
+            &time,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
 
         items
             .into_iter()
@@ -216,23 +578,449 @@ impl Scoutcloud for ScoutcloudService {
             .map(Response::new)
     }
 
+    async fn list_all_deployments(
+        &self,
+        request: Request<ListAllDeploymentsRequest>,
+    ) -> Result<Response<ListAllDeploymentsResponse>, Status> {
+        let (request, user_token): (ListAllDeploymentsRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsRead,
+            )
+            .await?;
+        let result = logic::deploy::list_all_deployments(self.db.as_ref(), &request, &user_token)
+            .await
+            .map_err(map_deploy_error)?;
+        Ok(Response::new(
+            ListAllDeploymentsResponse::try_convert(result).map_err(map_convert_error)?,
+        ))
+    }
+
+    async fn batch_stop(
+        &self,
+        request: Request<BatchStopRequest>,
+    ) -> Result<Response<BatchStopResponse>, Status> {
+        let (request, user_token): (BatchStopRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::DeploymentsWrite,
+        )
+        .await?;
+        let result =
+            logic::deploy::batch_stop(self.db.as_ref(), self.jobs.as_ref(), &request, &user_token)
+                .await
+                .map_err(map_deploy_error)?;
+        Ok(Response::new(
+            BatchStopResponse::try_convert(result).map_err(map_convert_error)?,
+        ))
+    }
+
+    async fn delete_deployment(
+        &self,
+        request: Request<DeleteDeploymentRequest>,
+    ) -> Result<Response<Deployment>, Status> {
+        let (request, user_token): (DeleteDeploymentRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
+        let internal =
+            logic::deploy::delete_deployment(self.db.as_ref(), &request.deployment_id, &user_token)
+                .await
+                .map_err(map_deploy_error)?;
+        let result = Deployment::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn restore_deployment(
+        &self,
+        request: Request<RestoreDeploymentRequest>,
+    ) -> Result<Response<Deployment>, Status> {
+        let (request, user_token): (RestoreDeploymentRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
+        let internal = logic::deploy::restore_deployment(
+            self.db.as_ref(),
+            &request.deployment_id,
+            self.instances.restore_grace_period,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Deployment::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn retry_deployment(
+        &self,
+        request: Request<RetryDeploymentRequest>,
+    ) -> Result<Response<Deployment>, Status> {
+        let (request, user_token): (RetryDeploymentRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
+        let internal = logic::deploy::retry_deployment(
+            self.db.as_ref(),
+            self.jobs.as_ref(),
+            &request.deployment_id,
+            self.quota.max_active_deployments_per_user,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Deployment::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn update_deployment_labels(
+        &self,
+        request: Request<UpdateDeploymentLabelsRequest>,
+    ) -> Result<Response<Deployment>, Status> {
+        let (request, user_token): (UpdateDeploymentLabelsRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
+        let internal = logic::deploy::update_deployment_labels(
+            self.db.as_ref(),
+            &request.deployment_id,
+            &request.labels,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Deployment::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn transfer_deployment(
+        &self,
+        request: Request<TransferDeploymentRequest>,
+    ) -> Result<Response<Deployment>, Status> {
+        let (request, user_token): (TransferDeploymentRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
+        let internal = logic::deploy::transfer_deployment(
+            self.db.as_ref(),
+            &request.deployment_id,
+            &request.new_owner_email,
+            self.quota.max_active_deployments_per_user,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Deployment::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn force_set_status(
+        &self,
+        request: Request<ForceSetStatusRequest>,
+    ) -> Result<Response<Deployment>, Status> {
+        let (request, user_token): (ForceSetStatusRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::DeploymentsWrite,
+        )
+        .await?;
+        let internal = logic::deploy::force_set_status(
+            self.db.as_ref(),
+            &request.deployment_id,
+            request.status,
+            &request.reason,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = Deployment::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn get_deployment_logs(
+        &self,
+        request: Request<GetDeploymentLogsRequest>,
+    ) -> Result<Response<DeploymentLogs>, Status> {
+        let (request, user_token): (GetDeploymentLogsRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsRead,
+            )
+            .await?;
+        let internal = logic::deploy::get_deployment_logs(
+            self.db.as_ref(),
+            &request.deployment_id,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = DeploymentLogs::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn stream_deployment_logs(
+        &self,
+        request: Request<StreamDeploymentLogsRequest>,
+    ) -> Result<Response<Self::StreamDeploymentLogsStream>, Status> {
+        let (request, user_token): (StreamDeploymentLogsRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsRead,
+            )
+            .await?;
+        let lines = logic::deploy::stream_deployment_logs(
+            self.db.clone(),
+            self.ci.clone(),
+            &request.deployment_id,
+            &user_token,
+            DEFAULT_LOGS_POLL_INTERVAL,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        // polling of logs stops once the client disconnects and the stream is dropped
+        let stream = ReceiverStream::new(lines).map(|line| {
+            line.map(|line| DeploymentLogLine {
+                run_id: line.run_id.to_string(),
+                line_number: line.number,
+                text: line.text,
+            })
+            .map_err(map_deploy_error)
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_log_tail(
+        &self,
+        request: Request<GetLogTailRequest>,
+    ) -> Result<Response<DeploymentLogTail>, Status> {
+        let (request, user_token): (GetLogTailRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::DeploymentsRead,
+        )
+        .await?;
+        let internal =
+            logic::deploy::get_log_tail(self.db.as_ref(), &request.deployment_id, &user_token)
+                .await
+                .map_err(map_deploy_error)?;
+        let result = DeploymentLogTail::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn get_deployment_status_history(
+        &self,
+        request: Request<GetDeploymentStatusHistoryRequest>,
+    ) -> Result<Response<DeploymentStatusHistory>, Status> {
+        let (request, user_token): (GetDeploymentStatusHistoryRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsRead,
+            )
+            .await?;
+        let internal = logic::deploy::get_deployment_status_history(
+            self.db.as_ref(),
+            &request.deployment_id,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = DeploymentStatusHistory::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn diff_instance_configs(
+        &self,
+        request: Request<DiffInstanceConfigsRequest>,
+    ) -> Result<Response<DiffInstanceConfigsResponse>, Status> {
+        let (request, user_token): (DiffInstanceConfigsRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::InstancesRead,
+            )
+            .await?;
+        let internal = logic::deploy::diff_instance_configs(
+            self.db.as_ref(),
+            &request.from_deployment_id,
+            &request.to_deployment_id,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result =
+            DiffInstanceConfigsResponse::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn compare_deployments(
+        &self,
+        request: Request<CompareDeploymentsRequest>,
+    ) -> Result<Response<CompareDeploymentsResponse>, Status> {
+        let (request, user_token): (CompareDeploymentsRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsRead,
+            )
+            .await?;
+        let internal = logic::deploy::compare_deployments(
+            self.db.as_ref(),
+            &request.first_deployment_id,
+            &request.second_deployment_id,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result =
+            CompareDeploymentsResponse::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn update_jobs_pause(
+        &self,
+        request: Request<UpdateJobsPauseRequest>,
+    ) -> Result<Response<JobsStatus>, Status> {
+        let (request, user_token): (UpdateJobsPauseRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
+        let internal = logic::deploy::update_jobs_pause(
+            self.db.as_ref(),
+            self.jobs.as_ref(),
+            request.paused,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result = JobsStatus::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn reconcile_deployments(
+        &self,
+        request: Request<ReconcileDeploymentsRequest>,
+    ) -> Result<Response<ReconcileDeploymentsResponse>, Status> {
+        let (request, user_token): (ReconcileDeploymentsRequestInternal, _) =
+            parse_request_with_headers(
+                self.db.as_ref(),
+                &self.rate_limiter,
+                request,
+                Scope::DeploymentsWrite,
+            )
+            .await?;
+        let internal = logic::deploy::reconcile_deployments(
+            self.db.as_ref(),
+            self.jobs.as_ref(),
+            self.ci.as_ref(),
+            request.fix,
+            &user_token,
+        )
+        .await
+        .map_err(map_deploy_error)?;
+        let result =
+            ReconcileDeploymentsResponse::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
     async fn get_profile(
         &self,
         request: Request<GetProfileRequest>,
     ) -> Result<Response<UserProfile>, Status> {
-        let (_, user_token): (GetProfileRequestInternal, _) =
-            parse_request_with_headers(self.db.as_ref(), request).await?;
+        let (_, user_token): (GetProfileRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::UsersRead,
+        )
+        .await?;
         let internal = logic::users::get_profile(self.db.as_ref(), &user_token)
             .await
             .map_err(map_auth_error)?;
         let result = UserProfile::try_convert(internal).map_err(map_convert_error)?;
         Ok(Response::new(result))
     }
+
+    async fn get_usage(
+        &self,
+        request: Request<GetUsageRequest>,
+    ) -> Result<Response<Usage>, Status> {
+        let (request, user_token): (GetUsageRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::UsersRead,
+        )
+        .await?;
+        let internal = logic::deploy::get_usage(self.db.as_ref(), &request, &user_token)
+            .await
+            .map_err(map_deploy_error)?;
+        let result = Usage::try_convert(internal).map_err(map_convert_error)?;
+        Ok(Response::new(result))
+    }
+
+    async fn update_webhook(
+        &self,
+        request: Request<UpdateWebhookRequest>,
+    ) -> Result<Response<UpdateWebhookResponse>, Status> {
+        let (request, user_token): (UpdateWebhookRequestInternal, _) = parse_request_with_headers(
+            self.db.as_ref(),
+            &self.rate_limiter,
+            request,
+            Scope::UsersWrite,
+        )
+        .await?;
+        let result = logic::users::update_webhook(self.db.as_ref(), &request, &user_token)
+            .await
+            .map_err(map_deploy_error)?;
+        Ok(Response::new(
+            UpdateWebhookResponse::try_convert(result).map_err(map_convert_error)?,
+        ))
+    }
 }
 
+/// Authenticates the request by api key, checks that the key has the `scope`
+/// and takes the request from its rate limit bucket
 async fn parse_request_with_headers<C, B, I>(
     db: &C,
+    rate_limiter: &RateLimiter,
     request: Request<B>,
+    scope: Scope,
 ) -> Result<(I, UserToken), Status>
 where
     C: ConnectionTrait,
@@ -242,6 +1030,11 @@ where
     let user_token = UserToken::try_from_http_headers(db, &meta.into_headers())
         .await
         .map_err(map_auth_error)?;
+    user_token.require_scope(scope).map_err(map_auth_error)?;
+    rate_limiter
+        .check(&user_token, scope)
+        .await
+        .map_err(map_auth_error)?;
     let request = I::try_convert(request).map_err(map_convert_error)?;
     Ok((request, user_token))
 }
@@ -258,20 +1051,52 @@ fn map_deploy_error(err: DeployError) -> Status {
 fn map_deploy_code(err: &DeployError) -> Code {
     match err {
         DeployError::InstanceExists(_) => Code::AlreadyExists,
+        DeployError::DuplicateName(_) => Code::AlreadyExists,
         DeployError::InstanceNotFound(_) => Code::NotFound,
+        DeployError::DuplicateTemplateName(_) => Code::AlreadyExists,
+        DeployError::TemplateNotFound(_) => Code::NotFound,
+        DeployError::PresetNotFound { .. } => Code::NotFound,
         DeployError::Config(_) => Code::InvalidArgument,
+        DeployError::InvalidConfig(_) => Code::InvalidArgument,
         DeployError::Github(_) => Code::Internal,
+        DeployError::GithubRateLimited => Code::Unavailable,
+        DeployError::WorkflowFailed { .. } => Code::Internal,
+        DeployError::WorkflowCancelled { .. } => Code::Aborted,
+        DeployError::WorkflowTimedOut { .. } => Code::DeadlineExceeded,
+        DeployError::WorkflowStartupFailure { .. } => Code::FailedPrecondition,
+        DeployError::WorkflowTimeout { .. } => Code::DeadlineExceeded,
+        DeployError::NoRunnerAvailable { .. } => Code::Unavailable,
+        DeployError::RunDisappeared { .. } => Code::FailedPrecondition,
+        DeployError::ActionsBudgetExhausted { .. } => Code::ResourceExhausted,
+        DeployError::Unhealthy(_) => Code::Internal,
+        DeployError::WatchdogTimeout { .. } => Code::DeadlineExceeded,
+        DeployError::Cancelled => Code::Cancelled,
+        DeployError::Forced(_) => Code::Aborted,
         DeployError::Db(_) => Code::Internal,
         DeployError::Internal(_) => Code::Internal,
         DeployError::Auth(e) => map_auth_code(e),
         DeployError::DeploymentNotFound => Code::NotFound,
+        DeployError::DeploymentLogsNotFound => Code::NotFound,
         DeployError::InvalidStateTransition(_, _) => Code::InvalidArgument,
         DeployError::InvalidValue(_) => Code::InvalidArgument,
+        DeployError::IdempotencyKeyConflict(_) => Code::AlreadyExists,
+        DeployError::RestorePeriodExpired(_) => Code::FailedPrecondition,
+        DeployError::Conflict(_) => Code::Aborted,
+        DeployError::InvalidTransition(_, _) => Code::FailedPrecondition,
+        DeployError::Interrupted => Code::Unavailable,
+        DeployError::Unavailable(_) => Code::Unavailable,
+        DeployError::Overloaded => Code::Unavailable,
     }
 }
 
 fn map_auth_error(err: AuthError) -> Status {
-    Status::new(map_auth_code(&err), err.to_string())
+    let mut status = Status::new(map_auth_code(&err), err.to_string());
+    if let AuthError::RateLimited { retry_after } = &err {
+        if let Ok(value) = retry_after.as_secs_f64().ceil().to_string().parse() {
+            status.metadata_mut().insert("retry-after", value);
+        }
+    }
+    status
 }
 
 fn map_auth_code(err: &AuthError) -> Code {
@@ -281,7 +1106,121 @@ fn map_auth_code(err: &AuthError) -> Code {
         AuthError::Internal(_) => Code::Internal,
         AuthError::NotFound => Code::NotFound,
         AuthError::Unauthorized(_) => Code::PermissionDenied,
+        AuthError::MissingScope(_) => Code::PermissionDenied,
         AuthError::Db(_) => Code::Internal,
         AuthError::InsufficientBalance => Code::PermissionDenied,
+        AuthError::QuotaExceeded { .. } => Code::ResourceExhausted,
+        AuthError::RateLimited { .. } => Code::ResourceExhausted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+    use scoutcloud_entity as db;
+    use sea_orm::EntityTrait;
+
+    const USER_ID: i32 = 1;
+    const INSTANCE_ID: i32 = 1;
+
+    fn with_token<T>(message: T, user_token: &UserToken) -> Request<T> {
+        let mut request = Request::new(message);
+        request.metadata_mut().insert(
+            "x-api-key",
+            user_token.token.token_value.to_string().parse().unwrap(),
+        );
+        request
+    }
+
+    fn list_request(instance_id: &str, user_token: &UserToken) -> Request<ListDeploymentsRequest> {
+        with_token(
+            ListDeploymentsRequest {
+                instance_id: instance_id.to_string(),
+                include_deleted: None,
+                label_selector: None,
+                created_after: None,
+                created_before: None,
+                updated_after: None,
+            },
+            user_token,
+        )
+    }
+
+    fn stop_request(
+        instance_id: &str,
+        user_token: &UserToken,
+    ) -> Request<UpdateInstanceStatusRequest> {
+        with_token(
+            UpdateInstanceStatusRequest {
+                instance_id: instance_id.to_string(),
+                action: UpdateInstanceAction::Finish.into(),
+                workflow_timeout_seconds: None,
+                ttl_seconds: None,
+                dry_run: false,
+                labels: Default::default(),
+                git_ref: None,
+                image: None,
+                tag: None,
+                scheduled_at: None,
+                max_auto_retries: None,
+            },
+            user_token,
+        )
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn scopes_of_token_are_enforced() {
+        let (db, github, _repo, runner) =
+            tests_utils::init::jobs_runner_test_case("scopes_of_token_are_enforced").await;
+        let conn = db.client();
+        let service = ScoutcloudService::new(
+            conn.clone(),
+            github.clone(),
+            github,
+            Arc::new(runner),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let instance_id = db::instances::Entity::find_by_id(INSTANCE_ID)
+            .one(conn.as_ref())
+            .await
+            .unwrap()
+            .unwrap()
+            .external_id
+            .to_string();
+
+        let read_only = UserToken::create(conn.as_ref(), USER_ID, &[Scope::DeploymentsRead])
+            .await
+            .unwrap();
+        let response = service
+            .list_deployments(list_request(&instance_id, &read_only))
+            .await
+            .expect("read-only token should list deployments");
+        assert!(!response.into_inner().items.is_empty());
+        let status = service
+            .update_instance_status(stop_request(&instance_id, &read_only))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied, "{status:?}");
+        assert!(status.message().contains("deployments:write"), "{status:?}");
+
+        let write = UserToken::create(conn.as_ref(), USER_ID, &[Scope::DeploymentsWrite])
+            .await
+            .unwrap();
+        service
+            .list_deployments(list_request(&instance_id, &write))
+            .await
+            .expect("write token should list deployments");
+        service
+            .update_instance_status(stop_request(&instance_id, &write))
+            .await
+            .expect("write token should stop deployment");
+        tests_utils::db::wait_for_empty_fang_tasks(conn.clone())
+            .await
+            .unwrap();
     }
 }
@@ -3,7 +3,11 @@ use blockscout_service_launcher::{
     launcher::{ConfigSettings, MetricsSettings, ServerSettings},
     tracing::{JaegerSettings, TracingSettings},
 };
+use scoutcloud_entity::sea_orm_active_enums::DeploymentStatusType;
+use sea_orm::ConnectOptions;
 use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use std::{collections::BTreeMap, time::Duration};
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
@@ -16,20 +20,937 @@ pub struct Settings {
     pub tracing: TracingSettings,
     #[serde(default)]
     pub jaeger: JaegerSettings,
+    #[serde(default)]
+    pub otlp: OtlpSettings,
     pub database: DatabaseSettings,
+    /// Connections to `database`, used by the server and background jobs
+    #[serde(default)]
+    pub database_pool: DatabasePoolSettings,
     pub github: GithubSettings,
+    #[serde(default)]
+    pub ci: CiSettings,
+    #[serde(default)]
+    pub jobs: JobsSettings,
+    #[serde(default)]
+    pub quota: QuotaSettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(default)]
+    pub instances: InstancesSettings,
+    /// Secret fields of instance configs are stored encrypted only if it is set
+    #[serde(default)]
+    pub secrets: Option<SecretsSettings>,
+    /// Store of secrets referenced by configs as `secret://<path>`
+    #[serde(default)]
+    pub secret_store: SecretStoreSettings,
+    /// Status changes of deployments are posted to slack only if it is set
+    #[serde(default)]
+    pub slack: Option<SlackSettings>,
 }
 
 impl ConfigSettings for Settings {
     const SERVICE_NAME: &'static str = "SCOUTCLOUD";
 }
 
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DatabasePoolSettings {
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    #[serde(default = "default_min_connections")]
+    pub min_connections: u32,
+    /// How long to wait for a new connection to be established
+    #[serde(default = "default_connect_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub connect_timeout: Duration,
+    /// How long to wait for a free connection when the pool is exhausted
+    #[serde(default = "default_acquire_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub acquire_timeout: Duration,
+}
+
+impl Default for DatabasePoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: default_max_connections(),
+            min_connections: default_min_connections(),
+            connect_timeout: default_connect_timeout(),
+            acquire_timeout: default_acquire_timeout(),
+        }
+    }
+}
+
+impl DatabasePoolSettings {
+    pub fn connect_options(&self, url: impl Into<String>) -> ConnectOptions {
+        ConnectOptions::new(url)
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .connect_timeout(self.connect_timeout)
+            .acquire_timeout(self.acquire_timeout)
+            .sqlx_logging_level(::tracing::log::LevelFilter::Debug)
+            .to_owned()
+    }
+}
+
+fn default_max_connections() -> u32 {
+    50
+}
+
+fn default_min_connections() -> u32 {
+    1
+}
+
+fn default_connect_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_acquire_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Spans are exported to OpenTelemetry collector over grpc. Can't be enabled
+/// together with `jaeger`, since both exporters register the global tracer
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_otlp_endpoint")]
+    pub endpoint: String,
+    /// Percentage of traces which are exported. Spans of a trace follow
+    /// the decision made for its root span
+    #[serde(default = "default_otlp_sample_percent")]
+    pub sample_percent: u8,
+}
+
+impl Default for OtlpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: default_otlp_endpoint(),
+            sample_percent: default_otlp_sample_percent(),
+        }
+    }
+}
+
+impl OtlpSettings {
+    pub fn sample_ratio(&self) -> f64 {
+        f64::from(self.sample_percent.min(100)) / 100.0
+    }
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://localhost:4317".to_string()
+}
+
+fn default_otlp_sample_percent() -> u8 {
+    100
+}
+
+#[serde_as]
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct GithubSettings {
-    pub token: String,
+    /// Personal access token, used only if `app` is not configured
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default)]
+    pub app: Option<GithubAppSettings>,
     pub owner: String,
     pub repo: String,
     #[serde(default)]
     pub branch: Option<String>,
+    /// Api url of github enterprise server, for example `https://github.example.com/api/v3`.
+    /// Public github api is used if it is not set
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Secret of `workflow_run` webhook. Webhook endpoint is enabled only if it is set
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSettings,
+    #[serde(default)]
+    pub request: GithubRequestSettings,
+    /// Repos which run workflows of instances with `deployment_target` in their config,
+    /// instances without it are deployed by `owner/repo`
+    #[serde(default)]
+    pub deployment_targets: BTreeMap<String, DeploymentTargetSettings>,
+    /// Repos which workflows may be dispatched to, including repos of deployment targets.
+    /// Forks of the workflows from configs of instances should be listed explicitly
+    #[serde(default)]
+    pub repo_allowlist: RepoAllowlistSettings,
+    #[serde(default)]
+    pub workflow_files: WorkflowFilesSettings,
+    /// Expected blob shas of workflow files of the repo, like `deploy.yaml`.
+    /// Workflow is not dispatched if its file differs from the pinned one
+    #[serde(default)]
+    pub pinned_workflows: BTreeMap<String, String>,
+    /// Dispatch workflows which differ from the pinned ones with a warning
+    #[serde(default)]
+    pub allow_workflow_drift: bool,
+    /// Deployment fails early if its run is not picked up by a runner in time.
+    /// Queued runs are waited for until the timeout of the workflow if it is not set
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub queued_timeout: Option<Duration>,
+    /// Remaining github actions minutes of the owner are checked before deploys
+    /// only if it is set
+    #[serde(default)]
+    pub actions_budget: Option<ActionsBudgetSettings>,
+}
+
+/// Github actions minutes of the organization which owns the repo, the credentials
+/// need read access to its billing. Only minutes included in the plan are counted
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ActionsBudgetSettings {
+    /// Deploys are rejected when fewer minutes are left in the billing cycle
+    pub min_remaining_minutes: u64,
+    /// Only log a warning instead of rejecting deploys
+    #[serde(default)]
+    pub warn_only: bool,
+    #[serde(default = "default_actions_budget_cache_ttl")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub cache_ttl: Duration,
+}
+
+fn default_actions_budget_cache_ttl() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+/// Repo with its own set of workflows, usually one per region.
+/// It is accessed with the same credentials as the default repo
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DeploymentTargetSettings {
+    /// Owner of the repo, `owner` of github settings is used if it is not set
+    #[serde(default)]
+    pub owner: Option<String>,
+    pub repo: String,
+    /// Ref which workflows are dispatched on and configs are committed to
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// Label of the region served by the repo, it is reported in logs of dispatched workflows
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Same as `pinned_workflows` of github settings, but for the repo of the target
+    #[serde(default)]
+    pub pinned_workflows: BTreeMap<String, String>,
+    /// `workflow_files` of github settings are used if it is not set
+    #[serde(default)]
+    pub workflow_files: Option<WorkflowFilesSettings>,
+}
+
+/// Guards against a deployment target which points to a wrong repo by mistake.
+/// Workflows of other repos are not dispatched
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoAllowlistSettings {
+    /// Allowed repos as `owner/repo`
+    #[serde(default)]
+    pub repos: Vec<String>,
+    /// Empty list of repos allows all of them unless it is set
+    #[serde(default)]
+    pub deny_if_empty: bool,
+}
+
+/// Names of workflow files in `.github/workflows` of the repo
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WorkflowFilesSettings {
+    /// Dispatched to start instances
+    #[serde(default = "default_deploy_workflow")]
+    pub deploy: String,
+    /// Dispatched to stop instances
+    #[serde(default = "default_cleanup_workflow")]
+    pub cleanup: String,
+}
+
+impl Default for WorkflowFilesSettings {
+    fn default() -> Self {
+        Self {
+            deploy: default_deploy_workflow(),
+            cleanup: default_cleanup_workflow(),
+        }
+    }
+}
+
+fn default_deploy_workflow() -> String {
+    "deploy.yaml".to_string()
+}
+
+fn default_cleanup_workflow() -> String {
+    "cleanup.yaml".to_string()
+}
+
+/// Requests to github are paused for `cooldown` after `failure_threshold`
+/// failed requests in a row, so tasks retry later instead of waiting for github
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerSettings {
+    #[serde(default = "default_circuit_breaker_failure_threshold")]
+    pub failure_threshold: u32,
+    #[serde(default = "default_circuit_breaker_cooldown")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_circuit_breaker_failure_threshold(),
+            cooldown: default_circuit_breaker_cooldown(),
+        }
+    }
+}
+
+fn default_circuit_breaker_failure_threshold() -> u32 {
+    5
+}
+
+fn default_circuit_breaker_cooldown() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Timeouts of single requests to github api. Only idempotent GET requests are retried,
+/// waiting for workflows uses its own polling backoff
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GithubRequestSettings {
+    #[serde(default = "default_github_connect_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub connect_timeout: Duration,
+    #[serde(default = "default_github_read_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub read_timeout: Duration,
+    #[serde(default = "default_github_max_get_retries")]
+    pub max_get_retries: u32,
+}
+
+impl Default for GithubRequestSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout: default_github_connect_timeout(),
+            read_timeout: default_github_read_timeout(),
+            max_get_retries: default_github_max_get_retries(),
+        }
+    }
+}
+
+fn default_github_connect_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_github_read_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_github_max_get_retries() -> u32 {
+    2
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GithubAppSettings {
+    pub app_id: u64,
+    pub installation_id: u64,
+    /// PEM encoded RSA private key of the app
+    pub private_key: String,
+}
+
+/// Backend which runs deploy and cleanup workflows of instances.
+/// Configs of instances are committed to the github repo with any backend
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CiSettings {
+    #[serde(default)]
+    pub backend: CiBackendType,
+    /// Required if `backend` is gitlab
+    #[serde(default)]
+    pub gitlab: Option<GitlabSettings>,
+    /// Required if `backend` is kubernetes
+    #[serde(default)]
+    pub kubernetes: Option<KubernetesSettings>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CiBackendType {
+    #[default]
+    Github,
+    Gitlab,
+    /// Instances are deployed as flux helm releases through kubernetes api
+    Kubernetes,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GitlabSettings {
+    #[serde(default = "default_gitlab_base_url")]
+    pub base_url: String,
+    /// Access token with `api` scope
+    pub token: String,
+    /// Id or full path of the project, like `group/repo`
+    pub project: String,
+    #[serde(default = "default_gitlab_branch")]
+    pub branch: String,
+}
+
+/// `{client}` in names is replaced with slug of the instance
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct KubernetesSettings {
+    /// Url of kubernetes api server
+    pub api_url: String,
+    /// Token of service account which manages helm releases
+    pub token: String,
+    /// PEM encoded certificate of cluster CA, public roots are trusted if it is not set
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+    /// Namespace of instances which don't set it in their config
+    #[serde(default = "default_kubernetes_namespace")]
+    pub namespace: String,
+    /// Deployment of blockscout created by the chart, its rollout is awaited
+    #[serde(default = "default_kubernetes_deployment")]
+    pub deployment: String,
+    pub chart: HelmChartSettings,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HelmChartSettings {
+    #[serde(default = "default_helm_chart_name")]
+    pub name: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Name of flux `HelmRepository` with the chart
+    pub repository: String,
+    #[serde(default = "default_helm_repository_namespace")]
+    pub repository_namespace: String,
+}
+
+fn default_kubernetes_namespace() -> String {
+    "{client}".to_string()
+}
+
+fn default_kubernetes_deployment() -> String {
+    "{client}-blockscout-stack-blockscout".to_string()
+}
+
+fn default_helm_chart_name() -> String {
+    "blockscout-stack".to_string()
+}
+
+fn default_helm_repository_namespace() -> String {
+    "flux-system".to_string()
+}
+
+fn default_gitlab_base_url() -> String {
+    "https://gitlab.com".to_string()
+}
+
+fn default_gitlab_branch() -> String {
+    "main".to_string()
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SecretsSettings {
+    /// Hex encoded 32 bytes AES-256-GCM keys by their versions.
+    /// Old keys should be kept after rotation to read values encrypted with them
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    pub keys: BTreeMap<u32, String>,
+    /// Version of the key used to encrypt new values
+    pub current_key_version: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SecretStoreSettings {
+    #[serde(default)]
+    pub backend: SecretStoreType,
+    /// Required if `backend` is vault
+    #[serde(default)]
+    pub vault: Option<VaultSettings>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretStoreType {
+    /// Secrets are kept in the database encrypted with `secrets` keys,
+    /// the store is disabled if encryption is not configured
+    #[default]
+    Database,
+    Vault,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct VaultSettings {
+    /// Like `https://vault.example.com:8200`
+    pub address: String,
+    /// Token with read access to secrets of instances
+    pub token: String,
+    /// Mount path of KV version 2 secrets engine
+    #[serde(default = "default_vault_mount")]
+    pub mount: String,
+    /// Key of the secret data which contains the value
+    #[serde(default = "default_vault_field")]
+    pub field: String,
+}
+
+fn default_vault_mount() -> String {
+    "secret".to_string()
+}
+
+fn default_vault_field() -> String {
+    "value".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SlackSettings {
+    /// Incoming webhook of slack app
+    pub webhook_url: String,
+    /// Overrides the default channel of the webhook
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// Deployments entering these statuses are reported
+    #[serde(default = "default_slack_notify_on")]
+    pub notify_on: Vec<NotifiedStatus>,
+    /// Workflows approaching their timeout are reported too,
+    /// see `WorkflowSettings::timeout_warning_percent`
+    #[serde(default)]
+    pub notify_approaching_timeout: bool,
+}
+
+fn default_slack_notify_on() -> Vec<NotifiedStatus> {
+    vec![NotifiedStatus::Failed]
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifiedStatus {
+    Running,
+    Stopped,
+    Failed,
+}
+
+impl NotifiedStatus {
+    pub fn matches(&self, status: &DeploymentStatusType) -> bool {
+        matches!(
+            (self, status),
+            (NotifiedStatus::Running, DeploymentStatusType::Running)
+                | (NotifiedStatus::Stopped, DeploymentStatusType::Stopped)
+                | (NotifiedStatus::Failed, DeploymentStatusType::Failed)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaSettings {
+    /// Max number of deployments of a user which are not stopped or failed.
+    /// Can be overridden for a user by `users.quota` column
+    #[serde(default = "default_max_active_deployments_per_user")]
+    pub max_active_deployments_per_user: u64,
+}
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        Self {
+            max_active_deployments_per_user: default_max_active_deployments_per_user(),
+        }
+    }
+}
+
+fn default_max_active_deployments_per_user() -> u64 {
+    5
+}
+
+/// Requests of every api key are throttled by token buckets,
+/// requests which change something have a separate stricter bucket
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitSettings {
+    #[serde(default = "default_rate_limit_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_rate_limit_read")]
+    pub read: BucketSettings,
+    #[serde(default = "default_rate_limit_write")]
+    pub write: BucketSettings,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: default_rate_limit_enabled(),
+            read: default_rate_limit_read(),
+            write: default_rate_limit_write(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BucketSettings {
+    /// Max number of requests sent at once
+    pub burst: u32,
+    /// Speed of refilling the bucket
+    pub per_minute: u32,
+}
+
+fn default_rate_limit_enabled() -> bool {
+    true
+}
+
+fn default_rate_limit_read() -> BucketSettings {
+    BucketSettings {
+        burst: 60,
+        per_minute: 600,
+    }
+}
+
+fn default_rate_limit_write() -> BucketSettings {
+    BucketSettings {
+        burst: 10,
+        per_minute: 30,
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct InstancesSettings {
+    /// How secret fields of the config are treated when an instance is cloned
+    #[serde(default)]
+    pub clone_secrets: CloneSecretsPolicy,
+    /// Soft-deleted deployments can be restored during this period
+    #[serde(default = "default_restore_grace_period")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub restore_grace_period: Duration,
+    /// Hosts which urls of instance configs may point to even if they are private,
+    /// loopback or link-local, e.g. `127.0.0.1` or `rpc.internal`
+    #[serde(default)]
+    pub allowed_private_hosts: Vec<String>,
+    /// Images which configs and starting requests may override the deployed image with,
+    /// e.g. `ghcr.io/blockscout/*`. No custom images are allowed by default
+    #[serde(default)]
+    pub allowed_image_registries: Vec<String>,
+    /// Complete configs which instances can be deployed from by name, in addition
+    /// to built-in presets. Presets with names of built-in ones replace them
+    #[serde(default)]
+    pub presets: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for InstancesSettings {
+    fn default() -> Self {
+        Self {
+            clone_secrets: Default::default(),
+            restore_grace_period: default_restore_grace_period(),
+            allowed_private_hosts: vec![],
+            allowed_image_registries: vec![],
+            presets: Default::default(),
+        }
+    }
+}
+
+fn default_restore_grace_period() -> Duration {
+    Duration::from_secs(7 * 24 * 60 * 60)
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloneSecretsPolicy {
+    /// Secrets of the source instance are copied to the clone
+    Copy,
+    /// Secrets are not copied and should be provided in overrides of the clone request
+    #[default]
+    Reprompt,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct JobsSettings {
+    /// How long to wait for running jobs on shutdown
+    #[serde(default = "default_shutdown_grace_period")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub shutdown_grace_period: Duration,
+    #[serde(default)]
+    pub health_check: HealthCheckSettings,
+    #[serde(default)]
+    pub retention: RetentionSettings,
+    #[serde(default)]
+    pub dispatch_limit: DispatchLimitSettings,
+    #[serde(default)]
+    pub workflows: WorkflowSettings,
+    /// Status changes of deployments are published to kafka only if it is set
+    #[serde(default)]
+    pub events: Option<EventsSettings>,
+    /// Number of fang workers, i.e. tasks which are run at once. Derived from the number
+    /// of cpus if it is not set. Tasks which dispatch workflows are limited by
+    /// `dispatch_limit` as well, so with fewer workers than `dispatch_limit.max_concurrent`
+    /// that limit is never reached, and extra workers only run other tasks while it is
+    #[serde(default)]
+    pub workers: Option<u32>,
+    /// Removes `in_progress` tasks which were not touched since the start of the process.
+    /// There is no way to tell whether their workers are alive, so it should be enabled
+    /// only if a single replica runs the jobs and no task runs longer without touching its row
+    #[serde(default)]
+    pub remove_stale_tasks: bool,
+}
+
+impl JobsSettings {
+    pub fn number_of_workers(&self) -> u32 {
+        self.workers
+            .unwrap_or_else(default_number_of_workers)
+            .max(1)
+    }
+}
+
+impl Default for JobsSettings {
+    fn default() -> Self {
+        Self {
+            shutdown_grace_period: default_shutdown_grace_period(),
+            health_check: Default::default(),
+            retention: Default::default(),
+            dispatch_limit: Default::default(),
+            workflows: Default::default(),
+            events: None,
+            workers: None,
+            remove_stale_tasks: false,
+        }
+    }
+}
+
+fn default_shutdown_grace_period() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Tasks mostly wait for github, so a few of them share every cpu
+fn default_number_of_workers() -> u32 {
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get()) as u32;
+    cpus.saturating_mul(4).clamp(4, 32)
+}
+
+/// Defaults of starting and stopping tasks, built-in defaults of the tasks are used
+/// for missing values. Timeout set in the deployment takes precedence
+#[serde_as]
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct WorkflowSettings {
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub start_timeout: Option<Duration>,
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub stop_timeout: Option<Duration>,
+    /// Initial interval between checks of the workflow status
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub check_interval: Option<Duration>,
+    /// Intervals of starting and stopping tasks, `check_interval` is used if they are not set.
+    /// Deploys bootstrap the whole chain index, so they are usually polled less often
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub start_check_interval: Option<Duration>,
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub stop_check_interval: Option<Duration>,
+    /// Delay before the first automatic retry of a failed start,
+    /// it is doubled for every next retry
+    #[serde(default)]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<u64>>")]
+    pub auto_retry_delay: Option<Duration>,
+    /// Percent of the workflow timeout, like 80, after which it is reported once
+    /// that the workflow is approaching its timeout. Nothing is reported if it is not set
+    #[serde(default)]
+    pub timeout_warning_percent: Option<u8>,
+}
+
+/// Started deployment is marked as running only after its instance responds
+/// successfully on the health endpoint
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheckSettings {
+    #[serde(default = "default_health_check_enabled")]
+    pub enabled: bool,
+    /// Path of the health endpoint relative to the instance url
+    #[serde(default = "default_health_check_path")]
+    pub path: String,
+    #[serde(default = "default_health_check_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub timeout: Duration,
+    #[serde(default = "default_health_check_interval")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub interval: Duration,
+}
+
+impl Default for HealthCheckSettings {
+    fn default() -> Self {
+        Self {
+            enabled: default_health_check_enabled(),
+            path: default_health_check_path(),
+            timeout: default_health_check_timeout(),
+            interval: default_health_check_interval(),
+        }
+    }
+}
+
+fn default_health_check_enabled() -> bool {
+    true
+}
+
+fn default_health_check_path() -> String {
+    "/api/health".to_string()
+}
+
+// blockscout needs some time to run migrations after the first start
+fn default_health_check_timeout() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+fn default_health_check_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// Stopped and failed deployments are deleted together with their logs and
+/// status history after the retention period. The latest deployment of an
+/// instance is always kept, so the instance can be started again
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionSettings {
+    #[serde(default = "default_retention_enabled")]
+    pub enabled: bool,
+    /// Time since the last status change of the deployment
+    #[serde(default = "default_retention_period")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub period: Duration,
+    /// Number of deployments deleted in one statement
+    #[serde(default = "default_retention_batch_size")]
+    pub batch_size: u64,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: default_retention_enabled(),
+            period: default_retention_period(),
+            batch_size: default_retention_batch_size(),
+        }
+    }
+}
+
+fn default_retention_enabled() -> bool {
+    true
+}
+
+fn default_retention_period() -> Duration {
+    Duration::from_secs(90 * 24 * 60 * 60)
+}
+
+fn default_retention_batch_size() -> u64 {
+    1000
+}
+
+/// Github limits the number of concurrent jobs, so only a few tasks may dispatch
+/// workflows and wait for them at once. Other tasks are postponed by `retry_delay`
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DispatchLimitSettings {
+    #[serde(default = "default_dispatch_max_concurrent")]
+    pub max_concurrent: usize,
+    #[serde(default = "default_dispatch_retry_delay")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub retry_delay: Duration,
+    /// New deploys are rejected while this number of tasks is waiting to dispatch
+    /// workflows, so the queue can drain after github outages. Not limited if it is not set
+    #[serde(default)]
+    pub max_pending_tasks: Option<u64>,
+}
+
+impl Default for DispatchLimitSettings {
+    fn default() -> Self {
+        Self {
+            max_concurrent: default_dispatch_max_concurrent(),
+            retry_delay: default_dispatch_retry_delay(),
+            max_pending_tasks: None,
+        }
+    }
+}
+
+fn default_dispatch_max_concurrent() -> usize {
+    10
+}
+
+fn default_dispatch_retry_delay() -> Duration {
+    Duration::from_secs(15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_utils;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn database_pool_options_are_applied() {
+        let db = tests_utils::init::test_db("test", "database_pool_options_are_applied").await;
+        let settings: DatabasePoolSettings = serde_json::from_value(serde_json::json!({
+            "max_connections": 7,
+            "min_connections": 2,
+            "connect_timeout": 3,
+            "acquire_timeout": 4,
+        }))
+        .unwrap();
+        let options = settings.connect_options(db.db_url());
+        assert_eq!(options.get_connect_timeout(), Some(Duration::from_secs(3)));
+
+        let conn = sea_orm::Database::connect(options).await.unwrap();
+        let pool = conn.get_postgres_connection_pool().options();
+        assert_eq!(pool.get_max_connections(), 7);
+        assert_eq!(pool.get_min_connections(), 2);
+        assert_eq!(pool.get_acquire_timeout(), Duration::from_secs(4));
+    }
+}
+
+/// Every status change of a deployment is published to the kafka topic as a json event.
+/// Changes are exported from the status history in background, so unavailable
+/// brokers only delay the events and don't affect deployments
+#[serde_as]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EventsSettings {
+    /// Comma separated list of `host:port` of kafka brokers
+    pub brokers: String,
+    #[serde(default = "default_events_topic")]
+    pub topic: String,
+    /// Max number of events published at once
+    #[serde(default = "default_events_batch_size")]
+    pub batch_size: u64,
+    /// How long to wait for brokers to acknowledge an event
+    #[serde(default = "default_events_delivery_timeout")]
+    #[serde_as(as = "serde_with::DurationSeconds<u64>")]
+    pub delivery_timeout: Duration,
+}
+
+fn default_events_topic() -> String {
+    "scoutcloud.deployments".to_string()
+}
+
+fn default_events_batch_size() -> u64 {
+    100
+}
+
+fn default_events_delivery_timeout() -> Duration {
+    Duration::from_secs(10)
 }
@@ -0,0 +1,90 @@
+use super::settings::{OtlpSettings, Settings};
+use blockscout_service_launcher::tracing::TracingFormat;
+use opentelemetry::{
+    sdk::{
+        trace::{self as sdktrace, Sampler},
+        Resource,
+    },
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::{Level, Metadata};
+use tracing_subscriber::{
+    filter::{filter_fn, FilterFn, LevelFilter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Initializes logs and export of spans. Jaeger exporter is set up by the launcher,
+/// OTLP exporter replaces it when enabled
+pub fn init_tracing(service_name: &str, settings: &Settings) -> Result<(), anyhow::Error> {
+    if !settings.otlp.enabled {
+        return blockscout_service_launcher::tracing::init_logs(
+            service_name,
+            &settings.tracing,
+            &settings.jaeger,
+            Some(skip_sql_queries()),
+        );
+    }
+    if settings.jaeger.enabled {
+        anyhow::bail!("jaeger and otlp exporters can't be enabled at once");
+    }
+
+    let mut layers: Vec<BoxedLayer> = vec![];
+    if settings.tracing.enabled {
+        let stdout = tracing_subscriber::fmt::layer();
+        let stdout = match settings.tracing.format {
+            TracingFormat::Default => stdout.boxed(),
+            TracingFormat::Json => stdout.json().boxed(),
+        };
+        let env_filter = EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy();
+        layers.push(stdout.with_filter(env_filter).boxed());
+    }
+    layers.push(otlp_layer(service_name, &settings.otlp)?);
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(skip_sql_queries())
+        .try_init()?;
+    Ok(())
+}
+
+fn otlp_layer(service_name: &str, settings: &OtlpSettings) -> Result<BoxedLayer, anyhow::Error> {
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(settings.endpoint.clone()),
+        )
+        .with_trace_config(
+            sdktrace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    settings.sample_ratio(),
+                ))))
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    service_name.to_string(),
+                )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer).boxed())
+}
+
+fn skip_sql_queries() -> FilterFn<impl Fn(&Metadata<'_>) -> bool> {
+    filter_fn(|metadata| {
+        ["sqlx::query"]
+            .iter()
+            .all(|&target| metadata.level().ge(&Level::INFO) && metadata.target() != target)
+    })
+}
+
+/// Exports spans which are not exported by the batch exporter yet
+pub fn shutdown_tracing() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,182 @@
+use crate::logic::{
+    ci::{CiBackend, CiRun, CiTarget, CiWorkflow},
+    github::{
+        logs::RunLogs,
+        types::{RunConclusion, RunStatus},
+        PollBackoff,
+    },
+    GithubError,
+};
+use octocrab::models::RunId;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+/// How the run of the fake backend completes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FakeRun {
+    Succeeds,
+    Fails,
+    /// Run is still in progress when waiting for it times out
+    TimesOut,
+    /// Run never completes, waiting for it is stopped only by cancellation
+    Hangs,
+    /// Run is not found in the backend anymore
+    Disappears,
+}
+
+/// Configurable ci backend of tests. By default every workflow is dispatched at once
+/// and succeeds. Runs get sequential ids starting from 1 in the order of dispatches
+#[derive(Debug)]
+pub struct FakeCi {
+    default_run: FakeRun,
+    runs: HashMap<RunId, FakeRun>,
+    failing_dispatches: u64,
+    failing_clients: HashSet<String>,
+    dispatch_delay: Duration,
+    dispatched: Mutex<Vec<(CiWorkflow, String)>>,
+    cancelled: Mutex<Vec<RunId>>,
+}
+
+impl Default for FakeCi {
+    fn default() -> Self {
+        Self {
+            default_run: FakeRun::Succeeds,
+            runs: Default::default(),
+            failing_dispatches: 0,
+            failing_clients: Default::default(),
+            dispatch_delay: Duration::ZERO,
+            dispatched: Default::default(),
+            cancelled: Default::default(),
+        }
+    }
+}
+
+impl FakeCi {
+    /// Completion of runs which are not configured otherwise
+    pub fn with_default_run(mut self, run: FakeRun) -> Self {
+        self.default_run = run;
+        self
+    }
+
+    /// Completion of the run with `run_id`, takes precedence over other settings
+    pub fn with_run(mut self, run_id: RunId, run: FakeRun) -> Self {
+        self.runs.insert(run_id, run);
+        self
+    }
+
+    /// Runs of the first `failures` dispatches fail
+    pub fn with_failing_dispatches(mut self, failures: u64) -> Self {
+        self.failing_dispatches = failures;
+        self
+    }
+
+    /// Runs dispatched for `client` fail
+    pub fn with_failing_client(mut self, client: impl Into<String>) -> Self {
+        self.failing_clients.insert(client.into());
+        self
+    }
+
+    /// Every dispatch takes `delay` before the run is created
+    pub fn with_dispatch_delay(mut self, delay: Duration) -> Self {
+        self.dispatch_delay = delay;
+        self
+    }
+
+    /// Dispatched workflows with clients of their targets, in the order of dispatches
+    pub fn dispatched(&self) -> Vec<(CiWorkflow, String)> {
+        self.dispatched.lock().unwrap().clone()
+    }
+
+    pub fn cancelled(&self) -> Vec<RunId> {
+        self.cancelled.lock().unwrap().clone()
+    }
+
+    fn completion_of(&self, run_id: RunId) -> FakeRun {
+        if let Some(run) = self.runs.get(&run_id) {
+            return *run;
+        }
+        if run_id.0 <= self.failing_dispatches {
+            return FakeRun::Fails;
+        }
+        let client = run_id
+            .0
+            .checked_sub(1)
+            .and_then(|index| self.dispatched.lock().unwrap().get(index as usize).cloned())
+            .map(|(_, client)| client);
+        match client {
+            Some(client) if self.failing_clients.contains(&client) => FakeRun::Fails,
+            _ => self.default_run,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CiBackend for FakeCi {
+    async fn dispatch(
+        &self,
+        workflow: CiWorkflow,
+        target: &CiTarget,
+    ) -> Result<CiRun, GithubError> {
+        if !self.dispatch_delay.is_zero() {
+            tokio::time::sleep(self.dispatch_delay).await;
+        }
+        let run_id = {
+            let mut dispatched = self.dispatched.lock().unwrap();
+            dispatched.push((workflow, target.client.clone()));
+            RunId(dispatched.len() as u64)
+        };
+        self.get_run(workflow, target, run_id).await
+    }
+
+    async fn get_run(
+        &self,
+        _workflow: CiWorkflow,
+        _target: &CiTarget,
+        run_id: RunId,
+    ) -> Result<CiRun, GithubError> {
+        Ok(CiRun::new(run_id, "fake".to_string()))
+    }
+
+    async fn wait_for_success(
+        &self,
+        run: &CiRun,
+        _timeout: Duration,
+        _backoff: PollBackoff,
+        cancel: &CancellationToken,
+    ) -> Result<RunConclusion, GithubError> {
+        match self.completion_of(run.id) {
+            FakeRun::Succeeds => Ok(RunConclusion::Success),
+            FakeRun::Fails => Err(GithubError::WorkflowFailed {
+                run_id: run.id,
+                conclusion: RunConclusion::Failure,
+            }),
+            FakeRun::TimesOut => Err(GithubError::WorkflowTimeout {
+                run_id: run.id,
+                status: RunStatus::InProgress,
+            }),
+            FakeRun::Hangs => {
+                cancel.cancelled().await;
+                Err(GithubError::Interrupted)
+            }
+            FakeRun::Disappears => Err(GithubError::RunDisappeared(run.id)),
+        }
+    }
+
+    async fn cancel(&self, run_id: RunId) -> Result<(), GithubError> {
+        self.cancelled.lock().unwrap().push(run_id);
+        Ok(())
+    }
+
+    async fn fetch_logs(
+        &self,
+        _target: &CiTarget,
+        _run_id: RunId,
+        _max_bytes: usize,
+    ) -> Result<RunLogs, GithubError> {
+        Ok(RunLogs::from_text("", 0))
+    }
+}
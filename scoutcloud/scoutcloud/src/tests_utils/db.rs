@@ -1,4 +1,5 @@
-use sea_orm::prelude::*;
+use scoutcloud_entity::{fang_tasks, sea_orm_active_enums::FangTaskState};
+use sea_orm::{prelude::*, ActiveValue::Set};
 use std::{
     sync::Arc,
     time::{Duration, Instant},
@@ -25,6 +26,36 @@ where
     Err(anyhow::anyhow!("task didn't finish in time"))
 }
 
+/// Inserts row of a task of `task_type` into the fang queue. The row has only metadata
+/// of the task, so it should be scheduled later than the runner of the test finishes
+pub async fn insert_fang_task<C: ConnectionTrait>(
+    db: &C,
+    task_type: &str,
+    deployment_id: i32,
+    state: FangTaskState,
+    scheduled_in: chrono::Duration,
+) {
+    let now = chrono::Utc::now().fixed_offset();
+    fang_tasks::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        metadata: Set(serde_json::json!({
+            "type": task_type,
+            "deployment_id": deployment_id,
+        })),
+        error_message: Set(None),
+        state: Set(state),
+        task_type: Set("common".to_string()),
+        uniq_hash: Set(None),
+        retries: Set(0),
+        scheduled_at: Set(now + scheduled_in),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(db)
+    .await
+    .unwrap();
+}
+
 pub async fn wait_for_empty_fang_tasks(db: Arc<DatabaseConnection>) -> Result<(), anyhow::Error> {
     wait_until_some_with_timeout(
         db,
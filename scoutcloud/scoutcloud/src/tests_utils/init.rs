@@ -4,6 +4,7 @@ use crate::{
         jobs::{global, JobsRunner},
         GithubClient,
     },
+    server::HealthCheckSettings,
     tests_utils,
 };
 use blockscout_service_launcher::test_database::TestDbGuard;
@@ -22,13 +23,17 @@ pub async fn test_github_client() -> (GithubClient, MockedGithubRepo) {
 }
 
 pub async fn test_jobs_runner(db: &TestDbGuard) -> JobsRunner {
+    test_jobs_runner_with_workers(db, 10).await
+}
+
+pub async fn test_jobs_runner_with_workers(db: &TestDbGuard, number_of_workers: u32) -> JobsRunner {
     let tests_sleep_params = SleepParams {
         sleep_period: Duration::from_millis(100),
         max_sleep_period: Duration::from_millis(100),
         min_sleep_period: Duration::from_millis(100),
         sleep_step: Duration::from_millis(100),
     };
-    JobsRunner::start_pool(&db.db_url(), tests_sleep_params)
+    JobsRunner::start_pool(&db.db_url(), 10, number_of_workers, tests_sleep_params)
         .await
         .expect("Failed to start jobs runner")
 }
@@ -47,6 +52,30 @@ pub async fn jobs_runner_test_case(
         .init(github.clone())
         .await
         .expect("failed to init github client");
+    global::CI
+        .init(github.clone())
+        .await
+        .expect("failed to init ci backend");
+    global::SHUTDOWN
+        .init(Default::default())
+        .await
+        .expect("failed to init shutdown");
+    // default data has no real instances, tests of health checks enable it explicitly
+    global::HEALTH_CHECK
+        .init(Arc::new(HealthCheckSettings {
+            enabled: false,
+            ..Default::default()
+        }))
+        .await
+        .expect("failed to init health check settings");
+    global::DISPATCH_LIMIT
+        .init(Default::default())
+        .await
+        .expect("failed to init dispatch limit");
+    global::PAUSE
+        .init(Default::default())
+        .await
+        .expect("failed to init pause");
     let runner = test_jobs_runner(&db).await;
     tests_utils::mock::insert_default_data(&db.client())
         .await
@@ -1,3 +1,6 @@
+pub mod ci;
 pub mod db;
 pub mod init;
 pub mod mock;
+pub mod span_capture;
+pub mod span_export;
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Span with its own fields and fields inherited from parent spans,
+/// own fields take precedence
+#[derive(Debug, Clone)]
+pub struct CapturedSpan {
+    pub name: &'static str,
+    pub fields: HashMap<String, String>,
+}
+
+/// Layer which stores all closed spans
+#[derive(Debug, Clone, Default)]
+pub struct SpanCapture {
+    spans: Arc<Mutex<Vec<CapturedSpan>>>,
+}
+
+impl SpanCapture {
+    pub fn find(&self, name: &str) -> Option<CapturedSpan> {
+        self.spans
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|span| span.name == name)
+            .cloned()
+    }
+}
+
+#[derive(Default)]
+struct SpanFields(HashMap<String, String>);
+
+impl Visit for SpanFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+}
+
+impl<S> Layer<S> for SpanCapture
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = SpanFields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let mut fields = HashMap::new();
+        for span in span.scope() {
+            if let Some(own) = span.extensions().get::<SpanFields>() {
+                for (name, value) in &own.0 {
+                    fields.entry(name.clone()).or_insert_with(|| value.clone());
+                }
+            }
+        }
+        self.spans.lock().unwrap().push(CapturedSpan {
+            name: span.name(),
+            fields,
+        });
+    }
+}
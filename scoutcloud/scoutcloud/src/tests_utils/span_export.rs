@@ -0,0 +1,72 @@
+use opentelemetry::{
+    sdk::{
+        export::trace::{ExportResult, SpanData, SpanExporter},
+        trace::TracerProvider,
+    },
+    trace::{SpanId, TracerProvider as _},
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+/// Span as it is received by OpenTelemetry collector
+#[derive(Debug, Clone)]
+pub struct ExportedSpan {
+    pub name: String,
+    pub span_id: SpanId,
+    pub parent_span_id: SpanId,
+    pub attributes: HashMap<String, String>,
+}
+
+/// Exporter which stores all exported spans in memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemorySpanExporter {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl InMemorySpanExporter {
+    pub fn spans(&self) -> Vec<ExportedSpan> {
+        self.spans
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|span| ExportedSpan {
+                name: span.name.to_string(),
+                span_id: span.span_context.span_id(),
+                parent_span_id: span.parent_span_id,
+                attributes: span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| (key.as_str().to_string(), value.as_str().to_string()))
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl SpanExporter for InMemorySpanExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.spans.lock().unwrap().extend(batch);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Subscriber which exports all spans to `exporter`. Spans are exported
+/// once they are closed, dropping the provider waits for the export
+pub fn exporting_subscriber(
+    exporter: InMemorySpanExporter,
+) -> (impl tracing::Subscriber + Send + Sync, TracerProvider) {
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .build();
+    let tracer = provider.tracer("scoutcloud-test");
+    let subscriber = Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    (subscriber, provider)
+}